@@ -0,0 +1,174 @@
+//! The symlink through which the active environment is used, e.g.
+//! `/net/obs-env/current`, repointed with [`activate`] and restored with
+//! [`deactivate`].
+use crate::{error::ObsEnvError, observing_environment::write_atomically};
+use std::{
+    fmt::{self, Display},
+    fs::{read_link, read_to_string, remove_file, rename, symlink_metadata},
+    io::{self, ErrorKind},
+    path::{self, Path, PathBuf},
+    process,
+};
+
+/// Suffix of the file recording the previous target of a link, next to
+/// the link.
+const PREVIOUS_SUFFIX: &str = ".previous";
+
+/// A link repointed at an environment.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Activation {
+    pub link: PathBuf,
+    /// Environment the link points at now.
+    pub target: PathBuf,
+    /// Environment the link pointed at before, if any.
+    pub previous: Option<PathBuf>,
+}
+
+impl Display for Activation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} -> {}", self.link.display(), self.target.display())?;
+        if let Some(previous) = &self.previous {
+            write!(f, " (was {})", previous.display())?;
+        }
+        Ok(())
+    }
+}
+
+/// Environment `link` points at, or none if there is no link.
+pub fn target(link: &Path) -> Result<Option<PathBuf>, ObsEnvError> {
+    match symlink_metadata(link) {
+        Ok(metadata) if metadata.file_type().is_symlink() => read_link(link)
+            .map(Some)
+            .map_err(|error| ObsEnvError::io(link, "read link", error)),
+        Ok(_) => Err(ObsEnvError::InvalidConfig {
+            message: format!("{} exists and is not a symlink", link.display()),
+        }),
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(ObsEnvError::io(link, "read link", error)),
+    }
+}
+
+/// Target `link` pointed at before the last [`activate`], if recorded.
+pub fn previous_target(link: &Path) -> Option<PathBuf> {
+    read_to_string(previous_path(link))
+        .ok()
+        .map(|previous| PathBuf::from(previous.trim_end_matches('\n')))
+}
+
+/// Point `link` at the environment at `target`, recording the environment
+/// it pointed at before for [`deactivate`].
+pub fn activate(link: &Path, target: &Path) -> Result<Activation, ObsEnvError> {
+    let activation = repoint(link, target)?;
+    match &activation.previous {
+        // Activating the active environment again keeps the environment to
+        // go back to.
+        Some(previous) if *previous != activation.target => {
+            write_atomically(&previous_path(link), &format!("{}\n", previous.display()))?
+        }
+        _ => {}
+    }
+    Ok(activation)
+}
+
+/// Point `link` back at the environment it pointed at before the last
+/// [`activate`].
+///
+/// Deactivating again leaves the link alone, rather than going back to the
+/// environment deactivated, which may have been removed since.
+pub fn deactivate(link: &Path) -> Result<Activation, ObsEnvError> {
+    let previous = previous_target(link).ok_or_else(|| ObsEnvError::NoPreviousTarget {
+        link: link.to_path_buf(),
+    })?;
+    if target(link)?.as_ref() == Some(&previous) {
+        return Ok(Activation {
+            link: link.to_path_buf(),
+            target: previous,
+            previous: None,
+        });
+    }
+    repoint(link, &previous)
+}
+
+/// Point `link` at `target`.
+///
+/// The link is replaced with a rename, so it is never missing while it is
+/// being repointed.
+fn repoint(link: &Path, target: &Path) -> Result<Activation, ObsEnvError> {
+    let target =
+        path::absolute(target).map_err(|error| ObsEnvError::io(target, "resolve", error))?;
+    let previous = self::target(link)?;
+    let mut temporary = link.as_os_str().to_owned();
+    temporary.push(format!(".{}.tmp", process::id()));
+    let temporary = PathBuf::from(temporary);
+    let _ = remove_file(&temporary);
+    symlink(&target, &temporary)
+        .and_then(|_| rename(&temporary, link))
+        .map_err(|error| ObsEnvError::io(link, "repoint", error))?;
+    Ok(Activation {
+        link: link.to_path_buf(),
+        target,
+        previous,
+    })
+}
+
+fn previous_path(link: &Path) -> PathBuf {
+    let mut path = link.as_os_str().to_owned();
+    path.push(PREVIOUS_SUFFIX);
+    PathBuf::from(path)
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(not(unix))]
+fn symlink(_target: &Path, _link: &Path) -> io::Result<()> {
+    Err(io::Error::from(ErrorKind::Unsupported))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{activate, deactivate, previous_target, target};
+    use crate::ObsEnvError;
+    use std::fs::create_dir_all;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_activate_and_deactivate() {
+        let root = TempDir::new().unwrap();
+        let link = root.path().join("current");
+        let (nightly, release) = (root.path().join("nightly"), root.path().join("release"));
+        create_dir_all(&nightly).unwrap();
+        create_dir_all(&release).unwrap();
+        assert!(matches!(
+            deactivate(&link),
+            Err(ObsEnvError::NoPreviousTarget { .. })
+        ));
+
+        let activation = activate(&link, &nightly).unwrap();
+        assert_eq!(activation.previous, None);
+        assert_eq!(target(&link).unwrap(), Some(nightly.clone()));
+        let activation = activate(&link, &release).unwrap();
+        assert_eq!(activation.previous, Some(nightly.clone()));
+        activate(&link, &release).unwrap();
+        assert_eq!(previous_target(&link), Some(nightly.clone()));
+
+        let activation = deactivate(&link).unwrap();
+        assert_eq!(activation.target, nightly);
+        assert_eq!(target(&link).unwrap(), Some(nightly.clone()));
+        let activation = deactivate(&link).unwrap();
+        assert_eq!(
+            (activation.target, activation.previous),
+            (nightly.clone(), None)
+        );
+        assert_eq!(target(&link).unwrap(), Some(nightly.clone()));
+        activate(&link, &release).unwrap();
+        assert_eq!(previous_target(&link), Some(nightly.clone()));
+
+        assert!(matches!(
+            activate(&nightly, &release),
+            Err(ObsEnvError::InvalidConfig { .. })
+        ));
+    }
+}
@@ -0,0 +1,373 @@
+use crate::{
+    error::ObsEnvError,
+    manifest::RepoVersion,
+    observing_environment::ResetReport,
+    setup::{RepoSetup, RepoSetupOutcome, SetupReport},
+    ObservingEnvironment,
+};
+use std::{collections::BTreeMap, future::Future, sync::Arc, time::Duration};
+use tokio_util::sync::CancellationToken;
+
+/// Async wrapper around an [`ObservingEnvironment`], for tokio based
+/// services.
+///
+/// The git operations are still blocking, so they are run on the tokio
+/// blocking pool. Cancellation is cooperative: operations on several
+/// repositories stop before the next repository once the token is
+/// cancelled, and an operation on a single repository returns
+/// [`ObsEnvError::Cancelled`] right away, leaving the blocking work to
+/// finish in the background.
+///
+/// ```no_run
+/// use ts_observing_environment::{asynchronous::AsyncObservingEnvironment, ObservingEnvironment};
+/// use tokio_util::sync::CancellationToken;
+///
+/// # async fn setup() {
+/// let obs_env = AsyncObservingEnvironment::new(ObservingEnvironment::with_destination("/obs-env"));
+/// let cancel = CancellationToken::new();
+/// let report = obs_env.clone_repositories(&cancel).await;
+/// for (repo_name, path) in report.cloned() {
+///     println!("Cloned {repo_name} into {}", path.display());
+/// }
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct AsyncObservingEnvironment {
+    inner: Arc<ObservingEnvironment>,
+}
+
+impl AsyncObservingEnvironment {
+    /// Wrap `obs_env`.
+    pub fn new(obs_env: ObservingEnvironment) -> AsyncObservingEnvironment {
+        AsyncObservingEnvironment {
+            inner: Arc::new(obs_env),
+        }
+    }
+
+    /// The wrapped environment, for the quick, local operations.
+    pub fn get_ref(&self) -> &ObservingEnvironment {
+        &self.inner
+    }
+
+    /// Async version of [`ObservingEnvironment::clone_repositories`].
+    ///
+    /// If cancelled, the repositories not cloned yet are reported as failed
+    /// with an [`ObsEnvError::Cancelled`] error.
+    pub async fn clone_repositories(&self, cancel: &CancellationToken) -> SetupReport {
+        let mut repos = Vec::new();
+        for repo_name in self.repo_names() {
+            if cancel.is_cancelled() {
+                repos.push(RepoSetup {
+                    outcome: RepoSetupOutcome::Failed {
+                        error: cancelled(&format!("clone {repo_name}")),
+                        duration: Duration::ZERO,
+                    },
+                    name: repo_name,
+                });
+                continue;
+            }
+            repos.push(
+                self.run_blocking(move |obs_env| obs_env.clone_missing_repository(&repo_name))
+                    .await,
+            );
+        }
+        SetupReport::new(repos)
+    }
+
+    /// Async version of [`ObservingEnvironment::get_base_env_versions`].
+    pub async fn get_base_env_versions(
+        &self,
+        base_env_branch: &str,
+        cancel: &CancellationToken,
+    ) -> Result<BTreeMap<String, RepoVersion>, ObsEnvError> {
+        let base_env_branch = base_env_branch.to_owned();
+        cancellable(
+            "read the base environment versions",
+            cancel,
+            self.run_blocking(move |obs_env| obs_env.get_base_env_versions(&base_env_branch)),
+        )
+        .await?
+    }
+
+    /// Async version of [`ObservingEnvironment::reset_base_environment`],
+    /// resetting the repositories one at a time.
+    ///
+    /// If cancelled, the last error is an [`ObsEnvError::Cancelled`] error
+    /// and the remaining repositories are not reset.
+    pub async fn reset_base_environment(
+        &self,
+        base_env_branch: &str,
+        cancel: &CancellationToken,
+    ) -> Result<ResetReport, Vec<ObsEnvError>> {
+        let targets = {
+            let base_env_branch = base_env_branch.to_owned();
+            cancellable(
+                "read the base environment versions",
+                cancel,
+                self.run_blocking(move |obs_env| obs_env.reset_targets(&base_env_branch)),
+            )
+            .await
+        };
+        let (mut report, versions) = targets
+            .and_then(|targets| targets)
+            .map_err(|error| vec![error])?;
+
+        let mut errors = Vec::new();
+        for (repo_name, version) in versions {
+            if !errors.is_empty() && self.inner.fails_fast() {
+                errors.push(ObsEnvError::not_attempted(&repo_name, "reset"));
+                continue;
+            }
+            if cancel.is_cancelled() {
+                errors.push(cancelled("reset the base environment"));
+                break;
+            }
+            let base_env_branch = base_env_branch.to_owned();
+            let result = self
+                .run_blocking({
+                    let repo_name = repo_name.clone();
+                    move |obs_env| {
+                        obs_env.reset_repository_to_base(&repo_name, &version, &base_env_branch)
+                    }
+                })
+                .await;
+            match result {
+                Ok(reset) => report.add(&repo_name, reset),
+                Err(error) => errors.push(error),
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(report)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Async version of [`ObservingEnvironment::checkout_branch`].
+    pub async fn checkout_branch(
+        &self,
+        repo_name: &str,
+        branch_name: &str,
+        cancel: &CancellationToken,
+    ) -> Result<String, ObsEnvError> {
+        let operation = format!("check out {branch_name} in {repo_name}");
+        let repo_name = repo_name.to_owned();
+        let branch_name = branch_name.to_owned();
+        cancellable(
+            &operation,
+            cancel,
+            self.run_blocking(move |obs_env| obs_env.checkout_branch(&repo_name, &branch_name)),
+        )
+        .await?
+    }
+
+    /// Async version of [`ObservingEnvironment::reset_index_to_version`].
+    pub async fn reset_index_to_version(
+        &self,
+        repo_name: &str,
+        version: &str,
+        cancel: &CancellationToken,
+    ) -> Result<(), ObsEnvError> {
+        let operation = format!("reset {repo_name} to {version}");
+        let repo_name = repo_name.to_owned();
+        let version = version.to_owned();
+        cancellable(
+            &operation,
+            cancel,
+            self.run_blocking(move |obs_env| obs_env.reset_index_to_version(&repo_name, &version)),
+        )
+        .await?
+    }
+
+    fn repo_names(&self) -> Vec<String> {
+        self.inner
+            .repos()
+            .map(|repo| repo.name().to_owned())
+            .collect()
+    }
+
+    /// Run `op` on the blocking pool, propagating its panics.
+    async fn run_blocking<T, F>(&self, op: F) -> T
+    where
+        T: Send + 'static,
+        F: FnOnce(&ObservingEnvironment) -> T + Send + 'static,
+    {
+        let obs_env = Arc::clone(&self.inner);
+        match tokio::task::spawn_blocking(move || op(&obs_env)).await {
+            Ok(result) => result,
+            Err(error) => std::panic::resume_unwind(error.into_panic()),
+        }
+    }
+}
+
+impl From<ObservingEnvironment> for AsyncObservingEnvironment {
+    fn from(obs_env: ObservingEnvironment) -> AsyncObservingEnvironment {
+        AsyncObservingEnvironment::new(obs_env)
+    }
+}
+
+/// Wait for `future`, unless `cancel` is cancelled first.
+async fn cancellable<T>(
+    operation: &str,
+    cancel: &CancellationToken,
+    future: impl Future<Output = T>,
+) -> Result<T, ObsEnvError> {
+    tokio::select! {
+        biased;
+        _ = cancel.cancelled() => Err(cancelled(operation)),
+        result = future => Ok(result),
+    }
+}
+
+fn cancelled(operation: &str) -> ObsEnvError {
+    ObsEnvError::Cancelled {
+        operation: operation.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AsyncObservingEnvironment;
+    use crate::{
+        observing_environment::{ErrorPolicy, VersionOverride},
+        testing::FakeBackend,
+        ObsEnvError, ObservingEnvironment, ObservingEnvironmentBuilder,
+    };
+    use tempfile::TempDir;
+    use tokio_util::sync::CancellationToken;
+
+    type TestResult<T = (), E = Box<dyn std::error::Error>> = std::result::Result<T, E>;
+
+    const FAKE_ORG: &str = "https://example.com/lsst-ts";
+
+    fn fake_environment(
+        root: &TempDir,
+        backend: &FakeBackend,
+    ) -> TestResult<AsyncObservingEnvironment> {
+        Ok(fake_builder(root, backend)?.build()?.into())
+    }
+
+    /// Builder of an environment of two repositories with base version
+    /// 1.2.0 and a main branch, cloned with `backend`.
+    fn fake_builder(
+        root: &TempDir,
+        backend: &FakeBackend,
+    ) -> TestResult<ObservingEnvironmentBuilder> {
+        for repo_name in ["ts_observatory_control", "ts_wep"] {
+            backend.set_branch(&format!("{FAKE_ORG}/{repo_name}"), "main", "1111aaaa");
+            backend.set_tag(&format!("{FAKE_ORG}/{repo_name}"), "v1.2.0", "2222bbbb");
+        }
+        let versions_file = root.path().join("versions.env");
+        std::fs::write(
+            &versions_file,
+            "ts_wep=1.2.0\nts_observatory_control=1.2.0\n",
+        )?;
+
+        Ok(ObservingEnvironment::builder()
+            .destination(&root.path().to_string_lossy())
+            .repositories(["ts_observatory_control", "ts_wep"].map(|name| (name, FAKE_ORG)))
+            .base_env_source(&versions_file.to_string_lossy())
+            .backend(backend.clone()))
+    }
+
+    #[tokio::test]
+    async fn test_async_clone_and_reset() -> TestResult {
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        let obs_env = fake_environment(&root, &backend)?;
+        let cancel = CancellationToken::new();
+
+        let report = obs_env.clone_repositories(&cancel).await;
+        assert!(report.is_success());
+        assert_eq!(report.cloned().count(), 2);
+
+        let versions = obs_env.get_base_env_versions("main", &cancel).await?;
+        assert_eq!(versions["ts_wep"].describe, "1.2.0");
+
+        assert!(obs_env
+            .reset_base_environment("main", &cancel)
+            .await
+            .is_ok());
+        assert_eq!(
+            backend.head(root.path().join("ts_wep")).as_deref(),
+            Some("2222bbbb")
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_async_reset_as_sync() -> TestResult {
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        let obs_env: AsyncObservingEnvironment = fake_builder(&root, &backend)?
+            .version_override("ts_wep", "main")
+            .build()?
+            .into();
+        let cancel = CancellationToken::new();
+        assert!(obs_env.clone_repositories(&cancel).await.is_success());
+
+        let report = obs_env
+            .reset_base_environment("main", &cancel)
+            .await
+            .map_err(|errors| format!("{errors:?}"))?;
+        assert_eq!(
+            report.overridden["ts_wep"],
+            VersionOverride {
+                version: "main".to_owned(),
+                base: Some("1.2.0".to_owned()),
+            }
+        );
+        assert_eq!(report.branches["ts_wep"], "main");
+        assert_eq!(
+            backend.head(root.path().join("ts_wep")).as_deref(),
+            Some("1111aaaa")
+        );
+
+        // The first repository fails, so the second is not attempted.
+        let obs_env: AsyncObservingEnvironment = fake_builder(&root, &backend)?
+            .version_override("ts_observatory_control", "9.9.9")
+            .on_error(ErrorPolicy::FailFast)
+            .build()?
+            .into();
+        let errors = obs_env
+            .reset_base_environment("main", &cancel)
+            .await
+            .unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].repo(), Some("ts_observatory_control"));
+        assert!(matches!(&errors[1], ObsEnvError::NotAttempted { repo, .. } if repo == "ts_wep"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_async_cancelled() -> TestResult {
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        let obs_env = fake_environment(&root, &backend)?;
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+
+        let report = obs_env.clone_repositories(&cancel).await;
+        assert_eq!(report.repos.len(), 2);
+        assert!(report
+            .failures()
+            .all(|(_, error)| matches!(error, ObsEnvError::Cancelled { .. })));
+        assert!(backend.head(root.path().join("ts_wep")).is_none());
+
+        assert!(matches!(
+            obs_env.get_base_env_versions("main", &cancel).await,
+            Err(ObsEnvError::Cancelled { .. })
+        ));
+        assert!(matches!(
+            obs_env.checkout_branch("ts_wep", "main", &cancel).await,
+            Err(ObsEnvError::Cancelled { .. })
+        ));
+        let errors = obs_env
+            .reset_base_environment("main", &cancel)
+            .await
+            .unwrap_err();
+        assert!(matches!(errors.as_slice(), [ObsEnvError::Cancelled { .. }]));
+        Ok(())
+    }
+}
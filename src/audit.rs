@@ -0,0 +1,283 @@
+//! Audit log of the operations modifying an environment, kept as json
+//! lines in `.obs_env/audit.log`, so that changes to a shared environment
+//! can be traced to who made them.
+use crate::error::ObsEnvError;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{self, Display},
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, ErrorKind, Write},
+    path::Path,
+};
+
+/// Seconds in a day.
+const DAY: u64 = 24 * 60 * 60;
+
+/// How an audited operation ended.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOutcome {
+    Success,
+    /// Some repositories failed, the others were changed.
+    PartialFailure,
+    Failed,
+}
+
+impl Display for AuditOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuditOutcome::Success => write!(f, "success"),
+            AuditOutcome::PartialFailure => write!(f, "partial failure"),
+            AuditOutcome::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+/// Commit checked out in a repository before and after an operation, or
+/// none if it was not cloned.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct RepoChange {
+    pub repo: String,
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+impl Display for RepoChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let short = |commit: &Option<String>| match commit {
+            Some(commit) => commit[..commit.len().min(12)].to_owned(),
+            None => "none".to_owned(),
+        };
+        write!(
+            f,
+            "{} {} -> {}",
+            self.repo,
+            short(&self.from),
+            short(&self.to)
+        )
+    }
+}
+
+/// Record of an operation in the audit log.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct AuditEntry {
+    /// Seconds since the Unix epoch when the operation ended.
+    pub timestamp: u64,
+    pub user: String,
+    pub host: String,
+    /// Name of the action, e.g. "reset".
+    pub action: String,
+    /// Repositories whose checked out commit changed.
+    pub repos: Vec<RepoChange>,
+    pub outcome: AuditOutcome,
+    /// Error the operation failed with, if it did.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Display for AuditEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {}@{} {}: {}",
+            format_utc(self.timestamp),
+            self.user,
+            self.host,
+            self.action,
+            self.outcome
+        )?;
+        if let Some(error) = &self.error {
+            write!(f, " ({error})")?;
+        }
+        for change in self.repos.iter() {
+            write!(f, "\n  {change}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Entries of the audit log to show.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HistoryFilter {
+    /// Only the entries changing one of these repositories, or all of
+    /// them if none.
+    pub repos: Vec<String>,
+    /// Only the entries from this time on, in seconds since the Unix
+    /// epoch.
+    pub since: Option<u64>,
+    /// Only the last entries, this many of them.
+    pub limit: Option<usize>,
+}
+
+impl HistoryFilter {
+    /// Entries of `entries` passing the filter, in order.
+    pub fn apply(&self, entries: Vec<AuditEntry>) -> Vec<AuditEntry> {
+        let mut entries: Vec<AuditEntry> = entries
+            .into_iter()
+            .filter(|entry| self.since.is_none_or(|since| entry.timestamp >= since))
+            .filter(|entry| {
+                self.repos.is_empty()
+                    || entry
+                        .repos
+                        .iter()
+                        .any(|change| self.repos.contains(&change.repo))
+            })
+            .collect();
+        if let Some(limit) = self.limit {
+            entries.drain(..entries.len().saturating_sub(limit));
+        }
+        entries
+    }
+}
+
+/// Append `entry` to the audit log at `path`.
+///
+/// The line is written with a single write to a file opened for appending,
+/// so concurrent readers and writers never see it torn.
+pub fn append(path: &Path, entry: &AuditEntry) -> Result<(), ObsEnvError> {
+    let mut line = serde_json::to_vec(entry).map_err(|error| {
+        ObsEnvError::io(path, "write", std::io::Error::other(error.to_string()))
+    })?;
+    line.push(b'\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(&line))
+        .map_err(|error| ObsEnvError::io(path, "write", error))
+}
+
+/// Entries of the audit log at `path`, oldest first. Lines that cannot be
+/// read are skipped, and a missing log has no entries.
+pub fn read(path: &Path) -> Result<Vec<AuditEntry>, ObsEnvError> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => return Err(ObsEnvError::io(path, "read", error)),
+    };
+    let mut entries = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(|error| ObsEnvError::io(path, "read", error))?;
+        match serde_json::from_str(&line) {
+            Ok(entry) => entries.push(entry),
+            Err(error) => log::warn!("Skipping an unreadable line of {}: {error}", path.display()),
+        }
+    }
+    Ok(entries)
+}
+
+/// Name of the user running the process.
+pub(crate) fn current_user() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("LOGNAME"))
+        .unwrap_or_else(|_| "unknown user".to_owned())
+}
+
+/// Seconds since the Unix epoch at the start of `date`, given as
+/// YYYY-MM-DD in UTC.
+pub fn parse_date(date: &str) -> Option<u64> {
+    let mut parts = date.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (parts.next()??, parts.next()??, parts.next()??);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    // Days from civil, by Howard Hinnant.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    u64::try_from(days).ok().map(|days| days * DAY)
+}
+
+/// `timestamp` as YYYY-MM-DD HH:MM:SS in UTC.
+pub(crate) fn format_utc(timestamp: u64) -> String {
+    let (days, seconds) = (timestamp / DAY, timestamp % DAY);
+    // Civil from days, by Howard Hinnant.
+    let days = days as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        append, format_utc, parse_date, read, AuditEntry, AuditOutcome, HistoryFilter, RepoChange,
+    };
+    use tempfile::TempDir;
+
+    type TestResult<T = (), E = Box<dyn std::error::Error>> = std::result::Result<T, E>;
+
+    fn entry(timestamp: u64, repo: &str) -> AuditEntry {
+        AuditEntry {
+            timestamp,
+            user: "observer".to_owned(),
+            host: "summit".to_owned(),
+            action: "reset".to_owned(),
+            repos: vec![RepoChange {
+                repo: repo.to_owned(),
+                from: Some("1111aaaa".to_owned()),
+                to: None,
+            }],
+            outcome: AuditOutcome::Success,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_audit_log() -> TestResult {
+        let root = TempDir::new()?;
+        let path = root.path().join("audit.log");
+        assert!(read(&path)?.is_empty());
+
+        let since = parse_date("2024-06-10").unwrap();
+        assert_eq!(since, 1717977600);
+        assert_eq!(format_utc(since + 3661), "2024-06-10 01:01:01");
+        assert_eq!(parse_date("2024-13-01"), None);
+
+        append(&path, &entry(since - 1, "ts_wep"))?;
+        append(&path, &entry(since, "ts_xml"))?;
+        append(&path, &entry(since + 1, "ts_wep"))?;
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .and_then(|mut file| std::io::Write::write_all(&mut file, b"{\"torn\n"))?;
+        let entries = read(&path)?;
+        assert_eq!(entries.len(), 3);
+
+        let filter = HistoryFilter {
+            repos: vec!["ts_wep".to_owned()],
+            since: Some(since),
+            limit: None,
+        };
+        assert_eq!(filter.apply(entries.clone()), [entry(since + 1, "ts_wep")]);
+        let last = HistoryFilter {
+            limit: Some(1),
+            ..HistoryFilter::default()
+        };
+        assert_eq!(last.apply(entries), [entry(since + 1, "ts_wep")]);
+        assert_eq!(
+            entry(since, "ts_xml").to_string(),
+            "2024-06-10 00:00:00 observer@summit reset: success\n  ts_xml 1111aaaa -> none"
+        );
+        Ok(())
+    }
+}
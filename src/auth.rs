@@ -0,0 +1,557 @@
+use git2::{
+    Config, Cred, CredentialType, Error, ErrorClass, ErrorCode, FetchOptions, Progress,
+    RemoteCallbacks,
+};
+use std::{
+    cell::RefCell,
+    env,
+    fs::read_to_string,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+/// Default private keys tried, in order, when nothing else is configured.
+const DEFAULT_SSH_KEYS: [&str; 3] = ["id_ed25519", "id_ecdsa", "id_rsa"];
+
+/// Run a network operation with credentials resolved from the user's setup.
+///
+/// SSH remotes try the ssh-agent first and then each candidate key file
+/// (from `GIT_SSH_COMMAND`, `~/.ssh/config` and the standard key names).
+/// HTTPS remotes consult the configured git credential helper. If the
+/// operation fails to authenticate, the returned error lists every
+/// mechanism that was tried.
+pub fn with_credentials<T, F>(url: &str, operation: F) -> Result<T, Error>
+where
+    F: FnOnce(FetchOptions) -> Result<T, Error>,
+{
+    with_credentials_and_progress(url, |_| true, operation)
+}
+
+/// Like [`with_credentials`], calling `progress` as objects are
+/// transferred. Returning false from `progress` cancels the transfer.
+pub fn with_credentials_and_progress<'a, T, P, F>(
+    url: &str,
+    progress: P,
+    operation: F,
+) -> Result<T, Error>
+where
+    P: FnMut(Progress<'_>) -> bool + 'a,
+    F: FnOnce(FetchOptions<'a>) -> Result<T, Error>,
+{
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.transfer_progress(progress);
+    with_credentials_and_callbacks(url, callbacks, operation)
+}
+
+/// Like [`with_credentials`], with `callbacks` set on the remote besides
+/// the credentials one.
+pub fn with_credentials_and_callbacks<'a, T, F>(
+    url: &str,
+    callbacks: RemoteCallbacks<'a>,
+    operation: F,
+) -> Result<T, Error>
+where
+    F: FnOnce(FetchOptions<'a>) -> Result<T, Error>,
+{
+    with_remote_callbacks(url, callbacks, |callbacks| {
+        let mut fetch_options = FetchOptions::new();
+        fetch_options.remote_callbacks(callbacks);
+        operation(fetch_options)
+    })
+}
+
+/// Like [`with_credentials_and_callbacks`], giving the operation the
+/// callbacks themselves, e.g. to connect to the remote.
+pub fn with_remote_callbacks<'a, T, F>(
+    url: &str,
+    mut callbacks: RemoteCallbacks<'a>,
+    operation: F,
+) -> Result<T, Error>
+where
+    F: FnOnce(RemoteCallbacks<'a>) -> Result<T, Error>,
+{
+    let ssh_settings = SshSettings::for_url(url);
+    let attempts = Rc::new(RefCell::new(CredentialAttempts::new(ssh_settings)));
+
+    let callback_attempts = Rc::clone(&attempts);
+    callbacks.credentials(move |url, username_from_url, allowed| {
+        callback_attempts
+            .borrow_mut()
+            .next_credential(url, username_from_url, allowed)
+    });
+
+    let result = operation(callbacks);
+
+    let tried = attempts.borrow().tried.join(", ");
+    result.map_err(|error| {
+        if tried.is_empty() || !is_auth_error(&error) {
+            error
+        } else {
+            Error::new(
+                error.code(),
+                error.class(),
+                format!("{} (authentication tried: {tried})", error.message()),
+            )
+        }
+    })
+}
+
+/// Rewrite an SSH url according to the user's `~/.ssh/config` and
+/// `GIT_SSH_COMMAND`, resolving host aliases to the real host name.
+///
+/// Urls that are not SSH urls are returned unchanged.
+pub fn resolve_url(url: &str) -> String {
+    match SshUrl::parse(url) {
+        Some(ssh_url) => {
+            let settings = SshSettings::for_host(&ssh_url.host);
+            ssh_url.resolve(&settings).to_string()
+        }
+        None => url.to_owned(),
+    }
+}
+
+fn is_auth_error(error: &Error) -> bool {
+    error.code() == ErrorCode::Auth
+        || error.class() == ErrorClass::Ssh
+        || error.class() == ErrorClass::Http
+        || error.class() == ErrorClass::Callback
+}
+
+/// Keeps track of which credentials were already handed to libgit2, so the
+/// callback moves on to the next mechanism instead of looping forever.
+struct CredentialAttempts {
+    ssh_settings: SshSettings,
+    agent_tried: bool,
+    next_key: usize,
+    helper_tried: bool,
+    default_tried: bool,
+    tried: Vec<String>,
+}
+
+impl CredentialAttempts {
+    fn new(ssh_settings: SshSettings) -> CredentialAttempts {
+        CredentialAttempts {
+            ssh_settings,
+            agent_tried: false,
+            next_key: 0,
+            helper_tried: false,
+            default_tried: false,
+            tried: Vec::new(),
+        }
+    }
+
+    fn next_credential(
+        &mut self,
+        url: &str,
+        username_from_url: Option<&str>,
+        allowed: CredentialType,
+    ) -> Result<Cred, Error> {
+        let ssh_user = username_from_url
+            .map(|user| user.to_owned())
+            .or_else(|| self.ssh_settings.user.clone())
+            .unwrap_or_else(|| "git".to_owned());
+
+        if allowed.contains(CredentialType::USERNAME) {
+            return Cred::username(&ssh_user);
+        }
+
+        if allowed.contains(CredentialType::SSH_KEY) {
+            if !self.agent_tried {
+                self.agent_tried = true;
+                self.tried.push("ssh-agent".to_owned());
+                log::trace!("Trying ssh-agent for {url}");
+                if let Ok(cred) = Cred::ssh_key_from_agent(&ssh_user) {
+                    return Ok(cred);
+                }
+            }
+            while self.next_key < self.ssh_settings.identity_files.len() {
+                let key = &self.ssh_settings.identity_files[self.next_key];
+                self.next_key += 1;
+                if key.exists() {
+                    self.tried.push(format!("key file {}", key.display()));
+                    log::trace!("Trying key file {} for {url}", key.display());
+                    return Cred::ssh_key(&ssh_user, None, key, None);
+                }
+            }
+        }
+
+        if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) && !self.helper_tried {
+            self.helper_tried = true;
+            self.tried.push("credential helper".to_owned());
+            log::trace!("Trying git credential helper for {url}");
+            let config = Config::open_default()?;
+            return Cred::credential_helper(&config, url, username_from_url);
+        }
+
+        if allowed.contains(CredentialType::DEFAULT) && !self.default_tried {
+            self.default_tried = true;
+            self.tried.push("default credentials".to_owned());
+            return Cred::default();
+        }
+
+        Err(Error::new(
+            ErrorCode::Auth,
+            ErrorClass::Callback,
+            format!("No more credentials to try for {url}"),
+        ))
+    }
+}
+
+/// SSH connection settings gathered from `GIT_SSH_COMMAND` and the ssh
+/// client configuration file.
+#[derive(Debug, Default, PartialEq)]
+struct SshSettings {
+    host_name: Option<String>,
+    user: Option<String>,
+    port: Option<u16>,
+    identity_files: Vec<PathBuf>,
+}
+
+impl SshSettings {
+    fn for_url(url: &str) -> SshSettings {
+        match SshUrl::parse(url) {
+            Some(ssh_url) => SshSettings::for_host(&ssh_url.host),
+            None => SshSettings::default(),
+        }
+    }
+
+    /// Settings for a host, in order of precedence: `GIT_SSH_COMMAND`
+    /// flags, the matching `Host` blocks of the ssh config and finally the
+    /// standard key files.
+    fn for_host(host: &str) -> SshSettings {
+        let command = env::var("GIT_SSH_COMMAND")
+            .map(|command| SshCommand::parse(&command))
+            .unwrap_or_default();
+
+        let config_file = command
+            .config_file
+            .clone()
+            .or_else(|| home_dir().map(|home| home.join(".ssh").join("config")));
+
+        let mut settings = SshSettings {
+            host_name: None,
+            user: command.user,
+            port: command.port,
+            identity_files: command.identity_files,
+        };
+
+        if let Some(config) = config_file.and_then(|path| read_to_string(path).ok()) {
+            settings.merge(parse_ssh_config(&config, host));
+        }
+
+        if let Some(home) = home_dir() {
+            settings.identity_files.extend(
+                DEFAULT_SSH_KEYS
+                    .iter()
+                    .map(|key| home.join(".ssh").join(key)),
+            );
+        }
+        settings
+    }
+
+    /// Fill in the values that are not set yet, keeping the existing ones.
+    fn merge(&mut self, other: SshSettings) {
+        if self.host_name.is_none() {
+            self.host_name = other.host_name;
+        }
+        if self.user.is_none() {
+            self.user = other.user;
+        }
+        if self.port.is_none() {
+            self.port = other.port;
+        }
+        self.identity_files.extend(other.identity_files);
+    }
+}
+
+/// Flags of interest from a `GIT_SSH_COMMAND` value.
+#[derive(Debug, Default, PartialEq)]
+struct SshCommand {
+    config_file: Option<PathBuf>,
+    user: Option<String>,
+    port: Option<u16>,
+    identity_files: Vec<PathBuf>,
+}
+
+impl SshCommand {
+    fn parse(command: &str) -> SshCommand {
+        let mut ssh_command = SshCommand::default();
+        let mut words = command.split_whitespace().skip(1);
+
+        while let Some(word) = words.next() {
+            match word {
+                "-i" => {
+                    if let Some(key) = words.next() {
+                        ssh_command.identity_files.push(expand_home(key));
+                    }
+                }
+                "-F" => ssh_command.config_file = words.next().map(expand_home),
+                "-l" => ssh_command.user = words.next().map(|user| user.to_owned()),
+                "-p" => ssh_command.port = words.next().and_then(|port| port.parse().ok()),
+                "-o" => {
+                    if let Some((key, value)) = words.next().and_then(|opt| opt.split_once('=')) {
+                        match key.to_lowercase().as_str() {
+                            "identityfile" => ssh_command.identity_files.push(expand_home(value)),
+                            "user" => ssh_command.user = Some(value.to_owned()),
+                            "port" => ssh_command.port = value.parse().ok(),
+                            _ => (),
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+        ssh_command
+    }
+}
+
+/// Parse the `Host` blocks of an ssh config that apply to `host`.
+///
+/// As with ssh, the first value found for each option wins.
+fn parse_ssh_config(config: &str, host: &str) -> SshSettings {
+    let mut settings = SshSettings::default();
+    let mut matching = true;
+
+    for line in config.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = match line.split_once(|c: char| c.is_whitespace() || c == '=') {
+            Some((key, value)) => (key.to_lowercase(), value.trim_start_matches('=').trim()),
+            None => continue,
+        };
+
+        match key.as_str() {
+            "host" => {
+                matching = value
+                    .split_whitespace()
+                    .any(|pattern| host_matches(pattern, host))
+            }
+            "match" => matching = false,
+            "hostname" if matching && settings.host_name.is_none() => {
+                settings.host_name = Some(value.to_owned())
+            }
+            "user" if matching && settings.user.is_none() => settings.user = Some(value.to_owned()),
+            "port" if matching && settings.port.is_none() => settings.port = value.parse().ok(),
+            "identityfile" if matching => settings.identity_files.push(expand_home(value)),
+            _ => (),
+        }
+    }
+    settings
+}
+
+/// Match a host against an ssh config pattern supporting `*` and `?`.
+fn host_matches(pattern: &str, host: &str) -> bool {
+    fn matches(pattern: &[char], host: &[char]) -> bool {
+        match (pattern.first(), host.first()) {
+            (None, None) => true,
+            (Some('*'), _) => {
+                matches(&pattern[1..], host) || (!host.is_empty() && matches(pattern, &host[1..]))
+            }
+            (Some('?'), Some(_)) => matches(&pattern[1..], &host[1..]),
+            (Some(p), Some(h)) if p == h => matches(&pattern[1..], &host[1..]),
+            _ => false,
+        }
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let host: Vec<char> = host.chars().collect();
+    matches(&pattern, &host)
+}
+
+fn home_dir() -> Option<PathBuf> {
+    env::var_os("HOME").map(PathBuf::from)
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), home_dir()) {
+        (Some(relative), Some(home)) => home.join(relative),
+        _ => Path::new(path).to_path_buf(),
+    }
+}
+
+/// An SSH url, either `ssh://[user@]host[:port]/path` or the scp-like
+/// `[user@]host:path`.
+#[derive(Debug, PartialEq)]
+struct SshUrl {
+    user: Option<String>,
+    host: String,
+    port: Option<u16>,
+    path: String,
+}
+
+impl SshUrl {
+    fn parse(url: &str) -> Option<SshUrl> {
+        if let Some(rest) = url.strip_prefix("ssh://") {
+            let (authority, path) = rest.split_once('/')?;
+            let (user, host_port) = split_user(authority);
+            let (host, port) = match host_port.split_once(':') {
+                Some((host, port)) => (host, port.parse().ok()),
+                None => (host_port, None),
+            };
+            Some(SshUrl {
+                user,
+                host: host.to_owned(),
+                port,
+                path: path.to_owned(),
+            })
+        } else if url.contains("://") {
+            None
+        } else {
+            let (authority, path) = url.split_once(':')?;
+            if authority.contains('/') {
+                return None;
+            }
+            let (user, host) = split_user(authority);
+            Some(SshUrl {
+                user,
+                host: host.to_owned(),
+                port: None,
+                path: path.to_owned(),
+            })
+        }
+    }
+
+    fn resolve(&self, settings: &SshSettings) -> SshUrl {
+        SshUrl {
+            user: self.user.clone().or_else(|| settings.user.clone()),
+            host: settings
+                .host_name
+                .clone()
+                .unwrap_or_else(|| self.host.clone()),
+            port: self.port.or(settings.port),
+            path: self.path.clone(),
+        }
+    }
+}
+
+impl std::fmt::Display for SshUrl {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "ssh://")?;
+        if let Some(user) = &self.user {
+            write!(f, "{user}@")?;
+        }
+        write!(f, "{}", self.host)?;
+        if let Some(port) = self.port {
+            write!(f, ":{port}")?;
+        }
+        write!(f, "/{}", self.path)
+    }
+}
+
+fn split_user(authority: &str) -> (Option<String>, &str) {
+    match authority.split_once('@') {
+        Some((user, host)) => (Some(user.to_owned()), host),
+        None => (None, authority),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{host_matches, parse_ssh_config, SshCommand, SshSettings, SshUrl};
+
+    const SSH_CONFIG: &str = r"
+# Summit gateway
+Host summit-gh
+    HostName github.com
+    User git
+    IdentityFile /keys/summit
+
+Host *.lsst.org
+    Port 2222
+    IdentityFile /keys/lsst
+
+Host *
+    User fallback
+    IdentityFile /keys/any
+";
+
+    #[test]
+    fn test_parse_ssh_config_alias() {
+        let settings = parse_ssh_config(SSH_CONFIG, "summit-gh");
+
+        assert_eq!(settings.host_name.as_deref(), Some("github.com"));
+        assert_eq!(settings.user.as_deref(), Some("git"));
+        assert_eq!(settings.port, None);
+        assert_eq!(
+            settings.identity_files,
+            vec![PathBuf::from("/keys/summit"), PathBuf::from("/keys/any")]
+        );
+    }
+
+    #[test]
+    fn test_parse_ssh_config_wildcard() {
+        let settings = parse_ssh_config(SSH_CONFIG, "git.lsst.org");
+
+        assert_eq!(settings.host_name, None);
+        assert_eq!(settings.user.as_deref(), Some("fallback"));
+        assert_eq!(settings.port, Some(2222));
+    }
+
+    #[test]
+    fn test_host_matches() {
+        assert!(host_matches("*", "github.com"));
+        assert!(host_matches("*.lsst.org", "git.lsst.org"));
+        assert!(host_matches("summit-??", "summit-gh"));
+        assert!(!host_matches("*.lsst.org", "github.com"));
+        assert!(!host_matches("summit-gh", "summit-gh2"));
+    }
+
+    #[test]
+    fn test_parse_git_ssh_command() {
+        let command =
+            SshCommand::parse("ssh -i /keys/deploy -p 2200 -o User=operator -F /etc/ssh_obs");
+
+        assert_eq!(
+            command,
+            SshCommand {
+                config_file: Some(PathBuf::from("/etc/ssh_obs")),
+                user: Some("operator".to_owned()),
+                port: Some(2200),
+                identity_files: vec![PathBuf::from("/keys/deploy")],
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_ssh_url() {
+        assert_eq!(
+            SshUrl::parse("git@summit-gh:lsst-ts/ts_wep.git"),
+            Some(SshUrl {
+                user: Some("git".to_owned()),
+                host: "summit-gh".to_owned(),
+                port: None,
+                path: "lsst-ts/ts_wep.git".to_owned(),
+            })
+        );
+        assert_eq!(
+            SshUrl::parse("ssh://git.lsst.org:2222/ts/ts_wep.git"),
+            Some(SshUrl {
+                user: None,
+                host: "git.lsst.org".to_owned(),
+                port: Some(2222),
+                path: "ts/ts_wep.git".to_owned(),
+            })
+        );
+        assert_eq!(SshUrl::parse("https://github.com/lsst-ts/ts_wep"), None);
+        assert_eq!(SshUrl::parse("/local/path/ts_wep"), None);
+    }
+
+    #[test]
+    fn test_resolve_ssh_url() {
+        let url = SshUrl::parse("summit-gh:lsst-ts/ts_wep.git").unwrap();
+        let settings = SshSettings {
+            host_name: Some("github.com".to_owned()),
+            user: Some("git".to_owned()),
+            port: Some(22),
+            identity_files: Vec::new(),
+        };
+
+        assert_eq!(
+            url.resolve(&settings).to_string(),
+            "ssh://git@github.com:22/lsst-ts/ts_wep.git"
+        );
+    }
+}
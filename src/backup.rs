@@ -0,0 +1,68 @@
+//! Backup branches keeping the local commits a reset moves a repository
+//! away from, so they do not become unreachable. They are made by
+//! [`ObservingEnvironment::reset_base_environment`](crate::ObservingEnvironment::reset_base_environment)
+//! and listed and dropped with
+//! [`ObservingEnvironment::list_backups`](crate::ObservingEnvironment::list_backups)
+//! and [`ObservingEnvironment::drop_backups`](crate::ObservingEnvironment::drop_backups).
+use serde::Serialize;
+use std::fmt::{self, Display};
+
+/// Prefix of the names of the backup branches, followed by the Unix time
+/// the backup was made at.
+pub const BACKUP_PREFIX: &str = "obs-env-backup/";
+
+/// Backup branch of a repository.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Backup {
+    /// Name of the repository.
+    pub repo: String,
+    /// Name of the backup branch, e.g. `obs-env-backup/1718000000`.
+    pub branch: String,
+    /// Seconds since the Unix epoch when the backup was made.
+    pub created: u64,
+    /// Ids of the commits kept only by the backup branch, newest first.
+    pub commits: Vec<String>,
+}
+
+impl Display for Backup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.repo, self.branch)?;
+        match self.commits.as_slice() {
+            [] => write!(f, " (no commit only in it)"),
+            [head, ..] => write!(
+                f,
+                " at {} ({} commits only in it)",
+                &head[..head.len().min(12)],
+                self.commits.len()
+            ),
+        }
+    }
+}
+
+/// Name of the `index`th backup branch made at `created`, counting from 0.
+pub(crate) fn branch_name(created: u64, index: usize) -> String {
+    match index {
+        0 => format!("{BACKUP_PREFIX}{created}"),
+        _ => format!("{BACKUP_PREFIX}{created}-{index}"),
+    }
+}
+
+/// Time the backup branch `branch` was made at, if it is one.
+pub(crate) fn created(branch: &str) -> Option<u64> {
+    let suffix = branch.strip_prefix(BACKUP_PREFIX)?;
+    suffix.split('-').next()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{branch_name, created};
+
+    #[test]
+    fn test_branch_name() {
+        assert_eq!(branch_name(1718000000, 0), "obs-env-backup/1718000000");
+        assert_eq!(branch_name(1718000000, 2), "obs-env-backup/1718000000-2");
+        assert_eq!(created("obs-env-backup/1718000000-2"), Some(1718000000));
+        assert_eq!(created("obs-env-backup/latest"), None);
+        assert_eq!(created("main"), None);
+    }
+}
@@ -1,7 +1,7 @@
 use clap::CommandFactory;
 use clap_complete::{generate, Shell};
 use std::io;
-use ts_observing_environment::manage_obs_env::ManageObsEnv;
+use ts_observing_environment::ManageObsEnv;
 
 fn main() {
     let mut command = ManageObsEnv::command();
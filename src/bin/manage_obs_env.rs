@@ -1,15 +1,18 @@
 use clap::Parser;
 use simple_logger::SimpleLogger;
 use std::process;
-use ts_observing_environment::manage_obs_env::{run, ManageObsEnv};
+use ts_observing_environment::{error::report, run, ManageObsEnv, ManageObsEnvCli};
 
 fn main() {
-    SimpleLogger::new().init().unwrap();
-
     let args = ManageObsEnv::parse();
 
+    SimpleLogger::new()
+        .with_level(args.get_log_level().into())
+        .init()
+        .unwrap();
+
     if let Err(e) = run(&args) {
-        eprintln!("Application error: {:?}", e);
+        eprintln!("error: {}", report(e.as_ref()));
         process::exit(1);
     }
 }
@@ -0,0 +1,108 @@
+//! Local branches left behind once their work is merged or dropped, like
+//! the `tickets/DM-*` branches of past tests, found and deleted by
+//! [`ObservingEnvironment::stale_branches`](crate::ObservingEnvironment::stale_branches).
+use serde::Serialize;
+use std::fmt::{self, Display};
+
+/// Why a local branch is stale.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "reason")]
+pub enum StaleReason {
+    /// Its tip is contained in the default branch `into` of origin.
+    Merged { into: String },
+    /// origin no longer has it, and it has no commit that is not on
+    /// origin, or was not when last fetched.
+    Gone,
+}
+
+impl Display for StaleReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StaleReason::Merged { into } => write!(f, "merged into {into}"),
+            StaleReason::Gone => write!(f, "gone from origin"),
+        }
+    }
+}
+
+/// Stale local branch of a repository.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct StaleBranch {
+    pub branch: String,
+    #[serde(flatten)]
+    pub reason: StaleReason,
+}
+
+/// Remote-tracking branches of a repository pruned, as a fetch with
+/// `--prune` would.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct PrunedRemotes {
+    /// Name of the repository.
+    pub repo: String,
+    /// Full names of the references deleted, e.g.
+    /// `refs/remotes/origin/tickets/DM-12345`.
+    pub refs: Vec<String>,
+}
+
+impl Display for PrunedRemotes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} stale remote-tracking branches pruned",
+            self.repo,
+            self.refs.len()
+        )?;
+        for name in self.refs.iter() {
+            write!(f, "\n  {}", name.trim_start_matches("refs/remotes/"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Stale branches of a repository, and whether they were deleted.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct StaleBranches {
+    /// Name of the repository.
+    pub repo: String,
+    /// Local branches that can be deleted without losing any commit. The
+    /// branch checked out is never one of them.
+    pub branches: Vec<StaleBranch>,
+    /// Remote-tracking branches, e.g. `origin/tickets/DM-12345`, whose
+    /// branch origin no longer has.
+    pub remote_refs: Vec<String>,
+    /// Whether the branches and remote-tracking branches were deleted.
+    pub deleted: bool,
+}
+
+impl StaleBranches {
+    /// Number of local and remote-tracking branches found.
+    pub fn len(&self) -> usize {
+        self.branches.len() + self.remote_refs.len()
+    }
+
+    /// Whether there is no stale branch.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Display for StaleBranches {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} stale branches, {} stale remote-tracking branches",
+            self.repo,
+            self.branches.len(),
+            self.remote_refs.len()
+        )?;
+        if self.deleted {
+            write!(f, ", deleted")?;
+        }
+        for branch in self.branches.iter() {
+            write!(f, "\n  {}: {}", branch.branch, branch.reason)?;
+        }
+        for remote_ref in self.remote_refs.iter() {
+            write!(f, "\n  {remote_ref}: gone from origin")?;
+        }
+        Ok(())
+    }
+}
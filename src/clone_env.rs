@@ -0,0 +1,43 @@
+//! Copies of the repositories of another environment, made from its local
+//! clones with no network access by
+//! [`ObservingEnvironment::clone_env`](crate::ObservingEnvironment::clone_env).
+use serde::Serialize;
+use std::fmt::{self, Display};
+
+/// What became of the changes to tracked files of a repository copied.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LocalChanges {
+    /// The source repository had none.
+    None,
+    /// The source repository had some, which were left out.
+    Skipped,
+    /// The changes were applied to the copy.
+    Copied,
+}
+
+/// Repository copied from another environment.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ClonedRepo {
+    pub repo: String,
+    /// Branch checked out, as in the source, or none if HEAD is detached.
+    pub branch: Option<String>,
+    /// Commit checked out.
+    pub commit: String,
+    pub local_changes: LocalChanges,
+}
+
+impl Display for ClonedRepo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let commit = &self.commit[..self.commit.len().min(12)];
+        match &self.branch {
+            Some(branch) => write!(f, "{}: {branch} at {commit}", self.repo)?,
+            None => write!(f, "{}: detached at {commit}", self.repo)?,
+        }
+        match self.local_changes {
+            LocalChanges::None => Ok(()),
+            LocalChanges::Skipped => write!(f, ", local changes left out"),
+            LocalChanges::Copied => write!(f, ", local changes copied"),
+        }
+    }
+}
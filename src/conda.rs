@@ -0,0 +1,221 @@
+use crate::{error::ObsEnvError, manifest::RepoVersion};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::{self, Display},
+    process::Command,
+};
+
+/// Package installed in a conda environment, as listed by `conda list
+/// --json`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CondaPackage {
+    pub name: String,
+    pub version: String,
+}
+
+impl CondaPackage {
+    /// Package `name` at `version`.
+    pub fn new(name: &str, version: &str) -> CondaPackage {
+        CondaPackage {
+            name: name.to_owned(),
+            version: version.to_owned(),
+        }
+    }
+}
+
+/// Packages of the active conda environment, listed with `conda list
+/// --json` using the `conda` executable.
+pub fn list_packages(conda: &str) -> Result<Vec<CondaPackage>, ObsEnvError> {
+    let command = format!("{conda} list --json");
+    let command_failed = |message: String| ObsEnvError::CommandFailed {
+        command: command.clone(),
+        message,
+    };
+    let output = Command::new(conda)
+        .args(["list", "--json"])
+        .output()
+        .map_err(|error| command_failed(error.to_string()))?;
+    if !output.status.success() {
+        return Err(command_failed(
+            String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        ));
+    }
+    serde_json::from_slice(&output.stdout).map_err(|error| command_failed(error.to_string()))
+}
+
+/// Active conda environment exported with `conda env export`, or none if
+/// conda is not available.
+pub fn export_environment(conda: &str) -> Option<String> {
+    match Command::new(conda).args(["env", "export"]).output() {
+        Ok(output) if output.status.success() => {
+            Some(String::from_utf8_lossy(&output.stdout).into_owned())
+        }
+        _ => {
+            log::debug!("Conda not available, not exporting the conda environment.");
+            None
+        }
+    }
+}
+
+/// Name of the conda package of `repo_name`: the one given in `mapping`,
+/// or the repository name with dashes instead of underscores, as for
+/// ts_observatory_control and ts-observatory-control.
+pub fn package_name(repo_name: &str, mapping: &BTreeMap<String, String>) -> String {
+    mapping
+        .get(repo_name)
+        .cloned()
+        .unwrap_or_else(|| repo_name.replace('_', "-"))
+}
+
+/// Repository checked out at a different version than its conda package.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CondaMismatch {
+    pub repo: String,
+    pub repo_version: String,
+    pub package: String,
+    pub package_version: String,
+}
+
+/// Result of comparing the repositories of the environment with the
+/// packages of a conda environment.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct CondaComparison {
+    /// Repositories whose version differs from their package, sorted by
+    /// repository.
+    pub mismatches: Vec<CondaMismatch>,
+    /// Repositories at the version of their package.
+    pub matching: Vec<String>,
+    /// Repositories with no conda package installed.
+    pub repos_without_package: Vec<String>,
+    /// Conda packages with no repository in the environment.
+    pub packages_without_repo: Vec<String>,
+}
+
+/// Compare the checked out `versions` with the conda `packages`, matching
+/// them with [`package_name`].
+///
+/// Versions are compared without a leading "v", so tag v1.2.0 matches
+/// package version 1.2.0; a checkout past a tag, like v1.2.0-3-g1111aaa,
+/// is a mismatch.
+pub fn compare(
+    versions: &BTreeMap<String, RepoVersion>,
+    packages: &[CondaPackage],
+    mapping: &BTreeMap<String, String>,
+) -> CondaComparison {
+    let packages: BTreeMap<&str, &str> = packages
+        .iter()
+        .map(|package| (package.name.as_str(), package.version.as_str()))
+        .collect();
+    let mut matched = BTreeSet::new();
+    let mut comparison = CondaComparison::default();
+    for (repo_name, version) in versions {
+        let package = package_name(repo_name, mapping);
+        match packages.get(package.as_str()) {
+            Some(package_version) => {
+                if normalize(&version.describe) == normalize(package_version) {
+                    comparison.matching.push(repo_name.to_owned());
+                } else {
+                    comparison.mismatches.push(CondaMismatch {
+                        repo: repo_name.to_owned(),
+                        repo_version: version.describe.clone(),
+                        package: package.clone(),
+                        package_version: package_version.to_string(),
+                    });
+                }
+                matched.insert(package);
+            }
+            None => comparison.repos_without_package.push(repo_name.to_owned()),
+        }
+    }
+    comparison.packages_without_repo = packages
+        .keys()
+        .filter(|package| !matched.contains(**package))
+        .map(|package| package.to_string())
+        .collect();
+    comparison
+}
+
+fn normalize(version: &str) -> &str {
+    version.strip_prefix('v').unwrap_or(version)
+}
+
+impl Display for CondaComparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.mismatches.is_empty() {
+            write!(f, "No repository differs from its conda package.")?;
+        } else {
+            write!(f, "Repositories not matching their conda package:")?;
+            for mismatch in self.mismatches.iter() {
+                write!(
+                    f,
+                    "\n{}: {} (conda {} {})",
+                    mismatch.repo,
+                    mismatch.repo_version,
+                    mismatch.package,
+                    mismatch.package_version
+                )?;
+            }
+        }
+        if !self.repos_without_package.is_empty() {
+            write!(
+                f,
+                "\nRepositories without a conda package: {}",
+                self.repos_without_package.join(", ")
+            )?;
+        }
+        if !self.packages_without_repo.is_empty() {
+            write!(
+                f,
+                "\nConda packages without a repository: {}",
+                self.packages_without_repo.join(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compare, CondaMismatch, CondaPackage};
+    use crate::manifest::RepoVersion;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_compare() {
+        let versions = BTreeMap::from(
+            [
+                ("ts_wep", "v1.2.0"),
+                ("ts_observatory_control", "0.3.1-2-g1111aaa"),
+                ("cwfs", "0.4.0"),
+                ("ts_config_ocs", "1.0.0"),
+            ]
+            .map(|(name, describe)| (name.to_owned(), RepoVersion::new(name, describe))),
+        );
+        let packages = [
+            CondaPackage::new("ts-wep", "1.2.0"),
+            CondaPackage::new("ts-observatory-control", "0.3.1"),
+            CondaPackage::new("lsst-cwfs", "0.4.0"),
+            CondaPackage::new("numpy", "1.26.4"),
+        ];
+        let mapping = BTreeMap::from([("cwfs".to_owned(), "lsst-cwfs".to_owned())]);
+
+        let comparison = compare(&versions, &packages, &mapping);
+        assert_eq!(
+            comparison.mismatches,
+            [CondaMismatch {
+                repo: "ts_observatory_control".to_owned(),
+                repo_version: "0.3.1-2-g1111aaa".to_owned(),
+                package: "ts-observatory-control".to_owned(),
+                package_version: "0.3.1".to_owned(),
+            }]
+        );
+        assert_eq!(comparison.matching, ["cwfs", "ts_wep"]);
+        assert_eq!(comparison.repos_without_package, ["ts_config_ocs"]);
+        assert_eq!(comparison.packages_without_repo, ["numpy"]);
+        assert_eq!(
+            comparison.to_string(),
+            "Repositories not matching their conda package:\nts_observatory_control: 0.3.1-2-g1111aaa (conda ts-observatory-control 0.3.1)\nRepositories without a conda package: ts_config_ocs\nConda packages without a repository: numpy"
+        );
+    }
+}
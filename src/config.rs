@@ -0,0 +1,185 @@
+use crate::{
+    error::ObsEnvError,
+    hooks::Hooks,
+    repos::{validate_repo_specs, RepoOverride, RepoSpec},
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs::read_to_string, path::Path};
+
+/// Settings read from the observing environment configuration file.
+///
+/// The file is toml, e.g.:
+///
+/// ```toml
+/// [forks]
+/// ts_wep = "tribeiro"
+///
+/// [[repositories]]
+/// name = "ts_wep"
+/// url = "https://github.com/lsst-ts/ts_wep"
+///
+/// [overrides.ts_wep]
+/// default_branch = "main"
+///
+/// [version_overrides]
+/// cwfs = "v1.2.0"
+///
+/// [conda_packages]
+/// cwfs = "lsst-cwfs"
+///
+/// [eups_products]
+/// ts_wep = "ts_wep"
+///
+/// [hooks]
+/// post_reset = "systemctl --user restart obs-env"
+/// ```
+///
+/// Top-level keys, like `notify_url = "https://hooks.slack.com/..."` or
+/// `ticket_patterns = ["DM-\\d+", "SITCOM-\\d+"]` or
+/// `outdated_ignore = ["ts_xml"]`, go before the tables.
+#[derive(Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Repositories that should be taken from a fork, mapping the
+    /// repository name to the owner of the fork.
+    pub forks: BTreeMap<String, String>,
+    /// Repositories of the environment, replacing the built-in list if
+    /// not empty.
+    pub repositories: Vec<RepoSpec>,
+    /// Changes to the url or default branch of repositories, by name.
+    pub overrides: BTreeMap<String, RepoOverride>,
+    /// Versions checked out by Reset and ApplyManifest instead of the base
+    /// or manifest version, by repository name.
+    pub version_overrides: BTreeMap<String, String>,
+    /// Conda package of the repositories whose package is not named after
+    /// them, mapping the repository name to the package name.
+    pub conda_packages: BTreeMap<String, String>,
+    /// EUPS product of the repositories listed in the tag file, mapping the
+    /// repository name to the product name.
+    pub eups_products: BTreeMap<String, String>,
+    /// Webhook notified at the end of each run.
+    pub notify_url: Option<String>,
+    /// Regular expressions of the branch names checked out from
+    /// `tickets/<name>` when they do not exist as given, instead of
+    /// `DM-\d+`.
+    pub ticket_patterns: Option<Vec<String>>,
+    /// Repositories pinned to an old version on purpose, e.g. a tag of the
+    /// manifest, that "Outdated" reports but does not count as outdated.
+    pub outdated_ignore: Vec<String>,
+    /// Commands run after the actions changing the environment.
+    pub hooks: Hooks,
+}
+
+impl Config {
+    /// Load configuration from a toml file.
+    pub fn from_file(path: &Path) -> Result<Config, ObsEnvError> {
+        let config = Config::read_file(path)?;
+        if !config.repositories.is_empty() {
+            validate_repo_specs(&config.repositories)?;
+        }
+        Ok(config)
+    }
+
+    /// Load configuration from a toml file without validating its
+    /// repositories, for "ValidateConfig" to check them.
+    pub fn read_file(path: &Path) -> Result<Config, ObsEnvError> {
+        match read_to_string(path) {
+            Ok(content) => {
+                Config::from_toml(&content).map_err(|error| ObsEnvError::InvalidConfig {
+                    message: format!("{}: {error}", path.display()),
+                })
+            }
+            Err(error) => Err(ObsEnvError::io(path, "read configuration file", error)),
+        }
+    }
+
+    fn from_toml(content: &str) -> Result<Config, toml::de::Error> {
+        toml::from_str(content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Config;
+
+    #[test]
+    fn test_config_forks() {
+        let config = Config::from_toml("[forks]\nts_wep = \"tribeiro\"\n").unwrap();
+
+        assert_eq!(config.forks.get("ts_wep").unwrap(), "tribeiro");
+    }
+
+    #[test]
+    fn test_config_repositories() {
+        let config = Config::from_toml(
+            "[[repositories]]\nname = \"ts_wep\"\nurl = \"https://github.com/lsst-ts/ts_wep\"\ndefault_branch = \"develop\"\ngroups = [\"aos\"]\n",
+        )
+        .unwrap();
+
+        assert_eq!(config.repositories.len(), 1);
+        assert_eq!(config.repositories[0].name, "ts_wep");
+        assert_eq!(
+            config.repositories[0].default_branch.as_deref(),
+            Some("develop")
+        );
+        assert_eq!(config.repositories[0].groups, ["aos"]);
+    }
+
+    #[test]
+    fn test_config_overrides() {
+        let config = Config::from_toml(
+            "[overrides.ts_wep]\nurl = \"git@github.com:lsst-ts/ts_wep.git\"\n\n[overrides.cwfs]\ndefault_branch = \"main\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.overrides["ts_wep"].url.as_deref(),
+            Some("git@github.com:lsst-ts/ts_wep.git")
+        );
+        assert_eq!(config.overrides["ts_wep"].default_branch, None);
+        assert_eq!(
+            config.overrides["cwfs"].default_branch.as_deref(),
+            Some("main")
+        );
+    }
+
+    #[test]
+    fn test_config_round_trip() {
+        let config = Config::from_toml(
+            "[forks]\nts_wep = \"tribeiro\"\n\n[conda_packages]\ncwfs = \"lsst-cwfs\"\n\n[eups_products]\nts_wep = \"ts_wep\"\n\n[[repositories]]\nname = \"ts_wep\"\nurl = \"https://github.com/lsst-ts/ts_wep\"\n\n[overrides.ts_wep]\ndefault_branch = \"main\"\n\n[version_overrides]\ncwfs = \"v1.2.0\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.version_overrides["cwfs"], "v1.2.0");
+
+        let serialized = toml::to_string(&config).unwrap();
+        assert_eq!(Config::from_toml(&serialized).unwrap(), config);
+    }
+
+    #[test]
+    fn test_config_ticket_patterns() {
+        let config = Config::from_toml("ticket_patterns = [\"SITCOM-\\\\d+\"]\n").unwrap();
+
+        assert_eq!(config.ticket_patterns.unwrap(), [r"SITCOM-\d+"]);
+        assert_eq!(Config::default().ticket_patterns, None);
+    }
+
+    #[test]
+    fn test_config_outdated_ignore() {
+        let config =
+            Config::from_toml("outdated_ignore = [\"ts_xml\"]\n\n[forks]\nts_wep = \"tribeiro\"\n")
+                .unwrap();
+
+        assert_eq!(config.outdated_ignore, ["ts_xml"]);
+        assert_eq!(config.forks["ts_wep"], "tribeiro");
+    }
+
+    #[test]
+    fn test_config_empty() {
+        assert_eq!(Config::from_toml("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn test_config_unknown_field() {
+        assert!(Config::from_toml("unknown = 1").is_err());
+    }
+}
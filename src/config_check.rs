@@ -0,0 +1,309 @@
+//! Checks of the effective configuration of the repositories, made by the
+//! "ValidateConfig" action so that a typo in a configuration file shows up
+//! before an action fails on it.
+use crate::{
+    config::Config,
+    repos::{is_valid_subdirectory, is_valid_url, RepoSpec},
+};
+use regex::Regex;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+};
+
+/// Outcome of a check of the configuration.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ConfigCheck {
+    /// What is checked, e.g. "repository names are unique".
+    pub check: String,
+    pub passed: bool,
+    /// What is wrong, nothing if the check passed.
+    pub problems: Vec<String>,
+}
+
+impl ConfigCheck {
+    /// Check of `check`, failed if there are `problems`.
+    pub fn new(check: &str, problems: Vec<String>) -> ConfigCheck {
+        ConfigCheck {
+            check: check.to_owned(),
+            passed: problems.is_empty(),
+            problems,
+        }
+    }
+}
+
+impl Display for ConfigCheck {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.passed {
+            true => write!(f, "pass: {}", self.check),
+            false => write!(f, "FAIL: {}: {}", self.check, self.problems.join("; ")),
+        }
+    }
+}
+
+/// Repositories and groups selected on the command line.
+#[derive(Clone, Debug, Default)]
+pub struct Selection {
+    pub only: Vec<String>,
+    pub groups: Vec<String>,
+    pub excluded: Vec<String>,
+}
+
+/// Check `repo_specs`, the repository list, and the settings of `config`
+/// and `selection` naming its repositories and groups, without network
+/// access.
+pub fn check_config(
+    repo_specs: &[RepoSpec],
+    config: &Config,
+    selection: &Selection,
+) -> Vec<ConfigCheck> {
+    let mut names: BTreeMap<&str, usize> = BTreeMap::new();
+    for repo_spec in repo_specs {
+        *names.entry(repo_spec.name.as_str()).or_default() += 1;
+    }
+    let mut name_problems: Vec<String> = names
+        .iter()
+        .filter(|(name, count)| !name.is_empty() && **count > 1)
+        .map(|(name, count)| format!("{name} is given {count} times"))
+        .collect();
+    if names.contains_key("") {
+        name_problems.insert(0, "a repository has no name".to_owned());
+    }
+    if repo_specs.is_empty() {
+        name_problems.push("the list has no repository".to_owned());
+    }
+
+    let mut url_problems = Vec::new();
+    for repo_spec in repo_specs {
+        if !is_valid_url(&repo_spec.url) {
+            url_problems.push(format!(
+                "{}: malformed url {}",
+                repo_spec.name, repo_spec.url
+            ));
+        }
+    }
+    for (repo_name, repo_override) in config.overrides.iter() {
+        if let Some(url) = repo_override
+            .url
+            .as_deref()
+            .filter(|url| !is_valid_url(url))
+        {
+            url_problems.push(format!("{repo_name}: malformed url {url} in overrides"));
+        }
+    }
+    for (repo_name, owner) in config.forks.iter() {
+        if owner.is_empty() || owner.contains(|c: char| c == '/' || c.is_whitespace()) {
+            url_problems.push(format!("{repo_name}: malformed fork owner \"{owner}\""));
+        }
+    }
+
+    let mut group_problems = Vec::new();
+    for repo_spec in repo_specs {
+        let mut groups: BTreeMap<&str, usize> = BTreeMap::new();
+        for group in repo_spec.groups.iter() {
+            *groups.entry(group.as_str()).or_default() += 1;
+        }
+        for (group, count) in groups {
+            match (group, count) {
+                ("", _) => group_problems.push(format!("{}: empty group name", repo_spec.name)),
+                (_, 1) => {}
+                _ => group_problems.push(format!(
+                    "{}: group {group} is given {count} times",
+                    repo_spec.name
+                )),
+            }
+        }
+    }
+
+    let mut subdirectories: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for repo_spec in repo_specs {
+        subdirectories
+            .entry(repo_spec.subdirectory())
+            .or_default()
+            .push(&repo_spec.name);
+    }
+    let mut path_problems = Vec::new();
+    for (subdirectory, repo_names) in subdirectories {
+        if !is_valid_subdirectory(subdirectory) {
+            path_problems.push(format!(
+                "{}: {subdirectory} is not a subdirectory of the environment",
+                repo_names.join(", ")
+            ));
+        } else if repo_names.len() > 1 && repo_names.iter().any(|name| *name != subdirectory) {
+            path_problems.push(format!(
+                "{subdirectory} is the path of {}",
+                repo_names.join(", ")
+            ));
+        }
+    }
+
+    let references = [
+        ("forks", config.forks.keys().collect::<Vec<_>>()),
+        ("overrides", config.overrides.keys().collect()),
+        (
+            "version_overrides",
+            config.version_overrides.keys().collect(),
+        ),
+        ("conda_packages", config.conda_packages.keys().collect()),
+        ("eups_products", config.eups_products.keys().collect()),
+        ("outdated_ignore", config.outdated_ignore.iter().collect()),
+        ("--only", selection.only.iter().collect()),
+        ("--exclude", selection.excluded.iter().collect()),
+    ];
+    let mut repo_problems = Vec::new();
+    for (setting, repo_names) in references {
+        for repo_name in repo_names {
+            if !names.contains_key(repo_name.as_str()) {
+                repo_problems.push(format!("{setting}: {repo_name} is not in the list"));
+            }
+        }
+    }
+    for (repo_name, version) in config.version_overrides.iter() {
+        if version.is_empty() {
+            repo_problems.push(format!(
+                "version_overrides: {repo_name} has an empty version"
+            ));
+        }
+    }
+
+    let group_references: Vec<String> = selection
+        .groups
+        .iter()
+        .filter(|group| {
+            !repo_specs
+                .iter()
+                .any(|repo_spec| repo_spec.groups.contains(group))
+        })
+        .map(|group| format!("--group: no repository belongs to {group}"))
+        .collect();
+
+    let pattern_problems: Vec<String> = config
+        .ticket_patterns
+        .iter()
+        .flatten()
+        .filter_map(|pattern| {
+            Regex::new(&format!("^(?:{pattern})$"))
+                .err()
+                // The last line of the message says what is wrong, the
+                // others point at where.
+                .map(|error| {
+                    let error = error.to_string();
+                    format!("{pattern}: {}", error.lines().last().unwrap_or_default())
+                })
+        })
+        .collect();
+
+    vec![
+        ConfigCheck::new("repository names are set and unique", name_problems),
+        ConfigCheck::new("urls are well formed", url_problems),
+        ConfigCheck::new(
+            "groups of each repository are named and unique",
+            group_problems,
+        ),
+        ConfigCheck::new(
+            "paths are unique subdirectories of the environment",
+            path_problems,
+        ),
+        ConfigCheck::new("referenced repositories exist", repo_problems),
+        ConfigCheck::new("referenced groups exist", group_references),
+        ConfigCheck::new(
+            "ticket patterns are valid regular expressions",
+            pattern_problems,
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_config, ConfigCheck, Selection};
+    use crate::{
+        config::Config,
+        repos::{RepoOverride, RepoSpec},
+    };
+
+    #[test]
+    fn test_check_config() {
+        let mut ts_wep = RepoSpec::new("ts_wep", "https://github.com/lsst-ts/ts_wep");
+        ts_wep.groups = vec!["aos".to_owned(), "aos".to_owned()];
+        let mut ts_xml = RepoSpec::new("ts_xml", "https://github.com/lsst-ts/ts_xml");
+        ts_xml.path = Some("../ts_xml".to_owned());
+        let repo_specs = [
+            ts_wep,
+            ts_xml,
+            RepoSpec::new("cwfs", "github.com/lsst-ts/cwfs"),
+            RepoSpec::new("cwfs", "https://github.com/lsst-ts/cwfs"),
+        ];
+        let mut config = Config::default();
+        config
+            .forks
+            .insert("ts_wepp".to_owned(), "tribeiro".to_owned());
+        config.overrides.insert(
+            "ts_wep".to_owned(),
+            RepoOverride {
+                url: Some("not a url".to_owned()),
+                ..RepoOverride::default()
+            },
+        );
+        config.ticket_patterns = Some(vec!["DM-(\\d+".to_owned()]);
+        let selection = Selection {
+            groups: vec!["aos".to_owned(), "sitcom".to_owned()],
+            ..Selection::default()
+        };
+
+        let checks = check_config(&repo_specs, &config, &selection);
+        let problems: Vec<(&str, Vec<&str>)> = checks
+            .iter()
+            .map(|check| {
+                (
+                    check.check.as_str(),
+                    check.problems.iter().map(String::as_str).collect(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            problems[..6],
+            [
+                (
+                    "repository names are set and unique",
+                    vec!["cwfs is given 2 times"]
+                ),
+                (
+                    "urls are well formed",
+                    vec![
+                        "cwfs: malformed url github.com/lsst-ts/cwfs",
+                        "ts_wep: malformed url not a url in overrides"
+                    ]
+                ),
+                (
+                    "groups of each repository are named and unique",
+                    vec!["ts_wep: group aos is given 2 times"]
+                ),
+                (
+                    "paths are unique subdirectories of the environment",
+                    vec!["ts_xml: ../ts_xml is not a subdirectory of the environment"]
+                ),
+                (
+                    "referenced repositories exist",
+                    vec!["forks: ts_wepp is not in the list"]
+                ),
+                (
+                    "referenced groups exist",
+                    vec!["--group: no repository belongs to sitcom"]
+                ),
+            ]
+        );
+        assert!(!checks[6].passed);
+        assert_eq!(
+            checks[5].to_string(),
+            "FAIL: referenced groups exist: --group: no repository belongs to sitcom"
+        );
+
+        let checks = check_config(&repo_specs[..1], &Config::default(), &Selection::default());
+        assert_eq!(
+            checks[4],
+            ConfigCheck::new("referenced repositories exist", Vec::new())
+        );
+        assert_eq!(checks[4].to_string(), "pass: referenced repositories exist");
+    }
+}
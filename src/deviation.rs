@@ -0,0 +1,86 @@
+//! Repositories not at their base environment version, found by
+//! [`ObservingEnvironment::base_deviations`](crate::ObservingEnvironment::base_deviations)
+//! for the "Diff" action, instead of comparing "ShowCurrentVersions" and
+//! "ShowOriginalVersions" by eye.
+use crate::manifest::{AheadBehind, RepoVersion};
+use serde::Serialize;
+use std::fmt::{self, Display};
+
+/// Repository of the environment that is not at its base version, or has
+/// local changes.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BaseDeviation {
+    pub repo: String,
+    /// Version of the base environment.
+    pub base: String,
+    /// Version checked out, none if the repository is not cloned.
+    pub current: Option<RepoVersion>,
+    /// Commits HEAD has that the base version has not, and the other way
+    /// round, or none if the base version is not in the repository as of
+    /// its last fetch.
+    pub ahead_behind: Option<AheadBehind>,
+}
+
+impl Display for BaseDeviation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Some(current) = &self.current else {
+            return write!(f, "{}: not cloned, base {}", self.repo, self.base);
+        };
+        let at = current.branch.as_deref().unwrap_or(&current.describe);
+        match &current.sha {
+            Some(sha) => write!(f, "{}: {at} at {}", self.repo, &sha[..sha.len().min(12)])?,
+            None => write!(f, "{}: {at}", self.repo)?,
+        }
+        if current.dirty {
+            write!(f, ", dirty")?;
+        }
+        match &self.ahead_behind {
+            Some(ahead_behind) => write!(f, ", {ahead_behind} from base {}", self.base),
+            None => write!(f, ", base {} not fetched", self.base),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BaseDeviation;
+    use crate::manifest::{AheadBehind, RepoVersion};
+
+    #[test]
+    fn test_base_deviation() {
+        let mut current = RepoVersion::new("ts_wep", "v1.2.0-3-g0123456");
+        current.branch = Some("tickets/DM-12345".to_owned());
+        current.sha = Some("0123456789abcdef0123".to_owned());
+        let deviation = BaseDeviation {
+            repo: "ts_wep".to_owned(),
+            base: "v1.2.0".to_owned(),
+            current: Some(current.clone()),
+            ahead_behind: Some(AheadBehind {
+                ahead: 3,
+                behind: 0,
+            }),
+        };
+        assert_eq!(
+            deviation.to_string(),
+            "ts_wep: tickets/DM-12345 at 0123456789ab, +3/-0 from base v1.2.0"
+        );
+
+        current.branch = None;
+        current.dirty = true;
+        let detached = BaseDeviation {
+            current: Some(current),
+            ahead_behind: None,
+            ..deviation.clone()
+        };
+        assert_eq!(
+            detached.to_string(),
+            "ts_wep: v1.2.0-3-g0123456 at 0123456789ab, dirty, base v1.2.0 not fetched"
+        );
+
+        let missing = BaseDeviation {
+            current: None,
+            ..deviation
+        };
+        assert_eq!(missing.to_string(), "ts_wep: not cloned, base v1.2.0");
+    }
+}
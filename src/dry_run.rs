@@ -0,0 +1,638 @@
+//! Planning the changes of an operation without making them, for
+//! `--dry-run`: [`DryRunBackend`] records the operations that would change
+//! the repositories instead of carrying them out, so they can be shown as
+//! the equivalent git commands.
+use crate::git_backend::{
+    CloneOptions, CommitInfo, CommitQuery, CommitSummary, GitBackend, GitOperation, Identity,
+    RepoStatus, TransferObserver,
+};
+use git2::Error;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::{self, Display},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+#[derive(Debug, Default)]
+struct DryRunState {
+    /// Operations recorded, in order, with the path of their repository.
+    operations: Vec<(PathBuf, GitOperation)>,
+    /// Repositories that would have been cloned.
+    cloned: BTreeSet<PathBuf>,
+    /// Where HEAD was in the existing repositories before their first
+    /// operation.
+    heads: BTreeMap<PathBuf, Head>,
+}
+
+/// Where HEAD of a repository is.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Head {
+    /// Local branch checked out, or none if HEAD is detached.
+    pub branch: Option<String>,
+    pub commit: String,
+}
+
+impl Display for Head {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let commit = &self.commit[..self.commit.len().min(12)];
+        match &self.branch {
+            Some(branch) => write!(f, "{branch} at {commit}"),
+            None => write!(f, "detached at {commit}"),
+        }
+    }
+}
+
+/// [`GitBackend`] reading repositories through `inner` but only recording
+/// the operations that would change them.
+///
+/// Fetches are the exception: they only bring refs from origin, and are
+/// carried out so that versions resolve as they would for real, besides
+/// being recorded. Repositories that would be cloned look cloned, with no
+/// branch checked out and every revision resolving to itself. Paths under
+/// those given to [`pass_through`](DryRunBackend::pass_through), like the
+/// caches of the environment, are left to `inner` entirely.
+///
+/// Clones of the backend share the same record.
+#[derive(Clone, Debug)]
+pub struct DryRunBackend<B> {
+    inner: B,
+    pass_through: Vec<PathBuf>,
+    state: Arc<Mutex<DryRunState>>,
+}
+
+/// Operations recorded by a [`DryRunBackend`], by repository path.
+///
+/// It is displayed as the git commands doing the same, grouped by
+/// repository, each after where HEAD currently is.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DryRunPlan {
+    pub repos: BTreeMap<PathBuf, Vec<GitOperation>>,
+    /// Where HEAD is in the repositories already cloned, before the
+    /// operations.
+    pub heads: BTreeMap<PathBuf, Head>,
+}
+
+impl DryRunPlan {
+    /// Whether no operation would change a repository.
+    pub fn is_empty(&self) -> bool {
+        self.repos.is_empty()
+    }
+}
+
+impl Display for DryRunPlan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, (path, operations)) in self.repos.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            match self.heads.get(path) {
+                Some(head) => writeln!(f, "# {} ({head})", path.display())?,
+                None => writeln!(f, "# {}", path.display())?,
+            }
+            for operation in operations {
+                for command in operation.to_commands(path) {
+                    writeln!(f, "{command}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<B: GitBackend> DryRunBackend<B> {
+    /// Backend recording the changes and reading through `inner`.
+    pub fn new(inner: B) -> DryRunBackend<B> {
+        DryRunBackend {
+            inner,
+            pass_through: Vec::new(),
+            state: Arc::default(),
+        }
+    }
+
+    /// Carry out every operation on the repositories under `path`.
+    pub fn pass_through(mut self, path: impl Into<PathBuf>) -> Self {
+        self.pass_through.push(path.into());
+        self
+    }
+
+    /// Operations recorded so far.
+    pub fn plan(&self) -> DryRunPlan {
+        let state = self.state();
+        let mut plan = DryRunPlan {
+            heads: state.heads.clone(),
+            ..DryRunPlan::default()
+        };
+        for (path, operation) in state.operations.iter() {
+            plan.repos
+                .entry(path.clone())
+                .or_default()
+                .push(operation.clone());
+        }
+        plan
+    }
+
+    fn state(&self) -> MutexGuard<'_, DryRunState> {
+        self.state.lock().unwrap_or_else(|error| error.into_inner())
+    }
+
+    fn passes_through(&self, path: &Path) -> bool {
+        self.pass_through
+            .iter()
+            .any(|prefix| path.starts_with(prefix))
+    }
+
+    fn is_planned_clone(&self, path: &Path) -> bool {
+        self.state().cloned.contains(path)
+    }
+
+    fn record(&self, path: &Path, operation: GitOperation) {
+        let recorded = self.state().operations.iter().any(|(seen, _)| seen == path);
+        let head = match recorded || self.is_planned_clone(path) {
+            true => None,
+            false => self.inner.rev_parse(path, "HEAD").ok().map(|commit| Head {
+                branch: self.inner.current_branch(path).ok().flatten(),
+                commit,
+            }),
+        };
+        let mut state = self.state();
+        if let Some(head) = head {
+            state.heads.insert(path.to_path_buf(), head);
+        }
+        state.operations.push((path.to_path_buf(), operation));
+    }
+
+    /// Record `operation` unless `path` passes through, in which case
+    /// `carry_out` is called.
+    fn change(
+        &self,
+        path: &Path,
+        operation: GitOperation,
+        carry_out: impl FnOnce() -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        if self.passes_through(path) {
+            carry_out()
+        } else {
+            self.record(path, operation);
+            Ok(())
+        }
+    }
+}
+
+impl<B: GitBackend> GitBackend for DryRunBackend<B> {
+    fn open(&self, path: &Path) -> Result<(), Error> {
+        match self.is_planned_clone(path) {
+            true => Ok(()),
+            false => self.inner.open(path),
+        }
+    }
+
+    fn is_empty(&self, path: &Path) -> Result<bool, Error> {
+        match self.is_planned_clone(path) {
+            true => Ok(false),
+            false => self.inner.is_empty(path),
+        }
+    }
+
+    fn clone(
+        &self,
+        url: &str,
+        path: &Path,
+        bare: bool,
+        options: CloneOptions,
+        progress: &dyn TransferObserver,
+    ) -> Result<(), Error> {
+        if self.passes_through(path) {
+            return self.inner.clone(url, path, bare, options, progress);
+        }
+        self.record(
+            path,
+            GitOperation::Clone {
+                url: url.to_owned(),
+                bare,
+                options,
+            },
+        );
+        self.state().cloned.insert(path.to_path_buf());
+        Ok(())
+    }
+
+    fn clone_branch(
+        &self,
+        url: &str,
+        path: &Path,
+        branch: &str,
+        options: CloneOptions,
+        progress: &dyn TransferObserver,
+    ) -> Result<(), Error> {
+        if self.passes_through(path) {
+            return self
+                .inner
+                .clone_branch(url, path, branch, options, progress);
+        }
+        self.record(
+            path,
+            GitOperation::CloneBranch {
+                url: url.to_owned(),
+                branch: branch.to_owned(),
+                options,
+            },
+        );
+        self.state().cloned.insert(path.to_path_buf());
+        Ok(())
+    }
+
+    fn fetch(
+        &self,
+        path: &Path,
+        refspecs: &[&str],
+        download_tags: bool,
+        progress: &dyn TransferObserver,
+    ) -> Result<(), Error> {
+        if !self.is_planned_clone(path) {
+            self.inner.fetch(path, refspecs, download_tags, progress)?;
+        }
+        if !self.passes_through(path) {
+            self.record(
+                path,
+                GitOperation::Fetch {
+                    refspecs: refspecs.iter().map(|refspec| refspec.to_string()).collect(),
+                    download_tags,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    fn is_shallow(&self, path: &Path) -> Result<bool, Error> {
+        match self.is_planned_clone(path) {
+            true => Ok(false),
+            false => self.inner.is_shallow(path),
+        }
+    }
+
+    fn deepen(
+        &self,
+        path: &Path,
+        depth: Option<u32>,
+        progress: &dyn TransferObserver,
+    ) -> Result<(), Error> {
+        // Like fetches, deepening only brings objects, which the plan needs
+        // to resolve what would be checked out.
+        if !self.is_planned_clone(path) {
+            self.inner.deepen(path, depth, progress)?;
+        }
+        if !self.passes_through(path) {
+            self.record(path, GitOperation::Deepen { depth });
+        }
+        Ok(())
+    }
+
+    fn checkout_branch(&self, path: &Path, branch: &str) -> Result<(), Error> {
+        let operation = GitOperation::CheckoutBranch {
+            branch: branch.to_owned(),
+        };
+        self.change(path, operation, || self.inner.checkout_branch(path, branch))
+    }
+
+    fn fast_forward(&self, path: &Path, branch: &str) -> Result<(), Error> {
+        let operation = GitOperation::FastForward {
+            branch: branch.to_owned(),
+        };
+        self.change(path, operation, || self.inner.fast_forward(path, branch))
+    }
+
+    fn reset(&self, path: &Path, revision: &str, branch: Option<&str>) -> Result<(), Error> {
+        let operation = GitOperation::Reset {
+            revision: revision.to_owned(),
+            branch: branch.map(str::to_owned),
+        };
+        self.change(path, operation, || self.inner.reset(path, revision, branch))
+    }
+
+    fn rev_parse(&self, path: &Path, spec: &str) -> Result<String, Error> {
+        match self.is_planned_clone(path) {
+            true => Ok(spec.to_owned()),
+            false => self.inner.rev_parse(path, spec),
+        }
+    }
+
+    fn status(&self, path: &Path) -> Result<RepoStatus, Error> {
+        match self.is_planned_clone(path) {
+            true => Ok(RepoStatus::default()),
+            false => self.inner.status(path),
+        }
+    }
+
+    fn abort_in_progress(&self, path: &Path) -> Result<(), Error> {
+        let operation = GitOperation::AbortInProgress {
+            in_progress: self.status(path)?.in_progress,
+        };
+        self.change(path, operation, || self.inner.abort_in_progress(path))
+    }
+
+    fn list_refs(&self, path: &Path, glob: &str) -> Result<Vec<String>, Error> {
+        match self.is_planned_clone(path) {
+            true => Ok(Vec::new()),
+            false => self.inner.list_refs(path, glob),
+        }
+    }
+
+    fn describe(&self, path: &Path) -> Result<String, Error> {
+        self.inner.describe(path)
+    }
+
+    fn current_branch(&self, path: &Path) -> Result<Option<String>, Error> {
+        match self.is_planned_clone(path) {
+            true => Ok(None),
+            false => self.inner.current_branch(path),
+        }
+    }
+
+    fn remote_head(&self, path: &Path) -> Result<Option<String>, Error> {
+        match self.is_planned_clone(path) {
+            true => Ok(None),
+            false => self.inner.remote_head(path),
+        }
+    }
+
+    fn local_commits(&self, path: &Path) -> Result<usize, Error> {
+        match self.is_planned_clone(path) {
+            true => Ok(0),
+            false => self.inner.local_commits(path),
+        }
+    }
+
+    fn check_objects(&self, path: &Path) -> Result<Vec<String>, Error> {
+        match self.is_planned_clone(path) {
+            true => Ok(Vec::new()),
+            false => self.inner.check_objects(path),
+        }
+    }
+
+    fn commits_only_in(
+        &self,
+        path: &Path,
+        revision: &str,
+        hidden: &[&str],
+    ) -> Result<Vec<String>, Error> {
+        match self.is_planned_clone(path) {
+            true => Ok(Vec::new()),
+            false => self.inner.commits_only_in(path, revision, hidden),
+        }
+    }
+
+    fn ahead_behind(
+        &self,
+        path: &Path,
+        local: &str,
+        upstream: &str,
+    ) -> Result<(usize, usize), Error> {
+        match self.is_planned_clone(path) {
+            true => Ok((0, 0)),
+            false => self.inner.ahead_behind(path, local, upstream),
+        }
+    }
+
+    fn commit_log(&self, path: &Path, from: &str, to: &str) -> Result<Vec<CommitSummary>, Error> {
+        match self.is_planned_clone(path) {
+            true => Ok(Vec::new()),
+            false => self.inner.commit_log(path, from, to),
+        }
+    }
+
+    fn search_commits(&self, path: &Path, query: &CommitQuery) -> Result<Vec<CommitInfo>, Error> {
+        match self.is_planned_clone(path) {
+            true => Ok(Vec::new()),
+            false => self.inner.search_commits(path, query),
+        }
+    }
+
+    fn commit_time(&self, path: &Path, revision: &str) -> Result<u64, Error> {
+        self.inner.commit_time(path, revision)
+    }
+
+    fn commit_before(
+        &self,
+        path: &Path,
+        revision: &str,
+        time: u64,
+    ) -> Result<Option<String>, Error> {
+        match self.is_planned_clone(path) {
+            true => Ok(Some(revision.to_owned())),
+            false => self.inner.commit_before(path, revision, time),
+        }
+    }
+
+    fn create_branch(&self, path: &Path, branch: &str, revision: &str) -> Result<(), Error> {
+        let operation = GitOperation::CreateBranch {
+            branch: branch.to_owned(),
+            revision: revision.to_owned(),
+        };
+        self.change(path, operation, || {
+            self.inner.create_branch(path, branch, revision)
+        })
+    }
+
+    fn delete_branch(&self, path: &Path, branch: &str) -> Result<(), Error> {
+        let operation = GitOperation::DeleteBranch {
+            branch: branch.to_owned(),
+        };
+        self.change(path, operation, || self.inner.delete_branch(path, branch))
+    }
+
+    fn delete_reference(&self, path: &Path, name: &str) -> Result<(), Error> {
+        let operation = GitOperation::DeleteReference {
+            name: name.to_owned(),
+        };
+        self.change(path, operation, || self.inner.delete_reference(path, name))
+    }
+
+    fn remote_branches(&self, path: &Path) -> Result<Vec<String>, Error> {
+        match self.is_planned_clone(path) {
+            true => Ok(Vec::new()),
+            false => self.inner.remote_branches(path),
+        }
+    }
+
+    fn stale_remote_refs(&self, path: &Path) -> Result<Vec<String>, Error> {
+        match self.is_planned_clone(path) {
+            true => Ok(Vec::new()),
+            false => self.inner.stale_remote_refs(path),
+        }
+    }
+
+    fn ls_remote(&self, url: &str) -> Result<Vec<String>, Error> {
+        self.inner.ls_remote(url)
+    }
+
+    fn read_file(&self, path: &Path, revision: &str, file: &Path) -> Result<String, Error> {
+        self.inner.read_file(path, revision, file)
+    }
+
+    fn remote_url(&self, path: &Path) -> Result<Option<String>, Error> {
+        self.inner.remote_url(path)
+    }
+
+    fn set_remote_url(&self, path: &Path, url: &str) -> Result<(), Error> {
+        let operation = GitOperation::SetRemoteUrl {
+            url: url.to_owned(),
+        };
+        self.change(path, operation, || self.inner.set_remote_url(path, url))
+    }
+
+    fn identity(&self, path: &Path) -> Result<Option<Identity>, Error> {
+        match self.is_planned_clone(path) {
+            true => Ok(None),
+            false => self.inner.identity(path),
+        }
+    }
+
+    fn config(&self, path: &Path, name: &str) -> Result<Option<String>, Error> {
+        match self.is_planned_clone(path) {
+            true => Ok(None),
+            false => self.inner.config(path, name),
+        }
+    }
+
+    fn set_config(&self, path: &Path, name: &str, value: &str) -> Result<(), Error> {
+        let operation = GitOperation::SetConfig {
+            name: name.to_owned(),
+            value: value.to_owned(),
+        };
+        self.change(path, operation, || self.inner.set_config(path, name, value))
+    }
+
+    fn push(&self, path: &Path, url: &str, refspecs: &[String]) -> Result<(), Error> {
+        let operation = GitOperation::Push {
+            url: url.to_owned(),
+            refspecs: refspecs.to_vec(),
+        };
+        self.change(path, operation, || self.inner.push(path, url, refspecs))
+    }
+
+    fn diff_head(&self, path: &Path) -> Result<String, Error> {
+        match self.is_planned_clone(path) {
+            true => Ok(String::new()),
+            false => self.inner.diff_head(path),
+        }
+    }
+
+    fn apply_patch(&self, path: &Path, patch: &str) -> Result<(), Error> {
+        let operation = GitOperation::ApplyPatch {
+            patch: patch.to_owned(),
+        };
+        self.change(path, operation, || self.inner.apply_patch(path, patch))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DryRunBackend;
+    use crate::{
+        git_backend::{CloneFilter, CloneOptions, GitOperation},
+        testing::FakeBackend,
+        ObservingEnvironment,
+    };
+    use std::path::Path;
+
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    #[test]
+    fn test_dry_run_plan() -> TestResult {
+        let fake = FakeBackend::new();
+        fake.set_branch("https://example.com/lsst-ts/ts_wep", "develop", "1111aaaa");
+        fake.set_tag("https://example.com/lsst-ts/ts_wep", "v1.2.0", "2222bbbb");
+        fake.set_branch("https://example.com/lsst-ts/cwfs", "main", "3333cccc");
+        let setup = ObservingEnvironment::builder()
+            .destination("/obs-env")
+            .repositories([("ts_wep", "https://example.com/lsst-ts/")])
+            .backend(fake.clone())
+            .build()?;
+        setup.clone_repositories().into_result()?;
+
+        let backend = DryRunBackend::new(fake.clone());
+        let obs_env = ObservingEnvironment::builder()
+            .destination("/obs-env")
+            .repositories([
+                ("ts_wep", "https://example.com/lsst-ts/"),
+                ("cwfs", "https://example.com/lsst-ts/"),
+            ])
+            .backend(backend.clone())
+            .build()?;
+        obs_env.clone_repositories().into_result()?;
+        obs_env.reset_index_to_version("ts_wep", "1.2.0")?;
+
+        assert_eq!(
+            backend.plan().to_string(),
+            concat!(
+                "# /obs-env/cwfs\n",
+                "git clone https://example.com/lsst-ts/cwfs /obs-env/cwfs\n",
+                "\n",
+                "# /obs-env/ts_wep (develop at 1111aaaa)\n",
+                "git -C /obs-env/ts_wep fetch origin +refs/tags/v1.2.0:refs/tags/v1.2.0 +refs/tags/1.2.0:refs/tags/1.2.0\n",
+                "git -C /obs-env/ts_wep branch --force 1.2.0 refs/tags/v1.2.0\n",
+                "git -C /obs-env/ts_wep checkout --force --detach refs/tags/v1.2.0\n",
+            )
+        );
+        assert_eq!(
+            fake.head(Path::new("/obs-env/ts_wep")).as_deref(),
+            Some("1111aaaa")
+        );
+        assert!(fake.head(Path::new("/obs-env/cwfs")).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_git_operation_commands() {
+        let path = Path::new("/net/obs env/ts_wep");
+        let commands = |operation: GitOperation| operation.to_commands(path);
+        assert_eq!(
+            commands(GitOperation::Clone {
+                url: "https://example.com/lsst-ts/ts_wep".to_owned(),
+                bare: true,
+                options: CloneOptions {
+                    depth: Some(1),
+                    filter: None,
+                },
+            }),
+            ["git clone --bare --depth 1 https://example.com/lsst-ts/ts_wep '/net/obs env/ts_wep'"]
+        );
+        assert_eq!(
+            commands(GitOperation::CloneBranch {
+                url: "https://example.com/lsst-ts/ts_wep".to_owned(),
+                branch: "develop".to_owned(),
+                options: CloneOptions {
+                    depth: None,
+                    filter: Some(CloneFilter::Blobless),
+                },
+            }),
+            ["git clone --single-branch --branch develop --filter=blob:none https://example.com/lsst-ts/ts_wep '/net/obs env/ts_wep'"]
+        );
+        assert_eq!(
+            commands(GitOperation::Fetch {
+                refspecs: vec![String::new()],
+                download_tags: true,
+            }),
+            ["git -C '/net/obs env/ts_wep' fetch --tags origin"]
+        );
+        assert_eq!(
+            commands(GitOperation::CheckoutBranch {
+                branch: "tickets/DM-1234".to_owned(),
+            }),
+            ["git -C '/net/obs env/ts_wep' checkout --force -B tickets/DM-1234 origin/tickets/DM-1234"]
+        );
+        assert_eq!(
+            commands(GitOperation::AbortInProgress {
+                in_progress: Some("rebase".to_owned()),
+            }),
+            ["git -C '/net/obs env/ts_wep' rebase --abort"]
+        );
+        assert_eq!(
+            commands(GitOperation::SetRemoteUrl {
+                url: "it's".to_owned(),
+            }),
+            ["git -C '/net/obs env/ts_wep' remote set-url origin 'it'\\''s'"]
+        );
+    }
+}
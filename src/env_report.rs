@@ -0,0 +1,527 @@
+//! Summary of the state of an environment as a single self-contained
+//! Markdown or HTML document, e.g. for a weekly report of what is
+//! deployed, collected by
+//! [`ObservingEnvironment::env_report`](crate::ObservingEnvironment::env_report).
+use crate::{
+    audit::{format_utc, AuditEntry, HistoryFilter},
+    manifest::{RepoDifference, RepoVersion},
+    metadata::EnvMetadata,
+    outdated::{OutdatedReport, OutdatedThresholds},
+};
+use clap::ValueEnum;
+use std::{collections::BTreeMap, fmt::Write};
+
+/// Format of the document an [`EnvReport`] is rendered to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum ReportFormat {
+    #[default]
+    Markdown,
+    /// A standalone page, with its style inline.
+    Html,
+}
+
+/// Part of an [`EnvReport`], in the order they appear in it.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ReportSection {
+    /// Versions checked out in the repositories.
+    Versions,
+    /// Repositories not at their base environment version.
+    BaseDiff,
+    /// Repositories with local changes.
+    Dirty,
+    /// Repositories trailing the default branch of origin. Fetches it.
+    Outdated,
+    /// Last Setup, Reset or ApplyManifest.
+    LastReset,
+    /// Most recent entries of the audit log.
+    History,
+}
+
+impl ReportSection {
+    /// Every section, in order.
+    pub fn all() -> Vec<ReportSection> {
+        ReportSection::value_variants().to_vec()
+    }
+}
+
+/// What goes in an [`EnvReport`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReportOptions {
+    pub sections: Vec<ReportSection>,
+    /// Thresholds of the [`Outdated`](ReportSection::Outdated) section.
+    pub thresholds: OutdatedThresholds,
+    /// Repositories pinned on purpose, not counted as outdated.
+    pub outdated_ignore: Vec<String>,
+    /// Entries of the [`History`](ReportSection::History) section.
+    pub history: HistoryFilter,
+}
+
+impl Default for ReportOptions {
+    fn default() -> Self {
+        ReportOptions {
+            sections: ReportSection::all(),
+            thresholds: OutdatedThresholds::default(),
+            outdated_ignore: Vec::new(),
+            history: HistoryFilter {
+                limit: Some(20),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// Data of a section of an [`EnvReport`]. What could not be read is kept
+/// as the report of its error, so the rest of the report is still made.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SectionContent {
+    /// Version of each repository, by name.
+    Versions(BTreeMap<String, Result<RepoVersion, String>>),
+    /// Repositories whose version is not the base one, or have local
+    /// changes, from the base version to the current one.
+    BaseDiff(Result<Vec<RepoDifference>, String>),
+    /// Versions of the repositories with local changes or untracked files.
+    Dirty(Vec<RepoVersion>),
+    /// Staleness of the repositories, and the errors of those that could
+    /// not be compared with their default branch.
+    Outdated(OutdatedReport, Vec<String>),
+    /// Metadata of the last operation that set the versions, if any.
+    LastReset(Result<Option<EnvMetadata>, String>),
+    History(Result<Vec<AuditEntry>, String>),
+}
+
+/// State of the environment at `env_path` at `time`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EnvReport {
+    pub env_path: String,
+    /// When the report was made, in seconds since the Unix epoch.
+    pub time: u64,
+    pub sections: Vec<SectionContent>,
+}
+
+/// Piece of a document, rendered the same way in every format.
+enum Block {
+    Heading(u8, String),
+    Paragraph(String),
+    List(Vec<String>),
+    Table(Vec<&'static str>, Vec<Vec<String>>),
+}
+
+impl EnvReport {
+    /// The report in `format`.
+    pub fn render(&self, format: ReportFormat) -> String {
+        match format {
+            ReportFormat::Markdown => self.to_markdown(),
+            ReportFormat::Html => self.to_html(),
+        }
+    }
+
+    /// The report as a Markdown document, with GitHub flavored tables.
+    pub fn to_markdown(&self) -> String {
+        let mut content = String::new();
+        for block in self.blocks() {
+            // Writing to a string cannot fail.
+            let _ = match block {
+                Block::Heading(level, text) => {
+                    writeln!(content, "{} {}\n", "#".repeat(level.into()), text)
+                }
+                Block::Paragraph(text) => writeln!(content, "{text}\n"),
+                Block::List(items) => {
+                    for item in items {
+                        let _ = writeln!(content, "- {item}");
+                    }
+                    writeln!(content)
+                }
+                Block::Table(header, rows) => {
+                    let row = |cells: Vec<String>| {
+                        let cells: Vec<String> =
+                            cells.iter().map(|cell| cell.replace('|', "\\|")).collect();
+                        format!("| {} |", cells.join(" | "))
+                    };
+                    let _ = writeln!(
+                        content,
+                        "{}\n|{}",
+                        row(header.iter().map(|cell| cell.to_string()).collect()),
+                        "---|".repeat(header.len())
+                    );
+                    for cells in rows {
+                        let _ = writeln!(content, "{}", row(cells));
+                    }
+                    writeln!(content)
+                }
+            };
+        }
+        content.trim_end().to_owned() + "\n"
+    }
+
+    /// The report as an HTML page needing no other file.
+    pub fn to_html(&self) -> String {
+        let title = escape_html(&self.title());
+        let mut content = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n\
+             <style>\n{STYLE}</style>\n</head>\n<body>\n"
+        );
+        for block in self.blocks() {
+            let _ = match block {
+                Block::Heading(level, text) => {
+                    writeln!(content, "<h{level}>{}</h{level}>", escape_html(&text))
+                }
+                Block::Paragraph(text) => writeln!(content, "<p>{}</p>", escape_html(&text)),
+                Block::List(items) => {
+                    let _ = writeln!(content, "<ul>");
+                    for item in items {
+                        let _ = writeln!(content, "<li>{}</li>", escape_html(&item));
+                    }
+                    writeln!(content, "</ul>")
+                }
+                Block::Table(header, rows) => {
+                    let _ = writeln!(content, "<table>\n<tr>");
+                    for cell in header {
+                        let _ = writeln!(content, "<th>{}</th>", escape_html(cell));
+                    }
+                    let _ = writeln!(content, "</tr>");
+                    for cells in rows {
+                        let _ = writeln!(content, "<tr>");
+                        for cell in cells {
+                            let _ = writeln!(content, "<td>{}</td>", escape_html(&cell));
+                        }
+                        let _ = writeln!(content, "</tr>");
+                    }
+                    writeln!(content, "</table>")
+                }
+            };
+        }
+        content + "</body>\n</html>\n"
+    }
+
+    fn title(&self) -> String {
+        format!("Observing environment {}", self.env_path)
+    }
+
+    fn blocks(&self) -> Vec<Block> {
+        let mut blocks = vec![
+            Block::Heading(1, self.title()),
+            Block::Paragraph(format!("Generated on {} UTC.", format_utc(self.time))),
+        ];
+        for section in self.sections.iter() {
+            section.blocks(&mut blocks);
+        }
+        blocks
+    }
+}
+
+impl SectionContent {
+    fn blocks(&self, blocks: &mut Vec<Block>) {
+        let short = |sha: &Option<String>| match sha {
+            Some(sha) => sha[..sha.len().min(12)].to_owned(),
+            None => String::new(),
+        };
+        match self {
+            SectionContent::Versions(versions) => {
+                blocks.push(Block::Heading(2, "Versions".to_owned()));
+                let rows = versions
+                    .iter()
+                    .map(|(repo_name, version)| match version {
+                        Ok(version) => {
+                            let mut state = Vec::new();
+                            if version.dirty {
+                                state.push("dirty".to_owned());
+                            }
+                            if version.untracked > 0 {
+                                state.push(format!("{} untracked", version.untracked));
+                            }
+                            vec![
+                                repo_name.clone(),
+                                version.describe.clone(),
+                                version.branch.clone().unwrap_or_default(),
+                                short(&version.sha),
+                                version.describe_upstream(),
+                                state.join(", "),
+                            ]
+                        }
+                        Err(error) => vec![
+                            repo_name.clone(),
+                            error.clone(),
+                            String::new(),
+                            String::new(),
+                            String::new(),
+                            String::new(),
+                        ],
+                    })
+                    .collect();
+                blocks.push(Block::Table(
+                    vec![
+                        "Repository",
+                        "Version",
+                        "Branch",
+                        "Commit",
+                        "Upstream",
+                        "State",
+                    ],
+                    rows,
+                ));
+            }
+            SectionContent::BaseDiff(differences) => {
+                blocks.push(Block::Heading(
+                    2,
+                    "Differences with the base environment".to_owned(),
+                ));
+                match differences {
+                    Ok(differences) if differences.is_empty() => blocks.push(Block::Paragraph(
+                        "Every repository is at its base version.".to_owned(),
+                    )),
+                    Ok(differences) => {
+                        let describe = |version: &Option<RepoVersion>, missing: &str| match version
+                        {
+                            Some(version) if version.dirty => format!("{version} (dirty)"),
+                            Some(version) => version.to_string(),
+                            None => missing.to_owned(),
+                        };
+                        let rows = differences
+                            .iter()
+                            .map(|difference| {
+                                vec![
+                                    difference.repo.clone(),
+                                    describe(&difference.from, "none"),
+                                    describe(&difference.to, "not cloned"),
+                                ]
+                            })
+                            .collect();
+                        blocks.push(Block::Table(vec!["Repository", "Base", "Current"], rows));
+                    }
+                    Err(error) => blocks.push(Block::Paragraph(format!(
+                        "The base versions are not known: {error}"
+                    ))),
+                }
+            }
+            SectionContent::Dirty(versions) => {
+                blocks.push(Block::Heading(2, "Local changes".to_owned()));
+                if versions.is_empty() {
+                    blocks.push(Block::Paragraph(
+                        "No repository has local changes.".to_owned(),
+                    ));
+                    return;
+                }
+                let items = versions
+                    .iter()
+                    .map(|version| {
+                        let mut state = Vec::new();
+                        if version.dirty {
+                            state.push("changed tracked files".to_owned());
+                        }
+                        if version.untracked > 0 {
+                            state.push(format!("{} untracked", version.untracked));
+                        }
+                        format!("{}: {}", version.name, state.join(", "))
+                    })
+                    .collect();
+                blocks.push(Block::List(items));
+            }
+            SectionContent::Outdated(outdated, errors) => {
+                blocks.push(Block::Heading(2, "Outdated repositories".to_owned()));
+                let rows: Vec<Vec<String>> = outdated
+                    .repos
+                    .iter()
+                    .filter(|repo| repo.staleness.behind > 0)
+                    .map(|repo| {
+                        let status = match (repo.ignored, repo.outdated) {
+                            (true, _) => "ignored",
+                            (false, true) => "outdated",
+                            (false, false) => "",
+                        };
+                        vec![
+                            repo.staleness.repo.clone(),
+                            repo.staleness.default_branch.clone(),
+                            repo.staleness.behind.to_string(),
+                            repo.days_behind
+                                .map(|days| days.to_string())
+                                .unwrap_or_default(),
+                            repo.staleness
+                                .newest_unpulled
+                                .map(|time| format_utc(time)[..10].to_owned())
+                                .unwrap_or_default(),
+                            status.to_owned(),
+                        ]
+                    })
+                    .collect();
+                if rows.is_empty() {
+                    blocks.push(Block::Paragraph(
+                        "Every repository is up to date with its default branch.".to_owned(),
+                    ));
+                } else {
+                    blocks.push(Block::Table(
+                        vec![
+                            "Repository",
+                            "Default branch",
+                            "Commits behind",
+                            "Days behind",
+                            "Newest commit",
+                            "Status",
+                        ],
+                        rows,
+                    ));
+                }
+                if !errors.is_empty() {
+                    blocks.push(Block::List(errors.clone()));
+                }
+            }
+            SectionContent::LastReset(metadata) => {
+                blocks.push(Block::Heading(2, "Last reset".to_owned()));
+                blocks.push(Block::Paragraph(match metadata {
+                    Ok(Some(metadata)) => format!(
+                        "{} by {} on {} UTC, from base branch {}, with manage_obs_env {}.",
+                        metadata.action,
+                        metadata.user,
+                        format_utc(metadata.timestamp),
+                        metadata.base_branch,
+                        metadata.tool_version
+                    ),
+                    Ok(None) => {
+                        "The environment was never set up, reset or given a manifest.".to_owned()
+                    }
+                    Err(error) => format!("The last reset is not known: {error}"),
+                }));
+            }
+            SectionContent::History(entries) => {
+                blocks.push(Block::Heading(2, "Recent operations".to_owned()));
+                match entries {
+                    Ok(entries) if entries.is_empty() => {
+                        blocks.push(Block::Paragraph("No recorded operation found.".to_owned()))
+                    }
+                    Ok(entries) => {
+                        let rows = entries
+                            .iter()
+                            .rev()
+                            .map(|entry| {
+                                let repos: Vec<&str> = entry
+                                    .repos
+                                    .iter()
+                                    .map(|change| change.repo.as_str())
+                                    .collect();
+                                vec![
+                                    format_utc(entry.timestamp),
+                                    format!("{}@{}", entry.user, entry.host),
+                                    entry.action.clone(),
+                                    match &entry.error {
+                                        Some(error) => format!("{} ({error})", entry.outcome),
+                                        None => entry.outcome.to_string(),
+                                    },
+                                    repos.join(", "),
+                                ]
+                            })
+                            .collect();
+                        blocks.push(Block::Table(
+                            vec![
+                                "Time (UTC)",
+                                "User",
+                                "Action",
+                                "Outcome",
+                                "Repositories changed",
+                            ],
+                            rows,
+                        ));
+                    }
+                    Err(error) => blocks.push(Block::Paragraph(format!(
+                        "The audit log cannot be read: {error}"
+                    ))),
+                }
+            }
+        }
+    }
+}
+
+/// Style of the HTML page.
+const STYLE: &str = "body { font-family: sans-serif; margin: 2em; }\n\
+table { border-collapse: collapse; margin-bottom: 1em; }\n\
+th, td { border: 1px solid #ccc; padding: 0.3em 0.6em; text-align: left; }\n\
+th { background: #eee; }\n";
+
+/// Escape `text` to be used in HTML content.
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EnvReport, ReportFormat, SectionContent};
+    use crate::{
+        manifest::{RepoDifference, RepoVersion},
+        outdated::{OutdatedReport, OutdatedThresholds, RepoStaleness},
+    };
+    use std::collections::BTreeMap;
+
+    fn version(name: &str, describe: &str, dirty: bool) -> RepoVersion {
+        RepoVersion {
+            name: name.to_owned(),
+            url: None,
+            branch: None,
+            sha: Some("1111aaaa2222bbbb".to_owned()),
+            describe: describe.to_owned(),
+            dirty,
+            dirty_files: Vec::new(),
+            untracked: 0,
+            ahead_behind: None,
+        }
+    }
+
+    #[test]
+    fn test_env_report() {
+        let now = 1_760_400_000;
+        let report = EnvReport {
+            env_path: "/net/obs-env/auto_base_packages".to_owned(),
+            time: now,
+            sections: vec![
+                SectionContent::Versions(BTreeMap::from([
+                    ("cwfs".to_owned(), Ok(version("cwfs", "v1.0.0", false))),
+                    ("ts_wep".to_owned(), Err("ts_wep is not cloned".to_owned())),
+                ])),
+                SectionContent::BaseDiff(Ok(vec![RepoDifference {
+                    repo: "cwfs".to_owned(),
+                    from: Some(version("cwfs", "v0.9.0", false)),
+                    to: Some(version("cwfs", "v1.0.0|rc", true)),
+                    commits: None,
+                }])),
+                SectionContent::Dirty(Vec::new()),
+                SectionContent::Outdated(
+                    OutdatedReport::new(
+                        vec![RepoStaleness {
+                            repo: "cwfs".to_owned(),
+                            default_branch: "main".to_owned(),
+                            behind: 2,
+                            behind_since: Some(now - 3 * 24 * 60 * 60),
+                            newest_unpulled: Some(now),
+                        }],
+                        OutdatedThresholds::default(),
+                        &[],
+                        now,
+                    ),
+                    Vec::new(),
+                ),
+                SectionContent::LastReset(Ok(None)),
+            ],
+        };
+
+        let markdown = report.render(ReportFormat::Markdown);
+        assert!(markdown.starts_with(
+            "# Observing environment /net/obs-env/auto_base_packages\n\n\
+             Generated on 2025-10-14 00:00:00 UTC.\n\n## Versions\n\n\
+             | Repository | Version | Branch | Commit | Upstream | State |\n\
+             |---|---|---|---|---|---|\n\
+             | cwfs | v1.0.0 |  | 1111aaaa2222 | detached |  |\n\
+             | ts_wep | ts_wep is not cloned |  |  |  |  |\n"
+        ));
+        assert!(markdown.contains("| cwfs | v0.9.0 | v1.0.0\\|rc (dirty) |\n"));
+        assert!(markdown.contains("## Local changes\n\nNo repository has local changes.\n"));
+        assert!(markdown.contains("| cwfs | main | 2 | 3 | 2025-10-14 | outdated |\n"));
+        assert!(
+            markdown.ends_with("The environment was never set up, reset or given a manifest.\n")
+        );
+
+        let html = report.render(ReportFormat::Html);
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<td>v1.0.0|rc (dirty)</td>"));
+        assert!(html.ends_with("</html>\n"));
+    }
+}
@@ -0,0 +1,390 @@
+//! Environments kept side by side under a common root, each in the
+//! `<root>/<name>` directory, e.g. nightly, release and experimental
+//! environments.
+use crate::{
+    audit,
+    error::{report, ObsEnvError},
+    git_backend::GitBackend,
+    metadata::{self, EnvMetadata},
+    observing_environment::{METADATA_FILE, OBS_ENV_DIR},
+};
+use serde::Serialize;
+use std::{
+    fmt::{self, Display},
+    fs::{read_dir, symlink_metadata},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Environment found under a root.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct NamedEnvironment {
+    /// Name of the environment, the name of its directory.
+    pub name: String,
+    /// Path of the environment.
+    pub path: PathBuf,
+    /// Metadata of its last setup, if recorded.
+    pub metadata: Option<EnvMetadata>,
+}
+
+impl Display for NamedEnvironment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.metadata {
+            Some(metadata) => write!(
+                f,
+                "{}: last {} on {}, base branch {}, {} repositories",
+                self.name,
+                metadata.action,
+                audit::format_utc(metadata.timestamp),
+                metadata.base_branch,
+                metadata.manifest.repos.len()
+            ),
+            None => write!(f, "{}: no metadata recorded", self.name),
+        }
+    }
+}
+
+/// Whether `name` can name an environment: a single path component.
+pub fn validate_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+        return Err(format!(
+            "Invalid environment name {name:?}, expected a directory name."
+        ));
+    }
+    Ok(())
+}
+
+/// Whether the directory at `path` is an environment managed by this tool,
+/// which has a `.obs_env` directory.
+pub fn is_environment(path: &Path) -> bool {
+    path.join(OBS_ENV_DIR).is_dir()
+}
+
+/// Whether an environment can be placed at `path`: it is already one, or
+/// is missing or an empty directory.
+pub fn can_hold_environment(path: &Path) -> bool {
+    if is_environment(path) {
+        return true;
+    }
+    match read_dir(path) {
+        Ok(mut entries) => entries.next().is_none(),
+        Err(_) => !path.exists(),
+    }
+}
+
+/// Environments under `root`, sorted by name. Directories that are not
+/// environments are skipped.
+pub fn list(root: &Path) -> Result<Vec<NamedEnvironment>, ObsEnvError> {
+    let entries = read_dir(root).map_err(|error| ObsEnvError::io(root, "list", error))?;
+    let mut environments = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        // Links, like the one to the active environment, are not
+        // environments of their own.
+        if !entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+            continue;
+        }
+        if !is_environment(&path) {
+            log::debug!("Skipping {}, not an environment.", path.display());
+            continue;
+        }
+        let metadata =
+            metadata::read(&path.join(OBS_ENV_DIR).join(METADATA_FILE)).unwrap_or_else(|error| {
+                log::warn!("{}", report(&error));
+                None
+            });
+        environments.push(NamedEnvironment {
+            name: entry.file_name().to_string_lossy().into_owned(),
+            path,
+            metadata,
+        });
+    }
+    environments.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(environments)
+}
+
+/// Environments to remove from a root.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PruneFilter {
+    /// Only those whose metadata was written longer ago than this.
+    pub older_than: Option<Duration>,
+    /// Only those beyond this many most recent ones.
+    pub keep: Option<usize>,
+}
+
+impl PruneFilter {
+    /// Environments of `environments` to remove at `now`, in seconds since
+    /// the Unix epoch, oldest first.
+    ///
+    /// Environments with no metadata are of unknown age and never
+    /// selected, nor is the `active` one, which does not count in those to
+    /// keep either.
+    pub fn select(
+        &self,
+        environments: Vec<NamedEnvironment>,
+        active: Option<&Path>,
+        now: u64,
+    ) -> Vec<NamedEnvironment> {
+        let mut dated: Vec<(u64, NamedEnvironment)> = environments
+            .into_iter()
+            .filter(|environment| Some(environment.path.as_path()) != active)
+            .filter_map(|environment| {
+                let timestamp = environment.metadata.as_ref()?.timestamp;
+                Some((timestamp, environment))
+            })
+            .collect();
+        dated.sort_by(|(a, _), (b, _)| b.cmp(a));
+        let mut selected: Vec<NamedEnvironment> = dated
+            .into_iter()
+            .skip(self.keep.unwrap_or_default())
+            .filter(|(timestamp, _)| {
+                self.older_than
+                    .is_none_or(|older_than| now.saturating_sub(*timestamp) > older_than.as_secs())
+            })
+            .map(|(_, environment)| environment)
+            .collect();
+        selected.reverse();
+        selected
+    }
+}
+
+/// Check that removing the environment at `path` loses no work: none of
+/// its repositories, however deep under it, has changes, untracked files,
+/// stash or commits that are on no remote.
+pub fn check_removable(path: &Path, backend: &dyn GitBackend) -> Result<(), ObsEnvError> {
+    for repo_path in repositories_under(path)? {
+        let repo_name = repo_path
+            .strip_prefix(path)
+            .unwrap_or(&repo_path)
+            .to_string_lossy()
+            .into_owned();
+        check_repository_removable(&repo_name, &repo_path, backend)?;
+    }
+    Ok(())
+}
+
+/// Check that removing the repository `repo_name` at `repo_path` loses no
+/// work: it has no changes, untracked files, stash nor commits that are on
+/// no remote.
+pub fn check_repository_removable(
+    repo_name: &str,
+    repo_path: &Path,
+    backend: &dyn GitBackend,
+) -> Result<(), ObsEnvError> {
+    let status = backend
+        .status(repo_path)
+        .map_err(|error| ObsEnvError::git(repo_name, repo_path, "read status", error))?;
+    if status.dirty {
+        return Err(ObsEnvError::DirtyWorkingTree {
+            repo: repo_name.to_owned(),
+        });
+    }
+    if status.untracked > 0 {
+        return Err(ObsEnvError::UnsavedWork {
+            repo: repo_name.to_owned(),
+            work: format!("{} untracked files", status.untracked),
+        });
+    }
+    let stash = backend
+        .list_refs(repo_path, "refs/stash")
+        .map_err(|error| ObsEnvError::git(repo_name, repo_path, "list stash", error))?;
+    if !stash.is_empty() {
+        return Err(ObsEnvError::UnsavedWork {
+            repo: repo_name.to_owned(),
+            work: "stashed changes".to_owned(),
+        });
+    }
+    let count = backend
+        .local_commits(repo_path)
+        .map_err(|error| ObsEnvError::git(repo_name, repo_path, "read commits", error))?;
+    if count > 0 {
+        return Err(ObsEnvError::UnsavedWork {
+            repo: repo_name.to_owned(),
+            work: format!("{count} commits on no remote"),
+        });
+    }
+    Ok(())
+}
+
+/// Git repositories, the directories with a `.git` entry, in the tree
+/// under `path`, including those nested in another repository. Links are
+/// not followed.
+fn repositories_under(path: &Path) -> Result<Vec<PathBuf>, ObsEnvError> {
+    let mut repositories = Vec::new();
+    let entries = read_dir(path).map_err(|error| ObsEnvError::io(path, "list", error))?;
+    for entry in entries {
+        let entry = entry.map_err(|error| ObsEnvError::io(path, "list", error))?;
+        let is_dir = entry
+            .file_type()
+            .map_err(|error| ObsEnvError::io(entry.path(), "read", error))?
+            .is_dir();
+        if !is_dir || entry.file_name() == ".git" {
+            continue;
+        }
+        let subdirectory = entry.path();
+        if symlink_metadata(subdirectory.join(".git")).is_ok() {
+            repositories.push(subdirectory.clone());
+        }
+        repositories.extend(repositories_under(&subdirectory)?);
+    }
+    Ok(repositories)
+}
+
+/// Bytes used by the files under `path`, without following links.
+pub fn disk_usage(path: &Path) -> u64 {
+    let Ok(metadata) = symlink_metadata(path) else {
+        return 0;
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+    read_dir(path)
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|entry| disk_usage(&entry.path()))
+                .sum()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        can_hold_environment, check_removable, list, validate_name, NamedEnvironment, PruneFilter,
+    };
+    use crate::{
+        metadata::{EnvMetadata, TOOL_VERSION},
+        EnvironmentManifest, Git2Backend, ObsEnvError,
+    };
+    use std::{
+        fs::{create_dir_all, write},
+        path::{Path, PathBuf},
+        time::Duration,
+    };
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_list_environments() {
+        let root = TempDir::new().unwrap();
+        create_dir_all(root.path().join("release/.obs_env")).unwrap();
+        create_dir_all(root.path().join("nightly/.obs_env")).unwrap();
+        create_dir_all(root.path().join("scratch")).unwrap();
+        write(root.path().join("scratch/notes.txt"), "").unwrap();
+        write(root.path().join("README"), "").unwrap();
+
+        let environments = list(root.path()).unwrap();
+        let names: Vec<&str> = environments.iter().map(|env| env.name.as_str()).collect();
+        assert_eq!(names, ["nightly", "release"]);
+        assert_eq!(environments[0].to_string(), "nightly: no metadata recorded");
+
+        assert!(can_hold_environment(&root.path().join("nightly")));
+        assert!(can_hold_environment(&root.path().join("missing")));
+        assert!(!can_hold_environment(&root.path().join("scratch")));
+
+        assert!(validate_name("nightly").is_ok());
+        for name in ["", "..", "a/b"] {
+            assert!(validate_name(name).is_err());
+        }
+    }
+
+    #[test]
+    fn test_check_removable_nested() -> Result<(), Box<dyn std::error::Error>> {
+        let root = TempDir::new()?;
+        create_dir_all(root.path().join(".obs_env"))?;
+        assert!(check_removable(root.path(), &Git2Backend).is_ok());
+
+        let nested = root.path().join("aos/ts_wep");
+        let repository = git2::Repository::init(&nested)?;
+        write(nested.join("setup.cfg"), "committed")?;
+        let mut index = repository.index()?;
+        index.add_path(Path::new("setup.cfg"))?;
+        let tree = repository.find_tree(index.write_tree()?)?;
+        let signature = git2::Signature::now("Test", "test@example.com")?;
+        repository.commit(Some("HEAD"), &signature, &signature, "Add", &tree, &[])?;
+        write(nested.join("setup.cfg"), "changed")?;
+
+        assert!(matches!(
+            check_removable(root.path(), &Git2Backend),
+            Err(ObsEnvError::DirtyWorkingTree { repo }) if repo == "aos/ts_wep"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_removable_untracked_and_stash() -> Result<(), Box<dyn std::error::Error>> {
+        let root = TempDir::new()?;
+        let path = root.path().join("ts_wep");
+        let mut repository = git2::Repository::init(&path)?;
+        write(path.join("setup.cfg"), "committed")?;
+        let mut index = repository.index()?;
+        index.add_path(Path::new("setup.cfg"))?;
+        index.write()?;
+        let tree = repository.find_tree(index.write_tree()?)?;
+        let signature = git2::Signature::now("Test", "test@example.com")?;
+        repository.commit(Some("HEAD"), &signature, &signature, "Add", &tree, &[])?;
+        drop(tree);
+
+        write(path.join("notes.txt"), "untracked")?;
+        assert!(matches!(
+            check_removable(root.path(), &Git2Backend),
+            Err(ObsEnvError::UnsavedWork { repo, .. }) if repo == "ts_wep"
+        ));
+
+        write(path.join("setup.cfg"), "changed")?;
+        repository.stash_save(
+            &signature,
+            "Work",
+            Some(git2::StashFlags::INCLUDE_UNTRACKED),
+        )?;
+        assert!(!path.join("notes.txt").exists());
+        assert!(matches!(
+            check_removable(root.path(), &Git2Backend),
+            Err(ObsEnvError::UnsavedWork { repo, work }) if repo == "ts_wep" && work == "stashed changes"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_filter() {
+        const DAY: u64 = 24 * 60 * 60;
+        let environment = |name: &str, age: Option<u64>| NamedEnvironment {
+            name: name.to_owned(),
+            path: PathBuf::from(format!("/obs-env/{name}")),
+            metadata: age.map(|age| EnvMetadata {
+                tool_version: TOOL_VERSION.to_owned(),
+                timestamp: 100 * DAY - age * DAY,
+                action: "reset".to_owned(),
+                base_branch: "main".to_owned(),
+                user: "saluser".to_owned(),
+                manifest: EnvironmentManifest::default(),
+            }),
+        };
+        let environments = vec![
+            environment("nightly", Some(1)),
+            environment("old", Some(30)),
+            environment("release", Some(10)),
+            environment("older", Some(60)),
+            environment("unknown", None),
+        ];
+        let select = |older_than: Option<u64>, keep: Option<usize>, active: Option<&str>| {
+            PruneFilter {
+                older_than: older_than.map(|days| Duration::from_secs(days * DAY)),
+                keep,
+            }
+            .select(environments.clone(), active.map(Path::new), 100 * DAY)
+            .into_iter()
+            .map(|environment| environment.name)
+            .collect::<Vec<_>>()
+        };
+
+        assert_eq!(select(Some(20), None, None), ["older", "old"]);
+        assert_eq!(select(None, Some(2), None), ["older", "old"]);
+        assert_eq!(select(None, Some(2), Some("/obs-env/nightly")), ["older"]);
+        assert_eq!(select(Some(20), Some(3), None), ["older"]);
+        assert_eq!(
+            select(Some(0), None, Some("/obs-env/older")),
+            ["old", "release", "nightly"]
+        );
+    }
+}
@@ -1,12 +1,366 @@
-use std::{error::Error, fmt, fmt::Display};
+use std::{
+    error::Error,
+    fmt,
+    fmt::Display,
+    io,
+    path::{Path, PathBuf},
+};
 
-#[derive(Clone, Debug)]
+/// Errors returned when managing the observing environment.
+#[derive(Debug)]
 pub enum ObsEnvError {
-    ERROR(String),
-    GIT(String),
+    /// An argument required by the action was not given.
+    MissingArgument { action: String, argument: String },
+    /// The repository is not in the list of managed repositories.
+    RepoNotFound { repo: String },
+    /// The repository could not be opened at its path in the environment.
+    RepoNotCloned {
+        repo: String,
+        path: PathBuf,
+        source: git2::Error,
+    },
+    /// The branch does not exist on origin.
+    BranchNotFound {
+        repo: String,
+        path: PathBuf,
+        branch: String,
+        /// Branches of origin with the closest names, the closest first,
+        /// if they were listed.
+        closest: Vec<String>,
+        source: git2::Error,
+    },
+    /// The revision does not resolve to anything in the repository.
+    RevisionNotFound {
+        repo: String,
+        path: PathBuf,
+        revision: String,
+        source: git2::Error,
+    },
+    /// The revision is not in the history of the shallow repository, even
+    /// once every commit of origin was fetched.
+    NotOnOrigin {
+        repo: String,
+        path: PathBuf,
+        revision: String,
+        source: git2::Error,
+    },
+    /// The revision is an abbreviated SHA matching more than one object.
+    AmbiguousRevision {
+        repo: String,
+        path: PathBuf,
+        revision: String,
+        source: git2::Error,
+    },
+    /// The repository has a merge, rebase or similar operation in progress.
+    RepoBusy { repo: String, state: String },
+    /// The working tree has changes that would be lost.
+    DirtyWorkingTree { repo: String },
+    /// Cloning the repository from `url` into `path` failed.
+    CloneFailed {
+        repo: String,
+        path: PathBuf,
+        url: String,
+        source: git2::Error,
+    },
+    /// Fetching from the remote of the repository failed.
+    FetchFailed {
+        repo: String,
+        path: PathBuf,
+        source: git2::Error,
+    },
+    /// A network operation on the repository timed out.
+    NetworkTimeout {
+        repo: String,
+        path: PathBuf,
+        source: git2::Error,
+    },
+    /// The operation needs network access, but the environment is offline.
+    Offline { operation: String },
+    /// The operation was cancelled before it completed.
+    Cancelled { operation: String },
+    /// The operation was not attempted on the repository, as it failed on
+    /// another one first and the environment stops at the first failure.
+    NotAttempted { repo: String, operation: String },
+    /// The operation failed on some of the repositories of the environment.
+    PartialFailure {
+        operation: String,
+        failed: Vec<String>,
+    },
+    /// The repository has no commits yet.
+    EmptyRepository { repo: String },
+    /// A directory is in the way of the repository but is not a git
+    /// repository.
+    NotARepository { repo: String, path: PathBuf },
+    /// The broken repository has commits on no remote, which re-cloning it
+    /// would lose.
+    LocalCommits { repo: String, count: usize },
+    /// Removing the repository would lose `work` that is saved nowhere
+    /// else, e.g. untracked files or a stash.
+    UnsavedWork { repo: String, work: String },
+    /// The commits of the broken repository cannot be read, so re-cloning
+    /// it may lose commits on no remote.
+    UnreadableCommits { repo: String, source: git2::Error },
+    /// Lock files were left in the repository, but a git process is
+    /// running and may still hold them.
+    StaleLocks { repo: String, locks: Vec<PathBuf> },
+    /// The operation panicked, e.g. on a bug in a worker thread.
+    Panicked { operation: String, message: String },
+    /// The environment path cannot be used.
+    InvalidEnvPath { path: PathBuf, source: io::Error },
+    /// The directory picked for an environment under a root has other
+    /// content in it.
+    NotAnEnvironment { path: PathBuf },
+    /// The environment at `path` has missing or damaged repositories.
+    VerificationFailed {
+        path: PathBuf,
+        problems: Vec<String>,
+    },
+    /// The link was never repointed, so there is no environment to go back
+    /// to.
+    NoPreviousTarget { link: PathBuf },
+    /// The current user cannot write to the environment path.
+    InsufficientPermissions { path: PathBuf },
+    /// The volume of the environment path has less space available than
+    /// required.
+    InsufficientSpace {
+        path: PathBuf,
+        available_mib: u64,
+        required_mib: u64,
+    },
+    /// Another run holds the lock of the environment at `path`.
+    EnvLocked { path: PathBuf, holder: String },
+    /// The base environment definition could not be read from `location`.
+    BaseEnvUnavailable { location: String, reason: String },
+    /// The manifest to watch could not be read from `location`.
+    ManifestUnavailable { location: String, reason: String },
+    /// The manifest read from `location` does not have the expected
+    /// structure.
+    InvalidManifest {
+        location: String,
+        problems: Vec<String>,
+    },
+    /// The configuration of the environment is not valid.
+    InvalidConfig { message: String },
+    /// A git operation on the repository failed.
+    Git {
+        repo: String,
+        path: PathBuf,
+        operation: String,
+        source: git2::Error,
+    },
+    /// An external command failed or could not be run.
+    CommandFailed { command: String, message: String },
+    /// A filesystem operation failed.
+    Io {
+        path: PathBuf,
+        operation: String,
+        source: io::Error,
+    },
 }
 
-impl Error for ObsEnvError {}
+impl ObsEnvError {
+    /// Error for a failed fetch, telling timeouts apart from other network
+    /// errors.
+    pub(crate) fn fetch_failed(repo: &str, path: &Path, source: git2::Error) -> ObsEnvError {
+        let repo = repo.to_owned();
+        let path = path.to_path_buf();
+        if source.code() == git2::ErrorCode::Timeout {
+            ObsEnvError::NetworkTimeout { repo, path, source }
+        } else {
+            ObsEnvError::FetchFailed { repo, path, source }
+        }
+    }
+
+    /// Error for a failed clone, telling timeouts apart from other errors.
+    pub(crate) fn clone_failed(
+        repo: &str,
+        url: &str,
+        path: &Path,
+        source: git2::Error,
+    ) -> ObsEnvError {
+        let repo = repo.to_owned();
+        let path = path.to_path_buf();
+        if source.code() == git2::ErrorCode::Timeout {
+            ObsEnvError::NetworkTimeout { repo, path, source }
+        } else {
+            ObsEnvError::CloneFailed {
+                repo,
+                path,
+                url: url.to_owned(),
+                source,
+            }
+        }
+    }
+
+    /// Error for a failed git operation on a repository.
+    pub(crate) fn git(
+        repo: &str,
+        path: &Path,
+        operation: &str,
+        source: git2::Error,
+    ) -> ObsEnvError {
+        ObsEnvError::Git {
+            repo: repo.to_owned(),
+            path: path.to_path_buf(),
+            operation: operation.to_owned(),
+            source,
+        }
+    }
+
+    /// Name of the repository the error is about, if it is about one.
+    pub fn repo(&self) -> Option<&str> {
+        match self {
+            ObsEnvError::RepoNotFound { repo }
+            | ObsEnvError::RepoNotCloned { repo, .. }
+            | ObsEnvError::BranchNotFound { repo, .. }
+            | ObsEnvError::RevisionNotFound { repo, .. }
+            | ObsEnvError::NotOnOrigin { repo, .. }
+            | ObsEnvError::AmbiguousRevision { repo, .. }
+            | ObsEnvError::RepoBusy { repo, .. }
+            | ObsEnvError::DirtyWorkingTree { repo }
+            | ObsEnvError::CloneFailed { repo, .. }
+            | ObsEnvError::FetchFailed { repo, .. }
+            | ObsEnvError::NetworkTimeout { repo, .. }
+            | ObsEnvError::EmptyRepository { repo }
+            | ObsEnvError::NotARepository { repo, .. }
+            | ObsEnvError::LocalCommits { repo, .. }
+            | ObsEnvError::UnsavedWork { repo, .. }
+            | ObsEnvError::UnreadableCommits { repo, .. }
+            | ObsEnvError::StaleLocks { repo, .. }
+            | ObsEnvError::NotAttempted { repo, .. }
+            | ObsEnvError::Git { repo, .. } => Some(repo),
+            _ => None,
+        }
+    }
+
+    /// Name of the variant of the error, e.g. "RepoNotFound", for the
+    /// programs reading the json output.
+    ///
+    /// ```
+    /// use ts_observing_environment::ObsEnvError;
+    ///
+    /// let error = ObsEnvError::RepoNotFound {
+    ///     repo: "ts_wep".to_owned(),
+    /// };
+    /// assert_eq!(error.kind(), "RepoNotFound");
+    /// ```
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ObsEnvError::MissingArgument { .. } => "MissingArgument",
+            ObsEnvError::RepoNotFound { .. } => "RepoNotFound",
+            ObsEnvError::RepoNotCloned { .. } => "RepoNotCloned",
+            ObsEnvError::BranchNotFound { .. } => "BranchNotFound",
+            ObsEnvError::RevisionNotFound { .. } => "RevisionNotFound",
+            ObsEnvError::NotOnOrigin { .. } => "NotOnOrigin",
+            ObsEnvError::AmbiguousRevision { .. } => "AmbiguousRevision",
+            ObsEnvError::RepoBusy { .. } => "RepoBusy",
+            ObsEnvError::DirtyWorkingTree { .. } => "DirtyWorkingTree",
+            ObsEnvError::CloneFailed { .. } => "CloneFailed",
+            ObsEnvError::FetchFailed { .. } => "FetchFailed",
+            ObsEnvError::NetworkTimeout { .. } => "NetworkTimeout",
+            ObsEnvError::Offline { .. } => "Offline",
+            ObsEnvError::Cancelled { .. } => "Cancelled",
+            ObsEnvError::NotAttempted { .. } => "NotAttempted",
+            ObsEnvError::PartialFailure { .. } => "PartialFailure",
+            ObsEnvError::EmptyRepository { .. } => "EmptyRepository",
+            ObsEnvError::NotARepository { .. } => "NotARepository",
+            ObsEnvError::LocalCommits { .. } => "LocalCommits",
+            ObsEnvError::UnsavedWork { .. } => "UnsavedWork",
+            ObsEnvError::UnreadableCommits { .. } => "UnreadableCommits",
+            ObsEnvError::StaleLocks { .. } => "StaleLocks",
+            ObsEnvError::Panicked { .. } => "Panicked",
+            ObsEnvError::InvalidEnvPath { .. } => "InvalidEnvPath",
+            ObsEnvError::NotAnEnvironment { .. } => "NotAnEnvironment",
+            ObsEnvError::VerificationFailed { .. } => "VerificationFailed",
+            ObsEnvError::NoPreviousTarget { .. } => "NoPreviousTarget",
+            ObsEnvError::InsufficientPermissions { .. } => "InsufficientPermissions",
+            ObsEnvError::InsufficientSpace { .. } => "InsufficientSpace",
+            ObsEnvError::EnvLocked { .. } => "EnvLocked",
+            ObsEnvError::BaseEnvUnavailable { .. } => "BaseEnvUnavailable",
+            ObsEnvError::ManifestUnavailable { .. } => "ManifestUnavailable",
+            ObsEnvError::InvalidManifest { .. } => "InvalidManifest",
+            ObsEnvError::InvalidConfig { .. } => "InvalidConfig",
+            ObsEnvError::Git { .. } => "Git",
+            ObsEnvError::CommandFailed { .. } => "CommandFailed",
+            ObsEnvError::Io { .. } => "Io",
+        }
+    }
+
+    /// Error for a repository `operation` was not attempted on.
+    pub(crate) fn not_attempted(repo: &str, operation: &str) -> ObsEnvError {
+        ObsEnvError::NotAttempted {
+            repo: repo.to_owned(),
+            operation: operation.to_owned(),
+        }
+    }
+
+    /// Error for a failed filesystem operation.
+    pub(crate) fn io(path: impl Into<PathBuf>, operation: &str, source: io::Error) -> ObsEnvError {
+        ObsEnvError::Io {
+            path: path.into(),
+            operation: operation.to_owned(),
+            source,
+        }
+    }
+}
+
+impl Error for ObsEnvError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ObsEnvError::RepoNotCloned { source, .. }
+            | ObsEnvError::BranchNotFound { source, .. }
+            | ObsEnvError::RevisionNotFound { source, .. }
+            | ObsEnvError::NotOnOrigin { source, .. }
+            | ObsEnvError::AmbiguousRevision { source, .. }
+            | ObsEnvError::CloneFailed { source, .. }
+            | ObsEnvError::FetchFailed { source, .. }
+            | ObsEnvError::NetworkTimeout { source, .. }
+            | ObsEnvError::UnreadableCommits { source, .. }
+            | ObsEnvError::Git { source, .. } => Some(source),
+            ObsEnvError::InvalidEnvPath { source, .. } | ObsEnvError::Io { source, .. } => {
+                Some(source)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Render the error followed by the chain of its causes, one per line.
+///
+/// ```
+/// use ts_observing_environment::{error::report, ObsEnvError};
+///
+/// let error = ObsEnvError::RepoNotFound {
+///     repo: "ts_wep".to_owned(),
+/// };
+/// assert_eq!(
+///     report(&error),
+///     "Repository ts_wep not in the list of managed repositories"
+/// );
+/// ```
+pub fn report(error: &dyn Error) -> String {
+    chain(error).join("\ncaused by: ")
+}
+
+/// Message of the error followed by those of the chain of its causes.
+pub fn chain(error: &dyn Error) -> Vec<String> {
+    let mut messages = vec![error.to_string()];
+    let mut source = error.source();
+    while let Some(cause) = source {
+        messages.push(cause.to_string());
+        source = cause.source();
+    }
+    messages
+}
+
+/// How to get a repository out of the given in-progress state by hand.
+fn resolve_hint(state: &str) -> String {
+    match state {
+        "conflicted index" => "resolve the conflicts or run `git reset --merge`".to_owned(),
+        "bisect" => "run `git bisect reset`".to_owned(),
+        state => format!("run `git {state} --abort`"),
+    }
+}
 
 impl Display for ObsEnvError {
     // This trait requires `fmt` with this exact signature.
@@ -16,8 +370,192 @@ impl Display for ObsEnvError {
         // operation succeeded or failed. Note that `write!` uses syntax which
         // is very similar to `println!`.
         match self {
-            ObsEnvError::ERROR(err_msg) => write!(f, "ERROR: {}", err_msg),
-            ObsEnvError::GIT(err_msg) => write!(f, "GIT: {}", err_msg),
+            ObsEnvError::MissingArgument { action, argument } => {
+                write!(f, "{action} requires {argument}")
+            }
+            ObsEnvError::RepoNotFound { repo } => {
+                write!(
+                    f,
+                    "Repository {repo} not in the list of managed repositories"
+                )
+            }
+            ObsEnvError::RepoNotCloned { repo, path, .. } => {
+                write!(f, "Repository {repo} not found in {}", path.display())
+            }
+            ObsEnvError::BranchNotFound {
+                repo,
+                path,
+                branch,
+                closest,
+                ..
+            } => {
+                write!(
+                    f,
+                    "Branch {branch} not found on origin of {repo} ({})",
+                    path.display()
+                )?;
+                match closest.is_empty() {
+                    true => Ok(()),
+                    false => write!(f, ", did you mean {}?", closest.join(", ")),
+                }
+            }
+            ObsEnvError::RevisionNotFound {
+                repo,
+                path,
+                revision,
+                ..
+            } => write!(
+                f,
+                "Revision {revision} not found in {repo} ({})",
+                path.display()
+            ),
+            ObsEnvError::NotOnOrigin {
+                repo,
+                path,
+                revision,
+                ..
+            } => write!(
+                f,
+                "Commit {revision} not found on origin of {repo} ({}), even with its whole history fetched",
+                path.display()
+            ),
+            ObsEnvError::AmbiguousRevision {
+                repo,
+                path,
+                revision,
+                ..
+            } => write!(
+                f,
+                "Revision {revision} is ambiguous in {repo} ({})",
+                path.display()
+            ),
+            ObsEnvError::RepoBusy { repo, state } => write!(
+                f,
+                "{repo} has a {state} in progress: {} or rerun with --abort-in-progress",
+                resolve_hint(state)
+            ),
+            ObsEnvError::DirtyWorkingTree { repo } => {
+                write!(f, "{repo} has uncommitted changes")
+            }
+            ObsEnvError::CloneFailed {
+                repo, path, url, ..
+            } => write!(
+                f,
+                "Failed to clone {repo} from {url} into {}",
+                path.display()
+            ),
+            ObsEnvError::FetchFailed { repo, path, .. } => {
+                write!(f, "Failed to fetch {repo} ({})", path.display())
+            }
+            ObsEnvError::NetworkTimeout { repo, path, .. } => write!(
+                f,
+                "Timed out accessing the remote of {repo} ({})",
+                path.display()
+            ),
+            ObsEnvError::Offline { operation } => write!(f, "Cannot {operation} offline"),
+            ObsEnvError::Cancelled { operation } => write!(f, "Cancelled {operation}"),
+            ObsEnvError::NotAttempted { repo, operation } => {
+                write!(f, "Did not {operation} {repo}, stopped at the first failure")
+            }
+            ObsEnvError::PartialFailure { operation, failed } => write!(
+                f,
+                "Failed to {operation} {} repositories: {}",
+                failed.len(),
+                failed.join(", ")
+            ),
+            ObsEnvError::EmptyRepository { repo } => {
+                write!(f, "{repo} is an empty repository, with no commits yet")
+            }
+            ObsEnvError::LocalCommits { repo, count } => write!(
+                f,
+                "{repo} is broken but has {count} commits on no remote; push or save them, then remove it and run Setup"
+            ),
+            ObsEnvError::UnsavedWork { repo, work } => write!(
+                f,
+                "Refusing to remove {repo}, which has {work} that would be lost; save or remove it by hand first"
+            ),
+            ObsEnvError::UnreadableCommits { repo, .. } => write!(
+                f,
+                "Cannot tell whether broken {repo} has commits on no remote; save what can be, then run Doctor --repair --force to clone it again"
+            ),
+            ObsEnvError::StaleLocks { repo, locks } => write!(
+                f,
+                "{repo} has lock files left ({}) but a git process is running; rerun Doctor --repair once it is done",
+                locks
+                    .iter()
+                    .map(|lock| lock.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            ObsEnvError::NotARepository { repo, path } => write!(
+                f,
+                "{} exists but is not a git repository of {repo}; re-clone it with --force-reclone",
+                path.display()
+            ),
+            ObsEnvError::Panicked { operation, message } => {
+                write!(f, "Unexpected failure to {operation}: {message}")
+            }
+            ObsEnvError::InvalidEnvPath { path, .. } => {
+                write!(f, "Invalid environment path {}", path.display())
+            }
+            ObsEnvError::NotAnEnvironment { path } => write!(
+                f,
+                "{} is not an observing environment and is not empty; pick another --env-name",
+                path.display()
+            ),
+            ObsEnvError::VerificationFailed { path, problems } => write!(
+                f,
+                "{} failed verification: {}",
+                path.display(),
+                problems.join("; ")
+            ),
+            ObsEnvError::NoPreviousTarget { link } => write!(
+                f,
+                "{} was never repointed by Activate, there is no environment to go back to",
+                link.display()
+            ),
+            ObsEnvError::InsufficientPermissions { path } => write!(
+                f,
+                "Cannot write to {}: check its permissions and that it is not mounted read-only",
+                path.display()
+            ),
+            ObsEnvError::EnvLocked { path, holder } => write!(
+                f,
+                "The environment is in use by {holder}, which holds {}; try again once it is done or raise --lock-timeout",
+                path.display()
+            ),
+            ObsEnvError::InsufficientSpace {
+                path,
+                available_mib,
+                required_mib,
+            } => write!(
+                f,
+                "Only {available_mib} MiB available for {}, {required_mib} MiB required; free some space or lower --min-free-space",
+                path.display()
+            ),
+            ObsEnvError::BaseEnvUnavailable { location, reason } => write!(
+                f,
+                "Failed to read the base environment definition from {location}: {reason}"
+            ),
+            ObsEnvError::ManifestUnavailable { location, reason } => {
+                write!(f, "Failed to read the manifest from {location}: {reason}")
+            }
+            ObsEnvError::InvalidManifest { location, problems } => {
+                write!(f, "Invalid manifest {location}: {}", problems.join("; "))
+            }
+            ObsEnvError::InvalidConfig { message } => write!(f, "Invalid configuration: {message}"),
+            ObsEnvError::Git {
+                repo,
+                path,
+                operation,
+                ..
+            } => write!(f, "Failed to {operation} in {repo} ({})", path.display()),
+            ObsEnvError::CommandFailed { command, message } => {
+                write!(f, "Failed to run {command}: {message}")
+            }
+            ObsEnvError::Io {
+                path, operation, ..
+            } => write!(f, "Failed to {operation} {}", path.display()),
         }
     }
 }
@@ -0,0 +1,199 @@
+use crate::{error::ObsEnvError, manifest::RepoVersion};
+use std::{collections::BTreeMap, path::Path, process::Command};
+
+/// Flavor of the products listed in a tag file.
+const TAG_FILE_FLAVOR: &str = "generic";
+
+/// Declares repositories of the environment as EUPS products, by running
+/// the `eups` command.
+///
+/// The product is named after the repository and its version is the
+/// checked out version, e.g. `eups declare -r /obs-env/ts_wep ts_wep v1.2.0
+/// -t current`.
+///
+/// ```
+/// use ts_observing_environment::eups::Eups;
+///
+/// let eups = Eups::new("current").command("/opt/lsst/bin/eups");
+/// assert_eq!(eups.get_tag(), "current");
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Eups {
+    command: String,
+    tag: String,
+}
+
+impl Eups {
+    /// Declare products with the `tag` EUPS tag.
+    pub fn new(tag: &str) -> Eups {
+        Eups {
+            command: "eups".to_owned(),
+            tag: tag.to_owned(),
+        }
+    }
+
+    /// Run `command` instead of the `eups` found in PATH.
+    pub fn command(mut self, command: &str) -> Self {
+        self.command = command.to_owned();
+        self
+    }
+
+    /// Tag given to the declared products.
+    pub fn get_tag(&self) -> &str {
+        &self.tag
+    }
+
+    /// Command run to declare the products.
+    pub fn get_command(&self) -> &str {
+        &self.command
+    }
+
+    /// Whether the command is installed: an executable at its path, or in
+    /// PATH for a bare name.
+    pub fn is_available(&self) -> bool {
+        let command = Path::new(&self.command);
+        if command.components().count() > 1 {
+            return is_executable(command);
+        }
+        std::env::var_os("PATH").is_some_and(|path| {
+            std::env::split_paths(&path).any(|dir| is_executable(&dir.join(command)))
+        })
+    }
+
+    /// Declare `product` at `version` from `path`, with the tag.
+    ///
+    /// A previous declaration of the same version is undeclared first, so
+    /// declaring again after the repository moved works.
+    pub fn declare(&self, product: &str, path: &Path, version: &str) -> Result<(), ObsEnvError> {
+        // Undeclaring fails when the version was never declared, which is
+        // fine.
+        let _ = self.run(&["undeclare", "--force", product, version]);
+        self.run(&[
+            "declare",
+            "-r",
+            &path.to_string_lossy(),
+            product,
+            version,
+            "-t",
+            &self.tag,
+        ])
+    }
+
+    fn run(&self, args: &[&str]) -> Result<(), ObsEnvError> {
+        let command = format!("{} {}", self.command, args.join(" "));
+        log::debug!("Running {command}");
+        match Command::new(&self.command).args(args).output() {
+            Ok(output) if output.status.success() => Ok(()),
+            Ok(output) => Err(ObsEnvError::CommandFailed {
+                command,
+                message: String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+            }),
+            Err(error) => Err(ObsEnvError::CommandFailed {
+                command,
+                message: error.to_string(),
+            }),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.metadata()
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// Render `versions` as an EUPS tagged product list for `tag`, the
+/// `current.list` format read from `ups_db`:
+///
+/// ```text
+/// EUPS distribution current version list. Version 1.0
+/// #product             flavor     version
+/// #--------------------------------------
+/// ts_wep               generic    v1.2.0
+/// ```
+///
+/// Products are named with `products`, which maps repository names to
+/// EUPS product names. Repositories missing from it are left out of the
+/// list and returned.
+pub fn tag_file(
+    tag: &str,
+    versions: &BTreeMap<String, RepoVersion>,
+    products: &BTreeMap<String, String>,
+) -> (String, Vec<String>) {
+    let mut content = format!(
+        "EUPS distribution {tag} version list. Version 1.0\n{:<20} {:<10} version\n#{}\n",
+        "#product",
+        "flavor",
+        "-".repeat(38)
+    );
+    let mut omitted = Vec::new();
+    let mut lines: Vec<(&str, &str)> = Vec::new();
+    for (repo_name, version) in versions {
+        match products.get(repo_name) {
+            Some(product) => lines.push((product, &version.describe)),
+            None => omitted.push(repo_name.to_owned()),
+        }
+    }
+    lines.sort();
+    for (product, version) in lines {
+        content.push_str(&format!("{product:<20} {TAG_FILE_FLAVOR:<10} {version}\n"));
+    }
+    (content, omitted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{tag_file, Eups};
+    use crate::manifest::RepoVersion;
+    use std::collections::BTreeMap;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_is_available() {
+        assert!(Eups::new("current").command("sh").is_available());
+        assert!(Eups::new("current").command("/bin/sh").is_available());
+        assert!(!Eups::new("current").command("/bin").is_available());
+        assert!(!Eups::new("current")
+            .command("no-such-eups-command")
+            .is_available());
+    }
+
+    #[test]
+    fn test_tag_file() {
+        let versions = BTreeMap::from(
+            [
+                ("ts_wep", "v1.2.0"),
+                ("ts_observatory_control", "v0.3.1-2-g1111aaa"),
+                ("ts_unknown", "1.0.0"),
+            ]
+            .map(|(name, describe)| (name.to_owned(), RepoVersion::new(name, describe))),
+        );
+        let products = BTreeMap::from(
+            [
+                ("ts_wep", "ts_wep"),
+                ("ts_observatory_control", "ts_observatoryControl"),
+            ]
+            .map(|(repo_name, product)| (repo_name.to_owned(), product.to_owned())),
+        );
+
+        let (content, omitted) = tag_file("current", &versions, &products);
+        assert_eq!(
+            content,
+            concat!(
+                "EUPS distribution current version list. Version 1.0\n",
+                "#product             flavor     version\n",
+                "#--------------------------------------\n",
+                "ts_observatoryControl generic    v0.3.1-2-g1111aaa\n",
+                "ts_wep               generic    v1.2.0\n",
+            )
+        );
+        assert_eq!(omitted, ["ts_unknown"]);
+    }
+}
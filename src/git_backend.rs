@@ -0,0 +1,1745 @@
+use crate::auth;
+use git2::{
+    build::{CheckoutBuilder, RepoBuilder},
+    ApplyLocation, BranchType, Config, ConfigLevel, DescribeOptions, Diff, DiffFormat, DiffOptions,
+    Error, ErrorClass, ErrorCode, ObjectType, Odb, Oid, PushOptions, Remote, RemoteCallbacks,
+    Repository, RepositoryState, StatusOptions, Tree, Worktree,
+};
+use log::{debug, trace};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::RefCell,
+    collections::HashSet,
+    fmt::{self, Display},
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::Mutex,
+};
+
+/// Directories whose repositories are opened even when owned by another
+/// user, as if they were listed in git's safe.directory.
+static SAFE_DIRECTORIES: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Name and email commits are made with.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Identity {
+    pub name: String,
+    pub email: String,
+}
+
+impl Identity {
+    /// Identity named `name`, with `email`.
+    pub fn new(name: &str, email: &str) -> Identity {
+        Identity {
+            name: name.to_owned(),
+            email: email.to_owned(),
+        }
+    }
+}
+
+impl Default for Identity {
+    /// Identity of the commits made by the manager in repositories with no
+    /// identity configured.
+    fn default() -> Identity {
+        Identity::new("obs-env manager", "obs-env@localhost")
+    }
+}
+
+impl Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} <{}>", self.name, self.email)
+    }
+}
+
+/// Id and subject of a commit.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CommitSummary {
+    pub id: String,
+    /// First line of the message.
+    pub subject: String,
+}
+
+impl Display for CommitSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", &self.id[..self.id.len().min(12)], self.subject)
+    }
+}
+
+/// Commit found by [`GitBackend::search_commits`].
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CommitInfo {
+    pub id: String,
+    /// Commit time, in seconds since the Unix epoch.
+    pub time: u64,
+    /// Name of the author.
+    pub author: String,
+    /// First line of the message.
+    pub subject: String,
+}
+
+impl Display for CommitInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            &self.id[..self.id.len().min(12)],
+            &crate::audit::format_utc(self.time)[..10],
+            self.author,
+            self.subject
+        )
+    }
+}
+
+/// Commits looked for by [`GitBackend::search_commits`].
+#[derive(Clone, Debug)]
+pub struct CommitQuery {
+    /// Matched against the whole message.
+    pub pattern: Regex,
+    /// Only the commits made from this time on, in seconds since the Unix
+    /// epoch.
+    pub since: Option<u64>,
+    /// Only the commits whose author name or email contains this, ignoring
+    /// case.
+    pub author: Option<String>,
+    /// Most recent matches returned.
+    pub max_results: usize,
+}
+
+impl CommitQuery {
+    /// Whether the commit made at `time` by `author`, with `message`, is
+    /// looked for.
+    pub fn matches(&self, time: u64, author: &Identity, message: &str) -> bool {
+        let author_matches = self.author.as_ref().is_none_or(|wanted| {
+            let wanted = wanted.to_lowercase();
+            author.name.to_lowercase().contains(&wanted)
+                || author.email.to_lowercase().contains(&wanted)
+        });
+        self.since.is_none_or(|since| time >= since)
+            && author_matches
+            && self.pattern.is_match(message)
+    }
+}
+
+/// Objects transferred so far by a clone or fetch.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TransferProgress {
+    /// Objects received from the remote.
+    pub received_objects: usize,
+    /// Objects the remote is sending.
+    pub total_objects: usize,
+    /// Bytes received from the remote.
+    pub received_bytes: usize,
+}
+
+impl From<git2::Progress<'_>> for TransferProgress {
+    fn from(progress: git2::Progress<'_>) -> TransferProgress {
+        TransferProgress {
+            received_objects: progress.received_objects(),
+            total_objects: progress.total_objects(),
+            received_bytes: progress.received_bytes(),
+        }
+    }
+}
+
+/// Receives what happens during a clone or fetch.
+///
+/// Any `Fn(&TransferProgress)` closure is one, ignoring the messages.
+pub trait TransferObserver {
+    /// Objects were transferred.
+    fn on_progress(&self, progress: &TransferProgress);
+
+    /// The remote sent `message`, as git shows with `--progress`, or a
+    /// reference was updated, as git shows with `--verbose`.
+    fn on_message(&self, _message: &str) {}
+}
+
+impl<F: Fn(&TransferProgress)> TransferObserver for F {
+    fn on_progress(&self, progress: &TransferProgress) {
+        self(progress)
+    }
+}
+
+/// State of a repository in the environment.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RepoStatus {
+    /// Operation left in progress, as reported by [`in_progress_state`].
+    pub in_progress: Option<String>,
+    /// Whether tracked files were changed since HEAD.
+    pub dirty: bool,
+    /// Tracked files changed since HEAD, staged or not.
+    pub modified_files: Vec<String>,
+    /// Number of untracked files, or directories, not ignored.
+    pub untracked: usize,
+}
+
+/// Objects a partial clone leaves out, to fetch from origin when they are
+/// needed.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum CloneFilter {
+    /// Every commit and tree, but only the files checked out.
+    Blobless,
+    /// Every commit, but only the trees and files checked out.
+    Treeless,
+}
+
+impl CloneFilter {
+    /// Filter given to `git clone --filter`.
+    pub fn spec(&self) -> &'static str {
+        match self {
+            CloneFilter::Blobless => "blob:none",
+            CloneFilter::Treeless => "tree:0",
+        }
+    }
+}
+
+/// How much of a repository a clone fetches: everything by default.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CloneOptions {
+    /// Number of commits of history, for a shallow clone.
+    pub depth: Option<u32>,
+    /// Objects left out, for a partial clone.
+    pub filter: Option<CloneFilter>,
+}
+
+impl CloneOptions {
+    /// Arguments of `git clone` making the same clone.
+    fn args(&self) -> Vec<String> {
+        let depth = self
+            .depth
+            .into_iter()
+            .flat_map(|depth| ["--depth".to_owned(), depth.to_string()]);
+        let filter = self
+            .filter
+            .map(|filter| format!("--filter={}", filter.spec()));
+        depth.chain(filter).collect()
+    }
+}
+
+/// Operation of a [`GitBackend`] changing a repository, with what is
+/// needed to render it as the git commands doing the same.
+#[derive(Clone, Debug, PartialEq)]
+pub enum GitOperation {
+    Clone {
+        url: String,
+        bare: bool,
+        options: CloneOptions,
+    },
+    CloneBranch {
+        url: String,
+        branch: String,
+        options: CloneOptions,
+    },
+    Fetch {
+        refspecs: Vec<String>,
+        download_tags: bool,
+    },
+    /// Fetch `depth` commits of history from origin, or all of it.
+    Deepen {
+        depth: Option<u32>,
+    },
+    CheckoutBranch {
+        branch: String,
+    },
+    FastForward {
+        branch: String,
+    },
+    Reset {
+        revision: String,
+        branch: Option<String>,
+    },
+    /// Abort `in_progress`, as reported by [`in_progress_state`].
+    AbortInProgress {
+        in_progress: Option<String>,
+    },
+    CreateBranch {
+        branch: String,
+        revision: String,
+    },
+    DeleteBranch {
+        branch: String,
+    },
+    DeleteReference {
+        name: String,
+    },
+    SetRemoteUrl {
+        url: String,
+    },
+    SetConfig {
+        name: String,
+        value: String,
+    },
+    Push {
+        url: String,
+        refspecs: Vec<String>,
+    },
+    ApplyPatch {
+        patch: String,
+    },
+}
+
+impl GitOperation {
+    /// Shell commands doing the operation on the repository at `path`
+    /// with the git command line.
+    ///
+    /// ```
+    /// use std::path::Path;
+    /// use ts_observing_environment::git_backend::GitOperation;
+    ///
+    /// let reset = GitOperation::Reset {
+    ///     revision: "refs/tags/v1.2.0".to_owned(),
+    ///     branch: None,
+    /// };
+    /// assert_eq!(
+    ///     reset.to_commands(Path::new("/obs-env/ts_wep")),
+    ///     ["git -C /obs-env/ts_wep checkout --force --detach refs/tags/v1.2.0"]
+    /// );
+    /// ```
+    pub fn to_commands(&self, path: &Path) -> Vec<String> {
+        let path = path.to_string_lossy();
+        let git = |args: &[&str]| {
+            ["git", "-C", &path]
+                .iter()
+                .chain(args)
+                .map(|arg| shell_quote(arg))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        match self {
+            GitOperation::Clone { url, bare, options } => {
+                let options = options.args();
+                let mut args = vec!["git", "clone"];
+                if *bare {
+                    args.push("--bare");
+                }
+                args.extend(options.iter().map(String::as_str));
+                args.extend([url.as_str(), &path]);
+                vec![args
+                    .into_iter()
+                    .map(shell_quote)
+                    .collect::<Vec<_>>()
+                    .join(" ")]
+            }
+            GitOperation::CloneBranch {
+                url,
+                branch,
+                options,
+            } => {
+                let options = options.args();
+                let mut args = vec!["git", "clone", "--single-branch", "--branch", branch];
+                args.extend(options.iter().map(String::as_str));
+                args.extend([url.as_str(), &path]);
+                vec![args
+                    .into_iter()
+                    .map(shell_quote)
+                    .collect::<Vec<_>>()
+                    .join(" ")]
+            }
+            GitOperation::Deepen { depth: Some(depth) } => {
+                vec![git(&[
+                    "fetch",
+                    "--tags",
+                    "--depth",
+                    &depth.to_string(),
+                    "origin",
+                ])]
+            }
+            GitOperation::Deepen { depth: None } => {
+                vec![git(&["fetch", "--tags", "--unshallow", "origin"])]
+            }
+            GitOperation::Fetch {
+                refspecs,
+                download_tags,
+            } => {
+                let mut args = vec!["fetch"];
+                if *download_tags {
+                    args.push("--tags");
+                }
+                args.push("origin");
+                args.extend(
+                    refspecs
+                        .iter()
+                        .map(String::as_str)
+                        .filter(|refspec| !refspec.is_empty()),
+                );
+                vec![git(&args)]
+            }
+            GitOperation::CheckoutBranch { branch } => vec![git(&[
+                "checkout",
+                "--force",
+                "-B",
+                branch,
+                &format!("origin/{branch}"),
+            ])],
+            GitOperation::FastForward { branch } => {
+                vec![git(&["merge", "--ff-only", &format!("origin/{branch}")])]
+            }
+            GitOperation::Reset { revision, branch } => {
+                let mut commands = Vec::new();
+                if let Some(branch) = branch {
+                    commands.push(git(&["branch", "--force", branch, revision]));
+                }
+                commands.push(git(&["checkout", "--force", "--detach", revision]));
+                commands
+            }
+            GitOperation::AbortInProgress { in_progress } => match in_progress.as_deref() {
+                Some("rebase") => vec![git(&["rebase", "--abort"])],
+                _ => vec![git(&["reset", "--hard", "HEAD"])],
+            },
+            GitOperation::CreateBranch { branch, revision } => {
+                vec![git(&["branch", branch, revision])]
+            }
+            GitOperation::DeleteBranch { branch } => vec![git(&["branch", "-D", branch])],
+            GitOperation::DeleteReference { name } => vec![git(&["update-ref", "-d", name])],
+            GitOperation::SetRemoteUrl { url } => vec![git(&["remote", "set-url", "origin", url])],
+            GitOperation::SetConfig { name, value } => vec![git(&["config", name, value])],
+            GitOperation::Push { url, refspecs } => {
+                let mut args = vec!["push", url.as_str()];
+                args.extend(refspecs.iter().map(String::as_str));
+                vec![git(&args)]
+            }
+            GitOperation::ApplyPatch { patch } => {
+                vec![format!(
+                    "{} <<'EOF'\n{patch}EOF",
+                    git(&["apply", "--index"])
+                )]
+            }
+        }
+    }
+}
+
+/// `arg` quoted for a POSIX shell, if needed.
+fn shell_quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:+=@%,^~".contains(c));
+    if plain {
+        arg.to_owned()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// Git operations an [`ObservingEnvironment`](crate::ObservingEnvironment)
+/// is built on.
+///
+/// Repositories are identified by their path. [`Git2Backend`] implements
+/// the operations with libgit2 and is the default;
+/// [`FakeBackend`](crate::testing::FakeBackend) keeps repositories in
+/// memory, for tests.
+pub trait GitBackend: Send + Sync {
+    /// Make sure there is a repository at `path`.
+    fn open(&self, path: &Path) -> Result<(), Error>;
+
+    /// Whether the repository has no commits yet, with HEAD pointing to an
+    /// unborn branch and no other reference.
+    fn is_empty(&self, path: &Path) -> Result<bool, Error>;
+
+    /// Clone `url` into `path`, shallow or partial as `options` ask,
+    /// reporting the transfer to `progress`.
+    fn clone(
+        &self,
+        url: &str,
+        path: &Path,
+        bare: bool,
+        options: CloneOptions,
+        progress: &dyn TransferObserver,
+    ) -> Result<(), Error>;
+
+    /// Clone the repository at `url` into `path` as [`clone`](Self::clone)
+    /// does, but only fetching `branch`, and the tags on it, and checking
+    /// it out. Fails with [`ErrorCode::NotFound`] if origin has no such
+    /// branch.
+    fn clone_branch(
+        &self,
+        url: &str,
+        path: &Path,
+        branch: &str,
+        options: CloneOptions,
+        progress: &dyn TransferObserver,
+    ) -> Result<(), Error>;
+
+    /// Fetch `refspecs` from origin, together with all tags if
+    /// `download_tags` is set, reporting the transfer to `progress`.
+    fn fetch(
+        &self,
+        path: &Path,
+        refspecs: &[&str],
+        download_tags: bool,
+        progress: &dyn TransferObserver,
+    ) -> Result<(), Error>;
+
+    /// Whether the repository at `path` is a shallow clone.
+    fn is_shallow(&self, path: &Path) -> Result<bool, Error>;
+
+    /// Fetch the branches and tags of origin with `depth` commits of
+    /// history into the shallow repository at `path`, or with all of it,
+    /// making it a full clone.
+    fn deepen(
+        &self,
+        path: &Path,
+        depth: Option<u32>,
+        progress: &dyn TransferObserver,
+    ) -> Result<(), Error>;
+
+    /// Check out `branch` from origin, which must have been fetched already.
+    /// In a worktree, if another worktree of the object store has `branch`
+    /// checked out, HEAD is detached at origin's `branch` instead, leaving
+    /// the shared local branch where it is.
+    fn checkout_branch(&self, path: &Path, branch: &str) -> Result<(), Error>;
+
+    /// Move the local `branch`, which must be checked out, to its fetched
+    /// origin counterpart, failing with [`ErrorCode::NotFastForward`] if
+    /// they have diverged, or with [`ErrorCode::Locked`] if another worktree
+    /// of the object store has it checked out too.
+    fn fast_forward(&self, path: &Path, branch: &str) -> Result<(), Error>;
+
+    /// Detach HEAD at `revision` and reset the working tree to it, throwing
+    /// away local changes. If `branch` is given, a local branch with that
+    /// name is also created, or moved, at the revision, unless another
+    /// worktree of the object store has it checked out.
+    fn reset(&self, path: &Path, revision: &str, branch: Option<&str>) -> Result<(), Error>;
+
+    /// Id of the commit `spec` resolves to, as understood by rev-parse.
+    fn rev_parse(&self, path: &Path, spec: &str) -> Result<String, Error>;
+
+    /// Operation in progress and working tree state of the repository.
+    fn status(&self, path: &Path) -> Result<RepoStatus, Error>;
+
+    /// Abort the operation in progress, leaving the working tree at HEAD.
+    fn abort_in_progress(&self, path: &Path) -> Result<(), Error>;
+
+    /// Names of the references matching `glob`, e.g. `refs/tags/*`.
+    fn list_refs(&self, path: &Path, glob: &str) -> Result<Vec<String>, Error>;
+
+    /// Describe HEAD by the closest tag, or by its commit id if there is
+    /// none.
+    fn describe(&self, path: &Path) -> Result<String, Error>;
+
+    /// Local branch checked out, or none if HEAD is detached.
+    fn current_branch(&self, path: &Path) -> Result<Option<String>, Error>;
+
+    /// Branch `refs/remotes/origin/HEAD` points to, i.e. the default branch
+    /// of origin when cloned, if any.
+    fn remote_head(&self, path: &Path) -> Result<Option<String>, Error>;
+
+    /// Number of commits reachable from the local branches or HEAD, but
+    /// from no remote branch or tag.
+    fn local_commits(&self, path: &Path) -> Result<usize, Error>;
+
+    /// Corrupt objects of the repository at `path`, and objects missing
+    /// from the history of its references and HEAD, reading every object,
+    /// as `git fsck` would.
+    fn check_objects(&self, path: &Path) -> Result<Vec<String>, Error>;
+
+    /// Ids of the commits reachable from `revision` but from no reference
+    /// matching one of the `hidden` globs, e.g. `refs/remotes/*`, newest
+    /// first.
+    fn commits_only_in(
+        &self,
+        path: &Path,
+        revision: &str,
+        hidden: &[&str],
+    ) -> Result<Vec<String>, Error>;
+
+    /// Number of commits reachable from `local` but not from `upstream`,
+    /// and from `upstream` but not from `local`.
+    fn ahead_behind(
+        &self,
+        path: &Path,
+        local: &str,
+        upstream: &str,
+    ) -> Result<(usize, usize), Error>;
+
+    /// Commits reachable from `to` but not from `from`, newest first.
+    fn commit_log(&self, path: &Path, from: &str, to: &str) -> Result<Vec<CommitSummary>, Error>;
+
+    /// Most recent commits of the local branches, remote-tracking branches
+    /// and HEAD matching `query`, newest first.
+    fn search_commits(&self, path: &Path, query: &CommitQuery) -> Result<Vec<CommitInfo>, Error>;
+
+    /// Commit time of `revision`, in seconds since the Unix epoch.
+    fn commit_time(&self, path: &Path, revision: &str) -> Result<u64, Error>;
+
+    /// Id of the last commit of the first-parent history of `revision`
+    /// committed before `time`, in seconds since the Unix epoch, or none
+    /// if the history starts later.
+    fn commit_before(
+        &self,
+        path: &Path,
+        revision: &str,
+        time: u64,
+    ) -> Result<Option<String>, Error>;
+
+    /// Create the local `branch` at `revision`, failing with
+    /// [`ErrorCode::Exists`] if it exists.
+    fn create_branch(&self, path: &Path, branch: &str, revision: &str) -> Result<(), Error>;
+
+    /// Delete the local `branch`.
+    fn delete_branch(&self, path: &Path, branch: &str) -> Result<(), Error>;
+
+    /// Delete the reference `name`, e.g. `refs/remotes/origin/main`.
+    fn delete_reference(&self, path: &Path, name: &str) -> Result<(), Error>;
+
+    /// Names of the branches of origin, asked to origin itself.
+    fn remote_branches(&self, path: &Path) -> Result<Vec<String>, Error>;
+
+    /// Remote-tracking branches, e.g. `refs/remotes/origin/tickets/DM-1`,
+    /// of the branches the remotes of the repository no longer have, asked
+    /// to each of them.
+    fn stale_remote_refs(&self, path: &Path) -> Result<Vec<String>, Error>;
+
+    /// Names of the references of the repository at `url`, e.g. `HEAD` and
+    /// `refs/heads/main`, asked to it with no local repository.
+    fn ls_remote(&self, url: &str) -> Result<Vec<String>, Error>;
+
+    /// Content of `file` in the tree of `revision`.
+    fn read_file(&self, path: &Path, revision: &str, file: &Path) -> Result<String, Error>;
+
+    /// Url of origin.
+    fn remote_url(&self, path: &Path) -> Result<Option<String>, Error>;
+
+    /// Point origin at `url`.
+    fn set_remote_url(&self, path: &Path, url: &str) -> Result<(), Error>;
+
+    /// Identity configured for commits in the repository, by its own, the
+    /// user's or the system git configuration.
+    fn identity(&self, path: &Path) -> Result<Option<Identity>, Error>;
+
+    /// Value of the configuration `name` of the repository, if set.
+    fn config(&self, path: &Path, name: &str) -> Result<Option<String>, Error>;
+
+    /// Set the configuration `name` of the repository itself to `value`.
+    fn set_config(&self, path: &Path, name: &str, value: &str) -> Result<(), Error>;
+
+    /// Push `refspecs` to the remote at `url`, which need not be configured
+    /// in the repository, failing if the remote rejects any of them.
+    fn push(&self, path: &Path, url: &str, refspecs: &[String]) -> Result<(), Error>;
+
+    /// Changes to the tracked files since HEAD, staged or not, as a patch.
+    fn diff_head(&self, path: &Path) -> Result<String, Error>;
+
+    /// Apply `patch`, as made by [`diff_head`](Self::diff_head), to the
+    /// working tree and the index.
+    fn apply_patch(&self, path: &Path, patch: &str) -> Result<(), Error>;
+}
+
+/// [`GitBackend`] using libgit2, authenticating with the user's
+/// credentials.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Git2Backend;
+
+impl GitBackend for Git2Backend {
+    fn open(&self, path: &Path) -> Result<(), Error> {
+        open_repository(path).map(|_| ())
+    }
+
+    fn is_empty(&self, path: &Path) -> Result<bool, Error> {
+        open_repository(path)?.is_empty()
+    }
+
+    fn clone(
+        &self,
+        url: &str,
+        path: &Path,
+        bare: bool,
+        options: CloneOptions,
+        progress: &dyn TransferObserver,
+    ) -> Result<(), Error> {
+        clone(url, path, bare, None, options, progress).map(|_| ())
+    }
+
+    fn clone_branch(
+        &self,
+        url: &str,
+        path: &Path,
+        branch: &str,
+        options: CloneOptions,
+        progress: &dyn TransferObserver,
+    ) -> Result<(), Error> {
+        clone(url, path, false, Some(branch), options, progress).map(|_| ())
+    }
+
+    fn fetch(
+        &self,
+        path: &Path,
+        refspecs: &[&str],
+        download_tags: bool,
+        progress: &dyn TransferObserver,
+    ) -> Result<(), Error> {
+        fetch(&open_repository(path)?, refspecs, download_tags, progress)
+    }
+
+    fn is_shallow(&self, path: &Path) -> Result<bool, Error> {
+        Ok(open_repository(path)?.is_shallow())
+    }
+
+    fn deepen(
+        &self,
+        path: &Path,
+        depth: Option<u32>,
+        progress: &dyn TransferObserver,
+    ) -> Result<(), Error> {
+        let repository = open_repository(path)?;
+        let mut remote = repository.find_remote("origin")?;
+        let url = auth::resolve_url(remote.url().unwrap_or_default());
+        auth::with_credentials_and_callbacks(&url, callbacks(progress), |mut fetch_options| {
+            // libgit2 unshallows for the largest depth, as git does for
+            // --unshallow.
+            fetch_options
+                .depth(depth.map_or(i32::MAX, |depth| depth.min(i32::MAX as u32) as i32))
+                .download_tags(git2::AutotagOption::All);
+            remote.fetch::<&str>(&[], Some(&mut fetch_options), None)
+        })
+    }
+
+    fn checkout_branch(&self, path: &Path, branch: &str) -> Result<(), Error> {
+        checkout_branch(&open_repository(path)?, branch)
+    }
+
+    fn fast_forward(&self, path: &Path, branch: &str) -> Result<(), Error> {
+        let repository = open_repository(path)?;
+        let upstream = repository
+            .find_branch(&format!("origin/{branch}"), BranchType::Remote)?
+            .get()
+            .peel_to_commit()?;
+        if let Some(worktree) = checked_out_elsewhere(&repository, branch)? {
+            return Err(Error::new(
+                ErrorCode::Locked,
+                ErrorClass::Worktree,
+                format!("{branch} is checked out in worktree {worktree} of the object store"),
+            ));
+        }
+        let mut local = repository.find_branch(branch, BranchType::Local)?;
+        let head = local.get().peel_to_commit()?;
+        if head.id() == upstream.id() {
+            return Ok(());
+        }
+        if !repository.graph_descendant_of(upstream.id(), head.id())? {
+            return Err(Error::new(
+                ErrorCode::NotFastForward,
+                ErrorClass::Reference,
+                format!("{branch} has diverged from origin/{branch}"),
+            ));
+        }
+        fetch_missing_objects(&repository, upstream.id())?;
+        repository.checkout_tree(upstream.as_object(), Some(CheckoutBuilder::new().safe()))?;
+        local
+            .get_mut()
+            .set_target(upstream.id(), &format!("fast-forward {branch}"))?;
+        Ok(())
+    }
+
+    fn reset(&self, path: &Path, revision: &str, branch: Option<&str>) -> Result<(), Error> {
+        let repository = open_repository(path)?;
+        let object = repository.revparse_single(revision)?;
+        let commit = object.peel_to_commit()?;
+
+        if let Some(branch) = branch {
+            match checked_out_elsewhere(&repository, branch)? {
+                Some(worktree) => {
+                    debug!("{branch} is checked out in worktree {worktree}, not moving it")
+                }
+                None => {
+                    repository.branch(branch, &commit, true)?;
+                }
+            }
+        }
+        fetch_missing_objects(&repository, commit.id())?;
+        repository.set_head_detached(commit.id())?;
+        let mut checkout_build = CheckoutBuilder::new();
+        repository.reset(
+            commit.as_object(),
+            git2::ResetType::Hard,
+            Some(checkout_build.force()),
+        )
+    }
+
+    fn rev_parse(&self, path: &Path, spec: &str) -> Result<String, Error> {
+        let repository = open_repository(path)?;
+        let commit = repository.revparse_single(spec)?.peel_to_commit()?;
+        Ok(commit.id().to_string())
+    }
+
+    fn status(&self, path: &Path) -> Result<RepoStatus, Error> {
+        let repository = open_repository(path)?;
+        let mut status = RepoStatus {
+            in_progress: in_progress_state(&repository),
+            ..RepoStatus::default()
+        };
+        if !repository.is_bare() {
+            let mut options = StatusOptions::new();
+            options.include_untracked(true).include_ignored(false);
+            for entry in repository.statuses(Some(&mut options))?.iter() {
+                if entry.status() == git2::Status::WT_NEW {
+                    status.untracked += 1;
+                } else {
+                    let path = String::from_utf8_lossy(entry.path_bytes());
+                    status.modified_files.push(path.into_owned());
+                }
+            }
+            status.dirty = !status.modified_files.is_empty();
+        }
+        Ok(status)
+    }
+
+    fn abort_in_progress(&self, path: &Path) -> Result<(), Error> {
+        abort_in_progress(&open_repository(path)?)
+    }
+
+    fn list_refs(&self, path: &Path, glob: &str) -> Result<Vec<String>, Error> {
+        let repository = open_repository(path)?;
+        let mut names = repository.references_glob(glob)?;
+        Ok(names
+            .names()
+            .filter_map(|name| name.ok().map(|name| name.to_owned()))
+            .collect())
+    }
+
+    fn describe(&self, path: &Path) -> Result<String, Error> {
+        let repository = open_repository(path)?;
+        let mut opts = DescribeOptions::new();
+
+        repository
+            .describe(opts.describe_tags())
+            .or_else(|_| repository.describe(opts.show_commit_oid_as_fallback(true)))
+            .and_then(|description| description.format(None))
+    }
+
+    fn current_branch(&self, path: &Path) -> Result<Option<String>, Error> {
+        let repository = open_repository(path)?;
+        let head = repository.head()?;
+        if head.is_branch() {
+            Ok(head.shorthand().map(|name| name.to_owned()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn remote_head(&self, path: &Path) -> Result<Option<String>, Error> {
+        let repository = open_repository(path)?;
+        let head = match repository.find_reference("refs/remotes/origin/HEAD") {
+            Ok(head) => head,
+            Err(error) if error.code() == ErrorCode::NotFound => return Ok(None),
+            Err(error) => return Err(error),
+        };
+        Ok(head
+            .symbolic_target()
+            .and_then(|target| target.strip_prefix("refs/remotes/origin/"))
+            .map(|branch| branch.to_owned()))
+    }
+
+    fn local_commits(&self, path: &Path) -> Result<usize, Error> {
+        let repository = open_repository(path)?;
+        let mut revwalk = repository.revwalk()?;
+        revwalk.push_glob("refs/heads")?;
+        if repository.head_detached()? {
+            revwalk.push_head()?;
+        }
+        revwalk.hide_glob("refs/remotes")?;
+        revwalk.hide_glob("refs/tags")?;
+        Ok(revwalk.collect::<Result<Vec<_>, _>>()?.len())
+    }
+
+    fn check_objects(&self, path: &Path) -> Result<Vec<String>, Error> {
+        let repository = open_repository(path)?;
+        let odb = repository.odb()?;
+        let mut ids = Vec::new();
+        odb.foreach(|id| {
+            ids.push(*id);
+            true
+        })?;
+        let mut problems: Vec<String> = ids
+            .iter()
+            .filter_map(|id| {
+                let error = odb.read(*id).err()?;
+                Some(format!("object {id} is corrupt: {}", error.message()))
+            })
+            .collect();
+
+        let mut tips = Vec::new();
+        for reference in repository.references()? {
+            let reference = reference?;
+            if let (Some(name), Some(target)) = (reference.name(), reference.target()) {
+                tips.push((name.to_owned(), target));
+            }
+        }
+        if let Some(target) = repository.head().ok().and_then(|head| head.target()) {
+            tips.push(("HEAD".to_owned(), target));
+        }
+        let mut revwalk = repository.revwalk()?;
+        for (name, target) in tips {
+            match repository
+                .find_object(target, None)
+                .and_then(|object| object.peel_to_commit())
+            {
+                Ok(commit) => revwalk.push(commit.id())?,
+                Err(error) if error.code() == ErrorCode::NotFound => {
+                    problems.push(format!("{name} points to missing object {target}"))
+                }
+                // Tags of trees or blobs have no history.
+                Err(_) => {}
+            }
+        }
+        // The trees and files a partial clone leaves out are missing on
+        // purpose, only the references to its commits can be checked.
+        if is_partial(&repository) {
+            return Ok(problems);
+        }
+        let mut seen = HashSet::new();
+        for commit in revwalk {
+            let tree = commit.and_then(|commit| repository.find_commit(commit)?.tree());
+            match tree {
+                Ok(tree) => check_tree(&repository, &odb, &tree, &mut seen, &mut problems),
+                Err(error) => {
+                    problems.push(format!("history is incomplete: {}", error.message()));
+                    break;
+                }
+            }
+        }
+        Ok(problems)
+    }
+
+    fn commits_only_in(
+        &self,
+        path: &Path,
+        revision: &str,
+        hidden: &[&str],
+    ) -> Result<Vec<String>, Error> {
+        let repository = open_repository(path)?;
+        let mut revwalk = repository.revwalk()?;
+        revwalk.push(repository.revparse_single(revision)?.peel_to_commit()?.id())?;
+        for glob in hidden {
+            revwalk.hide_glob(glob)?;
+        }
+        revwalk
+            .map(|commit| commit.map(|commit| commit.to_string()))
+            .collect()
+    }
+
+    fn ahead_behind(
+        &self,
+        path: &Path,
+        local: &str,
+        upstream: &str,
+    ) -> Result<(usize, usize), Error> {
+        let repository = open_repository(path)?;
+        let local = repository.revparse_single(local)?.peel_to_commit()?.id();
+        let upstream = repository.revparse_single(upstream)?.peel_to_commit()?.id();
+        repository.graph_ahead_behind(local, upstream)
+    }
+
+    fn commit_log(&self, path: &Path, from: &str, to: &str) -> Result<Vec<CommitSummary>, Error> {
+        let repository = open_repository(path)?;
+        let mut revwalk = repository.revwalk()?;
+        revwalk.push(repository.revparse_single(to)?.peel_to_commit()?.id())?;
+        revwalk.hide(repository.revparse_single(from)?.peel_to_commit()?.id())?;
+        revwalk
+            .map(|id| {
+                let commit = repository.find_commit(id?)?;
+                Ok(CommitSummary {
+                    id: commit.id().to_string(),
+                    subject: commit.summary().unwrap_or_default().to_owned(),
+                })
+            })
+            .collect()
+    }
+
+    fn search_commits(&self, path: &Path, query: &CommitQuery) -> Result<Vec<CommitInfo>, Error> {
+        let repository = open_repository(path)?;
+        let mut revwalk = repository.revwalk()?;
+        revwalk.set_sorting(git2::Sort::TIME)?;
+        if repository.head().is_ok() {
+            revwalk.push_head()?;
+        }
+        revwalk.push_glob("refs/heads")?;
+        revwalk.push_glob("refs/remotes")?;
+        let mut matches = Vec::new();
+        for id in revwalk {
+            if matches.len() >= query.max_results {
+                break;
+            }
+            let commit = repository.find_commit(id?)?;
+            let time = commit.time().seconds().max(0) as u64;
+            if query.since.is_some_and(|since| time < since) {
+                break;
+            }
+            let author = commit.author();
+            let author = Identity::new(
+                author.name().unwrap_or_default(),
+                author.email().unwrap_or_default(),
+            );
+            if query.matches(time, &author, commit.message().unwrap_or_default()) {
+                matches.push(CommitInfo {
+                    id: commit.id().to_string(),
+                    time,
+                    author: author.name,
+                    subject: commit.summary().unwrap_or_default().to_owned(),
+                });
+            }
+        }
+        Ok(matches)
+    }
+
+    fn commit_time(&self, path: &Path, revision: &str) -> Result<u64, Error> {
+        let repository = open_repository(path)?;
+        let commit = repository.revparse_single(revision)?.peel_to_commit()?;
+        Ok(commit.time().seconds().max(0) as u64)
+    }
+
+    fn commit_before(
+        &self,
+        path: &Path,
+        revision: &str,
+        time: u64,
+    ) -> Result<Option<String>, Error> {
+        let repository = open_repository(path)?;
+        let mut revwalk = repository.revwalk()?;
+        revwalk.push(repository.revparse_single(revision)?.peel_to_commit()?.id())?;
+        revwalk.simplify_first_parent()?;
+        for id in revwalk {
+            let commit = repository.find_commit(id?)?;
+            if commit.time().seconds() < time as i64 {
+                return Ok(Some(commit.id().to_string()));
+            }
+        }
+        Ok(None)
+    }
+
+    fn create_branch(&self, path: &Path, branch: &str, revision: &str) -> Result<(), Error> {
+        let repository = open_repository(path)?;
+        let commit = repository.revparse_single(revision)?.peel_to_commit()?;
+        repository.branch(branch, &commit, false).map(|_| ())
+    }
+
+    fn delete_branch(&self, path: &Path, branch: &str) -> Result<(), Error> {
+        open_repository(path)?
+            .find_branch(branch, BranchType::Local)?
+            .delete()
+    }
+
+    fn delete_reference(&self, path: &Path, name: &str) -> Result<(), Error> {
+        open_repository(path)?.find_reference(name)?.delete()
+    }
+
+    fn remote_branches(&self, path: &Path) -> Result<Vec<String>, Error> {
+        let repository = open_repository(path)?;
+        let mut remote = repository.find_remote("origin")?;
+        let url = auth::resolve_url(remote.url().unwrap_or_default());
+        auth::with_remote_callbacks(&url, RemoteCallbacks::new(), |callbacks| {
+            let connection = remote.connect_auth(git2::Direction::Fetch, Some(callbacks), None)?;
+            Ok(connection
+                .list()?
+                .iter()
+                .filter_map(|head| head.name().strip_prefix("refs/heads/"))
+                .map(str::to_owned)
+                .collect())
+        })
+    }
+
+    fn stale_remote_refs(&self, path: &Path) -> Result<Vec<String>, Error> {
+        let repository = open_repository(path)?;
+        let mut stale = Vec::new();
+        for name in repository.remotes()?.iter().flatten() {
+            let mut remote = repository.find_remote(name)?;
+            let url = auth::resolve_url(remote.url().unwrap_or_default());
+            let on_remote: Vec<String> =
+                auth::with_remote_callbacks(&url, RemoteCallbacks::new(), |callbacks| {
+                    let connection =
+                        remote.connect_auth(git2::Direction::Fetch, Some(callbacks), None)?;
+                    Ok(connection
+                        .list()?
+                        .iter()
+                        .filter_map(|head| head.name().strip_prefix("refs/heads/"))
+                        .map(str::to_owned)
+                        .collect())
+                })?;
+            let prefix = format!("refs/remotes/{name}/");
+            for reference in repository.references_glob(&format!("{prefix}*"))? {
+                let reference = reference?;
+                let Some(ref_name) = reference.name() else {
+                    continue;
+                };
+                let branch = ref_name.trim_start_matches(&prefix);
+                if branch != "HEAD" && !on_remote.iter().any(|on_remote| on_remote == branch) {
+                    stale.push(ref_name.to_owned());
+                }
+            }
+        }
+        Ok(stale)
+    }
+
+    fn ls_remote(&self, url: &str) -> Result<Vec<String>, Error> {
+        let url = auth::resolve_url(url);
+        let mut remote = Remote::create_detached(url.as_str())?;
+        auth::with_remote_callbacks(&url, RemoteCallbacks::new(), |callbacks| {
+            let connection = remote.connect_auth(git2::Direction::Fetch, Some(callbacks), None)?;
+            Ok(connection
+                .list()?
+                .iter()
+                .map(|head| head.name().to_owned())
+                .collect())
+        })
+    }
+
+    fn read_file(&self, path: &Path, revision: &str, file: &Path) -> Result<String, Error> {
+        let repository = open_repository(path)?;
+        let blob = repository
+            .revparse_single(revision)?
+            .peel_to_tree()?
+            .get_path(file)?
+            .to_object(&repository)?
+            .peel_to_blob()?;
+        Ok(String::from_utf8_lossy(blob.content()).into_owned())
+    }
+
+    fn remote_url(&self, path: &Path) -> Result<Option<String>, Error> {
+        let repository = open_repository(path)?;
+        let remote = repository.find_remote("origin")?;
+        Ok(remote.url().map(|url| url.to_owned()))
+    }
+
+    fn set_remote_url(&self, path: &Path, url: &str) -> Result<(), Error> {
+        open_repository(path)?.remote_set_url("origin", url)
+    }
+
+    fn identity(&self, path: &Path) -> Result<Option<Identity>, Error> {
+        let config = open_repository(path)?.config()?.snapshot()?;
+        match (
+            config.get_string("user.name"),
+            config.get_string("user.email"),
+        ) {
+            (Ok(name), Ok(email)) => Ok(Some(Identity { name, email })),
+            _ => Ok(None),
+        }
+    }
+
+    fn config(&self, path: &Path, name: &str) -> Result<Option<String>, Error> {
+        let config = open_repository(path)?.config()?.snapshot()?;
+        match config.get_string(name) {
+            Ok(value) => Ok(Some(value)),
+            Err(error) if error.code() == ErrorCode::NotFound => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn set_config(&self, path: &Path, name: &str, value: &str) -> Result<(), Error> {
+        open_repository(path)?
+            .config()?
+            .open_level(ConfigLevel::Local)?
+            .set_str(name, value)
+    }
+
+    fn push(&self, path: &Path, url: &str, refspecs: &[String]) -> Result<(), Error> {
+        let repository = open_repository(path)?;
+        let url = auth::resolve_url(url);
+        let mut remote = repository.remote_anonymous(&url)?;
+        let rejected = RefCell::new(Vec::new());
+        let mut callbacks = RemoteCallbacks::new();
+        callbacks.push_update_reference(|reference, status| {
+            if let Some(status) = status {
+                rejected
+                    .borrow_mut()
+                    .push(format!("{reference} ({status})"));
+            }
+            Ok(())
+        });
+        auth::with_remote_callbacks(&url, callbacks, |callbacks| {
+            let mut push_options = PushOptions::new();
+            push_options.remote_callbacks(callbacks);
+            remote.push(refspecs, Some(&mut push_options))
+        })?;
+        let rejected = rejected.into_inner();
+        match rejected.is_empty() {
+            true => Ok(()),
+            false => Err(Error::new(
+                ErrorCode::GenericError,
+                ErrorClass::Reference,
+                format!("{url} rejected {}", rejected.join(", ")),
+            )),
+        }
+    }
+
+    fn diff_head(&self, path: &Path) -> Result<String, Error> {
+        let repository = open_repository(path)?;
+        let head = repository.head()?.peel_to_tree()?;
+        let mut diff_options = DiffOptions::new();
+        diff_options.show_binary(true);
+        let diff =
+            repository.diff_tree_to_workdir_with_index(Some(&head), Some(&mut diff_options))?;
+        let mut patch = Vec::new();
+        diff.print(DiffFormat::Patch, |_, _, line| {
+            if matches!(line.origin(), '+' | '-' | ' ') {
+                patch.push(line.origin() as u8);
+            }
+            patch.extend_from_slice(line.content());
+            true
+        })?;
+        String::from_utf8(patch).map_err(|_| {
+            Error::new(
+                ErrorCode::Invalid,
+                ErrorClass::Patch,
+                "the changes are not valid UTF-8",
+            )
+        })
+    }
+
+    fn apply_patch(&self, path: &Path, patch: &str) -> Result<(), Error> {
+        let repository = open_repository(path)?;
+        let diff = Diff::from_buffer(patch.as_bytes())?;
+        repository.apply(&diff, ApplyLocation::Both, None)
+    }
+}
+
+/// Open the repositories under `path` even when they are owned by another
+/// user, as on shared volumes, without adding it to git's safe.directory.
+///
+/// libgit2 can only skip the ownership check for every repository, so it
+/// is done by [`open_repository`] instead once a directory is trusted.
+pub fn trust_directory(path: &Path) {
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    let mut safe_directories = SAFE_DIRECTORIES
+        .lock()
+        .unwrap_or_else(|error| error.into_inner());
+    if !safe_directories.contains(&path) {
+        debug!("Trusting repositories under {}", path.display());
+        safe_directories.push(path);
+    }
+    // Only fails for an unknown option.
+    let _ = unsafe { git2::opts::set_verify_owner_validation(false) };
+}
+
+/// Whether the repository at `path` is owned by another user and is not
+/// trusted, by [`trust_directory`] or git's safe.directory.
+pub fn is_untrusted(path: &Path) -> bool {
+    if !owned_by_other_user(path) {
+        return false;
+    }
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    let safe_directories = SAFE_DIRECTORIES
+        .lock()
+        .unwrap_or_else(|error| error.into_inner());
+    if safe_directories
+        .iter()
+        .any(|safe_directory| path.starts_with(safe_directory))
+    {
+        return false;
+    }
+    !configured_safe_directories()
+        .iter()
+        .any(|safe_directory| safe_directory == "*" || path == Path::new(safe_directory))
+}
+
+/// Add the objects missing from `tree` and its subtrees to `problems`,
+/// skipping the objects in `seen`, which those checked are added to.
+fn check_tree(
+    repository: &Repository,
+    odb: &Odb,
+    tree: &Tree,
+    seen: &mut HashSet<Oid>,
+    problems: &mut Vec<String>,
+) {
+    if !seen.insert(tree.id()) {
+        return;
+    }
+    for entry in tree.iter() {
+        match entry.kind() {
+            Some(ObjectType::Tree) => match repository.find_tree(entry.id()) {
+                Ok(subtree) => check_tree(repository, odb, &subtree, seen, problems),
+                Err(error) => problems.push(format!(
+                    "tree {} is missing: {}",
+                    entry.id(),
+                    error.message()
+                )),
+            },
+            Some(ObjectType::Blob) if seen.insert(entry.id()) && !odb.exists(entry.id()) => {
+                problems.push(format!("blob {} is missing", entry.id()))
+            }
+            // Commits of submodules are in their own repository.
+            _ => {}
+        }
+    }
+}
+
+/// Open the repository at `path`, checking its owner as libgit2 would when
+/// [`trust_directory`] turned its own check off.
+pub(crate) fn open_repository(path: &Path) -> Result<Repository, Error> {
+    let repository = Repository::open(path)?;
+    if !SAFE_DIRECTORIES
+        .lock()
+        .unwrap_or_else(|error| error.into_inner())
+        .is_empty()
+        && is_untrusted(repository.workdir().unwrap_or(repository.path()))
+    {
+        return Err(Error::new(
+            ErrorCode::Owner,
+            ErrorClass::Config,
+            "repository path is not owned by current user",
+        ));
+    }
+    Ok(repository)
+}
+
+#[cfg(unix)]
+fn owned_by_other_user(path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    path.metadata()
+        .is_ok_and(|metadata| metadata.uid() != unsafe { libc::geteuid() })
+}
+
+#[cfg(not(unix))]
+fn owned_by_other_user(_path: &Path) -> bool {
+    false
+}
+
+fn configured_safe_directories() -> Vec<String> {
+    let Ok(config) = Config::open_default() else {
+        return Vec::new();
+    };
+    let mut safe_directories = Vec::new();
+    if let Ok(entries) = config.multivar("safe.directory", None) {
+        let _ = entries.for_each(|entry| {
+            if let Some(value) = entry.value() {
+                safe_directories.push(value.to_owned());
+            }
+        });
+    }
+    safe_directories
+}
+
+/// Describe the operation left in progress in the repository, if any.
+///
+/// A conflicted index is reported even if no operation is in progress.
+pub fn in_progress_state(repository: &Repository) -> Option<String> {
+    let state = match repository.state() {
+        RepositoryState::Clean => None,
+        RepositoryState::Merge => Some("merge"),
+        RepositoryState::Revert | RepositoryState::RevertSequence => Some("revert"),
+        RepositoryState::CherryPick | RepositoryState::CherryPickSequence => Some("cherry-pick"),
+        RepositoryState::Bisect => Some("bisect"),
+        RepositoryState::Rebase
+        | RepositoryState::RebaseInteractive
+        | RepositoryState::RebaseMerge => Some("rebase"),
+        RepositoryState::ApplyMailbox | RepositoryState::ApplyMailboxOrRebase => Some("am"),
+    };
+
+    match state {
+        Some(state) => Some(state.to_owned()),
+        None => match repository.index() {
+            Ok(index) if index.has_conflicts() => Some("conflicted index".to_owned()),
+            _ => None,
+        },
+    }
+}
+
+/// Abort the operation in progress in the repository, leaving the working
+/// tree at HEAD.
+fn abort_in_progress(repository: &Repository) -> Result<(), Error> {
+    if let RepositoryState::Rebase
+    | RepositoryState::RebaseInteractive
+    | RepositoryState::RebaseMerge = repository.state()
+    {
+        match repository.open_rebase(None) {
+            Ok(mut rebase) => return rebase.abort(),
+            Err(error) => log::debug!(
+                "{}: could not open rebase, resetting instead: {error}",
+                repository.path().display()
+            ),
+        }
+    }
+
+    let head = repository.head()?.peel_to_commit()?;
+    let mut checkout_build = CheckoutBuilder::new();
+    repository.reset(
+        head.as_object(),
+        git2::ResetType::Hard,
+        Some(checkout_build.force()),
+    )?;
+    repository.cleanup_state()
+}
+
+/// Remote callbacks reporting the transfer and the messages of the remote
+/// to `observer`.
+fn callbacks(observer: &dyn TransferObserver) -> RemoteCallbacks<'_> {
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.transfer_progress(|stats| {
+        observer.on_progress(&stats.into());
+        true
+    });
+    callbacks.sideband_progress(|data| {
+        for line in remote_lines(data) {
+            observer.on_message(line);
+        }
+        true
+    });
+    callbacks.update_tips(|reference, old, new| {
+        observer.on_message(&tip_update(reference, old, new));
+        true
+    });
+    callbacks
+}
+
+/// Lines of the sideband `data` of the remote, which rewrites progress
+/// lines by ending them with a carriage return.
+fn remote_lines(data: &[u8]) -> impl Iterator<Item = &str> {
+    std::str::from_utf8(data)
+        .unwrap_or_default()
+        .split(['\r', '\n'])
+        .map(str::trim_end)
+        .filter(|line| !line.is_empty())
+}
+
+/// Update of `reference` from `old` to `new`, as git fetch --verbose
+/// shows it.
+fn tip_update(reference: &str, old: Oid, new: Oid) -> String {
+    let short = |oid: Oid| oid.to_string()[..7].to_owned();
+    if old.is_zero() {
+        format!(" * [new] {} -> {reference}", short(new))
+    } else {
+        format!("   {}..{} -> {reference}", short(old), short(new))
+    }
+}
+
+/// Clone a repository, authenticating with the user's credentials.
+///
+/// If a depth is given, the clone is shallow with that many commits. libgit2
+/// cannot make partial clones, so those are made by git, which
+/// authenticates on its own.
+fn clone(
+    url: &str,
+    into: &Path,
+    bare: bool,
+    branch: Option<&str>,
+    options: CloneOptions,
+    progress: &dyn TransferObserver,
+) -> Result<Repository, Error> {
+    if options.filter.is_some() {
+        let mut args = vec!["clone".to_owned(), "--quiet".to_owned()];
+        if bare {
+            args.push("--bare".to_owned());
+        }
+        if let Some(branch) = branch {
+            args.extend(["--single-branch", "--branch", branch].map(str::to_owned));
+        }
+        args.extend(options.args());
+        args.extend(["--".to_owned(), url.to_owned()]);
+        args.push(into.to_string_lossy().into_owned());
+        git(None, &args, None)?;
+        let repository = open_repository(into)?;
+        if bare {
+            // git makes local branches of all those of origin in a bare
+            // clone, and no remote-tracking ones, where libgit2 only makes
+            // one of the default branch, as object stores expect.
+            repository.remote_add_fetch("origin", "+refs/heads/*:refs/remotes/origin/*")?;
+            git(
+                Some(repository.path()),
+                &["fetch", "--quiet", "origin"].map(str::to_owned),
+                None,
+            )?;
+            for branch in repository.branches(Some(BranchType::Local))? {
+                let (mut branch, _) = branch?;
+                if !branch.is_head() {
+                    branch.delete()?;
+                }
+            }
+        }
+        return Ok(repository);
+    }
+    let url = auth::resolve_url(url);
+    auth::with_credentials_and_callbacks(&url, callbacks(progress), |mut fetch_options| {
+        if let Some(depth) = options.depth {
+            fetch_options.depth(depth as i32);
+        }
+        let mut builder = RepoBuilder::new();
+        builder.bare(bare).fetch_options(fetch_options);
+        if let Some(branch) = branch {
+            let refspec = format!("+refs/heads/{branch}:refs/remotes/origin/{branch}");
+            builder
+                .branch(branch)
+                .remote_create(move |repository, name, url| {
+                    repository.remote_with_fetch(name, url, &refspec)
+                });
+        }
+        builder.clone(&url, into)
+    })
+}
+
+/// Fetch refspecs from origin, authenticating with the user's credentials.
+fn fetch(
+    repository: &Repository,
+    refspecs: &[&str],
+    download_tags: bool,
+    progress: &dyn TransferObserver,
+) -> Result<(), Error> {
+    let mut remote = repository.find_remote("origin")?;
+    let url = auth::resolve_url(remote.url().unwrap_or_default());
+    auth::with_credentials_and_callbacks(&url, callbacks(progress), |mut fetch_options| {
+        if download_tags {
+            fetch_options.download_tags(git2::AutotagOption::All);
+        }
+        remote.fetch(refspecs, Some(&mut fetch_options), None)
+    })
+}
+
+/// Whether `repository` is a partial clone, missing objects its origin
+/// promises to send when they are needed.
+fn is_partial(repository: &Repository) -> bool {
+    repository
+        .config()
+        .and_then(|config| config.get_bool("remote.origin.promisor"))
+        .unwrap_or(false)
+}
+
+/// Run git with `args`, in the repository whose git directory is `git_dir`
+/// if given, writing `input` to its standard input, and return its output.
+///
+/// Failures are returned as network errors with the message of git, as
+/// git is only run to talk to remotes.
+fn git(git_dir: Option<&Path>, args: &[String], input: Option<&str>) -> Result<String, Error> {
+    let mut command = Command::new("git");
+    if let Some(git_dir) = git_dir {
+        // git refuses repositories owned by another user unless they are
+        // in its safe.directory, as trusted ones are for libgit2.
+        if owned_by_other_user(git_dir) && !is_untrusted(git_dir) {
+            command.args(["-c", "safe.directory=*"]);
+        }
+        command.arg("--git-dir").arg(git_dir);
+    }
+    let rendered = format!("git {}", args.join(" "));
+    debug!("Running {rendered}");
+    let mut child = command
+        .args(args)
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|error| {
+            Error::new(
+                ErrorCode::GenericError,
+                ErrorClass::Os,
+                format!("{rendered}: {error}"),
+            )
+        })?;
+    if let (Some(mut stdin), Some(input)) = (child.stdin.take(), input) {
+        // git may exit before reading all of it, which its status tells.
+        let _ = stdin.write_all(input.as_bytes());
+    }
+    let output = child.wait_with_output().map_err(|error| {
+        Error::new(
+            ErrorCode::GenericError,
+            ErrorClass::Os,
+            format!("{rendered}: {error}"),
+        )
+    })?;
+    if !output.status.success() {
+        return Err(Error::new(
+            ErrorCode::GenericError,
+            ErrorClass::Net,
+            String::from_utf8_lossy(&output.stderr).trim(),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Fetch the trees and files of `commit` a partial clone left out, so that
+/// libgit2, which cannot fetch them when it needs them, can check it out.
+///
+/// Does nothing in a full clone.
+pub(crate) fn fetch_missing_objects(repository: &Repository, commit: Oid) -> Result<(), Error> {
+    if !is_partial(repository) {
+        return Ok(());
+    }
+    let git_dir = repository.path();
+    let list = [
+        "rev-list",
+        "--objects",
+        "--no-walk",
+        "--missing=print",
+        &commit.to_string(),
+    ]
+    .map(str::to_owned);
+    let fetch = [
+        "-c",
+        "fetch.negotiationAlgorithm=noop",
+        "fetch",
+        "--quiet",
+        "--no-tags",
+        "--no-write-fetch-head",
+        "--recurse-submodules=no",
+        "--filter=blob:none",
+        "--stdin",
+        "origin",
+    ]
+    .map(str::to_owned);
+    let mut previous = Vec::new();
+    // The subtrees a tree lists are only known, and fetched, once it is,
+    // so a treeless clone takes a round per level.
+    loop {
+        let objects = git(Some(git_dir), &list, None)?;
+        let missing: Vec<&str> = objects
+            .lines()
+            .filter_map(|line| line.strip_prefix('?'))
+            .collect();
+        if missing.is_empty() {
+            return Ok(());
+        }
+        if missing == previous {
+            return Err(Error::new(
+                ErrorCode::NotFound,
+                ErrorClass::Odb,
+                format!(
+                    "origin did not send the {} objects missing from {commit}",
+                    missing.len()
+                ),
+            ));
+        }
+        trace!("Fetching {} objects of {commit}", missing.len());
+        git(Some(git_dir), &fetch, Some(&missing.join("\n")))?;
+        previous = missing.into_iter().map(str::to_owned).collect();
+    }
+}
+
+/// Name of the other worktree of the object store `repository` is a
+/// worktree of that has `branch` checked out, if any, as moving the branch
+/// would change that worktree's files under its feet.
+fn checked_out_elsewhere(repository: &Repository, branch: &str) -> Result<Option<String>, Error> {
+    if !repository.is_worktree() {
+        return Ok(None);
+    }
+    let own_worktree = Worktree::open_from_repository(repository)?;
+    let refname = format!("refs/heads/{branch}");
+    for name in repository.worktrees()?.iter().flatten() {
+        if Some(name) == own_worktree.name() {
+            continue;
+        }
+        // Worktrees whose directory is gone have nothing checked out.
+        let Ok(other) = repository
+            .find_worktree(name)
+            .and_then(|worktree| Repository::open_from_worktree(&worktree))
+        else {
+            continue;
+        };
+        let checked_out = other
+            .find_reference("HEAD")
+            .is_ok_and(|head| head.symbolic_target() == Some(refname.as_str()));
+        if checked_out {
+            return Ok(Some(name.to_owned()));
+        }
+    }
+    Ok(None)
+}
+
+/// Checkout `branch_name` from origin, which must have been fetched already.
+fn checkout_branch(repository: &Repository, branch_name: &str) -> Result<(), Error> {
+    // repository.branch(branch_name, &object.peel_to_commit().unwrap(), true)?;
+    // repository.set_head(spec)?;
+    // let mut checkout_build = CheckoutBuilder::new();
+    // repository.reset(&object, git2::ResetType::Hard, Some(checkout_build.force()))?;
+
+    let remote_branch_name = format!("origin/{branch_name}");
+    let branch = repository.find_branch(&remote_branch_name, git2::BranchType::Remote)?;
+
+    let branch_reference = branch.into_reference();
+    let commit = branch_reference.peel_to_commit()?;
+    fetch_missing_objects(repository, commit.id())?;
+    // Repositories are checked out concurrently, so the log lines tell
+    // which one they are about.
+    let location = repository.workdir().unwrap_or(repository.path()).display();
+
+    if let Some(worktree) = checked_out_elsewhere(repository, branch_name)? {
+        debug!("{location}: {branch_name} is checked out in worktree {worktree}, detaching HEAD at {remote_branch_name}");
+        repository.set_head_detached(commit.id())?;
+        let mut checkout_build = CheckoutBuilder::new();
+        return repository.reset(
+            commit.as_object(),
+            git2::ResetType::Hard,
+            Some(checkout_build.force()),
+        );
+    }
+    if repository.is_worktree() {
+        // Branches are shared by all the worktrees of the object store, so
+        // a temporary one would be moved under the others' feet.
+        trace!("{location}: detaching HEAD");
+        repository.set_head_detached(commit.id())?;
+    } else {
+        trace!("{location}: checking out temporary branch");
+        let temp_branch = repository.branch("temp", &commit, true)?;
+
+        if let Some(temp_refname) = temp_branch.get().name() {
+            repository.set_head(temp_refname)?;
+        } else {
+            return Err(Error::new(
+                git2::ErrorCode::Ambiguous,
+                git2::ErrorClass::FetchHead,
+                "Error",
+            ));
+        }
+    }
+
+    trace!("{location}: checking out branch {branch_name}");
+    let local_branch = repository.branch(branch_name, &commit, true)?;
+    trace!("{location}: branch {branch_name} checked out ok.");
+
+    if let Some(upstream_name) = branch_reference.name() {
+        debug!("{location}: upstream name: {upstream_name}");
+        let object = repository.revparse_single(upstream_name)?;
+        let mut checkout_build = CheckoutBuilder::new();
+        repository.reset(&object, git2::ResetType::Hard, Some(checkout_build.force()))?;
+        // local_branch.set_upstream(Some(upstream_name))?;
+        if let Some(refname) = local_branch.get().name() {
+            repository.set_head(refname)?;
+        } else {
+            return Err(Error::new(
+                git2::ErrorCode::Ambiguous,
+                git2::ErrorClass::FetchHead,
+                "Error",
+            ));
+        }
+    } else {
+        return Err(Error::new(
+            git2::ErrorCode::Ambiguous,
+            git2::ErrorClass::FetchHead,
+            "Error",
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{remote_lines, tip_update};
+    use git2::Oid;
+
+    #[test]
+    fn test_transfer_messages() -> Result<(), git2::Error> {
+        assert_eq!(
+            remote_lines(b"Counting objects:  50% (1/2)\rCounting objects: 100% (2/2), done.\n")
+                .collect::<Vec<_>>(),
+            [
+                "Counting objects:  50% (1/2)",
+                "Counting objects: 100% (2/2), done."
+            ]
+        );
+        let old = Oid::from_str("1111aaaa1111aaaa1111aaaa1111aaaa1111aaaa")?;
+        let new = Oid::from_str("2222bbbb2222bbbb2222bbbb2222bbbb2222bbbb")?;
+        assert_eq!(
+            tip_update("refs/remotes/origin/develop", old, new),
+            "   1111aaa..2222bbb -> refs/remotes/origin/develop"
+        );
+        assert_eq!(
+            tip_update("refs/tags/v1.2.0", Oid::zero(), new),
+            " * [new] 2222bbb -> refs/tags/v1.2.0"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,149 @@
+//! Commands run after an action changed the environment, configured in the
+//! `[hooks]` table of the configuration file:
+//!
+//! ```toml
+//! [hooks]
+//! post_reset = "make -C /obs-env/generated && systemctl --user restart obs-env"
+//! ```
+//!
+//! A hook is run with `sh -c` from the environment path, with what changed
+//! in its environment variables:
+//!
+//! - `OBS_ENV_PATH`: path of the environment.
+//! - `OBS_ENV_ACTION`: action that ran, e.g. "reset".
+//! - `OBS_ENV_REPOS`: repositories whose commit changed, space separated.
+//! - `OBS_ENV_OLD_REFS` and `OBS_ENV_NEW_REFS`: commit of each of those
+//!   repositories before and after, as `repo=commit` separated by spaces,
+//!   with `none` for a repository that was not cloned.
+use crate::error::ObsEnvError;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::Path, process::Command};
+
+/// Commands run after the actions changing the environment, if set.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Hooks {
+    pub post_setup: Option<String>,
+    pub post_reset: Option<String>,
+    pub post_checkout: Option<String>,
+    pub post_apply_manifest: Option<String>,
+}
+
+/// Run the hook `name`, `command`, after `action` changed the environment
+/// at `env_path` from the `before` commits of the repositories to the
+/// `after` ones, by repository name.
+///
+/// The output of the hook is logged line by line, at info level for stdout
+/// and warning level for stderr. The hook fails if it cannot be run or
+/// exits with an error.
+pub fn run(
+    name: &str,
+    command: &str,
+    action: &str,
+    env_path: &str,
+    before: &BTreeMap<String, Option<String>>,
+    after: &BTreeMap<String, Option<String>>,
+) -> Result<(), ObsEnvError> {
+    let changed: Vec<&String> = after
+        .iter()
+        .filter(|(repo, commit)| before.get(*repo).cloned().flatten() != **commit)
+        .map(|(repo, _)| repo)
+        .collect();
+    let refs = |commits: &BTreeMap<String, Option<String>>| {
+        changed
+            .iter()
+            .map(|repo| {
+                let commit = commits.get(*repo).cloned().flatten();
+                format!("{repo}={}", commit.as_deref().unwrap_or("none"))
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    let repos = changed
+        .iter()
+        .map(|repo| repo.as_str())
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    log::info!("Running the {name} hook: {command}");
+    let mut process = Command::new("sh");
+    process
+        .args(["-c", command])
+        .env("OBS_ENV_PATH", env_path)
+        .env("OBS_ENV_ACTION", action)
+        .env("OBS_ENV_REPOS", repos)
+        .env("OBS_ENV_OLD_REFS", refs(before))
+        .env("OBS_ENV_NEW_REFS", refs(after));
+    if Path::new(env_path).is_dir() {
+        process.current_dir(env_path);
+    }
+    let failed = |message: String| ObsEnvError::CommandFailed {
+        command: format!("{name} hook {command}"),
+        message,
+    };
+    let output = process
+        .output()
+        .map_err(|error| failed(error.to_string()))?;
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        log::info!("{name}: {line}");
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    for line in stderr.lines() {
+        log::warn!("{name}: {line}");
+    }
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(failed(format!("{}: {}", output.status, stderr.trim())))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::run;
+    use crate::error::ObsEnvError;
+    use std::collections::BTreeMap;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_run_hook() {
+        let root = TempDir::new().unwrap();
+        let env_path = root.path().to_string_lossy();
+        let before = BTreeMap::from([
+            ("cwfs".to_owned(), Some("1111aaaa".to_owned())),
+            ("ts_wep".to_owned(), None),
+        ]);
+        let after = BTreeMap::from([
+            ("cwfs".to_owned(), Some("1111aaaa".to_owned())),
+            ("ts_wep".to_owned(), Some("2222bbbb".to_owned())),
+        ]);
+
+        run(
+            "post_reset",
+            "echo \"$OBS_ENV_ACTION|$OBS_ENV_REPOS|$OBS_ENV_OLD_REFS|$OBS_ENV_NEW_REFS\" > hook.out",
+            "reset",
+            &env_path,
+            &before,
+            &after,
+        )
+        .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(root.path().join("hook.out")).unwrap(),
+            "reset|ts_wep|ts_wep=none|ts_wep=2222bbbb\n"
+        );
+
+        let error = run(
+            "post_reset",
+            "echo broken >&2; exit 3",
+            "reset",
+            &env_path,
+            &before,
+            &after,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            &error,
+            ObsEnvError::CommandFailed { message, .. } if message.ends_with(": broken")
+        ));
+    }
+}
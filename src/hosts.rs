@@ -0,0 +1,206 @@
+//! Git servers hosting the repositories, e.g. GitHub or an internal GitLab
+//! instance, and the SSH and HTTPS forms of their urls.
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+
+/// Kind of git server hosting a repository.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HostType {
+    GitHub,
+    GitLab,
+    /// Any other git server, with no known review workflow.
+    Other,
+}
+
+impl HostType {
+    /// Type of the server `url` is on: GitHub for github.com, GitLab for
+    /// hosts with gitlab in their name, e.g. gitlab.lsst.org.
+    pub fn detect(url: &str) -> HostType {
+        match RepoUrl::parse(url) {
+            Some(url) if url.host_name() == "github.com" => HostType::GitHub,
+            Some(url) if url.host_name().contains("gitlab") => HostType::GitLab,
+            _ => HostType::Other,
+        }
+    }
+
+    /// Reference of the head of review request `number`, i.e. a pull
+    /// request on GitHub or a merge request on GitLab, if the server has
+    /// them.
+    pub fn review_ref(&self, number: u64) -> Option<String> {
+        match self {
+            HostType::GitHub => Some(format!("refs/pull/{number}/head")),
+            HostType::GitLab => Some(format!("refs/merge-requests/{number}/head")),
+            HostType::Other => None,
+        }
+    }
+}
+
+impl Display for HostType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HostType::GitHub => write!(f, "GitHub"),
+            HostType::GitLab => write!(f, "GitLab"),
+            HostType::Other => write!(f, "other"),
+        }
+    }
+}
+
+/// Url of a repository on a git server, e.g.
+/// `https://gitlab.lsst.org/ts/ts_wep.git` or the scp-like
+/// `git@github.com:lsst-ts/ts_wep.git`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RepoUrl {
+    /// Scheme, e.g. `https` or `ssh`, or none for the scp-like form.
+    pub scheme: Option<String>,
+    pub user: Option<String>,
+    /// Host name, with the port if the url has one.
+    pub host: String,
+    /// Path of the repository on the server, e.g. `lsst-ts/ts_wep.git`.
+    pub path: String,
+}
+
+impl RepoUrl {
+    /// Parse `url`, or none if it is not the url of a repository on a
+    /// server, e.g. a local path.
+    pub fn parse(url: &str) -> Option<RepoUrl> {
+        // These should never fail because the expressions are valid.
+        let with_scheme = Regex::new(
+            r"^(?P<scheme>[a-zA-Z][a-zA-Z0-9+.-]*)://(?:(?P<user>[^@/\s]+)@)?(?P<host>[^/\s]+)/(?P<path>\S+)$",
+        )
+        .unwrap();
+        let scp_like =
+            Regex::new(r"^(?P<user>[^\s/@:]+)@(?P<host>[^\s/:]+):/?(?P<path>\S+)$").unwrap();
+        let captures = with_scheme
+            .captures(url)
+            .or_else(|| scp_like.captures(url))?;
+        let path = captures["path"].trim_end_matches('/');
+        Some(RepoUrl {
+            scheme: captures
+                .name("scheme")
+                .map(|scheme| scheme.as_str().to_owned()),
+            user: captures.name("user").map(|user| user.as_str().to_owned()),
+            host: captures["host"].to_owned(),
+            path: path.to_owned(),
+        })
+        .filter(|url| url.scheme.as_deref() != Some("file") && !url.path.is_empty())
+    }
+
+    /// Host name, without the port.
+    pub fn host_name(&self) -> &str {
+        match self.host.rsplit_once(':') {
+            Some((host_name, port)) if port.chars().all(|c| c.is_ascii_digit()) => host_name,
+            _ => &self.host,
+        }
+    }
+
+    /// Same repository in the namespace of `owner`, as forks are, keeping
+    /// the form of the url. The whole namespace is replaced, e.g. the
+    /// subgroups of GitLab.
+    pub fn with_owner(&self, owner: &str) -> RepoUrl {
+        let name = self.path.rsplit('/').next().unwrap_or_default();
+        RepoUrl {
+            path: format!("{owner}/{name}"),
+            ..self.clone()
+        }
+    }
+
+    /// HTTPS form of the url, e.g. `https://github.com/lsst-ts/ts_wep.git`.
+    pub fn to_https(&self) -> RepoUrl {
+        RepoUrl {
+            scheme: Some("https".to_owned()),
+            user: None,
+            host: self.host_name().to_owned(),
+            path: self.path.clone(),
+        }
+    }
+
+    /// SSH form of the url, e.g. `git@github.com:lsst-ts/ts_wep.git`, as
+    /// the user `git` unless the url is already an SSH one.
+    pub fn to_ssh(&self) -> RepoUrl {
+        let user = match self.scheme.as_deref() {
+            None | Some("ssh") => self.user.clone(),
+            Some(_) => None,
+        };
+        RepoUrl {
+            scheme: None,
+            user: Some(user.unwrap_or_else(|| "git".to_owned())),
+            host: self.host_name().to_owned(),
+            path: self.path.clone(),
+        }
+    }
+}
+
+impl Display for RepoUrl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let user = self
+            .user
+            .as_ref()
+            .map(|user| format!("{user}@"))
+            .unwrap_or_default();
+        match &self.scheme {
+            Some(scheme) => write!(f, "{scheme}://{user}{}/{}", self.host, self.path),
+            None => write!(f, "{user}{}:{}", self.host, self.path),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HostType, RepoUrl};
+
+    #[test]
+    fn test_repo_url() {
+        let url = RepoUrl::parse("https://gitlab.lsst.org/ts/aos/ts_wep.git").unwrap();
+        assert_eq!(url.host_name(), "gitlab.lsst.org");
+        assert_eq!(url.path, "ts/aos/ts_wep.git");
+        assert_eq!(
+            url.with_owner("tribeiro").to_string(),
+            "https://gitlab.lsst.org/tribeiro/ts_wep.git"
+        );
+        assert_eq!(
+            url.to_ssh().to_string(),
+            "git@gitlab.lsst.org:ts/aos/ts_wep.git"
+        );
+
+        let url = RepoUrl::parse("ssh://git@github.com:22/lsst-ts/ts_wep").unwrap();
+        assert_eq!(url.host_name(), "github.com");
+        assert_eq!(
+            url.to_https().to_string(),
+            "https://github.com/lsst-ts/ts_wep"
+        );
+        assert_eq!(url.to_ssh().to_string(), "git@github.com:lsst-ts/ts_wep");
+
+        let url = RepoUrl::parse("git@github.com:lsst-ts/ts_wep.git").unwrap();
+        assert_eq!(url.to_string(), "git@github.com:lsst-ts/ts_wep.git");
+        assert_eq!(
+            url.with_owner("tribeiro").to_string(),
+            "git@github.com:tribeiro/ts_wep.git"
+        );
+
+        assert_eq!(RepoUrl::parse("/data/repos/ts_wep"), None);
+        assert_eq!(RepoUrl::parse("file:///data/repos/ts_wep"), None);
+    }
+
+    #[test]
+    fn test_host_type() {
+        assert_eq!(
+            HostType::detect("git@github.com:lsst-ts/ts_wep.git"),
+            HostType::GitHub
+        );
+        assert_eq!(
+            HostType::detect("https://gitlab.lsst.org/ts/ts_wep"),
+            HostType::GitLab
+        );
+        assert_eq!(
+            HostType::detect("https://example.com/lsst-ts/ts_wep"),
+            HostType::Other
+        );
+        assert_eq!(
+            HostType::GitLab.review_ref(12).as_deref(),
+            Some("refs/merge-requests/12/head")
+        );
+        assert_eq!(HostType::Other.review_ref(12), None);
+    }
+}
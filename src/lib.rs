@@ -1,4 +1,78 @@
+//! Manage the observing environment: a set of git repositories checked out
+//! under a common path, whose official versions are defined by a base
+//! environment repository.
+//!
+//! The [`ObservingEnvironment`] type drives all the git operations and
+//! returns typed results, so it can be used without initializing a logger.
+//! The `manage_obs_env` binary is a thin command line wrapper around
+//! [`manage_obs_env::run`].
+//!
+//! ```no_run
+//! use ts_observing_environment::ObservingEnvironment;
+//!
+//! let obs_env = ObservingEnvironment::with_destination("/net/obs-env/auto_base_packages");
+//! for (repo_name, version) in obs_env.get_current_env_versions() {
+//!     match version {
+//!         Ok(version) => println!("{repo_name}: {version}"),
+//!         Err(error) => eprintln!("{repo_name}: {error}"),
+//!     }
+//! }
+//! ```
+pub mod activation;
+#[cfg(feature = "async")]
+pub mod asynchronous;
+pub mod audit;
+pub mod auth;
+pub mod backup;
+pub mod branches;
+pub mod clone_env;
+pub mod conda;
+pub mod config;
+pub mod config_check;
+pub mod deviation;
+pub mod dry_run;
+pub mod env_report;
+pub mod environments;
 pub mod error;
+pub mod eups;
+pub mod git_backend;
+pub mod hooks;
+pub mod hosts;
+pub mod lock;
+pub mod lockfile;
 pub mod manage_obs_env;
+pub mod manifest;
+pub mod metadata;
+pub mod metrics;
+pub mod mirror;
+pub mod notify;
+pub mod observer;
 pub mod observing_environment;
+pub mod outdated;
+mod parallel;
+pub mod permissions;
+pub mod pip;
+pub mod preflight;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod repair;
 pub mod repos;
+pub mod resume;
+pub mod retry;
+pub mod schema;
+pub mod serve;
+pub mod setup;
+pub mod signal;
+pub mod snapshot;
+pub mod status;
+pub mod testing;
+pub mod timing;
+pub mod watch;
+
+pub use error::ObsEnvError;
+pub use git2;
+pub use git_backend::{Git2Backend, GitBackend};
+pub use manage_obs_env::{run, run_with_output, Action, ManageObsEnv, ManageObsEnvCli};
+pub use manifest::{EnvironmentManifest, RepoVersion};
+pub use observing_environment::{ObservingEnvironment, ObservingEnvironmentBuilder, RepoHandle};
+pub use repos::Repos;
@@ -0,0 +1,245 @@
+//! Advisory lock on an environment, taken by the actions modifying it so
+//! that two runs do not check out repositories of the same environment at
+//! once.
+use crate::error::ObsEnvError;
+use serde::Serialize;
+use std::{
+    fmt::{self, Display},
+    fs::{read_to_string, remove_file, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+/// Time between two attempts to take a lock held by another run.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Process holding a lock, as written in the lock file.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LockHolder {
+    pub pid: u32,
+    pub host: String,
+}
+
+impl LockHolder {
+    /// The current process.
+    pub fn current() -> LockHolder {
+        LockHolder {
+            pid: std::process::id(),
+            host: hostname(),
+        }
+    }
+
+    /// Holder written in a lock file as "{pid} {host}".
+    fn parse(content: &str) -> Option<LockHolder> {
+        let (pid, host) = content.trim().split_once(' ')?;
+        Some(LockHolder {
+            pid: pid.parse().ok()?,
+            host: host.to_owned(),
+        })
+    }
+
+    /// Whether the holder is a process of this host that is not running
+    /// anymore. Processes of other hosts are assumed to be running.
+    pub fn is_dead(&self) -> bool {
+        self.host == hostname() && !process_running(self.pid)
+    }
+}
+
+impl Display for LockHolder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "process {} on {}", self.pid, self.host)
+    }
+}
+
+/// Exclusive lock on a lock file, released when dropped.
+#[derive(Debug)]
+pub struct EnvLock {
+    path: PathBuf,
+    file: File,
+}
+
+impl EnvLock {
+    /// Lock `path`, waiting up to `timeout` for the run holding it to
+    /// release it. A lock left by a dead process of this host is broken.
+    pub fn acquire(path: &Path, timeout: Duration) -> Result<EnvLock, ObsEnvError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(path)
+                .map_err(|error| ObsEnvError::io(path, "open", error))?;
+            if try_lock(&file) {
+                // The file may have been removed, by a run breaking the
+                // lock, between opening and locking it.
+                if !is_same_file(&file, path) {
+                    continue;
+                }
+                let mut lock = EnvLock {
+                    path: path.to_path_buf(),
+                    file,
+                };
+                lock.write_holder()
+                    .map_err(|error| ObsEnvError::io(path, "write", error))?;
+                log::debug!("Locked {}", path.display());
+                return Ok(lock);
+            }
+
+            let holder = read_to_string(path)
+                .ok()
+                .and_then(|content| LockHolder::parse(&content));
+            match holder {
+                Some(holder) if holder.is_dead() => {
+                    log::warn!("Breaking the lock {} left by {holder}", path.display());
+                    let _ = remove_file(path);
+                    continue;
+                }
+                _ if Instant::now() >= deadline => {
+                    return Err(ObsEnvError::EnvLocked {
+                        path: path.to_path_buf(),
+                        holder: holder.map_or_else(
+                            || "an unknown process".to_owned(),
+                            |holder| holder.to_string(),
+                        ),
+                    });
+                }
+                _ => sleep(POLL_INTERVAL),
+            }
+        }
+    }
+
+    /// Path of the lock file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn write_holder(&mut self) -> std::io::Result<()> {
+        let holder = LockHolder::current();
+        self.file.set_len(0)?;
+        write!(self.file, "{} {}", holder.pid, holder.host)?;
+        self.file.flush()
+    }
+}
+
+impl Drop for EnvLock {
+    fn drop(&mut self) {
+        // The lock itself is released with the file; the holder is only
+        // cleared so that it is not reported after the run.
+        let _ = self.file.set_len(0);
+        log::debug!("Unlocked {}", self.path.display());
+    }
+}
+
+#[cfg(unix)]
+fn try_lock(file: &File) -> bool {
+    use std::os::unix::io::AsRawFd;
+
+    unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) == 0 }
+}
+
+#[cfg(not(unix))]
+fn try_lock(_file: &File) -> bool {
+    true
+}
+
+#[cfg(unix)]
+fn is_same_file(file: &File, path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (file.metadata(), path.metadata()) {
+        (Ok(opened), Ok(current)) => opened.dev() == current.dev() && opened.ino() == current.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn is_same_file(_file: &File, path: &Path) -> bool {
+    path.exists()
+}
+
+#[cfg(unix)]
+pub(crate) fn hostname() -> String {
+    let mut name = [0u8; 256];
+    if unsafe { libc::gethostname(name.as_mut_ptr().cast(), name.len()) } != 0 {
+        return "unknown host".to_owned();
+    }
+    let length = name
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(name.len());
+    String::from_utf8_lossy(&name[..length]).into_owned()
+}
+
+#[cfg(not(unix))]
+pub(crate) fn hostname() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_else(|_| "unknown host".to_owned())
+}
+
+#[cfg(unix)]
+fn process_running(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // Signal 0 only checks that the process exists; EPERM means it does,
+    // but belongs to another user.
+    let running = unsafe { libc::kill(pid, 0) } == 0;
+    running || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn process_running(_pid: u32) -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EnvLock, LockHolder};
+    use crate::ObsEnvError;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    type TestResult<T = (), E = Box<dyn std::error::Error>> = std::result::Result<T, E>;
+
+    #[test]
+    fn test_env_lock() -> TestResult {
+        let root = TempDir::new()?;
+        let path = root.path().join("lock");
+
+        let lock = EnvLock::acquire(&path, Duration::ZERO)?;
+        let holder = LockHolder::current();
+        assert_eq!(
+            std::fs::read_to_string(&path)?,
+            format!("{} {}", holder.pid, holder.host)
+        );
+        match EnvLock::acquire(&path, Duration::from_millis(200)) {
+            Err(ObsEnvError::EnvLocked {
+                holder: locked_by, ..
+            }) => {
+                assert_eq!(locked_by, holder.to_string())
+            }
+            result => panic!("expected the environment to be locked, got {result:?}"),
+        }
+        drop(lock);
+        assert!(EnvLock::acquire(&path, Duration::ZERO).is_ok());
+
+        // A lock still held, e.g. by a child of a dead process, is broken
+        // when the process that wrote it is gone.
+        let _stale = EnvLock::acquire(&path, Duration::ZERO)?;
+        let dead = LockHolder {
+            pid: i32::MAX as u32,
+            ..holder.clone()
+        };
+        assert!(dead.is_dead());
+        std::fs::write(&path, format!("{} {}", dead.pid, dead.host))?;
+        let lock = EnvLock::acquire(&path, Duration::ZERO)?;
+        assert_eq!(
+            std::fs::read_to_string(lock.path())?,
+            format!("{} {}", holder.pid, holder.host)
+        );
+        Ok(())
+    }
+}
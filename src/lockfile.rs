@@ -0,0 +1,285 @@
+//! Exact commits the repositories of an environment were left at by the
+//! last Setup, Reset or ApplyManifest, kept in `env.lock` in the
+//! environment path so the same code can be checked out again after the
+//! branches of the base environment moved.
+//!
+//! The file is toml, and its format only changes along with `version`:
+//!
+//! ```toml
+//! # Written by manage_obs_env, do not edit.
+//! version = 1
+//! created = 1700000000
+//! base_branch = "main"
+//!
+//! [repos.ts_wep]
+//! sha = "3b0c5e3d9e1a0f5c2a4b6d8e0f1a2b3c4d5e6f70"
+//! describe = "v1.2.0"
+//! ```
+//!
+//! `created` is in seconds since the Unix epoch, and `describe` is the version
+//! the commit was checked out as, for information only.
+use crate::{
+    error::ObsEnvError,
+    manifest::{EnvironmentManifest, RepoVersion},
+    observing_environment::{serialized, write_atomically},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+    fs::read_to_string,
+    path::Path,
+};
+
+/// Name of the lock file, in the environment path.
+pub const LOCK_FILE_NAME: &str = "env.lock";
+
+/// Version of the format written.
+pub const LOCK_FILE_VERSION: u32 = 1;
+
+/// Commit a repository is locked at.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct LockedRepo {
+    pub sha: String,
+    pub describe: String,
+}
+
+/// Content of `env.lock`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct LockFile {
+    /// Version of the format.
+    pub version: u32,
+    /// When the lock was written, in seconds since the Unix epoch.
+    pub created: u64,
+    /// Branch of the base environment in use.
+    pub base_branch: String,
+    /// Locked commits, by repository name.
+    pub repos: BTreeMap<String, LockedRepo>,
+}
+
+/// Difference between the environment and its lock file.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Drift {
+    /// The repository is checked out at another commit.
+    Moved {
+        repo: String,
+        locked: String,
+        current: String,
+    },
+    /// Tracked files of the repository were changed.
+    Dirty { repo: String },
+    /// The repository is locked but not cloned.
+    Missing { repo: String },
+    /// The repository is cloned but not locked.
+    Unlocked { repo: String },
+}
+
+impl Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Drift::Moved {
+                repo,
+                locked,
+                current,
+            } => write!(f, "{repo}: at {current} instead of {locked}"),
+            Drift::Dirty { repo } => write!(f, "{repo}: has uncommitted changes"),
+            Drift::Missing { repo } => write!(f, "{repo}: not cloned"),
+            Drift::Unlocked { repo } => write!(f, "{repo}: not in the lock file"),
+        }
+    }
+}
+
+impl LockFile {
+    /// Lock of the `versions` checked out, leaving out those with no
+    /// commit.
+    pub fn new(base_branch: &str, created: u64, versions: &[RepoVersion]) -> LockFile {
+        LockFile {
+            version: LOCK_FILE_VERSION,
+            created,
+            base_branch: base_branch.to_owned(),
+            repos: versions
+                .iter()
+                .filter_map(|version| {
+                    let locked = LockedRepo {
+                        sha: version.sha.clone()?,
+                        describe: version.describe.clone(),
+                    };
+                    Some((version.name.clone(), locked))
+                })
+                .collect(),
+        }
+    }
+
+    /// Read the lock file at `path`.
+    pub fn load(path: &Path) -> Result<LockFile, ObsEnvError> {
+        let content =
+            read_to_string(path).map_err(|error| ObsEnvError::io(path, "read lock file", error))?;
+        let lock_file: LockFile =
+            toml::from_str(&content).map_err(|error| ObsEnvError::InvalidConfig {
+                message: format!("{}: {error}", path.display()),
+            })?;
+        if lock_file.version > LOCK_FILE_VERSION {
+            return Err(ObsEnvError::InvalidConfig {
+                message: format!(
+                    "{}: version {} is newer than the supported version {LOCK_FILE_VERSION}",
+                    path.display(),
+                    lock_file.version
+                ),
+            });
+        }
+        Ok(lock_file)
+    }
+
+    /// Write the lock file to `path`, atomically.
+    pub fn save(&self, path: &Path) -> Result<(), ObsEnvError> {
+        let content = format!(
+            "# Written by manage_obs_env, do not edit.\n{}",
+            serialized(toml::to_string(self))
+        );
+        write_atomically(path, &content)
+    }
+
+    /// Differences between the lock and the `current` versions of the
+    /// repositories, by repository name, or none if the version of a
+    /// repository could not be read because it is not cloned.
+    pub fn drift(&self, current: &BTreeMap<String, Option<RepoVersion>>) -> Vec<Drift> {
+        let mut drift = Vec::new();
+        for (repo_name, version) in current {
+            let repo = repo_name.clone();
+            match (self.repos.get(repo_name), version) {
+                (Some(_), None) => drift.push(Drift::Missing { repo }),
+                (None, Some(_)) => drift.push(Drift::Unlocked { repo }),
+                (None, None) => {}
+                (Some(locked), Some(version)) => {
+                    if version.sha.as_ref() != Some(&locked.sha) {
+                        drift.push(Drift::Moved {
+                            repo: repo.clone(),
+                            locked: locked.sha.clone(),
+                            current: version.sha.clone().unwrap_or_default(),
+                        });
+                    }
+                    if version.dirty {
+                        drift.push(Drift::Dirty { repo });
+                    }
+                }
+            }
+        }
+        drift
+    }
+
+    /// Manifest checking out the locked commits of the repositories of
+    /// `manifest`, or of every locked repository if none is given.
+    pub fn pin(
+        &self,
+        env_path: &str,
+        manifest: Option<&EnvironmentManifest>,
+    ) -> Result<EnvironmentManifest, ObsEnvError> {
+        let repo_names: Vec<&String> = match manifest {
+            Some(manifest) => manifest.repos.iter().map(|version| &version.name).collect(),
+            None => self.repos.keys().collect(),
+        };
+        let versions = repo_names
+            .into_iter()
+            .map(|repo_name| match self.repos.get(repo_name) {
+                Some(locked) => {
+                    let mut version = RepoVersion::new(repo_name, &locked.describe);
+                    version.sha = Some(locked.sha.clone());
+                    Ok(version)
+                }
+                None => Err(ObsEnvError::InvalidConfig {
+                    message: format!("{repo_name} is not in {LOCK_FILE_NAME}"),
+                }),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(EnvironmentManifest::new(env_path, versions))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Drift, LockFile, LOCK_FILE_VERSION};
+    use crate::manifest::{EnvironmentManifest, RepoVersion};
+    use std::collections::BTreeMap;
+    use tempfile::TempDir;
+
+    fn version(name: &str, sha: &str) -> RepoVersion {
+        RepoVersion {
+            name: name.to_owned(),
+            branch: Some("main".to_owned()),
+            sha: Some(sha.to_owned()),
+            describe: "v1.0.0".to_owned(),
+            dirty: false,
+            ahead_behind: None,
+            ..RepoVersion::default()
+        }
+    }
+
+    #[test]
+    fn test_lock_file() {
+        let root = TempDir::new().unwrap();
+        let path = root.path().join("env.lock");
+        let lock_file = LockFile::new(
+            "main",
+            1_700_000_000,
+            &[
+                version("ts_wep", "1111aaaa"),
+                RepoVersion::new("cwfs", "v2"),
+            ],
+        );
+        lock_file.save(&path).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            content,
+            concat!(
+                "# Written by manage_obs_env, do not edit.\n",
+                "version = 1\n",
+                "created = 1700000000\n",
+                "base_branch = \"main\"\n",
+                "\n",
+                "[repos.ts_wep]\n",
+                "sha = \"1111aaaa\"\n",
+                "describe = \"v1.0.0\"\n",
+            )
+        );
+        assert_eq!(LockFile::load(&path).unwrap(), lock_file);
+
+        std::fs::write(&path, content.replace("version = 1", "version = 2")).unwrap();
+        assert!(LockFile::load(&path).is_err());
+        assert_eq!(lock_file.version, LOCK_FILE_VERSION);
+    }
+
+    #[test]
+    fn test_drift() {
+        let lock_file = LockFile::new(
+            "main",
+            0,
+            &[version("ts_wep", "1111aaaa"), version("cwfs", "2222bbbb")],
+        );
+        let mut dirty = version("ts_wep", "3333cccc");
+        dirty.dirty = true;
+        let current = BTreeMap::from([
+            ("cwfs".to_owned(), None),
+            ("ts_wep".to_owned(), Some(dirty)),
+            ("ts_utils".to_owned(), Some(version("ts_utils", "4444dddd"))),
+        ]);
+        assert_eq!(
+            lock_file
+                .drift(&current)
+                .iter()
+                .map(Drift::to_string)
+                .collect::<Vec<_>>(),
+            [
+                "cwfs: not cloned",
+                "ts_utils: not in the lock file",
+                "ts_wep: at 3333cccc instead of 1111aaaa",
+                "ts_wep: has uncommitted changes",
+            ]
+        );
+
+        let pinned = lock_file.pin("/obs-env", None).unwrap();
+        assert_eq!(pinned.repos[1].sha.as_deref(), Some("1111aaaa"));
+        let manifest =
+            EnvironmentManifest::new("/obs-env", vec![RepoVersion::new("ts_utils", "v1")]);
+        assert!(lock_file.pin("/obs-env", Some(&manifest)).is_err());
+    }
+}
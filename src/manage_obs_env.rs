@@ -1,7 +1,330 @@
-use crate::{error::ObsEnvError, observing_environment::ObservingEnvironment, repos::Repos};
-use clap::Parser;
+use crate::{
+    activation,
+    audit::{self, AuditOutcome, HistoryFilter},
+    branches::{PrunedRemotes, StaleBranches},
+    conda,
+    config::Config,
+    config_check::{check_config, ConfigCheck, Selection},
+    deviation::BaseDeviation,
+    dry_run::DryRunBackend,
+    env_report::{ReportFormat, ReportOptions, ReportSection},
+    environments::{self, NamedEnvironment, PruneFilter},
+    error::{self, report, ObsEnvError},
+    eups::{self, Eups},
+    git_backend::{CloneFilter, CommitQuery, Git2Backend},
+    hooks::{self, Hooks},
+    manifest::{EnvironmentManifest, PythonEnvironment},
+    mirror::Mirror,
+    notify::{Notification, Webhook},
+    observer::{ObsEnvObserver, TransferProgress},
+    observing_environment::{
+        serialized, write_atomically, BaseEnvRevision, ErrorPolicy, ExistingClones,
+        ObservingEnvironment, OBS_ENV_DIR,
+    },
+    outdated::{OutdatedReport, OutdatedThresholds},
+    permissions::{Group, SharedAccess},
+    pip::PipInstall,
+    preflight::MIB,
+    repair::{Repair, RepoDiagnosis},
+    repos::{RepoOverride, RepoSource, RepoSpec, Repos},
+    resume::{ResumeState, RESUME_FILE},
+    retry::RetryPolicy,
+    schema,
+    serve::{self, StatusServer},
+    setup::SetupReport,
+    signal,
+    status::WorkingTree,
+    timing::{TimingReport, Timings},
+    watch::{ManifestSource, Watcher},
+};
+use clap::{Parser, ValueEnum};
 use log;
-use std::error::Error;
+use regex::Regex;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    error::Error,
+    fs::{read_to_string, remove_dir_all},
+    io::{self, Write},
+    net::TcpListener,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Shows the progress of the operation on each repository on stderr.
+struct ProgressBar;
+
+impl ObsEnvObserver for ProgressBar {
+    fn on_repo_start(&self, repo: &str, operation: &str) {
+        eprintln!("{repo}: {operation}...");
+    }
+
+    fn on_transfer_progress(&self, repo: &str, progress: &TransferProgress) {
+        if progress.total_objects > 0 {
+            eprint!(
+                "\r{repo}: {}/{} objects ({} KiB)",
+                progress.received_objects,
+                progress.total_objects,
+                progress.received_bytes / 1024
+            );
+            if progress.received_objects == progress.total_objects {
+                eprintln!();
+            }
+        }
+    }
+
+    fn on_repo_done(&self, repo: &str) {
+        eprintln!("{repo}: done");
+    }
+
+    fn on_repo_failed(&self, repo: &str, _error: &ObsEnvError) {
+        eprintln!("{repo}: failed");
+    }
+}
+
+/// One line of the output of --progress-events.
+#[derive(Serialize)]
+struct ProgressEvent<'a> {
+    /// One of repo_start, transfer_progress, repo_done, repo_failed or
+    /// run_complete.
+    event: &'a str,
+    repo: Option<&'a str>,
+    percent: Option<u32>,
+    message: String,
+    /// Seconds since the Unix epoch.
+    timestamp: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    report: Option<RunReport<'a>>,
+}
+
+/// Overall result of [`run`], carried by the run_complete event.
+#[derive(Serialize)]
+struct RunReport<'a> {
+    action: Option<&'a str>,
+    success: bool,
+    error: Option<String>,
+    setup: Option<&'a SetupReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timing: Option<TimingReport>,
+}
+
+/// Writes the progress of the operations as newline-delimited json events.
+struct ProgressEvents<W> {
+    out: Mutex<W>,
+}
+
+impl<W: Write> ProgressEvents<W> {
+    fn new(out: W) -> ProgressEvents<W> {
+        ProgressEvents {
+            out: Mutex::new(out),
+        }
+    }
+
+    fn emit(&self, event: &ProgressEvent) -> io::Result<()> {
+        let mut out = self.out.lock().unwrap_or_else(|error| error.into_inner());
+        serde_json::to_writer(&mut *out, event)?;
+        writeln!(out)?;
+        out.flush()
+    }
+
+    /// Emit an event about `repo`; progress reporting must not interrupt
+    /// the operation, so write errors are ignored.
+    fn emit_repo_event(&self, event: &str, repo: &str, percent: Option<u32>, message: String) {
+        let _ = self.emit(&ProgressEvent {
+            event,
+            repo: Some(repo),
+            percent,
+            message,
+            timestamp: timestamp(),
+            report: None,
+        });
+    }
+
+    fn run_complete(
+        &self,
+        action: Option<&str>,
+        result: &Result<(), Box<dyn Error>>,
+        setup_report: Option<&SetupReport>,
+        timing: Option<TimingReport>,
+    ) -> io::Result<()> {
+        let error = result.as_ref().err().map(|error| report(error.as_ref()));
+        self.emit(&ProgressEvent {
+            event: "run_complete",
+            repo: None,
+            percent: Some(100),
+            message: error.clone().unwrap_or_else(|| "done".to_owned()),
+            timestamp: timestamp(),
+            report: Some(RunReport {
+                action,
+                success: error.is_none(),
+                error,
+                setup: setup_report,
+                timing,
+            }),
+        })
+    }
+}
+
+impl<W: Write + Send> ObsEnvObserver for ProgressEvents<W> {
+    fn on_repo_start(&self, repo: &str, operation: &str) {
+        self.emit_repo_event("repo_start", repo, Some(0), operation.to_owned());
+    }
+
+    fn on_transfer_progress(&self, repo: &str, progress: &TransferProgress) {
+        if let Some(percent) = (progress.received_objects * 100).checked_div(progress.total_objects)
+        {
+            self.emit_repo_event(
+                "transfer_progress",
+                repo,
+                Some(percent as u32),
+                format!(
+                    "{}/{} objects ({} KiB)",
+                    progress.received_objects,
+                    progress.total_objects,
+                    progress.received_bytes / 1024
+                ),
+            );
+        }
+    }
+
+    fn on_repo_done(&self, repo: &str) {
+        self.emit_repo_event("repo_done", repo, Some(100), "done".to_owned());
+    }
+
+    fn on_repo_failed(&self, repo: &str, error: &ObsEnvError) {
+        self.emit_repo_event("repo_failed", repo, None, report(error));
+    }
+}
+
+/// Repositories the operations of a run completed or failed on, for the
+/// json report of a failed run.
+#[derive(Default)]
+struct RepoOutcomes {
+    done: Mutex<BTreeSet<String>>,
+    /// Report of the first error of each repository that failed.
+    failed: Mutex<BTreeMap<String, String>>,
+}
+
+/// Records the outcomes of the repositories, passing the events on to the
+/// observer showing the progress, if any.
+struct RecordOutcomes {
+    outcomes: Arc<RepoOutcomes>,
+    inner: Option<Box<dyn ObsEnvObserver>>,
+}
+
+impl ObsEnvObserver for RecordOutcomes {
+    fn on_repo_start(&self, repo: &str, operation: &str) {
+        if let Some(inner) = &self.inner {
+            inner.on_repo_start(repo, operation);
+        }
+    }
+
+    fn on_transfer_progress(&self, repo: &str, progress: &TransferProgress) {
+        if let Some(inner) = &self.inner {
+            inner.on_transfer_progress(repo, progress);
+        }
+    }
+
+    fn on_repo_done(&self, repo: &str) {
+        if let Ok(mut done) = self.outcomes.done.lock() {
+            done.insert(repo.to_owned());
+        }
+        if let Some(inner) = &self.inner {
+            inner.on_repo_done(repo);
+        }
+    }
+
+    fn on_repo_failed(&self, repo: &str, error: &ObsEnvError) {
+        if let Ok(mut failed) = self.outcomes.failed.lock() {
+            failed
+                .entry(repo.to_owned())
+                .or_insert_with(|| report(error));
+        }
+        if let Some(inner) = &self.inner {
+            inner.on_repo_failed(repo, error);
+        }
+    }
+}
+
+/// Final json object of a failed run with `--output json`, so one parse
+/// of the output handles every outcome.
+#[derive(Serialize)]
+struct FailureReport<'a> {
+    /// "partial" if the action only failed on some repositories, "error"
+    /// otherwise.
+    status: &'static str,
+    action: Option<&'a str>,
+    /// Variant of the [`ObsEnvError`] the run failed with, if it is one.
+    kind: Option<&'static str>,
+    /// Repository the error is about, if it is about one.
+    repo: Option<&'a str>,
+    /// Message of the error, followed by those of its causes.
+    messages: Vec<String>,
+    /// Repositories the operations completed on.
+    succeeded: Vec<String>,
+    /// Error of each repository the operations failed on.
+    failed: BTreeMap<String, String>,
+}
+
+impl<'a> FailureReport<'a> {
+    fn new(
+        action: Option<&'a str>,
+        error: &'a (dyn Error + 'static),
+        outcomes: &RepoOutcomes,
+    ) -> FailureReport<'a> {
+        let obs_env_error = error.downcast_ref::<ObsEnvError>();
+        let mut failed = outcomes
+            .failed
+            .lock()
+            .map(|failed| failed.clone())
+            .unwrap_or_default();
+        let status = match obs_env_error {
+            Some(ObsEnvError::PartialFailure {
+                operation,
+                failed: failed_repos,
+            }) => {
+                for repo_name in failed_repos {
+                    failed
+                        .entry(repo_name.clone())
+                        .or_insert_with(|| format!("Failed to {operation}"));
+                }
+                "partial"
+            }
+            _ => "error",
+        };
+        let succeeded = outcomes
+            .done
+            .lock()
+            .map(|done| {
+                done.iter()
+                    .filter(|repo_name| !failed.contains_key(*repo_name))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
+        FailureReport {
+            status,
+            action,
+            kind: obs_env_error.map(ObsEnvError::kind),
+            repo: obs_env_error.and_then(ObsEnvError::repo),
+            messages: error::chain(error),
+            succeeded,
+            failed,
+        }
+    }
+}
+
+/// Seconds since the Unix epoch.
+fn timestamp() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs_f64())
+        .unwrap_or_default()
+}
+
+/// Repositories of the environment and where they were read from.
+type RepoSpecs = (Vec<RepoSpec>, RepoSource);
 
 /// Manage observing environment.
 #[derive(Parser, Debug)]
@@ -16,39 +339,571 @@ pub struct ManageObsEnv {
     /// Path to the environment.
     #[arg(long = "env-path", default_value = "/net/obs-env/auto_base_packages")]
     env_path: String,
-    /// Repository to act on (for actions on individual repos).
-    #[arg(value_enum, long = "repository")]
-    repository: Option<Repos>,
-    /// Name of the branch or version to checkout when running the "CheckoutBranch"
-    /// or "CheckoutVersion" action.
+    /// Directory holding several environments side by side. With
+    /// --env-name, the environment is <env-root>/<env-name> instead of
+    /// --env-path; "ListEnvs" lists the environments in it.
+    #[arg(long = "env-root", conflicts_with = "env_path")]
+    env_root: Option<String>,
+    /// Name of the environment under --env-root.
+    #[arg(long = "env-name", requires = "env_root", value_parser = parse_env_name)]
+    env_name: Option<String>,
+    /// Symlink repointed at the environment by "Activate" and back by
+    /// "Deactivate", by default <env-root>/current.
+    #[arg(long = "link")]
+    link: Option<String>,
+    /// Activate the environment even if it has missing or damaged
+    /// repositories, remove the environments selected by "PruneEnvs"
+    /// instead of only listing them, clone again with "Doctor --repair"
+    /// the broken repositories whose commits cannot be read, and remove
+    /// with "Teardown" the repositories with changes, untracked files,
+    /// stashes or commits on no remote.
+    #[arg(long = "force")]
+    force: bool,
+    /// Only prune the environments last set up more than this many days
+    /// ago with "PruneEnvs".
+    #[arg(long = "older-than")]
+    older_than: Option<u64>,
+    /// Keep this many of the most recently set up environments with
+    /// "PruneEnvs".
+    #[arg(long = "keep")]
+    keep: Option<usize>,
+    /// Repositories to act on (for actions on individual repos). Can be
+    /// repeated or given as a comma separated list.
+    #[arg(long = "repository", value_delimiter = ',')]
+    repository: Vec<String>,
+    /// Name of the branch or version to checkout when running the "CheckoutBranch",
+    /// "CheckoutBranchAll" or "CheckoutVersion" action.
+    /// A branch like DM-12345 that does not exist is checked out from
+    /// tickets/DM-12345 (see ticket_patterns in --config).
     #[arg(long = "branch-name", default_value = "")]
     branch_name: String,
     /// Name of the branch to checkout when running the "Reset"
     /// action.
     #[arg(long = "base-env-branch-name", default_value = "main")]
     base_env_branch_name: String,
+    /// Abort merges, rebases and similar operations left in progress in a
+    /// repository instead of refusing to operate on it.
+    #[arg(long = "abort-in-progress")]
+    abort_in_progress: bool,
+    /// Work without network access: nothing is fetched and the base
+    /// environment versions are read from the local cache.
+    #[arg(long = "offline")]
+    offline: bool,
+    /// Discard the cached base environment source and versions and fetch
+    /// them again.
+    #[arg(long = "refresh-base-cache", visible_alias = "refresh")]
+    refresh_base_cache: bool,
+    /// Seconds the cached base environment versions are used for before
+    /// reading them from the base environment source again. Offline, the
+    /// cache is used whatever its age.
+    #[arg(long = "base-cache-ttl", default_value = "600")]
+    base_cache_ttl: u64,
+    /// Seconds to wait for another run modifying the environment to finish
+    /// before failing. The actions that only read the environment do not
+    /// wait.
+    #[arg(long = "lock-timeout", default_value = "30")]
+    lock_timeout: u64,
+    /// Fetch the repositories already cloned when running "Setup", and
+    /// fast-forward the branch they have checked out.
+    #[arg(long = "update-existing", conflicts_with = "force_reclone")]
+    update_existing: bool,
+    /// Remove the repositories already cloned when running "Setup", and
+    /// clone them again. Also replaces directories in the way that are not
+    /// git repositories.
+    #[arg(long = "force-reclone")]
+    force_reclone: bool,
+    /// Require the environment path to be empty when running "Setup",
+    /// instead of cloning only the repositories missing from it.
+    #[arg(long = "fresh", conflicts_with_all = ["update_existing", "force_reclone"])]
+    fresh: bool,
+    /// Clone the repositories with only this many commits of history,
+    /// unless their configuration gives another depth. Older versions are
+    /// fetched when they are checked out.
+    #[arg(long = "clone-depth", value_parser = clap::value_parser!(u32).range(1..))]
+    clone_depth: Option<u32>,
+    /// Make partial clones, without the files, or also the trees, of the
+    /// commits not checked out, unless the configuration of a repository
+    /// gives another filter. They are made by git, which must be
+    /// installed, and fetch the missing objects when they are checked out.
+    #[arg(value_enum, long = "clone-filter")]
+    clone_filter: Option<CloneFilter>,
+    /// Repair the damaged repositories found by "Doctor": remove stale
+    /// lock files, and clone the broken repositories again unless they
+    /// have commits on no remote, or, without --force, their commits
+    /// cannot be read.
+    #[arg(long = "repair")]
+    repair: bool,
+    /// Make "Doctor" also read every object of the repositories to find
+    /// corrupt or missing ones, as git fsck does, which is slow. Scope it
+    /// with --only or --exclude.
+    #[arg(long = "deep")]
+    deep: bool,
+    /// Mebibytes that must be available on the volume of the environment
+    /// path for "Setup", "Reset" and "ApplyManifest" to start. 0 only
+    /// checks that the path is writable.
+    #[arg(long = "min-free-space", default_value = "1024")]
+    min_free_space: u64,
+    /// Name of the commits made by the manager in repositories with no
+    /// user.name configured in git.
+    #[arg(long = "commit-name", default_value = "obs-env manager")]
+    commit_name: String,
+    /// Email of the commits made by the manager in repositories with no
+    /// user.email configured in git.
+    #[arg(long = "commit-email", default_value = "obs-env@localhost")]
+    commit_email: String,
+    /// Do not keep the local commits "Reset" moves a repository away from
+    /// on an obs-env-backup/<time> branch.
+    #[arg(long = "no-backup")]
+    no_backup: bool,
+    /// Delete the branches "ListStaleBranches" lists instead of only
+    /// listing them.
+    #[arg(long = "delete")]
+    delete: bool,
+    /// Only drop the backup branches older than this many days with
+    /// "DropBackups".
+    #[arg(long = "backup-age", default_value = "0")]
+    backup_age: u64,
+    /// Only show the entries of the audit log, with "ShowHistory", or the
+    /// commits, with "SearchCommits", from this date on, given as
+    /// YYYY-MM-DD in UTC.
+    #[arg(long = "since", value_parser = parse_since)]
+    since: Option<u64>,
+    /// Number of the most recent entries of the audit log shown by
+    /// "ShowHistory".
+    #[arg(long = "history-limit", default_value = "20")]
+    history_limit: usize,
+    /// Show on stderr where the time was spent at the end of the run, by
+    /// phase (clones, fetches, checkouts, ...). Also added to the
+    /// run_complete event of --progress-events.
+    #[arg(long = "timing")]
+    timing: bool,
+    /// Show the progress of clones, fetches and resets on stderr.
+    #[arg(long = "progress")]
+    progress: bool,
+    /// Write the progress of the operations to stdout as newline-delimited
+    /// json events, ending with a run_complete event carrying the overall
+    /// result. The results of the action are written to stderr instead.
+    #[arg(long = "progress-events")]
+    progress_events: bool,
+    /// Number of repositories inspected, cloned or reset at the same time,
+    /// by default the number of CPUs.
+    #[arg(long = "jobs")]
+    jobs: Option<usize>,
+    /// Times a clone, fetch or other operation reaching a remote is tried
+    /// again after failing with a network error that may not happen again,
+    /// e.g. a timeout or an HTTP 5xx status, before the failure is
+    /// reported.
+    #[arg(long = "retries", default_value = "3")]
+    retries: u32,
+    /// Seconds to wait before the first retry of a network operation,
+    /// doubled for each of the next ones.
+    #[arg(long = "retry-backoff-secs", default_value = "2")]
+    retry_backoff_secs: u64,
+    /// Whether Setup, Reset, Fetch and ApplyManifest go on with the other
+    /// repositories once one fails, or start no other one and report them
+    /// as not attempted.
+    #[arg(value_enum, long = "on-error", default_value = "keep-going")]
+    on_error: ErrorPolicy,
+    /// Only redo the repositories of the last "Setup" or "Reset" that
+    /// failed, or were not attempted, with the same arguments.
+    #[arg(long = "resume")]
+    resume: bool,
+    /// Path to a shared store of bare repositories. When given, the
+    /// repositories in the environment are created as worktrees of these.
+    /// Repositories taken from a fork get a store of their own.
+    #[arg(long = "object-store-path")]
+    object_store_path: Option<String>,
+    /// Local file or directory to read the base environment versions from,
+    /// instead of the remote base environment repository. Either a versions
+    /// file or a checked-out copy of the base environment repository.
+    #[arg(long = "base-env-source")]
+    base_env_source: Option<String>,
+    /// Path to the configuration file.
+    #[arg(long = "config")]
+    config: Option<String>,
+    /// Take a repository from a fork, given as "owner:repo_name". Can be
+    /// repeated, and takes precedence over the configuration file.
+    #[arg(long = "fork", value_parser = parse_fork)]
+    fork: Vec<(String, String)>,
+    /// Check out a version of a repository with "Reset" and
+    /// "ApplyManifest" instead of the base or manifest version, given as
+    /// "repo_name=version". Can be repeated, and takes precedence over the
+    /// configuration file.
+    #[arg(long = "override", value_parser = parse_version_override)]
+    version_override: Vec<(String, String)>,
+    /// Toml file with the repositories of the environment, replacing the
+    /// built-in list and any list in the configuration file.
+    #[arg(long = "repos-file", alias = "repos-manifest")]
+    repos_file: Option<String>,
+    /// Only act on these repositories. Can be repeated or given as a comma
+    /// separated list.
+    #[arg(long = "only", value_delimiter = ',')]
+    only: Vec<String>,
+    /// Only act on the repositories in this group. Can be repeated, and
+    /// combined with --only.
+    #[arg(long = "group")]
+    group: Vec<String>,
+    /// Leave these repositories out of "Setup", "Reset", "Fetch",
+    /// "ShowCurrentVersions", "ApplyManifest" and the other actions, even
+    /// if selected by --only or --group. Can be repeated or given as a
+    /// comma separated list.
+    #[arg(long = "exclude", value_delimiter = ',')]
+    exclude: Vec<String>,
+    /// EUPS tag given to the repositories declared after "Setup", "Reset"
+    /// and the checkouts, and of the tag file written by "WriteTagFile".
+    #[arg(long = "eups-tag", default_value = "current")]
+    eups_tag: String,
+    /// Do not declare the repositories in EUPS. On hosts without the eups
+    /// command, they are not declared either, with a warning.
+    #[arg(long = "no-eups")]
+    no_eups: bool,
+    /// Do not run the hooks of the configuration file.
+    #[arg(long = "no-hooks")]
+    no_hooks: bool,
+    /// Unix group, by name or id, the directories and clones created by
+    /// "Setup" are given to, with the setgid bit set on directories.
+    /// "Doctor" reports the repositories with files outside of it.
+    #[arg(long = "unix-group")]
+    unix_group: Option<String>,
+    /// Permissions given to the directories and clones created by "Setup",
+    /// whose clones are then configured with core.sharedRepository=group.
+    /// "Doctor" reports the repositories the group could not write to.
+    #[arg(long = "permissions", value_enum)]
+    permissions: Option<Permissions>,
+    /// Manifest file written by "Export" (instead of stdout) and read by
+    /// "ApplyManifest". With "Watch", the manifest file in the repository
+    /// of --manifest-source, by default manifest.toml.
+    #[arg(long = "manifest")]
+    manifest: Option<String>,
+    /// Manifest file, or git repository holding it, that "Watch" keeps the
+    /// environment in sync with.
+    #[arg(long = "manifest-source")]
+    manifest_source: Option<String>,
+    /// Branch of the --manifest-source repository the manifest is read
+    /// from.
+    #[arg(long = "manifest-branch", default_value = "main")]
+    manifest_branch: String,
+    /// Check out the commits recorded in env.lock with "Reset", instead of
+    /// the base versions, and with "ApplyManifest", instead of the
+    /// versions of the manifest.
+    #[arg(long = "locked")]
+    locked: bool,
+    /// With "ApplyManifest", only check the --manifest file, without
+    /// touching the environment, e.g. in CI.
+    #[arg(long = "validate-only")]
+    validate_only: bool,
+    /// Read the base versions of "ShowOriginalVersions" and "Reset" at the
+    /// last commit of the base environment branch before this date, given
+    /// as YYYY-MM-DD in UTC.
+    #[arg(long = "as-of", value_parser = parse_since, conflicts_with_all = ["at_ref", "locked"])]
+    as_of: Option<u64>,
+    /// Read the base versions of "ShowOriginalVersions" and "Reset" at this
+    /// tag or commit of the base environment repository.
+    #[arg(long = "at-ref", conflicts_with = "locked")]
+    at_ref: Option<String>,
+    /// Manifest "CompareManifests" compares from.
+    #[arg(long = "from")]
+    from_manifest: Option<String>,
+    /// Manifest "Setup" clones the repositories at, adding those it has
+    /// that the repository list does not, verifying the environment
+    /// matches it afterwards.
+    #[arg(long = "from-manifest")]
+    setup_manifest: Option<String>,
+    /// Manifest "CompareManifests" compares to.
+    #[arg(long = "to")]
+    to_manifest: Option<String>,
+    /// With "CompareManifests", list the commits between the two versions
+    /// of the repositories cloned in the environment, when both manifests
+    /// have their commit.
+    #[arg(long = "commits")]
+    commits: bool,
+    /// Regular expression "SearchCommits" looks for in the commit messages.
+    #[arg(long = "pattern")]
+    pattern: Option<String>,
+    /// Only the commits whose author name or email contains this, ignoring
+    /// case, with "SearchCommits".
+    #[arg(long = "author")]
+    author: Option<String>,
+    /// Most recent matching commits "SearchCommits" shows by repository.
+    #[arg(long = "max-results", default_value = "20")]
+    max_results: usize,
+    /// Fetch every branch of the repositories before "SearchCommits"
+    /// searches them, "ShowCurrentVersions" and "Status" compare them to
+    /// origin or "Diff" to the base versions, instead of using only what
+    /// was last fetched.
+    #[arg(long = "fetch-first")]
+    fetch_first: bool,
+    /// Print the git commands "Setup", "Reset", "ApplyManifest",
+    /// "Restore", "CheckoutBranch", "CheckoutBranchAll" and
+    /// "CheckoutVersion" amount to, by repository with the branch and
+    /// commit it is at, instead of changing the repositories. Fetches are
+    /// still carried out, so versions resolve as they would for real.
+    #[arg(long = "dry-run")]
+    dry_run: bool,
+    /// Name of the snapshot "Snapshot" takes, by default the time it is
+    /// taken at, or "Restore" returns to, by default the latest.
+    #[arg(long = "snapshot")]
+    snapshot: Option<String>,
+    /// Seconds between two reconciliations of "Watch".
+    #[arg(long = "interval", default_value = "300")]
+    interval: u64,
+    /// File written by "WriteTagFile", by default <tag>.list in the
+    /// environment path, with <tag> given by --eups-tag.
+    #[arg(long = "tag-file")]
+    tag_file: Option<String>,
+    /// Record the Python packages (pip freeze, and conda env export when
+    /// conda is available) in the manifest written by "Export".
+    #[arg(long = "include-python-env")]
+    include_python_env: bool,
+    /// Record the files changed in the dirty repositories in the manifest
+    /// written by "Export".
+    #[arg(long = "include-dirty-files")]
+    include_dirty_files: bool,
+    /// Install the repositories in editable mode with pip after "Setup",
+    /// "Reset" and the checkouts.
+    #[arg(long = "develop-install")]
+    develop_install: bool,
+    /// Python interpreter used by --develop-install and
+    /// --include-python-env.
+    #[arg(long = "python", default_value = "python")]
+    python: String,
+    /// Conda executable used by the "CompareConda" action.
+    #[arg(long = "conda", default_value = "conda")]
+    conda: String,
+    /// Prometheus textfile written by "WriteMetrics".
+    #[arg(long = "metrics-file")]
+    metrics_file: Option<String>,
+    /// Url of the mirror of each repository "Mirror" pushes to, with
+    /// {repo} replaced by the repository name, e.g.
+    /// git@backup.example.com:obs-env/{repo}.git.
+    #[arg(long = "mirror-remote")]
+    mirror_remote: Option<String>,
+    /// Prefix of the branches and tags "Mirror" pushes, by default
+    /// deployed/<name of the environment path>.
+    #[arg(long = "mirror-prefix")]
+    mirror_prefix: Option<String>,
+    /// Days a repository can trail its default branch for before
+    /// "Outdated" counts it as outdated.
+    #[arg(long = "threshold-days")]
+    threshold_days: Option<u64>,
+    /// Commits a repository can trail its default branch by before
+    /// "Outdated" counts it as outdated.
+    #[arg(long = "threshold-commits")]
+    threshold_commits: Option<usize>,
+    /// File "Report" writes the report to, instead of the standard output.
+    #[arg(long = "report-path")]
+    report_path: Option<String>,
+    /// Format of the document written by "Report".
+    #[arg(value_enum, long = "report-format", default_value = "markdown")]
+    report_format: ReportFormat,
+    /// Sections of the document written by "Report", all of them by
+    /// default. Can be repeated or given as a comma separated list.
+    #[arg(value_enum, long = "report-sections", value_delimiter = ',')]
+    report_sections: Vec<ReportSection>,
+    /// Also check with "ValidateConfig" that the url of every repository
+    /// can be listed and has its default branch, which needs network
+    /// access.
+    #[arg(long = "check-remote")]
+    check_remote: bool,
+    /// Environment whose repositories "CloneEnv" copies into --env-path.
+    #[arg(long = "source-env-path")]
+    source_env_path: Option<String>,
+    /// Apply the changes to the tracked files of the repositories of
+    /// --source-env-path to their copies, which "CloneEnv" otherwise leaves
+    /// out.
+    #[arg(long = "copy-local-changes")]
+    copy_local_changes: bool,
+    /// Address and port "Serve" listens on. The default only accepts
+    /// connections from this host.
+    #[arg(long = "listen", default_value = serve::DEFAULT_ADDRESS)]
+    listen: String,
+    /// Webhook to post a json summary of the run to when it completes,
+    /// e.g. a Slack incoming webhook. Overrides the notify_url of the
+    /// configuration file. Failing to notify does not fail the run.
+    #[arg(long = "notify-url")]
+    notify_url: Option<String>,
+    /// Format of the results of the "Setup" and "CompareConda" actions,
+    /// among others. With json, a failed run ends with a json object of
+    /// its error and of the repositories that failed or not.
+    #[arg(value_enum, long = "output", default_value = "text")]
+    output: OutputFormat,
 }
+/// Settings needed by [`run`], so it can be driven by something other than
+/// the command line parser.
 pub trait ManageObsEnvCli {
     fn get_action(&self) -> Result<&Action, Box<dyn Error>>;
     fn get_log_level(&self) -> &LogLevel;
-    fn get_env_path(&self) -> &str;
+    fn get_env_path(&self) -> String;
+    fn get_env_root(&self) -> Option<&str>;
+    fn get_link(&self) -> Option<String>;
+    fn get_force(&self) -> bool;
+    fn get_prune_filter(&self) -> PruneFilter;
     fn get_branch_name(&self) -> &str;
     fn get_version(&self) -> &str;
-    fn get_repository_name(&self) -> &str;
+    fn get_repository_names(&self) -> &[String];
     fn get_base_env_source_repo(&self) -> &str;
+    fn get_abort_in_progress(&self) -> bool;
+    fn get_offline(&self) -> bool;
+    fn get_refresh_base_cache(&self) -> bool;
+    fn get_base_cache_ttl(&self) -> Duration;
+    fn get_lock_timeout(&self) -> Duration;
+    fn get_existing_clones(&self) -> ExistingClones;
+    fn get_fresh(&self) -> bool;
+    fn get_repair(&self) -> bool;
+    fn get_deep(&self) -> bool;
+    fn get_min_free_space(&self) -> u64;
+    fn get_backup(&self) -> bool;
+    fn get_backup_age(&self) -> Duration;
+    fn get_history_filter(&self) -> HistoryFilter;
+    fn get_commit_identity(&self) -> (&str, &str);
+    fn get_timing(&self) -> bool;
+    fn get_progress(&self) -> bool;
+    fn get_progress_events(&self) -> bool;
+    fn get_jobs(&self) -> Option<usize>;
+    fn get_retry_policy(&self) -> RetryPolicy;
+    fn get_on_error(&self) -> ErrorPolicy;
+    fn get_resume(&self) -> bool;
+    fn get_object_store_path(&self) -> Option<&str>;
+    fn get_clone_depth(&self) -> Option<u32>;
+    fn get_clone_filter(&self) -> Option<CloneFilter>;
+    fn get_only(&self) -> &[String];
+    fn get_groups(&self) -> &[String];
+    fn get_excluded(&self) -> &[String];
+    fn get_output_format(&self) -> &OutputFormat;
+    fn get_eups_tag(&self) -> Option<&str>;
+    fn get_manifest_path(&self) -> Option<&str>;
+    fn get_eups_tag_name(&self) -> &str;
+    fn get_tag_file(&self) -> String;
+    fn get_eups_products(&self) -> Result<BTreeMap<String, String>, Box<dyn Error>>;
+    fn get_include_python_env(&self) -> bool;
+    fn get_include_dirty_files(&self) -> bool;
+    fn get_python(&self) -> &str;
+    fn get_develop_install(&self) -> Option<&str>;
+    fn get_conda_command(&self) -> &str;
+    fn get_conda_packages(&self) -> Result<BTreeMap<String, String>, Box<dyn Error>>;
+    fn get_base_env_local_source(&self) -> Option<&str>;
+    fn get_config(&self) -> Result<Config, Box<dyn Error>>;
+    fn get_forks(&self) -> Result<BTreeMap<String, String>, Box<dyn Error>>;
+    fn get_repositories(&self) -> Result<Option<RepoSpecs>, Box<dyn Error>>;
+    fn get_overrides(&self) -> Result<BTreeMap<String, RepoOverride>, Box<dyn Error>>;
+    fn get_version_overrides(&self) -> Result<BTreeMap<String, String>, Box<dyn Error>>;
+    fn get_ticket_patterns(&self) -> Result<Option<Vec<String>>, Box<dyn Error>>;
+    fn get_notify_url(&self) -> Result<Option<String>, Box<dyn Error>>;
+    fn get_hooks(&self) -> Result<Hooks, Box<dyn Error>>;
+    fn get_shared_access(&self) -> Result<SharedAccess, ObsEnvError>;
+    fn get_metrics_file(&self) -> Option<&str>;
+    fn get_mirror_remote(&self) -> &str;
+    fn get_mirror_prefix(&self) -> String;
+    fn get_outdated_thresholds(&self) -> OutdatedThresholds;
+    fn get_outdated_ignore(&self) -> Result<Vec<String>, Box<dyn Error>>;
+    fn get_report_path(&self) -> Option<&str>;
+    fn get_report_format(&self) -> ReportFormat;
+    fn get_report_options(&self) -> Result<ReportOptions, Box<dyn Error>>;
+    fn get_config_file(&self) -> Option<&str>;
+    fn get_repos_file(&self) -> Option<&str>;
+    fn get_check_remote(&self) -> bool;
+    fn get_source_env_path(&self) -> Option<&str>;
+    fn get_copy_local_changes(&self) -> bool;
+    fn get_listen_address(&self) -> &str;
+    fn get_manifest_source(&self) -> Option<ManifestSource>;
+    fn get_watch_interval(&self) -> Duration;
+    fn get_locked(&self) -> bool;
+    fn get_validate_only(&self) -> bool;
+    fn get_base_env_revision(&self) -> Result<BaseEnvRevision, ObsEnvError>;
+    fn get_from_manifest(&self) -> Option<&str>;
+    fn get_setup_manifest(&self) -> Option<&str>;
+    fn get_to_manifest(&self) -> Option<&str>;
+    fn get_commits(&self) -> bool;
+    fn get_commit_query(&self) -> Result<CommitQuery, ObsEnvError>;
+    fn get_fetch_first(&self) -> bool;
+    fn get_delete(&self) -> bool;
+    fn get_dry_run(&self) -> bool;
+    fn get_snapshot_name(&self) -> Option<&str>;
 }
 
 impl ManageObsEnvCli for ManageObsEnv {
     fn get_action(&self) -> Result<&Action, Box<dyn Error>> {
         match self.action {
-            Action::CheckoutBranch => {
-                if self.repository.is_none() {
-                    Err(Box::new(ObsEnvError::ERROR(
-                        "Checkout branch action requires a repository, none given".to_owned(),
-                    )))
-                } else {
-                    Ok(&self.action)
-                }
+            Action::CheckoutBranch | Action::CheckoutVersion if self.repository.is_empty() => {
+                Err(Box::new(ObsEnvError::MissingArgument {
+                    action: format!("{:?}", self.action),
+                    argument: "--repository".to_owned(),
+                }))
+            }
+            Action::CheckoutBranchAll if self.branch_name.is_empty() => {
+                Err(Box::new(ObsEnvError::MissingArgument {
+                    action: format!("{:?}", self.action),
+                    argument: "--branch-name".to_owned(),
+                }))
+            }
+            Action::SearchCommits if self.pattern.is_none() => {
+                Err(Box::new(ObsEnvError::MissingArgument {
+                    action: format!("{:?}", self.action),
+                    argument: "--pattern".to_owned(),
+                }))
+            }
+            Action::ApplyManifest if self.manifest.is_none() => {
+                Err(Box::new(ObsEnvError::MissingArgument {
+                    action: format!("{:?}", self.action),
+                    argument: "--manifest".to_owned(),
+                }))
+            }
+            Action::CompareManifests
+                if self.from_manifest.is_none() || self.to_manifest.is_none() =>
+            {
+                Err(Box::new(ObsEnvError::MissingArgument {
+                    action: format!("{:?}", self.action),
+                    argument: "--from and --to".to_owned(),
+                }))
+            }
+            Action::Watch if self.manifest_source.is_none() => {
+                Err(Box::new(ObsEnvError::MissingArgument {
+                    action: format!("{:?}", self.action),
+                    argument: "--manifest-source".to_owned(),
+                }))
+            }
+            Action::WriteMetrics if self.metrics_file.is_none() => {
+                Err(Box::new(ObsEnvError::MissingArgument {
+                    action: format!("{:?}", self.action),
+                    argument: "--metrics-file".to_owned(),
+                }))
+            }
+            Action::Mirror if self.mirror_remote.is_none() => {
+                Err(Box::new(ObsEnvError::MissingArgument {
+                    action: format!("{:?}", self.action),
+                    argument: "--mirror-remote".to_owned(),
+                }))
+            }
+            Action::CloneEnv if self.source_env_path.is_none() => {
+                Err(Box::new(ObsEnvError::MissingArgument {
+                    action: format!("{:?}", self.action),
+                    argument: "--source-env-path".to_owned(),
+                }))
+            }
+            Action::ListEnvs | Action::PruneEnvs if self.env_root.is_none() => {
+                Err(Box::new(ObsEnvError::MissingArgument {
+                    action: format!("{:?}", self.action),
+                    argument: "--env-root".to_owned(),
+                }))
+            }
+            Action::Activate | Action::Deactivate if self.get_link().is_none() => {
+                Err(Box::new(ObsEnvError::MissingArgument {
+                    action: format!("{:?}", self.action),
+                    argument: "--link".to_owned(),
+                }))
+            }
+            Action::PruneEnvs if self.older_than.is_none() && self.keep.is_none() => {
+                Err(Box::new(ObsEnvError::MissingArgument {
+                    action: format!("{:?}", self.action),
+                    argument: "--older-than or --keep".to_owned(),
+                }))
+            }
+            Action::ListEnvs | Action::Deactivate | Action::PruneEnvs => Ok(&self.action),
+            _ if self.env_root.is_some() && self.env_name.is_none() => {
+                Err(Box::new(ObsEnvError::MissingArgument {
+                    action: format!("{:?}", self.action),
+                    argument: "--env-name".to_owned(),
+                }))
             }
             _ => Ok(&self.action),
         }
@@ -56,8 +911,38 @@ impl ManageObsEnvCli for ManageObsEnv {
     fn get_log_level(&self) -> &LogLevel {
         &self.log_level
     }
-    fn get_env_path(&self) -> &str {
-        &self.env_path
+    fn get_env_path(&self) -> String {
+        match (&self.env_root, &self.env_name) {
+            (Some(env_root), Some(env_name)) => Path::new(env_root)
+                .join(env_name)
+                .to_string_lossy()
+                .into_owned(),
+            _ => self.env_path.clone(),
+        }
+    }
+    fn get_env_root(&self) -> Option<&str> {
+        self.env_root.as_deref()
+    }
+    fn get_link(&self) -> Option<String> {
+        self.link.clone().or_else(|| {
+            self.env_root.as_ref().map(|env_root| {
+                Path::new(env_root)
+                    .join("current")
+                    .to_string_lossy()
+                    .into_owned()
+            })
+        })
+    }
+    fn get_force(&self) -> bool {
+        self.force
+    }
+    fn get_prune_filter(&self) -> PruneFilter {
+        PruneFilter {
+            older_than: self
+                .older_than
+                .map(|days| Duration::from_secs(days.saturating_mul(24 * 60 * 60))),
+            keep: self.keep,
+        }
     }
     fn get_branch_name(&self) -> &str {
         &self.branch_name
@@ -65,119 +950,2695 @@ impl ManageObsEnvCli for ManageObsEnv {
     fn get_version(&self) -> &str {
         &self.branch_name
     }
-    fn get_repository_name(&self) -> &str {
-        if let Some(repository) = &self.repository {
-            repository.get_name()
-        } else {
-            ""
-        }
+    fn get_repository_names(&self) -> &[String] {
+        &self.repository
     }
     fn get_base_env_source_repo(&self) -> &str {
         &self.base_env_branch_name
     }
+    fn get_abort_in_progress(&self) -> bool {
+        self.abort_in_progress
+    }
+    fn get_offline(&self) -> bool {
+        self.offline
+    }
+    fn get_refresh_base_cache(&self) -> bool {
+        self.refresh_base_cache
+    }
+    fn get_base_cache_ttl(&self) -> Duration {
+        Duration::from_secs(self.base_cache_ttl)
+    }
+    fn get_lock_timeout(&self) -> Duration {
+        Duration::from_secs(self.lock_timeout)
+    }
+    fn get_existing_clones(&self) -> ExistingClones {
+        if self.force_reclone {
+            ExistingClones::Reclone
+        } else if self.update_existing {
+            ExistingClones::Update
+        } else {
+            ExistingClones::Skip
+        }
+    }
+    fn get_fresh(&self) -> bool {
+        self.fresh
+    }
+    fn get_repair(&self) -> bool {
+        self.repair
+    }
+    fn get_deep(&self) -> bool {
+        self.deep
+    }
+    fn get_min_free_space(&self) -> u64 {
+        self.min_free_space.saturating_mul(1024 * 1024)
+    }
+    fn get_backup(&self) -> bool {
+        !self.no_backup
+    }
+    fn get_backup_age(&self) -> Duration {
+        Duration::from_secs(self.backup_age.saturating_mul(24 * 60 * 60))
+    }
+    fn get_history_filter(&self) -> HistoryFilter {
+        HistoryFilter {
+            repos: self.repository.clone(),
+            since: self.since,
+            limit: Some(self.history_limit),
+        }
+    }
+    fn get_commit_identity(&self) -> (&str, &str) {
+        (&self.commit_name, &self.commit_email)
+    }
+    fn get_timing(&self) -> bool {
+        self.timing
+    }
+    fn get_progress(&self) -> bool {
+        self.progress
+    }
+    fn get_progress_events(&self) -> bool {
+        self.progress_events
+    }
+    fn get_jobs(&self) -> Option<usize> {
+        self.jobs
+    }
+    fn get_retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            retries: self.retries,
+            backoff: Duration::from_secs(self.retry_backoff_secs),
+        }
+    }
+    fn get_on_error(&self) -> ErrorPolicy {
+        self.on_error
+    }
+    fn get_resume(&self) -> bool {
+        self.resume
+    }
+    fn get_object_store_path(&self) -> Option<&str> {
+        self.object_store_path.as_deref()
+    }
+    fn get_clone_depth(&self) -> Option<u32> {
+        self.clone_depth
+    }
+    fn get_clone_filter(&self) -> Option<CloneFilter> {
+        self.clone_filter
+    }
+    fn get_only(&self) -> &[String] {
+        &self.only
+    }
+    fn get_groups(&self) -> &[String] {
+        &self.group
+    }
+    fn get_excluded(&self) -> &[String] {
+        &self.exclude
+    }
+    fn get_output_format(&self) -> &OutputFormat {
+        &self.output
+    }
+    fn get_eups_tag(&self) -> Option<&str> {
+        (!self.no_eups).then_some(self.eups_tag.as_str())
+    }
+    fn get_manifest_path(&self) -> Option<&str> {
+        self.manifest.as_deref()
+    }
+    fn get_eups_tag_name(&self) -> &str {
+        &self.eups_tag
+    }
+    fn get_tag_file(&self) -> String {
+        self.tag_file.clone().unwrap_or_else(|| {
+            Path::new(&self.get_env_path())
+                .join(format!("{}.list", self.eups_tag))
+                .to_string_lossy()
+                .into_owned()
+        })
+    }
+    fn get_eups_products(&self) -> Result<BTreeMap<String, String>, Box<dyn Error>> {
+        Ok(self.get_config()?.eups_products)
+    }
+    fn get_include_python_env(&self) -> bool {
+        self.include_python_env
+    }
+    fn get_include_dirty_files(&self) -> bool {
+        self.include_dirty_files
+    }
+    fn get_python(&self) -> &str {
+        &self.python
+    }
+    fn get_develop_install(&self) -> Option<&str> {
+        self.develop_install.then_some(self.python.as_str())
+    }
+    fn get_conda_command(&self) -> &str {
+        &self.conda
+    }
+    fn get_conda_packages(&self) -> Result<BTreeMap<String, String>, Box<dyn Error>> {
+        Ok(self.get_config()?.conda_packages)
+    }
+    fn get_base_env_local_source(&self) -> Option<&str> {
+        self.base_env_source.as_deref()
+    }
+    fn get_config(&self) -> Result<Config, Box<dyn Error>> {
+        if let Some(config) = &self.config {
+            Ok(Config::from_file(Path::new(config))?)
+        } else {
+            Ok(Config::default())
+        }
+    }
+    fn get_forks(&self) -> Result<BTreeMap<String, String>, Box<dyn Error>> {
+        let mut forks = self.get_config()?.forks;
+        for (owner, repo_name) in self.fork.iter() {
+            forks.insert(repo_name.to_owned(), owner.to_owned());
+        }
+        Ok(forks)
+    }
+    fn get_repositories(&self) -> Result<Option<RepoSpecs>, Box<dyn Error>> {
+        if let Some(repos_file) = &self.repos_file {
+            let path = Path::new(repos_file);
+            return Ok(Some((
+                RepoSpec::load_file(path)?,
+                RepoSource::File(path.to_path_buf()),
+            )));
+        }
+        let repositories = self.get_config()?.repositories;
+        match &self.config {
+            Some(config) if !repositories.is_empty() => Ok(Some((
+                repositories,
+                RepoSource::File(Path::new(config).to_path_buf()),
+            ))),
+            _ => Ok(None),
+        }
+    }
+    fn get_overrides(&self) -> Result<BTreeMap<String, RepoOverride>, Box<dyn Error>> {
+        Ok(self.get_config()?.overrides)
+    }
+    fn get_version_overrides(&self) -> Result<BTreeMap<String, String>, Box<dyn Error>> {
+        let mut version_overrides = self.get_config()?.version_overrides;
+        version_overrides.extend(self.version_override.iter().cloned());
+        Ok(version_overrides)
+    }
+    fn get_ticket_patterns(&self) -> Result<Option<Vec<String>>, Box<dyn Error>> {
+        Ok(self.get_config()?.ticket_patterns)
+    }
+    fn get_metrics_file(&self) -> Option<&str> {
+        self.metrics_file.as_deref()
+    }
+    fn get_mirror_remote(&self) -> &str {
+        self.mirror_remote.as_deref().unwrap_or_default()
+    }
+    fn get_mirror_prefix(&self) -> String {
+        self.mirror_prefix.clone().unwrap_or_else(|| {
+            let env_path = self.get_env_path();
+            let name = Path::new(&env_path)
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            format!("deployed/{name}")
+        })
+    }
+    fn get_outdated_thresholds(&self) -> OutdatedThresholds {
+        OutdatedThresholds {
+            days: self.threshold_days,
+            commits: self.threshold_commits,
+        }
+    }
+    fn get_outdated_ignore(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        Ok(self.get_config()?.outdated_ignore)
+    }
+    fn get_report_path(&self) -> Option<&str> {
+        self.report_path.as_deref()
+    }
+    fn get_report_format(&self) -> ReportFormat {
+        self.report_format
+    }
+    fn get_report_options(&self) -> Result<ReportOptions, Box<dyn Error>> {
+        let sections = match self.report_sections.is_empty() {
+            true => ReportSection::all(),
+            false => self.report_sections.clone(),
+        };
+        Ok(ReportOptions {
+            sections,
+            thresholds: self.get_outdated_thresholds(),
+            outdated_ignore: self.get_outdated_ignore()?,
+            history: self.get_history_filter(),
+        })
+    }
+    fn get_config_file(&self) -> Option<&str> {
+        self.config.as_deref()
+    }
+    fn get_repos_file(&self) -> Option<&str> {
+        self.repos_file.as_deref()
+    }
+    fn get_check_remote(&self) -> bool {
+        self.check_remote
+    }
+    fn get_source_env_path(&self) -> Option<&str> {
+        self.source_env_path.as_deref()
+    }
+    fn get_copy_local_changes(&self) -> bool {
+        self.copy_local_changes
+    }
+    fn get_listen_address(&self) -> &str {
+        &self.listen
+    }
+    fn get_manifest_source(&self) -> Option<ManifestSource> {
+        self.manifest_source.as_deref().map(|source| {
+            ManifestSource::new(
+                source,
+                &self.manifest_branch,
+                self.manifest.as_deref().unwrap_or("manifest.toml"),
+            )
+        })
+    }
+    fn get_watch_interval(&self) -> Duration {
+        Duration::from_secs(self.interval)
+    }
+    fn get_locked(&self) -> bool {
+        self.locked
+    }
+    fn get_validate_only(&self) -> bool {
+        self.validate_only
+    }
+    fn get_base_env_revision(&self) -> Result<BaseEnvRevision, ObsEnvError> {
+        let revision = match (self.as_of, &self.at_ref) {
+            (Some(time), _) => BaseEnvRevision::AsOf(time),
+            (None, Some(revision)) => BaseEnvRevision::At(revision.clone()),
+            (None, None) => BaseEnvRevision::Latest,
+        };
+        if revision != BaseEnvRevision::Latest
+            && !matches!(self.action, Action::Reset | Action::ShowOriginalVersions)
+        {
+            return Err(ObsEnvError::InvalidConfig {
+                message: format!(
+                    "--as-of and --at-ref are not supported by {}",
+                    action_name(&self.action)
+                ),
+            });
+        }
+        Ok(revision)
+    }
+    fn get_from_manifest(&self) -> Option<&str> {
+        self.from_manifest.as_deref()
+    }
+    fn get_setup_manifest(&self) -> Option<&str> {
+        self.setup_manifest.as_deref()
+    }
+    fn get_to_manifest(&self) -> Option<&str> {
+        self.to_manifest.as_deref()
+    }
+    fn get_commits(&self) -> bool {
+        self.commits
+    }
+    fn get_commit_query(&self) -> Result<CommitQuery, ObsEnvError> {
+        let pattern = self.pattern.as_deref().unwrap_or_default();
+        Ok(CommitQuery {
+            pattern: Regex::new(pattern).map_err(|error| ObsEnvError::InvalidConfig {
+                message: format!("Invalid --pattern {pattern}: {error}"),
+            })?,
+            since: self.since,
+            author: self.author.clone(),
+            max_results: self.max_results,
+        })
+    }
+    fn get_fetch_first(&self) -> bool {
+        self.fetch_first
+    }
+    fn get_delete(&self) -> bool {
+        self.delete
+    }
+    fn get_dry_run(&self) -> bool {
+        self.dry_run
+    }
+    fn get_snapshot_name(&self) -> Option<&str> {
+        self.snapshot.as_deref()
+    }
+    fn get_notify_url(&self) -> Result<Option<String>, Box<dyn Error>> {
+        match &self.notify_url {
+            Some(notify_url) => Ok(Some(notify_url.clone())),
+            None => Ok(self.get_config()?.notify_url),
+        }
+    }
+    fn get_hooks(&self) -> Result<Hooks, Box<dyn Error>> {
+        match self.no_hooks {
+            true => Ok(Hooks::default()),
+            false => Ok(self.get_config()?.hooks),
+        }
+    }
+    fn get_shared_access(&self) -> Result<SharedAccess, ObsEnvError> {
+        Ok(SharedAccess {
+            group: self.unix_group.as_deref().map(Group::lookup).transpose()?,
+            group_writable: matches!(self.permissions, Some(Permissions::GroupWritable)),
+        })
+    }
+}
+
+/// Parse a date given as YYYY-MM-DD, in seconds since the Unix epoch.
+fn parse_since(since: &str) -> Result<u64, String> {
+    audit::parse_date(since).ok_or_else(|| format!("Invalid date {since}, expected YYYY-MM-DD."))
+}
+
+fn parse_env_name(env_name: &str) -> Result<String, String> {
+    environments::validate_name(env_name).map(|_| env_name.to_owned())
+}
+
+fn parse_version_override(version_override: &str) -> Result<(String, String), String> {
+    match version_override.split_once('=') {
+        Some((repo_name, version)) if !repo_name.is_empty() && !version.is_empty() => {
+            Ok((repo_name.to_owned(), version.to_owned()))
+        }
+        _ => Err(format!(
+            "Invalid override {version_override}, expected repo_name=version."
+        )),
+    }
+}
+
+fn parse_fork(fork: &str) -> Result<(String, String), String> {
+    match fork.split_once(':') {
+        Some((owner, repo_name)) if !owner.is_empty() && !repo_name.is_empty() => {
+            Ok((owner.to_owned(), repo_name.to_owned()))
+        }
+        _ => Err(format!("Invalid fork {fork}, expected owner:repo_name.")),
+    }
 }
 
+/// Execute the action selected in `config`, writing the results to stdout,
+/// or to stderr if stdout carries the progress events.
 pub fn run<T>(config: &T) -> Result<(), Box<dyn Error>>
 where
     T: ManageObsEnvCli,
 {
-    match config.get_log_level() {
-        LogLevel::Trace => log::set_max_level(log::LevelFilter::Trace),
-        LogLevel::Debug => log::set_max_level(log::LevelFilter::Debug),
-        LogLevel::Info => log::set_max_level(log::LevelFilter::Info),
-        LogLevel::Warn => log::set_max_level(log::LevelFilter::Warn),
-        LogLevel::Error => log::set_max_level(log::LevelFilter::Error),
-    };
-
-    log::info!("Running manage obs env...");
+    if config.get_progress_events() {
+        run_with_output(config, &mut io::stderr())
+    } else {
+        run_with_output(config, &mut io::stdout())
+    }
+}
 
-    let obs_env = ObservingEnvironment::with_destination(config.get_env_path());
+/// Execute the action selected in `config`, writing the results to `out`.
+///
+/// Only the results of the action (version listings, summaries, ...) are
+/// written to `out`; progress and diagnostics go through the logger. With
+/// [`ManageObsEnvCli::get_progress_events`], the progress events are
+/// written to stdout.
+///
+/// The logger is left as the caller set it up:
+/// [`ManageObsEnvCli::get_log_level`] is only applied by the
+/// `manage_obs_env` binary, so that a program embedding the crate keeps
+/// its own log level.
+pub fn run_with_output<T, W>(config: &T, out: &mut W) -> Result<(), Box<dyn Error>>
+where
+    T: ManageObsEnvCli,
+    W: Write,
+{
+    let progress_events = config
+        .get_progress_events()
+        .then(|| Arc::new(ProgressEvents::new(io::stdout())));
+    run_with_events(config, out, progress_events)
+}
 
-    match config.get_action()? {
-        Action::Setup => {
-            log::info!("Executing Setup...");
+/// Execute the action selected in `config`, reporting progress to
+/// `progress_events` if given and finishing with their run_complete event.
+fn run_with_events<T, W, E>(
+    config: &T,
+    out: &mut W,
+    progress_events: Option<Arc<ProgressEvents<E>>>,
+) -> Result<(), Box<dyn Error>>
+where
+    T: ManageObsEnvCli,
+    W: Write,
+    E: Write + Send + 'static,
+{
+    let timings = Arc::new(Timings::new());
+    let outcomes = Arc::new(RepoOutcomes::default());
+    let start = Instant::now();
+    let (result, setup_report) = match timings.time("total", None, || {
+        run_action(
+            config,
+            out,
+            progress_events.clone(),
+            timings.clone(),
+            outcomes.clone(),
+        )
+    }) {
+        Ok(Some(setup_report)) => match setup_report.partial_failure() {
+            Some(error) => (Err(error.into()), Some(setup_report)),
+            None => (Ok(()), Some(setup_report)),
+        },
+        Ok(None) => (Ok(()), None),
+        Err(error) => (Err(error), None),
+    };
+    if let Some(progress_events) = progress_events {
+        let action = config
+            .get_action()
+            .ok()
+            .and_then(|action| action.to_possible_value());
+        progress_events.run_complete(
+            action.as_ref().map(|action| action.get_name()),
+            &result,
+            setup_report.as_ref(),
+            config.get_timing().then(|| timings.report()),
+        )?;
+    }
+    // The json report of Setup already has the outcome of every
+    // repository, and the same "partial" status.
+    if let (OutputFormat::Json, Err(error), None) =
+        (config.get_output_format(), &result, &setup_report)
+    {
+        let action = config.get_action().ok().map(action_name);
+        let failure = FailureReport::new(action.as_deref(), error.as_ref(), &outcomes);
+        serde_json::to_writer_pretty(&mut *out, &failure)?;
+        writeln!(out)?;
+    }
+    if config.get_timing() {
+        eprintln!("{}", timings.report());
+    }
+    notify(config, &result, start.elapsed());
+    result
+}
 
-            log::debug!("Creating path...");
-            obs_env.create_path()?;
+/// Post the outcome of the run to the webhook of `config`, if any, logging
+/// the failure to do so without failing the run.
+fn notify<T: ManageObsEnvCli>(config: &T, result: &Result<(), Box<dyn Error>>, duration: Duration) {
+    let url = match config.get_notify_url() {
+        Ok(Some(url)) => url,
+        Ok(None) => return,
+        Err(error) => {
+            log::warn!("Cannot read the webhook to notify: {error}");
+            return;
+        }
+    };
+    let (outcome, failed_repos, error) = match result {
+        Ok(()) => (AuditOutcome::Success, 0, None),
+        Err(error) => match error.downcast_ref::<ObsEnvError>() {
+            Some(ObsEnvError::PartialFailure { failed, .. }) => (
+                AuditOutcome::PartialFailure,
+                failed.len(),
+                Some(error.to_string()),
+            ),
+            _ => (AuditOutcome::Failed, 0, Some(error.to_string())),
+        },
+    };
+    let action = config.get_action().map(action_name).unwrap_or_default();
+    let webhook = Webhook::new(&url);
+    log::debug!("Notifying {}", webhook.redacted_url());
+    let notification = Notification::new(&action, outcome, failed_repos, duration, error);
+    if let Err(error) = webhook.send(&notification) {
+        log::warn!(
+            "Could not notify {}: {}",
+            webhook.redacted_url(),
+            report(&error)
+        );
+    }
+}
 
-            log::debug!("Cloning repositories...");
-            let cloned_repos = obs_env.clone_repositories();
-            log::info!("The following repositories where cloned: ");
-            for repo in cloned_repos.iter() {
-                match repo {
-                    Ok(repo) => log::info!("{:?}", repo.path()),
-                    Err(error) => log::error!("Failed to clone: {error:?}"),
-                }
+/// Reset the environment to its base versions for the "Reset" action,
+/// recording the outcome of each repository in `resume`.
+///
+/// The error of every repository that could not be reset is logged, and a
+/// [`ObsEnvError::PartialFailure`] naming them is returned, so the exit
+/// status tells the reset did not complete. Errors that are not about a
+/// repository, like an unreachable base environment, are returned as is.
+fn reset<W: Write>(
+    obs_env: &ObservingEnvironment,
+    out: &mut W,
+    resume: Option<&mut ResumeState>,
+) -> Result<(), Box<dyn Error>> {
+    let base_env_branch = obs_env.get_base_env_branch();
+    let mut errors = match obs_env.reset_base_environment(base_env_branch) {
+        Ok(reset_report) => {
+            writeln!(
+                out,
+                "All repositories set to their base versions, from {}.",
+                obs_env.describe_base_env_source(base_env_branch)
+            )?;
+            if let Some(commit) = &reset_report.base_commit {
+                writeln!(out, "Base environment commit: {commit}")?;
             }
-        }
-        Action::PrintConfig => {
-            log::info!("{}", obs_env.summarize());
-        }
-        Action::Reset => {
-            log::info!("Resetting Observing environment...");
-            if let Err(error) = obs_env.reset_base_environment(config.get_base_env_source_repo()) {
-                log::error!("Error resetting {} repositories.", error.len());
-                for err in error {
-                    log::error!("{:?}", err);
-                }
-            } else {
-                log::info!("All repositories set to they base versions.");
+            for (repo_name, branch) in reset_report.branches {
+                writeln!(out, "{repo_name}: branch {branch}")?;
             }
-        }
-        Action::ShowCurrentVersions => {
-            log::info!("Current environment versions:");
-            let current_versions = obs_env.get_current_env_versions();
-            for (name, version) in current_versions.iter() {
-                match version {
-                    Ok(version) => log::info!("{name}: {version}"),
-                    Err(error) => log::error!("{name}: {error:?}"),
-                }
+            for (repo_name, version_override) in reset_report.overridden {
+                let base = version_override.base.as_deref().unwrap_or("none");
+                writeln!(
+                    out,
+                    "{repo_name}: {} (override, base version {base})",
+                    version_override.version
+                )?;
             }
-        }
-        Action::ShowOriginalVersions => {
-            match obs_env.get_base_env_versions(config.get_base_env_source_repo()) {
-                Ok(base_env_versions) => {
-                    log::info!("Base Environment versions:");
-                    for (name, version) in base_env_versions.iter() {
-                        log::info!("{name}: {version}");
-                    }
-                }
-                Err(error) => {
-                    log::error!("{error:?}");
-                }
+            for backup in reset_report.backups {
+                writeln!(
+                    out,
+                    "{}: local commits kept on {}",
+                    backup.repo, backup.branch
+                )?;
             }
+            record_reset(obs_env, resume, &[]);
+            return Ok(());
         }
-        Action::CheckoutBranch => {
-            obs_env.checkout_branch(config.get_repository_name(), config.get_branch_name())?;
+        Err(errors) => errors,
+    };
+    record_reset(obs_env, resume, &errors);
+    Err(reset_failure(&mut errors))
+}
+
+/// Check out the commits of the lock file of the environment for the
+/// "Reset" action with --locked, failing as [`reset`] does.
+fn reset_locked<W: Write>(
+    obs_env: &ObservingEnvironment,
+    env_path: &str,
+    out: &mut W,
+    resume: Option<&mut ResumeState>,
+) -> Result<(), Box<dyn Error>> {
+    let manifest = obs_env.read_lock_file()?.pin(env_path, None)?;
+    let mut errors = match obs_env.apply_manifest(&manifest) {
+        Ok(()) => {
+            writeln!(
+                out,
+                "All repositories set to their commits in {}.",
+                obs_env.lock_file_path().display()
+            )?;
+            record_reset(obs_env, resume, &[]);
+            return Ok(());
         }
-        Action::CheckoutVersion => {
-            obs_env.reset_index_to_version(config.get_repository_name(), config.get_version())?;
+        Err(errors) => errors,
+    };
+    record_reset(obs_env, resume, &errors);
+    Err(reset_failure(&mut errors))
+}
+
+/// Record in `resume` the outcomes of the repositories of `obs_env` a reset
+/// failed on with `errors`.
+fn record_reset(
+    obs_env: &ObservingEnvironment,
+    resume: Option<&mut ResumeState>,
+    errors: &[ObsEnvError],
+) {
+    if let Some(resume) = resume {
+        let repo_names: Vec<String> = obs_env.repos().map(|repo| repo.name().to_owned()).collect();
+        resume.record_errors(repo_names.iter().map(String::as_str), errors);
+    }
+}
+
+/// Log the `errors` of a reset, returning a [`ObsEnvError::PartialFailure`]
+/// naming the repositories that could not be reset, or the first error if
+/// none is about a repository.
+fn reset_failure(errors: &mut Vec<ObsEnvError>) -> Box<dyn Error> {
+    log::error!("Error resetting {} repositories.", errors.len());
+    for error in errors.iter() {
+        log::error!("{}", report(error));
+    }
+    let failed: Vec<String> = errors
+        .iter()
+        .filter_map(|error| error.repo().map(str::to_owned))
+        .collect();
+    if failed.is_empty() {
+        return errors.remove(0).into();
+    }
+    ObsEnvError::PartialFailure {
+        operation: "reset".to_owned(),
+        failed,
+    }
+    .into()
+}
+
+/// Push the repositories to their mirror for the "Mirror" action,
+/// returning the mirrors pushed to, and a
+/// [`ObsEnvError::PartialFailure`] naming the repositories that could not
+/// be pushed, whose errors are logged, if any.
+fn mirror<T: ManageObsEnvCli>(
+    config: &T,
+    obs_env: &ObservingEnvironment,
+) -> (Vec<Mirror>, Result<(), ObsEnvError>) {
+    log::info!(
+        "Pushing the repositories to {}...",
+        config.get_mirror_remote()
+    );
+    let mut mirrors = Vec::new();
+    let mut failed = Vec::new();
+    for (repo_name, result) in
+        obs_env.mirror(config.get_mirror_remote(), &config.get_mirror_prefix())
+    {
+        match result {
+            Ok(mirror) => mirrors.push(mirror),
+            Err(error) => {
+                log::error!("{}", report(&error));
+                failed.push(repo_name);
+            }
         }
+    }
+    let result = match failed.is_empty() {
+        true => Ok(()),
+        false => Err(ObsEnvError::PartialFailure {
+            operation: "mirror".to_owned(),
+            failed,
+        }),
     };
-    Ok(())
+    (mirrors, result)
 }
 
-#[derive(clap::ValueEnum, Clone, Debug)]
+/// Prune the remote-tracking branches of the repositories for the
+/// "PruneRemotes" action, returning those pruned, and a
+/// [`ObsEnvError::PartialFailure`] naming the repositories that could not
+/// be pruned, whose errors are logged, if any.
+fn prune_remotes(
+    obs_env: &ObservingEnvironment,
+) -> Result<(Vec<PrunedRemotes>, Result<(), ObsEnvError>), ObsEnvError> {
+    let mut pruned = Vec::new();
+    let mut failed = Vec::new();
+    for (repo_name, result) in obs_env.prune_remotes()? {
+        match result {
+            Ok(remotes) => pruned.push(remotes),
+            Err(error) => {
+                log::error!("{}", report(&error));
+                failed.push(repo_name);
+            }
+        }
+    }
+    let result = match failed.is_empty() {
+        true => Ok(()),
+        false => Err(ObsEnvError::PartialFailure {
+            operation: "prune remote-tracking branches".to_owned(),
+            failed,
+        }),
+    };
+    Ok((pruned, result))
+}
+
+/// Checkout each repository given with --repository with `checkout`, for
+/// "CheckoutBranch" and "CheckoutVersion", returning the repositories
+/// checked out, in order, with what they were checked out at.
+///
+/// A single repository fails with its error. With several, they are all
+/// attempted, and those that failed, whose errors are logged, are named by
+/// a [`ObsEnvError::PartialFailure`].
+fn checkout_each<T: ManageObsEnvCli>(
+    config: &T,
+    checkout: impl Fn(&str) -> Result<String, ObsEnvError>,
+) -> (Vec<(String, String)>, Result<(), ObsEnvError>) {
+    let repo_names = config.get_repository_names();
+    let mut checked_out = Vec::new();
+    let mut failed = Vec::new();
+    for repo_name in repo_names {
+        match checkout(repo_name) {
+            Ok(target) => checked_out.push((repo_name.clone(), target)),
+            Err(error) if repo_names.len() == 1 => return (checked_out, Err(error)),
+            Err(error) => {
+                log::error!("{}", report(&error));
+                failed.push(repo_name.clone());
+            }
+        }
+    }
+    let result = match failed.is_empty() {
+        true => Ok(()),
+        false => Err(ObsEnvError::PartialFailure {
+            operation: "checkout".to_owned(),
+            failed,
+        }),
+    };
+    (checked_out, result)
+}
+
+/// Checkout the repositories given with --repository at the --branch-name
+/// branch, or at --version, for `action`, "CheckoutBranch" or
+/// "CheckoutVersion".
+fn checkout_repositories<T: ManageObsEnvCli>(
+    config: &T,
+    obs_env: &ObservingEnvironment,
+    action: &Action,
+) -> (Vec<(String, String)>, Result<(), ObsEnvError>) {
+    match action {
+        Action::CheckoutBranch => checkout_each(config, |repo_name| {
+            obs_env.checkout_branch(repo_name, config.get_branch_name())
+        }),
+        _ => checkout_each(config, |repo_name| {
+            obs_env
+                .reset_index_to_version(repo_name, config.get_version())
+                .map(|_| config.get_version().to_owned())
+        }),
+    }
+}
+
+/// Checkout `branch_name` in the repositories that have it for the
+/// "CheckoutBranchAll" action, returning the branch each repository
+/// switched to, if any, and a [`ObsEnvError::PartialFailure`] naming the
+/// repositories that failed, whose errors are logged, if any.
+fn checkout_branch_all(
+    obs_env: &ObservingEnvironment,
+    branch_name: &str,
+) -> (BTreeMap<String, Option<String>>, Result<(), ObsEnvError>) {
+    let mut switched = BTreeMap::new();
+    let mut failed = Vec::new();
+    for (repo_name, result) in obs_env.checkout_branch_all(branch_name) {
+        match result {
+            Ok(branch) => {
+                switched.insert(repo_name, branch);
+            }
+            Err(error) => {
+                log::error!("{}", report(&error));
+                failed.push(repo_name);
+            }
+        }
+    }
+    let result = match failed.is_empty() {
+        true => Ok(()),
+        false => Err(ObsEnvError::PartialFailure {
+            operation: format!("checkout {branch_name}"),
+            failed,
+        }),
+    };
+    (switched, result)
+}
+
+/// Check the manifest at `path` for "ApplyManifest" with --validate-only,
+/// writing its warnings to `out` and failing on its errors.
+fn validate_manifest<W: Write>(path: &Path, out: &mut W) -> Result<(), Box<dyn Error>> {
+    let content =
+        read_to_string(path).map_err(|error| ObsEnvError::io(path, "read manifest", error))?;
+    let validation = schema::validate(&content);
+    for warning in validation.warnings.iter() {
+        writeln!(out, "{}: warning: {warning}", path.display())?;
+    }
+    if !validation.is_valid() {
+        return Err(ObsEnvError::InvalidManifest {
+            location: path.display().to_string(),
+            problems: validation
+                .errors
+                .iter()
+                .map(|error| error.to_string())
+                .collect(),
+        }
+        .into());
+    }
+    writeln!(out, "{} is a valid manifest.", path.display())?;
+    Ok(())
+}
+
+/// Check the configuration of the repositories given by `config`, its
+/// files read as they are, for "ValidateConfig".
+fn check_configuration<T: ManageObsEnvCli>(config: &T) -> Result<Vec<ConfigCheck>, Box<dyn Error>> {
+    let mut read_problems = Vec::new();
+    let mut file_config = Config::default();
+    if let Some(path) = config.get_config_file() {
+        match Config::read_file(Path::new(path)) {
+            Ok(read) => file_config = read,
+            Err(error) => read_problems.push(report(&error)),
+        }
+    }
+    let mut repo_specs = file_config.repositories.clone();
+    if let Some(path) = config.get_repos_file() {
+        match RepoSpec::read_file(Path::new(path)) {
+            Ok(read) => repo_specs = read,
+            Err(error) => read_problems.push(report(&error)),
+        }
+    }
+    let read = ConfigCheck::new("configuration files can be read", read_problems);
+    if !read.passed {
+        return Ok(vec![read]);
+    }
+    if repo_specs.is_empty() {
+        repo_specs = Repos::value_variants().iter().map(RepoSpec::from).collect();
+    }
+    file_config.forks = config.get_forks()?;
+    file_config.version_overrides = config.get_version_overrides()?;
+    let selection = Selection {
+        only: config.get_only().to_vec(),
+        groups: config.get_groups().to_vec(),
+        excluded: config.get_excluded().to_vec(),
+    };
+    let mut checks = vec![read];
+    checks.extend(check_config(&repo_specs, &file_config, &selection));
+    Ok(checks)
+}
+
+/// Write `checks` to `out`, failing if any of them did.
+fn write_config_checks<T: ManageObsEnvCli, W: Write>(
+    config: &T,
+    out: &mut W,
+    checks: &[ConfigCheck],
+) -> Result<(), Box<dyn Error>> {
+    match config.get_output_format() {
+        OutputFormat::Text => {
+            for check in checks.iter() {
+                writeln!(out, "{check}")?;
+            }
+        }
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut *out, checks)?;
+            writeln!(out)?;
+        }
+    }
+    let failed = checks.iter().filter(|check| !check.passed).count();
+    if failed > 0 {
+        return Err(ObsEnvError::InvalidConfig {
+            message: format!("{failed} of {} configuration checks failed", checks.len()),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Carry out `action` on `obs_env`, built on `dry_run`, for --dry-run,
+/// writing the git commands it amounts to to `out`, even if it fails.
+fn plan_action<T: ManageObsEnvCli, W: Write>(
+    config: &T,
+    out: &mut W,
+    obs_env: &ObservingEnvironment,
+    action: &Action,
+    setup_manifest: Option<&EnvironmentManifest>,
+    dry_run: &DryRunBackend<Git2Backend>,
+) -> Result<(), Box<dyn Error>> {
+    let apply = |manifest: &EnvironmentManifest| {
+        obs_env
+            .apply_manifest(manifest)
+            .map_err(|mut errors| reset_failure(&mut errors))
+    };
+    let result: Result<(), Box<dyn Error>> = match action {
+        Action::Setup => match setup_manifest {
+            Some(manifest) => obs_env.clone_repositories_at(manifest),
+            None => obs_env.clone_repositories(),
+        }
+        .into_result()
+        .map(|_| ())
+        .map_err(Into::into),
+        Action::Reset if config.get_locked() => obs_env
+            .read_lock_file()
+            .and_then(|lock_file| lock_file.pin(&config.get_env_path(), None))
+            .map_err(Into::into)
+            .and_then(|manifest| apply(&manifest)),
+        Action::Reset => obs_env
+            .reset_base_environment(obs_env.get_base_env_branch())
+            .map(|_| ())
+            .map_err(|mut errors| reset_failure(&mut errors)),
+        Action::ApplyManifest => {
+            EnvironmentManifest::load(Path::new(config.get_manifest_path().unwrap_or_default()))
+                .and_then(|manifest| match config.get_locked() {
+                    true => obs_env
+                        .read_lock_file()?
+                        .pin(&config.get_env_path(), Some(&manifest)),
+                    false => Ok(manifest),
+                })
+                .map_err(Into::into)
+                .and_then(|manifest| apply(&manifest))
+        }
+        Action::Restore => obs_env
+            .load_snapshot(config.get_snapshot_name())
+            .map_err(Into::into)
+            .and_then(|snapshot| apply(&snapshot.manifest)),
+        Action::CheckoutBranch | Action::CheckoutVersion => {
+            checkout_repositories(config, obs_env, action)
+                .1
+                .map_err(Into::into)
+        }
+        Action::CheckoutBranchAll => checkout_branch_all(obs_env, config.get_branch_name())
+            .1
+            .map_err(Into::into),
+        Action::Mirror => mirror(config, obs_env).1.map_err(Into::into),
+        Action::PruneRemotes => prune_remotes(obs_env)
+            .and_then(|(_, result)| result)
+            .map_err(Into::into),
+        _ => {
+            return Err(ObsEnvError::InvalidConfig {
+                message: format!("--dry-run is not supported by {}", action_name(action)),
+            }
+            .into())
+        }
+    };
+    let plan = dry_run.plan();
+    if plan.is_empty() {
+        writeln!(out, "No repository would change.")?;
+    } else {
+        write!(out, "{plan}")?;
+    }
+    result
+}
+
+/// Execute the action selected in `config`, returning the report of the
+/// "Setup" action.
+fn run_action<T, W, E>(
+    config: &T,
+    out: &mut W,
+    progress_events: Option<Arc<ProgressEvents<E>>>,
+    timings: Arc<Timings>,
+    outcomes: Arc<RepoOutcomes>,
+) -> Result<Option<SetupReport>, Box<dyn Error>>
+where
+    T: ManageObsEnvCli,
+    W: Write,
+    E: Write + Send + 'static,
+{
+    log::info!("Running manage obs env...");
+
+    if config.get_validate_only() && matches!(config.get_action()?, Action::ApplyManifest) {
+        validate_manifest(
+            Path::new(config.get_manifest_path().unwrap_or_default()),
+            out,
+        )?;
+        return Ok(None);
+    }
+
+    // The environment cannot be built from a configuration with errors,
+    // which are listed instead.
+    if matches!(config.get_action()?, Action::ValidateConfig) {
+        let checks = check_configuration(config)?;
+        if checks.iter().any(|check| !check.passed) {
+            return write_config_checks(config, out, &checks).map(|_| None);
+        }
+    }
+
+    let setup_manifest = match (config.get_action()?, config.get_setup_manifest()) {
+        (Action::Setup, Some(path)) => Some(EnvironmentManifest::load(Path::new(path))?),
+        _ => None,
+    };
+
+    let mut resume = resume_state(config)?;
+
+    let mut builder = ObservingEnvironment::builder()
+        .destination(&config.get_env_path())
+        .base_branch(config.get_base_env_source_repo())
+        .abort_in_progress(config.get_abort_in_progress())
+        .offline(config.get_offline())
+        .refresh_base_cache(config.get_refresh_base_cache())
+        .base_versions_ttl(config.get_base_cache_ttl())
+        .lock_timeout(config.get_lock_timeout())
+        .existing_clones(config.get_existing_clones())
+        .on_error(config.get_on_error())
+        .deep_check(config.get_deep())
+        .fresh(config.get_fresh())
+        .backup(config.get_backup())
+        .min_free_space(config.get_min_free_space())
+        .identity(
+            config.get_commit_identity().0,
+            config.get_commit_identity().1,
+        )
+        .trust_env_path(true)
+        .shared_access(config.get_shared_access()?)
+        .base_env_revision(config.get_base_env_revision()?)
+        .timings(timings);
+    let inner: Option<Box<dyn ObsEnvObserver>> = if let Some(progress_events) = progress_events {
+        Some(Box::new(progress_events))
+    } else if config.get_progress() {
+        Some(Box::new(ProgressBar))
+    } else {
+        None
+    };
+    builder = builder.observer(RecordOutcomes { outcomes, inner });
+    if let Some((repo_specs, source)) = config.get_repositories()? {
+        builder = builder.repository_specs(repo_specs, source);
+    }
+    if let Some(manifest) = &setup_manifest {
+        builder = builder.manifest_repositories(manifest);
+    }
+    for (repo_name, repo_override) in config.get_overrides()? {
+        builder = builder.repo_override(&repo_name, repo_override);
+    }
+    for (repo_name, owner) in config.get_forks()?.iter() {
+        builder = builder.fork(repo_name, owner);
+    }
+    for (repo_name, version) in config.get_version_overrides()?.iter() {
+        builder = builder.version_override(repo_name, version);
+    }
+    if let Some(patterns) = config.get_ticket_patterns()? {
+        builder = builder.ticket_patterns(patterns);
+    }
+    if let Some(jobs) = config.get_jobs() {
+        builder = builder.jobs(jobs);
+    }
+    builder = builder.retry(config.get_retry_policy());
+    if let Some(clone_depth) = config.get_clone_depth() {
+        builder = builder.clone_depth(clone_depth);
+    }
+    if let Some(clone_filter) = config.get_clone_filter() {
+        builder = builder.clone_filter(clone_filter);
+    }
+    if let Some(object_store_path) = config.get_object_store_path() {
+        builder = builder.object_store(object_store_path);
+    }
+    if let Some(base_env_source) = config.get_base_env_local_source() {
+        builder = builder.base_env_source(base_env_source);
+    }
+    match resume.as_ref().filter(|_| config.get_resume()) {
+        // The repositories pending are among those selected by the run
+        // resumed, which had the same arguments.
+        Some(resume) => {
+            for repo_name in resume.pending() {
+                builder = builder.only(repo_name);
+            }
+        }
+        None => {
+            for repo_name in config.get_only() {
+                builder = builder.only(repo_name);
+            }
+            for group in config.get_groups() {
+                builder = builder.group(group);
+            }
+        }
+    }
+    for repo_name in config.get_excluded() {
+        builder = builder.exclude(repo_name);
+    }
+    if let Some(eups_tag) = config.get_eups_tag() {
+        builder = builder.eups(Eups::new(eups_tag));
+    }
+    if let Some(python) = config.get_develop_install() {
+        builder = builder.pip_install(PipInstall::new(python));
+    }
+    let dry_run = config.get_dry_run().then(|| {
+        DryRunBackend::new(Git2Backend)
+            .pass_through(Path::new(&config.get_env_path()).join(OBS_ENV_DIR))
+    });
+    if let Some(dry_run) = &dry_run {
+        builder = builder.backend(dry_run.clone());
+    }
+    let obs_env = builder.build()?;
+
+    let action = config.get_action()?;
+    if let Some(dry_run) = &dry_run {
+        plan_action(
+            config,
+            out,
+            &obs_env,
+            action,
+            setup_manifest.as_ref(),
+            dry_run,
+        )?;
+        return Ok(None);
+    }
+    let env_path = config.get_env_path();
+    if config.get_env_root().is_some()
+        && !matches!(
+            action,
+            Action::ListEnvs | Action::Deactivate | Action::PruneEnvs
+        )
+        && !environments::can_hold_environment(Path::new(&env_path))
+    {
+        return Err(ObsEnvError::NotAnEnvironment {
+            path: env_path.into(),
+        }
+        .into());
+    }
+    // Taking the lock creates the environment path.
+    let path_existed = Path::new(&env_path).is_dir();
+    let lock = if action.modifies_environment()
+        || (matches!(action, Action::Doctor) && config.get_repair())
+        || (matches!(action, Action::ListStaleBranches) && config.get_delete())
+    {
+        Some(obs_env.lock()?)
+    } else {
+        None
+    };
+
+    let before = lock.as_ref().map(|_| obs_env.head_commits());
+    let mut result = execute_action(
+        config,
+        out,
+        &obs_env,
+        action,
+        setup_manifest.as_ref(),
+        resume.as_mut(),
+        path_existed,
+    );
+    // Nothing is recorded when the action failed before reaching the
+    // repositories, which leaves the state of the last failure as it was.
+    if let Some(resume) = resume.filter(|resume| !resume.repos.is_empty()) {
+        if let Err(error) = resume.save(&resume_path(config)) {
+            log::warn!("Could not write the resume state: {}", report(&error));
+        }
+    }
+    if let Some(before) = before {
+        record_audit(&obs_env, action, &before, &result);
+        if result.is_ok() {
+            if let Err(error) = run_hook(config, &obs_env, action, &before) {
+                log::error!("{}", report(&error));
+                result = Err(error.into());
+            }
+        }
+    }
+    result
+}
+
+/// Resume state of the environment, `.obs_env/resume.json`.
+fn resume_path<T: ManageObsEnvCli>(config: &T) -> PathBuf {
+    Path::new(&config.get_env_path())
+        .join(OBS_ENV_DIR)
+        .join(RESUME_FILE)
+}
+
+/// State the outcomes of the repositories of the action are recorded in,
+/// if it is "Setup" or "Reset": that of the run that failed with --resume,
+/// after checking it had the same arguments, and a new one otherwise.
+fn resume_state<T: ManageObsEnvCli>(config: &T) -> Result<Option<ResumeState>, Box<dyn Error>> {
+    let action = config.get_action()?;
+    let name = action_name(action);
+    match action {
+        Action::Setup | Action::Reset => {}
+        _ if config.get_resume() => {
+            return Err(ObsEnvError::InvalidConfig {
+                message: format!("--resume is not supported by {name}"),
+            }
+            .into())
+        }
+        _ => return Ok(None),
+    }
+    let mut parameters = BTreeMap::from([
+        ("base_branch", config.get_base_env_source_repo().to_owned()),
+        (
+            "base_env_source",
+            config
+                .get_base_env_local_source()
+                .unwrap_or_default()
+                .to_owned(),
+        ),
+        (
+            "repos_file",
+            config.get_repos_file().unwrap_or_default().to_owned(),
+        ),
+        (
+            "config",
+            config.get_config_file().unwrap_or_default().to_owned(),
+        ),
+        (
+            "clone_depth",
+            config
+                .get_clone_depth()
+                .map(|depth| depth.to_string())
+                .unwrap_or_default(),
+        ),
+        ("repository", config.get_repository_names().join(",")),
+        ("only", config.get_only().join(",")),
+        ("group", config.get_groups().join(",")),
+        ("exclude", config.get_excluded().join(",")),
+        ("fork", join_pairs(&config.get_forks()?)),
+        (
+            "version_override",
+            join_pairs(&config.get_version_overrides()?),
+        ),
+    ]);
+    match action {
+        Action::Setup => {
+            let manifest = config.get_setup_manifest().unwrap_or_default();
+            parameters.insert("from_manifest", manifest.to_owned());
+            let existing_clones = format!("{:?}", config.get_existing_clones());
+            parameters.insert("existing_clones", existing_clones.to_lowercase());
+        }
+        Action::Reset => {
+            parameters.insert("revision", config.get_base_env_revision()?.to_string());
+            parameters.insert("locked", config.get_locked().to_string());
+        }
+        _ => {}
+    }
+    let parameters: BTreeMap<String, String> = parameters
+        .into_iter()
+        .map(|(name, value)| (name.to_owned(), value))
+        .collect();
+    if !config.get_resume() {
+        return Ok(Some(ResumeState::new(&name, parameters)));
+    }
+    match ResumeState::load(&resume_path(config))? {
+        Some(resume) => {
+            resume.check(&name, &parameters)?;
+            Ok(Some(resume))
+        }
+        None => Err(ObsEnvError::InvalidConfig {
+            message: format!(
+                "There is no failed {name} to resume in {}",
+                config.get_env_path()
+            ),
+        }
+        .into()),
+    }
+}
+
+/// `pairs` as `key=value`, separated by commas.
+fn join_pairs(pairs: &BTreeMap<String, String>) -> String {
+    pairs
+        .iter()
+        .map(|(key, value)| format!("{key}={value}"))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Run the hook of the configuration for `action`, if any, once it changed
+/// the repositories of `obs_env` from the `before` commits.
+fn run_hook<T: ManageObsEnvCli>(
+    config: &T,
+    obs_env: &ObservingEnvironment,
+    action: &Action,
+    before: &BTreeMap<String, Option<String>>,
+) -> Result<(), ObsEnvError> {
+    let hooks = config
+        .get_hooks()
+        .map_err(|error| ObsEnvError::InvalidConfig {
+            message: error.to_string(),
+        })?;
+    let (name, command) = match action {
+        Action::Setup => ("post_setup", hooks.post_setup),
+        Action::Reset => ("post_reset", hooks.post_reset),
+        Action::CheckoutBranch | Action::CheckoutBranchAll | Action::CheckoutVersion => {
+            ("post_checkout", hooks.post_checkout)
+        }
+        Action::ApplyManifest | Action::Restore => {
+            ("post_apply_manifest", hooks.post_apply_manifest)
+        }
+        _ => return Ok(()),
+    };
+    match command {
+        Some(command) => hooks::run(
+            name,
+            &command,
+            &action_name(action),
+            &config.get_env_path(),
+            before,
+            &obs_env.head_commits(),
+        ),
+        None => Ok(()),
+    }
+}
+
+/// Execute `action` on `obs_env`, returning the report of a setup, and
+/// recording the outcomes of the repositories of a setup or reset in
+/// `resume`.
+fn execute_action<T, W>(
+    config: &T,
+    out: &mut W,
+    obs_env: &ObservingEnvironment,
+    action: &Action,
+    setup_manifest: Option<&EnvironmentManifest>,
+    resume: Option<&mut ResumeState>,
+    path_existed: bool,
+) -> Result<Option<SetupReport>, Box<dyn Error>>
+where
+    T: ManageObsEnvCli,
+    W: Write,
+{
+    match action {
+        Action::Setup => {
+            log::info!("Executing Setup...");
+
+            log::debug!("Creating path...");
+            obs_env.create_path()?;
+            if path_existed && matches!(config.get_output_format(), OutputFormat::Text) {
+                writeln!(out, "Repositories in {}:", config.get_env_path())?;
+                writeln!(out, "{}", obs_env.survey())?;
+            }
+
+            log::debug!("Cloning repositories...");
+            let setup_report = match setup_manifest {
+                Some(manifest) => obs_env.clone_repositories_at(manifest),
+                None => obs_env.clone_repositories(),
+            };
+            match config.get_output_format() {
+                OutputFormat::Text => {
+                    writeln!(out, "The following repositories were cloned:")?;
+                    for (_, path) in setup_report.cloned() {
+                        writeln!(out, "{}", path.display())?;
+                    }
+                    if setup_report.updated().next().is_some() {
+                        writeln!(out, "The following repositories were updated:")?;
+                        for (_, path) in setup_report.updated() {
+                            writeln!(out, "{}", path.display())?;
+                        }
+                    }
+                    for (_, error) in setup_report.failures() {
+                        log::error!("{}", report(error));
+                    }
+                    write_excluded(out, obs_env)?;
+                }
+                OutputFormat::Json => {
+                    serde_json::to_writer_pretty(&mut *out, &setup_report)?;
+                    writeln!(out)?;
+                }
+            }
+            let changed: Vec<&str> = setup_report
+                .cloned()
+                .chain(setup_report.updated())
+                .map(|(repo_name, _)| repo_name)
+                .collect();
+            after_update(obs_env, &changed);
+            if let Some(resume) = resume {
+                resume.record_setup(&setup_report);
+            }
+            if setup_report.is_success() {
+                write_metadata(obs_env, action);
+                write_lock_file(obs_env);
+            }
+            return Ok(Some(setup_report));
+        }
+        Action::Update => {
+            let update_report = obs_env.update_repositories();
+            match config.get_output_format() {
+                OutputFormat::Text => {
+                    writeln!(out, "The following repositories were updated:")?;
+                    for (_, path) in update_report.updated() {
+                        writeln!(out, "{}", path.display())?;
+                    }
+                    for (_, error) in update_report.failures() {
+                        log::error!("{}", report(error));
+                    }
+                    write_excluded(out, obs_env)?;
+                }
+                OutputFormat::Json => {
+                    serde_json::to_writer_pretty(&mut *out, &update_report)?;
+                    writeln!(out)?;
+                }
+            }
+            let updated: Vec<&str> = update_report
+                .updated()
+                .map(|(repo_name, _)| repo_name)
+                .collect();
+            after_update(obs_env, &updated);
+            if update_report.is_success() {
+                write_metadata(obs_env, action);
+                write_lock_file(obs_env);
+            }
+            return Ok(Some(update_report));
+        }
+        Action::WriteSetupScript => {
+            let path = obs_env.write_setup_script()?;
+            writeln!(out, "Wrote {}", path.display())?;
+        }
+        Action::WriteTagFile => {
+            let versions = obs_env
+                .get_current_env_versions()
+                .into_iter()
+                .filter_map(|(repo_name, version)| version.ok().map(|version| (repo_name, version)))
+                .collect();
+            let (content, omitted) = eups::tag_file(
+                config.get_eups_tag_name(),
+                &versions,
+                &config.get_eups_products()?,
+            );
+            for repo_name in omitted {
+                log::warn!("{repo_name} has no EUPS product, leaving it out of the tag file.");
+            }
+            let path = config.get_tag_file();
+            write_atomically(Path::new(&path), &content)?;
+            writeln!(out, "Wrote {path}")?;
+        }
+        Action::WriteMetrics => {
+            let base_versions = obs_env
+                .get_base_env_versions_cached(obs_env.get_base_env_branch())
+                .inspect_err(|error| {
+                    log::warn!(
+                        "Cannot compare the repositories with the base environment: {}",
+                        report(error)
+                    )
+                })
+                .ok();
+            let metrics = obs_env.metrics(base_versions.as_ref().map(|base| &base.versions));
+            let path = config.get_metrics_file().unwrap_or_default();
+            write_atomically(Path::new(path), &metrics.to_prometheus())?;
+            writeln!(out, "Wrote {path}")?;
+        }
+        Action::Serve => {
+            let address = config.get_listen_address();
+            let listener = TcpListener::bind(address)
+                .map_err(|error| ObsEnvError::io(address, "listen on", error))?;
+            log::info!(
+                "Serving the status of {} on http://{address}/",
+                config.get_env_path()
+            );
+            StatusServer::new(obs_env).serve(listener, signal::shutdown_on_signal())?;
+        }
+        Action::Teardown => {
+            log::info!("Removing repositories from the environment...");
+            let mut failed = Vec::new();
+            for (repo_name, result) in obs_env.teardown(config.get_force()) {
+                match result {
+                    Ok(()) => writeln!(out, "Removed {repo_name}")?,
+                    Err(error) => {
+                        log::error!("{}", report(&error));
+                        failed.push(repo_name);
+                    }
+                }
+            }
+            if !failed.is_empty() {
+                return Err(ObsEnvError::PartialFailure {
+                    operation: "tear down".to_owned(),
+                    failed,
+                }
+                .into());
+            }
+        }
+        Action::PrintConfig => match config.get_output_format() {
+            OutputFormat::Text => {
+                writeln!(out, "{}", obs_env.summarize())?;
+                show_metadata(out, obs_env)?;
+            }
+            OutputFormat::Json => {
+                let metadata = obs_env.read_metadata().unwrap_or_else(|error| {
+                    log::warn!("{}", report(&error));
+                    None
+                });
+                let modified = metadata
+                    .as_ref()
+                    .map(|metadata| obs_env.modified_since(metadata))
+                    .unwrap_or_default();
+                let summary = serde_json::json!({
+                    "summary": obs_env.summarize(),
+                    "metadata": metadata,
+                    "modified_outside": modified,
+                });
+                serde_json::to_writer_pretty(&mut *out, &summary)?;
+                writeln!(out)?;
+            }
+        },
+        Action::ListRepos => {
+            writeln!(
+                out,
+                "Repositories from {}:",
+                obs_env.get_repositories_source()
+            )?;
+            for repo_spec in obs_env.get_repositories() {
+                write!(
+                    out,
+                    "{}: {}",
+                    repo_spec.name,
+                    obs_env
+                        .get_repository_url(&repo_spec.name)
+                        .unwrap_or_default()
+                )?;
+                if let Some(default_branch) = &repo_spec.default_branch {
+                    write!(out, " ({default_branch})")?;
+                }
+                if !repo_spec.groups.is_empty() {
+                    write!(out, " [{}]", repo_spec.groups.join(", "))?;
+                }
+                if let Some(path) = &repo_spec.path {
+                    write!(out, " in {path}")?;
+                }
+                writeln!(out)?;
+            }
+        }
+        Action::Reset => {
+            log::info!("Resetting Observing environment...");
+            let result = if config.get_locked() {
+                reset_locked(obs_env, &config.get_env_path(), out, resume)
+            } else {
+                reset(obs_env, out, resume)
+            };
+            write_excluded(out, obs_env)?;
+            let present: Vec<String> = obs_env
+                .repos()
+                .filter(|repo| repo.exists())
+                .map(|repo| repo.name().to_owned())
+                .collect();
+            after_update(
+                obs_env,
+                &present.iter().map(String::as_str).collect::<Vec<_>>(),
+            );
+            result?;
+            write_metadata(obs_env, action);
+            if !config.get_locked() {
+                write_lock_file(obs_env);
+            }
+        }
+        Action::ShowCurrentVersions => {
+            if config.get_fetch_first() {
+                for (_, result) in obs_env.fetch_repositories() {
+                    if let Err(error) = result {
+                        log::error!("{}", report(&error));
+                    }
+                }
+            }
+            log::info!("Current environment versions:");
+            let current_versions = obs_env.get_current_env_versions();
+            match config.get_output_format() {
+                OutputFormat::Text => {
+                    for (name, version) in current_versions.iter() {
+                        match version {
+                            Ok(version) => {
+                                let mut details = vec![version.describe_upstream()];
+                                if version.dirty {
+                                    details.push("dirty".to_owned());
+                                }
+                                if version.untracked > 0 {
+                                    details.push(format!("{} untracked", version.untracked));
+                                }
+                                writeln!(out, "{name}: {version} ({})", details.join(", "))?
+                            }
+                            Err(ObsEnvError::EmptyRepository { .. }) => {
+                                writeln!(out, "{name}: empty repository")?
+                            }
+                            Err(error) => writeln!(out, "{name}: {error}")?,
+                        }
+                    }
+                    write_excluded(out, obs_env)?;
+                }
+                OutputFormat::Json => {
+                    let versions: BTreeMap<String, serde_json::Value> = current_versions
+                        .into_iter()
+                        .map(|(name, version)| {
+                            let version = match version {
+                                Ok(version) => serialized(serde_json::to_value(version)),
+                                Err(error) => serde_json::json!({ "error": report(&error) }),
+                            };
+                            (name, version)
+                        })
+                        .collect();
+                    serde_json::to_writer_pretty(&mut *out, &versions)?;
+                    writeln!(out)?;
+                }
+            }
+        }
+        Action::ShowOriginalVersions => {
+            match obs_env.get_base_env_versions_cached(obs_env.get_base_env_branch()) {
+                Ok(base_env_versions) => {
+                    let source = obs_env.describe_base_env_source(obs_env.get_base_env_branch());
+                    let source = match base_env_versions.cache_age {
+                        Some(age) => format!("{source}, cached {}s ago", age.as_secs()),
+                        None => source,
+                    };
+                    match config.get_output_format() {
+                        OutputFormat::Text => {
+                            writeln!(out, "Base environment versions ({source}):")?;
+                            if let Some(commit) = &base_env_versions.commit {
+                                writeln!(out, "Base environment commit: {commit}")?;
+                            }
+                            for (name, version) in base_env_versions.versions.iter() {
+                                writeln!(out, "{name}: {version}")?;
+                            }
+                        }
+                        OutputFormat::Json => {
+                            let versions = serde_json::json!({
+                                "branch": obs_env.get_base_env_branch(),
+                                "source": source,
+                                "commit": base_env_versions.commit,
+                                "cache_age_secs": base_env_versions
+                                    .cache_age
+                                    .map(|age| age.as_secs()),
+                                "versions": base_env_versions.versions,
+                            });
+                            serde_json::to_writer_pretty(&mut *out, &versions)?;
+                            writeln!(out)?;
+                        }
+                    }
+                }
+                // The json output has to end with the error for the caller
+                // to tell the versions could not be read.
+                Err(error) if matches!(config.get_output_format(), OutputFormat::Json) => {
+                    return Err(error.into())
+                }
+                Err(error) => {
+                    log::error!("{}", report(&error));
+                }
+            }
+        }
+        Action::Diff => {
+            if config.get_fetch_first() {
+                for (_, result) in obs_env.fetch_repositories() {
+                    if let Err(error) = result {
+                        log::error!("{}", report(&error));
+                    }
+                }
+            }
+            let mut deviations: BTreeMap<String, BaseDeviation> = BTreeMap::new();
+            let mut failed = Vec::new();
+            for (repo_name, result) in obs_env.base_deviations()? {
+                match result {
+                    Ok(deviation) => {
+                        deviations.insert(repo_name, deviation);
+                    }
+                    Err(error) => {
+                        log::error!("{}", report(&error));
+                        failed.push(repo_name);
+                    }
+                }
+            }
+            match config.get_output_format() {
+                OutputFormat::Text => {
+                    if deviations.is_empty() && failed.is_empty() {
+                        writeln!(out, "Every repository is at its base version.")?;
+                    }
+                    for deviation in deviations.values() {
+                        writeln!(out, "{deviation}")?;
+                    }
+                    write_excluded(out, obs_env)?;
+                }
+                OutputFormat::Json => {
+                    serde_json::to_writer_pretty(&mut *out, &deviations)?;
+                    writeln!(out)?;
+                }
+            }
+            if !failed.is_empty() {
+                return Err(ObsEnvError::PartialFailure {
+                    operation: "compare with the base environment".to_owned(),
+                    failed,
+                }
+                .into());
+            }
+        }
+        Action::Status => {
+            if config.get_fetch_first() {
+                for (_, result) in obs_env.fetch_repositories() {
+                    if let Err(error) = result {
+                        log::error!("{}", report(&error));
+                    }
+                }
+            }
+            let mut working_trees: BTreeMap<String, WorkingTree> = BTreeMap::new();
+            let mut failed = Vec::new();
+            for (repo_name, result) in obs_env.working_trees() {
+                match result {
+                    Ok(working_tree) => {
+                        working_trees.insert(repo_name, working_tree);
+                    }
+                    Err(error) => {
+                        log::error!("{}", report(&error));
+                        failed.push(repo_name);
+                    }
+                }
+            }
+            match config.get_output_format() {
+                OutputFormat::Text => {
+                    for working_tree in working_trees.values() {
+                        writeln!(out, "{working_tree}")?;
+                        for file in working_tree.modified_files.iter() {
+                            writeln!(out, "  modified: {file}")?;
+                        }
+                    }
+                    write_excluded(out, obs_env)?;
+                }
+                OutputFormat::Json => {
+                    serde_json::to_writer_pretty(&mut *out, &working_trees)?;
+                    writeln!(out)?;
+                }
+            }
+            let dirty: Vec<String> = working_trees
+                .values()
+                .filter(|working_tree| working_tree.is_dirty())
+                .map(|working_tree| working_tree.to_string())
+                .collect();
+            if !dirty.is_empty() {
+                return Err(ObsEnvError::VerificationFailed {
+                    path: PathBuf::from(config.get_env_path()),
+                    problems: dirty,
+                }
+                .into());
+            }
+            if !failed.is_empty() {
+                return Err(ObsEnvError::PartialFailure {
+                    operation: "read the status".to_owned(),
+                    failed,
+                }
+                .into());
+            }
+        }
+        Action::Export => {
+            let mut manifest = match config.get_include_dirty_files() {
+                true => obs_env.get_manifest_with_dirty_files(),
+                false => obs_env.get_manifest(),
+            };
+            if manifest.overrides.is_empty() {
+                // Overrides given for the last reset only, on the command
+                // line, are known from its metadata.
+                if let Ok(Some(metadata)) = obs_env.read_metadata() {
+                    manifest.overrides = metadata.manifest.overrides;
+                }
+            }
+            if config.get_include_python_env() {
+                manifest.python_env = Some(PythonEnvironment::capture(
+                    config.get_python(),
+                    config.get_conda_command(),
+                )?);
+            }
+            match config.get_manifest_path() {
+                Some(path) => {
+                    manifest.save(Path::new(path))?;
+                    writeln!(out, "Wrote {path}")?;
+                }
+                None => write!(out, "{}", manifest.to_toml())?,
+            }
+        }
+        Action::ApplyManifest => {
+            let mut manifest = EnvironmentManifest::load(Path::new(
+                config.get_manifest_path().unwrap_or_default(),
+            ))?;
+            if config.get_locked() {
+                manifest = obs_env
+                    .read_lock_file()?
+                    .pin(&config.get_env_path(), Some(&manifest))?;
+            }
+            apply_manifest(config, out, obs_env, action, &manifest)?;
+        }
+        Action::Snapshot => {
+            let snapshot = obs_env.snapshot(config.get_snapshot_name())?;
+            match config.get_output_format() {
+                OutputFormat::Text => {
+                    writeln!(out, "Wrote {}", snapshot.path.display())?;
+                    writeln!(out, "{snapshot}")?;
+                    for repo_name in snapshot.dirty() {
+                        writeln!(out, "{repo_name}: local changes are not in the snapshot")?;
+                    }
+                }
+                OutputFormat::Json => {
+                    serde_json::to_writer_pretty(&mut *out, &snapshot)?;
+                    writeln!(out)?;
+                }
+            }
+            write_excluded(out, obs_env)?;
+        }
+        Action::Restore => {
+            let snapshot = obs_env.load_snapshot(config.get_snapshot_name())?;
+            writeln!(out, "Restoring {snapshot}")?;
+            apply_manifest(config, out, obs_env, action, &snapshot.manifest)?;
+        }
+        Action::VerifyLock => {
+            let drift = obs_env.lock_drift()?;
+            if drift.is_empty() {
+                writeln!(
+                    out,
+                    "The environment matches {}.",
+                    obs_env.lock_file_path().display()
+                )?;
+            }
+            for drift in drift.iter() {
+                writeln!(out, "{drift}")?;
+            }
+            if !drift.is_empty() {
+                return Err(ObsEnvError::VerificationFailed {
+                    path: obs_env.lock_file_path(),
+                    problems: drift.iter().map(|drift| drift.to_string()).collect(),
+                }
+                .into());
+            }
+        }
+        Action::Watch => {
+            let source =
+                config
+                    .get_manifest_source()
+                    .ok_or_else(|| ObsEnvError::MissingArgument {
+                        action: format!("{action:?}"),
+                        argument: "--manifest-source".to_owned(),
+                    })?;
+            log::info!(
+                "Watching {source} every {}s...",
+                config.get_watch_interval().as_secs()
+            );
+            let report = Watcher::new(obs_env, source, config.get_watch_interval())
+                .run(signal::shutdown_on_signal());
+            writeln!(out, "{report}")?;
+        }
+        Action::CompareManifests => {
+            let load =
+                |path: Option<&str>| EnvironmentManifest::load(Path::new(path.unwrap_or_default()));
+            let differences = obs_env.compare_manifests(
+                &load(config.get_from_manifest())?,
+                &load(config.get_to_manifest())?,
+                config.get_commits(),
+            );
+            match config.get_output_format() {
+                OutputFormat::Text => {
+                    if differences.is_empty() {
+                        writeln!(out, "The manifests have the same versions.")?;
+                    }
+                    for difference in differences.iter() {
+                        writeln!(out, "{difference}")?;
+                    }
+                }
+                OutputFormat::Json => {
+                    serde_json::to_writer_pretty(&mut *out, &differences)?;
+                    writeln!(out)?;
+                }
+            }
+        }
+        Action::SearchCommits => {
+            let query = config.get_commit_query()?;
+            let mut matches = BTreeMap::new();
+            for (repo_name, result) in obs_env.search_commits(&query, config.get_fetch_first()) {
+                match result {
+                    Ok(commits) if commits.is_empty() => {}
+                    Ok(commits) => {
+                        matches.insert(repo_name, commits);
+                    }
+                    Err(error) => log::error!("{}", report(&error)),
+                }
+            }
+            match config.get_output_format() {
+                OutputFormat::Text => {
+                    if matches.is_empty() {
+                        writeln!(out, "No commit matches {}.", query.pattern)?;
+                    }
+                    for (repo_name, commits) in matches.iter() {
+                        writeln!(out, "{repo_name}:")?;
+                        for commit in commits {
+                            writeln!(out, "  {commit}")?;
+                        }
+                    }
+                }
+                OutputFormat::Json => {
+                    serde_json::to_writer_pretty(&mut *out, &matches)?;
+                    writeln!(out)?;
+                }
+            }
+        }
+        Action::CompareConda => {
+            let versions = obs_env
+                .get_current_env_versions()
+                .into_iter()
+                .filter_map(|(repo_name, version)| version.ok().map(|version| (repo_name, version)))
+                .collect();
+            let packages = conda::list_packages(config.get_conda_command())?;
+            let comparison = conda::compare(&versions, &packages, &config.get_conda_packages()?);
+            match config.get_output_format() {
+                OutputFormat::Text => writeln!(out, "{comparison}")?,
+                OutputFormat::Json => {
+                    serde_json::to_writer_pretty(&mut *out, &comparison)?;
+                    writeln!(out)?;
+                }
+            }
+        }
+        Action::Doctor if config.get_repair() => {
+            writeln!(out, "Environment path {}", obs_env.check_path())?;
+            let repairs = obs_env.repair_repositories(config.get_force());
+            if repairs.is_empty() {
+                writeln!(out, "No damaged repository found.")?;
+            }
+            let mut recloned = Vec::new();
+            let mut failed = Vec::new();
+            for (diagnosis, result) in repairs.iter() {
+                writeln!(out, "{diagnosis}")?;
+                match result {
+                    Ok(repair) => {
+                        writeln!(out, "{}: {repair}", diagnosis.name)?;
+                        if let Repair::Recloned { .. } = repair {
+                            recloned.push(diagnosis.name.as_str());
+                        }
+                    }
+                    Err(error) => {
+                        log::error!("{}", report(error));
+                        failed.push(diagnosis.name.clone());
+                    }
+                }
+            }
+            after_update(obs_env, &recloned);
+            if !failed.is_empty() {
+                return Err(ObsEnvError::PartialFailure {
+                    operation: "repair".to_owned(),
+                    failed,
+                }
+                .into());
+            }
+        }
+        Action::Doctor => {
+            writeln!(out, "Environment path {}", obs_env.check_path())?;
+            show_metadata(out, obs_env)?;
+            let diagnoses: Vec<RepoDiagnosis> = obs_env
+                .diagnose_repositories()
+                .into_iter()
+                .filter(|diagnosis| !diagnosis.is_healthy() || diagnosis.is_blocked())
+                .collect();
+            if !diagnoses.iter().any(|diagnosis| !diagnosis.is_healthy()) {
+                writeln!(out, "No damaged repository found.")?;
+            }
+            for diagnosis in diagnoses.iter() {
+                writeln!(out, "{diagnosis}")?;
+            }
+            if diagnoses.iter().any(|diagnosis| !diagnosis.is_healthy()) {
+                let deep = if config.get_deep() { " --deep" } else { "" };
+                writeln!(out, "Run Doctor{deep} with --repair to repair them.")?;
+            }
+        }
+        Action::ListEnvs => {
+            let env_root = config.get_env_root().unwrap_or_default();
+            let environments = environments::list(Path::new(env_root))?;
+            if environments.is_empty() {
+                writeln!(out, "No environment found in {env_root}.")?;
+            }
+            let active = active_environment(config, &environments);
+            for environment in environments {
+                if Some(&environment.path) == active.as_ref() {
+                    writeln!(out, "{environment} (active)")?;
+                } else {
+                    writeln!(out, "{environment}")?;
+                }
+            }
+        }
+        Action::PruneEnvs => {
+            let env_root = config.get_env_root().unwrap_or_default();
+            let environments = environments::list(Path::new(env_root))?;
+            let active = active_environment(config, &environments);
+            let selected = config.get_prune_filter().select(
+                environments,
+                active.as_deref(),
+                timestamp() as u64,
+            );
+            if selected.is_empty() {
+                writeln!(out, "No environment to prune in {env_root}.")?;
+            }
+            let mut reclaimed = 0;
+            for environment in selected {
+                match prune_environment(config, &environment) {
+                    Ok(size) => {
+                        let verb = if config.get_force() {
+                            "Removed"
+                        } else {
+                            "Would remove"
+                        };
+                        writeln!(out, "{verb} {environment} ({} MiB)", size / MIB)?;
+                        reclaimed += size;
+                    }
+                    Err(error) => {
+                        log::error!("Keeping {}: {}", environment.name, report(&error))
+                    }
+                }
+            }
+            if config.get_force() {
+                writeln!(out, "Reclaimed {} MiB.", reclaimed / MIB)?;
+            } else {
+                writeln!(
+                    out,
+                    "Would reclaim {} MiB, run with --force to remove them.",
+                    reclaimed / MIB
+                )?;
+            }
+        }
+        Action::Activate => {
+            if let Err(error) = obs_env.verify() {
+                if !config.get_force() {
+                    return Err(error.into());
+                }
+                log::warn!("{}; activating it anyway.", report(&error));
+            }
+            let link = config.get_link().unwrap_or_default();
+            let activation =
+                activation::activate(Path::new(&link), Path::new(&config.get_env_path()))?;
+            writeln!(out, "{activation}")?;
+        }
+        Action::Deactivate => {
+            let link = config.get_link().unwrap_or_default();
+            writeln!(out, "{}", activation::deactivate(Path::new(&link))?)?;
+        }
+        Action::ListBackups => {
+            let mut found = false;
+            for (_, backups) in obs_env.list_backups() {
+                match backups {
+                    Ok(backups) => {
+                        for backup in backups {
+                            found = true;
+                            writeln!(out, "{backup}")?;
+                        }
+                    }
+                    Err(error) => log::error!("{}", report(&error)),
+                }
+            }
+            if !found {
+                writeln!(out, "No backup branch found.")?;
+            }
+        }
+        Action::ShowHistory => {
+            let entries = obs_env.history(&config.get_history_filter())?;
+            if entries.is_empty() {
+                writeln!(out, "No recorded operation found.")?;
+            }
+            for entry in entries {
+                writeln!(out, "{entry}")?;
+            }
+        }
+        Action::DropBackups => {
+            for result in obs_env.drop_backups(config.get_backup_age()) {
+                match result {
+                    Ok(backup) => writeln!(out, "Dropped {backup}")?,
+                    Err(error) => log::error!("{}", report(&error)),
+                }
+            }
+        }
+        Action::ListStaleBranches => {
+            let delete = config.get_delete();
+            let mut stale = Vec::new();
+            for (_, result) in obs_env.stale_branches(delete) {
+                match result {
+                    Ok(branches) if branches.is_empty() => {}
+                    Ok(branches) => stale.push(branches),
+                    Err(error) => log::error!("{}", report(&error)),
+                }
+            }
+            match config.get_output_format() {
+                OutputFormat::Text => {
+                    for branches in stale.iter() {
+                        writeln!(out, "{branches}")?;
+                    }
+                    let count: usize = stale.iter().map(StaleBranches::len).sum();
+                    if count == 0 {
+                        writeln!(out, "No stale branch found.")?;
+                    } else if delete {
+                        writeln!(
+                            out,
+                            "Deleted {count} branches in {} repositories.",
+                            stale.len()
+                        )?;
+                    } else {
+                        writeln!(
+                            out,
+                            "Would delete {count} branches in {} repositories, run with --delete to delete them.",
+                            stale.len()
+                        )?;
+                    }
+                }
+                OutputFormat::Json => {
+                    serde_json::to_writer_pretty(&mut *out, &stale)?;
+                    writeln!(out)?;
+                }
+            }
+        }
+        Action::PruneRemotes => {
+            let (pruned, result) = prune_remotes(obs_env)?;
+            match config.get_output_format() {
+                OutputFormat::Text => {
+                    for remotes in pruned.iter().filter(|remotes| !remotes.refs.is_empty()) {
+                        writeln!(out, "{remotes}")?;
+                    }
+                    let count: usize = pruned.iter().map(|remotes| remotes.refs.len()).sum();
+                    if count == 0 {
+                        writeln!(out, "No stale remote-tracking branch found.")?;
+                    } else {
+                        writeln!(
+                            out,
+                            "Pruned {count} remote-tracking branches in {} repositories.",
+                            pruned
+                                .iter()
+                                .filter(|remotes| !remotes.refs.is_empty())
+                                .count()
+                        )?;
+                    }
+                }
+                OutputFormat::Json => {
+                    serde_json::to_writer_pretty(&mut *out, &pruned)?;
+                    writeln!(out)?;
+                }
+            }
+            write_excluded(out, obs_env)?;
+            result?;
+        }
+        Action::Mirror => {
+            let (mirrors, result) = mirror(config, obs_env);
+            match config.get_output_format() {
+                OutputFormat::Text => {
+                    for mirror in mirrors.iter() {
+                        writeln!(out, "{mirror}")?;
+                    }
+                }
+                OutputFormat::Json => {
+                    serde_json::to_writer_pretty(&mut *out, &mirrors)?;
+                    writeln!(out)?;
+                }
+            }
+            write_excluded(out, obs_env)?;
+            result?;
+        }
+        Action::Outdated => {
+            let mut stalenesses = Vec::new();
+            let mut failed = Vec::new();
+            for (repo_name, result) in obs_env.outdated() {
+                match result {
+                    Ok(staleness) => stalenesses.push(staleness),
+                    Err(error) => {
+                        log::error!("{}", report(&error));
+                        failed.push(repo_name);
+                    }
+                }
+            }
+            let report = OutdatedReport::new(
+                stalenesses,
+                config.get_outdated_thresholds(),
+                &config.get_outdated_ignore()?,
+                timestamp() as u64,
+            );
+            match config.get_output_format() {
+                OutputFormat::Text => {
+                    if !report.repos.is_empty() {
+                        writeln!(out, "{report}")?;
+                    }
+                }
+                OutputFormat::Json => {
+                    serde_json::to_writer_pretty(&mut *out, &report)?;
+                    writeln!(out)?;
+                }
+            }
+            write_excluded(out, obs_env)?;
+            let outdated: Vec<String> = report.outdated().map(|repo| repo.to_string()).collect();
+            if !outdated.is_empty() {
+                return Err(ObsEnvError::VerificationFailed {
+                    path: PathBuf::from(config.get_env_path()),
+                    problems: outdated,
+                }
+                .into());
+            }
+            if !failed.is_empty() {
+                return Err(ObsEnvError::PartialFailure {
+                    operation: "compare with the default branch".to_owned(),
+                    failed,
+                }
+                .into());
+            }
+        }
+        Action::Report => {
+            let report = obs_env.env_report(&config.get_report_options()?);
+            let content = report.render(config.get_report_format());
+            match config.get_report_path() {
+                Some(path) => {
+                    write_atomically(Path::new(path), &content)?;
+                    writeln!(out, "Wrote {path}")?;
+                }
+                None => write!(out, "{content}")?,
+            }
+        }
+        Action::ValidateConfig => {
+            let mut checks = check_configuration(config)?;
+            if config.get_check_remote() {
+                checks.extend(obs_env.check_remotes());
+            }
+            write_config_checks(config, out, &checks)?;
+        }
+        Action::CloneEnv => {
+            // get_action already checked that it is set.
+            let source = config.get_source_env_path().unwrap_or_default();
+            let mut cloned = Vec::new();
+            let mut failed = Vec::new();
+            for (repo_name, result) in
+                obs_env.clone_env(Path::new(source), config.get_copy_local_changes())?
+            {
+                match result {
+                    Ok(repo) => cloned.push(repo),
+                    Err(error) => {
+                        log::error!("{}", report(&error));
+                        failed.push(repo_name);
+                    }
+                }
+            }
+            match config.get_output_format() {
+                OutputFormat::Text => {
+                    for repo in cloned.iter() {
+                        writeln!(out, "{repo}")?;
+                    }
+                }
+                OutputFormat::Json => {
+                    serde_json::to_writer_pretty(&mut *out, &cloned)?;
+                    writeln!(out)?;
+                }
+            }
+            write_metadata(obs_env, action);
+            write_lock_file(obs_env);
+            if !failed.is_empty() {
+                return Err(ObsEnvError::PartialFailure {
+                    operation: "clone from the source environment".to_owned(),
+                    failed,
+                }
+                .into());
+            }
+            obs_env.verify()?;
+        }
+        Action::Fetch => {
+            for (repo_name, result) in obs_env.fetch_repositories() {
+                match result {
+                    Ok(()) => writeln!(out, "Fetched {repo_name}")?,
+                    Err(error) => log::error!("{}", report(&error)),
+                }
+            }
+            write_excluded(out, obs_env)?;
+        }
+        Action::CheckoutBranch | Action::CheckoutVersion => {
+            let (checked_out, result) = checkout_repositories(config, obs_env, action);
+            for (repo_name, target) in checked_out.iter() {
+                writeln!(out, "{repo_name}: {target}")?;
+            }
+            let repo_names: Vec<&str> = checked_out
+                .iter()
+                .map(|(repo_name, _)| repo_name.as_str())
+                .collect();
+            after_update(obs_env, &repo_names);
+            result?;
+        }
+        Action::CheckoutBranchAll => {
+            let (switched, result) = checkout_branch_all(obs_env, config.get_branch_name());
+            match config.get_output_format() {
+                OutputFormat::Text => {
+                    if switched.values().all(Option::is_none) {
+                        writeln!(
+                            out,
+                            "No repository has branch {}.",
+                            config.get_branch_name()
+                        )?;
+                    }
+                    for (repo_name, branch) in switched.iter() {
+                        match branch {
+                            Some(branch) => writeln!(out, "{repo_name}: {branch}")?,
+                            None => writeln!(out, "{repo_name}: left as it was")?,
+                        }
+                    }
+                }
+                OutputFormat::Json => {
+                    serde_json::to_writer_pretty(&mut *out, &switched)?;
+                    writeln!(out)?;
+                }
+            }
+            let repo_names: Vec<&str> = switched
+                .iter()
+                .filter(|(_, branch)| branch.is_some())
+                .map(|(repo_name, _)| repo_name.as_str())
+                .collect();
+            after_update(obs_env, &repo_names);
+            result?;
+        }
+    };
+    Ok(None)
+}
+
+/// Check out the versions of `manifest`, for "ApplyManifest" and
+/// "Restore", warning about a Python environment that differs from the one
+/// recorded, then update the EUPS tables and pip installs of the
+/// repositories applied.
+fn apply_manifest<T: ManageObsEnvCli, W: Write>(
+    config: &T,
+    out: &mut W,
+    obs_env: &ObservingEnvironment,
+    action: &Action,
+    manifest: &EnvironmentManifest,
+) -> io::Result<()> {
+    if let Some(recorded) = &manifest.python_env {
+        match PythonEnvironment::capture(config.get_python(), config.get_conda_command()) {
+            Ok(current) => {
+                let differences = recorded.differences(&current);
+                if !differences.is_empty() {
+                    log::warn!(
+                        "The Python environment differs from the manifest:\n{}",
+                        differences.join("\n")
+                    );
+                }
+            }
+            Err(error) => log::warn!(
+                "Cannot compare the Python environment with the manifest: {}",
+                report(&error)
+            ),
+        }
+    }
+    if let Err(errors) = obs_env.apply_manifest(manifest) {
+        log::error!(
+            "Error applying the manifest to {} repositories.",
+            errors.len()
+        );
+        for error in errors {
+            log::error!("{}", report(&error));
+        }
+    } else {
+        writeln!(out, "All repositories set to their manifest versions.")?;
+        write_metadata(obs_env, action);
+        if !config.get_locked() {
+            write_lock_file(obs_env);
+        }
+    }
+    write_excluded(out, obs_env)?;
+    let applied: Vec<&str> = manifest
+        .repos
+        .iter()
+        .map(|version| version.name.as_str())
+        .filter(|repo_name| obs_env.repo(repo_name).is_ok_and(|repo| repo.exists()))
+        .collect();
+    after_update(obs_env, &applied);
+    Ok(())
+}
+
+/// List the repositories left out with --exclude, so that their absence
+/// from the results is not taken for a failure.
+fn write_excluded<W: Write>(out: &mut W, obs_env: &ObservingEnvironment) -> io::Result<()> {
+    for repo_name in obs_env.excluded() {
+        writeln!(out, "{repo_name}: skipped (excluded)")?;
+    }
+    Ok(())
+}
+
+/// Append the outcome of `action` to the audit log of `obs_env`, logging
+/// the failure to write it without failing the action.
+fn record_audit(
+    obs_env: &ObservingEnvironment,
+    action: &Action,
+    before: &BTreeMap<String, Option<String>>,
+    result: &Result<Option<SetupReport>, Box<dyn Error>>,
+) {
+    let (outcome, error) = match result {
+        Ok(Some(setup_report)) => match setup_report.partial_failure() {
+            Some(error) => (AuditOutcome::PartialFailure, Some(error.to_string())),
+            None => (AuditOutcome::Success, None),
+        },
+        Ok(None) => (AuditOutcome::Success, None),
+        Err(error) => match error.downcast_ref::<ObsEnvError>() {
+            Some(ObsEnvError::PartialFailure { .. }) => {
+                (AuditOutcome::PartialFailure, Some(error.to_string()))
+            }
+            _ => (AuditOutcome::Failed, Some(error.to_string())),
+        },
+    };
+    if let Err(error) = obs_env.record_audit(&action_name(action), before, outcome, error) {
+        log::warn!("Could not write the audit log: {}", report(&error));
+    }
+}
+
+/// Environment of `environments` the link of `config` points at, if any.
+fn active_environment<T: ManageObsEnvCli>(
+    config: &T,
+    environments: &[NamedEnvironment],
+) -> Option<PathBuf> {
+    let active = Path::new(&config.get_link()?).canonicalize().ok()?;
+    environments
+        .iter()
+        .find(|environment| environment.path.canonicalize().ok().as_ref() == Some(&active))
+        .map(|environment| environment.path.clone())
+}
+
+/// Check that `environment` can be removed, then remove it if forced,
+/// with its lock held, returning the bytes it used.
+fn prune_environment<T: ManageObsEnvCli>(
+    config: &T,
+    environment: &NamedEnvironment,
+) -> Result<u64, ObsEnvError> {
+    let path = &environment.path;
+    let _lock = if config.get_force() {
+        Some(
+            ObservingEnvironment::builder()
+                .destination(&path.to_string_lossy())
+                .lock_timeout(config.get_lock_timeout())
+                .build()?
+                .lock()?,
+        )
+    } else {
+        None
+    };
+    environments::check_removable(path, &Git2Backend)?;
+    let size = environments::disk_usage(path);
+    if config.get_force() {
+        remove_dir_all(path).map_err(|error| ObsEnvError::io(path, "remove", error))?;
+    }
+    Ok(size)
+}
+
+/// Name of `action` on the command line, e.g. "apply-manifest".
+fn action_name(action: &Action) -> String {
+    action
+        .to_possible_value()
+        .map(|action| action.get_name().to_owned())
+        .unwrap_or_default()
+}
+
+/// Pin the commits the repositories were left at in the lock file,
+/// logging the failure to write it without failing the action.
+fn write_lock_file(obs_env: &ObservingEnvironment) {
+    if let Err(error) = obs_env.write_lock_file() {
+        log::warn!("Could not write the lock file: {}", report(&error));
+    }
+}
+
+/// Record the versions `action` left the environment at, logging the
+/// failure to write them without failing the action.
+fn write_metadata(obs_env: &ObservingEnvironment, action: &Action) {
+    if let Err(error) = obs_env.write_metadata(&action_name(action)) {
+        log::warn!(
+            "Could not write the environment metadata: {}",
+            report(&error)
+        );
+    }
+}
+
+/// Write the metadata of the environment, if any, flagging the
+/// repositories changed since it was written.
+fn show_metadata<W: Write>(out: &mut W, obs_env: &ObservingEnvironment) -> io::Result<()> {
+    match obs_env.read_metadata() {
+        Ok(Some(metadata)) => {
+            writeln!(out, "{metadata}")?;
+            let modified = obs_env.modified_since(&metadata);
+            if !modified.is_empty() {
+                writeln!(
+                    out,
+                    "Environment modified outside this tool: {}",
+                    modified.join(", ")
+                )?;
+            }
+        }
+        Ok(None) => {}
+        Err(error) => log::warn!("{}", report(&error)),
+    }
+    Ok(())
+}
+
+/// Declare `repo_names` in EUPS and install them in editable mode, if
+/// enabled, logging the failures without failing the action.
+fn after_update(obs_env: &ObservingEnvironment, repo_names: &[&str]) {
+    for (repo_name, result) in obs_env.declare_eups(repo_names.iter().copied()) {
+        match result {
+            Ok(version) => log::info!("Declared {repo_name} {version} in EUPS."),
+            Err(error) => log::error!("Failed to declare {repo_name} in EUPS: {}", report(&error)),
+        }
+    }
+    for (repo_name, result) in obs_env.develop_install(repo_names.iter().copied()) {
+        match result {
+            Ok(()) => log::info!("Installed {repo_name} in editable mode."),
+            Err(error) => log::error!("Failed to install {repo_name}: {}", report(&error)),
+        }
+    }
+}
+
+/// Actions supported by [`run`].
+#[derive(clap::ValueEnum, Clone, Debug)]
 pub enum Action {
     /// Setup the observing environment?
     /// This will create the destination directory and clone all repositories.
+    /// With --from-manifest, they are cloned at the versions of the manifest.
     Setup,
+    /// Write setup_obs_env.sh in the environment path, adding the python/
+    /// and bin/ directories of the cloned repositories to PYTHONPATH and
+    /// PATH when sourced.
+    WriteSetupScript,
+    /// Write the versions of the cloned repositories as an EUPS tag file
+    /// (see --tag-file), naming the products after the eups_products of
+    /// the configuration file.
+    WriteTagFile,
+    /// Write Prometheus gauges about the health of the environment to
+    /// --metrics-file, for the textfile collector of node_exporter.
+    WriteMetrics,
+    /// Serve the versions checked out (/versions), the base versions
+    /// (/base-versions), their differences (/diff) and the problems of the
+    /// environment (/health) as json over HTTP on --listen, until stopped
+    /// with SIGTERM or SIGINT. Nothing served modifies the environment.
+    Serve,
+    /// Remove the repositories from the environment, except, without
+    /// --force, those with changes, untracked files, stashes or commits on
+    /// no remote. Worktrees of a shared object store are detached from it,
+    /// leaving the store untouched.
+    Teardown,
     /// Show observing environment configuration?
     /// This will only print the observing environment configuration.
     PrintConfig,
+    /// List the repositories of the environment and where the list comes
+    /// from.
+    ListRepos,
     /// Reset obs environment. This will bring all repositories in the
     /// environment to their original versions.
     Reset,
-    /// Show current versions.
+    /// Fetch every cloned repository and fast-forward the branch checked
+    /// out to origin. Repositories with local changes, or whose branch has
+    /// diverged from origin, fail and are left as they are. Missing
+    /// repositories are not cloned, see "Setup".
+    Update,
+    /// Show current versions, with the commits the branch checked out has
+    /// that origin has not and the other way round, as +ahead/-behind, as
+    /// of the last fetch unless --fetch-first.
     ShowCurrentVersions,
     /// Show original versions.
     ShowOriginalVersions,
+    /// Show only the repositories not at their base environment version,
+    /// or with local changes: the branch or version and commit checked
+    /// out and how many commits it is ahead of and behind the base
+    /// version, as of the last fetch unless --fetch-first.
+    Diff,
+    /// Show, for every cloned repository, the branch checked out or the
+    /// detached commit, its +ahead/-behind origin as of the last fetch
+    /// unless --fetch-first, and its modified and untracked files. Exit
+    /// with an error if any has modified files or an operation in
+    /// progress, which "Reset" would throw away.
+    Status,
+    /// Write a manifest with the versions checked out in the environment,
+    /// to --manifest or stdout.
+    Export,
+    /// Check out the versions recorded in the --manifest file.
+    ApplyManifest,
+    /// Record the commits the repositories are at in the --snapshot
+    /// snapshot, kept in the environment, warning about the repositories
+    /// with local changes, which the snapshot cannot restore.
+    Snapshot,
+    /// Check out the commits recorded in the --snapshot snapshot, or in
+    /// the latest one, as "ApplyManifest" does.
+    Restore,
+    /// List the repositories whose version differs between the --from and
+    /// --to manifests, or that only one of them has, with the commits in
+    /// between with --commits. Neither has to match the environment.
+    CompareManifests,
+    /// List the commits whose message matches --pattern in every cloned
+    /// repository, newest first and at most --max-results by repository,
+    /// only those since --since or by --author if given. Only the local
+    /// history is searched, unless --fetch-first.
+    SearchCommits,
+    /// Compare the repositories with the commits recorded in env.lock by
+    /// the last "Setup", "Reset" or "ApplyManifest", failing if any moved,
+    /// has changes or is not cloned.
+    VerifyLock,
+    /// Keep the environment in sync with the manifest of
+    /// --manifest-source: every --interval seconds, read the manifest and
+    /// check out the versions the environment is not at, until stopped
+    /// with SIGTERM or SIGINT. Each reconciliation holds the lock of the
+    /// environment and is recorded in its audit log. Failed cycles make
+    /// the next one wait longer.
+    Watch,
+    /// Compare the versions of the cloned repositories with the packages
+    /// of the active conda environment.
+    CompareConda,
+    /// Look for repositories left damaged, e.g. by an interrupted Setup:
+    /// repositories that do not open, have no HEAD or no objects, or have
+    /// lock files left. Also reports the repositories owned by another
+    /// user, and those with no git identity to commit with. With --deep,
+    /// also look for corrupt or missing objects. Repair the damaged ones
+    /// with --repair, which clones the corrupt ones again.
+    Doctor,
+    /// List the environments under --env-root, with when they were last
+    /// set up, their base branch and their number of repositories.
+    ListEnvs,
+    /// List the environments under --env-root last set up more than
+    /// --older-than days ago, or beyond the --keep most recent ones, and
+    /// remove them with --force. The active environment, and those with
+    /// changes, untracked files, stashes or commits on no remote, are kept.
+    PruneEnvs,
+    /// Point the --link symlink at the environment, once every repository
+    /// of it is cloned and undamaged (see --force), recording the
+    /// environment it pointed at before.
+    Activate,
+    /// Point the --link symlink back at the environment it pointed at
+    /// before the last "Activate". Does nothing if it already points there.
+    Deactivate,
+    /// List the obs-env-backup/ branches "Reset" kept local commits on.
+    ListBackups,
+    /// Show the most recent operations that modified the environment, from
+    /// its audit log, only those changing --repository or since --since
+    /// if given.
+    ShowHistory,
+    /// Delete the obs-env-backup/ branches, or only those older than
+    /// --backup-age days.
+    DropBackups,
+    /// List, by repository, the local branches whose tip is in the default
+    /// branch of origin, or that origin no longer has and have no commit
+    /// of their own, and the remote-tracking branches of the branches
+    /// origin no longer has. Delete them with --delete. The branch checked
+    /// out is never deleted.
+    ListStaleBranches,
+    /// Fetch every branch and tag of the cloned repositories. The other
+    /// actions only fetch what they need.
+    Fetch,
+    /// Push the commit checked out in every cloned repository, and the
+    /// tags on it, to its --mirror-remote, as branches and tags under
+    /// --mirror-prefix, so the deployed versions survive an outage of
+    /// origin. --dry-run lists the refs that would be pushed.
+    Mirror,
+    /// Delete, in every cloned repository, the remote-tracking branches of
+    /// the branches that origin, or any other remote, no longer has, as a
+    /// fetch with --prune would, and list them by repository. --dry-run
+    /// lists the refs that would be deleted.
+    PruneRemotes,
+    /// Fetch the default branch of every cloned repository and list them,
+    /// the most stale first, with how many commits of the branch they do
+    /// not have and since when. Exit with an error if any is beyond
+    /// --threshold-days or --threshold-commits, or behind at all without
+    /// them, unless listed in outdated_ignore in --config.
+    Outdated,
+    /// Write a Markdown or HTML document of the state of the environment:
+    /// the versions checked out, the differences with the base
+    /// environment, the local changes, the outdated repositories, the last
+    /// reset and the recent operations. --report-sections selects them.
+    Report,
+    /// Check the repository list, its overrides and forks, and the
+    /// repositories and groups the configuration and the command line
+    /// name, listing each check as passed or failed. --check-remote also
+    /// lists the url of every repository. Exit with an error if any check
+    /// fails, e.g. to gate changes to the configuration files.
+    ValidateConfig,
+    /// Create the environment at --env-path by cloning the repositories of
+    /// the one at --source-env-path from its clones, with no network
+    /// access, and checking out the same branches or detached commits.
+    /// Local changes are left out, and listed, unless
+    /// --copy-local-changes. The new environment is then verified.
+    CloneEnv,
     /// Checkout a branch in a repository.
     CheckoutBranch,
     /// Checkout a version in a repository.
     CheckoutVersion,
+    /// Checkout the --branch-name branch, or the ticket branch it expands
+    /// to, in every cloned repository that has it, and list the branch
+    /// each repository switched to. The others are left as they are.
+    CheckoutBranchAll,
+}
+
+impl Action {
+    /// Whether the action changes the repositories or files of the
+    /// environment, and so has to hold its lock and is recorded in its
+    /// audit log. "Doctor" only does with --repair, and "ListStaleBranches"
+    /// with --delete. "Watch" does, but takes the lock and records each of
+    /// its reconciliations itself.
+    pub fn modifies_environment(&self) -> bool {
+        match self {
+            Action::Setup
+            | Action::WriteSetupScript
+            | Action::WriteTagFile
+            | Action::Teardown
+            | Action::Reset
+            | Action::Update
+            | Action::ApplyManifest
+            | Action::Snapshot
+            | Action::Restore
+            | Action::DropBackups
+            | Action::Fetch
+            | Action::PruneRemotes
+            | Action::CloneEnv
+            | Action::CheckoutBranch
+            | Action::CheckoutVersion
+            | Action::CheckoutBranchAll => true,
+            Action::PrintConfig
+            | Action::WriteMetrics
+            | Action::Serve
+            | Action::Watch
+            | Action::VerifyLock
+            | Action::ListRepos
+            | Action::ShowCurrentVersions
+            | Action::ShowOriginalVersions
+            | Action::Diff
+            | Action::Status
+            | Action::Export
+            | Action::CompareManifests
+            | Action::SearchCommits
+            | Action::CompareConda
+            | Action::Doctor
+            | Action::ListEnvs
+            | Action::Activate
+            | Action::Deactivate
+            | Action::PruneEnvs
+            | Action::ListBackups
+            | Action::ShowHistory
+            | Action::ListStaleBranches
+            | Action::Mirror
+            | Action::Outdated
+            | Action::Report
+            | Action::ValidateConfig => false,
+        }
+    }
+}
+
+/// Format of the results written by [`run`].
+#[derive(clap::ValueEnum, Clone, Debug)]
+pub enum OutputFormat {
+    /// Human readable text.
+    Text,
+    /// Pretty printed json.
+    Json,
 }
 
+/// Permissions of the files of the environment, shared with its group.
+#[derive(clap::ValueEnum, Clone, Debug)]
+pub enum Permissions {
+    /// Writable by the group, and executable by it when by their owner.
+    GroupWritable,
+}
+
+/// Verbosity of the log messages.
 #[derive(clap::ValueEnum, Clone, Debug)]
 pub enum LogLevel {
     Trace,
@@ -186,3 +3647,1466 @@ pub enum LogLevel {
     Warn,
     Error,
 }
+
+impl From<&LogLevel> for log::LevelFilter {
+    fn from(level: &LogLevel) -> log::LevelFilter {
+        match level {
+            LogLevel::Trace => log::LevelFilter::Trace,
+            LogLevel::Debug => log::LevelFilter::Debug,
+            LogLevel::Info => log::LevelFilter::Info,
+            LogLevel::Warn => log::LevelFilter::Warn,
+            LogLevel::Error => log::LevelFilter::Error,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        reset, run_with_events, run_with_output, ManageObsEnv, ManageObsEnvCli, ProgressEvents,
+    };
+    use crate::{
+        audit::{AuditOutcome, HistoryFilter, RepoChange},
+        manifest::{EnvironmentManifest, RepoVersion},
+        observing_environment::OBS_ENV_DIR,
+        resume::{RepoOutcome, ResumeState, RESUME_FILE},
+        testing::FakeBackend,
+        ObsEnvError, ObservingEnvironment,
+    };
+    use clap::Parser;
+    use git2::{Oid, Repository, Signature};
+    use std::{
+        path::{Path, PathBuf},
+        sync::Arc,
+    };
+    use tempfile::TempDir;
+
+    type TestResult<T = (), E = Box<dyn std::error::Error>> = std::result::Result<T, E>;
+
+    const FAKE_ORG: &str = "https://example.com/lsst-ts";
+
+    fn run_to_string(args: &[&str]) -> TestResult<String> {
+        // Without network access, retrying the operations reaching GitHub
+        // would only slow the tests down.
+        let config = ManageObsEnv::try_parse_from(
+            ["manage_obs_env", "--log-level", "error", "--retries", "0"]
+                .iter()
+                .chain(args.iter()),
+        )?;
+        let mut out = Vec::new();
+        run_with_output(&config, &mut out)?;
+        Ok(String::from_utf8(out)?)
+    }
+
+    /// Run `args` on the environment at `env_path` with the repositories of
+    /// `repos_file`.
+    fn run_in_env(env_path: &Path, repos_file: &Path, args: &[&str]) -> TestResult<String> {
+        let env_path = env_path.to_string_lossy();
+        let repos_file = repos_file.to_string_lossy();
+        run_to_string(
+            &[
+                &["--env-path", &env_path, "--repos-file", &repos_file],
+                args,
+            ]
+            .concat(),
+        )
+    }
+
+    /// Initialize a remote at `path` with an empty commit on main, and
+    /// return it with the commit.
+    fn fixture_remote(path: &Path) -> TestResult<(Repository, Oid)> {
+        let remote = Repository::init(path)?;
+        let signature = Signature::now("Test", "test@example.com")?;
+        let tree_id = remote.index()?.write_tree()?;
+        let tree = remote.find_tree(tree_id)?;
+        let commit = remote.commit(
+            Some("refs/heads/main"),
+            &signature,
+            &signature,
+            "Initial",
+            &tree,
+            &[],
+        )?;
+        drop(tree);
+        remote.set_head("refs/heads/main")?;
+        Ok((remote, commit))
+    }
+
+    /// Add an empty commit on top of main.
+    fn fixture_commit(repository: &Repository, message: &str) -> TestResult<Oid> {
+        let signature = Signature::now("Test", "test@example.com")?;
+        let parent = repository.head()?.peel_to_commit()?;
+        let tree = parent.tree()?;
+        Ok(repository.commit(
+            Some("refs/heads/main"),
+            &signature,
+            &signature,
+            message,
+            &tree,
+            &[&parent],
+        )?)
+    }
+
+    /// Write a repos file under `root` listing the remotes there named
+    /// `repo_names`, on main, and return its path.
+    fn fixture_repos_file(root: &Path, repo_names: &[&str]) -> TestResult<PathBuf> {
+        let repos: String = repo_names
+            .iter()
+            .map(|repo_name| {
+                format!(
+                    "[[repositories]]\nname = \"{repo_name}\"\nurl = \"{}\"\ndefault_branch = \"main\"\n",
+                    root.join(repo_name).display()
+                )
+            })
+            .collect();
+        let repos_file = root.join("repos.toml");
+        std::fs::write(&repos_file, repos)?;
+        Ok(repos_file)
+    }
+
+    #[test]
+    fn test_print_config_output() -> TestResult {
+        let output = run_to_string(&[
+            "--action",
+            "print-config",
+            "--env-path",
+            "/obs-env",
+            "--fork",
+            "tribeiro:ts_wep",
+        ])?;
+
+        assert_eq!(
+            output,
+            "Obs. Env. Path: /obs-env.\nNumber of repositories: 12 (from built-in list)\nts_wep taken from fork: tribeiro\n"
+        );
+
+        let output = run_to_string(&[
+            "--action",
+            "print-config",
+            "--env-path",
+            "/obs-env",
+            "--fork",
+            "tribeiro:ts_wep",
+            "--output",
+            "json",
+        ])?;
+        let output: serde_json::Value = serde_json::from_str(&output)?;
+        assert_eq!(output["summary"]["env_path"], "/obs-env");
+        assert_eq!(output["summary"]["forks"]["ts_wep"], "tribeiro");
+        assert_eq!(
+            output["summary"]["repos"].as_array().map(Vec::len),
+            Some(12)
+        );
+        assert!(output["metadata"].is_null());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hooks() -> TestResult {
+        let root = TempDir::new()?;
+        let env_path = root.path().join("env");
+        let manifest = root.path().join("manifest.toml");
+        std::fs::write(
+            &manifest,
+            "created = 0\nenv_path = \"/obs-env\"\nrepos = []\n",
+        )?;
+        let config_file = root.path().join("config.toml");
+        let hook_out = root.path().join("hook.out");
+        let write_config = |command: &str| {
+            std::fs::write(
+                &config_file,
+                format!("[hooks]\npost_apply_manifest = '{command}'\n"),
+            )
+        };
+        let args = |no_hooks: bool| {
+            let mut args = vec![
+                "--action".to_owned(),
+                "apply-manifest".to_owned(),
+                "--env-path".to_owned(),
+                env_path.to_string_lossy().into_owned(),
+                "--manifest".to_owned(),
+                manifest.to_string_lossy().into_owned(),
+                "--config".to_owned(),
+                config_file.to_string_lossy().into_owned(),
+                "--no-eups".to_owned(),
+            ];
+            if no_hooks {
+                args.push("--no-hooks".to_owned());
+            }
+            args
+        };
+        let run = |no_hooks: bool| {
+            let args = args(no_hooks);
+            run_to_string(&args.iter().map(String::as_str).collect::<Vec<_>>())
+        };
+
+        write_config(&format!(
+            "echo $OBS_ENV_ACTION $OBS_ENV_PATH > {}",
+            hook_out.display()
+        ))?;
+        run(true)?;
+        assert!(!hook_out.exists());
+        run(false)?;
+        assert_eq!(
+            std::fs::read_to_string(&hook_out)?,
+            format!("apply-manifest {}\n", env_path.display())
+        );
+
+        write_config("exit 1")?;
+        let error = run(false).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ObsEnvError>(),
+            Some(ObsEnvError::CommandFailed { command, .. }) if command == "post_apply_manifest hook exit 1"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_commits() -> TestResult {
+        let root = TempDir::new()?;
+        let env_path = root.path().to_string_lossy();
+        let args = ["--action", "search-commits", "--env-path", &env_path];
+
+        assert!(run_to_string(&args).is_err());
+        assert_eq!(
+            run_to_string(&[&args[..], &["--pattern", "M2 LUT"]].concat())?,
+            "No commit matches M2 LUT.\n"
+        );
+        assert!(run_to_string(&[&args[..], &["--pattern", "M2 ("]].concat()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_compare_manifests() -> TestResult {
+        let root = TempDir::new()?;
+        let from = root.path().join("from.toml");
+        let to = root.path().join("to.toml");
+        let manifest = |repos: &[(&str, &str)]| {
+            EnvironmentManifest::new(
+                "/obs-env",
+                repos
+                    .iter()
+                    .map(|(name, describe)| RepoVersion::new(name, describe))
+                    .collect(),
+            )
+        };
+        manifest(&[("cwfs", "0.3.1"), ("ts_wep", "v1.2.0")]).save(&from)?;
+        manifest(&[("ts_wep", "v1.3.0")]).save(&to)?;
+        let args = [
+            "--action",
+            "compare-manifests",
+            "--env-path",
+            &root.path().join("env").to_string_lossy(),
+            "--from",
+            &from.to_string_lossy(),
+            "--to",
+            &to.to_string_lossy(),
+            "--commits",
+        ]
+        .map(str::to_owned);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+        assert_eq!(
+            run_to_string(&args)?,
+            "cwfs: removed, was at 0.3.1\nts_wep: v1.2.0 -> v1.3.0\n"
+        );
+        let json: serde_json::Value = serde_json::from_str(&run_to_string(
+            &[&args[..], &["--output", "json"]].concat(),
+        )?)?;
+        assert_eq!(json[1]["repo"], "ts_wep");
+        assert_eq!(json[1]["to"]["describe"], "v1.3.0");
+        assert!(run_to_string(&args[..args.len() - 3]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_version_override_validation() -> TestResult {
+        assert!(ManageObsEnv::try_parse_from(["manage_obs_env", "--override", "ts_wep"]).is_err());
+
+        let root = TempDir::new()?;
+        let env_path = root.path().join("env");
+        let error = run_to_string(&[
+            "--action",
+            "reset",
+            "--env-path",
+            &env_path.to_string_lossy(),
+            "--override",
+            "ts_unknown=1.0.0",
+        ])
+        .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ObsEnvError>(),
+            Some(ObsEnvError::RepoNotFound { repo }) if repo == "ts_unknown"
+        ));
+        assert!(!env_path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_show_original_versions_output() -> TestResult {
+        let root = TempDir::new()?;
+        let versions_file = root.path().join("versions.env");
+        std::fs::write(&versions_file, "ts_wep=1.2.3\ncwfs=0.3.1\nunknown=1.0.0\n")?;
+
+        let output = run_to_string(&[
+            "--action",
+            "show-original-versions",
+            "--env-path",
+            &root.path().to_string_lossy(),
+            "--base-env-source",
+            &versions_file.to_string_lossy(),
+        ])?;
+
+        assert_eq!(
+            output,
+            format!(
+                "Base environment versions (local path {}):\ncwfs: 0.3.1\nts_wep: 1.2.3\n",
+                versions_file.display()
+            )
+        );
+
+        let output = run_to_string(&[
+            "--action",
+            "show-original-versions",
+            "--env-path",
+            &root.path().to_string_lossy(),
+            "--base-env-source",
+            &versions_file.to_string_lossy(),
+            "--output",
+            "json",
+        ])?;
+        let output: serde_json::Value = serde_json::from_str(&output)?;
+        assert_eq!(output["versions"]["ts_wep"]["describe"], "1.2.3");
+        assert_eq!(output["versions"]["cwfs"]["describe"], "0.3.1");
+        assert!(output["versions"].get("unknown").is_none());
+        assert!(output["commit"].is_null());
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_output() -> TestResult {
+        let root = TempDir::new()?;
+        let (remote, tagged) = fixture_remote(&root.path().join("ts_wep"))?;
+        remote.tag_lightweight("v1.2.3", &remote.find_object(tagged, None)?, false)?;
+        let head = fixture_commit(&remote, "After the release")?;
+        let repos_file = fixture_repos_file(root.path(), &["ts_wep"])?;
+        let versions_file = root.path().join("versions.env");
+        std::fs::write(&versions_file, "ts_wep=1.2.3\n")?;
+        let env_path = root.path().join("env");
+        let versions_file = versions_file.to_string_lossy();
+        let run = |args: &[&str]| {
+            run_in_env(
+                &env_path,
+                &repos_file,
+                &[&["--base-env-source", &versions_file], args].concat(),
+            )
+        };
+        run(&["--action", "setup"])?;
+        run(&["--action", "reset"])?;
+        assert_eq!(
+            run(&["--action", "diff"])?,
+            "Every repository is at its base version.\n"
+        );
+
+        run(&[
+            "--action",
+            "checkout-branch",
+            "--repository",
+            "ts_wep",
+            "--branch-name",
+            "main",
+        ])?;
+        assert_eq!(
+            run(&["--action", "diff"])?,
+            format!(
+                "ts_wep: main at {}, +1/-0 from base 1.2.3\n",
+                &head.to_string()[..12]
+            )
+        );
+        let output: serde_json::Value =
+            serde_json::from_str(&run(&["--action", "diff", "--output", "json"])?)?;
+        assert_eq!(output["ts_wep"]["base"], "1.2.3");
+        assert_eq!(output["ts_wep"]["ahead_behind"]["ahead"], 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_repair_output() -> TestResult {
+        let root = TempDir::new()?;
+        fixture_remote(&root.path().join("ts_wep"))?;
+        let repos_file = fixture_repos_file(root.path(), &["ts_wep"])?;
+        let env_path = root.path().join("env");
+        let run = |args: &[&str]| run_in_env(&env_path, &repos_file, args);
+        run(&["--action", "setup"])?;
+        std::fs::remove_file(env_path.join("ts_wep/.git/HEAD"))?;
+
+        // Without its HEAD, the repository cannot be read to tell that
+        // cloning it again loses no commit.
+        let error = run(&["--action", "doctor", "--repair"]).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ObsEnvError>(),
+            Some(ObsEnvError::PartialFailure { failed, .. }) if failed == &["ts_wep"]
+        ));
+        assert!(!env_path.join("ts_wep/.git/HEAD").exists());
+
+        let output = run(&["--action", "doctor", "--repair", "--force"])?;
+        assert!(output.contains("ts_wep: cloned again"), "{output}");
+        assert!(env_path.join("ts_wep/.git/HEAD").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_teardown_output() -> TestResult {
+        let root = TempDir::new()?;
+        fixture_remote(&root.path().join("ts_wep"))?;
+        let repos_file = fixture_repos_file(root.path(), &["ts_wep"])?;
+        let env_path = root.path().join("env");
+        let run = |args: &[&str]| run_in_env(&env_path, &repos_file, args);
+        run(&["--action", "setup"])?;
+        let refused = |args: &[&str]| {
+            matches!(
+                run(args).unwrap_err().downcast_ref::<ObsEnvError>(),
+                Some(ObsEnvError::PartialFailure { failed, .. }) if failed == &["ts_wep"]
+            )
+        };
+
+        std::fs::write(env_path.join("ts_wep/notes.txt"), "untracked")?;
+        assert!(refused(&["--action", "teardown"]));
+        assert!(env_path.join("ts_wep/notes.txt").exists());
+        std::fs::remove_file(env_path.join("ts_wep/notes.txt"))?;
+
+        let local = Repository::open(env_path.join("ts_wep"))?;
+        let head = local.head()?.peel_to_commit()?;
+        let signature = Signature::now("Test", "test@example.com")?;
+        local.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            "Unpushed",
+            &head.tree()?,
+            &[&head],
+        )?;
+        assert!(refused(&["--action", "teardown"]));
+        assert!(env_path.join("ts_wep").exists());
+
+        let output = run(&["--action", "teardown", "--force"])?;
+        assert!(output.contains("Removed ts_wep"), "{output}");
+        assert!(!env_path.join("ts_wep").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_report_output() -> TestResult {
+        let root = TempDir::new()?;
+        let versions_file = root.path().join("versions.env");
+        std::fs::write(&versions_file, "ts_wep=1.2.3\n")?;
+        let report_path = root.path().join("report.html");
+
+        let output = run_to_string(&[
+            "--action",
+            "report",
+            "--env-path",
+            &root.path().to_string_lossy(),
+            "--base-env-source",
+            &versions_file.to_string_lossy(),
+            "--report-sections",
+            "base-diff,last-reset",
+            "--report-format",
+            "html",
+            "--report-path",
+            &report_path.to_string_lossy(),
+        ])?;
+
+        assert_eq!(output, format!("Wrote {}\n", report_path.display()));
+        let report = std::fs::read_to_string(&report_path)?;
+        assert!(report.contains("<h2>Differences with the base environment</h2>"));
+        assert!(report.contains("<td>ts_wep</td>\n<td>1.2.3</td>\n<td>not cloned</td>"));
+        assert!(report.contains("<h2>Last reset</h2>"));
+        assert!(!report.contains("<h2>Versions</h2>"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_reset_failure_is_an_error() -> TestResult {
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        let url = format!("{FAKE_ORG}/ts_wep");
+        backend.set_branch(&url, "main", "1111aaaa");
+        backend.set_tag(&url, "v1.2.0", "2222bbbb");
+        let versions_file = root.path().join("versions.env");
+        std::fs::write(&versions_file, "ts_wep=1.2.0\nts_missing=0.1.0\n")?;
+        let obs_env = ObservingEnvironment::builder()
+            .destination(&root.path().to_string_lossy())
+            .repositories(["ts_missing", "ts_wep"].map(|name| (name, FAKE_ORG)))
+            .base_env_source(&versions_file.to_string_lossy())
+            .backend(backend.clone())
+            .build()?;
+        obs_env.clone_repositories();
+
+        let mut out = Vec::new();
+        let error = reset(&obs_env, &mut out, None).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ObsEnvError>(),
+            Some(ObsEnvError::PartialFailure { failed, .. }) if failed == &["ts_missing"]
+        ));
+        assert!(out.is_empty());
+        assert_eq!(
+            backend.head(root.path().join("ts_wep")).as_deref(),
+            Some("2222bbbb")
+        );
+
+        std::fs::write(&versions_file, "ts_wep=1.2.0\n")?;
+        reset(&obs_env, &mut out, None)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            format!(
+                "All repositories set to their base versions, from local path {}.\n",
+                versions_file.display()
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_setup_json_output() -> TestResult {
+        let root = TempDir::new()?;
+        // ts_wep has no remote to be cloned from.
+        let repos_file = fixture_repos_file(root.path(), &["ts_wep"])?;
+        let config = ManageObsEnv::try_parse_from([
+            "manage_obs_env",
+            "--log-level",
+            "error",
+            "--action",
+            "setup",
+            "--output",
+            "json",
+            "--retries",
+            "0",
+            "--env-path",
+            &root.path().join("env").to_string_lossy(),
+            "--repos-file",
+            &repos_file.to_string_lossy(),
+        ])?;
+
+        let mut out = Vec::new();
+        let error = run_with_output(&config, &mut out).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ObsEnvError>(),
+            Some(ObsEnvError::PartialFailure { failed, .. }) if failed == &["ts_wep"]
+        ));
+        let report: serde_json::Value = serde_json::from_slice(&out)?;
+        assert_eq!(report["status"], "partial");
+        assert_eq!(report["repos"][0]["name"], "ts_wep");
+        assert_eq!(report["repos"][0]["outcome"], "failed");
+        Ok(())
+    }
+
+    #[test]
+    fn test_modifying_actions_take_the_lock() -> TestResult {
+        let root = TempDir::new()?;
+        let env_path = root.path().join("env");
+        let repos_file = root.path().join("repos.toml");
+        std::fs::write(
+            &repos_file,
+            "[[repositories]]\nname = \"ts_wep\"\nurl = \"https://github.com/lsst-ts/\"\n",
+        )?;
+        let args = |action: &'static str| {
+            ManageObsEnv::try_parse_from([
+                "manage_obs_env",
+                "--log-level",
+                "error",
+                "--action",
+                action,
+                "--lock-timeout",
+                "0",
+                "--env-path",
+                &env_path.to_string_lossy(),
+                "--repos-file",
+                &repos_file.to_string_lossy(),
+            ])
+        };
+        let lock = ObservingEnvironment::with_destination(&env_path.to_string_lossy()).lock()?;
+
+        let error = run_with_output(&args("teardown")?, &mut Vec::new()).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ObsEnvError>(),
+            Some(ObsEnvError::EnvLocked { .. })
+        ));
+        run_with_output(&args("list-repos")?, &mut Vec::new())?;
+
+        drop(lock);
+        run_with_output(&args("teardown")?, &mut Vec::new())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_config() -> TestResult {
+        let root = TempDir::new()?;
+        let mut repos = String::new();
+        for (repo_name, default_branch) in [("cwfs", "develop"), ("ts_wep", "main")] {
+            fixture_remote(&root.path().join(repo_name))?;
+            repos.push_str(&format!(
+                "[[repositories]]\nname = \"{repo_name}\"\nurl = \"{}\"\ndefault_branch = \"{default_branch}\"\n",
+                root.path().join(repo_name).display()
+            ));
+        }
+        let repos_file = root.path().join("repos.toml");
+        std::fs::write(&repos_file, repos)?;
+        let config_file = root.path().join("config.toml");
+        std::fs::write(&config_file, "[forks]\nts_wepp = \"tribeiro\"\n")?;
+        let repos_file = repos_file.to_string_lossy();
+        let config_file = config_file.to_string_lossy();
+        let run = |args: &[&str]| -> TestResult<(String, bool)> {
+            let config = ManageObsEnv::try_parse_from(
+                [
+                    &["manage_obs_env", "--log-level", "error"],
+                    &["--action", "validate-config", "--repos-file", &repos_file][..],
+                    args,
+                ]
+                .concat(),
+            )?;
+            let mut out = Vec::new();
+            let failed = run_with_output(&config, &mut out).is_err();
+            Ok((String::from_utf8(out)?, failed))
+        };
+
+        let (output, failed) = run(&["--config", &config_file, "--check-remote"])?;
+        assert!(failed);
+        assert!(output.contains("pass: urls are well formed\n"));
+        assert!(output
+            .contains("FAIL: referenced repositories exist: forks: ts_wepp is not in the list\n"));
+        // The remotes are not checked for a configuration with errors.
+        assert!(!output.contains("reachable"));
+
+        let (output, failed) = run(&[])?;
+        assert!(!failed);
+        assert!(output.lines().all(|line| line.starts_with("pass: ")));
+
+        let (output, failed) = run(&["--check-remote"])?;
+        assert!(failed);
+        assert!(output.contains(&format!(
+            "FAIL: cwfs is reachable at {} with branch develop: refs/heads/develop not found\n",
+            root.path().join("cwfs").display()
+        )));
+        assert!(output.contains(&format!(
+            "pass: ts_wep is reachable at {} with branch main\n",
+            root.path().join("ts_wep").display()
+        )));
+        Ok(())
+    }
+
+    #[test]
+    fn test_show_current_versions() -> TestResult {
+        let root = TempDir::new()?;
+        let (remote, _) = fixture_remote(&root.path().join("ts_wep"))?;
+        let repos_file = fixture_repos_file(root.path(), &["ts_wep"])?;
+        let env_path = root.path().join("env");
+        let run = |args: &[&str]| run_in_env(&env_path, &repos_file, args);
+        run(&["--action", "setup"])?;
+        fixture_commit(&remote, "Second")?;
+
+        let show = ["--action", "show-current-versions"];
+        assert!(run(&show)?.ends_with(" (+0/-0)\n"));
+        assert!(run(&[&show[..], &["--fetch-first"]].concat())?.ends_with(" (+0/-1)\n"));
+        let json: serde_json::Value =
+            serde_json::from_str(&run(&[&show[..], &["--output", "json"]].concat())?)?;
+        assert_eq!(json["ts_wep"]["branch"], "main");
+        assert_eq!(json["ts_wep"]["ahead_behind"]["behind"], 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_failure_output() -> TestResult {
+        let root = TempDir::new()?;
+        for repo_name in ["cwfs", "ts_wep"] {
+            fixture_remote(&root.path().join(repo_name))?;
+        }
+        let repos_file = fixture_repos_file(root.path(), &["cwfs", "ts_wep"])?;
+        let env_path = root.path().join("env");
+        let env_path = env_path.to_string_lossy();
+        let repos_file = repos_file.to_string_lossy();
+        let run = |args: &[&str]| -> TestResult<(String, bool)> {
+            let config = ManageObsEnv::try_parse_from(
+                [
+                    &[
+                        "manage_obs_env",
+                        "--log-level",
+                        "error",
+                        "--output",
+                        "json",
+                        "--retries",
+                        "0",
+                    ],
+                    &["--env-path", &env_path, "--repos-file", &repos_file][..],
+                    args,
+                ]
+                .concat(),
+            )?;
+            let mut out = Vec::new();
+            let failed = run_with_output(&config, &mut out).is_err();
+            Ok((String::from_utf8(out)?, failed))
+        };
+        run(&["--action", "setup"])?;
+        std::fs::remove_dir_all(root.path().join("ts_wep"))?;
+
+        let (output, failed) = run(&["--action", "outdated"])?;
+        assert!(failed);
+        let json: serde_json::Value =
+            serde_json::from_str(&output[output.rfind("\n{").map_or(0, |start| start + 1)..])?;
+        assert_eq!(json["status"], "partial");
+        assert_eq!(json["action"], "outdated");
+        assert_eq!(json["kind"], "PartialFailure");
+        assert_eq!(json["succeeded"], serde_json::json!(["cwfs"]));
+        assert!(json["failed"]["ts_wep"].is_string());
+
+        let (output, failed) = run(&["--action", "checkout-branch"])?;
+        assert!(failed);
+        let json: serde_json::Value = serde_json::from_str(&output)?;
+        assert_eq!(json["status"], "error");
+        assert_eq!(json["kind"], "MissingArgument");
+        assert_eq!(json["failed"], serde_json::json!({}));
+        Ok(())
+    }
+
+    #[test]
+    fn test_resume_setup() -> TestResult {
+        let root = TempDir::new()?;
+        fixture_remote(&root.path().join("ts_wep"))?;
+        let repos_file = fixture_repos_file(root.path(), &["ts_wep", "ts_xml"])?;
+        let env_path = root.path().join("env");
+        let run = |args: &[&str]| run_in_env(&env_path, &repos_file, args);
+        let error = run(&["--action", "setup", "--resume"]).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("There is no failed setup to resume"));
+
+        assert!(run(&["--action", "setup"]).is_err());
+        let resume_file = env_path.join(OBS_ENV_DIR).join(RESUME_FILE);
+        let resume = ResumeState::load(&resume_file)?.unwrap();
+        assert_eq!(resume.pending().collect::<Vec<_>>(), ["ts_xml"]);
+        assert_eq!(resume.repos["ts_wep"], RepoOutcome::Succeeded);
+
+        let error = run(&["--action", "setup", "--resume", "--update-existing"]).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("existing_clones was \"skip\", not \"update\""));
+        let error = run(&["--action", "setup", "--resume", "--clone-depth", "1"]).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("clone_depth was \"\", not \"1\""));
+        let versions_file = root.path().join("versions.env");
+        std::fs::write(&versions_file, "ts_wep=1.2.3\n")?;
+        let error = run(&[
+            "--action",
+            "setup",
+            "--resume",
+            "--base-env-source",
+            &versions_file.to_string_lossy(),
+        ])
+        .unwrap_err();
+        assert!(error.to_string().contains("base_env_source was \"\""));
+        let error = run(&["--action", "reset", "--resume"]).unwrap_err();
+        assert!(error.to_string().contains("the failed action is setup"));
+        assert!(run(&["--action", "list-repos", "--resume"]).is_err());
+
+        // Only ts_xml is cloned, not ts_wep again.
+        std::fs::remove_dir_all(env_path.join("ts_wep"))?;
+        fixture_remote(&root.path().join("ts_xml"))?;
+        run(&["--action", "setup", "--resume"])?;
+        assert!(env_path.join("ts_xml/.git").exists());
+        assert!(!env_path.join("ts_wep").exists());
+        assert!(!resume_file.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_status_output() -> TestResult {
+        let root = TempDir::new()?;
+        let (_, commit) = fixture_remote(&root.path().join("ts_wep"))?;
+        let repos_file = fixture_repos_file(root.path(), &["ts_wep"])?;
+        let env_path = root.path().join("env");
+        let run = |action: &str| run_in_env(&env_path, &repos_file, &["--action", action]);
+        run("setup")?;
+        std::fs::write(env_path.join("ts_wep/notes.txt"), "")?;
+        let commit = &commit.to_string()[..12];
+        assert_eq!(
+            run("status")?,
+            format!("ts_wep: main at {commit} (+0/-0), 1 untracked\n")
+        );
+
+        let clone = Repository::open(env_path.join("ts_wep"))?;
+        let mut index = clone.index()?;
+        index.add_path(Path::new("notes.txt"))?;
+        index.write()?;
+        let error = run("status").unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ObsEnvError>(),
+            Some(ObsEnvError::VerificationFailed { problems, .. })
+                if problems == &[format!("ts_wep: main at {commit} (+0/-0), 1 modified")]
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot_and_restore_output() -> TestResult {
+        let root = TempDir::new()?;
+        let (remote, first) = fixture_remote(&root.path().join("ts_wep"))?;
+        let second = fixture_commit(&remote, "Second")?;
+        let repos_file = fixture_repos_file(root.path(), &["ts_wep"])?;
+        let env_path = root.path().join("env");
+        let run = |args: &[&str]| run_in_env(&env_path, &repos_file, args);
+        run(&["--action", "setup"])?;
+        let output = run(&["--action", "snapshot", "--snapshot", "night"])?;
+        let path = env_path.join(".obs_env/snapshots/night.toml");
+        assert!(output.starts_with(&format!(
+            "Wrote {}\nnight: 1 repositories, taken on ",
+            path.display()
+        )));
+        assert!(path.exists());
+
+        let clone = Repository::open(env_path.join("ts_wep"))?;
+        clone.set_head_detached(first)?;
+        let output = run(&["--action", "restore"])?;
+        assert!(output.starts_with("Restoring night: 1 repositories"));
+        assert!(output.ends_with("All repositories set to their manifest versions.\n"));
+        assert_eq!(clone.head()?.peel_to_commit()?.id(), second);
+        Ok(())
+    }
+
+    #[test]
+    fn test_checkout_several_repositories() -> TestResult {
+        let root = TempDir::new()?;
+        for repo_name in ["cwfs", "ts_wep", "ts_xml"] {
+            let (remote, commit) = fixture_remote(&root.path().join(repo_name))?;
+            if repo_name != "ts_xml" {
+                remote.branch("tickets/DM-1", &remote.find_commit(commit)?, false)?;
+            }
+        }
+        let repos_file = fixture_repos_file(root.path(), &["cwfs", "ts_wep", "ts_xml"])?;
+        let env_path = root.path().join("env");
+        let run = |args: &[&str]| run_in_env(&env_path, &repos_file, args);
+        run(&["--action", "setup"])?;
+
+        let output = run(&[
+            "--action",
+            "checkout-branch",
+            "--branch-name",
+            "DM-1",
+            "--repository",
+            "ts_wep",
+            "--repository",
+            "cwfs",
+        ])?;
+        assert_eq!(output, "ts_wep: tickets/DM-1\ncwfs: tickets/DM-1\n");
+
+        let error = run(&[
+            "--action",
+            "checkout-branch",
+            "--branch-name",
+            "main",
+            "--repository",
+            "cwfs,ts_unknown,ts_wep",
+        ])
+        .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ObsEnvError>(),
+            Some(ObsEnvError::PartialFailure { failed, .. }) if failed == &["ts_unknown"]
+        ));
+        let repository = Repository::open(env_path.join("ts_wep"))?;
+        assert_eq!(repository.head()?.shorthand(), Some("main"));
+
+        let error = run(&[
+            "--action",
+            "checkout-branch",
+            "--branch-name",
+            "DM-1",
+            "--repository",
+            "ts_xml",
+        ])
+        .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ObsEnvError>(),
+            Some(ObsEnvError::BranchNotFound { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_audit_log() -> TestResult {
+        let root = TempDir::new()?;
+        let (_, commit) = fixture_remote(&root.path().join("ts_wep"))?;
+        let repos_file = fixture_repos_file(root.path(), &["ts_missing", "ts_wep"])?;
+        let env_path = root.path().join("env");
+        let run = |args: &[&str]| run_in_env(&env_path, &repos_file, args);
+
+        assert!(run(&["--action", "setup"]).is_err());
+        run(&["--action", "list-repos"])?;
+        run(&["--action", "teardown"])?;
+
+        let obs_env = ObservingEnvironment::with_destination(&env_path.to_string_lossy());
+        let entries = obs_env.history(&HistoryFilter::default())?;
+        let actions: Vec<(&str, AuditOutcome)> = entries
+            .iter()
+            .map(|entry| (entry.action.as_str(), entry.outcome))
+            .collect();
+        assert_eq!(
+            actions,
+            [
+                ("setup", AuditOutcome::PartialFailure),
+                ("teardown", AuditOutcome::Success)
+            ]
+        );
+        let change = |from: Option<&str>, to: Option<&str>| RepoChange {
+            repo: "ts_wep".to_owned(),
+            from: from.map(str::to_owned),
+            to: to.map(str::to_owned),
+        };
+        let commit = commit.to_string();
+        assert_eq!(entries[0].repos, [change(None, Some(&commit))]);
+        assert_eq!(entries[1].repos, [change(Some(&commit), None)]);
+        assert_eq!(entries[0].host, crate::lock::hostname());
+
+        let history = run(&[
+            "--action",
+            "show-history",
+            "--repository",
+            "ts_wep",
+            "--history-limit",
+            "1",
+        ])?;
+        assert!(history.contains("teardown: success"));
+        assert!(!history.contains("setup"));
+        assert!(run(&["--action", "show-history", "--since", "2999-01-01"])?
+            .contains("No recorded operation found."));
+        Ok(())
+    }
+
+    #[test]
+    fn test_progress_events() -> TestResult {
+        let root = TempDir::new()?;
+        fixture_remote(&root.path().join("ts_wep"))?;
+        // Without a default branch, cloning is the only phase of ts_wep.
+        let repos_file = root.path().join("repos.toml");
+        std::fs::write(
+            &repos_file,
+            format!(
+                "[[repositories]]\nname = \"ts_missing\"\nurl = \"{}\"\n\n[[repositories]]\nname = \"ts_wep\"\nurl = \"{}\"\n",
+                root.path().join("nowhere").display(),
+                root.path().join("ts_wep").display()
+            ),
+        )?;
+        let config = ManageObsEnv::try_parse_from([
+            "manage_obs_env",
+            "--log-level",
+            "error",
+            "--action",
+            "setup",
+            "--progress-events",
+            "--timing",
+            "--no-eups",
+            "--retries",
+            "0",
+            "--env-path",
+            &root.path().join("env").to_string_lossy(),
+            "--repos-file",
+            &repos_file.to_string_lossy(),
+        ])?;
+
+        let progress_events = Arc::new(ProgressEvents::new(Vec::new()));
+        let mut out = Vec::new();
+        assert!(run_with_events(&config, &mut out, Some(Arc::clone(&progress_events))).is_err());
+
+        let events = progress_events.out.lock().unwrap();
+        let events = std::str::from_utf8(&events)?
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<serde_json::Value>, _>>()?;
+        let names: Vec<_> = events
+            .iter()
+            .map(|event| format!("{} {}", event["event"], event["repo"]))
+            .filter(|event| !event.starts_with("\"transfer_progress\""))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                r#""repo_start" "ts_missing""#,
+                r#""repo_failed" "ts_missing""#,
+                r#""repo_start" "ts_wep""#,
+                r#""repo_done" "ts_wep""#,
+                r#""run_complete" null"#,
+            ]
+        );
+        let run_complete = &events[events.len() - 1]["report"];
+        assert_eq!(run_complete["action"], "setup");
+        assert_eq!(run_complete["success"], false);
+        assert_eq!(run_complete["setup"]["repos"][1]["outcome"], "cloned");
+        let phases: Vec<_> = run_complete["timing"]["phases"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|phase| phase["phase"].as_str().unwrap())
+            .collect();
+        assert_eq!(phases, ["lock", "create path", "clone", "total"]);
+        assert_eq!(run_complete["timing"]["phases"][2]["count"], 2);
+        assert!(events.iter().all(|event| event["timestamp"].is_f64()));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_export_with_python_env() -> TestResult {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = TempDir::new()?;
+        let python = root.path().join("python");
+        std::fs::write(
+            &python,
+            "#!/bin/sh\necho numpy==1.26.4\necho astropy==6.0.0\n",
+        )?;
+        std::fs::set_permissions(&python, std::fs::Permissions::from_mode(0o755))?;
+        let manifest = root.path().join("manifest.toml");
+        let args = |action: &'static str| {
+            [
+                "--action",
+                action,
+                "--env-path",
+                &root.path().join("env").to_string_lossy(),
+                "--manifest",
+                &manifest.to_string_lossy(),
+                "--python",
+                &python.to_string_lossy(),
+                "--conda",
+                &root.path().join("no-conda").to_string_lossy(),
+                "--include-python-env",
+                "--no-eups",
+            ]
+            .map(str::to_owned)
+        };
+
+        let output = run_to_string(&args("export").each_ref().map(String::as_str))?;
+        assert_eq!(output, format!("Wrote {}\n", manifest.display()));
+        let exported = EnvironmentManifest::load(&manifest)?;
+        assert_eq!(
+            exported.python_env.unwrap().pip_freeze,
+            ["numpy==1.26.4", "astropy==6.0.0"]
+        );
+
+        std::fs::write(&python, "#!/bin/sh\necho numpy==1.26.0\n")?;
+        let output = run_to_string(&args("apply-manifest").each_ref().map(String::as_str))?;
+        assert_eq!(output, "All repositories set to their manifest versions.\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_only() -> TestResult {
+        let root = TempDir::new()?;
+        let env_path = root.path().join("env");
+        let manifest = root.path().join("manifest.toml");
+        let args = [
+            "--action",
+            "apply-manifest",
+            "--validate-only",
+            "--env-path",
+            &env_path.to_string_lossy(),
+            "--manifest",
+            &manifest.to_string_lossy(),
+        ]
+        .map(str::to_owned);
+        let args = args.each_ref().map(String::as_str);
+
+        std::fs::write(
+            &manifest,
+            "created = 0\nenv_path = \"/obs-env\"\n\n[[repos]]\nname = \"ts_wep\"\ndescribe = \"v1\"\n",
+        )?;
+        assert_eq!(
+            run_to_string(&args)?,
+            format!(
+                "{0}: warning: line 1: format_version: missing, assuming 1\n{0} is a valid manifest.\n",
+                manifest.display()
+            )
+        );
+
+        std::fs::write(&manifest, "created = 0\nrepos = []\n")?;
+        let error = run_to_string(&args).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Invalid manifest {}: line 1: env_path: missing, expected a string",
+                manifest.display()
+            )
+        );
+        assert!(!env_path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_dry_run_unsupported_action() -> TestResult {
+        let root = TempDir::new()?;
+        let error = run_to_string(&[
+            "--action",
+            "list-backups",
+            "--dry-run",
+            "--env-path",
+            &root.path().to_string_lossy(),
+        ])
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid configuration: --dry-run is not supported by list-backups"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_checkout_requires_repository() -> TestResult {
+        for action in ["checkout-branch", "checkout-version"] {
+            let config = ManageObsEnv::try_parse_from(["manage_obs_env", "--action", action])?;
+            let error = config.get_action().unwrap_err();
+
+            assert!(matches!(
+                error.downcast_ref::<ObsEnvError>(),
+                Some(ObsEnvError::MissingArgument { argument, .. }) if argument == "--repository"
+            ));
+        }
+
+        let config =
+            ManageObsEnv::try_parse_from(["manage_obs_env", "--action", "checkout-branch-all"])?;
+        assert!(matches!(
+            config.get_action().unwrap_err().downcast_ref::<ObsEnvError>(),
+            Some(ObsEnvError::MissingArgument { argument, .. }) if argument == "--branch-name"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_repos_with_overrides() -> TestResult {
+        let root = TempDir::new()?;
+        let config_file = root.path().join("config.toml");
+        std::fs::write(
+            &config_file,
+            "[overrides.ts_wep]\nurl = \"git@github.com:lsst-ts/ts_wep.git\"\ndefault_branch = \"main\"\n",
+        )?;
+
+        let output = run_to_string(&[
+            "--action",
+            "list-repos",
+            "--config",
+            &config_file.to_string_lossy(),
+        ])?;
+        assert!(output.starts_with("Repositories from built-in list:\n"));
+        assert!(output.contains("\natmospec: https://github.com/lsst/atmospec (main)\n"));
+        assert!(output.contains("\nts_wep: git@github.com:lsst-ts/ts_wep.git (main)\n"));
+
+        std::fs::write(
+            &config_file,
+            "[overrides.ts_unknown]\nurl = \"/remotes/x\"\n",
+        )?;
+        let error = run_to_string(&[
+            "--action",
+            "list-repos",
+            "--config",
+            &config_file.to_string_lossy(),
+        ])
+        .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ObsEnvError>(),
+            Some(ObsEnvError::RepoNotFound { repo }) if repo == "ts_unknown"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_repos_from_file() -> TestResult {
+        let root = TempDir::new()?;
+        let repos_file = root.path().join("repos.toml");
+        std::fs::write(
+            &repos_file,
+            "[[repositories]]\nname = \"ts_wep\"\nurl = \"https://github.com/lsst-ts/ts_wep\"\ndefault_branch = \"develop\"\ngroups = [\"aos\"]\n\n[[repositories]]\nname = \"ts_new\"\nurl = \"git@github.com:lsst-ts/ts_new.git\"\n",
+        )?;
+        let repos_file = repos_file.to_string_lossy();
+
+        let output = run_to_string(&["--action", "list-repos", "--repos-file", &repos_file])?;
+        assert_eq!(
+            output,
+            format!(
+                "Repositories from {repos_file}:\nts_new: git@github.com:lsst-ts/ts_new.git\nts_wep: https://github.com/lsst-ts/ts_wep (develop) [aos]\n"
+            )
+        );
+
+        let output = run_to_string(&[
+            "--action",
+            "print-config",
+            "--env-path",
+            "/obs-env",
+            "--repos-file",
+            &repos_file,
+        ])?;
+        assert_eq!(
+            output,
+            format!("Obs. Env. Path: /obs-env.\nNumber of repositories: 2 (from {repos_file})\n")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_only_and_group_filters() -> TestResult {
+        let root = TempDir::new()?;
+        let repos_file = root.path().join("repos.toml");
+        std::fs::write(
+            &repos_file,
+            "[[repositories]]\nname = \"ts_wep\"\nurl = \"/remotes/ts_wep\"\ngroups = [\"aos\"]\n\n[[repositories]]\nname = \"cwfs\"\nurl = \"/remotes/cwfs\"\ngroups = [\"aos\"]\n\n[[repositories]]\nname = \"ts_config_ocs\"\nurl = \"/remotes/ts_config_ocs\"\n",
+        )?;
+        let repos_file = repos_file.to_string_lossy();
+
+        let list = |filters: &[&str]| {
+            run_to_string(
+                &[
+                    &["--action", "list-repos", "--repos-file", &repos_file],
+                    filters,
+                ]
+                .concat(),
+            )
+        };
+        let header = format!("Repositories from {repos_file}:\n");
+        assert_eq!(
+            list(&["--group", "aos"])?,
+            format!("{header}cwfs: /remotes/cwfs [aos]\nts_wep: /remotes/ts_wep [aos]\n")
+        );
+        assert_eq!(
+            list(&["--only", "ts_config_ocs,cwfs"])?,
+            format!("{header}cwfs: /remotes/cwfs [aos]\nts_config_ocs: /remotes/ts_config_ocs\n")
+        );
+        assert_eq!(
+            list(&["--only", "ts_config_ocs", "--group", "aos"])?
+                .lines()
+                .count(),
+            4
+        );
+        assert_eq!(
+            list(&["--group", "aos", "--exclude", "cwfs"])?,
+            format!("{header}ts_wep: /remotes/ts_wep [aos]\n")
+        );
+        assert_eq!(list(&["--only", "cwfs", "--exclude", "cwfs"])?, header);
+        let env_path = root.path().join("env");
+        let output = run_to_string(&[
+            "--action",
+            "show-current-versions",
+            "--env-path",
+            &env_path.to_string_lossy(),
+            "--repos-file",
+            &repos_file,
+            "--exclude",
+            "ts_wep,cwfs",
+        ])?;
+        assert!(output.starts_with("ts_config_ocs: "));
+        assert!(output.ends_with("cwfs: skipped (excluded)\nts_wep: skipped (excluded)\n"));
+
+        let error = list(&["--only", "ts_unknown"]).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ObsEnvError>(),
+            Some(ObsEnvError::RepoNotFound { repo }) if repo == "ts_unknown"
+        ));
+        let error = list(&["--group", "unknown"]).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ObsEnvError>(),
+            Some(ObsEnvError::InvalidConfig { .. })
+        ));
+        let error = list(&["--exclude", "ts_unknown"]).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ObsEnvError>(),
+            Some(ObsEnvError::RepoNotFound { repo }) if repo == "ts_unknown"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_repos_file_validation() -> TestResult {
+        let root = TempDir::new()?;
+        let repos_file = root.path().join("repos.toml");
+        for content in [
+            "[[repositories]]\nname = \"ts_wep\"\nurl = \"not a url\"\n",
+            "[[repositories]]\nname = \"ts_wep\"\nurl = \"https://a/ts_wep\"\n[[repositories]]\nname = \"ts_wep\"\nurl = \"https://b/ts_wep\"\n",
+        ] {
+            std::fs::write(&repos_file, content)?;
+            let error = run_to_string(&[
+                "--action",
+                "print-config",
+                "--repos-file",
+                &repos_file.to_string_lossy(),
+            ])
+            .unwrap_err();
+            assert!(matches!(
+                error.downcast_ref::<ObsEnvError>(),
+                Some(ObsEnvError::InvalidConfig { .. })
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_metadata_output() -> TestResult {
+        let root = TempDir::new()?;
+        let env_path = root.path().join("env");
+        let env_path = env_path.to_string_lossy();
+        let print_config = || run_to_string(&["--action", "print-config", "--env-path", &env_path]);
+        assert!(!print_config()?.contains("Last "));
+
+        let obs_env = ObservingEnvironment::with_destination(&env_path);
+        obs_env.create_path()?;
+        obs_env.write_metadata("reset")?;
+        let output = print_config()?;
+        assert!(output.contains("Last reset by "), "{output}");
+        assert!(output.contains("(base branch main, manage_obs_env "));
+        assert!(!output.contains("modified outside"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_named_environments() -> TestResult {
+        let root = TempDir::new()?;
+        let env_root = root.path().to_string_lossy();
+        ObservingEnvironment::with_destination(&format!("{env_root}/nightly"))
+            .write_metadata("reset")?;
+        std::fs::create_dir_all(root.path().join("release/.obs_env"))?;
+        std::fs::create_dir_all(root.path().join("scratch"))?;
+        std::fs::write(root.path().join("scratch/notes.txt"), "")?;
+
+        let output = run_to_string(&["--action", "list-envs", "--env-root", &env_root])?;
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 2, "{output}");
+        assert!(lines[0].starts_with("nightly: last reset on "));
+        assert!(lines[0].ends_with(", base branch main, 0 repositories"));
+        assert_eq!(lines[1], "release: no metadata recorded");
+
+        let print_config = |env_name: &str| {
+            run_to_string(&[
+                "--action",
+                "print-config",
+                "--env-root",
+                &env_root,
+                "--env-name",
+                env_name,
+            ])
+        };
+        assert!(
+            print_config("release")?.starts_with(&format!("Obs. Env. Path: {env_root}/release.\n"))
+        );
+        assert!(print_config("new")?.starts_with(&format!("Obs. Env. Path: {env_root}/new.\n")));
+        let error = print_config("scratch").unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ObsEnvError>(),
+            Some(ObsEnvError::NotAnEnvironment { .. })
+        ));
+        assert!(print_config("../scratch").is_err());
+
+        for args in [
+            &["--action", "list-envs"][..],
+            &["--action", "print-config", "--env-root", &env_root],
+        ] {
+            let error = run_to_string(args).unwrap_err();
+            assert!(matches!(
+                error.downcast_ref::<ObsEnvError>(),
+                Some(ObsEnvError::MissingArgument { .. })
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_activate_and_deactivate() -> TestResult {
+        let root = TempDir::new()?;
+        fixture_remote(&root.path().join("ts_wep"))?;
+        let repos_file = fixture_repos_file(root.path(), &["ts_wep"])?;
+        let env_root = root.path().join("envs");
+        std::fs::create_dir_all(env_root.join("release"))?;
+        let env_root = env_root.to_string_lossy();
+        let repos_file = repos_file.to_string_lossy();
+        let run = |args: &[&str]| {
+            run_to_string(
+                &[
+                    &[
+                        "--env-root",
+                        &env_root,
+                        "--repos-file",
+                        &repos_file,
+                        "--no-eups",
+                    ],
+                    args,
+                ]
+                .concat(),
+            )
+        };
+        run(&["--action", "setup", "--env-name", "nightly"])?;
+
+        let error = run(&["--action", "activate", "--env-name", "release"]).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ObsEnvError>(),
+            Some(ObsEnvError::VerificationFailed { problems, .. }) if problems == &["ts_wep is not cloned"]
+        ));
+        run(&["--action", "activate", "--env-name", "release", "--force"])?;
+        assert_eq!(
+            run(&["--action", "activate", "--env-name", "nightly"])?,
+            format!("{env_root}/current -> {env_root}/nightly (was {env_root}/release)\n")
+        );
+        assert!(run(&["--action", "list-envs"])?.contains(", 1 repositories (active)\n"));
+        assert!(Path::new(&format!("{env_root}/current/ts_wep/.git")).is_dir());
+
+        assert_eq!(
+            run(&["--action", "deactivate"])?,
+            format!("{env_root}/current -> {env_root}/release (was {env_root}/nightly)\n")
+        );
+        assert_eq!(
+            run(&["--action", "deactivate"])?,
+            format!("{env_root}/current -> {env_root}/release\n")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_envs() -> TestResult {
+        let root = TempDir::new()?;
+        let env_root = root.path().to_string_lossy();
+        let set_up = |name: &str, days_ago: u64| -> TestResult {
+            let obs_env = ObservingEnvironment::with_destination(&format!("{env_root}/{name}"));
+            let mut metadata = obs_env.write_metadata("setup")?;
+            metadata.timestamp -= days_ago * 24 * 60 * 60;
+            std::fs::write(obs_env.metadata_path(), serde_json::to_string(&metadata)?)?;
+            Ok(())
+        };
+        set_up("recent", 1)?;
+        set_up("old", 40)?;
+        set_up("active", 50)?;
+        set_up("unpushed", 60)?;
+        let local = Repository::init(root.path().join("unpushed/ts_wep"))?;
+        let signature = Signature::now("Test", "test@example.com")?;
+        let tree = local.find_tree(local.index()?.write_tree()?)?;
+        local.commit(Some("HEAD"), &signature, &signature, "Local", &tree, &[])?;
+        std::os::unix::fs::symlink(root.path().join("active"), root.path().join("current"))?;
+
+        let prune = |args: &[&str]| {
+            run_to_string(&[&["--action", "prune-envs", "--env-root", &env_root], args].concat())
+        };
+        let output = prune(&["--older-than", "30"])?;
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 2, "{output}");
+        assert!(lines[0].starts_with("Would remove old: last setup on "));
+        assert!(lines[1].starts_with("Would reclaim "));
+        assert!(root.path().join("old").exists());
+
+        let output = prune(&["--keep", "1", "--force"])?;
+        assert!(output.starts_with("Removed old: "), "{output}");
+        assert!(output.ends_with(" MiB.\n"));
+        assert!(!root.path().join("old").exists());
+        assert!(root.path().join("recent").exists());
+        assert!(root.path().join("active").exists());
+        assert!(root.path().join("unpushed/ts_wep").exists());
+
+        let error = prune(&[]).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ObsEnvError>(),
+            Some(ObsEnvError::MissingArgument { .. })
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,483 @@
+use crate::{
+    conda,
+    error::ObsEnvError,
+    git_backend::CommitSummary,
+    observing_environment::{serialized, write_atomically},
+    pip, schema,
+};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+    fs::read_to_string,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Version of the manifest format written.
+pub const MANIFEST_FORMAT_VERSION: u32 = 1;
+
+fn default_format_version() -> u32 {
+    MANIFEST_FORMAT_VERSION
+}
+
+fn is_zero(count: &usize) -> bool {
+    *count == 0
+}
+
+/// Commits a branch has that its upstream has not, and the other way
+/// round. It is displayed as `+ahead/-behind`.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct AheadBehind {
+    pub ahead: usize,
+    pub behind: usize,
+}
+
+impl Display for AheadBehind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "+{}/-{}", self.ahead, self.behind)
+    }
+}
+
+/// Version of a repository of the environment.
+///
+/// For a checked-out repository all fields are filled in from its HEAD;
+/// for a version defined by the base environment only `name` and `describe`
+/// are. It is displayed as `describe`.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct RepoVersion {
+    /// Name of the repository.
+    pub name: String,
+    /// Url of origin, for Setup to clone the repository from if the
+    /// repository list does not have it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// Local branch checked out, or none if HEAD is detached.
+    pub branch: Option<String>,
+    /// Id of the commit checked out.
+    pub sha: Option<String>,
+    /// HEAD described by the closest tag, or the version from the base
+    /// environment.
+    pub describe: String,
+    /// Whether tracked files were changed since HEAD, staged or not.
+    #[serde(default)]
+    pub dirty: bool,
+    /// Tracked files changed since HEAD. Only recorded in manifests
+    /// exported with `--include-dirty-files`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dirty_files: Vec<String>,
+    /// Number of untracked files, or directories, not ignored.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub untracked: usize,
+    /// Position of the branch checked out relative to the same branch on
+    /// origin, as of the last fetch, or none if HEAD is detached or the
+    /// branch is not on origin. Not recorded in manifests.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ahead_behind: Option<AheadBehind>,
+}
+
+impl RepoVersion {
+    /// Version `describe` of repository `name`, with no checkout details.
+    pub fn new(name: &str, describe: &str) -> RepoVersion {
+        RepoVersion {
+            name: name.to_owned(),
+            describe: describe.to_owned(),
+            ..RepoVersion::default()
+        }
+    }
+
+    /// Whether this version, checked out, is the version `base` of the
+    /// base environment: described by it, or on the branch it names.
+    pub fn matches(&self, base: &RepoVersion) -> bool {
+        base.describe == self.describe || self.branch.as_deref() == Some(base.describe.as_str())
+    }
+
+    /// Position relative to origin, `+ahead/-behind`, or why there is
+    /// none.
+    pub fn describe_upstream(&self) -> String {
+        match (&self.ahead_behind, &self.branch) {
+            (Some(ahead_behind), _) => ahead_behind.to_string(),
+            (None, Some(_)) => "no upstream".to_owned(),
+            (None, None) => "detached".to_owned(),
+        }
+    }
+
+    /// Whether this version and `other` are the same commit, or have the
+    /// same description if either has no commit, both with or both
+    /// without local changes.
+    fn same_as(&self, other: &RepoVersion) -> bool {
+        let same_commit = match (&self.sha, &other.sha) {
+            (Some(sha), Some(other_sha)) => sha == other_sha,
+            _ => self.describe == other.describe,
+        };
+        same_commit && self.dirty == other.dirty
+    }
+}
+
+impl Display for RepoVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.describe)
+    }
+}
+
+/// Versions of the repositories of an environment at a point in time.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct EnvironmentManifest {
+    /// Version of the format, 1 for manifests written before it was
+    /// recorded.
+    #[serde(default = "default_format_version")]
+    pub format_version: u32,
+    /// When the manifest was created, in seconds since the Unix epoch.
+    pub created: u64,
+    /// Path of the environment.
+    pub env_path: String,
+    /// Versions of the repositories, sorted by name.
+    pub repos: Vec<RepoVersion>,
+    /// Versions given as overrides of the base versions, by repository
+    /// name, so the repositories that deviate from the base environment
+    /// are known.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub overrides: BTreeMap<String, String>,
+    /// Python environment the repositories were used with, if recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub python_env: Option<PythonEnvironment>,
+}
+
+impl EnvironmentManifest {
+    /// Manifest of the environment at `env_path`, created now.
+    pub fn new(env_path: &str, mut repos: Vec<RepoVersion>) -> EnvironmentManifest {
+        repos.sort_by(|a, b| a.name.cmp(&b.name));
+        EnvironmentManifest {
+            format_version: MANIFEST_FORMAT_VERSION,
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or_default(),
+            env_path: env_path.to_owned(),
+            repos,
+            overrides: BTreeMap::new(),
+            python_env: None,
+        }
+    }
+
+    /// Read a manifest from the toml file at `path`.
+    pub fn load(path: &Path) -> Result<EnvironmentManifest, ObsEnvError> {
+        let content =
+            read_to_string(path).map_err(|error| ObsEnvError::io(path, "read manifest", error))?;
+        EnvironmentManifest::parse(&content, &path.display().to_string())
+    }
+
+    /// Read a manifest from the toml `content` read from `location`,
+    /// once [validated](schema::validate), logging the warnings.
+    pub fn parse(content: &str, location: &str) -> Result<EnvironmentManifest, ObsEnvError> {
+        let validation = schema::validate(content);
+        for warning in validation.warnings.iter() {
+            warn!("{location}: {warning}");
+        }
+        if !validation.is_valid() {
+            return Err(ObsEnvError::InvalidManifest {
+                location: location.to_owned(),
+                problems: validation
+                    .errors
+                    .iter()
+                    .map(|error| error.to_string())
+                    .collect(),
+            });
+        }
+        toml::from_str(content).map_err(|error| ObsEnvError::InvalidConfig {
+            message: format!("{location}: {error}"),
+        })
+    }
+
+    /// Write the manifest to `path` as toml.
+    pub fn save(&self, path: &Path) -> Result<(), ObsEnvError> {
+        write_atomically(path, &self.to_toml())
+    }
+
+    /// The manifest as toml.
+    pub fn to_toml(&self) -> String {
+        serialized(toml::to_string(self))
+    }
+
+    /// Repositories whose version differs from `self` to `other`, added
+    /// in `other` or removed from it, sorted by name, with no commits.
+    pub fn compare(&self, other: &EnvironmentManifest) -> Vec<RepoDifference> {
+        let versions = |manifest: &EnvironmentManifest| -> BTreeMap<String, RepoVersion> {
+            manifest
+                .repos
+                .iter()
+                .map(|version| (version.name.clone(), version.clone()))
+                .collect()
+        };
+        let (mut from, mut to) = (versions(self), versions(other));
+        let mut repo_names: Vec<String> = from.keys().chain(to.keys()).cloned().collect();
+        repo_names.sort();
+        repo_names.dedup();
+        repo_names
+            .into_iter()
+            .filter_map(|repo| {
+                let (from, to) = (from.remove(&repo), to.remove(&repo));
+                if let (Some(from), Some(to)) = (&from, &to) {
+                    if from.same_as(to) {
+                        return None;
+                    }
+                }
+                Some(RepoDifference {
+                    repo,
+                    from,
+                    to,
+                    commits: None,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Version of a repository that differs between two manifests.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RepoDifference {
+    pub repo: String,
+    /// Version in the first manifest, none if the repository was added.
+    pub from: Option<RepoVersion>,
+    /// Version in the second manifest, none if it was removed.
+    pub to: Option<RepoVersion>,
+    /// Commits reachable from the second version but not from the first,
+    /// newest first, if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commits: Option<Vec<CommitSummary>>,
+}
+
+impl Display for RepoDifference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let version = |version: &RepoVersion| {
+            let details: Vec<&str> = version
+                .sha
+                .iter()
+                .map(|sha| &sha[..sha.len().min(12)])
+                .chain(version.dirty.then_some("dirty"))
+                .collect();
+            match details.is_empty() {
+                true => version.to_string(),
+                false => format!("{version} ({})", details.join(", ")),
+            }
+        };
+        match (&self.from, &self.to) {
+            (Some(from), Some(to)) => {
+                write!(f, "{}: {} -> {}", self.repo, version(from), version(to))?
+            }
+            (None, Some(to)) => write!(f, "{}: added at {}", self.repo, version(to))?,
+            (Some(from), None) => write!(f, "{}: removed, was at {}", self.repo, version(from))?,
+            (None, None) => write!(f, "{}: in neither manifest", self.repo)?,
+        }
+        if let Some(commits) = &self.commits {
+            write!(f, " ({} commits)", commits.len())?;
+            for commit in commits {
+                write!(f, "\n    {commit}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Python packages installed where the environment is used.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct PythonEnvironment {
+    /// Output of `pip freeze`, one requirement per entry.
+    pub pip_freeze: Vec<String>,
+    /// Output of `conda env export`, if conda is used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conda_env: Option<String>,
+}
+
+impl PythonEnvironment {
+    /// Packages installed for the `python` interpreter, along with the
+    /// conda environment exported with `conda` if conda is available.
+    pub fn capture(python: &str, conda: &str) -> Result<PythonEnvironment, ObsEnvError> {
+        Ok(PythonEnvironment {
+            pip_freeze: pip::freeze(python)?,
+            conda_env: conda::export_environment(conda),
+        })
+    }
+
+    /// Packages that differ between `self` and `other` according to pip,
+    /// as "name: version in self -> version in other", with "none" for
+    /// packages that are not installed, sorted by name.
+    pub fn differences(&self, other: &PythonEnvironment) -> Vec<String> {
+        let requirements = |python_env: &PythonEnvironment| -> BTreeMap<String, String> {
+            python_env
+                .pip_freeze
+                .iter()
+                .map(|requirement| match requirement.split_once("==") {
+                    Some((name, version)) => (name.to_lowercase(), version.to_owned()),
+                    None => match requirement.split_once(" @ ") {
+                        Some((name, location)) => (name.to_lowercase(), location.to_owned()),
+                        None => (requirement.to_lowercase(), String::new()),
+                    },
+                })
+                .collect()
+        };
+        let (recorded, current) = (requirements(self), requirements(other));
+        let mut names: Vec<&String> = recorded.keys().chain(current.keys()).collect();
+        names.sort();
+        names.dedup();
+        names
+            .into_iter()
+            .filter(|name| recorded.get(*name) != current.get(*name))
+            .map(|name| {
+                let version = |requirements: &BTreeMap<String, String>| {
+                    requirements
+                        .get(name)
+                        .cloned()
+                        .unwrap_or_else(|| "none".to_owned())
+                };
+                format!("{name}: {} -> {}", version(&recorded), version(&current))
+            })
+            .collect()
+    }
+}
+
+impl Display for EnvironmentManifest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Obs. Env. Path: {}.", self.env_path)?;
+        for repo in self.repos.iter() {
+            write!(f, "\n{}: {repo}", repo.name)?;
+            if self.overrides.contains_key(&repo.name) {
+                write!(f, " (override)")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EnvironmentManifest, PythonEnvironment, RepoVersion};
+    use crate::git_backend::CommitSummary;
+
+    #[test]
+    fn test_manifest_display_and_toml() {
+        let ts_wep = RepoVersion {
+            name: "ts_wep".to_owned(),
+            branch: Some("develop".to_owned()),
+            sha: Some("1111aaaa".to_owned()),
+            describe: "v1.2.0-3-g1111aaa".to_owned(),
+            dirty: true,
+            ahead_behind: None,
+            ..RepoVersion::default()
+        };
+        let manifest = EnvironmentManifest::new(
+            "/obs-env",
+            vec![ts_wep.clone(), RepoVersion::new("cwfs", "0.3.1")],
+        );
+
+        assert_eq!(ts_wep.to_string(), "v1.2.0-3-g1111aaa");
+        assert_eq!(
+            manifest.to_string(),
+            "Obs. Env. Path: /obs-env.\ncwfs: 0.3.1\nts_wep: v1.2.0-3-g1111aaa"
+        );
+
+        let serialized = toml::to_string(&manifest).unwrap();
+        assert_eq!(
+            toml::from_str::<EnvironmentManifest>(&serialized).unwrap(),
+            manifest
+        );
+    }
+
+    #[test]
+    fn test_compare_manifests() {
+        let version = |name: &str, describe: &str, sha: Option<&str>| RepoVersion {
+            sha: sha.map(str::to_owned),
+            ..RepoVersion::new(name, describe)
+        };
+        let from = EnvironmentManifest::new(
+            "/obs-env",
+            vec![
+                version("cwfs", "0.3.1", None),
+                version("ts_wep", "v1.2.0", Some("1111aaaa")),
+                version("ts_xml", "v20.0.0", Some("3333cccc")),
+                version("ts_utils", "v1.0.0", Some("5555eeee")),
+            ],
+        );
+        let to = EnvironmentManifest::new(
+            "/other-env",
+            vec![
+                version("cwfs", "0.3.1", None),
+                version("ts_wep", "v1.2.0", Some("2222bbbb")),
+                version("ts_xml", "v20.0.0", Some("3333cccc")),
+                version("ts_ofc", "v0.1.0", None),
+            ],
+        );
+
+        let mut differences = from.compare(&to);
+        assert_eq!(
+            differences
+                .iter()
+                .map(|difference| difference.to_string())
+                .collect::<Vec<_>>(),
+            [
+                "ts_ofc: added at v0.1.0",
+                "ts_utils: removed, was at v1.0.0 (5555eeee)",
+                "ts_wep: v1.2.0 (1111aaaa) -> v1.2.0 (2222bbbb)",
+            ]
+        );
+        assert!(from.compare(&from).is_empty());
+        let mut dirty = from.clone();
+        dirty.repos[2].dirty = true;
+        assert_eq!(
+            from.compare(&dirty)[0].to_string(),
+            "ts_wep: v1.2.0 (1111aaaa) -> v1.2.0 (1111aaaa, dirty)"
+        );
+
+        differences[2].commits = Some(vec![CommitSummary {
+            id: "2222bbbb".to_owned(),
+            subject: "Fix the wavefront estimation".to_owned(),
+        }]);
+        assert_eq!(
+            differences[2].to_string(),
+            "ts_wep: v1.2.0 (1111aaaa) -> v1.2.0 (2222bbbb) (1 commits)\n    2222bbbb Fix the wavefront estimation"
+        );
+    }
+
+    #[test]
+    fn test_python_environment() {
+        let recorded = PythonEnvironment {
+            pip_freeze: [
+                "numpy==1.26.4",
+                "astropy==6.0.0",
+                "ts-wep @ file:///obs-env/ts_wep",
+            ]
+            .map(str::to_owned)
+            .to_vec(),
+            conda_env: Some("name: base\n".to_owned()),
+        };
+        let current = PythonEnvironment {
+            pip_freeze: [
+                "numpy==1.26.0",
+                "ts-wep @ file:///obs-env/ts_wep",
+                "scipy==1.12.0",
+            ]
+            .map(str::to_owned)
+            .to_vec(),
+            conda_env: None,
+        };
+
+        assert_eq!(
+            recorded.differences(&current),
+            [
+                "astropy: 6.0.0 -> none",
+                "numpy: 1.26.4 -> 1.26.0",
+                "scipy: none -> 1.12.0"
+            ]
+        );
+        assert!(recorded.differences(&recorded).is_empty());
+
+        let mut manifest =
+            EnvironmentManifest::new("/obs-env", vec![RepoVersion::new("cwfs", "0.3.1")]);
+        manifest.python_env = Some(recorded);
+        assert_eq!(
+            toml::from_str::<EnvironmentManifest>(&manifest.to_toml()).unwrap(),
+            manifest
+        );
+    }
+}
@@ -0,0 +1,101 @@
+//! Record of the last operation that set the versions of the environment,
+//! kept in `.obs_env/metadata.json` by
+//! [`ObservingEnvironment::write_metadata`](crate::ObservingEnvironment::write_metadata).
+use crate::{audit, error::ObsEnvError, manifest::EnvironmentManifest};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{self, Display},
+    fs::read_to_string,
+    io::ErrorKind,
+    path::Path,
+};
+
+/// Version of this tool, recorded in the metadata.
+pub const TOOL_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// How the environment was last set up, and the versions it was left at.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct EnvMetadata {
+    /// Version of the tool that wrote the metadata.
+    pub tool_version: String,
+    /// When the metadata was written, in seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// Action that set the versions, e.g. "setup".
+    pub action: String,
+    /// Branch of the base environment in use.
+    pub base_branch: String,
+    /// User that ran the action.
+    pub user: String,
+    /// Versions of the repositories after the action.
+    pub manifest: EnvironmentManifest,
+}
+
+impl Display for EnvMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Last {} by {} on {} (base branch {}, manage_obs_env {})",
+            self.action,
+            self.user,
+            audit::format_utc(self.timestamp),
+            self.base_branch,
+            self.tool_version
+        )?;
+        for version in self.manifest.repos.iter() {
+            write!(f, "\n  {}: {}", version.name, version.describe)?;
+        }
+        Ok(())
+    }
+}
+
+/// Metadata read from `path`, or none if it was never written.
+pub fn read(path: &Path) -> Result<Option<EnvMetadata>, ObsEnvError> {
+    let content = match read_to_string(path) {
+        Ok(content) => content,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(ObsEnvError::io(path, "read metadata", error)),
+    };
+    serde_json::from_str(&content)
+        .map(Some)
+        .map_err(|error| ObsEnvError::InvalidConfig {
+            message: format!("{}: {error}", path.display()),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read, EnvMetadata, TOOL_VERSION};
+    use crate::manifest::{EnvironmentManifest, RepoVersion};
+    use std::fs::write;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_read_metadata() {
+        let root = TempDir::new().unwrap();
+        let path = root.path().join("metadata.json");
+        assert_eq!(read(&path).unwrap(), None);
+
+        let metadata = EnvMetadata {
+            tool_version: TOOL_VERSION.to_owned(),
+            timestamp: 1_700_000_000,
+            action: "reset".to_owned(),
+            base_branch: "main".to_owned(),
+            user: "saluser".to_owned(),
+            manifest: EnvironmentManifest::new(
+                "/obs-env",
+                vec![RepoVersion::new("ts_wep", "v1.2.0")],
+            ),
+        };
+        write(&path, serde_json::to_string(&metadata).unwrap()).unwrap();
+        assert_eq!(read(&path).unwrap(), Some(metadata.clone()));
+        assert_eq!(
+            metadata.to_string(),
+            format!(
+                "Last reset by saluser on 2023-11-14 22:13:20 (base branch main, manage_obs_env {TOOL_VERSION})\n  ts_wep: v1.2.0"
+            )
+        );
+
+        write(&path, "{").unwrap();
+        assert!(read(&path).is_err());
+    }
+}
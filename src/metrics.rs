@@ -0,0 +1,151 @@
+//! Health of an environment as Prometheus gauges, written by
+//! [`ObservingEnvironment::metrics`](crate::ObservingEnvironment::metrics)
+//! for the textfile collector of node_exporter.
+use std::fmt::Write;
+
+/// State of one repository of the environment.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RepoMetrics {
+    pub repo: String,
+    /// Whether tracked files were changed since HEAD.
+    pub dirty: bool,
+    /// Commits of origin the checked out branch does not have, as of the
+    /// last fetch; none if HEAD is detached or has no upstream.
+    pub behind_origin: Option<usize>,
+    /// Whether the version checked out is the base version; none if the
+    /// base versions are not known.
+    pub matches_base: Option<bool>,
+}
+
+/// State of the environment at `env_path`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EnvMetrics {
+    pub env_path: String,
+    /// Repositories of the environment that are cloned.
+    pub repos: Vec<RepoMetrics>,
+    /// When the environment was last set up, reset or given a manifest,
+    /// in seconds since the Unix epoch.
+    pub last_reset: Option<u64>,
+    /// Whether the last operation recorded in the audit log succeeded.
+    pub last_run_success: Option<bool>,
+}
+
+impl EnvMetrics {
+    /// Metrics in the Prometheus text exposition format, labelled with the
+    /// environment path so several environments can be scraped together.
+    pub fn to_prometheus(&self) -> String {
+        let env = escape(&self.env_path);
+        let mut content = String::new();
+        let mut gauge = |name: &str, help: &str, samples: Vec<(Option<&str>, u64)>| {
+            if samples.is_empty() {
+                return;
+            }
+            let _ = writeln!(content, "# HELP {name} {help}\n# TYPE {name} gauge");
+            for (repo, value) in samples {
+                match repo {
+                    Some(repo) => {
+                        let repo = escape(repo);
+                        let _ =
+                            writeln!(content, "{name}{{env=\"{env}\",repo=\"{repo}\"}} {value}");
+                    }
+                    None => {
+                        let _ = writeln!(content, "{name}{{env=\"{env}\"}} {value}");
+                    }
+                }
+            }
+        };
+        gauge(
+            "obs_env_repo_dirty",
+            "Whether tracked files of the repository were changed since HEAD.",
+            self.repos
+                .iter()
+                .map(|repo| (Some(repo.repo.as_str()), u64::from(repo.dirty)))
+                .collect(),
+        );
+        gauge(
+            "obs_env_repo_behind_origin",
+            "Commits of origin the checked out branch does not have, as of the last fetch.",
+            self.repos
+                .iter()
+                .filter_map(|repo| Some((Some(repo.repo.as_str()), repo.behind_origin? as u64)))
+                .collect(),
+        );
+        gauge(
+            "obs_env_repo_matches_base",
+            "Whether the repository is at its base environment version.",
+            self.repos
+                .iter()
+                .filter_map(|repo| Some((Some(repo.repo.as_str()), u64::from(repo.matches_base?))))
+                .collect(),
+        );
+        gauge(
+            "obs_env_last_reset_timestamp",
+            "When the environment was last set up, reset or given a manifest, in seconds since the Unix epoch.",
+            self.last_reset.map(|last_reset| (None, last_reset)).into_iter().collect(),
+        );
+        gauge(
+            "obs_env_last_run_success",
+            "Whether the last operation modifying the environment succeeded.",
+            self.last_run_success
+                .map(|success| (None, u64::from(success)))
+                .into_iter()
+                .collect(),
+        );
+        content
+    }
+}
+
+/// `value` escaped for a label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EnvMetrics, RepoMetrics};
+
+    #[test]
+    fn test_prometheus_metrics() {
+        let metrics = EnvMetrics {
+            env_path: "/obs-env".to_owned(),
+            repos: vec![
+                RepoMetrics {
+                    repo: "cwfs".to_owned(),
+                    dirty: true,
+                    behind_origin: None,
+                    matches_base: Some(false),
+                },
+                RepoMetrics {
+                    repo: "ts_wep".to_owned(),
+                    dirty: false,
+                    behind_origin: Some(3),
+                    matches_base: Some(true),
+                },
+            ],
+            last_reset: Some(1_700_000_000),
+            last_run_success: None,
+        };
+        assert_eq!(
+            metrics.to_prometheus(),
+            concat!(
+                "# HELP obs_env_repo_dirty Whether tracked files of the repository were changed since HEAD.\n",
+                "# TYPE obs_env_repo_dirty gauge\n",
+                "obs_env_repo_dirty{env=\"/obs-env\",repo=\"cwfs\"} 1\n",
+                "obs_env_repo_dirty{env=\"/obs-env\",repo=\"ts_wep\"} 0\n",
+                "# HELP obs_env_repo_behind_origin Commits of origin the checked out branch does not have, as of the last fetch.\n",
+                "# TYPE obs_env_repo_behind_origin gauge\n",
+                "obs_env_repo_behind_origin{env=\"/obs-env\",repo=\"ts_wep\"} 3\n",
+                "# HELP obs_env_repo_matches_base Whether the repository is at its base environment version.\n",
+                "# TYPE obs_env_repo_matches_base gauge\n",
+                "obs_env_repo_matches_base{env=\"/obs-env\",repo=\"cwfs\"} 0\n",
+                "obs_env_repo_matches_base{env=\"/obs-env\",repo=\"ts_wep\"} 1\n",
+                "# HELP obs_env_last_reset_timestamp When the environment was last set up, reset or given a manifest, in seconds since the Unix epoch.\n",
+                "# TYPE obs_env_last_reset_timestamp gauge\n",
+                "obs_env_last_reset_timestamp{env=\"/obs-env\"} 1700000000\n",
+            )
+        );
+    }
+}
@@ -0,0 +1,48 @@
+//! Copies of the refs the environment is deployed at on a backup git
+//! server, which survives outages of origin, pushed by
+//! [`ObservingEnvironment::mirror`](crate::ObservingEnvironment::mirror).
+use serde::Serialize;
+use std::fmt::{self, Display};
+
+/// Placeholder of a mirror url template replaced by the repository name,
+/// e.g. in `git@backup.example.com:obs-env/{repo}.git`.
+pub const REPO_PLACEHOLDER: &str = "{repo}";
+
+/// Url of the mirror of `repo_name`, from `template`.
+pub fn mirror_url(template: &str, repo_name: &str) -> String {
+    template.replace(REPO_PLACEHOLDER, repo_name)
+}
+
+/// Reference pushed to a mirror.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct MirroredRef {
+    /// Local reference, or commit of a detached HEAD.
+    pub source: String,
+    /// Reference it is pushed to on the mirror.
+    pub destination: String,
+}
+
+impl MirroredRef {
+    /// Refspec pushing the reference, replacing what the mirror has.
+    pub fn refspec(&self) -> String {
+        format!("+{}:{}", self.source, self.destination)
+    }
+}
+
+/// References of a repository pushed to its mirror.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Mirror {
+    pub repo: String,
+    pub url: String,
+    pub refs: Vec<MirroredRef>,
+}
+
+impl Display for Mirror {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.repo, self.url)?;
+        for mirrored in self.refs.iter() {
+            write!(f, "\n  {} -> {}", mirrored.source, mirrored.destination)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+use crate::error::ObsEnvError;
+pub use crate::git_backend::TransferProgress;
+use std::sync::Arc;
+
+/// Receives events about the repositories an
+/// [`ObservingEnvironment`](crate::ObservingEnvironment) works on, e.g. to
+/// show progress.
+///
+/// Events are emitted while cloning the repositories and resetting the
+/// environment. Every method does nothing by default, so implementations
+/// only need the ones they care about.
+///
+/// ```
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use ts_observing_environment::observer::ObsEnvObserver;
+///
+/// #[derive(Default)]
+/// struct CountDone(AtomicUsize);
+///
+/// impl ObsEnvObserver for CountDone {
+///     fn on_repo_done(&self, _repo: &str) {
+///         self.0.fetch_add(1, Ordering::Relaxed);
+///     }
+/// }
+/// ```
+pub trait ObsEnvObserver: Send + Sync {
+    /// `operation` (e.g. "clone" or "reset") started on `repo`.
+    fn on_repo_start(&self, _repo: &str, _operation: &str) {}
+
+    /// Objects were transferred from the remote of `repo`.
+    fn on_transfer_progress(&self, _repo: &str, _progress: &TransferProgress) {}
+
+    /// The operation on `repo` completed.
+    fn on_repo_done(&self, _repo: &str) {}
+
+    /// The operation on `repo` failed with `error`.
+    fn on_repo_failed(&self, _repo: &str, _error: &ObsEnvError) {}
+}
+
+/// [`ObsEnvObserver`] ignoring every event, used when none is given.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoopObserver;
+
+impl ObsEnvObserver for NoopObserver {}
+
+/// Shared observers, so the caller can keep a handle on the observer given
+/// to the environment.
+impl<T: ObsEnvObserver + ?Sized> ObsEnvObserver for Arc<T> {
+    fn on_repo_start(&self, repo: &str, operation: &str) {
+        (**self).on_repo_start(repo, operation)
+    }
+
+    fn on_transfer_progress(&self, repo: &str, progress: &TransferProgress) {
+        (**self).on_transfer_progress(repo, progress)
+    }
+
+    fn on_repo_done(&self, repo: &str) {
+        (**self).on_repo_done(repo)
+    }
+
+    fn on_repo_failed(&self, repo: &str, error: &ObsEnvError) {
+        (**self).on_repo_failed(repo, error)
+    }
+}
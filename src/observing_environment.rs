@@ -1,20 +1,105 @@
-use crate::error::ObsEnvError;
-use git2::{build::CheckoutBuilder, DescribeOptions, Error, FetchOptions, Repository};
-use log::{debug, trace};
+pub use crate::git_backend::in_progress_state;
+use crate::repos::{validate_repo_specs, RepoOverride, RepoSource, RepoSpec, Repos};
+use crate::{
+    audit::{self, AuditEntry, AuditOutcome, HistoryFilter, RepoChange},
+    backup::{self, Backup, BACKUP_PREFIX},
+    branches::{PrunedRemotes, StaleBranch, StaleBranches, StaleReason},
+    clone_env::{ClonedRepo, LocalChanges},
+    config_check::ConfigCheck,
+    deviation::BaseDeviation,
+    env_report::{EnvReport, ReportOptions, ReportSection, SectionContent},
+    environments,
+    error::ObsEnvError,
+    eups::Eups,
+    git_backend::{
+        self, CloneFilter, CloneOptions, CommitInfo, CommitQuery, Git2Backend, GitBackend,
+        Identity, TransferObserver, TransferProgress,
+    },
+    hosts::RepoUrl,
+    lock::{self, EnvLock},
+    lockfile::{Drift, LockFile, LOCK_FILE_NAME},
+    manifest::{AheadBehind, EnvironmentManifest, RepoDifference, RepoVersion},
+    metadata::{self, EnvMetadata, TOOL_VERSION},
+    metrics::{EnvMetrics, RepoMetrics},
+    mirror::{self, Mirror, MirroredRef},
+    observer::{NoopObserver, ObsEnvObserver},
+    outdated::{OutdatedReport, RepoStaleness},
+    parallel,
+    permissions::{SharedAccess, SHARED_REPOSITORY},
+    pip::PipInstall,
+    preflight::PathCheck,
+    repair::{self, Repair, RepoBlocker, RepoDamage, RepoDiagnosis},
+    retry::{RetryBackend, RetryPolicy},
+    setup::{EnvSurvey, RepoPresence, RepoSetup, RepoSetupOutcome, SetupReport},
+    snapshot::{self, Snapshot, SNAPSHOT_EXTENSION},
+    status::WorkingTree,
+    timing::Timings,
+    watch::ManifestSource,
+};
+use clap::ValueEnum;
+use git2::{
+    Error, ErrorClass, ErrorCode, Repository, Worktree, WorktreeAddOptions, WorktreePruneOptions,
+};
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 use std::{
+    cell::Cell,
     collections::BTreeMap,
-    fs::{create_dir, File},
-    io::{BufRead, BufReader},
-    path::Path,
+    fmt::{self, Display},
+    fs::{
+        create_dir, create_dir_all, read_dir, read_to_string, remove_dir_all, remove_file, rename,
+        write,
+    },
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 const REPO_VERSION_REGEXP: &str = r"(?P<name>[a-zA-Z0-9_]*)=(?P<version>[a-zA-Z0-9._]*)";
 const VALID_VERSION: &str = r"^(?P<major>[0-9]*)\.(?P<minor>[0-9]*)\.(?P<patch>[0-9]*)";
+const GITHUB_URL: &str = r"https://github.com/";
+/// Directory under the environment path where the tool keeps its own data.
+pub(crate) const OBS_ENV_DIR: &str = ".obs_env";
+/// Directory of the cached base environment versions, in OBS_ENV_DIR.
+const VERSIONS_CACHE_DIR: &str = "cache";
+/// Bare clone of the base environment source repository, in OBS_ENV_DIR.
+const BASE_ENV_CACHE: &str = "base_env.git";
+/// Bare clone of the repository of the manifest watched, in OBS_ENV_DIR.
+const MANIFEST_SOURCE_CACHE: &str = "manifest_source.git";
+/// Lock file of the environment, in OBS_ENV_DIR.
+const LOCK_FILE: &str = "lock";
+/// Audit log of the environment, in OBS_ENV_DIR.
+const AUDIT_LOG: &str = "audit.log";
+/// Directory of the snapshots of the environment, in OBS_ENV_DIR.
+const SNAPSHOTS_DIR: &str = "snapshots";
+/// Metadata of the last setup of the environment, in OBS_ENV_DIR.
+pub(crate) const METADATA_FILE: &str = "metadata.json";
+/// Shell script setting up the paths of the environment, in the
+/// environment path.
+const SETUP_SCRIPT: &str = "setup_obs_env.sh";
+/// Depths, in commits, a shallow repository is deepened to in turn when a
+/// version is not in its history, before all of it is fetched.
+const DEEPEN_DEPTHS: [u32; 2] = [100, 1000];
+/// Branch names that are looked for under `tickets/` when they do not
+/// exist as given.
+pub const DEFAULT_TICKET_PATTERNS: &[&str] = &[r"DM-\d+"];
 
+/// A set of git repositories checked out under a common path, together with
+/// the base environment that defines their official versions.
 pub struct ObservingEnvironment {
     /// List of repositories that belong to the observing environment.
-    repositories: BTreeMap<String, String>,
+    repositories: BTreeMap<String, RepoSpec>,
+    /// Where the list of repositories comes from.
+    repositories_source: RepoSource,
+    /// Repositories taken from a fork, mapping repository name to the
+    /// owner of the fork.
+    forks: BTreeMap<String, String>,
+    /// Versions checked out by resets and manifests instead of the base or
+    /// manifest version, by repository name.
+    version_overrides: BTreeMap<String, String>,
+    /// Repositories left out with
+    /// [`exclude`](ObservingEnvironmentBuilder::exclude), sorted.
+    excluded: Vec<String>,
     /// Organzation url for the base env sourve repository
     base_env_source_org: String,
     /// Repository with the base environment version definitions
@@ -22,529 +107,8460 @@ pub struct ObservingEnvironment {
     /// File path in the base environment version definitions repository
     /// with the version information
     base_env_def_file: String,
-    /// Location where the repositories should be placed in the host.
+    /// Location where the repositories should be placed in the host. A
+    /// symbolic link is resolved to its target when creating the
+    /// environment.
     destination: String,
+    /// Branch of the base environment source repository with the versions.
+    base_env_branch: String,
+    /// Number of commits to fetch when cloning, for shallow clones.
+    clone_depth: Option<u32>,
+    /// Objects to leave out when cloning, for partial clones.
+    clone_filter: Option<CloneFilter>,
+    /// Abort merges, rebases and similar operations left in progress in a
+    /// repository instead of refusing to operate on it.
+    abort_in_progress: bool,
+    /// Do not access the network, working only with what is available
+    /// locally.
+    offline: bool,
+    /// Re-fetch the base environment source cache from scratch.
+    refresh_base_cache: bool,
+    /// Local file or directory to read the base environment versions from,
+    /// instead of the base environment source repository.
+    base_env_local_source: Option<String>,
+    /// Point of the history of the base environment branch its versions
+    /// are read at.
+    base_env_revision: BaseEnvRevision,
+    /// Location of shared bare repositories. When set, repositories in the
+    /// environment are linked worktrees of these instead of full clones.
+    object_store: Option<String>,
+    /// Git operations are carried out through this backend.
+    backend: Box<dyn GitBackend>,
+    /// Receives events about the repositories being worked on.
+    observer: Arc<dyn ObsEnvObserver>,
+    /// Declares the repositories as EUPS products, if set.
+    eups: Option<Eups>,
+    /// Installs the repositories in editable mode, if set.
+    pip_install: Option<PipInstall>,
+    /// Number of repositories inspected or reset at the same time.
+    jobs: usize,
+    /// How long the cached base environment versions are used before
+    /// reading them from the source again. They are not cached if unset.
+    base_versions_ttl: Option<Duration>,
+    /// What setting up does with the repositories already cloned.
+    existing_clones: ExistingClones,
+    /// What operations on every repository do once one fails.
+    on_error: ErrorPolicy,
+    /// Whether diagnosing the repositories reads all their objects.
+    deep_check: bool,
+    /// Whether setting up requires the environment path to be empty.
+    fresh: bool,
+    /// Whether resetting to the base environment keeps the local commits
+    /// it moves away from on a backup branch.
+    backup: bool,
+    /// Bytes that must be available on the volume of the environment path
+    /// before modifying it.
+    min_free_space: u64,
+    /// Identity of the commits made by the manager in repositories with no
+    /// identity configured.
+    identity: Identity,
+    /// Directory of the base environment caches, instead of `.obs_env` in
+    /// the environment path.
+    cache_dir: Option<PathBuf>,
+    /// How long [`lock`](ObservingEnvironment::lock) waits for another
+    /// run to release the environment.
+    lock_timeout: Duration,
+    /// Time spent in each phase of the operations.
+    timings: Arc<Timings>,
+    /// How the environment is shared with the group of its operators.
+    shared_access: SharedAccess,
+    /// Branch names checked out from `tickets/<name>` when they do not
+    /// exist as given.
+    ticket_patterns: Vec<Regex>,
 }
 
 impl Default for ObservingEnvironment {
     fn default() -> ObservingEnvironment {
         ObservingEnvironment {
-            repositories: BTreeMap::from_iter([
-                (
-                    "atmospec".to_owned(),
-                    r"https://github.com/lsst/".to_owned(),
-                ),
-                ("cwfs".to_owned(), r"https://github.com/lsst-ts/".to_owned()),
-                (
-                    "Spectractor".to_owned(),
-                    r"https://github.com/lsst-dm/".to_owned(),
-                ),
-                (
-                    "summit_extras".to_owned(),
-                    r"https://github.com/lsst-sitcom/".to_owned(),
-                ),
-                (
-                    "summit_utils".to_owned(),
-                    r"https://github.com/lsst-sitcom/".to_owned(),
-                ),
-                (
-                    "ts_config_attcs".to_owned(),
-                    r"https://github.com/lsst-ts/".to_owned(),
-                ),
-                (
-                    "ts_config_ocs".to_owned(),
-                    r"https://github.com/lsst-ts/".to_owned(),
-                ),
-                (
-                    "ts_externalscripts".to_owned(),
-                    r"https://github.com/lsst-ts/".to_owned(),
-                ),
-                (
-                    "ts_observatory_control".to_owned(),
-                    r"https://github.com/lsst-ts/".to_owned(),
-                ),
-                (
-                    "ts_observing_utilities".to_owned(),
-                    r"https://github.com/lsst-ts/".to_owned(),
-                ),
-                (
-                    "ts_standardscripts".to_owned(),
-                    r"https://github.com/lsst-ts/".to_owned(),
-                ),
-                (
-                    "ts_wep".to_owned(),
-                    r"https://github.com/lsst-ts/".to_owned(),
-                ),
-            ]),
+            repositories: Repos::value_variants()
+                .iter()
+                .map(|repo| (repo.get_name().to_owned(), RepoSpec::from(repo)))
+                .collect(),
+            repositories_source: RepoSource::BuiltIn,
+            forks: BTreeMap::new(),
+            version_overrides: BTreeMap::new(),
+            excluded: Vec::new(),
             base_env_source_org: r"https://github.com/lsst-ts/".to_owned(),
             base_env_source_repo: "ts_cycle_build".to_owned(),
             base_env_def_file: "cycle/cycle.env".to_owned(),
             destination: "/obs-env".to_owned(),
+            base_env_branch: "main".to_owned(),
+            clone_depth: None,
+            clone_filter: None,
+            abort_in_progress: false,
+            offline: false,
+            refresh_base_cache: false,
+            base_env_local_source: None,
+            base_env_revision: BaseEnvRevision::Latest,
+            object_store: None,
+            backend: Box::new(Git2Backend),
+            observer: Arc::new(NoopObserver),
+            eups: None,
+            pip_install: None,
+            jobs: parallel::default_jobs(),
+            base_versions_ttl: None,
+            existing_clones: ExistingClones::Skip,
+            on_error: ErrorPolicy::KeepGoing,
+            deep_check: false,
+            fresh: false,
+            backup: true,
+            min_free_space: 0,
+            identity: Identity::default(),
+            cache_dir: None,
+            lock_timeout: Duration::from_secs(30),
+            timings: Arc::new(Timings::new()),
+            shared_access: SharedAccess::default(),
+            ticket_patterns: DEFAULT_TICKET_PATTERNS
+                .iter()
+                .map(|pattern| ticket_pattern(pattern).unwrap())
+                .collect(),
         }
     }
 }
 
 impl ObservingEnvironment {
+    /// Environment with the default set of repositories, placed in `dest`.
+    ///
+    /// ```
+    /// use ts_observing_environment::ObservingEnvironment;
+    ///
+    /// let obs_env = ObservingEnvironment::with_destination("/net/obs-env/auto_base_packages");
+    /// assert!(obs_env.get_repository_url("ts_wep").is_some());
+    /// ```
     pub fn with_destination(dest: &str) -> ObservingEnvironment {
         ObservingEnvironment {
-            destination: dest.to_owned(),
+            destination: resolve_destination(dest),
             ..Default::default()
         }
     }
 
-    pub fn summarize(&self) -> String {
-        format!(
-            "Obs. Env. Path: {}.\nNumber of repositories: {}",
-            self.destination,
-            self.repositories.len()
-        )
+    /// Environment made of `repos` only, placed in `dest`.
+    ///
+    /// ```
+    /// use ts_observing_environment::{repos::RepoSpec, ObservingEnvironment};
+    ///
+    /// let obs_env = ObservingEnvironment::with_destination_and_repos(
+    ///     "/obs-env",
+    ///     vec![RepoSpec::new("ts_wep", "https://github.com/lsst-ts/ts_wep")],
+    /// )?;
+    /// assert_eq!(obs_env.repos().count(), 1);
+    /// # Ok::<(), ts_observing_environment::ObsEnvError>(())
+    /// ```
+    pub fn with_destination_and_repos(
+        dest: &str,
+        repos: Vec<RepoSpec>,
+    ) -> Result<ObservingEnvironment, ObsEnvError> {
+        ObservingEnvironment::builder()
+            .destination(dest)
+            .repository_specs(repos, RepoSource::Custom)
+            .build()
     }
-    /// Check if destination directory exists.
-    pub fn create_path(&self) -> Result<(), std::io::Error> {
-        let destination = Path::new(&self.destination);
 
-        if !destination.exists() {
-            create_dir(&self.destination)
-        } else {
+    /// Builder to configure an environment.
+    pub fn builder() -> ObservingEnvironmentBuilder {
+        ObservingEnvironmentBuilder::default()
+    }
+
+    /// Branch of the base environment source repository the environment
+    /// is based on.
+    pub fn get_base_env_branch(&self) -> &str {
+        &self.base_env_branch
+    }
+
+    /// Repositories of the environment, sorted by name.
+    pub fn get_repositories(&self) -> impl Iterator<Item = &RepoSpec> {
+        self.repositories.values()
+    }
+
+    /// Handles on the repositories of the environment, sorted by name.
+    ///
+    /// ```
+    /// use ts_observing_environment::ObservingEnvironment;
+    ///
+    /// let obs_env = ObservingEnvironment::with_destination("/does/not/exist");
+    /// for repo in obs_env.repos() {
+    ///     assert!(!repo.exists(), "{} is not cloned", repo.name());
+    /// }
+    /// ```
+    pub fn repos(&self) -> impl Iterator<Item = RepoHandle<'_>> {
+        self.repositories
+            .values()
+            .map(|repo_spec| RepoHandle::new(self, repo_spec))
+    }
+
+    /// Handle on the repository `repo_name` of the environment.
+    pub fn repo(&self, repo_name: &str) -> Result<RepoHandle<'_>, ObsEnvError> {
+        self.repositories
+            .get(repo_name)
+            .map(|repo_spec| RepoHandle::new(self, repo_spec))
+            .ok_or_else(|| ObsEnvError::RepoNotFound {
+                repo: repo_name.to_owned(),
+            })
+    }
+
+    /// Where the list of repositories comes from.
+    pub fn get_repositories_source(&self) -> &RepoSource {
+        &self.repositories_source
+    }
+
+    /// Take a repository from the fork owned by `owner` instead of its
+    /// canonical organization.
+    pub fn set_fork(&mut self, repo_name: &str, owner: &str) -> Result<(), ObsEnvError> {
+        if self.repositories.contains_key(repo_name) {
+            self.forks.insert(repo_name.to_owned(), owner.to_owned());
             Ok(())
+        } else {
+            Err(ObsEnvError::RepoNotFound {
+                repo: repo_name.to_owned(),
+            })
         }
     }
 
-    /// Clone repositories into the environment path.
-    pub fn clone_repositories(&self) -> Vec<Result<Repository, Error>> {
-        self.repositories
+    /// Repositories left out of the environment with
+    /// [`exclude`](ObservingEnvironmentBuilder::exclude), sorted, for the
+    /// actions to report them skipped.
+    pub fn excluded(&self) -> &[String] {
+        &self.excluded
+    }
+
+    /// Leave the repositories named in `excluded` out of the environment,
+    /// with their fork and version override.
+    fn exclude(&mut self, excluded: &[String]) {
+        self.excluded = excluded
             .iter()
-            .filter(|(repo_name, _)| !Path::new(&self.destination).join(repo_name).exists())
-            .map(|(repo_name, org)| {
-                log::debug!("Cloning: {repo_name}");
-                Repository::clone(
-                    &format!("{}/{}", org, repo_name),
-                    Path::new(&self.destination).join(repo_name),
-                )
-            })
-            .collect()
+            .filter(|repo_name| self.repositories.remove(*repo_name).is_some())
+            .cloned()
+            .collect();
+        self.excluded.sort();
+        self.excluded.dedup();
+        for repo_name in self.excluded.iter() {
+            self.forks.remove(repo_name);
+            self.version_overrides.remove(repo_name);
+        }
     }
 
-    /// Reset all repositories to their official version.
-    pub fn reset_base_environment(&self, base_env_branch: &str) -> Result<(), Vec<ObsEnvError>> {
-        match self.get_base_env_versions(base_env_branch) {
-            Ok(obs_env_versions) => {
-                let reset_result: Vec<ObsEnvError> = obs_env_versions
-                    .into_iter()
-                    .map(|(repo, version)| self.reset_index_to_version(&repo, &version))
-                    .into_iter()
-                    .filter(|result| result.is_err())
-                    .map(|err| err.unwrap_err())
-                    .collect();
-
-                if reset_result.is_empty() {
-                    Ok(())
-                } else {
-                    Err(reset_result)
-                }
-            }
-            Err(err_get_base_env_versions) => Err(vec![err_get_base_env_versions]),
+    /// Restrict the environment to the repositories named in `only` or
+    /// belonging to one of `groups`.
+    fn select(&mut self, only: &[String], groups: &[String]) -> Result<(), ObsEnvError> {
+        if let Some(repo_name) = only
+            .iter()
+            .find(|repo_name| !self.repositories.contains_key(*repo_name))
+        {
+            return Err(ObsEnvError::RepoNotFound {
+                repo: repo_name.to_owned(),
+            });
         }
+        if let Some(group) = groups.iter().find(|group| {
+            !self
+                .repositories
+                .values()
+                .any(|repo_spec| repo_spec.groups.contains(group))
+        }) {
+            return Err(ObsEnvError::InvalidConfig {
+                message: format!("No repository belongs to group {group}"),
+            });
+        }
+
+        self.repositories.retain(|repo_name, repo_spec| {
+            only.contains(repo_name) || repo_spec.groups.iter().any(|group| groups.contains(group))
+        });
+        let repositories = &self.repositories;
+        self.forks
+            .retain(|repo_name, _| repositories.contains_key(repo_name));
+        Ok(())
     }
 
-    /// Checkout branch on specified repository.
-    pub fn checkout_branch(&self, repo_name: &str, branch_name: &str) -> Result<(), ObsEnvError> {
-        if self.repositories.contains_key(repo_name) {
-            match Repository::open(Path::new(&self.destination).join(repo_name)) {
-                Ok(repository) => match checkout_branch(&repository, branch_name) {
-                    Ok(_) => Ok(()),
-                    Err(error) => Err(ObsEnvError::GIT(format!(
-                        "Failed to checkout branch {branch_name}: {}",
-                        error.message()
-                    ))),
-                },
-                Err(error) => Err(ObsEnvError::GIT(format!(
-                    "Failed to open repository {repo_name}: {}",
-                    error.message()
-                ))),
+    /// Change the url or default branch of a repository.
+    pub fn set_repo_override(
+        &mut self,
+        repo_name: &str,
+        repo_override: &RepoOverride,
+    ) -> Result<(), ObsEnvError> {
+        match self.repositories.get(repo_name) {
+            Some(repo_spec) => {
+                let repo_spec = repo_spec.clone().with_override(repo_override);
+                validate_repo_specs(std::slice::from_ref(&repo_spec))?;
+                self.repositories.insert(repo_name.to_owned(), repo_spec);
+                Ok(())
             }
-        } else {
-            Err(ObsEnvError::ERROR(format!(
-                "Repository {repo_name} not in the list of managed repositories."
-            )))
+            None => Err(ObsEnvError::RepoNotFound {
+                repo: repo_name.to_owned(),
+            }),
         }
     }
 
-    /// Update the base environment source file.
-    fn update_base_env_source(&self, base_env_branch: &str) -> Result<(), Error> {
-        let base_env_source_repo = self.get_base_env_source_repo()?;
+    /// Check out `version` of `repo_name` when resetting the environment or
+    /// applying a manifest, instead of the base or manifest version.
+    pub fn set_version_override(
+        &mut self,
+        repo_name: &str,
+        version: &str,
+    ) -> Result<(), ObsEnvError> {
+        if !self.repositories.contains_key(repo_name) {
+            return Err(ObsEnvError::RepoNotFound {
+                repo: repo_name.to_owned(),
+            });
+        }
+        if version.is_empty() {
+            return Err(ObsEnvError::InvalidConfig {
+                message: format!("The version override of {repo_name} cannot be empty"),
+            });
+        }
+        self.version_overrides
+            .insert(repo_name.to_owned(), version.to_owned());
+        Ok(())
+    }
 
-        let mut remote = base_env_source_repo.find_remote("origin")?;
+    /// Versions checked out instead of the base or manifest version, by
+    /// repository name.
+    pub fn version_overrides(&self) -> &BTreeMap<String, String> {
+        &self.version_overrides
+    }
 
-        remote.fetch(&[base_env_branch], None, None)?;
+    /// Create repositories as worktrees of bare clones stored in
+    /// `object_store`, so several environments can share their objects.
+    pub fn set_object_store(&mut self, object_store: &str) {
+        self.object_store = Some(object_store.to_owned());
+    }
 
-        let branch_main_remote = base_env_source_repo.find_branch(
-            &format!("/origin/{base_env_branch}"),
-            git2::BranchType::Remote,
-        )?;
+    /// Abort operations left in progress in a repository (merge, rebase,
+    /// cherry-pick, ...) before checking out or resetting it.
+    pub fn set_abort_in_progress(&mut self, abort_in_progress: bool) {
+        self.abort_in_progress = abort_in_progress;
+    }
 
-        let commit = branch_main_remote.get().peel_to_commit()?;
+    /// Work without network access: nothing is fetched and the base
+    /// environment versions are read from the local cache.
+    pub fn set_offline(&mut self, offline: bool) {
+        self.offline = offline;
+    }
 
-        let object = commit.as_object();
+    /// Discard the base environment source cache and fetch it again.
+    pub fn set_refresh_base_cache(&mut self, refresh_base_cache: bool) {
+        self.refresh_base_cache = refresh_base_cache;
+    }
 
-        base_env_source_repo.reset(object, git2::ResetType::Hard, None)
+    /// Read the base environment versions from a local path, either a
+    /// versions file or a checked-out copy of the base environment source
+    /// repository, without any network access.
+    pub fn set_base_env_source(&mut self, local_source: &str) {
+        self.base_env_local_source = Some(local_source.to_owned());
     }
 
-    fn get_base_env_source_repo(&self) -> Result<Repository, Error> {
-        let base_env_source_path = Path::new(&self.destination).join(&self.base_env_source_repo);
+    /// Url the repository is cloned and fetched from, taking forks into
+    /// account.
+    ///
+    /// Forks are on the server of the repository, with the same form of
+    /// url, except those of repositories that are not on a server, e.g.
+    /// local paths, which are taken from GitHub.
+    pub fn get_repository_url(&self, repo_name: &str) -> Option<String> {
+        match (self.forks.get(repo_name), self.repositories.get(repo_name)) {
+            (Some(owner), Some(repo_spec)) => Some(match RepoUrl::parse(&repo_spec.url) {
+                Some(url) => url.with_owner(owner).to_string(),
+                None => format!("{GITHUB_URL}{owner}/{repo_name}"),
+            }),
+            (None, Some(repo_spec)) => Some(repo_spec.url.clone()),
+            _ => None,
+        }
+    }
 
-        if !base_env_source_path.exists() {
-            // need to clone base env source repo
-            Repository::clone(
-                &format!("{}/{}", self.base_env_source_org, self.base_env_source_repo),
-                base_env_source_path,
-            )
+    /// Summary of the environment configuration and of its repositories,
+    /// displayed as a human readable text.
+    pub fn summarize(&self) -> EnvironmentSummary {
+        EnvironmentSummary {
+            env_path: self.destination.clone(),
+            base_branch: self.base_env_branch.clone(),
+            repositories_source: self.repositories_source.to_string(),
+            repos: self
+                .repos()
+                .map(|repo| RepoSummary {
+                    name: repo.name().to_owned(),
+                    url: repo.url(),
+                    present: repo.exists(),
+                    version: repo.version().ok().map(|version| version.describe),
+                })
+                .collect(),
+            forks: self.forks.clone(),
+            object_store: self.object_store.clone(),
+        }
+    }
+    /// Fetch refspecs from origin of the repository at `path`, unless
+    /// offline.
+    fn fetch_origin(
+        &self,
+        repo_name: &str,
+        path: &Path,
+        refspecs: &[&str],
+        download_tags: bool,
+    ) -> Result<(), Error> {
+        if self.offline {
+            log::debug!("Offline, not fetching {refspecs:?} into {repo_name}.");
+            Ok(())
         } else {
-            Repository::open(base_env_source_path.as_path())
+            log::debug!("Fetching {refspecs:?} (tags: {download_tags}) into {repo_name}");
+            self.timings.time("fetch", Some(repo_name), || {
+                self.backend.fetch(
+                    path,
+                    refspecs,
+                    download_tags,
+                    &self.transfer_progress(repo_name),
+                )
+            })
         }
     }
 
-    /// Get base versions of all the packages.
-    ///
-    /// This method will parse the base_env_def_file (e.g. cycle/cycle.env) to
-    /// get the versions of the base env packages.
-    pub fn get_base_env_versions(
+    /// Report transfers of `repo_name` to the observer.
+    fn transfer_progress<'a>(&'a self, repo_name: &'a str) -> impl TransferObserver + 'a {
+        RepoTransfer {
+            repo: repo_name,
+            progress: move |progress: &TransferProgress| {
+                self.observer.on_transfer_progress(repo_name, progress)
+            },
+        }
+    }
+
+    /// Run `operation` on `repo_name`, telling the observer when it starts
+    /// and how it ends.
+    fn observed<T>(
         &self,
-        base_env_branch: &str,
-    ) -> Result<BTreeMap<String, String>, ObsEnvError> {
-        match self.update_base_env_source(base_env_branch) {
-            Ok(_) => {
-                match self.load_base_env_def_file() {
-                    Ok(base_env_def) => {
-                        let base_env_versions: Vec<Option<&String>> = self
-                            .repositories
-                            .keys()
-                            .map(|repo_name| {
-                                base_env_def.iter().find(|line| line.starts_with(repo_name))
-                            })
-                            .collect();
-                        // This should never fail because we know REPO_VERSION_REGEXP is
-                        // valid.
-                        let regex = Regex::new(REPO_VERSION_REGEXP).unwrap();
-                        Ok(base_env_versions
-                            .into_iter()
-                            .filter(|name_version| name_version.is_some())
-                            .map(|name_version| regex.captures(name_version.unwrap()))
-                            .filter(|captured_name_version| captured_name_version.is_some())
-                            .map(|captured_name_version| {
-                                if let Some(captured_name_version) = captured_name_version {
-                                    (
-                                        captured_name_version["name"].to_owned(),
-                                        captured_name_version["version"].to_owned(),
-                                    )
-                                } else {
-                                    panic!("Could not read captured name/version");
-                                }
-                            })
-                            .collect())
-                    }
-                    Err(obs_env_err) => Err(obs_env_err),
-                }
-            }
-            Err(obs_env_err) => Err(ObsEnvError::ERROR(obs_env_err.to_string())),
+        repo_name: &str,
+        operation: &str,
+        op: impl FnOnce() -> Result<T, ObsEnvError>,
+    ) -> Result<T, ObsEnvError> {
+        self.observer.on_repo_start(repo_name, operation);
+        let result = op();
+        match &result {
+            Ok(_) => self.observer.on_repo_done(repo_name),
+            Err(error) => self.observer.on_repo_failed(repo_name, error),
         }
+        result
     }
 
-    /// Get current package versions.
-    pub fn get_current_env_versions(&self) -> BTreeMap<String, Result<String, ObsEnvError>> {
-        self.repositories
-            .keys()
-            .map(|repo_name| (repo_name.to_owned(), self.get_current_version(repo_name)))
-            .collect()
+    /// Time spent in each phase of the operations so far.
+    pub fn get_timings(&self) -> &Timings {
+        &self.timings
     }
 
-    /// Get current cycle/revision.
-    pub fn get_cycle_revision(&self, base_env_branch: &str) -> Result<String, ObsEnvError> {
-        match self.update_base_env_source(base_env_branch) {
-            Ok(_) => {
-                unimplemented!()
-            }
-            Err(obs_env_err) => Err(ObsEnvError::ERROR(obs_env_err.to_string())),
-        }
+    /// Whether the environment path, or its closest existing parent, can be
+    /// written to and has the
+    /// [minimum free space](ObservingEnvironmentBuilder::min_free_space).
+    pub fn check_path(&self) -> PathCheck {
+        PathCheck::new(Path::new(&self.destination), self.min_free_space)
     }
 
-    fn get_current_version(&self, repo_name: &str) -> Result<String, ObsEnvError> {
-        match Repository::open(Path::new(&self.destination).join(repo_name)) {
-            Ok(repository) => {
-                let mut opts = DescribeOptions::new();
+    /// Identity to make commits with in `repo_name`: the one configured
+    /// for it in git, or the [manager's](ObservingEnvironmentBuilder::identity).
+    /// The git configuration is left untouched.
+    pub fn commit_identity(&self, repo_name: &str) -> Result<Identity, ObsEnvError> {
+        let repo = self.repo(repo_name)?;
+        let identity = self
+            .backend
+            .identity(repo.path())
+            .map_err(|error| ObsEnvError::git(repo_name, repo.path(), "read identity", error))?;
+        Ok(identity.unwrap_or_else(|| self.identity.clone()))
+    }
 
-                match repository.describe(opts.describe_tags()) {
-                    Ok(description) => match description.format(None) {
-                        Ok(description) => Ok(description),
-                        Err(error) => Err(ObsEnvError::GIT(format!(
-                            "Error describing {repo_name}: {}",
-                            error.message()
-                        ))),
-                    },
-                    Err(_) => match repository.describe(opts.show_commit_oid_as_fallback(true)) {
-                        Ok(description) => match description.format(None) {
-                            Ok(description) => Ok(description),
-                            Err(error) => Err(ObsEnvError::GIT(format!(
-                                "Error describing {repo_name}: {}",
-                                error.message()
-                            ))),
-                        },
-                        Err(error) => Err(ObsEnvError::GIT(format!(
-                            "Error describing {repo_name}: {}",
-                            error.message()
-                        ))),
-                    },
-                }
-            }
-            Err(error) => Err(ObsEnvError::GIT(format!(
-                "Failed to open repository {repo_name}: {}",
-                error.message()
-            ))),
+    /// Fail before modifying the environment if
+    /// [`check_path`](Self::check_path) does not pass.
+    fn preflight(&self) -> Result<(), ObsEnvError> {
+        match self.check_path().error() {
+            Some(error) => Err(error),
+            None => Ok(()),
         }
     }
 
-    /// Read base_env_def_file and return the content.
-    fn load_base_env_def_file(&self) -> Result<Vec<String>, ObsEnvError> {
-        match File::open(
-            Path::new(&self.destination)
-                .join(&self.base_env_source_repo)
-                .join(&self.base_env_def_file),
-        ) {
-            Ok(file) => {
-                Ok(BufReader::new(file)
-                    .lines()
-                    .into_iter()
-                    .filter_map(|line| line.ok())
-                    .collect())
-                // Note it is safe to unwrap inside the map because of the filter.
+    /// Check if destination directory exists, creating it if needed.
+    ///
+    /// It fails first if [`check_path`](Self::check_path) does not pass.
+    /// With [`fresh`](ObservingEnvironmentBuilder::fresh), an existing
+    /// destination must be empty.
+    pub fn create_path(&self) -> Result<(), ObsEnvError> {
+        let destination = Path::new(&self.destination);
+        self.preflight()?;
+
+        let result = self.timings.time("create path", None, || {
+            if destination.is_symlink() && !destination.exists() {
+                // Creating the directory would fail on the link, or replace
+                // it.
+                Err(std::io::Error::other(
+                    "symbolic link to a missing directory",
+                ))
+            } else if !destination.exists() {
+                create_dir(destination)
+            } else if !destination.is_dir() {
+                Err(std::io::Error::other("not a directory"))
+            } else if self.fresh
+                && read_dir(destination)?
+                    .flatten()
+                    .any(|entry| entry.file_name() != OBS_ENV_DIR)
+            {
+                Err(std::io::Error::other("not empty"))
+            } else {
+                Ok(())
             }
-            Err(error) => Err(ObsEnvError::ERROR(error.to_string())),
+        });
+        result.map_err(|source| ObsEnvError::InvalidEnvPath {
+            path: destination.to_path_buf(),
+            source,
+        })?;
+        self.shared_access.apply(destination, false)?;
+        let obs_env_dir = destination.join(OBS_ENV_DIR);
+        if obs_env_dir.is_dir() {
+            self.shared_access.apply(&obs_env_dir, true)?;
         }
+        Ok(())
     }
 
-    /// Reset repo index to the provided version.
-    ///
-    /// The version string must have the following format <X>.<Y>.<Z><RT><RN>,
-    /// where:
-    ///     X, is the major version number.
-    ///     Y, is the minor version number.
-    ///     Z, is the patch version number.
-    ///     RT, is the type of the release. This should be empty if this is an
-    ///         official release or:
-    ///         a, alpha release.
-    ///         b, beta release.
-    ///         rc, release candidate.
-    ///     RN, is the major version number. If RT is provided than a release
-    ///         type number can also be provided.
+    /// Take the lock of the environment, `.obs_env/lock` in the environment
+    /// path, so that no other run modifies it until the lock is dropped.
     ///
-    /// Example valid release strings:
-    ///     0.1.0
-    ///     1.2.3
-    ///     1.0.0a, alpha release with no release number.
-    ///     1.0.0a1, alpha release with release number 1.
-    ///     1.0.0b5, beta release with release number 5.
-    ///     1.0.0rc3, release candidate with release number 3.
-    pub fn reset_index_to_version(&self, repo: &str, version: &str) -> Result<(), ObsEnvError> {
-        log::debug!("Resetting {repo} to {version}");
-        if let Ok(repository) = Repository::open(Path::new(&self.destination).join(repo)) {
-            let tag = ObservingEnvironment::expand_version_to_tag(version);
+    /// Waits for the [lock timeout](ObservingEnvironmentBuilder::lock_timeout)
+    /// if another run holds it, then fails with [`ObsEnvError::EnvLocked`].
+    /// The environment path is created if needed.
+    pub fn lock(&self) -> Result<EnvLock, ObsEnvError> {
+        // Reports an environment path that cannot be written to better
+        // than failing to create the lock file.
+        if let Some(error) = PathCheck::new(Path::new(&self.destination), 0).error() {
+            return Err(error);
+        }
+        let lock_dir = Path::new(&self.destination).join(OBS_ENV_DIR);
+        create_dir_all(&lock_dir).map_err(|error| ObsEnvError::io(&lock_dir, "create", error))?;
+        self.timings.time("lock", None, || {
+            EnvLock::acquire(&lock_dir.join(LOCK_FILE), self.lock_timeout)
+        })
+    }
 
-            match ObservingEnvironment::checkout_tag_or_branch(repository, &tag, version) {
-                Ok(()) => Ok(()),
-                Err(error) => Err(ObsEnvError::GIT(format!(
-                    "Could not checkout tag or branch for {repo}@{tag}[{version}]: {}",
-                    error.message().to_owned()
-                ))),
-            }
-        } else {
-            Err(ObsEnvError::GIT(format!(
-                "Failed to open repository: {repo}"
-            )))
+    /// Audit log of the environment, `.obs_env/audit.log` in the
+    /// environment path.
+    pub fn audit_log_path(&self) -> PathBuf {
+        Path::new(&self.destination)
+            .join(OBS_ENV_DIR)
+            .join(AUDIT_LOG)
+    }
+
+    /// Commit checked out in each repository, or none if it is not cloned
+    /// or has no commit, by repository name.
+    pub fn head_commits(&self) -> BTreeMap<String, Option<String>> {
+        self.repos()
+            .map(|repo| {
+                let commit = self.backend.rev_parse(repo.path(), "HEAD").ok();
+                (repo.name().to_owned(), commit)
+            })
+            .collect()
+    }
+
+    /// Append a record of `action` to the audit log, with the repositories
+    /// whose commit changed since the `before` [`head_commits`](Self::head_commits).
+    pub fn record_audit(
+        &self,
+        action: &str,
+        before: &BTreeMap<String, Option<String>>,
+        outcome: AuditOutcome,
+        error: Option<String>,
+    ) -> Result<AuditEntry, ObsEnvError> {
+        let repos = self
+            .head_commits()
+            .into_iter()
+            .filter_map(|(repo_name, to)| {
+                let from = before.get(&repo_name).cloned().flatten();
+                (from != to).then_some(RepoChange {
+                    repo: repo_name,
+                    from,
+                    to,
+                })
+            })
+            .collect();
+        let entry = AuditEntry {
+            timestamp: unix_time(),
+            user: audit::current_user(),
+            host: lock::hostname(),
+            action: action.to_owned(),
+            repos,
+            outcome,
+            error,
+        };
+        let path = self.audit_log_path();
+        if let Some(dir) = path.parent() {
+            create_dir_all(dir).map_err(|error| ObsEnvError::io(dir, "create", error))?;
         }
+        audit::append(&path, &entry)?;
+        Ok(entry)
     }
 
-    /// Expands version string into a tag, following the format adopted by
-    /// TSSW.
-    fn expand_version_to_tag(version: &str) -> String {
-        let version_regex = Regex::new(VALID_VERSION).unwrap();
+    /// Entries of the audit log passing `filter`, oldest first.
+    pub fn history(&self, filter: &HistoryFilter) -> Result<Vec<AuditEntry>, ObsEnvError> {
+        Ok(filter.apply(audit::read(&self.audit_log_path())?))
+    }
 
-        if version_regex.is_match(version) {
-            format!("v{version}")
-                .replace('a', ".alpha.")
-                .replace('b', ".beta.")
-                .replace("rc", ".rc.")
-        } else {
-            version.to_owned()
+    /// Metadata of the environment, `.obs_env/metadata.json` in the
+    /// environment path.
+    pub fn metadata_path(&self) -> PathBuf {
+        Path::new(&self.destination)
+            .join(OBS_ENV_DIR)
+            .join(METADATA_FILE)
+    }
+
+    /// Record that `action` left the environment at its current versions,
+    /// replacing the previous metadata.
+    pub fn write_metadata(&self, action: &str) -> Result<EnvMetadata, ObsEnvError> {
+        let metadata = EnvMetadata {
+            tool_version: TOOL_VERSION.to_owned(),
+            timestamp: unix_time(),
+            action: action.to_owned(),
+            base_branch: self.base_env_branch.clone(),
+            user: audit::current_user(),
+            manifest: self.get_manifest(),
+        };
+        let path = self.metadata_path();
+        if let Some(dir) = path.parent() {
+            create_dir_all(dir).map_err(|error| ObsEnvError::io(dir, "create", error))?;
         }
+        write_atomically(&path, &serialized(serde_json::to_string_pretty(&metadata)))?;
+        Ok(metadata)
     }
 
-    fn checkout_tag_or_branch(
-        repository: Repository,
-        tag: &str,
-        version: &str,
-    ) -> Result<(), Error> {
-        log::trace!("Fetching...");
-        let mut fetch_options = FetchOptions::new();
-        fetch_options.download_tags(git2::AutotagOption::All);
+    /// Metadata last written with [`write_metadata`](Self::write_metadata),
+    /// if any.
+    pub fn read_metadata(&self) -> Result<Option<EnvMetadata>, ObsEnvError> {
+        metadata::read(&self.metadata_path())
+    }
 
-        repository
-            .find_remote("origin")?
-            .fetch(&[""], Some(&mut fetch_options), None)?;
+    /// Directory the snapshots of the environment are kept in.
+    pub fn snapshots_dir(&self) -> PathBuf {
+        Path::new(&self.destination)
+            .join(OBS_ENV_DIR)
+            .join(SNAPSHOTS_DIR)
+    }
 
-        // Try to find the tag first
-        let spec = "refs/tags/".to_owned() + tag;
-        log::trace!("Checkout spec {spec}");
-        match repository.revparse_single(&spec) {
-            Ok(object) => checkout_tag(&repository, version, object, &spec),
-            Err(_) => {
-                // Fallback to try finding a branch
-                log::trace!("Failed to check tag, trying it as a branch: {version}");
-                checkout_branch(&repository, version)
-            }
+    /// Record the commits the repositories are at, and the files changed
+    /// in those with local changes, in the snapshot `name`, or in one
+    /// named after the current time. An existing snapshot is never
+    /// replaced.
+    ///
+    /// The local changes themselves are not kept: restoring the snapshot
+    /// returns to the commits only.
+    pub fn snapshot(&self, name: Option<&str>) -> Result<Snapshot, ObsEnvError> {
+        let manifest = self.get_manifest_with_dirty_files();
+        let name = name.map_or_else(|| snapshot::default_name(manifest.created), str::to_owned);
+        if !snapshot::is_valid_name(&name) {
+            return Err(ObsEnvError::InvalidConfig {
+                message: format!(
+                    "Invalid snapshot name {name:?}: use letters, digits, '-', '_' and '.'"
+                ),
+            });
         }
+        let dir = self.snapshots_dir();
+        let path = dir.join(format!("{name}.{SNAPSHOT_EXTENSION}"));
+        if path.exists() {
+            return Err(ObsEnvError::InvalidConfig {
+                message: format!("Snapshot {name} already exists in {}", dir.display()),
+            });
+        }
+        create_dir_all(&dir).map_err(|error| ObsEnvError::io(&dir, "create", error))?;
+        manifest.save(&path)?;
+        Ok(Snapshot {
+            name,
+            path,
+            manifest,
+        })
     }
-}
 
-fn checkout_tag(
-    repository: &Repository,
-    version: &str,
-    object: git2::Object,
-    spec: &str,
-) -> Result<(), Error> {
-    repository.branch(version, &object.peel_to_commit().unwrap(), true)?;
-    repository.set_head(spec)?;
-    let mut checkout_build = CheckoutBuilder::new();
-    repository.reset(&object, git2::ResetType::Hard, Some(checkout_build.force()))?;
-    Ok(())
-}
+    /// Names of the snapshots of the environment, from the oldest to the
+    /// newest for those named after the time they were taken.
+    pub fn snapshots(&self) -> Result<Vec<String>, ObsEnvError> {
+        let dir = self.snapshots_dir();
+        let entries = match read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(error) => return Err(ObsEnvError::io(&dir, "read", error)),
+        };
+        let mut names = Vec::new();
+        for entry in entries {
+            let path = entry
+                .map_err(|error| ObsEnvError::io(&dir, "read", error))?
+                .path();
+            if path
+                .extension()
+                .is_some_and(|ext| ext == SNAPSHOT_EXTENSION)
+            {
+                if let Some(name) = path.file_stem() {
+                    names.push(name.to_string_lossy().into_owned());
+                }
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
 
-fn checkout_branch(repository: &Repository, branch_name: &str) -> Result<(), Error> {
-    repository
-        .find_remote("origin")?
-        .fetch(&[branch_name], None, None)?;
+    /// Snapshot `name`, or the latest one, i.e. the last of
+    /// [`snapshots`](Self::snapshots), to restore with
+    /// [`apply_manifest`](Self::apply_manifest).
+    pub fn load_snapshot(&self, name: Option<&str>) -> Result<Snapshot, ObsEnvError> {
+        let names = self.snapshots()?;
+        let name = match name {
+            Some(name) if names.iter().any(|known| known == name) => name.to_owned(),
+            Some(name) => {
+                return Err(ObsEnvError::InvalidConfig {
+                    message: format!(
+                        "No snapshot {name} in {}, only: {}",
+                        self.snapshots_dir().display(),
+                        names.join(", ")
+                    ),
+                })
+            }
+            None => names
+                .last()
+                .cloned()
+                .ok_or_else(|| ObsEnvError::InvalidConfig {
+                    message: format!("No snapshot in {}", self.snapshots_dir().display()),
+                })?,
+        };
+        let path = self
+            .snapshots_dir()
+            .join(format!("{name}.{SNAPSHOT_EXTENSION}"));
+        Ok(Snapshot {
+            manifest: EnvironmentManifest::load(&path)?,
+            name,
+            path,
+        })
+    }
 
-    // repository.branch(branch_name, &object.peel_to_commit().unwrap(), true)?;
-    // repository.set_head(spec)?;
-    // let mut checkout_build = CheckoutBuilder::new();
-    // repository.reset(&object, git2::ResetType::Hard, Some(checkout_build.force()))?;
+    /// Lock file of the environment, `env.lock` in the environment path.
+    pub fn lock_file_path(&self) -> PathBuf {
+        Path::new(&self.destination).join(LOCK_FILE_NAME)
+    }
 
-    let remote_branch_name = format!("origin/{branch_name}");
-    let branch = repository.find_branch(&remote_branch_name, git2::BranchType::Remote)?;
+    /// Write the lock file with the commits the repositories are at,
+    /// atomically.
+    pub fn write_lock_file(&self) -> Result<LockFile, ObsEnvError> {
+        let lock_file = LockFile::new(
+            &self.base_env_branch,
+            unix_time(),
+            &self.get_manifest().repos,
+        );
+        lock_file.save(&self.lock_file_path())?;
+        Ok(lock_file)
+    }
 
-    let branch_reference = branch.into_reference();
-    let commit = branch_reference.peel_to_commit()?;
+    /// Lock file last written with [`write_lock_file`](Self::write_lock_file).
+    pub fn read_lock_file(&self) -> Result<LockFile, ObsEnvError> {
+        LockFile::load(&self.lock_file_path())
+    }
 
-    trace!("Checking out temporary branch");
-    let temp_branch = repository.branch("temp", &commit, true)?;
+    /// Differences between the repositories and the lock file.
+    pub fn lock_drift(&self) -> Result<Vec<Drift>, ObsEnvError> {
+        let lock_file = self.read_lock_file()?;
+        let current = self
+            .get_current_env_versions()
+            .into_iter()
+            .map(|(repo_name, version)| (repo_name, version.ok()))
+            .collect();
+        Ok(lock_file.drift(&current))
+    }
 
-    if let Some(temp_refname) = temp_branch.get().name() {
-        repository.set_head(temp_refname)?;
-    } else {
-        return Err(Error::new(
-            git2::ErrorCode::Ambiguous,
-            git2::ErrorClass::FetchHead,
-            "Error",
-        ));
+    /// Repositories whose HEAD moved after `metadata` was written, which
+    /// were modified outside of this tool.
+    pub fn modified_since(&self, metadata: &EnvMetadata) -> Vec<String> {
+        self.repos()
+            .filter(|repo| {
+                let Ok(repository) = git_backend::open_repository(repo.path()) else {
+                    return false;
+                };
+                // The reflog of HEAD is written on every move of HEAD, even
+                // to the same branch.
+                let git_dir = repository.path();
+                let changed = [git_dir.join("logs/HEAD"), git_dir.join("HEAD")]
+                    .iter()
+                    .filter_map(|path| path.metadata().and_then(|meta| meta.modified()).ok())
+                    .max();
+                changed
+                    .and_then(|changed| changed.duration_since(UNIX_EPOCH).ok())
+                    .is_some_and(|changed| changed.as_secs() > metadata.timestamp)
+            })
+            .map(|repo| repo.name().to_owned())
+            .collect()
     }
 
-    trace!("Checking out branch {branch_name}");
-    let local_branch = repository.branch(&branch_name, &commit, true)?;
-    trace!("Branch {branch_name} checked out ok.");
+    /// Health of the environment, comparing the repositories with the
+    /// `base_versions` if they are known. Repositories that are not cloned,
+    /// or whose version cannot be read, are left out.
+    pub fn metrics(&self, base_versions: Option<&BTreeMap<String, RepoVersion>>) -> EnvMetrics {
+        let repos = self
+            .repos()
+            .filter(|repo| repo.exists())
+            .filter_map(|repo| {
+                let version = repo.version().ok()?;
+                let matches_base = base_versions.map(|base_versions| {
+                    base_versions
+                        .get(repo.name())
+                        .is_some_and(|base| version.matches(base))
+                });
+                Some(RepoMetrics {
+                    repo: repo.name().to_owned(),
+                    dirty: version.dirty,
+                    behind_origin: repo.behind_origin().ok().flatten(),
+                    matches_base,
+                })
+            })
+            .collect();
+        let last_run_success = self
+            .history(&HistoryFilter {
+                limit: Some(1),
+                ..Default::default()
+            })
+            .ok()
+            .and_then(|entries| {
+                entries
+                    .last()
+                    .map(|entry| entry.outcome == AuditOutcome::Success)
+            });
+        EnvMetrics {
+            env_path: self.destination.clone(),
+            repos,
+            last_reset: self
+                .read_metadata()
+                .ok()
+                .flatten()
+                .map(|metadata| metadata.timestamp),
+            last_run_success,
+        }
+    }
 
-    if let Some(upstream_name) = branch_reference.name() {
-        debug!("Upstream name: {upstream_name}");
-        let object = repository.revparse_single(upstream_name)?;
-        let mut checkout_build = CheckoutBuilder::new();
-        repository.reset(&object, git2::ResetType::Hard, Some(checkout_build.force()))?;
-        // local_branch.set_upstream(Some(upstream_name))?;
-        if let Some(refname) = local_branch.get().name() {
-            repository.set_head(refname)?;
-        } else {
-            return Err(Error::new(
-                git2::ErrorCode::Ambiguous,
-                git2::ErrorClass::FetchHead,
-                "Error",
-            ));
+    /// Classify the repositories of the environment as present, broken
+    /// when something that is not a git repository is at their path, or
+    /// missing.
+    pub fn survey(&self) -> EnvSurvey {
+        EnvSurvey {
+            repos: self
+                .repos()
+                .map(|repo| {
+                    let presence = if repo.exists() {
+                        RepoPresence::Present
+                    } else if repo.path().exists() {
+                        RepoPresence::Broken
+                    } else {
+                        RepoPresence::Missing
+                    };
+                    (repo.name().to_owned(), presence)
+                })
+                .collect(),
         }
-    } else {
-        return Err(Error::new(
-            git2::ErrorCode::Ambiguous,
-            git2::ErrorClass::FetchHead,
-            "Error",
-        ));
     }
 
-    Ok(())
-}
+    /// Clone repositories into the environment path.
+    ///
+    /// Only the missing repositories are cloned: those present are
+    /// skipped, and broken ones fail with
+    /// [`ObsEnvError::NotARepository`], unless set otherwise with
+    /// [`existing_clones`](ObservingEnvironmentBuilder::existing_clones).
+    /// Up to [`jobs`](ObservingEnvironmentBuilder::jobs) repositories are
+    /// cloned at the same time. The report has an entry for every
+    /// repository, in the order of [`repos`](Self::repos). With
+    /// [`ErrorPolicy::FailFast`], those not started when the first failure
+    /// happens are not attempted.
+    ///
+    /// If an object store is configured, the bare repository in the store
+    /// is created or refreshed and a worktree of it is added to the
+    /// environment path instead.
+    ///
+    /// ```no_run
+    /// use ts_observing_environment::ObservingEnvironment;
+    ///
+    /// let obs_env = ObservingEnvironment::with_destination("/obs-env");
+    /// obs_env.create_path()?;
+    /// let report = obs_env.clone_repositories();
+    /// for (repo_name, path) in report.cloned() {
+    ///     println!("Cloned {repo_name} into {}", path.display());
+    /// }
+    /// report.into_result()?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn clone_repositories(&self) -> SetupReport {
+        SetupReport::new(self.clone_each(|repo| self.clone_missing_repository(repo.name())))
+    }
 
-#[cfg(test)]
-mod tests {
-    use std::path::Path;
+    /// Fetch every cloned repository and fast-forward the branch checked
+    /// out, if any, to origin, as [`ExistingClones::Update`] does. The
+    /// report has an entry for every cloned repository, in the order of
+    /// [`repos`](Self::repos): those with local changes, an operation in
+    /// progress or a branch that has diverged from origin fail and are left
+    /// alone. With [`ErrorPolicy::FailFast`], those not started when the
+    /// first failure happens are not attempted.
+    pub fn update_repositories(&self) -> SetupReport {
+        let cloned: Vec<RepoHandle> = self.repos().filter(|repo| repo.exists()).collect();
+        SetupReport::new(self.setup_each(cloned, "update", |repo| {
+            let start = Instant::now();
+            let outcome =
+                match self.observed(repo.name(), "update", || self.update_repository(repo)) {
+                    Ok(path) => RepoSetupOutcome::Updated {
+                        head: self.backend.rev_parse(&path, "HEAD").ok(),
+                        path,
+                        duration: start.elapsed(),
+                        received_bytes: 0,
+                    },
+                    Err(error) => RepoSetupOutcome::Failed {
+                        error,
+                        duration: start.elapsed(),
+                    },
+                };
+            RepoSetup {
+                name: repo.name().to_owned(),
+                outcome,
+            }
+        }))
+    }
 
-    use regex::Regex;
+    /// Clone repositories into the environment path as
+    /// [`clone_repositories`](Self::clone_repositories) does, checking each
+    /// clone out at its version in `manifest`, as
+    /// [`apply_manifest`](Self::apply_manifest) does, instead of its
+    /// default branch.
+    ///
+    /// Only the branch recorded in the manifest, or the default branch, is
+    /// fetched by the clone when origin still has it, then the version if
+    /// it is not on it. Repositories the manifest has that the environment
+    /// does not need adding with
+    /// [`manifest_repositories`](ObservingEnvironmentBuilder::manifest_repositories).
+    ///
+    /// The repositories are then compared with the manifest: those that
+    /// are not at their version, e.g. left as they were by
+    /// [`ExistingClones::Skip`], fail with
+    /// [`ObsEnvError::VerificationFailed`].
+    pub fn clone_repositories_at(&self, manifest: &EnvironmentManifest) -> SetupReport {
+        let mut repos = self.clone_each(|repo| {
+            let version = manifest
+                .repos
+                .iter()
+                .find(|version| version.name == repo.name());
+            self.clone_missing_repository_at(repo.name(), version)
+        });
+        let differences = self.manifest_differences(manifest);
+        for repo in repos.iter_mut() {
+            let Some(expected) = differences
+                .iter()
+                .find(|version| version.name == repo.name)
+                .filter(|_| !self.version_overrides.contains_key(&repo.name))
+            else {
+                continue;
+            };
+            let (path, duration) = match &repo.outcome {
+                RepoSetupOutcome::Failed { .. } => continue,
+                RepoSetupOutcome::Skipped { path } => (path.clone(), Duration::ZERO),
+                RepoSetupOutcome::Cloned { path, duration, .. }
+                | RepoSetupOutcome::Updated { path, duration, .. } => (path.clone(), *duration),
+            };
+            let current = self
+                .backend
+                .rev_parse(&path, "HEAD")
+                .unwrap_or_else(|_| "no commit".to_owned());
+            let error = ObsEnvError::VerificationFailed {
+                path,
+                problems: vec![format!(
+                    "{} is at {current}, not {}",
+                    repo.name,
+                    expected.sha.as_ref().unwrap_or(&expected.describe)
+                )],
+            };
+            repo.outcome = RepoSetupOutcome::Failed { error, duration };
+        }
+        SetupReport::new(repos)
+    }
 
-    use super::{ObservingEnvironment, REPO_VERSION_REGEXP, VALID_VERSION};
+    /// Set up each repository with `setup`, reporting them in the order of
+    /// [`repos`](Self::repos), until one fails with
+    /// [`ErrorPolicy::FailFast`].
+    fn clone_each(&self, setup: impl Fn(&RepoHandle) -> RepoSetup + Sync) -> Vec<RepoSetup> {
+        self.setup_each(self.repos().collect(), "clone", setup)
+    }
 
-    use once_cell::sync::Lazy;
-    use std::sync::Mutex;
+    /// Set up each of `repos` with `setup`, on up to
+    /// [`jobs`](ObservingEnvironmentBuilder::jobs) threads, until one fails
+    /// with [`ErrorPolicy::FailFast`], those not started then failing as
+    /// not attempted to `operation`. The results are in the order of
+    /// `repos`.
+    fn setup_each(
+        &self,
+        repos: Vec<RepoHandle>,
+        operation: &str,
+        setup: impl Fn(&RepoHandle) -> RepoSetup + Sync,
+    ) -> Vec<RepoSetup> {
+        let results = parallel::map_until(&repos, self.jobs, &setup, |result| {
+            self.on_error == ErrorPolicy::FailFast
+                && result
+                    .as_ref()
+                    .map_or(true, |repo_setup| repo_setup.error().is_some())
+        });
+        results
+            .into_iter()
+            .zip(repos.iter())
+            .map(|(result, repo)| {
+                let failed = |error| RepoSetup {
+                    name: repo.name().to_owned(),
+                    outcome: RepoSetupOutcome::Failed {
+                        error,
+                        duration: Duration::ZERO,
+                    },
+                };
+                match result {
+                    Some(Ok(repo_setup)) => repo_setup,
+                    Some(Err(message)) => failed(ObsEnvError::Panicked {
+                        operation: format!("{operation} {}", repo.name()),
+                        message,
+                    }),
+                    None => failed(ObsEnvError::not_attempted(repo.name(), operation)),
+                }
+            })
+            .collect()
+    }
 
-    static REPO_ACCESS: Lazy<Mutex<()>> = Lazy::new(Mutex::default);
+    /// Clone the repository `repo_name` unless it is already present, in
+    /// which case it is skipped, updated or re-cloned as set with
+    /// [`existing_clones`](ObservingEnvironmentBuilder::existing_clones).
+    pub(crate) fn clone_missing_repository(&self, repo_name: &str) -> RepoSetup {
+        self.clone_missing_repository_at(repo_name, None)
+    }
 
-    type TestResult<T = (), E = Box<dyn std::error::Error>> = std::result::Result<T, E>;
+    /// Clone the repository `repo_name` as
+    /// [`clone_missing_repository`](Self::clone_missing_repository) does,
+    /// checking out `version` of a manifest instead of the default branch
+    /// if given.
+    fn clone_missing_repository_at(
+        &self,
+        repo_name: &str,
+        version: Option<&RepoVersion>,
+    ) -> RepoSetup {
+        let start = Instant::now();
+        let received_bytes = Cell::new(0);
+        let progress = RepoTransfer {
+            repo: repo_name,
+            progress: |progress: &TransferProgress| {
+                received_bytes.set(progress.received_bytes);
+                self.observer.on_transfer_progress(repo_name, progress);
+            },
+        };
+        let failed = |error| RepoSetupOutcome::Failed {
+            error,
+            duration: start.elapsed(),
+        };
+        let cloned = |path: PathBuf| RepoSetupOutcome::Cloned {
+            head: self.backend.rev_parse(&path, "HEAD").ok(),
+            path,
+            duration: start.elapsed(),
+            received_bytes: received_bytes.get(),
+        };
 
-    #[test]
-    fn test_repo_version_regexp() {
-        let regexp = Regex::new(REPO_VERSION_REGEXP).unwrap();
+        let outcome = match self.repo(repo_name) {
+            Ok(repo) if repo.exists() => match self.existing_clones {
+                ExistingClones::Skip => RepoSetupOutcome::Skipped {
+                    path: repo.path().to_path_buf(),
+                },
+                ExistingClones::Update => {
+                    match self.observed(repo_name, "update", || self.update_repository(&repo)) {
+                        Ok(path) => RepoSetupOutcome::Updated {
+                            head: self.backend.rev_parse(&path, "HEAD").ok(),
+                            path,
+                            duration: start.elapsed(),
+                            received_bytes: received_bytes.get(),
+                        },
+                        Err(error) => failed(error),
+                    }
+                }
+                ExistingClones::Reclone => match self.observed(repo_name, "re-clone", || {
+                    self.remove_repository(repo_name)?;
+                    self.clone_repository(&repo, &progress, version)
+                }) {
+                    Ok(path) => cloned(path),
+                    Err(error) => failed(error),
+                },
+            },
+            Ok(repo) if repo.path().exists() => match self.existing_clones {
+                ExistingClones::Reclone => match self.observed(repo_name, "re-clone", || {
+                    remove_dir_all(repo.path())
+                        .map_err(|error| ObsEnvError::io(repo.path(), "remove", error))?;
+                    self.clone_repository(&repo, &progress, version)
+                }) {
+                    Ok(path) => cloned(path),
+                    Err(error) => failed(error),
+                },
+                _ => failed(ObsEnvError::NotARepository {
+                    repo: repo_name.to_owned(),
+                    path: repo.path().to_path_buf(),
+                }),
+            },
+            Ok(repo) => match self.observed(repo_name, "clone", || {
+                self.clone_repository(&repo, &progress, version)
+            }) {
+                Ok(path) => cloned(path),
+                Err(error) => failed(error),
+            },
+            Err(error) => failed(error),
+        };
+        RepoSetup {
+            name: repo_name.to_owned(),
+            outcome,
+        }
+    }
 
-        let repo_version = regexp.captures("ts_unit_test=X.Y.ZaN").unwrap();
+    /// Fetch a repository already cloned and fast-forward the branch
+    /// checked out, if any, to origin.
+    ///
+    /// Repositories with an operation in progress or local changes are
+    /// left alone, as are branches that have diverged from origin.
+    fn update_repository(&self, repo: &RepoHandle) -> Result<PathBuf, ObsEnvError> {
+        let repo_name = repo.name();
+        if self.offline {
+            return Err(ObsEnvError::Offline {
+                operation: format!("update {repo_name}"),
+            });
+        }
+        let path = repo.open()?;
+        self.check_not_busy(repo_name, path)?;
+        let status = self
+            .backend
+            .status(path)
+            .map_err(|error| ObsEnvError::git(repo_name, path, "read status", error))?;
+        if status.dirty {
+            return Err(ObsEnvError::DirtyWorkingTree {
+                repo: repo_name.to_owned(),
+            });
+        }
+        if repo.is_empty() {
+            // There is no branch to fast-forward yet, but commits may have
+            // been pushed since the clone.
+            self.fetch_origin(
+                repo_name,
+                path,
+                &["+refs/heads/*:refs/remotes/origin/*"],
+                true,
+            )
+            .map_err(|error| ObsEnvError::fetch_failed(repo_name, path, error))?;
+            if let Some(default_branch) = &repo.spec().default_branch {
+                let upstream = format!("refs/remotes/origin/{default_branch}");
+                if self.backend.rev_parse(path, &upstream).is_ok() {
+                    log::debug!("Checking out {default_branch} in {repo_name}");
+                    self.backend
+                        .checkout_branch(path, default_branch)
+                        .map_err(|error| {
+                            ObsEnvError::git(
+                                repo_name,
+                                path,
+                                &format!("checkout branch {default_branch}"),
+                                error,
+                            )
+                        })?;
+                }
+            }
+            return Ok(path.to_path_buf());
+        }
+        let branch = self
+            .backend
+            .current_branch(path)
+            .map_err(|error| ObsEnvError::git(repo_name, path, "read current branch", error))?;
+        let refspecs: Vec<String> = branch.iter().map(|branch| branch_refspec(branch)).collect();
+        let refspecs: Vec<&str> = refspecs.iter().map(String::as_str).collect();
+        self.fetch_origin(repo_name, path, &refspecs, true)
+            .map_err(|error| ObsEnvError::fetch_failed(repo_name, path, error))?;
+        if let Some(branch) = &branch {
+            log::debug!("Fast-forwarding {branch} in {repo_name}");
+            self.backend.fast_forward(path, branch).map_err(|error| {
+                ObsEnvError::git(repo_name, path, &format!("fast-forward {branch}"), error)
+            })?;
+        }
+        Ok(path.to_path_buf())
+    }
 
-        assert_eq!(&repo_version["name"], "ts_unit_test");
-        assert_eq!(&repo_version["version"], "X.Y.ZaN");
+    /// Depth and filter `repo_spec` is cloned with: its own, or those of
+    /// the environment. A depth of 0 clones the whole history.
+    fn clone_options(&self, repo_spec: &RepoSpec) -> CloneOptions {
+        CloneOptions {
+            depth: repo_spec
+                .clone_depth
+                .or(self.clone_depth)
+                .filter(|depth| *depth > 0),
+            filter: repo_spec.clone_filter.or(self.clone_filter),
+        }
+    }
+
+    /// Clone a repository into the environment path and check out its
+    /// default branch, if it has one, or `version` of a manifest.
+    fn clone_repository(
+        &self,
+        repo: &RepoHandle,
+        progress: &dyn TransferObserver,
+        version: Option<&RepoVersion>,
+    ) -> Result<PathBuf, ObsEnvError> {
+        let repo_name = repo.name();
+        let url = repo.url();
+        let path = repo.path().to_path_buf();
+        let options = self.clone_options(repo.spec());
+        if self.offline {
+            return Err(ObsEnvError::Offline {
+                operation: format!("clone {repo_name}"),
+            });
+        }
+        self.timings
+            .time("clone", Some(repo_name), || match &self.object_store {
+                Some(object_store) => {
+                    log::debug!("Adding worktree: {repo_name}");
+                    let object_store = Path::new(object_store);
+                    self.add_worktree(repo_name, &url, object_store, &path, options, progress)
+                }
+                None => {
+                    log::debug!("Cloning: {repo_name}");
+                    let branch = version
+                        .and_then(|version| version.branch.as_ref())
+                        .or(repo.spec().default_branch.as_ref())
+                        .filter(|_| version.is_some());
+                    match branch.map(|branch| {
+                        self.backend
+                            .clone_branch(&url, &path, branch, options, progress)
+                    }) {
+                        Some(Err(error)) if error.code() == ErrorCode::NotFound => {
+                            log::debug!("{repo_name}: {error}, cloning every branch");
+                            None
+                        }
+                        result => result,
+                    }
+                    .unwrap_or_else(|| self.backend.clone(&url, &path, false, options, progress))
+                    .map_err(|error| ObsEnvError::clone_failed(repo_name, &url, &path, error))
+                }
+            })?;
+        if let Some(version) = version {
+            let target = self
+                .version_overrides
+                .get(repo_name)
+                .or(version.sha.as_ref())
+                .unwrap_or(&version.describe);
+            log::debug!("Checking out {target} in {repo_name}");
+            // The commit is usually on the branch cloned, with no need to
+            // fetch it.
+            if self.backend.rev_parse(&path, target).as_ref() == Ok(target) {
+                self.timings
+                    .time("checkout", Some(repo_name), || {
+                        self.backend.reset(&path, target, None)
+                    })
+                    .map_err(|error| {
+                        ObsEnvError::git(repo_name, &path, &format!("checkout {target}"), error)
+                    })?;
+            } else {
+                self.reset_to_version(repo_name, target)?;
+            }
+        } else if let Some(default_branch) = &repo.spec().default_branch {
+            if repo.is_empty() {
+                log::info!("{repo_name} is empty, not checking out {default_branch}");
+                return Ok(path);
+            }
+            log::debug!("Checking out {default_branch} in {repo_name}");
+            self.timings
+                .time("checkout", Some(repo_name), || {
+                    self.backend.checkout_branch(&path, default_branch)
+                })
+                .map_err(|error| match error.code() {
+                    ErrorCode::NotFound => ObsEnvError::BranchNotFound {
+                        repo: repo_name.to_owned(),
+                        path: path.clone(),
+                        branch: default_branch.clone(),
+                        closest: Vec::new(),
+                        source: error,
+                    },
+                    _ => ObsEnvError::git(
+                        repo_name,
+                        &path,
+                        &format!("checkout branch {default_branch}"),
+                        error,
+                    ),
+                })?;
+        }
+        self.share_repository(repo_name, &path)?;
+        Ok(path)
+    }
+
+    /// Share the clone of `repo_name` at `path` as set with
+    /// [`shared_access`](ObservingEnvironmentBuilder::shared_access).
+    fn share_repository(&self, repo_name: &str, path: &Path) -> Result<(), ObsEnvError> {
+        if !self.shared_access.is_enabled() {
+            return Ok(());
+        }
+        if self.shared_access.group_writable {
+            self.backend
+                .set_config(path, SHARED_REPOSITORY, "group")
+                .map_err(|error| ObsEnvError::git(repo_name, path, "configure as shared", error))?;
+        }
+        if path.exists() {
+            self.shared_access.apply(path, true)?;
+        }
+        Ok(())
+    }
+
+    /// Remove the repositories from the environment path, by repository
+    /// name.
+    ///
+    /// Unless `force` is set, the repositories with changes are kept,
+    /// failing with [`ObsEnvError::DirtyWorkingTree`], as are those with
+    /// untracked files, stashes or commits on no remote, failing with
+    /// [`ObsEnvError::UnsavedWork`].
+    /// Worktrees are pruned from their bare repository, leaving the shared
+    /// object store and the worktrees of other environments untouched.
+    pub fn teardown(&self, force: bool) -> BTreeMap<String, Result<(), ObsEnvError>> {
+        self.repositories
+            .keys()
+            .chain([&self.base_env_source_repo])
+            .filter(|repo_name| self.repo_path(repo_name).exists())
+            .map(|repo_name| {
+                let result = match force {
+                    true => Ok(()),
+                    false => environments::check_repository_removable(
+                        repo_name,
+                        &self.repo_path(repo_name),
+                        self.backend.as_ref(),
+                    ),
+                };
+                let result = result.and_then(|_| {
+                    log::debug!("Removing: {repo_name}");
+                    self.remove_repository(repo_name)
+                });
+                (repo_name.to_owned(), result)
+            })
+            .collect()
+    }
+
+    /// Path of the repository `repo_name` in the environment, or of the
+    /// clone of the base environment repository.
+    fn repo_path(&self, repo_name: &str) -> PathBuf {
+        let subdirectory = self
+            .repositories
+            .get(repo_name)
+            .map_or(repo_name, RepoSpec::subdirectory);
+        Path::new(&self.destination).join(subdirectory)
+    }
+
+    fn remove_repository(&self, repo_name: &str) -> Result<(), ObsEnvError> {
+        let path = self.repo_path(repo_name);
+
+        match git_backend::open_repository(&path) {
+            Ok(repository) if repository.is_worktree() => {
+                let worktree = Worktree::open_from_repository(&repository)
+                    .map_err(|error| ObsEnvError::git(repo_name, &path, "open worktree", error))?;
+                remove_dir_all(&path).map_err(|error| ObsEnvError::io(&path, "remove", error))?;
+                worktree
+                    .prune(Some(WorktreePruneOptions::new().valid(true)))
+                    .map_err(|error| ObsEnvError::git(repo_name, &path, "prune worktree", error))
+            }
+            _ => remove_dir_all(&path).map_err(|error| ObsEnvError::io(&path, "remove", error)),
+        }
+    }
+
+    /// Check that every repository of the environment is cloned and
+    /// undamaged, as needed before using the environment, failing with
+    /// [`ObsEnvError::VerificationFailed`] listing the problems found.
+    pub fn verify(&self) -> Result<(), ObsEnvError> {
+        let mut problems: Vec<String> = self
+            .survey()
+            .with_presence(RepoPresence::Missing)
+            .map(|repo_name| format!("{repo_name} is not cloned"))
+            .collect();
+        problems.extend(
+            self.diagnose_repositories()
+                .into_iter()
+                .filter(|diagnosis| !diagnosis.is_healthy() || !diagnosis.is_shared())
+                .map(|diagnosis| diagnosis.to_string()),
+        );
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ObsEnvError::VerificationFailed {
+                path: PathBuf::from(&self.destination),
+                problems,
+            })
+        }
+    }
+
+    /// Look for damage left in the repositories of the environment, e.g.
+    /// by a clone that was killed. Repositories missing from the
+    /// environment path are not diagnosed.
+    ///
+    /// With a [deep check](ObservingEnvironmentBuilder::deep_check), every
+    /// object of the repositories that are not broken already is read, by
+    /// up to [`jobs`](ObservingEnvironmentBuilder::jobs) threads, as
+    /// `git fsck` would.
+    pub fn diagnose_repositories(&self) -> Vec<RepoDiagnosis> {
+        let repos: Vec<RepoHandle> = self
+            .repos()
+            .filter(|repo| repo.exists() || repo.path().exists())
+            .collect();
+        let mut diagnoses: Vec<RepoDiagnosis> = repos
+            .iter()
+            .map(|repo| self.diagnose_repository(repo))
+            .collect();
+        if !self.deep_check {
+            return diagnoses;
+        }
+        let checks = parallel::map(&repos, self.jobs, |repo| {
+            if diagnoses
+                .iter()
+                .any(|diagnosis| diagnosis.name == repo.name() && diagnosis.is_broken())
+            {
+                return Ok(Vec::new());
+            }
+            self.observed(repo.name(), "check objects", || {
+                log::info!("Checking the objects of {}...", repo.name());
+                self.backend.check_objects(repo.path()).map_err(|error| {
+                    ObsEnvError::git(repo.name(), repo.path(), "check objects", error)
+                })
+            })
+        });
+        for ((diagnosis, repo), check) in diagnoses.iter_mut().zip(repos.iter()).zip(checks) {
+            match check {
+                Ok(Ok(objects)) if objects.is_empty() => {}
+                Ok(Ok(objects)) => diagnosis
+                    .damage
+                    .push(RepoDamage::CorruptObjects { objects }),
+                Ok(Err(error)) => log::warn!("{}", crate::error::report(&error)),
+                Err(message) => log::warn!(
+                    "Checking the objects of {} panicked: {message}",
+                    repo.name()
+                ),
+            }
+        }
+        diagnoses
+    }
+
+    fn diagnose_repository(&self, repo: &RepoHandle) -> RepoDiagnosis {
+        let path = repo.path();
+        let mut damage = Vec::new();
+        let empty = repo.is_empty();
+        match self.backend.open(path) {
+            Ok(()) if !empty && self.backend.rev_parse(path, "HEAD").is_err() => {
+                damage.push(RepoDamage::MissingHead)
+            }
+            Ok(()) => {}
+            Err(error) => damage.push(RepoDamage::Unopenable {
+                reason: error.message().to_owned(),
+            }),
+        }
+        if let Some(git_dir) = repair::git_dir(path) {
+            if !empty && !repair::has_objects(&git_dir) {
+                damage.push(RepoDamage::NoObjects);
+            }
+            let locks = repair::find_locks(&git_dir);
+            if !locks.is_empty() {
+                damage.push(RepoDamage::StaleLocks { locks });
+            }
+        }
+        let mut blockers = Vec::new();
+        if git_backend::is_untrusted(path) {
+            blockers.push(RepoBlocker::NotOwned);
+        }
+        if let Ok(None) = self.backend.identity(path) {
+            blockers.push(RepoBlocker::NoIdentity {
+                identity: self.identity.clone(),
+            });
+        }
+        let unshared = self.shared_access.unshared(path);
+        if !unshared.is_empty() {
+            blockers.push(RepoBlocker::NotShared { paths: unshared });
+        }
+        if self.shared_access.group_writable
+            && !matches!(
+                self.backend
+                    .config(path, SHARED_REPOSITORY)
+                    .ok()
+                    .flatten()
+                    .as_deref(),
+                Some("group" | "true" | "1")
+            )
+        {
+            blockers.push(RepoBlocker::NotSharedRepository);
+        }
+        RepoDiagnosis {
+            name: repo.name().to_owned(),
+            path: path.to_path_buf(),
+            damage,
+            blockers,
+        }
+    }
+
+    /// Repair the damaged repositories found by
+    /// [`diagnose_repositories`](Self::diagnose_repositories), returning
+    /// their diagnosis and what was done.
+    ///
+    /// Broken repositories are removed and cloned again, unless they have
+    /// commits on no remote, or their commits cannot be read to tell,
+    /// which only `force` overrides. Lock files are removed if no git
+    /// process is running, as it may be holding them.
+    pub fn repair_repositories(
+        &self,
+        force: bool,
+    ) -> Vec<(RepoDiagnosis, Result<Repair, ObsEnvError>)> {
+        self.diagnose_repositories()
+            .into_iter()
+            .filter(|diagnosis| !diagnosis.is_healthy())
+            .map(|diagnosis| {
+                let result = self.observed(&diagnosis.name, "repair", || {
+                    self.repair_repository(&diagnosis, force)
+                });
+                (diagnosis, result)
+            })
+            .collect()
+    }
+
+    fn repair_repository(
+        &self,
+        diagnosis: &RepoDiagnosis,
+        force: bool,
+    ) -> Result<Repair, ObsEnvError> {
+        let repo_name = diagnosis.name.as_str();
+        let repo = self.repo(repo_name)?;
+        if diagnosis.is_broken() {
+            match self.backend.local_commits(repo.path()) {
+                Ok(0) => {}
+                Ok(count) => {
+                    return Err(ObsEnvError::LocalCommits {
+                        repo: repo_name.to_owned(),
+                        count,
+                    })
+                }
+                Err(error) if force => log::warn!(
+                    "Cannot read the commits of {repo_name}, cloning it again: {}",
+                    error.message()
+                ),
+                Err(error) => {
+                    return Err(ObsEnvError::UnreadableCommits {
+                        repo: repo_name.to_owned(),
+                        source: error,
+                    })
+                }
+            }
+            self.remove_repository(repo_name)?;
+            let path = self.clone_repository(&repo, &self.transfer_progress(repo_name), None)?;
+            return Ok(Repair::Recloned { path });
+        }
+
+        let locks = diagnosis.locks().to_vec();
+        if repair::git_process_running() {
+            return Err(ObsEnvError::StaleLocks {
+                repo: repo_name.to_owned(),
+                locks,
+            });
+        }
+        for lock in locks.iter() {
+            log::info!("Removing {}", lock.display());
+            remove_file(lock).map_err(|error| ObsEnvError::io(lock, "remove", error))?;
+        }
+        Ok(Repair::LocksRemoved { locks })
+    }
+
+    /// Create or refresh the bare repository for `repo_name` in the object
+    /// store and add a worktree of it at `path`.
+    ///
+    /// Worktrees share the origin of their store, so repositories taken from
+    /// a fork get a store of their own, named after the fork owner, rather
+    /// than pointing the origin of the canonical one at the fork.
+    ///
+    /// As with git, a branch can only be checked out in one worktree at a
+    /// time, so environments sharing a store check out detached the
+    /// branches another one has checked out. The store is cloned and
+    /// fetched through the backend, but worktrees are always managed with
+    /// libgit2, after fetching the files of a partial store they need.
+    fn add_worktree(
+        &self,
+        repo_name: &str,
+        url: &str,
+        object_store: &Path,
+        path: &Path,
+        options: CloneOptions,
+        progress: &dyn TransferObserver,
+    ) -> Result<(), ObsEnvError> {
+        let store_path = match self.forks.get(repo_name) {
+            Some(owner) => object_store.join(format!("{repo_name}@{owner}.git")),
+            None => object_store.join(format!("{repo_name}.git")),
+        };
+
+        if store_path.exists() {
+            log::debug!("Refreshing {}", store_path.display());
+            if !self.offline {
+                self.backend
+                    .fetch(&store_path, &[], false, progress)
+                    .map_err(|error| ObsEnvError::fetch_failed(repo_name, &store_path, error))?;
+            }
+        } else {
+            log::debug!("Cloning bare repository {}", store_path.display());
+            create_dir_all(object_store)
+                .map_err(|error| ObsEnvError::io(object_store, "create object store", error))?;
+            self.backend
+                .clone(url, &store_path, true, options, progress)
+                .map_err(|error| ObsEnvError::clone_failed(repo_name, url, &store_path, error))?;
+        }
+        let bare_repository =
+            Repository::open_bare(&store_path).map_err(|error| ObsEnvError::RepoNotCloned {
+                repo: repo_name.to_owned(),
+                path: store_path.clone(),
+                source: error,
+            })?;
+
+        let worktree_name = self.worktree_name();
+
+        // Leftover metadata from a worktree whose directory was removed by
+        // hand would make adding it again fail.
+        if let Ok(worktree) = bare_repository.find_worktree(&worktree_name) {
+            if worktree.validate().is_err() {
+                worktree.prune(None).map_err(|error| {
+                    ObsEnvError::git(repo_name, &store_path, "prune stale worktree", error)
+                })?;
+            }
+        }
+
+        let branch = bare_repository
+            .find_branch(&worktree_name, git2::BranchType::Local)
+            .ok();
+        let reference = branch.map(|branch| branch.into_reference());
+        // The worktree is checked out by libgit2, which cannot fetch the
+        // files a partial store left out.
+        let checked_out = match &reference {
+            Some(reference) => reference.peel_to_commit(),
+            None => bare_repository
+                .head()
+                .and_then(|head| head.peel_to_commit()),
+        };
+        if let Ok(commit) = checked_out {
+            git_backend::fetch_missing_objects(&bare_repository, commit.id())
+                .map_err(|error| ObsEnvError::fetch_failed(repo_name, &store_path, error))?;
+        }
+        let mut worktree_options = WorktreeAddOptions::new();
+        worktree_options.reference(reference.as_ref());
+
+        bare_repository
+            .worktree(&worktree_name, path, Some(&worktree_options))
+            .map(|_| ())
+            .map_err(|error| ObsEnvError::git(repo_name, path, "add worktree", error))
+    }
+
+    /// Name identifying this environment's worktrees in the object store.
+    fn worktree_name(&self) -> String {
+        let destination = Path::new(&self.destination)
+            .canonicalize()
+            .unwrap_or_else(|_| Path::new(&self.destination).to_path_buf());
+
+        destination
+            .to_string_lossy()
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect::<String>()
+            .trim_matches('_')
+            .to_owned()
+    }
+
+    /// Reset all repositories to their official version.
+    ///
+    /// Every repository is attempted, up to
+    /// [`jobs`](ObservingEnvironmentBuilder::jobs) at the same time, and
+    /// the errors of those that could not be reset are returned together,
+    /// in the order of the repository names. Nothing is attempted if
+    /// [`check_path`](Self::check_path) does not pass, and no other
+    /// repository once one fails with [`ErrorPolicy::FailFast`].
+    ///
+    /// A repository whose base version is `base_env_branch`, but which has
+    /// no such branch, e.g. because it still uses master instead of main,
+    /// is reset to its default branch instead.
+    ///
+    /// Local commits that HEAD has and no remote branch or tag has are kept
+    /// on a [backup branch](crate::backup) before moving HEAD away, unless
+    /// [disabled](ObservingEnvironmentBuilder::backup). The report lists
+    /// the backups made, and the branches the repositories whose base
+    /// version is a branch were reset to.
+    ///
+    /// ```no_run
+    /// use ts_observing_environment::ObservingEnvironment;
+    ///
+    /// let obs_env = ObservingEnvironment::with_destination("/obs-env");
+    /// match obs_env.reset_base_environment("main") {
+    ///     Ok(report) => {
+    ///         for (repo_name, branch) in report.branches {
+    ///             println!("{repo_name}: branch {branch}");
+    ///         }
+    ///         for backup in report.backups {
+    ///             println!("{backup}");
+    ///         }
+    ///     }
+    ///     Err(errors) => {
+    ///         for error in errors {
+    ///             eprintln!("{error}");
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub fn reset_base_environment(
+        &self,
+        base_env_branch: &str,
+    ) -> Result<ResetReport, Vec<ObsEnvError>> {
+        let (mut report, versions) = self
+            .reset_targets(base_env_branch)
+            .map_err(|error| vec![error])?;
+
+        let start = Instant::now();
+        let mut reset_result = Vec::new();
+        let results = parallel::map_until(
+            &versions,
+            self.jobs,
+            |(repo, version)| self.reset_repository_to_base(repo, version, base_env_branch),
+            |result| self.fails_fast() && !matches!(result, Ok(Ok(_))),
+        );
+        for (result, (repo, _)) in results.into_iter().zip(versions.iter()) {
+            let Some(result) = result else {
+                reset_result.push(ObsEnvError::not_attempted(repo, "reset"));
+                continue;
+            };
+            match result {
+                Ok(Ok(reset)) => report.add(repo, reset),
+                Ok(Err(error)) => reset_result.push(error),
+                Err(message) => reset_result.push(ObsEnvError::Panicked {
+                    operation: format!("reset {repo}"),
+                    message,
+                }),
+            }
+        }
+        log::info!(
+            "Reset {} repositories in {:.2?}.",
+            versions.len(),
+            start.elapsed()
+        );
+
+        if reset_result.is_empty() {
+            Ok(report)
+        } else {
+            Err(reset_result)
+        }
+    }
+
+    /// Check the environment can be reset to `base_env_branch` and read the
+    /// versions to reset the repositories to, their base version or its
+    /// override, by repository name, with the report started with the base
+    /// environment commit and the overrides.
+    pub(crate) fn reset_targets(
+        &self,
+        base_env_branch: &str,
+    ) -> Result<(ResetReport, Vec<(String, String)>), ObsEnvError> {
+        // The versions cache may be behind the branch, and a reset has to
+        // be exact, so the bare cache is fetched instead; that only brings
+        // the commits added since the last reset.
+        self.preflight()?;
+        let start = Instant::now();
+        let base_env_versions = self.base_env_versions(base_env_branch, false)?;
+        log::info!(
+            "Read the base environment versions in {:.2?}.",
+            start.elapsed()
+        );
+
+        let mut report = ResetReport {
+            base_commit: base_env_versions.commit,
+            ..ResetReport::default()
+        };
+        let mut targets: BTreeMap<String, String> = base_env_versions
+            .versions
+            .into_iter()
+            .map(|(repo, version)| (repo, version.describe))
+            .collect();
+        for (repo, version) in self.version_overrides.iter() {
+            let base = targets.insert(repo.clone(), version.clone());
+            report.overridden.insert(
+                repo.clone(),
+                VersionOverride {
+                    version: version.clone(),
+                    base,
+                },
+            );
+        }
+        Ok((report, targets.into_iter().collect()))
+    }
+
+    /// Whether the repositories left are not attempted after a failure, as
+    /// set with [`on_error`](ObservingEnvironmentBuilder::on_error).
+    pub(crate) fn fails_fast(&self) -> bool {
+        self.on_error == ErrorPolicy::FailFast
+    }
+
+    /// Reset `repo_name` to its base `version` as part of resetting the
+    /// environment to `base_env_branch`, reporting to the observer and
+    /// returning the branch checked out if the version is one, and the
+    /// backup of the local commits of HEAD if it had any.
+    ///
+    /// A version naming `base_env_branch` falls back to the default branch
+    /// of the repository when it has no such branch.
+    pub(crate) fn reset_repository_to_base(
+        &self,
+        repo_name: &str,
+        version: &str,
+        base_env_branch: &str,
+    ) -> Result<(Option<String>, Option<Backup>), ObsEnvError> {
+        self.observed(repo_name, "reset", || {
+            let backup = match self.backup {
+                true => self.back_up_head(repo_name)?,
+                false => None,
+            };
+            let branch = match self.reset_to_version(repo_name, version) {
+                Err(error @ ObsEnvError::RevisionNotFound { .. }) if version == base_env_branch => {
+                    let default_branch = self.repo(repo_name)?.spec().default_branch.clone();
+                    match default_branch.filter(|default_branch| default_branch != version) {
+                        Some(default_branch) => {
+                            log::warn!(
+                                "{repo_name} has no {version} branch, resetting it to its default branch {default_branch}"
+                            );
+                            self.reset_to_version(repo_name, &default_branch)
+                        }
+                        None => Err(error),
+                    }
+                }
+                result => result,
+            }?;
+            Ok((branch, backup))
+        })
+    }
+
+    /// Keep the commits HEAD of `repo_name` has and no remote branch, tag
+    /// or other backup has on a new backup branch, if there are any.
+    fn back_up_head(&self, repo_name: &str) -> Result<Option<Backup>, ObsEnvError> {
+        let repo = self.repo(repo_name)?;
+        let path = repo.open()?;
+        if repo.is_empty() {
+            return Ok(None);
+        }
+        let backups_glob = format!("refs/heads/{BACKUP_PREFIX}*");
+        let commits = self
+            .backend
+            .commits_only_in(
+                path,
+                "HEAD",
+                &["refs/remotes/*", "refs/tags/*", &backups_glob],
+            )
+            .map_err(|error| ObsEnvError::git(repo_name, path, "list local commits", error))?;
+        if commits.is_empty() {
+            return Ok(None);
+        }
+        let created = unix_time();
+        for index in 0.. {
+            let branch = backup::branch_name(created, index);
+            match self.backend.create_branch(path, &branch, "HEAD") {
+                Ok(()) => {
+                    log::info!(
+                        "{repo_name}: kept {} local commits on {branch}",
+                        commits.len()
+                    );
+                    return Ok(Some(Backup {
+                        repo: repo_name.to_owned(),
+                        branch,
+                        created,
+                        commits,
+                    }));
+                }
+                Err(error) if error.code() == ErrorCode::Exists => {}
+                Err(error) => {
+                    return Err(ObsEnvError::git(
+                        repo_name,
+                        path,
+                        &format!("create branch {branch}"),
+                        error,
+                    ))
+                }
+            }
+        }
+        unreachable!("there is always a free backup branch name")
+    }
+
+    /// Backup branches of the cloned repositories, oldest first, by
+    /// repository name.
+    pub fn list_backups(&self) -> BTreeMap<String, Result<Vec<Backup>, ObsEnvError>> {
+        self.repos()
+            .filter(|repo| repo.exists())
+            .map(|repo| (repo.name().to_owned(), self.repo_backups(&repo)))
+            .collect()
+    }
+
+    fn repo_backups(&self, repo: &RepoHandle) -> Result<Vec<Backup>, ObsEnvError> {
+        let path = repo.path();
+        let git_error = |error| ObsEnvError::git(repo.name(), path, "list backups", error);
+        let mut backups = Vec::new();
+        for reference in self
+            .backend
+            .list_refs(path, &format!("refs/heads/{BACKUP_PREFIX}*"))
+            .map_err(git_error)?
+        {
+            let branch = reference.trim_start_matches("refs/heads/").to_owned();
+            let Some(created) = backup::created(&branch) else {
+                continue;
+            };
+            let commits = self
+                .backend
+                .commits_only_in(path, &reference, &["refs/remotes/*", "refs/tags/*"])
+                .map_err(git_error)?;
+            backups.push(Backup {
+                repo: repo.name().to_owned(),
+                branch,
+                created,
+                commits,
+            });
+        }
+        backups.sort_by(|a, b| (a.created, &a.branch).cmp(&(b.created, &b.branch)));
+        Ok(backups)
+    }
+
+    /// Delete the backup branches made more than `older_than` ago,
+    /// returning the backups dropped and the errors of the repositories
+    /// whose backups could not be listed or deleted.
+    pub fn drop_backups(&self, older_than: Duration) -> Vec<Result<Backup, ObsEnvError>> {
+        let before = unix_time().saturating_sub(older_than.as_secs());
+        let mut results = Vec::new();
+        for repo in self.repos().filter(|repo| repo.exists()) {
+            let backups = match self.repo_backups(&repo) {
+                Ok(backups) => backups,
+                Err(error) => {
+                    results.push(Err(error));
+                    continue;
+                }
+            };
+            for backup in backups
+                .into_iter()
+                .filter(|backup| backup.created <= before)
+            {
+                let result = self
+                    .backend
+                    .delete_branch(repo.path(), &backup.branch)
+                    .map_err(|error| {
+                        ObsEnvError::git(
+                            repo.name(),
+                            repo.path(),
+                            &format!("delete branch {}", backup.branch),
+                            error,
+                        )
+                    });
+                results.push(result.map(|()| backup));
+            }
+        }
+        results
+    }
+
+    /// Reset `repo_name` to `version` as part of resetting the environment,
+    /// reporting to the observer.
+    pub(crate) fn reset_repository(
+        &self,
+        repo_name: &str,
+        version: &str,
+    ) -> Result<(), ObsEnvError> {
+        self.observed(repo_name, "reset", || {
+            self.reset_index_to_version(repo_name, version)
+        })
+    }
+
+    /// Local branches of the cloned repositories that can be deleted
+    /// without losing commits, and remote-tracking branches whose branch
+    /// origin no longer has, by repository. They are deleted if `delete`.
+    ///
+    /// A local branch is stale if its tip is in the default branch of
+    /// origin, or if origin no longer has it and it has no commit of its
+    /// own. The branch checked out, the default branch, or that of origin
+    /// if not set, and the backup branches never are. Origin is asked
+    /// which branches it has, unless offline, which only finds the merged
+    /// branches and those with no commit but on other branches of origin
+    /// or tags.
+    pub fn stale_branches(
+        &self,
+        delete: bool,
+    ) -> BTreeMap<String, Result<StaleBranches, ObsEnvError>> {
+        self.repos()
+            .filter(|repo| repo.exists())
+            .map(|repo| {
+                let result = repo
+                    .open()
+                    .and_then(|path| self.repo_stale_branches(&repo, path, delete));
+                (
+                    repo.name().to_owned(),
+                    result.map_err(|error| repo.or_empty(error)),
+                )
+            })
+            .collect()
+    }
+
+    fn repo_stale_branches(
+        &self,
+        repo: &RepoHandle,
+        path: &Path,
+        delete: bool,
+    ) -> Result<StaleBranches, ObsEnvError> {
+        let repo_name = repo.name();
+        let git_error =
+            |operation: String| move |error| ObsEnvError::git(repo_name, path, &operation, error);
+        let branches = |glob: &str, prefix: &str| -> Result<Vec<String>, ObsEnvError> {
+            Ok(self
+                .backend
+                .list_refs(path, glob)
+                .map_err(git_error("list branches".to_owned()))?
+                .iter()
+                .filter_map(|name| name.strip_prefix(prefix))
+                .filter(|branch| *branch != "HEAD")
+                .map(str::to_owned)
+                .collect())
+        };
+        let tracking = branches("refs/remotes/origin/*", "refs/remotes/origin/")?;
+        let gone: Vec<&String> = match self.offline {
+            true => Vec::new(),
+            false => {
+                let on_origin = self
+                    .backend
+                    .remote_branches(path)
+                    .map_err(|error| ObsEnvError::fetch_failed(repo_name, path, error))?;
+                tracking
+                    .iter()
+                    .filter(|branch| !on_origin.contains(branch))
+                    .collect()
+            }
+        };
+        let current = self
+            .backend
+            .current_branch(path)
+            .map_err(git_error("read HEAD".to_owned()))?;
+        let default_branch = match &repo.spec().default_branch {
+            Some(default_branch) => Some(default_branch.clone()),
+            None => self
+                .backend
+                .remote_head(path)
+                .map_err(git_error("read origin/HEAD".to_owned()))?,
+        };
+        let upstream = default_branch
+            .as_ref()
+            .map(|branch| format!("refs/remotes/origin/{branch}"))
+            .filter(|upstream| self.backend.rev_parse(path, upstream).is_ok());
+        let ahead = |branch: &str, of: &str| {
+            self.backend
+                .ahead_behind(path, &format!("refs/heads/{branch}"), of)
+                .map(|(ahead, _)| ahead)
+                .map_err(git_error(format!("compare {branch} with {of}")))
+        };
+
+        let mut stale = StaleBranches {
+            repo: repo_name.to_owned(),
+            ..StaleBranches::default()
+        };
+        for branch in branches("refs/heads/*", "refs/heads/")? {
+            if current.as_ref() == Some(&branch)
+                || default_branch.as_ref() == Some(&branch)
+                || branch.starts_with(BACKUP_PREFIX)
+            {
+                continue;
+            }
+            let reason = match &upstream {
+                Some(upstream) if ahead(&branch, upstream)? == 0 => Some(StaleReason::Merged {
+                    into: default_branch.clone().unwrap_or_default(),
+                }),
+                _ if gone.contains(&&branch) => {
+                    let tracking = format!("refs/remotes/origin/{branch}");
+                    (ahead(&branch, &tracking)? == 0).then_some(StaleReason::Gone)
+                }
+                _ if tracking.contains(&branch) => None,
+                _ => self
+                    .backend
+                    .commits_only_in(
+                        path,
+                        &format!("refs/heads/{branch}"),
+                        &["refs/remotes/*", "refs/tags/*"],
+                    )
+                    .map_err(git_error("list local commits".to_owned()))?
+                    .is_empty()
+                    .then_some(StaleReason::Gone),
+            };
+            if let Some(reason) = reason {
+                stale.branches.push(StaleBranch { branch, reason });
+            }
+        }
+        stale.remote_refs = gone
+            .iter()
+            .map(|branch| format!("origin/{branch}"))
+            .collect();
+
+        if delete {
+            for branch in stale.branches.iter() {
+                self.backend
+                    .delete_branch(path, &branch.branch)
+                    .map_err(git_error(format!("delete branch {}", branch.branch)))?;
+            }
+            for remote_ref in stale.remote_refs.iter() {
+                self.backend
+                    .delete_reference(path, &format!("refs/remotes/{remote_ref}"))
+                    .map_err(git_error(format!("delete {remote_ref}")))?;
+            }
+            stale.deleted = true;
+        }
+        Ok(stale)
+    }
+
+    /// Fetch every branch and tag from origin into the cloned
+    /// repositories, by repository name.
+    ///
+    /// The other operations only fetch what they need, so this is only
+    /// needed to bring a whole repository up to date. With
+    /// [`ErrorPolicy::FailFast`], the repositories after the first failure
+    /// are not fetched.
+    pub fn fetch_repositories(&self) -> BTreeMap<String, Result<(), ObsEnvError>> {
+        let mut failed = false;
+        self.repos()
+            .filter(|repo| repo.exists())
+            .map(|repo| {
+                if failed {
+                    let error = ObsEnvError::not_attempted(repo.name(), "fetch");
+                    return (repo.name().to_owned(), Err(error));
+                }
+                let result = self.observed(repo.name(), "fetch", || {
+                    let path = repo.open()?;
+                    self.fetch_origin(
+                        repo.name(),
+                        path,
+                        &["+refs/heads/*:refs/remotes/origin/*"],
+                        true,
+                    )
+                    .map_err(|error| ObsEnvError::fetch_failed(repo.name(), path, error))
+                });
+                failed = result.is_err() && self.on_error == ErrorPolicy::FailFast;
+                (repo.name().to_owned(), result)
+            })
+            .collect()
+    }
+
+    /// Push the commit checked out in each cloned repository, and the tags
+    /// on it, to its mirror, at `url_template` with
+    /// [`{repo}`](mirror::REPO_PLACEHOLDER) replaced by the name of the
+    /// repository, by repository name.
+    ///
+    /// The commit is pushed as the branch `{prefix}/{branch}`, or
+    /// `{prefix}/HEAD` if HEAD is detached, and the tags as
+    /// `{prefix}/{tag}`, replacing what the mirror had under those names.
+    pub fn mirror(
+        &self,
+        url_template: &str,
+        prefix: &str,
+    ) -> BTreeMap<String, Result<Mirror, ObsEnvError>> {
+        self.repos()
+            .filter(|repo| repo.exists())
+            .map(|repo| {
+                let result = self.observed(repo.name(), "mirror", || {
+                    let path = repo.open()?;
+                    self.mirror_repository(repo.name(), path, url_template, prefix)
+                });
+                (
+                    repo.name().to_owned(),
+                    result.map_err(|error| repo.or_empty(error)),
+                )
+            })
+            .collect()
+    }
+
+    /// Check that the url of every repository, or of its fork, can be
+    /// listed, and has the configured default branch, or a HEAD without
+    /// one. Nothing is cloned.
+    ///
+    /// The repositories are checked concurrently, by up to
+    /// [`jobs`](ObservingEnvironmentBuilder::jobs) threads.
+    pub fn check_remotes(&self) -> Vec<ConfigCheck> {
+        let repos: Vec<RepoHandle> = self.repos().collect();
+        let results = parallel::map(&repos, self.jobs, |repo| {
+            let url = repo.url();
+            let (check, wanted) = match &repo.spec().default_branch {
+                Some(branch) => (
+                    format!("{} is reachable at {url} with branch {branch}", repo.name()),
+                    format!("refs/heads/{branch}"),
+                ),
+                None => (
+                    format!("{} is reachable at {url}", repo.name()),
+                    "HEAD".to_owned(),
+                ),
+            };
+            let problems = match self.backend.ls_remote(&url) {
+                Ok(refs) if refs.contains(&wanted) => Vec::new(),
+                Ok(_) => vec![format!("{wanted} not found")],
+                Err(error) => vec![error.message().to_owned()],
+            };
+            ConfigCheck::new(&check, problems)
+        });
+        repos
+            .iter()
+            .zip(results)
+            .map(|(repo, result)| {
+                result.unwrap_or_else(|message| {
+                    ConfigCheck::new(&format!("{} is reachable", repo.name()), vec![message])
+                })
+            })
+            .collect()
+    }
+
+    /// How far each cloned repository trails the head of the default
+    /// branch of origin, by repository name. The default branch is the
+    /// configured one, or that of origin, and is fetched first unless
+    /// offline.
+    ///
+    /// The repositories are fetched concurrently, by up to
+    /// [`jobs`](ObservingEnvironmentBuilder::jobs) threads.
+    pub fn outdated(&self) -> BTreeMap<String, Result<RepoStaleness, ObsEnvError>> {
+        let repos: Vec<RepoHandle> = self.repos().filter(|repo| repo.exists()).collect();
+        let results = parallel::map(&repos, self.jobs, |repo| {
+            self.observed(repo.name(), "compare with the default branch", || {
+                let path = repo.open()?;
+                self.repo_staleness(repo, path)
+            })
+            .map_err(|error| repo.or_empty(error))
+        });
+        repos
+            .iter()
+            .zip(results)
+            .map(|(repo, result)| {
+                let result = result.unwrap_or_else(|message| {
+                    Err(ObsEnvError::Panicked {
+                        operation: format!("compare {} with its default branch", repo.name()),
+                        message,
+                    })
+                });
+                (repo.name().to_owned(), result)
+            })
+            .collect()
+    }
+
+    /// Branch, local changes and operation in progress of each cloned
+    /// repository, by repository name, with the position of the branch
+    /// relative to origin as of the last fetch.
+    ///
+    /// The repositories are inspected concurrently, by up to
+    /// [`jobs`](ObservingEnvironmentBuilder::jobs) threads.
+    pub fn working_trees(&self) -> BTreeMap<String, Result<WorkingTree, ObsEnvError>> {
+        let repos: Vec<RepoHandle> = self.repos().filter(|repo| repo.exists()).collect();
+        let results = parallel::map(&repos, self.jobs, |repo| {
+            Self::working_tree(repo).map_err(|error| repo.or_empty(error))
+        });
+        repos
+            .iter()
+            .zip(results)
+            .map(|(repo, result)| {
+                let result = result.unwrap_or_else(|message| {
+                    Err(ObsEnvError::Panicked {
+                        operation: format!("read the status of {}", repo.name()),
+                        message,
+                    })
+                });
+                (repo.name().to_owned(), result)
+            })
+            .collect()
+    }
+
+    fn working_tree(repo: &RepoHandle) -> Result<WorkingTree, ObsEnvError> {
+        let repo_name = repo.name();
+        let path = repo.open()?;
+        let backend = &repo.obs_env.backend;
+        let commit = backend
+            .rev_parse(path, "HEAD")
+            .map_err(|error| ObsEnvError::git(repo_name, path, "resolve HEAD", error))?;
+        let branch = backend
+            .current_branch(path)
+            .map_err(|error| ObsEnvError::git(repo_name, path, "read HEAD", error))?;
+        let status = backend
+            .status(path)
+            .map_err(|error| ObsEnvError::git(repo_name, path, "read status", error))?;
+        Ok(WorkingTree {
+            repo: repo_name.to_owned(),
+            branch,
+            commit,
+            dirty: status.dirty,
+            modified_files: status.modified_files,
+            untracked: status.untracked,
+            ahead_behind: repo.ahead_behind()?,
+            in_progress: status.in_progress,
+        })
+    }
+
+    /// Repositories of the environment not at their version of the base
+    /// environment, or with local changes, by repository name, with the
+    /// commits between HEAD and the base version as of the last fetch.
+    /// Repositories on the branch or at the commit of their base version
+    /// are left out, as are those the base environment has no version
+    /// for.
+    ///
+    /// Fails if the base versions cannot be read. The repositories are
+    /// inspected concurrently, by up to
+    /// [`jobs`](ObservingEnvironmentBuilder::jobs) threads.
+    pub fn base_deviations(
+        &self,
+    ) -> Result<BTreeMap<String, Result<BaseDeviation, ObsEnvError>>, ObsEnvError> {
+        let base_versions = self
+            .get_base_env_versions_cached(&self.base_env_branch)?
+            .versions;
+        let repos: Vec<(RepoHandle, &RepoVersion)> = self
+            .repos()
+            .filter_map(|repo| {
+                let base = base_versions.get(repo.name())?;
+                Some((repo, base))
+            })
+            .collect();
+        let results = parallel::map(&repos, self.jobs, |(repo, base)| {
+            self.base_deviation(repo, base)
+                .map_err(|error| repo.or_empty(error))
+        });
+        Ok(repos
+            .iter()
+            .zip(results)
+            .filter_map(|((repo, _), result)| {
+                let result = result.unwrap_or_else(|message| {
+                    Err(ObsEnvError::Panicked {
+                        operation: format!("compare {} with the base environment", repo.name()),
+                        message,
+                    })
+                });
+                Some((repo.name().to_owned(), result.transpose()?))
+            })
+            .collect())
+    }
+
+    fn base_deviation(
+        &self,
+        repo: &RepoHandle,
+        base: &RepoVersion,
+    ) -> Result<Option<BaseDeviation>, ObsEnvError> {
+        let repo_name = repo.name();
+        let mut deviation = BaseDeviation {
+            repo: repo_name.to_owned(),
+            base: base.describe.clone(),
+            current: None,
+            ahead_behind: None,
+        };
+        if !repo.exists() {
+            return Ok(Some(deviation));
+        }
+        let current = repo.version()?;
+        if current.matches(base) && !current.dirty {
+            return Ok(None);
+        }
+        let path = repo.path();
+        let tag = Self::expand_version_to_tag(&base.describe);
+        if let Ok(revision) = self.resolve_revision(repo_name, path, &tag, &base.describe) {
+            let spec = match revision {
+                Revision::Tag(spec) => spec,
+                Revision::Branch => format!("refs/remotes/origin/{}", base.describe),
+                Revision::Commit(commit) => commit,
+            };
+            let (ahead, behind) =
+                self.backend
+                    .ahead_behind(path, "HEAD", &spec)
+                    .map_err(|error| {
+                        ObsEnvError::git(repo_name, path, "count the commits from the base", error)
+                    })?;
+            // A TSSW version, e.g. 1.2.0, does not describe the commit of
+            // its tag, v1.2.0, which HEAD may be at.
+            if (ahead, behind) == (0, 0) && !current.dirty {
+                return Ok(None);
+            }
+            deviation.ahead_behind = Some(AheadBehind { ahead, behind });
+        }
+        deviation.current = Some(current);
+        Ok(Some(deviation))
+    }
+
+    /// Delete the remote-tracking branches of the branches the remotes of
+    /// each cloned repository no longer have, by repository name, as a
+    /// fetch with `--prune` would, but without fetching. Every remote is
+    /// asked, not only origin, so this cannot be done offline.
+    ///
+    /// The repositories are pruned concurrently, by up to
+    /// [`jobs`](ObservingEnvironmentBuilder::jobs) threads.
+    pub fn prune_remotes(
+        &self,
+    ) -> Result<BTreeMap<String, Result<PrunedRemotes, ObsEnvError>>, ObsEnvError> {
+        if self.offline {
+            return Err(ObsEnvError::InvalidConfig {
+                message: "Cannot prune the remote-tracking branches while offline".to_owned(),
+            });
+        }
+        let repos: Vec<RepoHandle> = self.repos().filter(|repo| repo.exists()).collect();
+        let results = parallel::map(&repos, self.jobs, |repo| {
+            self.observed(repo.name(), "prune remote-tracking branches", || {
+                let (repo_name, path) = (repo.name(), repo.open()?);
+                let refs = self
+                    .backend
+                    .stale_remote_refs(path)
+                    .map_err(|error| ObsEnvError::fetch_failed(repo_name, path, error))?;
+                for name in refs.iter() {
+                    self.backend.delete_reference(path, name).map_err(|error| {
+                        ObsEnvError::git(repo_name, path, "delete a remote-tracking branch", error)
+                    })?;
+                }
+                Ok(PrunedRemotes {
+                    repo: repo_name.to_owned(),
+                    refs,
+                })
+            })
+            .map_err(|error| repo.or_empty(error))
+        });
+        Ok(repos
+            .iter()
+            .zip(results)
+            .map(|(repo, result)| {
+                let result = result.unwrap_or_else(|message| {
+                    Err(ObsEnvError::Panicked {
+                        operation: format!("prune the remote-tracking branches of {}", repo.name()),
+                        message,
+                    })
+                });
+                (repo.name().to_owned(), result)
+            })
+            .collect())
+    }
+
+    /// Copy the repositories cloned in the environment at `source` into
+    /// this one, cloning them from there with no network access, by
+    /// repository name. Each copy has the local branches of the source, and
+    /// its branch, or detached commit, checked out. The remote-tracking
+    /// branches of origin and its url are those of the source.
+    ///
+    /// Changes to the tracked files of the source are applied to the copy
+    /// with `copy_local_changes`, and left out otherwise. Untracked files
+    /// are never copied.
+    ///
+    /// The repositories are copied concurrently, by up to
+    /// [`jobs`](ObservingEnvironmentBuilder::jobs) threads.
+    pub fn clone_env(
+        &self,
+        source: &Path,
+        copy_local_changes: bool,
+    ) -> Result<BTreeMap<String, Result<ClonedRepo, ObsEnvError>>, ObsEnvError> {
+        let destination = Path::new(&self.destination);
+        if source == destination
+            || source.canonicalize().is_ok_and(|source| {
+                destination
+                    .canonicalize()
+                    .is_ok_and(|destination| source == destination)
+            })
+        {
+            return Err(ObsEnvError::InvalidConfig {
+                message: format!("Cannot clone {} into itself", source.display()),
+            });
+        }
+        let repos: Vec<RepoHandle> = self
+            .repos()
+            .filter(|repo| {
+                self.backend
+                    .open(&source.join(repo.spec().subdirectory()))
+                    .is_ok()
+            })
+            .collect();
+        if repos.is_empty() {
+            return Err(ObsEnvError::InvalidConfig {
+                message: format!(
+                    "{} has none of the repositories of the environment",
+                    source.display()
+                ),
+            });
+        }
+        self.create_path()?;
+        let results = parallel::map(&repos, self.jobs, |repo| {
+            self.observed(repo.name(), "clone from the source environment", || {
+                let source_path = source.join(repo.spec().subdirectory());
+                self.copy_repository(repo, &source_path, copy_local_changes)
+            })
+        });
+        Ok(repos
+            .iter()
+            .zip(results)
+            .map(|(repo, result)| {
+                let result = result.unwrap_or_else(|message| {
+                    Err(ObsEnvError::Panicked {
+                        operation: format!("clone {} from the source environment", repo.name()),
+                        message,
+                    })
+                });
+                (repo.name().to_owned(), result)
+            })
+            .collect())
+    }
+
+    fn copy_repository(
+        &self,
+        repo: &RepoHandle,
+        source: &Path,
+        copy_local_changes: bool,
+    ) -> Result<ClonedRepo, ObsEnvError> {
+        let repo_name = repo.name();
+        let path = repo.path();
+        let source_error = |operation: &'static str| {
+            move |error| ObsEnvError::git(repo_name, source, operation, error)
+        };
+        let git_error = |operation: &'static str| {
+            move |error| ObsEnvError::git(repo_name, path, operation, error)
+        };
+        let branch = self
+            .backend
+            .current_branch(source)
+            .map_err(source_error("read the branch checked out"))?;
+        let commit = self
+            .backend
+            .rev_parse(source, "HEAD")
+            .map_err(source_error("resolve HEAD"))?;
+        let origin_url = self
+            .backend
+            .remote_url(source)
+            .map_err(source_error("read the url of origin"))?;
+        let dirty = self
+            .backend
+            .status(source)
+            .map_err(source_error("read the status"))?
+            .dirty;
+        let patch = match dirty && copy_local_changes {
+            true => Some(
+                self.backend
+                    .diff_head(source)
+                    .map_err(source_error("diff with HEAD"))?,
+            ),
+            false => None,
+        };
+        let local_branches: Vec<String> = self
+            .backend
+            .list_refs(source, "refs/heads/*")
+            .map_err(source_error("list the local branches"))?;
+
+        let url = source.to_string_lossy();
+        let progress = self.transfer_progress(repo_name);
+        self.timings
+            .time("clone", Some(repo_name), || {
+                self.backend
+                    .clone(&url, path, false, CloneOptions::default(), &progress)
+            })
+            .map_err(|error| ObsEnvError::clone_failed(repo_name, &url, path, error))?;
+        // The local branches of the source are the branches of origin of
+        // the clone.
+        let cloned_branches = self
+            .backend
+            .list_refs(path, "refs/heads/*")
+            .map_err(git_error("list the local branches"))?;
+        for name in local_branches.iter() {
+            let local = name.trim_start_matches("refs/heads/");
+            if cloned_branches.contains(name) || branch.as_deref() == Some(local) {
+                continue;
+            }
+            self.backend
+                .create_branch(path, local, &format!("refs/remotes/origin/{local}"))
+                .map_err(git_error("create a local branch"))?;
+        }
+        if let Some(branch) = &branch {
+            self.backend
+                .checkout_branch(path, branch)
+                .map_err(|error| ObsEnvError::BranchNotFound {
+                    repo: repo_name.to_owned(),
+                    path: path.to_path_buf(),
+                    branch: branch.clone(),
+                    closest: Vec::new(),
+                    source: error,
+                })?;
+        }
+        for name in self
+            .backend
+            .list_refs(path, "refs/remotes/origin/*")
+            .map_err(git_error("list the branches of origin"))?
+        {
+            self.backend
+                .delete_reference(path, &name)
+                .map_err(git_error("delete a branch of origin"))?;
+        }
+        self.backend
+            .fetch(
+                path,
+                &["+refs/remotes/origin/*:refs/remotes/origin/*"],
+                true,
+                &progress,
+            )
+            .map_err(git_error("fetch the branches of origin of the source"))?;
+        if branch.is_none() {
+            self.backend
+                .reset(path, &commit, None)
+                .map_err(git_error("check out the commit of the source"))?;
+        }
+        // Checking out a branch leaves a temporary one behind, and the
+        // clone has the default branch of the source even if it had no
+        // local branch.
+        for name in self
+            .backend
+            .list_refs(path, "refs/heads/*")
+            .map_err(git_error("list the local branches"))?
+        {
+            if !local_branches.contains(&name) {
+                self.backend
+                    .delete_branch(path, name.trim_start_matches("refs/heads/"))
+                    .map_err(git_error("delete a local branch"))?;
+            }
+        }
+        if let Some(origin_url) = &origin_url {
+            self.backend
+                .set_remote_url(path, origin_url)
+                .map_err(git_error("set the url of origin"))?;
+        }
+        let local_changes = match (dirty, patch) {
+            (false, _) => LocalChanges::None,
+            (true, None) => LocalChanges::Skipped,
+            (true, Some(patch)) => {
+                self.backend
+                    .apply_patch(path, &patch)
+                    .map_err(git_error("apply the local changes of the source"))?;
+                LocalChanges::Copied
+            }
+        };
+        self.share_repository(repo_name, path)?;
+        Ok(ClonedRepo {
+            repo: repo_name.to_owned(),
+            branch,
+            commit,
+            local_changes,
+        })
+    }
+
+    /// Report of the state of the environment, with the sections of
+    /// `options`. What cannot be read is reported in its section rather
+    /// than failing the report.
+    pub fn env_report(&self, options: &ReportOptions) -> EnvReport {
+        let sections = &options.sections;
+        let wants = |section| sections.contains(&section);
+        let current_versions = match wants(ReportSection::Versions)
+            || wants(ReportSection::BaseDiff)
+            || wants(ReportSection::Dirty)
+        {
+            true => self.get_current_env_versions(),
+            false => BTreeMap::new(),
+        };
+        let content = sections
+            .iter()
+            .map(|section| match section {
+                ReportSection::Versions => SectionContent::Versions(
+                    current_versions
+                        .iter()
+                        .map(|(repo_name, version)| {
+                            let version = version
+                                .as_ref()
+                                .map_err(|error| crate::error::report(error));
+                            (repo_name.clone(), version.cloned())
+                        })
+                        .collect(),
+                ),
+                ReportSection::BaseDiff => SectionContent::BaseDiff(
+                    self.get_base_env_versions_cached(&self.base_env_branch)
+                        .map(|base| {
+                            base.versions
+                                .into_iter()
+                                .filter_map(|(repo_name, base)| {
+                                    let current = current_versions
+                                        .get(&repo_name)
+                                        .and_then(|version| version.as_ref().ok());
+                                    if current.is_some_and(|current| {
+                                        current.matches(&base) && !current.dirty
+                                    }) {
+                                        return None;
+                                    }
+                                    Some(RepoDifference {
+                                        repo: repo_name,
+                                        from: Some(base),
+                                        to: current.cloned(),
+                                        commits: None,
+                                    })
+                                })
+                                .collect()
+                        })
+                        .map_err(|error| crate::error::report(&error)),
+                ),
+                ReportSection::Dirty => SectionContent::Dirty(
+                    current_versions
+                        .values()
+                        .filter_map(|version| version.as_ref().ok())
+                        .filter(|version| version.dirty || version.untracked > 0)
+                        .cloned()
+                        .collect(),
+                ),
+                ReportSection::Outdated => {
+                    let mut stalenesses = Vec::new();
+                    let mut errors = Vec::new();
+                    for (_, result) in self.outdated() {
+                        match result {
+                            Ok(staleness) => stalenesses.push(staleness),
+                            Err(error) => errors.push(crate::error::report(&error)),
+                        }
+                    }
+                    let report = OutdatedReport::new(
+                        stalenesses,
+                        options.thresholds.clone(),
+                        &options.outdated_ignore,
+                        unix_time(),
+                    );
+                    SectionContent::Outdated(report, errors)
+                }
+                ReportSection::LastReset => SectionContent::LastReset(
+                    self.read_metadata()
+                        .map_err(|error| crate::error::report(&error)),
+                ),
+                ReportSection::History => SectionContent::History(
+                    self.history(&options.history)
+                        .map_err(|error| crate::error::report(&error)),
+                ),
+            })
+            .collect();
+        EnvReport {
+            env_path: self.destination.clone(),
+            time: unix_time(),
+            sections: content,
+        }
+    }
+
+    fn repo_staleness(&self, repo: &RepoHandle, path: &Path) -> Result<RepoStaleness, ObsEnvError> {
+        let repo_name = repo.name();
+        let git_error = |operation: &'static str| {
+            move |error| ObsEnvError::git(repo_name, path, operation, error)
+        };
+        let default_branch = match &repo.spec().default_branch {
+            Some(default_branch) => default_branch.clone(),
+            None => self
+                .backend
+                .remote_head(path)
+                .map_err(git_error("read origin/HEAD"))?
+                .ok_or_else(|| {
+                    git_error("find the default branch of origin")(Error::new(
+                        ErrorCode::NotFound,
+                        ErrorClass::Reference,
+                        "origin/HEAD is not set, configure the default_branch of the repository",
+                    ))
+                })?,
+        };
+        self.fetch_origin(repo_name, path, &[&branch_refspec(&default_branch)], false)
+            .map_err(|error| ObsEnvError::fetch_failed(repo_name, path, error))?;
+        let upstream = format!("refs/remotes/origin/{default_branch}");
+        let (_, behind) = self
+            .backend
+            .ahead_behind(path, "HEAD", &upstream)
+            .map_err(git_error("compare HEAD with the default branch"))?;
+        let mut staleness = RepoStaleness {
+            repo: repo_name.to_owned(),
+            default_branch,
+            behind,
+            behind_since: None,
+            newest_unpulled: None,
+        };
+        if behind > 0 {
+            let unpulled = self
+                .backend
+                .commit_log(path, "HEAD", &upstream)
+                .map_err(git_error("list the commits of the default branch"))?;
+            let time = |revision: &str| {
+                self.backend
+                    .commit_time(path, revision)
+                    .map_err(git_error("read commit time"))
+            };
+            let oldest = unpulled
+                .last()
+                .map_or(upstream.as_str(), |commit| &commit.id);
+            staleness.behind_since = Some(time(oldest)?);
+            staleness.newest_unpulled = Some(time(&upstream)?);
+        }
+        Ok(staleness)
+    }
+
+    fn mirror_repository(
+        &self,
+        repo_name: &str,
+        path: &Path,
+        url_template: &str,
+        prefix: &str,
+    ) -> Result<Mirror, ObsEnvError> {
+        let url = mirror::mirror_url(url_template, repo_name);
+        if self.offline {
+            return Err(ObsEnvError::Offline {
+                operation: format!("push {repo_name} to {url}"),
+            });
+        }
+        let git_error = |operation: &'static str| {
+            move |error| ObsEnvError::git(repo_name, path, operation, error)
+        };
+        let head = self
+            .backend
+            .rev_parse(path, "HEAD")
+            .map_err(git_error("read HEAD"))?;
+        let mut refs = vec![match self
+            .backend
+            .current_branch(path)
+            .map_err(git_error("read HEAD"))?
+        {
+            Some(branch) => MirroredRef {
+                source: format!("refs/heads/{branch}"),
+                destination: format!("refs/heads/{prefix}/{branch}"),
+            },
+            None => MirroredRef {
+                source: head.clone(),
+                destination: format!("refs/heads/{prefix}/HEAD"),
+            },
+        }];
+        for tag in self
+            .backend
+            .list_refs(path, "refs/tags/*")
+            .map_err(git_error("list tags"))?
+        {
+            if self.backend.rev_parse(path, &tag).ok().as_ref() == Some(&head) {
+                let name = tag.trim_start_matches("refs/tags/");
+                refs.push(MirroredRef {
+                    destination: format!("refs/tags/{prefix}/{name}"),
+                    source: tag,
+                });
+            }
+        }
+        let refspecs: Vec<String> = refs.iter().map(MirroredRef::refspec).collect();
+        self.backend
+            .push(path, &url, &refspecs)
+            .map_err(|error| ObsEnvError::git(repo_name, path, &format!("push to {url}"), error))?;
+        Ok(Mirror {
+            repo: repo_name.to_owned(),
+            url,
+            refs,
+        })
+    }
+
+    /// Commits of the cloned repositories matching `query`, newest first,
+    /// by repository. Only the local history is searched, unless
+    /// `fetch_first`, which fetches every branch before.
+    pub fn search_commits(
+        &self,
+        query: &CommitQuery,
+        fetch_first: bool,
+    ) -> BTreeMap<String, Result<Vec<CommitInfo>, ObsEnvError>> {
+        self.repos()
+            .filter(|repo| repo.exists())
+            .map(|repo| {
+                let result = repo.open().and_then(|path| {
+                    if fetch_first {
+                        self.fetch_origin(
+                            repo.name(),
+                            path,
+                            &["+refs/heads/*:refs/remotes/origin/*"],
+                            false,
+                        )
+                        .map_err(|error| ObsEnvError::fetch_failed(repo.name(), path, error))?;
+                    }
+                    self.backend.search_commits(path, query).map_err(|error| {
+                        ObsEnvError::git(repo.name(), path, "search commits", error)
+                    })
+                });
+                (
+                    repo.name().to_owned(),
+                    result.map_err(|error| repo.or_empty(error)),
+                )
+            })
+            .collect()
+    }
+
+    /// Checkout branch on specified repository, returning the name of the
+    /// branch checked out.
+    ///
+    /// A branch that does not exist but whose name matches one of the
+    /// ticket patterns, like `DM-12345`, is checked out from
+    /// `tickets/DM-12345` instead. A name that exists as given is never
+    /// expanded.
+    ///
+    /// The branches of origin are listed first, or its remote-tracking
+    /// branches if offline, so a misspelled name fails before fetching,
+    /// with [`ObsEnvError::BranchNotFound`] naming the closest branches.
+    ///
+    /// ```no_run
+    /// use ts_observing_environment::ObservingEnvironment;
+    ///
+    /// let obs_env = ObservingEnvironment::with_destination("/obs-env");
+    /// assert_eq!(obs_env.checkout_branch("ts_wep", "DM-12345")?, "tickets/DM-12345");
+    /// # Ok::<(), ts_observing_environment::ObsEnvError>(())
+    /// ```
+    pub fn checkout_branch(
+        &self,
+        repo_name: &str,
+        branch_name: &str,
+    ) -> Result<String, ObsEnvError> {
+        let repo = self.repo(repo_name)?;
+        let path = repo.open()?;
+        self.check_not_busy(repo_name, path)?;
+
+        // An empty repository has no branches to list, and listing those of
+        // an empty origin trips git2.
+        let branches = match repo.is_empty() {
+            true => None,
+            false => self.known_branches(repo_name, path),
+        };
+        if let Some(branches) = branches {
+            let expanded = self.expand_branch_name(branch_name);
+            let Some(name) = std::iter::once(branch_name)
+                .chain(expanded.as_deref())
+                .find(|name| branches.iter().any(|branch| branch == name))
+            else {
+                return Err(repo.or_empty(ObsEnvError::BranchNotFound {
+                    repo: repo_name.to_owned(),
+                    path: path.to_path_buf(),
+                    branch: branch_name.to_owned(),
+                    closest: closest_names(branch_name, &branches),
+                    source: git2::Error::new(
+                        git2::ErrorCode::NotFound,
+                        git2::ErrorClass::Reference,
+                        format!("no branch {branch_name} on origin"),
+                    ),
+                }));
+            };
+            self.checkout_branch_named(repo_name, path, name)
+                .map_err(|error| repo.or_empty(error))?;
+            if name != branch_name {
+                log::info!("{repo_name}: checked out {name} for {branch_name}");
+            }
+            return Ok(name.to_owned());
+        }
+
+        match self.checkout_branch_named(repo_name, path, branch_name) {
+            Err(error @ ObsEnvError::BranchNotFound { .. }) => {
+                let Some(expanded) = self.expand_branch_name(branch_name) else {
+                    return Err(repo.or_empty(error));
+                };
+                log::debug!("{repo_name}: no branch {branch_name}, trying {expanded}");
+                match self.checkout_branch_named(repo_name, path, &expanded) {
+                    Ok(()) => {
+                        log::info!("{repo_name}: checked out {expanded} for {branch_name}");
+                        Ok(expanded)
+                    }
+                    Err(ObsEnvError::BranchNotFound { .. }) => Err(repo.or_empty(error)),
+                    Err(error) => Err(repo.or_empty(error)),
+                }
+            }
+            Ok(()) => Ok(branch_name.to_owned()),
+            Err(error) => Err(repo.or_empty(error)),
+        }
+    }
+
+    /// Checkout `branch_name`, or the ticket branch it expands to, in every
+    /// cloned repository that has it, as
+    /// [`checkout_branch`](Self::checkout_branch) does, by repository name.
+    /// The result is the branch checked out, or none for the repositories
+    /// without it, which are left as they are.
+    ///
+    /// The repositories are fetched concurrently, by up to
+    /// [`jobs`](ObservingEnvironmentBuilder::jobs) threads.
+    pub fn checkout_branch_all(
+        &self,
+        branch_name: &str,
+    ) -> BTreeMap<String, Result<Option<String>, ObsEnvError>> {
+        let repos: Vec<RepoHandle> = self.repos().filter(|repo| repo.exists()).collect();
+        let results = parallel::map(&repos, self.jobs, |repo| {
+            self.observed(repo.name(), "checkout", || {
+                match self.checkout_branch(repo.name(), branch_name) {
+                    Ok(branch) => Ok(Some(branch)),
+                    Err(ObsEnvError::BranchNotFound { .. }) => Ok(None),
+                    Err(error) => Err(error),
+                }
+            })
+        });
+        repos
+            .iter()
+            .zip(results)
+            .map(|(repo, result)| {
+                let result = result.unwrap_or_else(|message| {
+                    Err(ObsEnvError::Panicked {
+                        operation: format!("checkout {branch_name} in {}", repo.name()),
+                        message,
+                    })
+                });
+                (repo.name().to_owned(), result)
+            })
+            .collect()
+    }
+
+    /// Name `branch_name` expands to when it does not exist, if it matches
+    /// one of the ticket patterns.
+    pub fn expand_branch_name(&self, branch_name: &str) -> Option<String> {
+        self.ticket_patterns
+            .iter()
+            .any(|pattern| pattern.is_match(branch_name))
+            .then(|| format!("tickets/{branch_name}"))
+    }
+
+    /// Branches that can be checked out in the repository at `path`: the
+    /// local ones and those of origin, or its remote-tracking branches if
+    /// offline. None if they cannot be listed, e.g. origin is unreachable,
+    /// leaving the checkout to find out.
+    fn known_branches(&self, repo_name: &str, path: &Path) -> Option<Vec<String>> {
+        let listed = |glob: &str, prefix: &str| {
+            self.backend.list_refs(path, glob).map(|names| {
+                names
+                    .iter()
+                    .filter_map(|name| name.strip_prefix(prefix))
+                    .filter(|branch| *branch != "HEAD")
+                    .map(str::to_owned)
+                    .collect::<Vec<_>>()
+            })
+        };
+        let origin = match self.offline {
+            true => listed("refs/remotes/origin/*", "refs/remotes/origin/"),
+            false => self.backend.remote_branches(path),
+        };
+        match (listed("refs/heads/*", "refs/heads/"), origin) {
+            (Ok(mut branches), Ok(on_origin)) => {
+                branches.extend(on_origin);
+                branches.sort();
+                branches.dedup();
+                Some(branches)
+            }
+            (Err(error), _) | (_, Err(error)) => {
+                log::debug!("{repo_name}: cannot list the branches: {error}");
+                None
+            }
+        }
+    }
+
+    /// Fetch and checkout `branch_name`, exactly as named.
+    fn checkout_branch_named(
+        &self,
+        repo_name: &str,
+        path: &Path,
+        branch_name: &str,
+    ) -> Result<(), ObsEnvError> {
+        self.fetch_origin(repo_name, path, &[&branch_refspec(branch_name)], false)
+            .map_err(|error| ObsEnvError::fetch_failed(repo_name, path, error))?;
+        self.timings
+            .time("checkout", Some(repo_name), || {
+                self.backend.checkout_branch(path, branch_name)
+            })
+            .map_err(|error| {
+                if error.code() == git2::ErrorCode::NotFound {
+                    ObsEnvError::BranchNotFound {
+                        repo: repo_name.to_owned(),
+                        path: path.to_path_buf(),
+                        branch: branch_name.to_owned(),
+                        closest: Vec::new(),
+                        source: error,
+                    }
+                } else {
+                    ObsEnvError::git(
+                        repo_name,
+                        path,
+                        &format!("checkout branch {branch_name}"),
+                        error,
+                    )
+                }
+            })
+    }
+
+    /// Make sure a repository is not in the middle of a merge, rebase,
+    /// cherry-pick or similar operation and has no conflicted files.
+    ///
+    /// If the environment is set to abort in-progress operations, the
+    /// pending operation is aborted instead of failing.
+    fn check_not_busy(&self, repo_name: &str, path: &Path) -> Result<(), ObsEnvError> {
+        let status = self
+            .backend
+            .status(path)
+            .map_err(|error| ObsEnvError::git(repo_name, path, "read status", error))?;
+
+        match status.in_progress {
+            Some(state) if self.abort_in_progress => {
+                log::warn!("Aborting {state} in progress in {repo_name}");
+                self.backend.abort_in_progress(path).map_err(|error| {
+                    ObsEnvError::git(repo_name, path, &format!("abort {state}"), error)
+                })
+            }
+            Some(state) => Err(ObsEnvError::RepoBusy {
+                repo: repo_name.to_owned(),
+                state,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Update the local cache of the base environment source repository.
+    ///
+    /// The cache is a bare clone kept under the environment path, which is
+    /// fetched incrementally for the requested branch, unless offline.
+    fn update_base_env_source(&self, base_env_branch: &str) -> Result<PathBuf, ObsEnvError> {
+        let base_env_source_path = self.get_base_env_source_repo()?;
+
+        if self.offline {
+            log::debug!("Offline, using cached base environment source.");
+        } else {
+            self.backend
+                .fetch(
+                    &base_env_source_path,
+                    &[&format!(
+                        "+refs/heads/{base_env_branch}:refs/remotes/origin/{base_env_branch}"
+                    )],
+                    matches!(self.base_env_revision, BaseEnvRevision::At(_)),
+                    &self.transfer_progress(&self.base_env_source_repo),
+                )
+                .map_err(|error| {
+                    ObsEnvError::fetch_failed(
+                        &self.base_env_source_repo,
+                        &base_env_source_path,
+                        error,
+                    )
+                })?;
+        }
+        Ok(base_env_source_path)
+    }
+
+    /// Path to the bare clone caching the base environment source repository.
+    fn base_env_cache_path(&self) -> PathBuf {
+        self.cache_dir().join(BASE_ENV_CACHE)
+    }
+
+    /// Directory where the base environment caches are kept.
+    fn cache_dir(&self) -> PathBuf {
+        match &self.cache_dir {
+            Some(cache_dir) => cache_dir.clone(),
+            None => Path::new(&self.destination).join(OBS_ENV_DIR),
+        }
+    }
+
+    /// Path to the base environment source cache, cloning it if needed.
+    fn get_base_env_source_repo(&self) -> Result<PathBuf, ObsEnvError> {
+        let base_env_source_path = self.base_env_cache_path();
+
+        if self.refresh_base_cache && base_env_source_path.exists() && !self.offline {
+            log::debug!("Removing base environment cache for a full re-fetch.");
+            remove_dir_all(&base_env_source_path)
+                .map_err(|error| ObsEnvError::io(&base_env_source_path, "remove", error))?;
+        }
+
+        if self.backend.open(&base_env_source_path).is_ok() {
+            Ok(base_env_source_path)
+        } else if self.offline {
+            Err(ObsEnvError::Offline {
+                operation: format!(
+                    "fetch the base environment source without a cache in {}",
+                    base_env_source_path.display()
+                ),
+            })
+        } else {
+            // need to clone base env source repo
+            if let Some(parent) = base_env_source_path.parent() {
+                create_dir_all(parent).map_err(|error| ObsEnvError::io(parent, "create", error))?;
+            }
+            let url = format!(
+                "{}/{}",
+                self.base_env_source_org.trim_end_matches('/'),
+                self.base_env_source_repo
+            );
+            match self.backend.clone(
+                &url,
+                &base_env_source_path,
+                true,
+                CloneOptions::default(),
+                &self.transfer_progress(&self.base_env_source_repo),
+            ) {
+                Ok(()) => Ok(base_env_source_path),
+                Err(error) => Err(ObsEnvError::clone_failed(
+                    &self.base_env_source_repo,
+                    &url,
+                    &base_env_source_path,
+                    error,
+                )),
+            }
+        }
+    }
+
+    /// Get base versions of all the packages.
+    ///
+    /// This method will parse the base_env_def_file (e.g. cycle/cycle.env) to
+    /// get the versions of the base env packages. If a local base
+    /// environment source is set, it is read instead of the remote branch.
+    ///
+    /// ```
+    /// use ts_observing_environment::ObservingEnvironment;
+    ///
+    /// let versions_file = std::env::temp_dir().join("obs_env_doc_versions.env");
+    /// std::fs::write(&versions_file, "ts_wep=1.2.3\n")?;
+    ///
+    /// let mut obs_env = ObservingEnvironment::with_destination("/obs-env");
+    /// obs_env.set_base_env_source(&versions_file.to_string_lossy());
+    ///
+    /// let versions = obs_env.get_base_env_versions("main")?;
+    /// assert_eq!(versions["ts_wep"].to_string(), "1.2.3");
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn get_base_env_versions(
+        &self,
+        base_env_branch: &str,
+    ) -> Result<BTreeMap<String, RepoVersion>, ObsEnvError> {
+        Ok(self.get_base_env_versions_cached(base_env_branch)?.versions)
+    }
+
+    /// Get base versions of all the packages, telling whether they come
+    /// from the versions cache.
+    ///
+    /// With a [`base_versions_ttl`](ObservingEnvironmentBuilder::base_versions_ttl),
+    /// the versions read from the base environment source repository are
+    /// cached in `cache/base_versions_<branch>.json` of the
+    /// [`cache_dir`](ObservingEnvironmentBuilder::cache_dir), and the
+    /// cache is used while it is younger than the ttl, unless refreshing
+    /// the base cache. Offline, the cache is used whatever its age.
+    pub fn get_base_env_versions_cached(
+        &self,
+        base_env_branch: &str,
+    ) -> Result<BaseEnvVersions, ObsEnvError> {
+        self.base_env_versions(base_env_branch, true)
+    }
+
+    /// Base versions of `base_env_branch`, read from the versions cache if
+    /// `use_cache` is set and the cache can be used.
+    fn base_env_versions(
+        &self,
+        base_env_branch: &str,
+        use_cache: bool,
+    ) -> Result<BaseEnvVersions, ObsEnvError> {
+        // Only the tip of the branch is cached, as it is what moves.
+        let cached = (use_cache && self.base_env_revision == BaseEnvRevision::Latest)
+            .then(|| self.read_versions_cache(base_env_branch))
+            .flatten();
+        let (base_env_def, cache_age, commit) = match &self.base_env_local_source {
+            Some(local_source) => (
+                self.load_local_base_env_def(Path::new(local_source))?,
+                None,
+                None,
+            ),
+            None => match cached {
+                Some((cache, age)) => (cache.base_env_def, Some(age), cache.commit),
+                None => {
+                    let (base_env_def, commit) =
+                        self.timings.time("base env fetch", None, || {
+                            let base_env_source_path =
+                                self.update_base_env_source(base_env_branch)?;
+                            let commit =
+                                self.base_env_commit(&base_env_source_path, base_env_branch)?;
+                            let base_env_def = self.load_base_env_def_file(
+                                &base_env_source_path,
+                                base_env_branch,
+                                &commit,
+                            )?;
+                            Ok::<_, ObsEnvError>((base_env_def, commit))
+                        })?;
+                    if self.base_env_revision == BaseEnvRevision::Latest {
+                        self.write_versions_cache(base_env_branch, &base_env_def, &commit);
+                    }
+                    (base_env_def, None, Some(commit))
+                }
+            },
+        };
+        Ok(BaseEnvVersions {
+            versions: self.parse_base_env_versions(&base_env_def),
+            cache_age,
+            commit,
+        })
+    }
+
+    /// Commit of the cache of the base environment source at `path` the
+    /// versions of `base_env_branch` are read at, as set with
+    /// [`base_env_revision`](ObservingEnvironmentBuilder::base_env_revision).
+    fn base_env_commit(&self, path: &Path, base_env_branch: &str) -> Result<String, ObsEnvError> {
+        let branch = format!("refs/remotes/origin/{base_env_branch}");
+        let unavailable = |reason: String| ObsEnvError::BaseEnvUnavailable {
+            location: self.base_env_location(base_env_branch),
+            reason,
+        };
+        match &self.base_env_revision {
+            BaseEnvRevision::Latest => self.backend.rev_parse(path, &branch),
+            BaseEnvRevision::AsOf(time) => match self.backend.commit_before(path, &branch, *time) {
+                Ok(Some(commit)) => Ok(commit),
+                Ok(None) => {
+                    return Err(unavailable(format!(
+                        "{base_env_branch} has no commit before {}, its history starts later",
+                        audit::format_utc(*time)
+                    )))
+                }
+                Err(error) => Err(error),
+            },
+            BaseEnvRevision::At(revision) => self.backend.rev_parse(path, revision),
+        }
+        .map_err(|error| unavailable(error.message().to_owned()))
+    }
+
+    /// Base environment definition file of `base_env_branch` at the
+    /// revision read, for errors.
+    fn base_env_location(&self, base_env_branch: &str) -> String {
+        match &self.base_env_revision {
+            BaseEnvRevision::Latest => format!("{} on {base_env_branch}", self.base_env_def_file),
+            revision => format!("{} on {base_env_branch} {revision}", self.base_env_def_file),
+        }
+    }
+
+    /// Path to the cached versions of `base_env_branch`.
+    fn versions_cache_path(&self, base_env_branch: &str) -> PathBuf {
+        self.cache_dir().join(VERSIONS_CACHE_DIR).join(format!(
+            "base_versions_{}.json",
+            base_env_branch.replace('/', "_")
+        ))
+    }
+
+    /// Versions cache of `base_env_branch` and its age, if it can be used.
+    fn read_versions_cache(&self, base_env_branch: &str) -> Option<(VersionsCache, Duration)> {
+        let ttl = self.base_versions_ttl?;
+        if self.refresh_base_cache {
+            return None;
+        }
+        let path = self.versions_cache_path(base_env_branch);
+        let cache: VersionsCache = serde_json::from_str(&read_to_string(&path).ok()?)
+            .inspect_err(|error| log::warn!("Ignoring invalid {}: {error}", path.display()))
+            .ok()?;
+        let age = Duration::from_secs(unix_time().saturating_sub(cache.created));
+        if self.offline {
+            if age >= ttl {
+                log::warn!(
+                    "Offline, using base environment versions cached {}s ago.",
+                    age.as_secs()
+                );
+            }
+            Some((cache, age))
+        } else if age < ttl {
+            Some((cache, age))
+        } else {
+            None
+        }
+    }
+
+    /// Cache `base_env_def` as the definition of `base_env_branch` at
+    /// `commit`, if caching. Failing to write the cache only costs speed,
+    /// so it is logged and ignored.
+    fn write_versions_cache(&self, base_env_branch: &str, base_env_def: &[String], commit: &str) {
+        if self.base_versions_ttl.is_none() {
+            return;
+        }
+        let path = self.versions_cache_path(base_env_branch);
+        let cache = VersionsCache {
+            created: unix_time(),
+            base_env_def: base_env_def.to_vec(),
+            commit: Some(commit.to_owned()),
+        };
+        let content = serialized(serde_json::to_string(&cache));
+        let result = match path.parent() {
+            Some(parent) => {
+                create_dir_all(parent).map_err(|error| ObsEnvError::io(parent, "create", error))
+            }
+            None => Ok(()),
+        }
+        .and_then(|_| write_atomically(&path, &content));
+        if let Err(error) = result {
+            log::warn!("Failed to cache the base environment versions: {error}");
+        }
+    }
+
+    /// Point of the history of the base environment branch its versions
+    /// are read at.
+    pub fn get_base_env_revision(&self) -> &BaseEnvRevision {
+        &self.base_env_revision
+    }
+
+    /// Describe where the base environment versions are read from.
+    pub fn describe_base_env_source(&self, base_env_branch: &str) -> String {
+        match &self.base_env_local_source {
+            Some(local_source) => format!("local path {local_source}"),
+            None if self.offline => format!(
+                "cached copy of branch {base_env_branch} in {}{}",
+                self.base_env_cache_path().display(),
+                self.describe_base_env_revision()
+            ),
+            None => format!(
+                "remote branch {base_env_branch} of {}/{}{}",
+                self.base_env_source_org.trim_end_matches('/'),
+                self.base_env_source_repo,
+                self.describe_base_env_revision()
+            ),
+        }
+    }
+
+    fn describe_base_env_revision(&self) -> String {
+        match &self.base_env_revision {
+            BaseEnvRevision::Latest => String::new(),
+            revision => format!(" {revision}"),
+        }
+    }
+
+    /// Extract the versions of the managed repositories from the lines of a
+    /// base environment definition.
+    fn parse_base_env_versions(&self, base_env_def: &[String]) -> BTreeMap<String, RepoVersion> {
+        let base_env_versions: Vec<Option<&String>> = self
+            .repositories
+            .keys()
+            .map(|repo_name| base_env_def.iter().find(|line| line.starts_with(repo_name)))
+            .collect();
+        // This should never fail because we know REPO_VERSION_REGEXP is
+        // valid.
+        let regex = Regex::new(REPO_VERSION_REGEXP).unwrap();
+        base_env_versions
+            .into_iter()
+            .flatten()
+            .filter_map(|name_version| regex.captures(name_version))
+            .map(|captured_name_version| {
+                (
+                    captured_name_version["name"].to_owned(),
+                    RepoVersion::new(
+                        &captured_name_version["name"],
+                        &captured_name_version["version"],
+                    ),
+                )
+            })
+            .collect()
+    }
+
+    /// Get current package versions.
+    ///
+    /// There is an entry for each managed repository, with an error if its
+    /// version could not be determined (for example, if it is not cloned).
+    ///
+    /// ```
+    /// use ts_observing_environment::ObservingEnvironment;
+    ///
+    /// let obs_env = ObservingEnvironment::with_destination("/does/not/exist");
+    /// for (repo_name, version) in obs_env.get_current_env_versions() {
+    ///     assert!(version.is_err(), "{repo_name} is not cloned");
+    /// }
+    /// ```
+    ///
+    /// The repositories are inspected concurrently, by up to
+    /// [`jobs`](ObservingEnvironmentBuilder::jobs) threads.
+    pub fn get_current_env_versions(&self) -> BTreeMap<String, Result<RepoVersion, ObsEnvError>> {
+        let repos: Vec<RepoHandle> = self.repos().collect();
+        let versions = parallel::map(&repos, self.jobs, RepoHandle::version);
+        repos
+            .iter()
+            .zip(versions)
+            .map(|(repo, version)| {
+                let version = version.unwrap_or_else(|message| {
+                    Err(ObsEnvError::Panicked {
+                        operation: format!("read the version of {}", repo.name()),
+                        message,
+                    })
+                });
+                (repo.name().to_owned(), version)
+            })
+            .collect()
+    }
+
+    /// Manifest with the current versions of the repositories that could
+    /// be determined, and the version overrides in use.
+    pub fn get_manifest(&self) -> EnvironmentManifest {
+        self.current_manifest(false)
+    }
+
+    /// [`get_manifest`](ObservingEnvironment::get_manifest), with the
+    /// files changed in the dirty repositories.
+    pub fn get_manifest_with_dirty_files(&self) -> EnvironmentManifest {
+        self.current_manifest(true)
+    }
+
+    fn current_manifest(&self, dirty_files: bool) -> EnvironmentManifest {
+        let mut manifest = EnvironmentManifest::new(
+            &self.destination,
+            self.get_current_env_versions()
+                .into_values()
+                .filter_map(|version| version.ok())
+                .map(|version| RepoVersion {
+                    ahead_behind: None,
+                    dirty_files: match dirty_files {
+                        true => version.dirty_files,
+                        false => Vec::new(),
+                    },
+                    ..version
+                })
+                .collect(),
+        );
+        manifest.overrides = self.version_overrides.clone();
+        manifest
+    }
+
+    /// Check out in every repository the commit recorded in `manifest`, or
+    /// its version if the commit is not known, and the
+    /// [version overrides](ObservingEnvironmentBuilder::version_override)
+    /// instead.
+    ///
+    /// Like [`ObservingEnvironment::reset_base_environment`], every
+    /// repository is attempted, unless one fails with
+    /// [`ErrorPolicy::FailFast`], and the errors are returned together,
+    /// after [`check_path`](Self::check_path) passed.
+    pub fn apply_manifest(&self, manifest: &EnvironmentManifest) -> Result<(), Vec<ObsEnvError>> {
+        self.preflight().map_err(|error| vec![error])?;
+        let mut targets: BTreeMap<&str, &str> = manifest
+            .repos
+            .iter()
+            .filter(|version| !self.excluded.contains(&version.name))
+            .map(|version| {
+                let target = version.sha.as_deref().unwrap_or(&version.describe);
+                (version.name.as_str(), target)
+            })
+            .collect();
+        for (repo, version) in self.version_overrides.iter() {
+            targets.insert(repo, version);
+        }
+        let mut errors: Vec<ObsEnvError> = Vec::new();
+        for (repo, target) in targets {
+            if !errors.is_empty() && self.on_error == ErrorPolicy::FailFast {
+                errors.push(ObsEnvError::not_attempted(repo, "check out"));
+            } else if let Err(error) = self.reset_repository(repo, target) {
+                errors.push(error);
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Versions of `manifest` the environment is not at: repositories
+    /// checked out at another commit, or another version if the manifest
+    /// has no commit for them, and repositories not cloned.
+    pub fn manifest_differences(&self, manifest: &EnvironmentManifest) -> Vec<RepoVersion> {
+        let current_versions = self.get_current_env_versions();
+        manifest
+            .repos
+            .iter()
+            .filter(|version| match current_versions.get(&version.name) {
+                Some(Ok(current)) => match &version.sha {
+                    Some(sha) => current.sha.as_ref() != Some(sha),
+                    None => !current.matches(version),
+                },
+                _ => true,
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Repositories whose version differs between the manifests `from`
+    /// and `to`, as [`EnvironmentManifest::compare`], neither of which has
+    /// to match the environment.
+    ///
+    /// With `commits`, the commits between the two versions are listed for
+    /// the repositories with a commit in both manifests, if they are cloned
+    /// and have both commits.
+    pub fn compare_manifests(
+        &self,
+        from: &EnvironmentManifest,
+        to: &EnvironmentManifest,
+        commits: bool,
+    ) -> Vec<RepoDifference> {
+        let mut differences = from.compare(to);
+        if !commits {
+            return differences;
+        }
+        for difference in differences.iter_mut() {
+            let (Some(from), Some(to)) = (
+                difference.from.as_ref().and_then(|from| from.sha.as_ref()),
+                difference.to.as_ref().and_then(|to| to.sha.as_ref()),
+            ) else {
+                continue;
+            };
+            let Ok(repo) = self.repo(&difference.repo) else {
+                continue;
+            };
+            if !repo.exists() {
+                continue;
+            }
+            match self.backend.commit_log(repo.path(), from, to) {
+                Ok(commits) => difference.commits = Some(commits),
+                Err(error) => log::debug!(
+                    "{}: cannot list the commits from {from} to {to}: {error}",
+                    difference.repo
+                ),
+            }
+        }
+        differences
+    }
+
+    /// Manifest read from `source`.
+    ///
+    /// The repository of a [`ManifestSource::Repository`] is cached as a
+    /// bare clone in `.obs_env/manifest_source.git`, fetched on each call
+    /// unless offline.
+    pub fn fetch_manifest(
+        &self,
+        source: &ManifestSource,
+    ) -> Result<EnvironmentManifest, ObsEnvError> {
+        let (url, branch, file) = match source {
+            ManifestSource::File(path) => return EnvironmentManifest::load(path),
+            ManifestSource::Repository { url, branch, file } => (url, branch, file),
+        };
+        let path = self.cache_dir().join(MANIFEST_SOURCE_CACHE);
+        let progress = self.transfer_progress(url);
+        if self.backend.open(&path).is_err() {
+            if self.offline {
+                return Err(ObsEnvError::Offline {
+                    operation: format!("fetch the manifest from {url}"),
+                });
+            }
+            if let Some(parent) = path.parent() {
+                create_dir_all(parent).map_err(|error| ObsEnvError::io(parent, "create", error))?;
+            }
+            self.backend
+                .clone(url, &path, true, CloneOptions::default(), &progress)
+                .map_err(|error| ObsEnvError::clone_failed(url, url, &path, error))?;
+        } else if self
+            .backend
+            .remote_url(&path)
+            .is_ok_and(|remote_url| remote_url.as_ref() != Some(url))
+        {
+            self.backend
+                .set_remote_url(&path, url)
+                .map_err(|error| ObsEnvError::git(url, &path, "set the remote url", error))?;
+        }
+        if !self.offline {
+            self.backend
+                .fetch(
+                    &path,
+                    &[&format!(
+                        "+refs/heads/{branch}:refs/remotes/origin/{branch}"
+                    )],
+                    false,
+                    &progress,
+                )
+                .map_err(|error| ObsEnvError::fetch_failed(url, &path, error))?;
+        }
+        let location = format!("{} on branch {branch} of {url}", file.display());
+        let content = self
+            .backend
+            .read_file(&path, &format!("refs/remotes/origin/{branch}"), file)
+            .map_err(|error| ObsEnvError::ManifestUnavailable {
+                location: location.clone(),
+                reason: error.message().to_owned(),
+            })?;
+        EnvironmentManifest::parse(&content, &location)
+    }
+
+    /// Write `setup_obs_env.sh` in the environment path, returning its
+    /// path.
+    ///
+    /// Sourcing the script prepends the `python/` directory of every cloned
+    /// repository that has one to PYTHONPATH, and the `bin/` directory to
+    /// PATH. The script is rewritten from scratch each time, so it follows
+    /// the repositories added to or removed from the environment.
+    pub fn write_setup_script(&self) -> Result<PathBuf, ObsEnvError> {
+        let path = Path::new(&self.destination).join(SETUP_SCRIPT);
+        write_atomically(&path, &self.setup_script())?;
+        Ok(path)
+    }
+
+    /// Declare `repo_names` as EUPS products at their current version,
+    /// returning the version declared for each repository.
+    ///
+    /// Does nothing if the environment was not built with
+    /// [`ObservingEnvironmentBuilder::eups`], nor, with a warning, if the
+    /// `eups` command is not installed. Repositories are declared
+    /// independently, so a failure only affects its own entry.
+    pub fn declare_eups<'a>(
+        &self,
+        repo_names: impl IntoIterator<Item = &'a str>,
+    ) -> BTreeMap<String, Result<String, ObsEnvError>> {
+        let Some(eups) = &self.eups else {
+            return BTreeMap::new();
+        };
+        // Every declaration would fail the same way.
+        if !eups.is_available() {
+            log::warn!(
+                "{} is not installed, not declaring the repositories in EUPS",
+                eups.get_command()
+            );
+            return BTreeMap::new();
+        }
+        repo_names
+            .into_iter()
+            .map(|repo_name| {
+                let result = self.repo(repo_name).and_then(|repo| {
+                    let version = repo.version()?.describe;
+                    eups.declare(repo_name, repo.open()?, &version)?;
+                    Ok(version)
+                });
+                (repo_name.to_owned(), result)
+            })
+            .collect()
+    }
+
+    /// Install `repo_names` in editable mode into the Python environment,
+    /// returning the result for each repository installed.
+    ///
+    /// Repositories without Python packaging metadata are skipped, and
+    /// nothing is done if the environment was not built with
+    /// [`ObservingEnvironmentBuilder::pip_install`].
+    pub fn develop_install<'a>(
+        &self,
+        repo_names: impl IntoIterator<Item = &'a str>,
+    ) -> BTreeMap<String, Result<(), ObsEnvError>> {
+        let Some(pip_install) = &self.pip_install else {
+            return BTreeMap::new();
+        };
+        repo_names
+            .into_iter()
+            .filter_map(|repo_name| {
+                let result = match self
+                    .repo(repo_name)
+                    .and_then(|repo| Ok(repo.open()?.to_owned()))
+                {
+                    Ok(path) if !PipInstall::is_installable(&path) => return None,
+                    Ok(path) => pip_install.install_editable(&path),
+                    Err(error) => Err(error),
+                };
+                Some((repo_name.to_owned(), result))
+            })
+            .collect()
+    }
+
+    /// Content of `setup_obs_env.sh`: a header with the versions of the
+    /// cloned repositories followed by the exports, sorted by repository.
+    fn setup_script(&self) -> String {
+        let mut header = "# Generated by manage_obs_env, do not edit.\n# Versions:\n".to_owned();
+        let mut exports = String::new();
+        for repo in self.repos().filter(|repo| repo.exists()) {
+            let version = repo
+                .version()
+                .map(|version| version.describe)
+                .unwrap_or_else(|_| "unknown".to_owned());
+            header.push_str(&format!("#   {}: {version}\n", repo.name()));
+            for (dir, variable) in [("python", "PYTHONPATH"), ("bin", "PATH")] {
+                let dir = repo.path().join(dir);
+                if dir.is_dir() {
+                    exports.push_str(&format!(
+                        "export {variable}=\"{}${{{variable}:+:${variable}}}\"\n",
+                        shell_escape(&dir.to_string_lossy())
+                    ));
+                }
+            }
+        }
+        header + &exports
+    }
+
+    /// Get current cycle/revision.
+    pub fn get_cycle_revision(&self, base_env_branch: &str) -> Result<String, ObsEnvError> {
+        self.update_base_env_source(base_env_branch)?;
+        unimplemented!()
+    }
+
+    /// Read the base environment definition from a local path.
+    ///
+    /// The path is either a plain versions file or a checked-out copy of
+    /// the base environment source repository, in which case
+    /// base_env_def_file is read from its working tree.
+    fn load_local_base_env_def(&self, local_source: &Path) -> Result<Vec<String>, ObsEnvError> {
+        let def_file = if local_source.is_dir() {
+            local_source.join(&self.base_env_def_file)
+        } else {
+            local_source.to_path_buf()
+        };
+
+        match read_to_string(&def_file) {
+            Ok(content) => Ok(content.lines().map(|line| line.to_owned()).collect()),
+            Err(error) => Err(ObsEnvError::BaseEnvUnavailable {
+                location: def_file.display().to_string(),
+                reason: error.to_string(),
+            }),
+        }
+    }
+
+    /// Read base_env_def_file at `commit` of the base environment source
+    /// repository, on `base_env_branch`, and return the content.
+    fn load_base_env_def_file(
+        &self,
+        base_env_source_path: &Path,
+        base_env_branch: &str,
+        commit: &str,
+    ) -> Result<Vec<String>, ObsEnvError> {
+        let content = self.backend.read_file(
+            base_env_source_path,
+            commit,
+            Path::new(&self.base_env_def_file),
+        );
+
+        match content {
+            Ok(content) => Ok(content.lines().map(|line| line.to_owned()).collect()),
+            Err(error) => Err(ObsEnvError::BaseEnvUnavailable {
+                location: self.base_env_location(base_env_branch),
+                reason: error.message().to_owned(),
+            }),
+        }
+    }
+
+    /// Reset repo index to the provided version.
+    ///
+    /// The version string must have the following format <X>.<Y>.<Z><RT><RN>,
+    /// where:
+    ///     X, is the major version number.
+    ///     Y, is the minor version number.
+    ///     Z, is the patch version number.
+    ///     RT, is the type of the release. This should be empty if this is an
+    ///         official release or:
+    ///         a, alpha release.
+    ///         b, beta release.
+    ///         rc, release candidate.
+    ///     RN, is the major version number. If RT is provided than a release
+    ///         type number can also be provided.
+    ///
+    /// Example valid release strings:
+    ///     0.1.0
+    ///     1.2.3
+    ///     1.0.0a, alpha release with no release number.
+    ///     1.0.0a1, alpha release with release number 1.
+    ///     1.0.0b5, beta release with release number 5.
+    ///     1.0.0rc3, release candidate with release number 3.
+    ///
+    /// If no such tag exists, the version is tried as a tag name, a branch
+    /// on origin and finally as any revision understood by rev-parse, e.g.
+    /// a full or abbreviated SHA or `origin/develop~2`. Annotated tags are
+    /// peeled to the commit they point to.
+    pub fn reset_index_to_version(&self, repo: &str, version: &str) -> Result<(), ObsEnvError> {
+        self.reset_to_version(repo, version).map(|_| ())
+    }
+
+    /// Reset `repo` to `version` as
+    /// [`reset_index_to_version`](Self::reset_index_to_version) does,
+    /// returning the branch checked out if the version is one.
+    fn reset_to_version(&self, repo: &str, version: &str) -> Result<Option<String>, ObsEnvError> {
+        log::debug!("Resetting {repo} to {version}");
+        let handle = self.repo(repo)?;
+        let path = handle.open()?;
+        self.check_not_busy(repo, path)?;
+
+        let tag = ObservingEnvironment::expand_version_to_tag(version);
+
+        let revision = self
+            .fetch_revision(repo, path, &tag, version)
+            .map_err(|error| handle.or_empty(error))?;
+        let branch = matches!(revision, Revision::Branch).then(|| version.to_owned());
+
+        self.checkout_revision(repo, path, version, revision)
+            .map_err(|error| {
+                ObsEnvError::git(repo, path, &format!("checkout {tag}[{version}]"), error)
+            })?;
+        Ok(branch)
+    }
+
+    /// Expands version string into a tag, following the format adopted by
+    /// TSSW.
+    fn expand_version_to_tag(version: &str) -> String {
+        let version_regex = Regex::new(VALID_VERSION).unwrap();
+
+        if version_regex.is_match(version) {
+            format!("v{version}")
+                .replace('a', ".alpha.")
+                .replace('b', ".beta.")
+                .replace("rc", ".rc.")
+        } else {
+            version.to_owned()
+        }
+    }
+
+    /// Work out what a version string refers to.
+    ///
+    /// Tags are tried first (the expanded TSSW tag, then the version as a
+    /// tag name), then a branch on origin, and finally any revision
+    /// expression understood by rev-parse.
+    fn resolve_revision(
+        &self,
+        repo_name: &str,
+        path: &Path,
+        tag: &str,
+        version: &str,
+    ) -> Result<Revision, Error> {
+        for spec in [format!("refs/tags/{tag}"), format!("refs/tags/{version}")] {
+            log::trace!("{repo_name}: checkout spec {spec}");
+            if self.backend.rev_parse(path, &spec).is_ok() {
+                return Ok(Revision::Tag(spec));
+            }
+        }
+
+        log::trace!("{repo_name}: failed to check tag, trying it as a branch: {version}");
+        if self
+            .backend
+            .rev_parse(path, &format!("refs/remotes/origin/{version}"))
+            .is_ok()
+        {
+            return Ok(Revision::Branch);
+        }
+
+        log::trace!("{repo_name}: failed to check branch, trying it as a revision: {version}");
+        Ok(Revision::Commit(self.backend.rev_parse(path, version)?))
+    }
+
+    /// Fetch what is needed to check out `version` and resolve it.
+    ///
+    /// Only the tag, then only the branch, then only the commit are
+    /// fetched first, the last one relying on the server allowing to fetch
+    /// any commit. If `version` still cannot be resolved, everything is
+    /// fetched from origin, and shallow repositories are then deepened
+    /// until it can be.
+    fn fetch_revision(
+        &self,
+        repo_name: &str,
+        path: &Path,
+        tag: &str,
+        version: &str,
+    ) -> Result<Revision, ObsEnvError> {
+        let mut tags = vec![tag_refspec(tag)];
+        if tag != version {
+            tags.push(tag_refspec(version));
+        }
+        let mut narrow_fetches = vec![tags, vec![branch_refspec(version)]];
+        if is_commit_id(version) {
+            narrow_fetches.push(vec![version.to_owned()]);
+        }
+        if !self.offline {
+            for refspecs in narrow_fetches {
+                let refspecs: Vec<&str> = refspecs.iter().map(String::as_str).collect();
+                match self.fetch_origin(repo_name, path, &refspecs, false) {
+                    Ok(()) => {
+                        if let Ok(revision) = self.resolve_revision(repo_name, path, tag, version) {
+                            return Ok(revision);
+                        }
+                    }
+                    Err(error) => {
+                        log::debug!("Fetching {refspecs:?} into {repo_name} failed: {error}")
+                    }
+                }
+            }
+        }
+        self.fetch_origin(repo_name, path, &[""], true)
+            .map_err(|error| ObsEnvError::fetch_failed(repo_name, path, error))?;
+        match self.resolve_revision(repo_name, path, tag, version) {
+            Err(error)
+                if error.code() == git2::ErrorCode::NotFound
+                    && !self.offline
+                    && self.backend.is_shallow(path).unwrap_or(false) =>
+            {
+                self.deepen_to_revision(repo_name, path, tag, version)
+            }
+            result => result.map_err(|error| revision_error(repo_name, path, version, error)),
+        }
+    }
+
+    /// Deepen the shallow repository at `path` to each of
+    /// [`DEEPEN_DEPTHS`], then fetch all its history, until `version`
+    /// resolves, failing with [`ObsEnvError::NotOnOrigin`] if it never does.
+    fn deepen_to_revision(
+        &self,
+        repo_name: &str,
+        path: &Path,
+        tag: &str,
+        version: &str,
+    ) -> Result<Revision, ObsEnvError> {
+        let mut not_found = None;
+        for depth in DEEPEN_DEPTHS.into_iter().map(Some).chain([None]) {
+            match depth {
+                Some(depth) => log::info!(
+                    "{version} is not in the history of {repo_name}, deepening it to {depth} commits"
+                ),
+                None => log::info!(
+                    "{version} is not in the history of {repo_name}, fetching all of it"
+                ),
+            }
+            self.timings
+                .time("fetch", Some(repo_name), || {
+                    self.backend
+                        .deepen(path, depth, &self.transfer_progress(repo_name))
+                })
+                .map_err(|error| ObsEnvError::fetch_failed(repo_name, path, error))?;
+            match self.resolve_revision(repo_name, path, tag, version) {
+                Ok(revision) => {
+                    match depth {
+                        Some(depth) => {
+                            log::info!("Found {version} in {repo_name}, now {depth} commits deep")
+                        }
+                        None => {
+                            log::info!("Found {version} in {repo_name}, now with all its history")
+                        }
+                    }
+                    return Ok(revision);
+                }
+                Err(error) if error.code() == git2::ErrorCode::NotFound => not_found = Some(error),
+                Err(error) => return Err(revision_error(repo_name, path, version, error)),
+            }
+        }
+        Err(ObsEnvError::NotOnOrigin {
+            repo: repo_name.to_owned(),
+            path: path.to_path_buf(),
+            revision: version.to_owned(),
+            // The last depth fetches everything, so resolving failed with it.
+            source: not_found.unwrap(),
+        })
+    }
+
+    fn checkout_revision(
+        &self,
+        repo_name: &str,
+        path: &Path,
+        version: &str,
+        revision: Revision,
+    ) -> Result<(), Error> {
+        if let Revision::Branch = revision {
+            self.fetch_origin(repo_name, path, &[&branch_refspec(version)], false)?;
+        }
+        self.timings
+            .time("checkout", Some(repo_name), || match revision {
+                Revision::Tag(spec) => self.backend.reset(path, &spec, Some(version)),
+                Revision::Branch => self.backend.checkout_branch(path, version),
+                Revision::Commit(commit) => self.backend.reset(path, &commit, None),
+            })
+    }
+}
+
+/// Error for `version` failing to resolve in `repo_name` with `error`.
+fn revision_error(repo_name: &str, path: &Path, version: &str, error: Error) -> ObsEnvError {
+    match error.code() {
+        git2::ErrorCode::Ambiguous => ObsEnvError::AmbiguousRevision {
+            repo: repo_name.to_owned(),
+            path: path.to_path_buf(),
+            revision: version.to_owned(),
+            source: error,
+        },
+        _ => ObsEnvError::RevisionNotFound {
+            repo: repo_name.to_owned(),
+            path: path.to_path_buf(),
+            revision: version.to_owned(),
+            source: error,
+        },
+    }
+}
+
+/// Transfer of `repo`, reported to `progress`, with the messages of git
+/// logged at trace level so `--log-level trace` shows what git does with
+/// `--verbose --progress`.
+struct RepoTransfer<'a, F> {
+    repo: &'a str,
+    progress: F,
+}
+
+impl<F: Fn(&TransferProgress)> TransferObserver for RepoTransfer<'_, F> {
+    fn on_progress(&self, progress: &TransferProgress) {
+        (self.progress)(progress)
+    }
+
+    fn on_message(&self, message: &str) {
+        log::trace!("{}: {message}", self.repo);
+    }
+}
+
+/// Builder for an [`ObservingEnvironment`], validating the combination of
+/// options in [`build`](ObservingEnvironmentBuilder::build).
+///
+/// ```
+/// use ts_observing_environment::ObservingEnvironment;
+///
+/// let obs_env = ObservingEnvironment::builder()
+///     .destination("/obs-env")
+///     .repositories([("ts_wep", "https://github.com/lsst-ts/")])
+///     .base_branch("develop")
+///     .clone_depth(1)
+///     .offline(true)
+///     .build()?;
+///
+/// assert_eq!(obs_env.get_base_env_branch(), "develop");
+/// # Ok::<(), ts_observing_environment::ObsEnvError>(())
+/// ```
+#[derive(Default)]
+pub struct ObservingEnvironmentBuilder {
+    destination: Option<String>,
+    repositories: Option<(Vec<RepoSpec>, RepoSource)>,
+    manifest_repos: Vec<(String, Option<String>)>,
+    forks: Vec<(String, String)>,
+    overrides: Vec<(String, RepoOverride)>,
+    version_overrides: Vec<(String, String)>,
+    only: Vec<String>,
+    groups: Vec<String>,
+    excluded: Vec<String>,
+    base_branch: Option<String>,
+    base_env_source: Option<String>,
+    clone_depth: Option<u32>,
+    clone_filter: Option<CloneFilter>,
+    offline: bool,
+    refresh_base_cache: bool,
+    abort_in_progress: bool,
+    object_store: Option<String>,
+    backend: Option<Box<dyn GitBackend>>,
+    observer: Option<Arc<dyn ObsEnvObserver>>,
+    eups: Option<Eups>,
+    pip_install: Option<PipInstall>,
+    jobs: Option<usize>,
+    retry: Option<RetryPolicy>,
+    base_versions_ttl: Option<Duration>,
+    existing_clones: ExistingClones,
+    on_error: ErrorPolicy,
+    deep_check: bool,
+    fresh: bool,
+    backup: Option<bool>,
+    min_free_space: u64,
+    identity: Option<Identity>,
+    trust_env_path: bool,
+    shared_access: SharedAccess,
+    base_env_revision: BaseEnvRevision,
+    ticket_patterns: Option<Vec<String>>,
+    cache_dir: Option<PathBuf>,
+    lock_timeout: Option<Duration>,
+    timings: Option<Arc<Timings>>,
+}
+
+impl ObservingEnvironmentBuilder {
+    /// Location where the repositories are placed.
+    pub fn destination(mut self, destination: &str) -> Self {
+        self.destination = Some(destination.to_owned());
+        self
+    }
+
+    /// Repositories of the environment, as pairs of repository name and
+    /// the url of the organization hosting it. Replaces the default set.
+    pub fn repositories<I, N, U>(mut self, repositories: I) -> Self
+    where
+        I: IntoIterator<Item = (N, U)>,
+        N: Into<String>,
+        U: Into<String>,
+    {
+        self.repositories = Some((
+            repositories
+                .into_iter()
+                .map(|(name, org)| repo_spec_in_org(&name.into(), &org.into()))
+                .collect(),
+            RepoSource::Custom,
+        ));
+        self
+    }
+
+    /// Repositories of the environment, e.g. loaded from a repositories
+    /// file, and where they come from. Replaces the default set.
+    pub fn repository_specs(mut self, repo_specs: Vec<RepoSpec>, source: RepoSource) -> Self {
+        self.repositories = Some((repo_specs, source));
+        self
+    }
+
+    /// Add the repositories of `manifest` the repositories of the
+    /// environment do not have, cloned from the url it records.
+    pub fn manifest_repositories(mut self, manifest: &EnvironmentManifest) -> Self {
+        self.manifest_repos.extend(
+            manifest
+                .repos
+                .iter()
+                .map(|version| (version.name.clone(), version.url.clone())),
+        );
+        self
+    }
+
+    /// Take a repository from the fork owned by `owner`.
+    pub fn fork(mut self, repo_name: &str, owner: &str) -> Self {
+        self.forks.push((repo_name.to_owned(), owner.to_owned()));
+        self
+    }
+
+    /// Change the url or default branch of a repository.
+    pub fn repo_override(mut self, repo_name: &str, repo_override: RepoOverride) -> Self {
+        self.overrides.push((repo_name.to_owned(), repo_override));
+        self
+    }
+
+    /// Check out `version` of `repo_name` when resetting the environment or
+    /// applying a manifest, instead of the base or manifest version.
+    pub fn version_override(mut self, repo_name: &str, version: &str) -> Self {
+        self.version_overrides
+            .push((repo_name.to_owned(), version.to_owned()));
+        self
+    }
+
+    /// Keep only the repository `repo_name`. Can be repeated, and combined
+    /// with [`group`](Self::group), keeping the repositories selected by
+    /// any of them.
+    pub fn only(mut self, repo_name: &str) -> Self {
+        self.only.push(repo_name.to_owned());
+        self
+    }
+
+    /// Keep only the repositories in `group`.
+    pub fn group(mut self, group: &str) -> Self {
+        self.groups.push(group.to_owned());
+        self
+    }
+
+    /// Leave out the repository `repo_name`, even if kept by
+    /// [`only`](Self::only) or [`group`](Self::group). Can be repeated.
+    pub fn exclude(mut self, repo_name: &str) -> Self {
+        self.excluded.push(repo_name.to_owned());
+        self
+    }
+
+    /// Branch of the base environment source repository with the versions.
+    pub fn base_branch(mut self, base_branch: &str) -> Self {
+        self.base_branch = Some(base_branch.to_owned());
+        self
+    }
+
+    /// Read the base environment versions from a local file or directory.
+    pub fn base_env_source(mut self, local_source: &str) -> Self {
+        self.base_env_source = Some(local_source.to_owned());
+        self
+    }
+
+    /// Clone repositories with only this many commits of history.
+    pub fn clone_depth(mut self, clone_depth: u32) -> Self {
+        self.clone_depth = Some(clone_depth);
+        self
+    }
+
+    /// Clone repositories without the objects `clone_filter` leaves out,
+    /// fetching them when needed.
+    pub fn clone_filter(mut self, clone_filter: CloneFilter) -> Self {
+        self.clone_filter = Some(clone_filter);
+        self
+    }
+
+    /// Inspect, clone or reset up to `jobs` repositories at the same time.
+    /// Defaults to the available parallelism of the host.
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = Some(jobs);
+        self
+    }
+
+    /// Retry the clones, fetches and other operations reaching a remote
+    /// that fail with a network error as `policy` sets, see
+    /// [`RetryBackend`]. They are not retried by default.
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    /// Cache the base environment versions, using the cache while it is
+    /// younger than `ttl`.
+    pub fn base_versions_ttl(mut self, ttl: Duration) -> Self {
+        self.base_versions_ttl = Some(ttl);
+        self
+    }
+
+    /// What [`ObservingEnvironment::clone_repositories`] does with the
+    /// repositories already cloned. They are skipped by default.
+    pub fn existing_clones(mut self, existing_clones: ExistingClones) -> Self {
+        self.existing_clones = existing_clones;
+        self
+    }
+
+    /// Whether the operations on every repository, e.g. cloning or
+    /// resetting them, go on with the others once one fails, which they do
+    /// by default.
+    pub fn on_error(mut self, on_error: ErrorPolicy) -> Self {
+        self.on_error = on_error;
+        self
+    }
+
+    /// Whether [`ObservingEnvironment::diagnose_repositories`] also reads
+    /// every object of the repositories, to find those corrupt or missing,
+    /// which is slow. It does not by default.
+    pub fn deep_check(mut self, deep_check: bool) -> Self {
+        self.deep_check = deep_check;
+        self
+    }
+
+    /// Make [`ObservingEnvironment::create_path`] fail if the environment
+    /// path already exists and is not empty.
+    pub fn fresh(mut self, fresh: bool) -> Self {
+        self.fresh = fresh;
+        self
+    }
+
+    /// Whether [`ObservingEnvironment::reset_base_environment`] keeps the
+    /// local commits it moves a repository away from on a backup branch,
+    /// which it does by default.
+    pub fn backup(mut self, backup: bool) -> Self {
+        self.backup = Some(backup);
+        self
+    }
+
+    /// Refuse to set up, reset or apply a manifest to the environment when
+    /// less than `bytes` are available on its volume. There is no minimum
+    /// by default.
+    pub fn min_free_space(mut self, bytes: u64) -> Self {
+        self.min_free_space = bytes;
+        self
+    }
+
+    /// Make commits as `name` <`email`> in the repositories with no git
+    /// identity configured, instead of "obs-env manager".
+    pub fn identity(mut self, name: &str, email: &str) -> Self {
+        self.identity = Some(Identity::new(name, email));
+        self
+    }
+
+    /// Open the repositories of the environment path and of the object
+    /// store even when they are owned by another user, as on a shared
+    /// volume, see [`git_backend::trust_directory`].
+    pub fn trust_env_path(mut self, trust_env_path: bool) -> Self {
+        self.trust_env_path = trust_env_path;
+        self
+    }
+
+    /// Share the environment with the group of its operators: the
+    /// directories and clones created by Setup are given to the group and
+    /// made writable by it, as set by `shared_access`, and
+    /// [`diagnose_repositories`](ObservingEnvironment::diagnose_repositories)
+    /// reports the repositories that are not.
+    pub fn shared_access(mut self, shared_access: SharedAccess) -> Self {
+        self.shared_access = shared_access;
+        self
+    }
+
+    /// Read the base environment versions at `revision` of the base
+    /// environment branch instead of its tip, e.g. to reproduce an old
+    /// night. Such versions are not cached, and need the base environment
+    /// repository rather than a local source.
+    pub fn base_env_revision(mut self, revision: BaseEnvRevision) -> Self {
+        self.base_env_revision = revision;
+        self
+    }
+
+    /// Regular expressions of the branch names checked out from
+    /// `tickets/<name>` when they do not exist as given, matching the whole
+    /// name, instead of [`DEFAULT_TICKET_PATTERNS`]. None are expanded if
+    /// empty.
+    pub fn ticket_patterns(mut self, patterns: Vec<String>) -> Self {
+        self.ticket_patterns = Some(patterns);
+        self
+    }
+
+    /// Wait up to `timeout` for another run to release the environment in
+    /// [`ObservingEnvironment::lock`], instead of 30 seconds.
+    pub fn lock_timeout(mut self, timeout: Duration) -> Self {
+        self.lock_timeout = Some(timeout);
+        self
+    }
+
+    /// Keep the base environment caches in `cache_dir` instead of
+    /// `.obs_env` in the environment path.
+    pub fn cache_dir(mut self, cache_dir: &str) -> Self {
+        self.cache_dir = Some(PathBuf::from(cache_dir));
+        self
+    }
+
+    /// Record the time spent in each phase into `timings`, e.g. to read
+    /// them after the environment is gone.
+    pub fn timings(mut self, timings: Arc<Timings>) -> Self {
+        self.timings = Some(timings);
+        self
+    }
+
+    /// Work without network access.
+    pub fn offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    /// Discard the base environment source cache and fetch it again.
+    pub fn refresh_base_cache(mut self, refresh_base_cache: bool) -> Self {
+        self.refresh_base_cache = refresh_base_cache;
+        self
+    }
+
+    /// Abort operations left in progress in a repository instead of
+    /// refusing to operate on it.
+    pub fn abort_in_progress(mut self, abort_in_progress: bool) -> Self {
+        self.abort_in_progress = abort_in_progress;
+        self
+    }
+
+    /// Create repositories as worktrees of bare clones in `object_store`.
+    pub fn object_store(mut self, object_store: &str) -> Self {
+        self.object_store = Some(object_store.to_owned());
+        self
+    }
+
+    /// Carry out the git operations through `backend` instead of libgit2.
+    pub fn backend(mut self, backend: impl GitBackend + 'static) -> Self {
+        self.backend = Some(Box::new(backend));
+        self
+    }
+
+    /// Report progress of long operations to `observer`.
+    pub fn observer(mut self, observer: impl ObsEnvObserver + 'static) -> Self {
+        self.observer = Some(Arc::new(observer));
+        self
+    }
+
+    /// Declare the repositories as EUPS products with `eups`, see
+    /// [`ObservingEnvironment::declare_eups`].
+    pub fn eups(mut self, eups: Eups) -> Self {
+        self.eups = Some(eups);
+        self
+    }
+
+    /// Install the repositories in editable mode with `pip_install`, see
+    /// [`ObservingEnvironment::develop_install`].
+    pub fn pip_install(mut self, pip_install: PipInstall) -> Self {
+        self.pip_install = Some(pip_install);
+        self
+    }
+
+    /// Validate the options and create the environment.
+    pub fn build(self) -> Result<ObservingEnvironment, ObsEnvError> {
+        let mut obs_env = ObservingEnvironment::default();
+
+        if let Some(destination) = self.destination {
+            if destination.is_empty() {
+                return Err(ObsEnvError::InvalidConfig {
+                    message: "The environment destination cannot be empty".to_owned(),
+                });
+            }
+            obs_env.destination = resolve_destination(&destination);
+        }
+
+        if let Some((repo_specs, source)) = self.repositories {
+            validate_repo_specs(&repo_specs)?;
+            obs_env.repositories = repo_specs
+                .into_iter()
+                .map(|repo_spec| (repo_spec.name.clone(), repo_spec))
+                .collect();
+            obs_env.repositories_source = source;
+        }
+
+        for (repo_name, url) in self.manifest_repos {
+            if obs_env.repositories.contains_key(&repo_name) {
+                continue;
+            }
+            let Some(url) = url else {
+                return Err(ObsEnvError::InvalidConfig {
+                    message: format!(
+                        "{repo_name} is not in the repository list and the manifest has no url for it"
+                    ),
+                });
+            };
+            obs_env
+                .repositories
+                .insert(repo_name.clone(), RepoSpec::new(&repo_name, &url));
+        }
+
+        for (repo_name, repo_override) in self.overrides.iter() {
+            obs_env.set_repo_override(repo_name, repo_override)?;
+        }
+
+        for (repo_name, owner) in self.forks.iter() {
+            obs_env.set_fork(repo_name, owner)?;
+        }
+
+        for (repo_name, version) in self.version_overrides {
+            obs_env.set_version_override(&repo_name, &version)?;
+        }
+
+        if let Some(base_branch) = self.base_branch {
+            if base_branch.is_empty() {
+                return Err(ObsEnvError::InvalidConfig {
+                    message: "The base environment branch cannot be empty".to_owned(),
+                });
+            }
+            obs_env.base_env_branch = base_branch;
+        }
+
+        if self.clone_depth == Some(0) {
+            return Err(ObsEnvError::InvalidConfig {
+                message: "The clone depth must be at least 1".to_owned(),
+            });
+        }
+        obs_env.clone_depth = self.clone_depth;
+        obs_env.clone_filter = self.clone_filter;
+
+        if let Some(jobs) = self.jobs {
+            if jobs == 0 {
+                return Err(ObsEnvError::InvalidConfig {
+                    message: "The number of jobs must be at least 1".to_owned(),
+                });
+            }
+            obs_env.jobs = jobs;
+        }
+
+        if self.offline && self.refresh_base_cache {
+            return Err(ObsEnvError::InvalidConfig {
+                message: "Cannot refresh the base environment cache while offline".to_owned(),
+            });
+        }
+        obs_env.offline = self.offline;
+        obs_env.refresh_base_cache = self.refresh_base_cache;
+        obs_env.abort_in_progress = self.abort_in_progress;
+        obs_env.base_versions_ttl = self.base_versions_ttl;
+        obs_env.existing_clones = self.existing_clones;
+        obs_env.on_error = self.on_error;
+        obs_env.deep_check = self.deep_check;
+        obs_env.fresh = self.fresh;
+        if let Some(backup) = self.backup {
+            obs_env.backup = backup;
+        }
+        obs_env.min_free_space = self.min_free_space;
+        if let Some(identity) = self.identity {
+            if identity.name.is_empty() || identity.email.is_empty() {
+                return Err(ObsEnvError::InvalidConfig {
+                    message: "The commit identity needs a name and an email".to_owned(),
+                });
+            }
+            obs_env.identity = identity;
+        }
+        obs_env.cache_dir = self.cache_dir;
+        obs_env.shared_access = self.shared_access;
+        if let Some(patterns) = self.ticket_patterns {
+            obs_env.ticket_patterns = patterns
+                .iter()
+                .map(|pattern| ticket_pattern(pattern))
+                .collect::<Result<_, _>>()?;
+        }
+        if let Some(lock_timeout) = self.lock_timeout {
+            obs_env.lock_timeout = lock_timeout;
+        }
+        if let Some(timings) = self.timings {
+            obs_env.timings = timings;
+        }
+        if self.base_env_source.is_some() && self.base_env_revision != BaseEnvRevision::Latest {
+            return Err(ObsEnvError::InvalidConfig {
+                message: format!(
+                    "Cannot read the base environment {} from a local source",
+                    self.base_env_revision
+                ),
+            });
+        }
+        obs_env.base_env_local_source = self.base_env_source;
+        obs_env.base_env_revision = self.base_env_revision;
+        obs_env.object_store = self.object_store;
+        if let Some(backend) = self.backend {
+            obs_env.backend = backend;
+        }
+        if let Some(policy) = self.retry.filter(|policy| policy.retries > 0) {
+            let backend = std::mem::replace(&mut obs_env.backend, Box::new(Git2Backend));
+            obs_env.backend = Box::new(RetryBackend::new(backend, policy));
+        }
+        if let Some(observer) = self.observer {
+            obs_env.observer = observer;
+        }
+        obs_env.eups = self.eups;
+        obs_env.pip_install = self.pip_install;
+
+        if let Some(repo_name) = self
+            .excluded
+            .iter()
+            .find(|repo_name| !obs_env.repositories.contains_key(*repo_name))
+        {
+            return Err(ObsEnvError::RepoNotFound {
+                repo: repo_name.to_owned(),
+            });
+        }
+        if !self.only.is_empty() || !self.groups.is_empty() {
+            obs_env.select(&self.only, &self.groups)?;
+        }
+        obs_env.exclude(&self.excluded);
+
+        if self.trust_env_path {
+            git_backend::trust_directory(Path::new(&obs_env.destination));
+            if let Some(object_store) = obs_env.object_store.as_ref() {
+                git_backend::trust_directory(Path::new(object_store));
+            }
+        }
+
+        Ok(obs_env)
+    }
+}
+
+/// Ticket pattern `pattern`, matching whole branch names.
+fn ticket_pattern(pattern: &str) -> Result<Regex, ObsEnvError> {
+    Regex::new(&format!("^(?:{pattern})$")).map_err(|error| ObsEnvError::InvalidConfig {
+        message: format!("Invalid ticket pattern {pattern}: {error}"),
+    })
+}
+
+/// Refspec fetching only `branch` from origin.
+fn branch_refspec(branch: &str) -> String {
+    format!("+refs/heads/{branch}:refs/remotes/origin/{branch}")
+}
+
+/// Up to three of `branches` with names close to `name`, the closest
+/// first: those containing it, or a few typos away from it.
+fn closest_names(name: &str, branches: &[String]) -> Vec<String> {
+    let limit = (name.chars().count() / 3).max(2);
+    let mut close: Vec<(usize, &String)> = branches
+        .iter()
+        .filter_map(|branch| {
+            let distance = edit_distance(name, branch);
+            (distance <= limit || branch.contains(name)).then_some((distance, branch))
+        })
+        .collect();
+    close.sort();
+    close
+        .into_iter()
+        .take(3)
+        .map(|(_, branch)| branch.clone())
+        .collect()
+}
+
+/// Number of characters to insert, delete or substitute to turn `a` into
+/// `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Refspec fetching only `tag` from origin.
+fn tag_refspec(tag: &str) -> String {
+    format!("+refs/tags/{tag}:refs/tags/{tag}")
+}
+
+/// Whether `revision` is a full commit id, which can be fetched by itself
+/// from servers allowing it.
+fn is_commit_id(revision: &str) -> bool {
+    revision.len() == 40 && revision.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Repository named `name` in the organization at `org`.
+fn repo_spec_in_org(name: &str, org: &str) -> RepoSpec {
+    RepoSpec::new(name, &format!("{}/{name}", org.trim_end_matches('/')))
+}
+
+/// A repository of an environment: its settings and where it is checked
+/// out.
+pub struct RepoHandle<'a> {
+    obs_env: &'a ObservingEnvironment,
+    spec: &'a RepoSpec,
+    path: PathBuf,
+}
+
+impl<'a> RepoHandle<'a> {
+    fn new(obs_env: &'a ObservingEnvironment, spec: &'a RepoSpec) -> RepoHandle<'a> {
+        RepoHandle {
+            obs_env,
+            spec,
+            path: Path::new(&obs_env.destination).join(spec.subdirectory()),
+        }
+    }
+
+    /// Name of the repository.
+    pub fn name(&self) -> &str {
+        &self.spec.name
+    }
+
+    /// Settings of the repository.
+    pub fn spec(&self) -> &RepoSpec {
+        self.spec
+    }
+
+    /// Url the repository is expected to be cloned from, taking forks into
+    /// account.
+    pub fn url(&self) -> String {
+        self.obs_env
+            .get_repository_url(self.name())
+            .unwrap_or_else(|| self.spec.url.clone())
+    }
+
+    /// Path of the repository in the environment.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the repository is cloned in the environment.
+    pub fn exists(&self) -> bool {
+        self.obs_env.backend.open(&self.path).is_ok()
+    }
+
+    /// Whether the repository is cloned but has no commits yet, as right
+    /// after cloning a repository just created on GitHub.
+    pub fn is_empty(&self) -> bool {
+        self.obs_env.backend.is_empty(&self.path).unwrap_or(false)
+    }
+
+    /// [`ObsEnvError::EmptyRepository`] instead of `error` if the
+    /// repository is empty, which is why nothing could be found in it.
+    pub(crate) fn or_empty(&self, error: ObsEnvError) -> ObsEnvError {
+        if self.is_empty() {
+            ObsEnvError::EmptyRepository {
+                repo: self.name().to_owned(),
+            }
+        } else {
+            error
+        }
+    }
+
+    /// Make sure the repository is cloned in the environment and return its
+    /// path.
+    ///
+    /// For repositories taken from a fork, origin is pointed at the fork so
+    /// subsequent fetches use it, except in worktrees of an object store,
+    /// whose origin is shared with the other environments: those fail and
+    /// must be removed and set up again to be taken from the fork.
+    pub fn open(&self) -> Result<&Path, ObsEnvError> {
+        let repo_name = self.name();
+        let path = self.path();
+        let backend = &self.obs_env.backend;
+        backend
+            .open(path)
+            .map_err(|error| ObsEnvError::RepoNotCloned {
+                repo: repo_name.to_owned(),
+                path: path.to_path_buf(),
+                source: error,
+            })?;
+
+        if self.obs_env.forks.contains_key(repo_name) {
+            let url = self.url();
+            let current_url = backend
+                .remote_url(path)
+                .map_err(|error| ObsEnvError::git(repo_name, path, "find origin", error))?;
+            if current_url.as_deref() != Some(url.as_str()) {
+                if Repository::open(path).is_ok_and(|repository| repository.is_worktree()) {
+                    return Err(ObsEnvError::git(
+                        repo_name,
+                        path,
+                        "point origin to fork",
+                        Error::new(
+                            ErrorCode::Locked,
+                            ErrorClass::Worktree,
+                            "origin is shared with the other worktrees of the object store; \
+                             remove the repository and run Setup again to take it from the fork",
+                        ),
+                    ));
+                }
+                log::info!("Pointing origin of {repo_name} to fork {url}");
+                backend.set_remote_url(path, &url).map_err(|error| {
+                    ObsEnvError::git(repo_name, path, "point origin to fork", error)
+                })?;
+            }
+        }
+        Ok(path)
+    }
+
+    /// Whether tracked files in the repository were changed since HEAD.
+    pub fn is_dirty(&self) -> Result<bool, ObsEnvError> {
+        let path = self.open()?;
+        self.obs_env
+            .backend
+            .status(path)
+            .map(|status| status.dirty)
+            .map_err(|error| ObsEnvError::git(self.name(), path, "read status", error))
+    }
+
+    /// Number of commits of origin missing from the branch checked out, as
+    /// of the last fetch, or none if HEAD is detached or the branch is not
+    /// on origin.
+    pub fn behind_origin(&self) -> Result<Option<usize>, ObsEnvError> {
+        Ok(self.ahead_behind()?.map(|ahead_behind| ahead_behind.behind))
+    }
+
+    /// Commits of the branch checked out missing from the same branch on
+    /// origin, and the other way round, as of the last fetch, or none if
+    /// HEAD is detached or the branch is not on origin.
+    pub fn ahead_behind(&self) -> Result<Option<AheadBehind>, ObsEnvError> {
+        let path = self.open()?;
+        let backend = &self.obs_env.backend;
+        let Some(branch) = backend
+            .current_branch(path)
+            .map_err(|error| ObsEnvError::git(self.name(), path, "read HEAD", error))?
+        else {
+            return Ok(None);
+        };
+        let upstream = format!("refs/remotes/origin/{branch}");
+        if backend.rev_parse(path, &upstream).is_err() {
+            return Ok(None);
+        }
+        backend
+            .ahead_behind(path, &format!("refs/heads/{branch}"), &upstream)
+            .map(|(ahead, behind)| Some(AheadBehind { ahead, behind }))
+            .map_err(|error| ObsEnvError::git(self.name(), path, "compare with origin", error))
+    }
+
+    /// Version currently checked out.
+    pub fn version(&self) -> Result<RepoVersion, ObsEnvError> {
+        let repo_name = self.name();
+        self.obs_env
+            .timings
+            .time("version", Some(repo_name), || self.read_version())
+    }
+
+    fn read_version(&self) -> Result<RepoVersion, ObsEnvError> {
+        let repo_name = self.name();
+        let path = self.open()?;
+        let backend = &self.obs_env.backend;
+        if self.is_empty() {
+            return Err(ObsEnvError::EmptyRepository {
+                repo: repo_name.to_owned(),
+            });
+        }
+
+        let describe = backend
+            .describe(path)
+            .map_err(|error| ObsEnvError::git(repo_name, path, "describe HEAD", error))?;
+        let sha = backend
+            .rev_parse(path, "HEAD")
+            .map_err(|error| ObsEnvError::git(repo_name, path, "resolve HEAD", error))?;
+        let branch = backend
+            .current_branch(path)
+            .map_err(|error| ObsEnvError::git(repo_name, path, "read HEAD", error))?;
+        let status = backend
+            .status(path)
+            .map_err(|error| ObsEnvError::git(repo_name, path, "read status", error))?;
+
+        Ok(RepoVersion {
+            name: repo_name.to_owned(),
+            url: Some(self.url()),
+            branch,
+            sha: Some(sha),
+            describe,
+            dirty: status.dirty,
+            dirty_files: status.modified_files,
+            untracked: status.untracked,
+            ahead_behind: self.ahead_behind()?,
+        })
+    }
+}
+
+/// Target of `destination` if it is a symbolic link, so the environment
+/// operates on the directory it points to and never on the link itself.
+/// Links to nothing are kept, for [`ObservingEnvironment::create_path`] to
+/// report.
+fn resolve_destination(destination: &str) -> String {
+    let path = Path::new(destination);
+    if !path.is_symlink() {
+        return destination.to_owned();
+    }
+    match path.canonicalize() {
+        Ok(target) => {
+            log::debug!(
+                "Environment path {destination} links to {}",
+                target.display()
+            );
+            target.to_string_lossy().into_owned()
+        }
+        Err(_) => destination.to_owned(),
+    }
+}
+
+/// Result of serializing a value to JSON or TOML, unwrapped: values made
+/// of strings, numbers, booleans, and lists and string keyed maps of them,
+/// always serialize.
+pub(crate) fn serialized<T, E: fmt::Debug>(serialized: Result<T, E>) -> T {
+    serialized.expect("serializing plain data cannot fail")
+}
+
+/// Write `content` to a temporary file next to `path` and move it over
+/// `path`, so readers never see a partially written file.
+pub(crate) fn write_atomically(path: &Path, content: &str) -> Result<(), ObsEnvError> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    write(&temporary, content)
+        .and_then(|_| rename(&temporary, path))
+        .map_err(|error| ObsEnvError::io(path, "write", error))
+}
+
+/// Seconds since the Unix epoch.
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// Escape `value` to be used inside double quotes in a shell script.
+fn shell_escape(value: &str) -> String {
+    value
+        .chars()
+        .flat_map(|c| match c {
+            '"' | '\\' | '$' | '`' => vec!['\\', c],
+            c => vec![c],
+        })
+        .collect()
+}
+
+/// What [`ObservingEnvironment::clone_repositories`] does with a
+/// repository already cloned.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ExistingClones {
+    /// Leave it as it is.
+    #[default]
+    Skip,
+    /// Fetch it and fast-forward the branch checked out.
+    Update,
+    /// Remove it and clone it again. Directories in the way that are not
+    /// git repositories are removed too.
+    Reclone,
+}
+
+/// What the operations on every repository of the environment do once one
+/// of them fails.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum ErrorPolicy {
+    /// Go on with the other repositories, reporting every failure at once.
+    #[default]
+    KeepGoing,
+    /// Start no other repository, reporting them as
+    /// [`NotAttempted`](ObsEnvError::NotAttempted). Those being worked on in
+    /// parallel are completed.
+    FailFast,
+}
+
+/// Base environment versions, from
+/// [`ObservingEnvironment::get_base_env_versions_cached`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct BaseEnvVersions {
+    pub versions: BTreeMap<String, RepoVersion>,
+    /// Age of the cache the versions were read from, or none if they were
+    /// read from the base environment source.
+    pub cache_age: Option<Duration>,
+    /// Commit of the base environment source repository the versions were
+    /// read at, or none if read from a local source.
+    pub commit: Option<String>,
+}
+
+/// Point of the history of the base environment branch its versions are
+/// read at, set with
+/// [`ObservingEnvironmentBuilder::base_env_revision`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum BaseEnvRevision {
+    /// The tip of the branch.
+    #[default]
+    Latest,
+    /// The last commit of the branch before the time, in seconds since the
+    /// Unix epoch.
+    AsOf(u64),
+    /// A tag or commit of the base environment repository.
+    At(String),
+}
+
+impl Display for BaseEnvRevision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BaseEnvRevision::Latest => write!(f, "latest"),
+            BaseEnvRevision::AsOf(time) => write!(f, "as of {} UTC", audit::format_utc(*time)),
+            BaseEnvRevision::At(revision) => write!(f, "at {revision}"),
+        }
+    }
+}
+
+/// Content of a base environment versions cache file.
+#[derive(Deserialize, Serialize)]
+struct VersionsCache {
+    /// When the cache was written, in seconds since the Unix epoch.
+    created: u64,
+    /// Lines of the base environment definition.
+    base_env_def: Vec<String>,
+    /// Commit of the base environment source they were read at, if
+    /// recorded.
+    #[serde(default)]
+    commit: Option<String>,
+}
+
+/// Result of [`ObservingEnvironment::reset_base_environment`].
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ResetReport {
+    /// Branch checked out in the repositories whose base version is a
+    /// branch, by repository name.
+    pub branches: BTreeMap<String, String>,
+    /// Backups of the local commits the reset moved away from.
+    pub backups: Vec<Backup>,
+    /// Repositories reset to a version override instead of their base
+    /// version, by repository name.
+    pub overridden: BTreeMap<String, VersionOverride>,
+    /// Commit of the base environment source repository the base versions
+    /// were read at, or none if read from a local source.
+    pub base_commit: Option<String>,
+}
+
+impl ResetReport {
+    /// Record the branch `repo` was reset to, if its version is one, and
+    /// the backup of its local commits, if it had any.
+    pub(crate) fn add(&mut self, repo: &str, (branch, backup): (Option<String>, Option<Backup>)) {
+        if let Some(branch) = branch {
+            self.branches.insert(repo.to_owned(), branch);
+        }
+        self.backups.extend(backup);
+    }
+}
+
+/// Version a repository was reset to instead of its base version.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct VersionOverride {
+    pub version: String,
+    /// Base version, or none if the base environment has none for the
+    /// repository.
+    pub base: Option<String>,
+}
+
+/// Repository of an environment, as shown by
+/// [`ObservingEnvironment::summarize`].
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct RepoSummary {
+    pub name: String,
+    pub url: String,
+    /// Whether the repository exists on disk.
+    pub present: bool,
+    /// Version checked out, if it could be determined.
+    pub version: Option<String>,
+}
+
+/// Settings of an environment, as shown by [`ObservingEnvironment::summarize`].
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct EnvironmentSummary {
+    /// Location of the environment.
+    pub env_path: String,
+    /// Branch of the base environment the versions are taken from.
+    pub base_branch: String,
+    /// Where the list of repositories comes from.
+    pub repositories_source: String,
+    /// Repositories of the environment, sorted by name.
+    pub repos: Vec<RepoSummary>,
+    /// Repositories taken from a fork, mapping repository name to the
+    /// owner of the fork.
+    pub forks: BTreeMap<String, String>,
+    /// Location of the shared bare repositories, if any.
+    pub object_store: Option<String>,
+}
+
+impl Display for EnvironmentSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Obs. Env. Path: {}.\nNumber of repositories: {} (from {})",
+            self.env_path,
+            self.repos.len(),
+            self.repositories_source
+        )?;
+        for (repo_name, owner) in self.forks.iter() {
+            write!(f, "\n{repo_name} taken from fork: {owner}")?;
+        }
+        if let Some(object_store) = &self.object_store {
+            write!(f, "\nObject store: {object_store}")?;
+        }
+        Ok(())
+    }
+}
+
+/// What a version string given to `reset_index_to_version` resolved to.
+enum Revision {
+    /// A tag, with the full reference name.
+    Tag(String),
+    /// A branch on origin.
+    Branch,
+    /// Any other revision, with the id of the commit it resolves to.
+    Commit(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use regex::Regex;
+
+    use super::{
+        in_progress_state, repo_spec_in_org, BaseEnvRevision, CommitQuery, ErrorPolicy,
+        ExistingClones, ObservingEnvironment, RepoSummary, VersionOverride, REPO_VERSION_REGEXP,
+        VALID_VERSION,
+    };
+    use crate::{
+        branches::{StaleBranch, StaleReason},
+        clone_env::{ClonedRepo, LocalChanges},
+        error::{report, ObsEnvError},
+        eups::Eups,
+        git_backend::{self, CloneFilter, CloneOptions, Identity, TransferProgress},
+        lockfile::Drift,
+        manifest::{AheadBehind, EnvironmentManifest, RepoVersion},
+        metrics::RepoMetrics,
+        observer::ObsEnvObserver,
+        outdated::RepoStaleness,
+        permissions::{Group, SharedAccess, SHARED_REPOSITORY},
+        pip::PipInstall,
+        repair::{Repair, RepoBlocker, RepoDamage, RepoDiagnosis},
+        repos::{RepoOverride, RepoSource, RepoSpec},
+        retry::RetryPolicy,
+        setup::{RepoPresence, RepoSetupOutcome},
+        testing::FakeBackend,
+    };
+    use git2::{BranchType, Oid, Repository, Signature};
+    use std::collections::{BTreeMap, HashMap};
+    use tempfile::TempDir;
+
+    use once_cell::sync::Lazy;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    static REPO_ACCESS: Lazy<Mutex<()>> = Lazy::new(Mutex::default);
+
+    /// Organization of the remotes in the fake backend.
+    const FAKE_ORG: &str = "https://example.com/lsst-ts";
+
+    type TestResult<T = (), E = Box<dyn std::error::Error>> = std::result::Result<T, E>;
+
+    #[test]
+    fn test_repo_version_regexp() {
+        let regexp = Regex::new(REPO_VERSION_REGEXP).unwrap();
+
+        let repo_version = regexp.captures("ts_unit_test=X.Y.ZaN").unwrap();
+
+        assert_eq!(&repo_version["name"], "ts_unit_test");
+        assert_eq!(&repo_version["version"], "X.Y.ZaN");
+    }
+
+    #[test]
+    fn expand_version_to_tag() {
+        assert_eq!(
+            ObservingEnvironment::expand_version_to_tag("1.0.0"),
+            "v1.0.0"
+        );
+        assert_eq!(
+            ObservingEnvironment::expand_version_to_tag("1.0.0a1"),
+            "v1.0.0.alpha.1"
+        );
+        assert_eq!(
+            ObservingEnvironment::expand_version_to_tag("1.0.0b1"),
+            "v1.0.0.beta.1"
+        );
+        assert_eq!(
+            ObservingEnvironment::expand_version_to_tag("1.0.0rc1"),
+            "v1.0.0.rc.1"
+        );
+    }
+
+    #[test]
+    fn test_update_base_env_source() {
+        let _shared = REPO_ACCESS.lock().unwrap();
+
+        let obs_env = ObservingEnvironment::with_destination(".");
+
+        obs_env.update_base_env_source("main").unwrap();
+
+        assert!(obs_env.base_env_cache_path().exists())
+    }
+
+    #[test]
+    fn test_get_base_env_versions() {
+        let _shared = REPO_ACCESS.lock().unwrap();
+        let obs_env = ObservingEnvironment::with_destination(".");
+
+        let base_env_versions = obs_env.get_base_env_versions("main").unwrap();
+
+        for (repo, _) in obs_env.repositories {
+            assert!(base_env_versions.contains_key(&repo))
+        }
+    }
+
+    /// Create a repository with a single commit on main, to be used as the
+    /// remote of the environment repositories.
+    fn fixture_remote(path: &Path) -> Repository {
+        let repository = Repository::init(path).unwrap();
+        {
+            let signature = Signature::now("Test", "test@example.com").unwrap();
+            let tree_id = repository.index().unwrap().write_tree().unwrap();
+            let tree = repository.find_tree(tree_id).unwrap();
+            repository
+                .commit(
+                    Some("refs/heads/main"),
+                    &signature,
+                    &signature,
+                    "Initial commit",
+                    &tree,
+                    &[],
+                )
+                .unwrap();
+            repository.set_head("refs/heads/main").unwrap();
+        }
+        repository
+    }
+
+    /// Add an empty commit on top of main.
+    fn fixture_commit(repository: &Repository, message: &str) -> Oid {
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+        let parent = repository.head().unwrap().peel_to_commit().unwrap();
+        let tree = parent.tree().unwrap();
+        repository
+            .commit(
+                Some("refs/heads/main"),
+                &signature,
+                &signature,
+                message,
+                &tree,
+                &[&parent],
+            )
+            .unwrap()
+    }
+
+    /// Commit `content` to `file_name` on top of main.
+    fn fixture_commit_file(
+        repository: &Repository,
+        file_name: &str,
+        content: &str,
+        message: &str,
+    ) -> Oid {
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+        fixture_commit_file_as(repository, file_name, content, message, &signature)
+    }
+
+    /// Commit `content` to `file_name` on top of main, by `signature`.
+    fn fixture_commit_file_as(
+        repository: &Repository,
+        file_name: &str,
+        content: &str,
+        message: &str,
+        signature: &Signature,
+    ) -> Oid {
+        let workdir = repository.workdir().unwrap();
+        let file_path = workdir.join(file_name);
+        std::fs::create_dir_all(file_path.parent().unwrap()).unwrap();
+        std::fs::write(&file_path, content).unwrap();
+
+        let mut index = repository.index().unwrap();
+        index.add_path(Path::new(file_name)).unwrap();
+        index.write().unwrap();
+        let tree = repository.find_tree(index.write_tree().unwrap()).unwrap();
+
+        let parent = repository.head().unwrap().peel_to_commit().unwrap();
+        repository
+            .commit(
+                Some("refs/heads/main"),
+                signature,
+                signature,
+                message,
+                &tree,
+                &[&parent],
+            )
+            .unwrap()
+    }
+
+    /// Environment at `destination` managing `repo_names`, cloned from
+    /// fixture remotes under `remotes`, created if they don't exist yet.
+    fn fixture_environment(
+        destination: &Path,
+        remotes: &Path,
+        repo_names: &[&str],
+    ) -> ObservingEnvironment {
+        for repo_name in repo_names {
+            if !remotes.join(repo_name).exists() {
+                fixture_remote(&remotes.join(repo_name));
+            }
+        }
+        ObservingEnvironment {
+            repositories: repo_names
+                .iter()
+                .map(|repo_name| {
+                    (
+                        repo_name.to_string(),
+                        repo_spec_in_org(repo_name, &remotes.to_string_lossy()),
+                    )
+                })
+                .collect(),
+            ..ObservingEnvironment::with_destination(&destination.to_string_lossy())
+        }
+    }
+
+    #[test]
+    fn test_partial_clones() -> TestResult {
+        let root = TempDir::new()?;
+        let remotes = root.path().join("remotes");
+        let remote = fixture_remote(&remotes.join("ts_wep"));
+        remote.config()?.set_bool("uploadpack.allowFilter", true)?;
+        fixture_commit_file(&remote, "python/lsst/ts/wep/version.py", "1", "Version 1");
+        // git ignores the filter of clones from a path.
+        let org = format!("file://{}", remotes.display());
+
+        for (env_name, filter, object_store) in [
+            ("blobless", CloneFilter::Blobless, None),
+            (
+                "treeless",
+                CloneFilter::Treeless,
+                Some(root.path().join("store")),
+            ),
+        ] {
+            let destination = root.path().join(env_name);
+            let mut obs_env = ObservingEnvironment {
+                repositories: BTreeMap::from([(
+                    "ts_wep".to_owned(),
+                    RepoSpec {
+                        default_branch: Some("main".to_owned()),
+                        ..repo_spec_in_org("ts_wep", &org)
+                    },
+                )]),
+                clone_filter: Some(filter),
+                ..ObservingEnvironment::with_destination(&destination.to_string_lossy())
+            };
+            if let Some(object_store) = &object_store {
+                obs_env.set_object_store(&object_store.to_string_lossy());
+            }
+            obs_env.create_path()?;
+            obs_env.clone_repositories().into_result()?;
+
+            let path = destination.join("ts_wep");
+            let version = path.join("python/lsst/ts/wep/version.py");
+            let repository = Repository::open(&path)?;
+            assert!(repository.config()?.get_bool("remote.origin.promisor")?);
+            assert_eq!(
+                std::fs::read_to_string(&version)?,
+                std::fs::read_to_string(remotes.join("ts_wep/python/lsst/ts/wep/version.py"))?
+            );
+            let problems =
+                git_backend::GitBackend::check_objects(&git_backend::Git2Backend, &path)?;
+            assert_eq!(problems, Vec::<String>::new());
+
+            // The files of the new commit are fetched when it is checked out.
+            let pushed = fixture_commit_file(
+                &remote,
+                "python/lsst/ts/wep/version.py",
+                env_name,
+                "Version 2",
+            );
+            obs_env.checkout_branch("ts_wep", "main")?;
+            assert_eq!(repository.head()?.peel_to_commit()?.id(), pushed);
+            assert_eq!(std::fs::read_to_string(&version)?, env_name);
+            assert!(repository.statuses(None)?.is_empty());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_worktree_environments_share_object_store() -> TestResult {
+        let root = TempDir::new()?;
+        let remotes = root.path().join("remotes");
+        let object_store = root.path().join("store");
+
+        let mut environments = Vec::new();
+        for env_name in ["nightly", "release"] {
+            let destination = root.path().join(env_name);
+            let mut obs_env = fixture_environment(&destination, &remotes, &["ts_wep"]);
+            obs_env
+                .repositories
+                .get_mut("ts_wep")
+                .unwrap()
+                .default_branch = Some("main".to_owned());
+            obs_env.set_object_store(&object_store.to_string_lossy());
+            obs_env.create_path()?;
+            for (_, path) in obs_env.clone_repositories().into_result()?.cloned() {
+                assert!(Repository::open(path)?.is_worktree());
+            }
+            environments.push(obs_env);
+        }
+
+        assert!(object_store.join("ts_wep.git").exists());
+        assert_eq!(
+            Repository::open_bare(object_store.join("ts_wep.git"))?
+                .worktrees()?
+                .len(),
+            2
+        );
+
+        // Branches are shared by the worktrees, so the default branch
+        // checked out in the first environment is checked out detached in
+        // the second, and moving it there leaves the first one alone.
+        let nightly = Repository::open(root.path().join("nightly").join("ts_wep"))?;
+        let release = Repository::open(root.path().join("release").join("ts_wep"))?;
+        let nightly_head = nightly.head()?.peel_to_commit()?.id();
+        assert_eq!(nightly.head()?.name(), Some("refs/heads/main"));
+        assert!(release.head_detached()?);
+        assert_eq!(release.head()?.peel_to_commit()?.id(), nightly_head);
+
+        let pushed = fixture_commit(&Repository::open(remotes.join("ts_wep"))?, "Pushed");
+        environments[1].checkout_branch("ts_wep", "main")?;
+        assert!(release.head_detached()?);
+        assert_eq!(release.head()?.peel_to_commit()?.id(), pushed);
+        environments[1].reset_index_to_version("ts_wep", "main")?;
+        assert_eq!(release.head()?.peel_to_commit()?.id(), pushed);
+        assert_eq!(nightly.head()?.name(), Some("refs/heads/main"));
+        assert_eq!(nightly.head()?.peel_to_commit()?.id(), nightly_head);
+
+        for (_, result) in environments[0].teardown(false) {
+            result?;
+        }
+
+        assert!(!root.path().join("nightly").join("ts_wep").exists());
+        let bare_repository = Repository::open_bare(object_store.join("ts_wep.git"))?;
+        assert_eq!(bare_repository.worktrees()?.len(), 1);
+        assert!(release.is_worktree());
+        assert!(release.head()?.peel_to_commit().is_ok());
+        environments[1].checkout_branch("ts_wep", "main")?;
+        assert_eq!(release.head()?.name(), Some("refs/heads/main"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_worktree_fork_keeps_shared_origin() -> TestResult {
+        let root = TempDir::new()?;
+        let remotes = root.path().join("remotes");
+        let object_store = root.path().join("store");
+
+        let mut environments = Vec::new();
+        for env_name in ["nightly", "release"] {
+            let destination = root.path().join(env_name);
+            let mut obs_env = fixture_environment(&destination, &remotes, &["ts_wep"]);
+            obs_env.set_object_store(&object_store.to_string_lossy());
+            obs_env.create_path()?;
+            obs_env.clone_repositories().into_result()?;
+            environments.push(obs_env);
+        }
+
+        environments[1].set_fork("ts_wep", "tribeiro")?;
+        match environments[1].repo("ts_wep")?.open() {
+            Err(ObsEnvError::Git { source, .. }) => {
+                assert_eq!(source.code(), git2::ErrorCode::Locked)
+            }
+            other => panic!("expected the shared origin to be kept, got {other:?}"),
+        }
+        let bare_repository = Repository::open_bare(object_store.join("ts_wep.git"))?;
+        assert_eq!(
+            bare_repository.find_remote("origin")?.url(),
+            Some(remotes.join("ts_wep").to_string_lossy().as_ref())
+        );
+        assert!(environments[0].repo("ts_wep")?.open().is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_worktree_fork_has_own_store() -> TestResult {
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        let fork_url = "https://example.com/tribeiro/ts_wep";
+        backend.set_branch(&format!("{FAKE_ORG}/ts_wep"), "main", "1111aaaa");
+        backend.set_branch(fork_url, "main", "2222bbbb");
+        let object_store = root.path().join("store");
+
+        let mut obs_env = fake_environment(&root.path().join("env"), &backend, &["ts_wep"]);
+        obs_env.set_object_store(&object_store.to_string_lossy());
+        obs_env.set_fork("ts_wep", "tribeiro")?;
+        // The fake store can't have worktrees, but its clone shows where
+        // the fork went.
+        assert!(obs_env.clone_repositories().into_result().is_err());
+        let remote_url =
+            |name: &str| git_backend::GitBackend::remote_url(&backend, &object_store.join(name));
+        assert_eq!(
+            remote_url("ts_wep@tribeiro.git")?,
+            Some(fork_url.to_owned())
+        );
+        assert!(remote_url("ts_wep.git").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_reset_index_to_version_revisions() -> TestResult {
+        let root = TempDir::new()?;
+        let remotes = root.path().join("remotes");
+
+        let remote = fixture_remote(&remotes.join("ts_wep"));
+        let first = remote.head()?.peel_to_commit()?.id();
+        let second = fixture_commit(&remote, "Second commit");
+        let third = fixture_commit(&remote, "Third commit");
+        let signature = Signature::now("Test", "test@example.com")?;
+        remote.tag(
+            "v1.2.0",
+            &remote.find_object(first, None)?,
+            &signature,
+            "Release 1.2.0",
+            false,
+        )?;
+        remote.tag_lightweight("special", &remote.find_object(second, None)?, false)?;
+        remote.branch("develop", &remote.find_commit(third)?, false)?;
+
+        let obs_env = fixture_environment(&root.path().join("env"), &remotes, &["ts_wep"]);
+        obs_env.create_path()?;
+        obs_env.clone_repositories().into_result()?;
+        let repository = Repository::open(root.path().join("env").join("ts_wep"))?;
+
+        let second_short = second.to_string()[..8].to_owned();
+        let third_full = third.to_string();
+        for (version, expected) in [
+            ("1.2.0", first),
+            ("special", second),
+            (third_full.as_str(), third),
+            (second_short.as_str(), second),
+            ("origin/develop~2", first),
+        ] {
+            obs_env.reset_index_to_version("ts_wep", version)?;
+            assert_eq!(
+                repository.head()?.peel_to_commit()?.id(),
+                expected,
+                "{version}"
+            );
+        }
+
+        assert!(matches!(
+            obs_env.reset_index_to_version("ts_wep", "does_not_exist"),
+            Err(ObsEnvError::RevisionNotFound { .. })
+        ));
+
+        // Write blobs until two of them share an abbreviated id.
+        let mut prefixes = HashMap::new();
+        let ambiguous = (0..)
+            .find_map(|n: u32| {
+                let oid = repository.blob(n.to_string().as_bytes()).unwrap();
+                let prefix = oid.to_string()[..4].to_owned();
+                prefixes.insert(prefix.clone(), oid).map(|_| prefix)
+            })
+            .unwrap();
+        assert!(matches!(
+            obs_env.reset_index_to_version("ts_wep", &ambiguous),
+            Err(ObsEnvError::AmbiguousRevision { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_base_env_versions_cache() -> TestResult {
+        let root = TempDir::new()?;
+        let remotes = root.path().join("remotes");
+
+        let base_env_remote = fixture_remote(&remotes.join("ts_cycle_build"));
+        fixture_commit_file(
+            &base_env_remote,
+            "cycle/cycle.env",
+            "ts_wep=1.2.3\nts_observatory_control=0.20.0\n",
+            "Cycle 1",
+        );
+
+        let mut obs_env = ObservingEnvironment {
+            base_env_source_org: remotes.to_string_lossy().to_string(),
+            ..fixture_environment(
+                &root.path().join("env"),
+                &remotes,
+                &["ts_observatory_control", "ts_wep"],
+            )
+        };
+        obs_env.create_path()?;
+
+        let versions = obs_env.get_base_env_versions("main")?;
+        assert_eq!(versions["ts_wep"].describe, "1.2.3");
+        assert_eq!(versions["ts_observatory_control"].describe, "0.20.0");
+        assert!(obs_env.base_env_cache_path().exists());
+        assert!(!root.path().join("env").join("ts_cycle_build").exists());
+
+        fixture_commit_file(
+            &base_env_remote,
+            "cycle/cycle.env",
+            "ts_wep=1.3.0\nts_observatory_control=0.20.0\n",
+            "Cycle 2",
+        );
+
+        obs_env.set_offline(true);
+        let versions = obs_env.get_base_env_versions("main")?;
+        assert_eq!(versions["ts_wep"].describe, "1.2.3");
+
+        obs_env.set_offline(false);
+        let versions = obs_env.get_base_env_versions("main")?;
+        assert_eq!(versions["ts_wep"].describe, "1.3.0");
+
+        obs_env.set_refresh_base_cache(true);
+        let versions = obs_env.get_base_env_versions("main")?;
+        assert_eq!(versions["ts_wep"].describe, "1.3.0");
+        Ok(())
+    }
+
+    #[test]
+    fn test_base_env_revision() -> TestResult {
+        let root = TempDir::new()?;
+        let remotes = root.path().join("remotes");
+        let base_env_remote = fixture_remote(&remotes.join("ts_cycle_build"));
+        let now = super::unix_time();
+        let at = |days: u64| {
+            let time = git2::Time::new((now + days * 86400) as i64, 0);
+            Signature::new("Test", "test@example.com", &time).unwrap()
+        };
+        let cycle_1 = fixture_commit_file_as(
+            &base_env_remote,
+            "cycle/cycle.env",
+            "ts_wep=1.2.3\n",
+            "Cycle 1",
+            &at(1),
+        );
+        base_env_remote.tag_lightweight(
+            "cycle-1",
+            &base_env_remote.find_object(cycle_1, None)?,
+            false,
+        )?;
+        fixture_commit_file_as(
+            &base_env_remote,
+            "cycle/cycle.env",
+            "ts_wep=1.3.0\n",
+            "Cycle 2",
+            &at(3),
+        );
+
+        let versions_at = |revision: BaseEnvRevision| {
+            let obs_env = ObservingEnvironment {
+                base_env_source_org: remotes.to_string_lossy().to_string(),
+                base_env_revision: revision,
+                ..fixture_environment(&root.path().join("env"), &remotes, &["ts_wep"])
+            };
+            obs_env.create_path()?;
+            obs_env.get_base_env_versions_cached("main")
+        };
+        let versions = versions_at(BaseEnvRevision::AsOf(now + 2 * 86400))?;
+        assert_eq!(versions.versions["ts_wep"].describe, "1.2.3");
+        assert_eq!(versions.commit, Some(cycle_1.to_string()));
+        let versions = versions_at(BaseEnvRevision::At("cycle-1".to_owned()))?;
+        assert_eq!(versions.versions["ts_wep"].describe, "1.2.3");
+        assert_eq!(versions.commit, Some(cycle_1.to_string()));
+        let versions = versions_at(BaseEnvRevision::Latest)?;
+        assert_eq!(versions.versions["ts_wep"].describe, "1.3.0");
+
+        let error = versions_at(BaseEnvRevision::AsOf(946684800)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Failed to read the base environment definition from cycle/cycle.env on main as of 2000-01-01 00:00:00 UTC: \
+             main has no commit before 2000-01-01 00:00:00, its history starts later"
+        );
+        assert!(ObservingEnvironment::builder()
+            .base_env_source("/obs-env/cycle.env")
+            .base_env_revision(BaseEnvRevision::At("cycle-1".to_owned()))
+            .build()
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_base_env_versions_ttl() -> TestResult {
+        let root = TempDir::new()?;
+        let remotes = root.path().join("remotes");
+
+        let base_env_remote = fixture_remote(&remotes.join("ts_cycle_build"));
+        fixture_commit_file(
+            &base_env_remote,
+            "cycle/cycle.env",
+            "ts_wep=1.2.3\n",
+            "Cycle 1",
+        );
+
+        let mut obs_env = ObservingEnvironment {
+            base_env_source_org: remotes.to_string_lossy().to_string(),
+            base_versions_ttl: Some(Duration::from_secs(600)),
+            ..fixture_environment(&root.path().join("env"), &remotes, &["ts_wep"])
+        };
+        obs_env.create_path()?;
+
+        let versions = obs_env.get_base_env_versions_cached("main")?;
+        assert_eq!(versions.versions["ts_wep"].describe, "1.2.3");
+        assert_eq!(versions.cache_age, None);
+        assert!(obs_env.versions_cache_path("main").exists());
+
+        fixture_commit_file(
+            &base_env_remote,
+            "cycle/cycle.env",
+            "ts_wep=1.3.0\n",
+            "Cycle 2",
+        );
+
+        let versions = obs_env.get_base_env_versions_cached("main")?;
+        assert_eq!(versions.versions["ts_wep"].describe, "1.2.3");
+        assert!(versions.cache_age.is_some());
+
+        obs_env.set_refresh_base_cache(true);
+        let versions = obs_env.get_base_env_versions_cached("main")?;
+        assert_eq!(versions.versions["ts_wep"].describe, "1.3.0");
+        assert_eq!(versions.cache_age, None);
+        obs_env.set_refresh_base_cache(false);
+
+        // Expired, but still used offline.
+        fixture_commit_file(
+            &base_env_remote,
+            "cycle/cycle.env",
+            "ts_wep=1.4.0\n",
+            "Cycle 3",
+        );
+        obs_env.base_versions_ttl = Some(Duration::ZERO);
+        obs_env.set_offline(true);
+        let versions = obs_env.get_base_env_versions_cached("main")?;
+        assert_eq!(versions.versions["ts_wep"].describe, "1.3.0");
+        assert!(versions.cache_age.is_some());
+
+        obs_env.set_offline(false);
+        let versions = obs_env.get_base_env_versions_cached("main")?;
+        assert_eq!(versions.versions["ts_wep"].describe, "1.4.0");
+        Ok(())
+    }
+
+    #[test]
+    fn test_base_env_versions_local_source() -> TestResult {
+        let root = TempDir::new()?;
+        let mut obs_env = ObservingEnvironment::with_destination(&root.path().to_string_lossy());
+
+        let versions_file = root.path().join("versions.env");
+        std::fs::write(&versions_file, "ts_wep=1.2.3\ncwfs=0.3.1\n")?;
+        obs_env.set_base_env_source(&versions_file.to_string_lossy());
+
+        let versions = obs_env.get_base_env_versions("main")?;
+        assert_eq!(versions.len(), 2);
+        assert_eq!(versions["ts_wep"].describe, "1.2.3");
+        assert_eq!(
+            obs_env.describe_base_env_source("main"),
+            format!("local path {}", versions_file.display())
+        );
+
+        let checkout = root.path().join("ts_cycle_build");
+        std::fs::create_dir_all(checkout.join("cycle"))?;
+        std::fs::write(checkout.join("cycle").join("cycle.env"), "ts_wep=2.0.0\n")?;
+        obs_env.set_base_env_source(&checkout.to_string_lossy());
+
+        let versions = obs_env.get_base_env_versions("main")?;
+        assert_eq!(versions["ts_wep"].describe, "2.0.0");
+        assert!(!obs_env.base_env_cache_path().exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_busy_repository() -> TestResult {
+        let root = TempDir::new()?;
+        let remotes = root.path().join("remotes");
+
+        let remote = fixture_remote(&remotes.join("ts_wep"));
+        let head = remote.head()?.peel_to_commit()?.id();
+
+        let mut obs_env = fixture_environment(&root.path().join("env"), &remotes, &["ts_wep"]);
+        obs_env.create_path()?;
+        obs_env.clone_repositories().into_result()?;
+        let repository = Repository::open(root.path().join("env").join("ts_wep"))?;
+        std::fs::write(repository.path().join("MERGE_HEAD"), format!("{head}\n"))?;
+
+        match obs_env.reset_index_to_version("ts_wep", "main") {
+            Err(ObsEnvError::RepoBusy { repo, state }) => {
+                assert_eq!(repo, "ts_wep");
+                assert_eq!(state, "merge");
+            }
+            result => panic!("Expected RepoBusy, got {result:?}"),
+        }
+        assert!(matches!(
+            obs_env.checkout_branch("ts_wep", "main"),
+            Err(ObsEnvError::RepoBusy { .. })
+        ));
+
+        obs_env.set_abort_in_progress(true);
+        obs_env.checkout_branch("ts_wep", "main")?;
+        assert_eq!(repository.state(), git2::RepositoryState::Clean);
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_commits() -> TestResult {
+        let root = TempDir::new()?;
+        let remotes = root.path().join("remotes");
+        let remote = fixture_remote(&remotes.join("ts_wep"));
+        let now = super::unix_time();
+        let by = |name: &str, days_ago: u64| {
+            let time = git2::Time::new((now - days_ago * 86400) as i64, 0);
+            Signature::new(name, &format!("{}@example.com", name.to_lowercase()), &time).unwrap()
+        };
+        fixture_commit_file_as(&remote, "lut.txt", "1", "Update M2 LUT", &by("Alice", 3));
+        let recent = fixture_commit_file_as(
+            &remote,
+            "lut.txt",
+            "2",
+            "Fix typo\n\nFollow-up of the M2 LUT change.",
+            &by("Bob", 1),
+        );
+        let obs_env = fixture_environment(&root.path().join("env"), &remotes, &["ts_wep"]);
+        obs_env.create_path()?;
+        obs_env.clone_repositories().into_result()?;
+
+        let query = CommitQuery {
+            pattern: Regex::new("M2 LUT")?,
+            since: None,
+            author: None,
+            max_results: 20,
+        };
+        let subjects = |query: &CommitQuery| -> TestResult<Vec<String>> {
+            Ok(obs_env
+                .search_commits(query, false)
+                .remove("ts_wep")
+                .unwrap()?
+                .into_iter()
+                .map(|commit| commit.subject)
+                .collect())
+        };
+        assert_eq!(subjects(&query)?, ["Fix typo", "Update M2 LUT"]);
+        let commits = obs_env
+            .search_commits(&query, false)
+            .remove("ts_wep")
+            .unwrap()?;
+        assert_eq!(commits[0].id, recent.to_string());
+        assert_eq!(commits[0].author, "Bob");
+        let query = CommitQuery {
+            author: Some("ALICE".to_owned()),
+            ..query
+        };
+        assert_eq!(subjects(&query)?, ["Update M2 LUT"]);
+        let query = CommitQuery {
+            since: Some(now - 2 * 86400),
+            author: None,
+            ..query
+        };
+        assert_eq!(subjects(&query)?, ["Fix typo"]);
+        let query = CommitQuery {
+            since: None,
+            max_results: 1,
+            ..query
+        };
+        assert_eq!(subjects(&query)?, ["Fix typo"]);
+
+        // Only the local history is searched unless fetching first.
+        fixture_commit(&remote, "Revert the M2 LUT");
+        assert_eq!(subjects(&query)?, ["Fix typo"]);
+        let commits = obs_env
+            .search_commits(&query, true)
+            .remove("ts_wep")
+            .unwrap()?;
+        assert_eq!(commits[0].subject, "Revert the M2 LUT");
+        Ok(())
+    }
+
+    #[test]
+    fn test_dirty_files() -> TestResult {
+        let root = TempDir::new()?;
+        let remotes = root.path().join("remotes");
+        let remote = fixture_remote(&remotes.join("ts_wep"));
+        fixture_commit_file(&remote, "README.md", "ts_wep\n", "Add README");
+        let obs_env = fixture_environment(&root.path().join("env"), &remotes, &["ts_wep"]);
+        obs_env.create_path()?;
+        obs_env.clone_repositories().into_result()?;
+        let repo = obs_env.repo("ts_wep")?;
+        std::fs::write(repo.path().join("notes.txt"), "")?;
+        let version = repo.version()?;
+        assert!(!version.dirty);
+        assert_eq!(version.untracked, 1);
+
+        std::fs::write(repo.path().join("README.md"), "changed\n")?;
+        std::fs::write(repo.path().join("staged.txt"), "")?;
+        let repository = Repository::open(repo.path())?;
+        let mut index = repository.index()?;
+        index.add_path(Path::new("staged.txt"))?;
+        index.write()?;
+        let version = repo.version()?;
+        assert!(version.dirty);
+        assert_eq!(version.dirty_files, ["README.md", "staged.txt"]);
+        assert_eq!(version.untracked, 1);
+        assert!(obs_env.get_manifest().repos[0].dirty_files.is_empty());
+        assert_eq!(
+            obs_env.get_manifest_with_dirty_files().repos[0].dirty_files,
+            ["README.md", "staged.txt"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_ahead_behind() -> TestResult {
+        let root = TempDir::new()?;
+        let remotes = root.path().join("remotes");
+        let remote = fixture_remote(&remotes.join("ts_wep"));
+        let obs_env = fixture_environment(&root.path().join("env"), &remotes, &["ts_wep"]);
+        obs_env.create_path()?;
+        obs_env.clone_repositories().into_result()?;
+        let repo = obs_env.repo("ts_wep")?;
+        let version = repo.version()?;
+        assert_eq!(version.ahead_behind, Some(AheadBehind::default()));
+        assert_eq!(version.describe_upstream(), "+0/-0");
+
+        let local = Repository::open(repo.path())?;
+        fixture_commit(&local, "Local change");
+        fixture_commit(&remote, "Upstream change");
+        fixture_commit(&remote, "Another upstream change");
+        obs_env.fetch_repositories().remove("ts_wep").unwrap()?;
+        assert_eq!(repo.version()?.describe_upstream(), "+1/-2");
+        assert_eq!(repo.behind_origin()?, Some(2));
+        assert_eq!(obs_env.get_manifest().repos[0].ahead_behind, None);
+
+        local.set_head_detached(local.head()?.target().unwrap())?;
+        let version = repo.version()?;
+        assert_eq!(version.ahead_behind, None);
+        assert_eq!(version.describe_upstream(), "detached");
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_remotes() -> TestResult {
+        let root = TempDir::new()?;
+        let remotes = root.path().join("remotes");
+        let remote = fixture_remote(&remotes.join("ts_wep"));
+        let head = remote.head()?.peel_to_commit()?;
+        remote.branch("tickets/DM-1", &head, false)?;
+        remote.branch("tickets/DM-2", &head, false)?;
+        fixture_remote(&remotes.join("forks/ts_wep"));
+        let obs_env = fixture_environment(&root.path().join("env"), &remotes, &["ts_wep"]);
+        obs_env.create_path()?;
+        obs_env.clone_repositories().into_result()?;
+        for (_, result) in obs_env.fetch_repositories() {
+            result?;
+        }
+        remote
+            .find_branch("tickets/DM-1", BranchType::Local)?
+            .delete()?;
+        // A remote added by hand, which has no tickets/DM-3 either.
+        let local = Repository::open(root.path().join("env/ts_wep"))?;
+        local.remote("fork", &remotes.join("forks/ts_wep").to_string_lossy())?;
+        let local_head = local.head()?.peel_to_commit()?.id();
+        for name in ["refs/remotes/fork/main", "refs/remotes/fork/tickets/DM-3"] {
+            local.reference(name, local_head, false, "test")?;
+        }
+
+        let pruned = obs_env.prune_remotes()?;
+        let mut refs = pruned["ts_wep"].as_ref().unwrap().refs.clone();
+        refs.sort();
+        assert_eq!(
+            refs,
+            [
+                "refs/remotes/fork/tickets/DM-3",
+                "refs/remotes/origin/tickets/DM-1"
+            ]
+        );
+        for name in refs.iter() {
+            assert!(local.find_reference(name).is_err());
+        }
+        for name in ["refs/remotes/origin/tickets/DM-2", "refs/remotes/fork/main"] {
+            assert!(local.find_reference(name).is_ok());
+        }
+        assert!(obs_env.prune_remotes()?["ts_wep"]
+            .as_ref()
+            .unwrap()
+            .refs
+            .is_empty());
+
+        let offline = ObservingEnvironment {
+            offline: true,
+            ..obs_env
+        };
+        assert!(matches!(
+            offline.prune_remotes(),
+            Err(ObsEnvError::InvalidConfig { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_stale_branches() -> TestResult {
+        let root = TempDir::new()?;
+        let remotes = root.path().join("remotes");
+        let remote = fixture_remote(&remotes.join("ts_wep"));
+        let signature = Signature::now("Test", "test@example.com")?;
+        let head = remote.head()?.peel_to_commit()?;
+        remote.branch("tickets/DM-1", &head, false)?;
+        let tree = head.tree()?;
+        remote.commit(
+            Some("refs/heads/tickets/DM-2"),
+            &signature,
+            &signature,
+            "Work on DM-2",
+            &tree,
+            &[&head],
+        )?;
+        let obs_env = fixture_environment(&root.path().join("env"), &remotes, &["ts_wep"]);
+        obs_env.create_path()?;
+        obs_env.clone_repositories().into_result()?;
+        obs_env.checkout_branch("ts_wep", "tickets/DM-2")?;
+        obs_env.checkout_branch("ts_wep", "tickets/DM-1")?;
+        let local = Repository::open(root.path().join("env/ts_wep"))?;
+        let local_head = local.head()?.peel_to_commit()?;
+        local.commit(
+            Some("refs/heads/local-work"),
+            &signature,
+            &signature,
+            "Unpushed work",
+            &local_head.tree()?,
+            &[&local_head],
+        )?;
+        remote
+            .find_branch("tickets/DM-2", BranchType::Local)?
+            .delete()?;
+
+        // Checking out a branch leaves the temp branch behind.
+        let stale = obs_env.stale_branches(false).remove("ts_wep").unwrap()?;
+        assert_eq!(
+            stale.branches,
+            vec![
+                StaleBranch {
+                    branch: "temp".to_owned(),
+                    reason: StaleReason::Merged {
+                        into: "main".to_owned()
+                    },
+                },
+                StaleBranch {
+                    branch: "tickets/DM-2".to_owned(),
+                    reason: StaleReason::Gone,
+                },
+            ]
+        );
+        assert_eq!(stale.remote_refs, vec!["origin/tickets/DM-2".to_owned()]);
+        assert!(!stale.deleted);
+        assert!(local.find_branch("tickets/DM-2", BranchType::Local).is_ok());
+
+        let stale = obs_env.stale_branches(true).remove("ts_wep").unwrap()?;
+        assert_eq!(stale.len(), 3);
+        assert!(stale.deleted);
+        assert!(local
+            .find_branch("tickets/DM-2", BranchType::Local)
+            .is_err());
+        assert!(local
+            .find_branch("origin/tickets/DM-2", BranchType::Remote)
+            .is_err());
+
+        local.set_head("refs/heads/local-work")?;
+        let stale = obs_env.stale_branches(false).remove("ts_wep").unwrap()?;
+        assert_eq!(
+            stale.branches,
+            vec![StaleBranch {
+                branch: "tickets/DM-1".to_owned(),
+                reason: StaleReason::Merged {
+                    into: "main".to_owned()
+                },
+            }]
+        );
+        assert!(stale.remote_refs.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_checkout_ticket_branch() -> TestResult {
+        let root = TempDir::new()?;
+        let remotes = root.path().join("remotes");
+        let remote = fixture_remote(&remotes.join("ts_wep"));
+        let head = remote.head()?.peel_to_commit()?;
+        remote.branch("tickets/DM-12345", &head, false)?;
+        remote.branch("tickets/DM-999", &head, false)?;
+        remote.branch("DM-999", &head, false)?;
+        remote.branch("tickets/SITCOM-7", &head, false)?;
+        let obs_env = fixture_environment(&root.path().join("env"), &remotes, &["ts_wep"]);
+        obs_env.create_path()?;
+        obs_env.clone_repositories().into_result()?;
+
+        assert_eq!(
+            obs_env.checkout_branch("ts_wep", "DM-12345")?,
+            "tickets/DM-12345"
+        );
+        let repository = Repository::open(root.path().join("env/ts_wep"))?;
+        assert_eq!(repository.head()?.shorthand(), Some("tickets/DM-12345"));
+        assert_eq!(obs_env.checkout_branch("ts_wep", "DM-999")?, "DM-999");
+        assert!(matches!(
+            obs_env.checkout_branch("ts_wep", "DM-1"),
+            Err(ObsEnvError::BranchNotFound { branch, .. }) if branch == "DM-1"
+        ));
+        assert!(matches!(
+            obs_env.checkout_branch("ts_wep", "SITCOM-7"),
+            Err(ObsEnvError::BranchNotFound { .. })
+        ));
+
+        let obs_env = ObservingEnvironment {
+            ticket_patterns: vec![super::ticket_pattern(r"SITCOM-\d+")?],
+            ..obs_env
+        };
+        assert_eq!(
+            obs_env.checkout_branch("ts_wep", "SITCOM-7")?,
+            "tickets/SITCOM-7"
+        );
+        assert_eq!(obs_env.expand_branch_name("DM-12345"), None);
+        assert!(ObservingEnvironment::builder()
+            .ticket_patterns(vec!["DM-(".to_owned()])
+            .build()
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_in_progress_state_conflicts() -> TestResult {
+        let root = TempDir::new()?;
+        let repository = fixture_remote(&root.path().join("ts_wep"));
+        assert_eq!(in_progress_state(&repository), None);
+
+        let blob = repository.blob(b"conflict")?;
+        let entry = |stage: u16| git2::IndexEntry {
+            ctime: git2::IndexTime::new(0, 0),
+            mtime: git2::IndexTime::new(0, 0),
+            dev: 0,
+            ino: 0,
+            mode: 0o100644,
+            uid: 0,
+            gid: 0,
+            file_size: 8,
+            id: blob,
+            flags: stage << 12,
+            flags_extended: 0,
+            path: b"file.txt".to_vec(),
+        };
+        let mut index = repository.index()?;
+        for stage in 1..=3 {
+            index.add(&entry(stage))?;
+        }
+        index.write()?;
+
+        assert_eq!(
+            in_progress_state(&repository).as_deref(),
+            Some("conflicted index")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_error_variants() -> TestResult {
+        let root = TempDir::new()?;
+        let remotes = root.path().join("remotes");
+        let destination = root.path().join("obs_env");
+        let obs_env = fixture_environment(&destination, &remotes, &["ts_wep"]);
+
+        assert!(matches!(
+            obs_env.checkout_branch("not_a_repo", "main"),
+            Err(ObsEnvError::RepoNotFound { .. })
+        ));
+        match obs_env.checkout_branch("ts_wep", "main") {
+            Err(error @ ObsEnvError::RepoNotCloned { .. }) => {
+                assert!(std::error::Error::source(&error).is_some())
+            }
+            other => panic!("Expected RepoNotCloned, got {other:?}"),
+        }
+
+        obs_env.create_path()?;
+        obs_env.clone_repositories().into_result()?;
+        assert!(matches!(
+            obs_env.checkout_branch("ts_wep", "tickets/DM-0"),
+            Err(ObsEnvError::BranchNotFound { branch, .. }) if branch == "tickets/DM-0"
+        ));
+
+        let missing_remote = fixture_environment(&root.path().join("other"), &remotes, &[]);
+        let missing_remote = ObservingEnvironment {
+            repositories: BTreeMap::from([(
+                "ts_wep".to_owned(),
+                repo_spec_in_org("ts_wep", &root.path().join("nowhere").to_string_lossy()),
+            )]),
+            ..missing_remote
+        };
+        missing_remote.create_path()?;
+        match missing_remote.clone_repositories().into_result() {
+            Err(error @ ObsEnvError::CloneFailed { .. }) => {
+                let report = report(&error);
+                assert!(report.contains(
+                    &root
+                        .path()
+                        .join("other")
+                        .join("ts_wep")
+                        .to_string_lossy()
+                        .to_string()
+                ));
+                assert!(report.contains("\ncaused by: "));
+            }
+            _ => panic!("Expected CloneFailed"),
+        }
+
+        let file = root.path().join("file");
+        std::fs::write(&file, "")?;
+        assert!(matches!(
+            ObservingEnvironment::with_destination(&file.to_string_lossy()).create_path(),
+            Err(ObsEnvError::InvalidEnvPath { .. })
+        ));
+        Ok(())
+    }
+
+    /// Environment at `destination` managing `repo_names` through
+    /// `backend`, with the base environment taken from the fake remote at
+    /// FAKE_ORG.
+    fn fake_environment(
+        destination: &Path,
+        backend: &FakeBackend,
+        repo_names: &[&str],
+    ) -> ObservingEnvironment {
+        ObservingEnvironment {
+            base_env_source_org: FAKE_ORG.to_owned(),
+            ..ObservingEnvironment::builder()
+                .destination(&destination.to_string_lossy())
+                .repositories(repo_names.iter().map(|repo_name| (*repo_name, FAKE_ORG)))
+                .backend(backend.clone())
+                .build()
+                .unwrap()
+        }
+    }
+
+    #[test]
+    fn test_setup_with_fake_backend() -> TestResult {
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        backend.set_branch(&format!("{FAKE_ORG}/ts_wep"), "main", "1111aaaa");
+        backend.set_branch(&format!("{FAKE_ORG}/ts_wep"), "develop", "2222bbbb");
+
+        let obs_env = fake_environment(root.path(), &backend, &["ts_wep", "ts_missing"]);
+        let report = obs_env.clone_repositories();
+        assert_eq!(report.repos.len(), 2);
+        assert!(matches!(
+            report.repos[0].error(),
+            Some(ObsEnvError::CloneFailed { repo, .. }) if repo == "ts_missing"
+        ));
+        match &report.repos[1].outcome {
+            RepoSetupOutcome::Cloned { path, head, .. } => {
+                assert_eq!(path, &root.path().join("ts_wep"));
+                assert_eq!(head.as_deref(), Some("1111aaaa"));
+            }
+            outcome => panic!("Expected ts_wep to be cloned, got {outcome:?}"),
+        }
+        assert_eq!(
+            backend.head(root.path().join("ts_wep")).unwrap(),
+            "1111aaaa"
+        );
+        assert_eq!(backend.branch(root.path().join("ts_wep")).unwrap(), "main");
+
+        // Repositories already cloned are skipped.
+        let report = obs_env.clone_repositories();
+        assert!(!report.is_success());
+        assert!(report.repos[0].error().is_some());
+        assert!(matches!(
+            &report.repos[1].outcome,
+            RepoSetupOutcome::Skipped { path } if path == &root.path().join("ts_wep")
+        ));
+
+        obs_env.checkout_branch("ts_wep", "develop")?;
+        assert_eq!(
+            backend.head(root.path().join("ts_wep")).unwrap(),
+            "2222bbbb"
+        );
+        assert!(matches!(
+            obs_env.checkout_branch("ts_wep", "tickets/DM-0"),
+            Err(ObsEnvError::BranchNotFound { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_repositories() -> TestResult {
+        let root = TempDir::new()?;
+        let remotes = root.path().join("remotes");
+        let destination = root.path().join("env");
+        let obs_env = fixture_environment(&destination, &remotes, &["cwfs", "ts_wep"]);
+        obs_env.create_path()?;
+        obs_env.clone_repositories().into_result()?;
+        let obs_env = fixture_environment(&destination, &remotes, &["cwfs", "ts_wep", "ts_xml"]);
+
+        let ts_wep_remote = Repository::open(remotes.join("ts_wep"))?;
+        let upstream = fixture_commit(&ts_wep_remote, "Upstream change");
+        let cwfs_remote = Repository::open(remotes.join("cwfs"))?;
+        fixture_commit(&cwfs_remote, "Upstream change");
+        std::fs::write(destination.join("cwfs/local.txt"), "local")?;
+        let cwfs = Repository::open(destination.join("cwfs"))?;
+        let mut index = cwfs.index()?;
+        index.add_path(Path::new("local.txt"))?;
+        index.write()?;
+        let cwfs_head = cwfs.head()?.peel_to_commit()?.id();
+
+        let report = obs_env.update_repositories();
+        // ts_xml is not cloned, and not cloned by an update.
+        let names: Vec<&str> = report.repos.iter().map(|repo| repo.name.as_str()).collect();
+        assert_eq!(names, ["cwfs", "ts_wep"]);
+        assert!(matches!(
+            report.repos[0].error(),
+            Some(ObsEnvError::DirtyWorkingTree { repo }) if repo == "cwfs"
+        ));
+        assert_eq!(cwfs.head()?.peel_to_commit()?.id(), cwfs_head);
+        match &report.repos[1].outcome {
+            RepoSetupOutcome::Updated { head, .. } => {
+                assert_eq!(head.as_deref(), Some(upstream.to_string().as_str()))
+            }
+            outcome => panic!("Expected ts_wep to be updated, got {outcome:?}"),
+        }
+        assert!(!destination.join("ts_xml").exists());
+
+        let obs_env = ObservingEnvironment {
+            on_error: ErrorPolicy::FailFast,
+            ..obs_env
+        };
+        let report = obs_env.update_repositories();
+        assert!(matches!(
+            report.repos[1].error(),
+            Some(ObsEnvError::NotAttempted { repo, operation }) if repo == "ts_wep" && operation == "update"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_checkout_branch_all() -> TestResult {
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        for repo_name in ["cwfs", "ts_wep", "ts_xml"] {
+            backend.set_branch(&format!("{FAKE_ORG}/{repo_name}"), "main", "1111aaaa");
+        }
+        let obs_env = fake_environment(root.path(), &backend, &["cwfs", "ts_wep", "ts_xml"]);
+        obs_env.clone_repositories().into_result()?;
+        backend.set_branch(&format!("{FAKE_ORG}/ts_wep"), "tickets/DM-1", "2222bbbb");
+        backend.set_branch(&format!("{FAKE_ORG}/ts_xml"), "tickets/DM-1", "3333cccc");
+        backend.set_in_progress(root.path().join("ts_xml"), "merge");
+
+        let results = obs_env.checkout_branch_all("DM-1");
+        assert_eq!(results["cwfs"].as_ref().unwrap(), &None);
+        assert_eq!(
+            results["ts_wep"].as_ref().unwrap().as_deref(),
+            Some("tickets/DM-1")
+        );
+        assert!(matches!(
+            results["ts_xml"],
+            Err(ObsEnvError::RepoBusy { .. })
+        ));
+        assert_eq!(backend.head(root.path().join("cwfs")).unwrap(), "1111aaaa");
+        assert_eq!(
+            backend.head(root.path().join("ts_wep")).unwrap(),
+            "2222bbbb"
+        );
+        assert_eq!(
+            backend.head(root.path().join("ts_xml")).unwrap(),
+            "1111aaaa"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_working_trees() -> TestResult {
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        for repo_name in ["cwfs", "ts_wep"] {
+            backend.set_branch(&format!("{FAKE_ORG}/{repo_name}"), "main", "1111aaaa");
+        }
+        let obs_env = fake_environment(root.path(), &backend, &["cwfs", "ts_missing", "ts_wep"]);
+        obs_env.clone_repositories();
+        obs_env.reset_index_to_version("cwfs", "1111aaaa")?;
+        backend.set_dirty(root.path().join("ts_wep"));
+        backend.set_in_progress(root.path().join("ts_wep"), "merge");
+
+        let working_trees = obs_env.working_trees();
+        assert_eq!(working_trees.len(), 2);
+        let cwfs = working_trees["cwfs"].as_ref().unwrap();
+        assert_eq!(cwfs.branch, None);
+        assert!(!cwfs.is_dirty());
+        assert_eq!(cwfs.to_string(), "cwfs: detached at 1111aaaa, clean");
+        let ts_wep = working_trees["ts_wep"].as_ref().unwrap();
+        assert!(ts_wep.is_dirty());
+        assert_eq!(
+            ts_wep.to_string(),
+            "ts_wep: main at 1111aaaa (+0/-0), modified, merge in progress"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_clone_in_parallel() -> TestResult {
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        let repo_names: Vec<String> = (0..12).map(|index| format!("repo_{index:02}")).collect();
+        for repo_name in repo_names
+            .iter()
+            .filter(|repo_name| *repo_name != "repo_05")
+        {
+            backend.set_branch(&format!("{FAKE_ORG}/{repo_name}"), "main", "1111aaaa");
+        }
+        let repo_names: Vec<&str> = repo_names.iter().map(String::as_str).collect();
+        let obs_env = ObservingEnvironment {
+            jobs: 4,
+            ..fake_environment(root.path(), &backend, &repo_names)
+        };
+
+        let report = obs_env.clone_repositories();
+        let names: Vec<&str> = report.repos.iter().map(|repo| repo.name.as_str()).collect();
+        assert_eq!(names, repo_names);
+        let failures: Vec<&str> = report.failures().map(|(repo_name, _)| repo_name).collect();
+        assert_eq!(failures, ["repo_05"]);
+        for repo_name in repo_names
+            .iter()
+            .filter(|repo_name| **repo_name != "repo_05")
+        {
+            assert_eq!(
+                backend.head(root.path().join(repo_name)).unwrap(),
+                "1111aaaa"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_clone_with_retries() -> TestResult {
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        backend.set_branch(&format!("{FAKE_ORG}/ts_wep"), "main", "1111aaaa");
+        let retrying = |retries| {
+            ObservingEnvironment::builder()
+                .destination(&root.path().to_string_lossy())
+                .repositories([("ts_wep", FAKE_ORG)])
+                .backend(backend.clone())
+                .retry(RetryPolicy {
+                    retries,
+                    backoff: Duration::ZERO,
+                })
+                .build()
+        };
+
+        backend.set_network_failures(2);
+        assert_eq!(retrying(1)?.clone_repositories().failures().count(), 1);
+        backend.set_network_failures(2);
+        let report = retrying(2)?.clone_repositories();
+        assert_eq!(report.failures().count(), 0);
+        assert_eq!(
+            backend.head(root.path().join("ts_wep")).as_deref(),
+            Some("1111aaaa")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_repository_subdirectory() -> TestResult {
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        backend.set_branch(&format!("{FAKE_ORG}/ts_wep"), "develop", "2222bbbb");
+        let ts_wep = RepoSpec {
+            path: Some("aos/ts_wep".to_owned()),
+            ..RepoSpec::new("ts_wep", &format!("{FAKE_ORG}/ts_wep"))
+        };
+        let obs_env = ObservingEnvironment::builder()
+            .destination(&root.path().to_string_lossy())
+            .repository_specs(vec![ts_wep], RepoSource::Custom)
+            .backend(backend.clone())
+            .build()?;
+
+        let report = obs_env.clone_repositories();
+        let path = root.path().join("aos").join("ts_wep");
+        match &report.repos[0].outcome {
+            RepoSetupOutcome::Cloned { path: cloned, .. } => assert_eq!(cloned, &path),
+            outcome => panic!("Expected ts_wep to be cloned, got {outcome:?}"),
+        }
+        assert_eq!(backend.head(&path).unwrap(), "2222bbbb");
+        assert_eq!(obs_env.repo("ts_wep")?.path(), path);
+        assert_eq!(obs_env.repo_path("ts_wep"), path);
+        assert_eq!(
+            obs_env.repo_path(&obs_env.base_env_source_repo),
+            root.path().join(&obs_env.base_env_source_repo)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_setup_existing_clones() -> TestResult {
+        let root = TempDir::new()?;
+        let remotes = root.path().join("remotes");
+        let destination = root.path().join("env");
+        let obs_env = fixture_environment(&destination, &remotes, &["ts_wep", "ts_xml"]);
+        obs_env.create_path()?;
+        std::fs::create_dir(destination.join("ts_xml"))?;
+
+        let report = obs_env.clone_repositories();
+        assert!(matches!(
+            report.repos[0].outcome,
+            RepoSetupOutcome::Cloned { .. }
+        ));
+        assert!(matches!(
+            report.repos[1].error(),
+            Some(ObsEnvError::NotARepository { repo, .. }) if repo == "ts_xml"
+        ));
+
+        let remote = Repository::open(remotes.join("ts_wep"))?;
+        let upstream = fixture_commit(&remote, "Upstream change");
+        let obs_env = ObservingEnvironment {
+            existing_clones: ExistingClones::Update,
+            ..fixture_environment(&destination, &remotes, &["ts_wep"])
+        };
+        let report = obs_env.clone_repositories();
+        match &report.repos[0].outcome {
+            RepoSetupOutcome::Updated { head, .. } => {
+                assert_eq!(head.as_deref(), Some(upstream.to_string().as_str()))
+            }
+            outcome => panic!("Expected ts_wep to be updated, got {outcome:?}"),
+        }
+
+        let local = Repository::open(destination.join("ts_wep"))?;
+        fixture_commit(&local, "Local change");
+        fixture_commit(&remote, "Diverging change");
+        let report = obs_env.clone_repositories();
+        assert!(matches!(
+            report.repos[0].error(),
+            Some(ObsEnvError::Git { source, .. }) if source.code() == git2::ErrorCode::NotFastForward
+        ));
+
+        let obs_env = ObservingEnvironment {
+            existing_clones: ExistingClones::Reclone,
+            ..fixture_environment(&destination, &remotes, &["ts_wep", "ts_xml"])
+        };
+        let report = obs_env.clone_repositories();
+        assert!(report.is_success());
+        assert!(report
+            .repos
+            .iter()
+            .all(|repo| matches!(repo.outcome, RepoSetupOutcome::Cloned { .. })));
+        assert_eq!(
+            Repository::open(destination.join("ts_wep"))?
+                .head()?
+                .peel_to_commit()?
+                .id(),
+            remote.head()?.peel_to_commit()?.id()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_setup_existing_env_path() -> TestResult {
+        let root = TempDir::new()?;
+        let remotes = root.path().join("remotes");
+        let destination = root.path().join("env");
+        let repo_names = ["ts_config_ocs", "ts_wep", "ts_xml"];
+        let fresh = |repo_names: &[&str]| ObservingEnvironment {
+            fresh: true,
+            ..fixture_environment(&destination, &remotes, repo_names)
+        };
+        std::fs::create_dir(&destination)?;
+
+        let obs_env = fresh(&repo_names);
+        obs_env.create_path()?;
+        assert_eq!(
+            obs_env
+                .survey()
+                .with_presence(RepoPresence::Missing)
+                .count(),
+            3
+        );
+
+        fresh(&["ts_wep"]).clone_repositories().into_result()?;
+        std::fs::create_dir(destination.join("ts_xml"))?;
+        let survey = obs_env.survey();
+        assert_eq!(
+            survey.repos,
+            [
+                ("ts_config_ocs".to_owned(), RepoPresence::Missing),
+                ("ts_wep".to_owned(), RepoPresence::Present),
+                ("ts_xml".to_owned(), RepoPresence::Broken),
+            ]
+        );
+        assert_eq!(
+            survey.to_string(),
+            "Present: ts_wep\nBroken (not git repositories): ts_xml\nMissing: ts_config_ocs"
+        );
+        assert!(matches!(
+            obs_env.create_path(),
+            Err(ObsEnvError::InvalidEnvPath { .. })
+        ));
+
+        let obs_env = fixture_environment(&destination, &remotes, &repo_names);
+        obs_env.create_path()?;
+        let report = obs_env.clone_repositories();
+        assert!(matches!(
+            report.repos[0].outcome,
+            RepoSetupOutcome::Cloned { .. }
+        ));
+        assert!(matches!(
+            report.repos[1].outcome,
+            RepoSetupOutcome::Skipped { .. }
+        ));
+        assert!(matches!(
+            report.repos[2].error(),
+            Some(ObsEnvError::NotARepository { .. })
+        ));
+
+        std::fs::remove_dir(destination.join("ts_xml"))?;
+        obs_env.clone_repositories().into_result()?;
+        assert_eq!(
+            obs_env
+                .survey()
+                .with_presence(RepoPresence::Present)
+                .count(),
+            3
+        );
+        let report = obs_env.clone_repositories();
+        assert!(report
+            .repos
+            .iter()
+            .all(|repo| matches!(repo.outcome, RepoSetupOutcome::Skipped { .. })));
+        Ok(())
+    }
+
+    #[test]
+    fn test_repair_repositories() -> TestResult {
+        let root = TempDir::new()?;
+        let remotes = root.path().join("remotes");
+        let destination = root.path().join("env");
+        let obs_env = fixture_environment(
+            &destination,
+            &remotes,
+            &["ts_config_ocs", "ts_wep", "ts_xml"],
+        );
+        obs_env.create_path()?;
+        obs_env.clone_repositories().into_result()?;
+        assert!(obs_env
+            .diagnose_repositories()
+            .iter()
+            .all(RepoDiagnosis::is_healthy));
+
+        let local = Repository::open(destination.join("ts_config_ocs"))?;
+        fixture_commit(&local, "Local change");
+        local.set_head("refs/heads/unborn")?;
+        let lock = destination.join("ts_wep/.git/index.lock");
+        std::fs::write(&lock, "")?;
+        std::fs::remove_file(destination.join("ts_xml/.git/HEAD"))?;
+
+        let diagnoses = obs_env.diagnose_repositories();
+        assert_eq!(diagnoses[0].damage, [RepoDamage::MissingHead]);
+        assert_eq!(
+            diagnoses[1].damage,
+            [RepoDamage::StaleLocks {
+                locks: vec![lock.clone()]
+            }]
+        );
+        assert!(!diagnoses[1].is_broken());
+        assert!(matches!(
+            diagnoses[2].damage[..],
+            [RepoDamage::Unopenable { .. }]
+        ));
+
+        let repairs = obs_env.repair_repositories(false);
+        assert!(matches!(
+            repairs[0].1,
+            Err(ObsEnvError::LocalCommits { count: 1, .. })
+        ));
+        // The locks are only removed if no git process is running on the
+        // machine, which can change while the test runs.
+        match &repairs[1].1 {
+            Ok(repair) => assert_eq!(repair, &Repair::LocksRemoved { locks: vec![lock] }),
+            Err(error) => assert!(matches!(error, ObsEnvError::StaleLocks { .. })),
+        }
+        // Without its HEAD, the commits of ts_xml cannot be read to tell
+        // none would be lost.
+        assert!(matches!(
+            repairs[2].1,
+            Err(ObsEnvError::UnreadableCommits { .. })
+        ));
+        assert!(Repository::open(destination.join("ts_xml")).is_err());
+
+        let repairs = obs_env.repair_repositories(true);
+        assert!(matches!(
+            repairs[0].1,
+            Err(ObsEnvError::LocalCommits { count: 1, .. })
+        ));
+        assert!(matches!(
+            repairs
+                .iter()
+                .find(|(diagnosis, _)| diagnosis.name == "ts_xml"),
+            Some((_, Ok(Repair::Recloned { .. })))
+        ));
+        assert!(Repository::open(destination.join("ts_xml"))?
+            .head()?
+            .peel_to_commit()
+            .is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_deep_check() -> TestResult {
+        let root = TempDir::new()?;
+        let remotes = root.path().join("remotes");
+        let destination = root.path().join("env");
+        let obs_env = fixture_environment(&destination, &remotes, &["ts_wep", "ts_xml"]);
+        obs_env.create_path()?;
+        obs_env.clone_repositories().into_result()?;
+
+        let blob = Repository::open(destination.join("ts_wep"))?.blob(b"content")?;
+        let blob = blob.to_string();
+        let object = destination.join(format!("ts_wep/.git/objects/{}/{}", &blob[..2], &blob[2..]));
+        std::fs::remove_file(&object)?;
+        std::fs::write(&object, "not an object")?;
+        assert!(obs_env
+            .diagnose_repositories()
+            .iter()
+            .all(RepoDiagnosis::is_healthy));
+
+        let obs_env = ObservingEnvironment {
+            deep_check: true,
+            ..obs_env
+        };
+        let diagnoses = obs_env.diagnose_repositories();
+        match &diagnoses[0].damage[..] {
+            [RepoDamage::CorruptObjects { objects }] => {
+                assert_eq!(objects.len(), 1);
+                assert!(objects[0].contains(&blob));
+            }
+            damage => panic!("unexpected damage {damage:?}"),
+        }
+        assert!(diagnoses[0].is_broken());
+        assert!(diagnoses[1].is_healthy());
+
+        let repairs = obs_env.repair_repositories(false);
+        assert!(matches!(repairs[..], [(_, Ok(Repair::Recloned { .. }))]));
+        assert!(obs_env
+            .diagnose_repositories()
+            .iter()
+            .all(RepoDiagnosis::is_healthy));
+        Ok(())
+    }
+
+    #[test]
+    fn test_compare_manifests() -> TestResult {
+        let root = TempDir::new()?;
+        let remotes = root.path().join("remotes");
+        let remote = fixture_remote(&remotes.join("ts_wep"));
+        let initial = remote.head()?.peel_to_commit()?.id().to_string();
+        fixture_commit(&remote, "Fix the focus offsets");
+        let last = fixture_commit(&remote, "Add donut fitting").to_string();
+        let obs_env = fixture_environment(&root.path().join("env"), &remotes, &["ts_wep"]);
+        obs_env.create_path()?;
+        obs_env.clone_repositories().into_result()?;
+
+        let manifest = |sha: &str| {
+            let version = |name: &str| RepoVersion {
+                sha: Some(sha.to_owned()),
+                ..RepoVersion::new(name, "v1.0.0")
+            };
+            EnvironmentManifest::new("/other-env", vec![version("ts_wep"), version("ts_xml")])
+        };
+        let (from, to) = (manifest(&initial), manifest(&last));
+        let differences = obs_env.compare_manifests(&from, &to, true);
+        assert_eq!(
+            differences[0]
+                .commits
+                .iter()
+                .flatten()
+                .map(|commit| commit.subject.as_str())
+                .collect::<Vec<_>>(),
+            ["Add donut fitting", "Fix the focus offsets"]
+        );
+        assert_eq!(differences[1].repo, "ts_xml");
+        assert!(differences[1].commits.is_none());
+        assert!(obs_env.compare_manifests(&from, &to, false)[0]
+            .commits
+            .is_none());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_shared_access() -> TestResult {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = TempDir::new()?;
+        let remotes = root.path().join("remotes");
+        let destination = root.path().join("env");
+        let gid = unsafe { libc::getegid() };
+        let obs_env = ObservingEnvironment {
+            shared_access: SharedAccess {
+                group: Some(Group::lookup(&gid.to_string())?),
+                group_writable: true,
+            },
+            ..fixture_environment(&destination, &remotes, &["ts_wep"])
+        };
+        obs_env.create_path()?;
+        obs_env.clone_repositories().into_result()?;
+        let path = destination.join("ts_wep");
+        assert_eq!(
+            Repository::open(&path)?
+                .config()?
+                .get_string(SHARED_REPOSITORY)?,
+            "group"
+        );
+        assert_eq!(
+            destination.metadata()?.permissions().mode() & 0o2070,
+            0o2070
+        );
+        assert!(obs_env.diagnose_repositories()[0].is_shared());
+        obs_env.verify()?;
+
+        let head = path.join(".git/HEAD");
+        std::fs::set_permissions(&head, PermissionsExt::from_mode(0o600))?;
+        let diagnosis = obs_env.diagnose_repositories().remove(0);
+        assert_eq!(
+            diagnosis.blockers.last(),
+            Some(&RepoBlocker::NotShared { paths: vec![head] })
+        );
+        assert!(matches!(
+            obs_env.verify(),
+            Err(ObsEnvError::VerificationFailed { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_commit_identity_and_ownership() -> TestResult {
+        let root = TempDir::new()?;
+        let remotes = root.path().join("remotes");
+        let destination = root.path().join("env");
+        let obs_env = fixture_environment(&destination, &remotes, &["ts_wep"]);
+        obs_env.create_path()?;
+        obs_env.clone_repositories().into_result()?;
+        let mut config = Repository::open(destination.join("ts_wep"))?.config()?;
+        config.set_str("user.name", "Summit Operator")?;
+        config.set_str("user.email", "operator@summit")?;
+        assert_eq!(
+            obs_env.commit_identity("ts_wep")?,
+            Identity::new("Summit Operator", "operator@summit")
+        );
+
+        let backend = FakeBackend::new();
+        backend.set_branch(&format!("{FAKE_ORG}/ts_xml"), "main", "1111aaaa");
+        let fake_env = ObservingEnvironment {
+            identity: Identity::new("Night Log", "nightlog@summit"),
+            ..fake_environment(&root.path().join("fake"), &backend, &["ts_xml"])
+        };
+        fake_env.create_path()?;
+        fake_env.clone_repositories().into_result()?;
+        assert_eq!(
+            fake_env.commit_identity("ts_xml")?,
+            Identity::new("Night Log", "nightlog@summit")
+        );
+        assert_eq!(
+            fake_env.diagnose_repositories()[0].blockers,
+            [RepoBlocker::NoIdentity {
+                identity: Identity::new("Night Log", "nightlog@summit")
+            }]
+        );
+
+        // Only root can give the repository to another user.
+        let path = destination.join("ts_wep");
+        let c_path = std::ffi::CString::new(path.to_string_lossy().as_bytes())?;
+        if unsafe { libc::chown(c_path.as_ptr(), 1, 1) } == 0 {
+            assert!(git_backend::is_untrusted(&path));
+            git_backend::trust_directory(&destination);
+            assert!(!git_backend::is_untrusted(&path));
+            assert!(obs_env.get_current_env_versions()["ts_wep"].is_ok());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_symlinked_env_path() -> TestResult {
+        let root = TempDir::new()?;
+        let remotes = root.path().join("remotes");
+        let target = root.path().join("envs/v1");
+        std::fs::create_dir_all(&target)?;
+        let link = root.path().join("current");
+        std::os::unix::fs::symlink(&target, &link)?;
+        let target = target.canonicalize()?;
+
+        let obs_env = fixture_environment(&link, &remotes, &["ts_wep", "ts_xml"]);
+        assert_eq!(Path::new(&obs_env.destination), target);
+        obs_env.create_path()?;
+        let report = obs_env.clone_repositories().into_result()?;
+        assert!(report.cloned().all(|(_, path)| path.starts_with(&target)));
+        assert!(link.is_symlink());
+
+        let versions = obs_env.get_current_env_versions();
+        assert!(versions.values().all(Result::is_ok));
+        assert_eq!(Path::new(&obs_env.get_manifest().env_path), target);
+
+        assert!(obs_env.teardown(false).values().all(Result::is_ok));
+        assert!(link.is_symlink());
+        assert!(target.is_dir());
+        assert!(!target.join("ts_wep").exists());
+
+        let dangling = root.path().join("dangling");
+        std::os::unix::fs::symlink(root.path().join("envs/v2"), &dangling)?;
+        let obs_env = fixture_environment(&dangling, &remotes, &["ts_wep"]);
+        assert!(matches!(
+            obs_env.create_path(),
+            Err(ObsEnvError::InvalidEnvPath { .. })
+        ));
+        assert!(dangling.is_symlink());
+        Ok(())
+    }
+
+    #[test]
+    fn test_preflight_checks() -> TestResult {
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        let url = format!("{FAKE_ORG}/ts_wep");
+        backend.set_branch(&url, "main", "1111aaaa");
+        backend.set_tag(&url, "v1.2.0", "2222bbbb");
+        let destination = root.path().join("env");
+        let obs_env = ObservingEnvironment {
+            min_free_space: u64::MAX,
+            ..fake_environment(&destination, &backend, &["ts_wep"])
+        };
+        assert_eq!(obs_env.check_path().path, root.path());
+        assert!(matches!(
+            obs_env.create_path(),
+            Err(ObsEnvError::InsufficientSpace { .. })
+        ));
+        assert!(!destination.exists());
+
+        obs_env.clone_repositories().into_result()?;
+        let manifest = EnvironmentManifest::new(
+            &destination.to_string_lossy(),
+            vec![RepoVersion::new("ts_wep", "1.2.0")],
+        );
+        assert!(matches!(
+            obs_env.apply_manifest(&manifest).unwrap_err()[..],
+            [ObsEnvError::InsufficientSpace { .. }]
+        ));
+        assert!(matches!(
+            obs_env.reset_base_environment("main").unwrap_err()[..],
+            [ObsEnvError::InsufficientSpace { .. }]
+        ));
+        assert_eq!(
+            backend.head(destination.join("ts_wep")).as_deref(),
+            Some("1111aaaa")
+        );
+
+        let obs_env = fake_environment(&destination, &backend, &["ts_wep"]);
+        obs_env.create_path()?;
+        obs_env
+            .apply_manifest(&manifest)
+            .map_err(|mut errors| errors.remove(0))?;
+        assert_eq!(
+            backend.head(destination.join("ts_wep")).as_deref(),
+            Some("2222bbbb")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_repository() -> TestResult {
+        let root = TempDir::new()?;
+        let remotes = root.path().join("remotes");
+        let destination = root.path().join("env");
+        let remote = Repository::init(remotes.join("ts_config_new"))?;
+        let mut obs_env = fixture_environment(&destination, &remotes, &["ts_config_new", "ts_wep"]);
+        obs_env
+            .repositories
+            .get_mut("ts_config_new")
+            .unwrap()
+            .default_branch = Some("main".to_owned());
+        obs_env.create_path()?;
+        obs_env.clone_repositories().into_result()?;
+        assert!(obs_env.repo("ts_config_new")?.is_empty());
+
+        let versions = obs_env.get_current_env_versions();
+        assert!(matches!(
+            versions["ts_config_new"],
+            Err(ObsEnvError::EmptyRepository { .. })
+        ));
+        assert!(versions["ts_wep"].is_ok());
+        for result in [
+            obs_env.checkout_branch("ts_config_new", "main").map(|_| ()),
+            obs_env.reset_index_to_version("ts_config_new", "1.0.0"),
+        ] {
+            assert!(matches!(
+                result,
+                Err(ObsEnvError::EmptyRepository { repo }) if repo == "ts_config_new"
+            ));
+        }
+        assert!(obs_env
+            .diagnose_repositories()
+            .iter()
+            .all(RepoDiagnosis::is_healthy));
+
+        let obs_env = ObservingEnvironment {
+            existing_clones: ExistingClones::Update,
+            ..obs_env
+        };
+        obs_env.clone_repositories().into_result()?;
+        assert!(obs_env.repo("ts_config_new")?.is_empty());
+
+        drop(remote);
+        let remote = fixture_remote(&remotes.join("ts_config_new"));
+        obs_env.clone_repositories().into_result()?;
+        assert_eq!(
+            obs_env.get_current_env_versions()["ts_config_new"]
+                .as_ref()
+                .unwrap()
+                .sha,
+            Some(remote.head()?.peel_to_commit()?.id().to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_fetch_only_needed_refs() -> TestResult {
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        let url = format!("{FAKE_ORG}/ts_wep");
+        backend.set_branch(&url, "main", "1111aaaa");
+        let obs_env = fake_environment(root.path(), &backend, &["ts_wep"]);
+        obs_env.clone_repositories().into_result()?;
+        let path = root.path().join("ts_wep");
+
+        backend.set_tag(&url, "v1.3.0", "2222bbbb");
+        obs_env.reset_index_to_version("ts_wep", "1.3.0")?;
+        assert_eq!(backend.head(&path).as_deref(), Some("2222bbbb"));
+        assert_eq!(
+            backend.fetches(&path),
+            [[
+                "+refs/tags/v1.3.0:refs/tags/v1.3.0",
+                "+refs/tags/1.3.0:refs/tags/1.3.0"
+            ]]
+        );
+
+        backend.set_branch(&url, "develop", "3333cccc");
+        obs_env.checkout_branch("ts_wep", "develop")?;
+        assert_eq!(
+            backend.fetches(&path)[1],
+            ["+refs/heads/develop:refs/remotes/origin/develop"]
+        );
+
+        assert!(matches!(
+            obs_env.reset_index_to_version("ts_wep", "tickets/DM-0"),
+            Err(ObsEnvError::RevisionNotFound { .. })
+        ));
+        assert_eq!(
+            backend.fetches(&path)[2..],
+            [
+                vec!["+refs/tags/tickets/DM-0:refs/tags/tickets/DM-0"],
+                vec!["+refs/heads/tickets/DM-0:refs/remotes/origin/tickets/DM-0"],
+                vec![""],
+            ]
+        );
+
+        assert!(obs_env.fetch_repositories()["ts_wep"].is_ok());
+        assert_eq!(
+            backend.fetches(&path).last().unwrap(),
+            &["+refs/heads/*:refs/remotes/origin/*"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_clone_default_branch_with_fake_backend() -> TestResult {
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        let url = format!("{FAKE_ORG}/ts_wep");
+        backend.set_branch(&url, "main", "1111aaaa");
+        backend.set_branch(&url, "develop", "2222bbbb");
+
+        let mut with_develop = RepoSpec::new("ts_wep", &url);
+        with_develop.default_branch = Some("develop".to_owned());
+        let mut with_missing = RepoSpec::new("ts_other", &url);
+        with_missing.default_branch = Some("tickets/DM-0".to_owned());
+        let obs_env = ObservingEnvironment::builder()
+            .destination(&root.path().to_string_lossy())
+            .repository_specs(vec![with_develop, with_missing], RepoSource::Custom)
+            .backend(backend.clone())
+            .build()?;
+
+        let report = obs_env.clone_repositories();
+        assert!(matches!(
+            report.repos[0].error(),
+            Some(ObsEnvError::BranchNotFound { repo, branch, .. })
+                if repo == "ts_other" && branch == "tickets/DM-0"
+        ));
+        assert_eq!(
+            backend.head(root.path().join("ts_wep")).unwrap(),
+            "2222bbbb"
+        );
+        assert_eq!(
+            backend.branch(root.path().join("ts_wep")).unwrap(),
+            "develop"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_reset_backs_up_local_commits() -> TestResult {
+        let root = TempDir::new()?;
+        let remotes = root.path().join("remotes");
+        let destination = root.path().join("env");
+        let versions_file = root.path().join("versions.env");
+        std::fs::write(&versions_file, "ts_wep=main\n")?;
+        let obs_env = ObservingEnvironment {
+            base_env_local_source: Some(versions_file.to_string_lossy().into_owned()),
+            ..fixture_environment(&destination, &remotes, &["ts_wep"])
+        };
+        obs_env.create_path()?;
+        obs_env.clone_repositories().into_result()?;
+        let local = Repository::open(destination.join("ts_wep"))?;
+        let first = fixture_commit(&local, "Local work");
+        let second = fixture_commit(&local, "More local work");
+
+        let report = obs_env.reset_base_environment("main").unwrap();
+        let [backup] = &report.backups[..] else {
+            panic!("expected one backup, got {:?}", report.backups);
+        };
+        assert_eq!(backup.repo, "ts_wep");
+        assert!(backup.branch.starts_with("obs-env-backup/"));
+        assert_eq!(backup.commits, [second.to_string(), first.to_string()]);
+        assert_eq!(
+            local.revparse_single(&backup.branch)?.id(),
+            second,
+            "the local commits stay reachable"
+        );
+        assert_ne!(local.head()?.peel_to_commit()?.id(), second);
+        assert!(obs_env
+            .reset_base_environment("main")
+            .unwrap()
+            .backups
+            .is_empty());
+
+        let backups = obs_env.list_backups();
+        assert_eq!(
+            backups["ts_wep"].as_ref().unwrap(),
+            std::slice::from_ref(backup)
+        );
+        assert!(obs_env.drop_backups(Duration::from_secs(3600)).is_empty());
+        let dropped = obs_env.drop_backups(Duration::ZERO);
+        assert!(matches!(&dropped[..], [Ok(dropped)] if dropped == backup));
+        assert!(obs_env.list_backups()["ts_wep"]
+            .as_ref()
+            .unwrap()
+            .is_empty());
+
+        fixture_commit(&local, "Unwanted work");
+        let obs_env = ObservingEnvironment {
+            backup: false,
+            ..obs_env
+        };
+        assert!(obs_env
+            .reset_base_environment("main")
+            .unwrap()
+            .backups
+            .is_empty());
+        assert!(obs_env.list_backups()["ts_wep"]
+            .as_ref()
+            .unwrap()
+            .is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_metadata() -> TestResult {
+        let root = TempDir::new()?;
+        let destination = root.path().join("env");
+        let obs_env = fixture_environment(&destination, &root.path().join("remotes"), &["ts_wep"]);
+        obs_env.create_path()?;
+        assert_eq!(obs_env.read_metadata()?, None);
+        obs_env.clone_repositories().into_result()?;
+
+        let metadata = obs_env.write_metadata("setup")?;
+        assert_eq!(metadata.action, "setup");
+        assert_eq!(metadata.manifest.repos.len(), 1);
+        assert_eq!(metadata.manifest.repos[0].name, "ts_wep");
+        assert_eq!(obs_env.read_metadata()?, Some(metadata.clone()));
+        assert!(obs_env.modified_since(&metadata).is_empty());
+
+        let later = std::time::SystemTime::now() + Duration::from_secs(60);
+        std::fs::File::options()
+            .append(true)
+            .open(destination.join("ts_wep/.git/logs/HEAD"))?
+            .set_modified(later)?;
+        assert_eq!(obs_env.modified_since(&metadata), ["ts_wep"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lock_file() -> TestResult {
+        let root = TempDir::new()?;
+        let remotes = root.path().join("remotes");
+        let destination = root.path().join("env");
+        let remote = fixture_remote(&remotes.join("ts_wep"));
+        let obs_env = fixture_environment(&destination, &remotes, &["ts_wep"]);
+        obs_env.create_path()?;
+        obs_env.clone_repositories().into_result()?;
+        assert!(obs_env.lock_drift().is_err());
+
+        let lock_file = obs_env.write_lock_file()?;
+        let locked = lock_file.repos["ts_wep"].sha.clone();
+        assert_eq!(obs_env.read_lock_file()?, lock_file);
+        assert!(obs_env.lock_drift()?.is_empty());
+
+        let moved = fixture_commit(&remote, "Upstream work").to_string();
+        obs_env.reset_repository("ts_wep", &moved)?;
+        assert_eq!(
+            obs_env.lock_drift()?,
+            [Drift::Moved {
+                repo: "ts_wep".to_owned(),
+                locked: locked.clone(),
+                current: moved,
+            }]
+        );
+
+        let pinned = lock_file.pin(&destination.to_string_lossy(), None)?;
+        assert!(obs_env.apply_manifest(&pinned).is_ok());
+        assert!(obs_env.lock_drift()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_metrics() -> TestResult {
+        let root = TempDir::new()?;
+        let remotes = root.path().join("remotes");
+        let destination = root.path().join("env");
+        let remote = fixture_remote(&remotes.join("ts_wep"));
+        fixture_commit_file(&remote, "README", "ts_wep", "Add README");
+        let obs_env = fixture_environment(&destination, &remotes, &["ts_wep", "cwfs"]);
+        obs_env.create_path()?;
+        assert!(obs_env.clone_missing_repository("ts_wep").error().is_none());
+        fixture_commit(&remote, "Upstream work");
+        assert!(obs_env.fetch_repositories()["ts_wep"].is_ok());
+        std::fs::write(destination.join("ts_wep/README"), "changed")?;
+
+        let base_versions =
+            BTreeMap::from([("ts_wep".to_owned(), RepoVersion::new("ts_wep", "main"))]);
+        let metrics = obs_env.metrics(Some(&base_versions));
+        assert_eq!(
+            metrics.repos,
+            [RepoMetrics {
+                repo: "ts_wep".to_owned(),
+                dirty: true,
+                behind_origin: Some(1),
+                matches_base: Some(true),
+            }]
+        );
+        assert_eq!(metrics.last_reset, None);
+        assert_eq!(obs_env.metrics(None).repos[0].matches_base, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_reset_falls_back_to_default_branch() -> TestResult {
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        let cwfs_url = format!("{FAKE_ORG}/cwfs");
+        backend.set_branch(&cwfs_url, "master", "1111aaaa");
+        let wep_url = format!("{FAKE_ORG}/ts_wep");
+        backend.set_branch(&wep_url, "main", "2222bbbb");
+        backend.set_branch(&wep_url, "develop", "3333cccc");
+        let versions_file = root.path().join("versions.env");
+        std::fs::write(&versions_file, "cwfs=main\nts_wep=main\n")?;
+
+        let mut cwfs = RepoSpec::new("cwfs", &cwfs_url);
+        cwfs.default_branch = Some("master".to_owned());
+        let mut ts_wep = RepoSpec::new("ts_wep", &wep_url);
+        ts_wep.default_branch = Some("develop".to_owned());
+        let obs_env = ObservingEnvironment::builder()
+            .destination(&root.path().to_string_lossy())
+            .repository_specs(vec![cwfs, ts_wep], RepoSource::Custom)
+            .base_env_source(&versions_file.to_string_lossy())
+            .backend(backend.clone())
+            .build()?;
+        obs_env.clone_repositories().into_result()?;
+
+        let branches = obs_env.reset_base_environment("main").unwrap().branches;
+        assert_eq!(
+            branches,
+            BTreeMap::from([
+                ("cwfs".to_owned(), "master".to_owned()),
+                ("ts_wep".to_owned(), "main".to_owned()),
+            ])
+        );
+        assert_eq!(backend.head(root.path().join("cwfs")).unwrap(), "1111aaaa");
+        assert_eq!(
+            backend.head(root.path().join("ts_wep")).unwrap(),
+            "2222bbbb"
+        );
+
+        std::fs::write(&versions_file, "cwfs=develop\n")?;
+        assert!(matches!(
+            obs_env.reset_base_environment("main").unwrap_err()[..],
+            [ObsEnvError::RevisionNotFound { .. }]
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_summarize_with_fake_backend() -> TestResult {
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        backend.set_branch(&format!("{FAKE_ORG}/ts_wep"), "main", "1111aaaa");
+
+        let mut obs_env = fake_environment(root.path(), &backend, &["ts_wep", "ts_missing"]);
+        obs_env.set_fork("ts_missing", "tribeiro")?;
+        obs_env.clone_repositories();
+        let summary = obs_env.summarize();
+
+        assert_eq!(summary.env_path, root.path().to_string_lossy());
+        assert_eq!(summary.base_branch, "main");
+        assert_eq!(summary.repositories_source, "custom list");
+        assert_eq!(
+            summary.repos,
+            [
+                RepoSummary {
+                    name: "ts_missing".to_owned(),
+                    url: "https://example.com/tribeiro/ts_missing".to_owned(),
+                    present: false,
+                    version: None,
+                },
+                RepoSummary {
+                    name: "ts_wep".to_owned(),
+                    url: format!("{FAKE_ORG}/ts_wep"),
+                    present: true,
+                    version: Some("1111aaaa".to_owned()),
+                },
+            ]
+        );
+        assert_eq!(summary.forks["ts_missing"], "tribeiro");
+        assert_eq!(summary.object_store, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_setup_script() -> TestResult {
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        for repo_name in ["ts_wep", "ts_config_ocs", "cwfs"] {
+            backend.set_branch(&format!("{FAKE_ORG}/{repo_name}"), "main", "1111aaaa");
+        }
+
+        let obs_env = fake_environment(
+            root.path(),
+            &backend,
+            &["ts_wep", "ts_config_ocs", "ts_missing"],
+        );
+        obs_env.clone_repositories();
+        std::fs::create_dir_all(root.path().join("ts_wep").join("python"))?;
+        std::fs::create_dir_all(root.path().join("ts_wep").join("bin"))?;
+        let path = obs_env.write_setup_script()?;
+        let env = root.path().to_string_lossy();
+
+        assert_eq!(path, root.path().join("setup_obs_env.sh"));
+        assert_eq!(
+            std::fs::read_to_string(&path)?,
+            format!(
+                "# Generated by manage_obs_env, do not edit.\n# Versions:\n#   ts_config_ocs: 1111aaaa\n#   ts_wep: 1111aaaa\nexport PYTHONPATH=\"{env}/ts_wep/python${{PYTHONPATH:+:$PYTHONPATH}}\"\nexport PATH=\"{env}/ts_wep/bin${{PATH:+:$PATH}}\"\n"
+            )
+        );
+
+        let obs_env = fake_environment(root.path(), &backend, &["cwfs", "ts_config_ocs"]);
+        obs_env.clone_repositories();
+        std::fs::create_dir_all(root.path().join("cwfs").join("python"))?;
+        obs_env.write_setup_script()?;
+        assert_eq!(
+            std::fs::read_to_string(&path)?,
+            format!(
+                "# Generated by manage_obs_env, do not edit.\n# Versions:\n#   cwfs: 1111aaaa\n#   ts_config_ocs: 1111aaaa\nexport PYTHONPATH=\"{env}/cwfs/python${{PYTHONPATH:+:$PYTHONPATH}}\"\n"
+            )
+        );
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_declare_eups() -> TestResult {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        for repo_name in ["ts_wep", "ts_bad"] {
+            backend.set_branch(&format!("{FAKE_ORG}/{repo_name}"), "main", "1111aaaa");
+        }
+        let log = root.path().join("eups.log");
+        let script = root.path().join("eups");
+        std::fs::write(
+            &script,
+            format!(
+                "#!/bin/sh\necho \"$@\" >> {}\ncase \"$*\" in undeclare*|*ts_bad*) echo failed >&2; exit 1;; esac\n",
+                log.display()
+            ),
+        )?;
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755))?;
+
+        let env = root.path().join("env");
+        let obs_env = ObservingEnvironment {
+            eups: Some(Eups::new("current").command(&script.to_string_lossy())),
+            ..fake_environment(&env, &backend, &["ts_wep", "ts_bad", "ts_missing"])
+        };
+        obs_env.clone_repositories();
+        let declared = obs_env.declare_eups(["ts_wep", "ts_bad", "ts_missing"]);
+
+        assert_eq!(declared["ts_wep"].as_deref().unwrap(), "1111aaaa");
+        assert!(matches!(
+            &declared["ts_bad"],
+            Err(ObsEnvError::CommandFailed { message, .. }) if message == "failed"
+        ));
+        assert!(matches!(
+            declared["ts_missing"],
+            Err(ObsEnvError::RepoNotCloned { .. })
+        ));
+        assert_eq!(
+            std::fs::read_to_string(&log)?,
+            format!(
+                "undeclare --force ts_wep 1111aaaa\ndeclare -r {0}/ts_wep ts_wep 1111aaaa -t current\nundeclare --force ts_bad 1111aaaa\ndeclare -r {0}/ts_bad ts_bad 1111aaaa -t current\n",
+                env.display()
+            )
+        );
+        assert!(fake_environment(&env, &backend, &["ts_wep"])
+            .declare_eups(["ts_wep"])
+            .is_empty());
+
+        // Without eups, nothing is declared, rather than failing each.
+        let obs_env = ObservingEnvironment {
+            eups: Some(Eups::new("current").command(&root.path().join("none").to_string_lossy())),
+            ..fake_environment(&env, &backend, &["ts_wep"])
+        };
+        assert!(obs_env.declare_eups(["ts_wep"]).is_empty());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_develop_install() -> TestResult {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        for repo_name in ["ts_wep", "ts_bad", "ts_config_ocs"] {
+            backend.set_branch(&format!("{FAKE_ORG}/{repo_name}"), "main", "1111aaaa");
+        }
+        let log = root.path().join("pip.log");
+        let python = root.path().join("python");
+        std::fs::write(
+            &python,
+            format!(
+                "#!/bin/sh\necho \"$@\" >> {}\ncase \"$*\" in *ts_bad*) echo no matching distribution; exit 1;; esac\n",
+                log.display()
+            ),
+        )?;
+        std::fs::set_permissions(&python, std::fs::Permissions::from_mode(0o755))?;
+
+        let env = root.path().join("env");
+        let repo_names = ["ts_wep", "ts_bad", "ts_config_ocs", "ts_missing"];
+        let obs_env = ObservingEnvironment {
+            pip_install: Some(PipInstall::new(&python.to_string_lossy())),
+            ..fake_environment(&env, &backend, &repo_names)
+        };
+        obs_env.clone_repositories();
+        std::fs::create_dir_all(env.join("ts_wep"))?;
+        std::fs::write(env.join("ts_wep").join("pyproject.toml"), "")?;
+        std::fs::create_dir_all(env.join("ts_bad"))?;
+        std::fs::write(env.join("ts_bad").join("setup.py"), "")?;
+        let installed = obs_env.develop_install(repo_names);
+
+        assert_eq!(
+            installed.keys().collect::<Vec<_>>(),
+            ["ts_bad", "ts_missing", "ts_wep"]
+        );
+        assert!(installed["ts_wep"].is_ok());
+        assert!(matches!(
+            &installed["ts_bad"],
+            Err(ObsEnvError::CommandFailed { message, .. }) if message == "no matching distribution"
+        ));
+        assert!(matches!(
+            installed["ts_missing"],
+            Err(ObsEnvError::RepoNotCloned { .. })
+        ));
+        assert_eq!(
+            std::fs::read_to_string(&log)?,
+            format!(
+                "-m pip install -e {0}/ts_wep\n-m pip install -e {0}/ts_bad\n",
+                env.display()
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_manifest() -> TestResult {
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        for repo_name in ["ts_wep", "ts_observatory_control"] {
+            let url = format!("{FAKE_ORG}/{repo_name}");
+            backend.set_branch(&url, "main", "1111aaaa");
+            backend.set_tag(&url, "v1.2.0", "2222bbbb");
+        }
+        backend.set_branch(&format!("{FAKE_ORG}/ts_wep"), "develop", "3333cccc");
+
+        let obs_env = fake_environment(
+            root.path(),
+            &backend,
+            &["ts_wep", "ts_observatory_control", "ts_missing"],
+        );
+        obs_env.clone_repositories();
+        let mut ts_wep = RepoVersion::new("ts_wep", "v1.2.0-1-g3333ccc");
+        ts_wep.sha = Some("3333cccc".to_owned());
+        let manifest = EnvironmentManifest::new(
+            &root.path().to_string_lossy(),
+            vec![
+                ts_wep,
+                RepoVersion::new("ts_observatory_control", "1.2.0"),
+                RepoVersion::new("ts_missing", "1.0.0"),
+            ],
+        );
+
+        let errors = obs_env.apply_manifest(&manifest).unwrap_err();
+        assert!(matches!(
+            errors.as_slice(),
+            [ObsEnvError::RepoNotCloned { repo, .. }] if repo == "ts_missing"
+        ));
+        assert_eq!(
+            backend.head(root.path().join("ts_wep")).as_deref(),
+            Some("3333cccc")
+        );
+        assert_eq!(
+            backend
+                .head(root.path().join("ts_observatory_control"))
+                .as_deref(),
+            Some("2222bbbb")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot() -> TestResult {
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        let ts_wep_url = format!("{FAKE_ORG}/ts_wep");
+        backend.set_branch(&ts_wep_url, "main", "1111aaaa");
+        backend.set_branch(&ts_wep_url, "develop", "3333cccc");
+        backend.set_branch(&format!("{FAKE_ORG}/ts_xml"), "main", "2222bbbb");
+        let obs_env = fake_environment(root.path(), &backend, &["ts_wep", "ts_xml"]);
+        obs_env.clone_repositories();
+        assert!(obs_env.snapshots()?.is_empty());
+        assert!(matches!(
+            obs_env.load_snapshot(None),
+            Err(ObsEnvError::InvalidConfig { .. })
+        ));
+
+        let snapshot = obs_env.snapshot(Some("before"))?;
+        assert_eq!(snapshot.path, obs_env.snapshots_dir().join("before.toml"));
+        assert_eq!(snapshot.dirty().count(), 0);
+        obs_env.checkout_branch("ts_wep", "develop")?;
+        assert_eq!(
+            backend.head(root.path().join("ts_wep")).as_deref(),
+            Some("3333cccc")
+        );
+
+        let restored = obs_env.load_snapshot(None)?;
+        assert_eq!(restored, snapshot);
+        obs_env
+            .apply_manifest(&restored.manifest)
+            .map_err(|mut errors| errors.remove(0))?;
+        assert_eq!(
+            backend.head(root.path().join("ts_wep")).as_deref(),
+            Some("1111aaaa")
+        );
+
+        backend.set_dirty(root.path().join("ts_xml"));
+        let dirty = obs_env.snapshot(None)?;
+        assert_eq!(dirty.dirty().collect::<Vec<_>>(), ["ts_xml"]);
+        assert_eq!(obs_env.snapshots()?, [dirty.name.as_str(), "before"]);
+        for name in ["before", "../before"] {
+            assert!(matches!(
+                obs_env.snapshot(Some(name)),
+                Err(ObsEnvError::InvalidConfig { .. })
+            ));
+        }
+        assert!(matches!(
+            obs_env.load_snapshot(Some("after")),
+            Err(ObsEnvError::InvalidConfig { .. })
+        ));
+        Ok(())
     }
 
     #[test]
-    fn expand_version_to_tag() {
+    fn test_setup_from_manifest() -> TestResult {
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        let ts_wep_url = format!("{FAKE_ORG}/ts_wep");
+        backend.set_branch(&ts_wep_url, "main", "1111aaaa");
+        backend.set_branch(&ts_wep_url, "develop", "3333cccc");
+        backend.set_branch(&format!("{FAKE_ORG}/ts_xml"), "main", "2222bbbb");
+        backend.set_branch(&format!("{FAKE_ORG}/ts_extra"), "main", "4444dddd");
+        let mut ts_wep = RepoVersion::new("ts_wep", "v1.2.0-1-g3333ccc");
+        ts_wep.branch = Some("develop".to_owned());
+        ts_wep.sha = Some("3333cccc".to_owned());
+        let mut ts_xml = RepoVersion::new("ts_xml", "v2.0.0");
+        ts_xml.sha = Some("5555eeee".to_owned());
+        let mut ts_extra = RepoVersion::new("ts_extra", "v0.1.0");
+        ts_extra.url = Some(format!("{FAKE_ORG}/ts_extra"));
+        ts_extra.sha = Some("4444dddd".to_owned());
+        let manifest =
+            EnvironmentManifest::new("/obs-env", vec![ts_wep.clone(), ts_xml, ts_extra.clone()]);
+
+        let obs_env = ObservingEnvironment::builder()
+            .destination(&root.path().to_string_lossy())
+            .repositories(["ts_wep", "ts_xml"].map(|name| (name, FAKE_ORG)))
+            .manifest_repositories(&manifest)
+            .backend(backend.clone())
+            .build()?;
+        let report = obs_env.clone_repositories_at(&manifest);
+        let outcomes: Vec<(&str, bool)> = report
+            .repos
+            .iter()
+            .map(|repo| (repo.name.as_str(), repo.error().is_none()))
+            .collect();
         assert_eq!(
-            ObservingEnvironment::expand_version_to_tag("1.0.0"),
-            "v1.0.0"
+            outcomes,
+            [("ts_extra", true), ("ts_wep", true), ("ts_xml", false)]
         );
+        let ts_wep_path = root.path().join("ts_wep");
+        assert_eq!(backend.head(&ts_wep_path).as_deref(), Some("3333cccc"));
         assert_eq!(
-            ObservingEnvironment::expand_version_to_tag("1.0.0a1"),
-            "v1.0.0.alpha.1"
+            git_backend::GitBackend::list_refs(&backend, &ts_wep_path, "refs/remotes/*")?,
+            ["refs/remotes/origin/develop"]
         );
         assert_eq!(
-            ObservingEnvironment::expand_version_to_tag("1.0.0b1"),
-            "v1.0.0.beta.1"
+            backend.head(root.path().join("ts_extra")).as_deref(),
+            Some("4444dddd")
         );
+
+        ts_wep.sha = Some("1111aaaa".to_owned());
+        let manifest = EnvironmentManifest::new("/obs-env", vec![ts_wep]);
+        let report = obs_env.clone_repositories_at(&manifest);
+        assert!(matches!(
+            report.repos[1].error(),
+            Some(ObsEnvError::VerificationFailed { problems, .. })
+                if problems == &["ts_wep is at 3333cccc, not 1111aaaa"]
+        ));
+
+        ts_extra.url = None;
+        let manifest = EnvironmentManifest::new("/obs-env", vec![ts_extra]);
+        assert!(matches!(
+            ObservingEnvironment::builder()
+                .repositories([("ts_wep", FAKE_ORG)])
+                .manifest_repositories(&manifest)
+                .build(),
+            Err(ObsEnvError::InvalidConfig { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_repo_handles_with_fake_backend() -> TestResult {
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        backend.set_branch(&format!("{FAKE_ORG}/ts_wep"), "main", "1111aaaa");
+
+        let mut obs_env = fake_environment(root.path(), &backend, &["ts_wep", "ts_missing"]);
+        obs_env.set_fork("ts_missing", "tribeiro")?;
+        let handles: Vec<(String, String, PathBuf, bool)> = obs_env
+            .repos()
+            .map(|repo| {
+                (
+                    repo.name().to_owned(),
+                    repo.url(),
+                    repo.path().to_path_buf(),
+                    repo.exists(),
+                )
+            })
+            .collect();
         assert_eq!(
-            ObservingEnvironment::expand_version_to_tag("1.0.0rc1"),
-            "v1.0.0.rc.1"
+            handles,
+            [
+                (
+                    "ts_missing".to_owned(),
+                    "https://example.com/tribeiro/ts_missing".to_owned(),
+                    root.path().join("ts_missing"),
+                    false
+                ),
+                (
+                    "ts_wep".to_owned(),
+                    format!("{FAKE_ORG}/ts_wep"),
+                    root.path().join("ts_wep"),
+                    false
+                ),
+            ]
         );
+
+        obs_env.clone_repositories();
+        let ts_wep = obs_env.repo("ts_wep")?;
+        assert!(ts_wep.exists());
+        assert_eq!(ts_wep.open()?, root.path().join("ts_wep"));
+        assert!(!ts_wep.is_dirty()?);
+        backend.set_dirty(root.path().join("ts_wep"));
+        assert!(ts_wep.is_dirty()?);
+        assert!(ts_wep.version()?.dirty);
+
+        let ts_missing = obs_env.repo("ts_missing")?;
+        assert!(matches!(
+            ts_missing.is_dirty(),
+            Err(ObsEnvError::RepoNotCloned { repo, .. }) if repo == "ts_missing"
+        ));
+        assert!(matches!(
+            obs_env.get_current_env_versions()["ts_missing"],
+            Err(ObsEnvError::RepoNotCloned { .. })
+        ));
+        for result in [
+            obs_env.repo("not_a_repo").map(|_| ()),
+            obs_env.reset_index_to_version("not_a_repo", "1.0.0"),
+        ] {
+            assert!(matches!(result, Err(ObsEnvError::RepoNotFound { .. })));
+        }
+        Ok(())
     }
 
     #[test]
-    fn test_update_base_env_source() {
-        let _shared = REPO_ACCESS.lock().unwrap();
+    fn test_with_destination_and_repos() -> TestResult {
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        for repo_name in ["ts_wep", "cwfs"] {
+            backend.set_branch(&format!("{FAKE_ORG}/{repo_name}"), "main", "1111aaaa");
+        }
 
-        let obs_env = ObservingEnvironment::with_destination(".");
+        let obs_env = ObservingEnvironment {
+            backend: Box::new(backend.clone()),
+            ..ObservingEnvironment::with_destination_and_repos(
+                &root.path().to_string_lossy(),
+                vec![RepoSpec::new("ts_wep", &format!("{FAKE_ORG}/ts_wep"))],
+            )?
+        };
+        assert_eq!(obs_env.clone_repositories().repos.len(), 1);
+        assert!(backend.head(root.path().join("ts_wep")).is_some());
+        assert!(backend.head(root.path().join("cwfs")).is_none());
+        assert_eq!(
+            obs_env
+                .get_current_env_versions()
+                .keys()
+                .collect::<Vec<_>>(),
+            ["ts_wep"]
+        );
 
-        obs_env.update_base_env_source("main").unwrap();
+        let versions_file = root.path().join("versions.env");
+        std::fs::write(&versions_file, "ts_wep=1.2.3\ncwfs=0.3.1\n")?;
+        let obs_env = ObservingEnvironment {
+            base_env_local_source: Some(versions_file.to_string_lossy().to_string()),
+            ..obs_env
+        };
+        assert_eq!(
+            obs_env
+                .get_base_env_versions("main")?
+                .keys()
+                .collect::<Vec<_>>(),
+            ["ts_wep"]
+        );
+
+        assert!(matches!(
+            ObservingEnvironment::with_destination_and_repos("/obs-env", vec![]),
+            Err(ObsEnvError::InvalidConfig { .. })
+        ));
+        Ok(())
+    }
+
+    /// Observer recording the events it receives.
+    #[derive(Default)]
+    struct RecordingObserver(Mutex<Vec<String>>);
+
+    impl ObsEnvObserver for RecordingObserver {
+        fn on_repo_start(&self, repo: &str, operation: &str) {
+            self.0.lock().unwrap().push(format!("{operation} {repo}"));
+        }
+
+        fn on_transfer_progress(&self, repo: &str, progress: &TransferProgress) {
+            self.0.lock().unwrap().push(format!(
+                "{repo} {}/{}",
+                progress.received_objects, progress.total_objects
+            ));
+        }
 
-        assert!(Path::new(&obs_env.destination)
-            .join(obs_env.base_env_source_repo)
-            .exists())
+        fn on_repo_done(&self, repo: &str) {
+            self.0.lock().unwrap().push(format!("{repo} done"));
+        }
+
+        fn on_repo_failed(&self, repo: &str, _error: &ObsEnvError) {
+            self.0.lock().unwrap().push(format!("{repo} failed"));
+        }
     }
 
     #[test]
-    fn test_get_base_env_versions() {
-        let _shared = REPO_ACCESS.lock().unwrap();
-        let obs_env = ObservingEnvironment::with_destination(".");
+    fn test_observer_events() -> TestResult {
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        backend.set_branch(&format!("{FAKE_ORG}/ts_wep"), "main", "1111aaaa");
+        backend.set_tag(&format!("{FAKE_ORG}/ts_wep"), "v1.2.0", "2222bbbb");
+        let versions_file = root.path().join("versions.env");
+        std::fs::write(&versions_file, "ts_wep=1.2.0\nts_missing=1.0.0\n")?;
 
-        let base_env_versions = obs_env.get_base_env_versions("main").unwrap();
+        let observer = Arc::new(RecordingObserver::default());
+        let obs_env = ObservingEnvironment::builder()
+            .destination(&root.path().to_string_lossy())
+            .repositories(["ts_missing", "ts_wep"].map(|name| (name, FAKE_ORG)))
+            .base_env_source(&versions_file.to_string_lossy())
+            .backend(backend.clone())
+            .observer(Arc::clone(&observer))
+            .build()?;
 
-        for (repo, _) in obs_env.repositories {
-            assert!(base_env_versions.contains_key(&repo))
+        obs_env.clone_repositories();
+        assert!(obs_env.reset_base_environment("main").is_err());
+        assert_eq!(
+            *observer.0.lock().unwrap(),
+            [
+                "clone ts_missing",
+                "ts_missing failed",
+                "clone ts_wep",
+                "ts_wep 3/3",
+                "ts_wep done",
+                "reset ts_missing",
+                "ts_missing failed",
+                "reset ts_wep",
+                "ts_wep 3/3",
+                "ts_wep done",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_builder_validates_repo_specs() {
+        for repo_specs in [
+            vec![],
+            vec![RepoSpec::new("", "https://github.com/lsst-ts/ts_wep")],
+            vec![RepoSpec::new("ts_wep", "github.com/lsst-ts/ts_wep")],
+            vec![
+                RepoSpec::new("ts_wep", "https://github.com/lsst-ts/ts_wep"),
+                RepoSpec::new("ts_wep", "git@github.com:tribeiro/ts_wep.git"),
+            ],
+        ] {
+            assert!(matches!(
+                ObservingEnvironment::builder()
+                    .repository_specs(repo_specs, RepoSource::Custom)
+                    .build(),
+                Err(ObsEnvError::InvalidConfig { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_reset_with_fake_backend() -> TestResult {
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        let base_env_url = format!("{FAKE_ORG}/ts_cycle_build");
+        backend.set_branch(&base_env_url, "main", "cycle0001");
+        backend.set_file(
+            "cycle0001",
+            "cycle/cycle.env",
+            "ts_wep=1.2.0\nts_observatory_control=0.3.0\n",
+        );
+        for repo_name in ["ts_wep", "ts_observatory_control"] {
+            backend.set_branch(&format!("{FAKE_ORG}/{repo_name}"), "main", "3333cccc");
+        }
+        backend.set_tag(&format!("{FAKE_ORG}/ts_wep"), "v1.2.0", "1111aaaa");
+
+        let obs_env =
+            fake_environment(root.path(), &backend, &["ts_observatory_control", "ts_wep"]);
+        obs_env.clone_repositories().into_result()?;
+        backend.set_in_progress(root.path().join("ts_observatory_control"), "merge");
+
+        let errors = obs_env.reset_base_environment("main").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(
+            &errors[0],
+            ObsEnvError::RepoBusy { repo, .. } if repo == "ts_observatory_control"
+        ));
+        assert_eq!(
+            backend.head(root.path().join("ts_wep")).unwrap(),
+            "1111aaaa"
+        );
+        assert_eq!(
+            obs_env.get_current_env_versions()["ts_wep"]
+                .as_ref()
+                .unwrap(),
+            &RepoVersion {
+                name: "ts_wep".to_owned(),
+                url: Some(format!("{FAKE_ORG}/ts_wep")),
+                branch: None,
+                sha: Some("1111aaaa".to_owned()),
+                describe: "v1.2.0".to_owned(),
+                dirty: false,
+                ahead_behind: None,
+                ..RepoVersion::default()
+            }
+        );
+        let manifest = obs_env.get_manifest();
+        assert_eq!(manifest.repos.len(), 2);
+        assert_eq!(
+            manifest.to_string(),
+            format!(
+                "Obs. Env. Path: {}.\nts_observatory_control: 3333cccc\nts_wep: v1.2.0",
+                root.path().display()
+            )
+        );
+
+        backend.set_tag(
+            &format!("{FAKE_ORG}/ts_observatory_control"),
+            "v0.3.0",
+            "4444dddd",
+        );
+        let obs_env = ObservingEnvironment {
+            abort_in_progress: true,
+            ..obs_env
+        };
+        assert!(obs_env.reset_base_environment("main").is_ok());
+        assert_eq!(
+            backend
+                .head(root.path().join("ts_observatory_control"))
+                .unwrap(),
+            "4444dddd"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_fail_fast() -> TestResult {
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        let base_env_url = format!("{FAKE_ORG}/ts_cycle_build");
+        backend.set_branch(&base_env_url, "main", "cycle0001");
+        backend.set_file("cycle0001", "cycle/cycle.env", "cwfs=0.3.0\nts_wep=1.2.0\n");
+        for repo_name in ["cwfs", "ts_wep"] {
+            backend.set_branch(&format!("{FAKE_ORG}/{repo_name}"), "main", "3333cccc");
+        }
+        backend.set_tag(&format!("{FAKE_ORG}/ts_wep"), "v1.2.0", "1111aaaa");
+
+        let obs_env = ObservingEnvironment {
+            on_error: ErrorPolicy::FailFast,
+            jobs: 1,
+            ..fake_environment(root.path(), &backend, &["cwfs", "ts_missing", "ts_wep"])
+        };
+        let report = obs_env.clone_repositories();
+        assert!(report.repos[0].error().is_none());
+        assert!(report.repos[1].error().is_some());
+        assert!(matches!(
+            report.repos[2].error(),
+            Some(ObsEnvError::NotAttempted { repo, .. }) if repo == "ts_wep"
+        ));
+        assert!(backend.head(root.path().join("ts_wep")).is_none());
+
+        let obs_env = ObservingEnvironment {
+            on_error: ErrorPolicy::KeepGoing,
+            ..obs_env
+        };
+        let report = obs_env.clone_repositories();
+        assert_eq!(report.failures().count(), 1);
+        assert!(backend.head(root.path().join("ts_wep")).is_some());
+
+        // cwfs has no v0.3.0 tag, so ts_wep is not reset after it.
+        let obs_env = ObservingEnvironment {
+            on_error: ErrorPolicy::FailFast,
+            ..obs_env
+        };
+        let errors = obs_env.reset_base_environment("main").unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(&errors[0], ObsEnvError::RevisionNotFound { .. }));
+        assert!(matches!(
+            &errors[1],
+            ObsEnvError::NotAttempted { repo, operation } if repo == "ts_wep" && operation == "reset"
+        ));
+        assert_eq!(
+            backend.head(root.path().join("ts_wep")).unwrap(),
+            "3333cccc"
+        );
+
+        let obs_env = ObservingEnvironment {
+            on_error: ErrorPolicy::KeepGoing,
+            ..obs_env
+        };
+        assert_eq!(obs_env.reset_base_environment("main").unwrap_err().len(), 1);
+        assert_eq!(
+            backend.head(root.path().join("ts_wep")).unwrap(),
+            "1111aaaa"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_base_deviations() -> TestResult {
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        let base_env_url = format!("{FAKE_ORG}/ts_cycle_build");
+        backend.set_branch(&base_env_url, "main", "cycle0001");
+        backend.set_file(
+            "cycle0001",
+            "cycle/cycle.env",
+            "cwfs=0.3.0\nts_wep=1.2.0\nts_xml=2.0.0\n",
+        );
+        for repo_name in ["cwfs", "ts_wep", "ts_extra"] {
+            backend.set_branch(&format!("{FAKE_ORG}/{repo_name}"), "main", "3333cccc");
+        }
+        backend.set_tag(&format!("{FAKE_ORG}/cwfs"), "v0.3.0", "1111aaaa");
+        backend.set_tag(&format!("{FAKE_ORG}/ts_wep"), "v1.2.0", "2222bbbb");
+        let obs_env = fake_environment(
+            root.path(),
+            &backend,
+            &["cwfs", "ts_extra", "ts_wep", "ts_xml"],
+        );
+        obs_env.clone_repositories();
+        let _ = obs_env.reset_base_environment("main");
+        assert!(obs_env.checkout_branch("ts_wep", "main").is_ok());
+
+        let deviations = obs_env.base_deviations()?;
+        let lines: Vec<String> = deviations
+            .values()
+            .map(|deviation| {
+                deviation
+                    .as_ref()
+                    .map_or_else(ToString::to_string, ToString::to_string)
+            })
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "ts_wep: main at 3333cccc, +1/-1 from base 1.2.0",
+                "ts_xml: not cloned, base 2.0.0",
+            ]
+        );
+
+        backend.set_dirty(root.path().join("cwfs"));
+        assert!(obs_env.base_deviations()?["cwfs"]
+            .as_ref()
+            .is_ok_and(|deviation| deviation
+                .current
+                .as_ref()
+                .is_some_and(|current| current.dirty)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_checkout_branch_preflight() -> TestResult {
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        let url = format!("{FAKE_ORG}/ts_wep");
+        backend.set_branch(&url, "main", "1111aaaa");
+        backend.set_branch(&url, "develop", "2222bbbb");
+        backend.set_branch(&url, "tickets/DM-12345", "3333cccc");
+        let obs_env = fake_environment(root.path(), &backend, &["ts_wep"]);
+        obs_env.clone_repositories().into_result()?;
+
+        let error = obs_env.checkout_branch("ts_wep", "devlop").unwrap_err();
+        assert!(matches!(
+            &error,
+            ObsEnvError::BranchNotFound { closest, .. } if closest == &["develop"]
+        ));
+        assert!(error.to_string().ends_with(", did you mean develop?"));
+        assert_eq!(
+            backend.head(root.path().join("ts_wep")).unwrap(),
+            "1111aaaa"
+        );
+
+        assert_eq!(
+            obs_env.checkout_branch("ts_wep", "DM-12345")?,
+            "tickets/DM-12345"
+        );
+        assert_eq!(
+            backend.head(root.path().join("ts_wep")).unwrap(),
+            "3333cccc"
+        );
+
+        let branches =
+            ["develop", "main", "tickets/DM-12345", "tickets/DM-12346"].map(String::from);
+        assert_eq!(
+            super::closest_names("DM-1234", &branches),
+            ["tickets/DM-12345", "tickets/DM-12346"]
+        );
+        assert!(super::closest_names("release", &branches).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_deepen_to_version() -> TestResult {
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        let url = format!("{FAKE_ORG}/ts_wep");
+        backend.set_branch(&url, "main", "3333cccc");
+        backend.set_history(&url, "1111aaaa", 500);
+
+        let obs_env = ObservingEnvironment {
+            clone_depth: Some(1),
+            ..fake_environment(root.path(), &backend, &["ts_wep"])
+        };
+        obs_env.clone_repositories().into_result()?;
+        obs_env.reset_index_to_version("ts_wep", "1111aaaa")?;
+        assert_eq!(
+            backend.head(root.path().join("ts_wep")).as_deref(),
+            Some("1111aaaa")
+        );
+
+        assert!(matches!(
+            obs_env.reset_index_to_version("ts_wep", "2222bbbb"),
+            Err(ObsEnvError::NotOnOrigin { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_clone_options() -> TestResult {
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        let repo_specs: Vec<RepoSpec> = ["cwfs", "ts_config_ocs", "ts_wep"]
+            .iter()
+            .map(|repo_name| {
+                let url = format!("{FAKE_ORG}/{repo_name}");
+                backend.set_branch(&url, "main", "1111aaaa");
+                RepoSpec::new(repo_name, &url)
+            })
+            .collect();
+        let obs_env = ObservingEnvironment::builder()
+            .destination(&root.path().to_string_lossy())
+            .repository_specs(repo_specs, RepoSource::Custom)
+            .repo_override(
+                "cwfs",
+                RepoOverride {
+                    clone_depth: Some(0),
+                    ..RepoOverride::default()
+                },
+            )
+            .repo_override(
+                "ts_config_ocs",
+                RepoOverride {
+                    clone_filter: Some(CloneFilter::Treeless),
+                    ..RepoOverride::default()
+                },
+            )
+            .clone_depth(10)
+            .clone_filter(CloneFilter::Blobless)
+            .backend(backend.clone())
+            .build()?;
+        obs_env.clone_repositories().into_result()?;
+
+        let options = |repo_name| backend.clone_options(root.path().join(repo_name));
+        let expected = |depth, filter| {
+            Some(CloneOptions {
+                depth,
+                filter: Some(filter),
+            })
+        };
+        assert_eq!(options("cwfs"), expected(None, CloneFilter::Blobless));
+        assert_eq!(
+            options("ts_config_ocs"),
+            expected(Some(10), CloneFilter::Treeless)
+        );
+        assert_eq!(options("ts_wep"), expected(Some(10), CloneFilter::Blobless));
+        Ok(())
+    }
+
+    #[test]
+    fn test_mirror() -> TestResult {
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        for repo_name in ["cwfs", "ts_wep"] {
+            backend.set_branch(&format!("{FAKE_ORG}/{repo_name}"), "main", "3333cccc");
+        }
+        backend.set_tag(&format!("{FAKE_ORG}/ts_wep"), "v1.2.0", "3333cccc");
+        backend.set_tag(&format!("{FAKE_ORG}/ts_wep"), "v1.1.0", "1111aaaa");
+        // cwfs has no mirror.
+        backend.set_branch("backup/ts_wep", "main", "1111aaaa");
+
+        let obs_env = fake_environment(root.path(), &backend, &["cwfs", "ts_wep"]);
+        obs_env.clone_repositories().into_result()?;
+        let mirrors = obs_env.mirror("backup/{repo}", "deployed/env");
+        assert!(mirrors["cwfs"].is_err());
+        let mirror = mirrors["ts_wep"].as_ref().unwrap();
+        assert_eq!(mirror.url, "backup/ts_wep");
+        assert_eq!(
+            backend.remote_refs("backup/ts_wep"),
+            BTreeMap::from([
+                (
+                    "refs/heads/deployed/env/main".to_owned(),
+                    "3333cccc".to_owned()
+                ),
+                ("refs/heads/main".to_owned(), "1111aaaa".to_owned()),
+                (
+                    "refs/tags/deployed/env/v1.2.0".to_owned(),
+                    "3333cccc".to_owned()
+                ),
+            ])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_outdated() -> TestResult {
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        for repo_name in ["cwfs", "ts_wep"] {
+            backend.set_branch(&format!("{FAKE_ORG}/{repo_name}"), "main", "1111aaaa");
+        }
+        let obs_env = fake_environment(root.path(), &backend, &["cwfs", "ts_wep"]);
+        obs_env.clone_repositories().into_result()?;
+        backend.set_branch(&format!("{FAKE_ORG}/ts_wep"), "main", "3333cccc");
+        backend.set_commit_time("3333cccc", 1_760_400_000);
+
+        let outdated = obs_env.outdated();
+        let cwfs = outdated["cwfs"].as_ref().unwrap();
+        assert_eq!((cwfs.behind, cwfs.behind_since), (0, None));
+        assert_eq!(
+            outdated["ts_wep"].as_ref().unwrap(),
+            &RepoStaleness {
+                repo: "ts_wep".to_owned(),
+                default_branch: "main".to_owned(),
+                behind: 1,
+                behind_since: Some(1_760_400_000),
+                newest_unpulled: Some(1_760_400_000),
+            }
+        );
+        // The comparison only fetches, HEAD stays where it was.
+        assert_eq!(
+            backend.head(root.path().join("ts_wep")).unwrap(),
+            "1111aaaa"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_clone_env() -> TestResult {
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        for repo_name in ["cwfs", "ts_wep"] {
+            backend.set_branch(&format!("{FAKE_ORG}/{repo_name}"), "main", "1111aaaa");
+        }
+        backend.set_branch(&format!("{FAKE_ORG}/ts_wep"), "develop", "2222bbbb");
+        let source_path = root.path().join("source");
+        let source = fake_environment(&source_path, &backend, &["cwfs", "ts_wep"]);
+        source.clone_repositories().into_result()?;
+        git_backend::GitBackend::checkout_branch(&backend, &source_path.join("ts_wep"), "develop")?;
+        git_backend::GitBackend::reset(&backend, &source_path.join("cwfs"), "1111aaaa", None)?;
+        backend.set_dirty(source_path.join("cwfs"));
+
+        let copy_path = root.path().join("copy");
+        let obs_env = fake_environment(&copy_path, &backend, &["cwfs", "ts_wep"]);
+        assert!(matches!(
+            obs_env.clone_env(&copy_path, false),
+            Err(ObsEnvError::InvalidConfig { .. })
+        ));
+        let cloned = obs_env.clone_env(&source_path, false)?;
+        assert_eq!(
+            cloned["ts_wep"].as_ref().unwrap(),
+            &ClonedRepo {
+                repo: "ts_wep".to_owned(),
+                branch: Some("develop".to_owned()),
+                commit: "2222bbbb".to_owned(),
+                local_changes: LocalChanges::None,
+            }
+        );
+        let cwfs = cloned["cwfs"].as_ref().unwrap();
+        assert_eq!(
+            (cwfs.branch.as_deref(), cwfs.local_changes),
+            (None, LocalChanges::Skipped)
+        );
+        assert_eq!(backend.branch(copy_path.join("cwfs")), None);
+        assert_eq!(
+            backend.head(copy_path.join("cwfs")).as_deref(),
+            Some("1111aaaa")
+        );
+        assert_eq!(
+            backend.branch(copy_path.join("ts_wep")).as_deref(),
+            Some("develop")
+        );
+        assert!(!obs_env.repo("cwfs")?.is_dirty()?);
+        // The copy is of the source, not of origin.
+        assert_eq!(
+            git_backend::GitBackend::remote_url(&backend, &copy_path.join("ts_wep"))?,
+            git_backend::GitBackend::remote_url(&backend, &source_path.join("ts_wep"))?
+        );
+        obs_env.verify()?;
+
+        let copy_path = root.path().join("copy_with_changes");
+        let obs_env = fake_environment(&copy_path, &backend, &["cwfs", "ts_wep"]);
+        let cloned = obs_env.clone_env(&source_path, true)?;
+        assert_eq!(
+            cloned["cwfs"].as_ref().unwrap().local_changes,
+            LocalChanges::Copied
+        );
+        assert!(obs_env.repo("cwfs")?.is_dirty()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_clone_env_from_local_clones() -> TestResult {
+        let root = TempDir::new()?;
+        let remotes = root.path().join("remotes");
+        let cwfs_remote = fixture_remote(&remotes.join("cwfs"));
+        let first = fixture_commit_file(&cwfs_remote, "README.md", "cwfs\n", "Add README");
+        fixture_commit(&cwfs_remote, "Second commit");
+        let ts_wep_remote = fixture_remote(&remotes.join("ts_wep"));
+        let develop = fixture_commit(&ts_wep_remote, "Develop commit");
+        ts_wep_remote.branch("develop", &ts_wep_remote.find_commit(develop)?, false)?;
+
+        let source_path = root.path().join("source");
+        let source = fixture_environment(&source_path, &remotes, &["cwfs", "ts_wep"]);
+        source.create_path()?;
+        source.clone_repositories().into_result()?;
+        source.checkout_branch("ts_wep", "develop")?;
+        let cwfs_source = Repository::open(source_path.join("cwfs"))?;
+        cwfs_source.set_head_detached(first)?;
+        cwfs_source.checkout_head(Some(git2::build::CheckoutBuilder::new().force()))?;
+        std::fs::write(source_path.join("cwfs/README.md"), "cwfs changed\n")?;
+
+        let copy_path = root.path().join("copy");
+        let obs_env = fixture_environment(&copy_path, &remotes, &["cwfs", "ts_wep"]);
+        let cloned = obs_env.clone_env(&source_path, true)?;
+        assert_eq!(
+            cloned["cwfs"].as_ref().unwrap(),
+            &ClonedRepo {
+                repo: "cwfs".to_owned(),
+                branch: None,
+                commit: first.to_string(),
+                local_changes: LocalChanges::Copied,
+            }
+        );
+        let cwfs = Repository::open(copy_path.join("cwfs"))?;
+        assert!(cwfs.head_detached()?);
+        assert_eq!(
+            std::fs::read_to_string(copy_path.join("cwfs/README.md"))?,
+            "cwfs changed\n"
+        );
+        let ts_wep = Repository::open(copy_path.join("ts_wep"))?;
+        assert_eq!(ts_wep.head()?.shorthand(), Some("develop"));
+        let branches = |repository: &Repository| -> Vec<String> {
+            repository
+                .branches(Some(BranchType::Local))
+                .unwrap()
+                .map(|branch| branch.unwrap().0.name().unwrap().unwrap().to_owned())
+                .collect()
+        };
+        // The same local branches as the source, no more.
+        assert!(branches(&ts_wep).contains(&"develop".to_owned()));
+        assert_eq!(
+            branches(&ts_wep),
+            branches(&Repository::open(source_path.join("ts_wep"))?)
+        );
+        assert!(ts_wep.find_reference("refs/remotes/origin/develop").is_ok());
+        assert_eq!(
+            ts_wep.find_remote("origin")?.url(),
+            Some(format!("{}/ts_wep", remotes.to_string_lossy()).as_str())
+        );
+        obs_env.verify()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_reset_with_version_override() -> TestResult {
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        let base_env_url = format!("{FAKE_ORG}/ts_cycle_build");
+        backend.set_branch(&base_env_url, "main", "cycle0001");
+        backend.set_file("cycle0001", "cycle/cycle.env", "ts_wep=1.2.0\ncwfs=0.3.0\n");
+        for repo_name in ["ts_wep", "cwfs"] {
+            backend.set_branch(&format!("{FAKE_ORG}/{repo_name}"), "main", "3333cccc");
         }
+        backend.set_tag(&format!("{FAKE_ORG}/ts_wep"), "v1.2.0", "1111aaaa");
+        backend.set_tag(&format!("{FAKE_ORG}/ts_wep"), "v1.3.0", "2222bbbb");
+        backend.set_tag(&format!("{FAKE_ORG}/cwfs"), "v0.3.0", "4444dddd");
+
+        let mut obs_env = fake_environment(root.path(), &backend, &["cwfs", "ts_wep"]);
+        assert!(matches!(
+            obs_env.set_version_override("ts_unknown", "1.0.0"),
+            Err(ObsEnvError::RepoNotFound { .. })
+        ));
+        obs_env.set_version_override("ts_wep", "1.3.0")?;
+        obs_env.clone_repositories().into_result()?;
+
+        let report = obs_env.reset_base_environment("main").unwrap();
+        assert_eq!(
+            report.overridden["ts_wep"],
+            VersionOverride {
+                version: "1.3.0".to_owned(),
+                base: Some("1.2.0".to_owned()),
+            }
+        );
+        assert_eq!(
+            backend.head(root.path().join("ts_wep")).unwrap(),
+            "2222bbbb"
+        );
+        assert_eq!(backend.head(root.path().join("cwfs")).unwrap(), "4444dddd");
+        let manifest = obs_env.get_manifest();
+        assert_eq!(
+            manifest.to_string(),
+            format!(
+                "Obs. Env. Path: {}.\ncwfs: v0.3.0\nts_wep: v1.3.0 (override)",
+                root.path().display()
+            )
+        );
+        assert_eq!(
+            EnvironmentManifest::parse(&manifest.to_toml(), "export")?,
+            manifest
+        );
+
+        let mut base_manifest = manifest.clone();
+        base_manifest.repos[1].sha = Some("1111aaaa".to_owned());
+        obs_env.apply_manifest(&base_manifest).unwrap();
+        assert_eq!(
+            backend.head(root.path().join("ts_wep")).unwrap(),
+            "2222bbbb"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_reset_fetches_base_env_cache() -> TestResult {
+        let root = TempDir::new()?;
+        let cache_dir = TempDir::new()?;
+        let backend = FakeBackend::new();
+        let base_env_url = format!("{FAKE_ORG}/ts_cycle_build");
+        backend.set_branch(&base_env_url, "main", "cycle0001");
+        backend.set_file("cycle0001", "cycle/cycle.env", "ts_wep=1.2.0\n");
+        backend.set_branch(&format!("{FAKE_ORG}/ts_wep"), "main", "3333cccc");
+        backend.set_tag(&format!("{FAKE_ORG}/ts_wep"), "v1.2.0", "1111aaaa");
+        backend.set_tag(&format!("{FAKE_ORG}/ts_wep"), "v1.3.0", "2222bbbb");
+
+        let obs_env = ObservingEnvironment {
+            base_versions_ttl: Some(Duration::from_secs(600)),
+            cache_dir: Some(cache_dir.path().to_path_buf()),
+            ..fake_environment(root.path(), &backend, &["ts_wep"])
+        };
+        obs_env.clone_repositories().into_result()?;
+        assert_eq!(
+            obs_env.get_base_env_versions("main")?["ts_wep"].describe,
+            "1.2.0"
+        );
+        assert!(backend
+            .head(cache_dir.path().join("base_env.git"))
+            .is_none());
+        assert!(!root.path().join(".obs_env").exists());
+
+        // The versions cache is still fresh, but the reset fetches the new
+        // definition.
+        backend.set_branch(&base_env_url, "main", "cycle0002");
+        backend.set_file("cycle0002", "cycle/cycle.env", "ts_wep=1.3.0\n");
+        assert_eq!(
+            obs_env.get_base_env_versions("main")?["ts_wep"].describe,
+            "1.2.0"
+        );
+        obs_env.reset_base_environment("main").unwrap();
+        assert_eq!(
+            backend.head(root.path().join("ts_wep")).as_deref(),
+            Some("2222bbbb")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_builder_validation() {
+        let obs_env = ObservingEnvironment::builder()
+            .destination("/tmp/obs-env")
+            .repositories([("ts_wep", "https://github.com/lsst-ts/")])
+            .fork("ts_wep", "tribeiro")
+            .base_branch("develop")
+            .build()
+            .unwrap();
+        assert_eq!(obs_env.destination, "/tmp/obs-env");
+        assert_eq!(obs_env.repositories.len(), 1);
+        assert_eq!(obs_env.get_base_env_branch(), "develop");
+        assert_eq!(
+            obs_env.get_repository_url("ts_wep").unwrap(),
+            "https://github.com/tribeiro/ts_wep"
+        );
+
+        let shallow = ObservingEnvironment::builder()
+            .clone_depth(1)
+            .build()
+            .unwrap();
+        assert_eq!(shallow.clone_depth, Some(1));
+
+        let default = ObservingEnvironment::builder().build().unwrap();
+        assert_eq!(default.destination, "/obs-env");
+        assert_eq!(default.get_base_env_branch(), "main");
+
+        assert!(ObservingEnvironment::builder()
+            .destination("")
+            .build()
+            .is_err());
+        assert!(ObservingEnvironment::builder()
+            .repositories([("ts_wep", "a"), ("ts_wep", "b")])
+            .build()
+            .is_err());
+        assert!(ObservingEnvironment::builder()
+            .repositories(Vec::<(String, String)>::new())
+            .build()
+            .is_err());
+        assert!(ObservingEnvironment::builder()
+            .fork("not_a_repo", "tribeiro")
+            .build()
+            .is_err());
+        assert!(ObservingEnvironment::builder()
+            .clone_depth(0)
+            .build()
+            .is_err());
+        assert!(ObservingEnvironment::builder()
+            .offline(true)
+            .refresh_base_cache(true)
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn test_fork_repository_url() {
+        let mut obs_env = ObservingEnvironment::with_destination(".");
+
+        obs_env.set_fork("ts_wep", "tribeiro").unwrap();
+
+        assert_eq!(
+            obs_env.get_repository_url("ts_wep").unwrap(),
+            "https://github.com/tribeiro/ts_wep"
+        );
+        assert_eq!(
+            obs_env
+                .get_repository_url("ts_observatory_control")
+                .unwrap(),
+            "https://github.com/lsst-ts/ts_observatory_control"
+        );
+        assert!(matches!(
+            obs_env.set_fork("not_a_repo", "tribeiro"),
+            Err(ObsEnvError::RepoNotFound { .. })
+        ));
     }
 
     #[test]
@@ -0,0 +1,225 @@
+//! How far the repositories trail the head of the default branch of
+//! origin, found by
+//! [`ObservingEnvironment::outdated`](crate::ObservingEnvironment::outdated),
+//! for the nightly report.
+use crate::audit::format_utc;
+use serde::Serialize;
+use std::{
+    cmp::Reverse,
+    fmt::{self, Display},
+};
+
+const DAY: u64 = 24 * 60 * 60;
+
+/// Commits of the default branch of origin a repository does not have.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RepoStaleness {
+    pub repo: String,
+    pub default_branch: String,
+    /// Number of commits of the default branch not in HEAD.
+    pub behind: usize,
+    /// Commit time of the oldest of those commits, in seconds since the
+    /// Unix epoch, i.e. since when the repository trails the branch.
+    pub behind_since: Option<u64>,
+    /// Commit time of the newest of those commits, the head of the branch.
+    pub newest_unpulled: Option<u64>,
+}
+
+impl RepoStaleness {
+    /// Whole days the repository has trailed the branch for, at `now`.
+    pub fn days_behind(&self, now: u64) -> Option<u64> {
+        self.behind_since
+            .map(|since| now.saturating_sub(since) / DAY)
+    }
+}
+
+/// How far behind its default branch a repository can be before it is
+/// outdated. A repository behind at all is if no threshold is set, and one
+/// beyond either threshold otherwise.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct OutdatedThresholds {
+    pub days: Option<u64>,
+    pub commits: Option<usize>,
+}
+
+impl OutdatedThresholds {
+    /// Whether `staleness` at `now` is beyond the thresholds.
+    pub fn exceeded(&self, staleness: &RepoStaleness, now: u64) -> bool {
+        if staleness.behind == 0 {
+            return false;
+        }
+        if self.days.is_none() && self.commits.is_none() {
+            return true;
+        }
+        let days = staleness.days_behind(now).unwrap_or_default();
+        self.days.is_some_and(|threshold| days >= threshold)
+            || self
+                .commits
+                .is_some_and(|threshold| staleness.behind >= threshold)
+    }
+}
+
+/// Staleness of a repository in an [`OutdatedReport`].
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct OutdatedRepo {
+    #[serde(flatten)]
+    pub staleness: RepoStaleness,
+    pub days_behind: Option<u64>,
+    /// Whether it is beyond the thresholds.
+    pub outdated: bool,
+    /// Whether it is pinned on purpose, e.g. to an old tag, and does not
+    /// count as outdated.
+    pub ignored: bool,
+}
+
+impl Display for OutdatedRepo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let staleness = &self.staleness;
+        if staleness.behind == 0 {
+            return write!(
+                f,
+                "{}: up to date with origin/{}",
+                staleness.repo, staleness.default_branch
+            );
+        }
+        write!(
+            f,
+            "{}: {} commits behind origin/{}",
+            staleness.repo, staleness.behind, staleness.default_branch
+        )?;
+        if let (Some(days), Some(newest)) = (self.days_behind, staleness.newest_unpulled) {
+            write!(
+                f,
+                " for {days} days, newest commit on {}",
+                &format_utc(newest)[..10]
+            )?;
+        }
+        if self.ignored {
+            write!(f, " (ignored)")?;
+        } else if self.outdated {
+            write!(f, " (outdated)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Staleness of the repositories of an environment, the most stale first.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct OutdatedReport {
+    /// When the report was made, in seconds since the Unix epoch.
+    pub time: u64,
+    pub thresholds: OutdatedThresholds,
+    pub repos: Vec<OutdatedRepo>,
+}
+
+impl OutdatedReport {
+    /// Report of `stalenesses` at `now`, with the repositories in `ignored`
+    /// marked as such.
+    pub fn new(
+        stalenesses: Vec<RepoStaleness>,
+        thresholds: OutdatedThresholds,
+        ignored: &[String],
+        now: u64,
+    ) -> OutdatedReport {
+        let mut repos: Vec<OutdatedRepo> = stalenesses
+            .into_iter()
+            .map(|staleness| OutdatedRepo {
+                days_behind: staleness.days_behind(now),
+                outdated: thresholds.exceeded(&staleness, now),
+                ignored: ignored.contains(&staleness.repo),
+                staleness,
+            })
+            .collect();
+        repos.sort_by_key(|repo| {
+            (
+                Reverse(repo.days_behind),
+                Reverse(repo.staleness.behind),
+                repo.staleness.repo.clone(),
+            )
+        });
+        OutdatedReport {
+            time: now,
+            thresholds,
+            repos,
+        }
+    }
+
+    /// Repositories beyond the thresholds that are not ignored.
+    pub fn outdated(&self) -> impl Iterator<Item = &OutdatedRepo> {
+        self.repos
+            .iter()
+            .filter(|repo| repo.outdated && !repo.ignored)
+    }
+}
+
+impl Display for OutdatedReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let lines: Vec<String> = self.repos.iter().map(|repo| repo.to_string()).collect();
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OutdatedReport, OutdatedThresholds, RepoStaleness, DAY};
+
+    fn staleness(repo: &str, behind: usize, days: u64, now: u64) -> RepoStaleness {
+        RepoStaleness {
+            repo: repo.to_owned(),
+            default_branch: "develop".to_owned(),
+            behind,
+            behind_since: (behind > 0).then(|| now - days * DAY),
+            newest_unpulled: (behind > 0).then_some(now),
+        }
+    }
+
+    #[test]
+    fn test_outdated_report() {
+        let now = 1_760_400_000;
+        let report = OutdatedReport::new(
+            vec![
+                staleness("cwfs", 0, 0, now),
+                staleness("ts_wep", 3, 2, now),
+                staleness("ts_xml", 40, 10, now),
+                staleness("atmospec", 1, 30, now),
+            ],
+            OutdatedThresholds {
+                days: Some(7),
+                commits: Some(20),
+            },
+            &["atmospec".to_owned()],
+            now,
+        );
+        let repos: Vec<(&str, bool)> = report
+            .repos
+            .iter()
+            .map(|repo| (repo.staleness.repo.as_str(), repo.outdated))
+            .collect();
+        assert_eq!(
+            repos,
+            [
+                ("atmospec", true),
+                ("ts_xml", true),
+                ("ts_wep", false),
+                ("cwfs", false)
+            ]
+        );
+        let outdated: Vec<&str> = report
+            .outdated()
+            .map(|repo| repo.staleness.repo.as_str())
+            .collect();
+        assert_eq!(outdated, ["ts_xml"]);
+        assert_eq!(
+            report.repos[1].to_string(),
+            "ts_xml: 40 commits behind origin/develop for 10 days, newest commit on 2025-10-14 (outdated)"
+        );
+        assert_eq!(
+            report.repos[3].to_string(),
+            "cwfs: up to date with origin/develop"
+        );
+
+        let any = OutdatedThresholds::default();
+        assert!(any.exceeded(&report.repos[2].staleness, now));
+        assert!(!any.exceeded(&report.repos[3].staleness, now));
+    }
+}
@@ -0,0 +1,130 @@
+//! Pool of worker threads carrying out an operation on each repository of
+//! an environment.
+use std::{
+    any::Any,
+    num::NonZeroUsize,
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    thread,
+};
+
+/// Number of worker threads used by default: the available parallelism
+/// of the host.
+pub(crate) fn default_jobs() -> usize {
+    thread::available_parallelism()
+        .map(NonZeroUsize::get)
+        .unwrap_or(1)
+}
+
+/// Apply `op` to every item of `items` on at most `jobs` threads,
+/// returning the results in the order of `items`.
+///
+/// A panic of `op` only affects its item, whose result is then the panic
+/// message.
+pub(crate) fn map<T, R, F>(items: &[T], jobs: usize, op: F) -> Vec<Result<R, String>>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    map_until(items, jobs, op, |_| false)
+        .into_iter()
+        // Nothing stops the workers, so every item has a result.
+        .map(Option::unwrap)
+        .collect()
+}
+
+/// Apply `op` to the items of `items` as [`map`] does, until a result
+/// meets `stop`: the items being worked on then complete, but no other is
+/// started, and has none for result.
+pub(crate) fn map_until<T, R, F, S>(
+    items: &[T],
+    jobs: usize,
+    op: F,
+    stop: S,
+) -> Vec<Option<Result<R, String>>>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+    S: Fn(&Result<R, String>) -> bool + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let stopped = AtomicBool::new(false);
+    let worker = || {
+        let mut results = Vec::new();
+        while !stopped.load(Ordering::Relaxed) {
+            let index = next.fetch_add(1, Ordering::Relaxed);
+            let Some(item) = items.get(index) else {
+                break;
+            };
+            let result = catch_unwind(AssertUnwindSafe(|| op(item))).map_err(panic_message);
+            if stop(&result) {
+                stopped.store(true, Ordering::Relaxed);
+            }
+            results.push((index, result));
+        }
+        results
+    };
+    let mut results: Vec<Option<Result<R, String>>> = items.iter().map(|_| None).collect();
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs.clamp(1, items.len().max(1)))
+            .map(|_| scope.spawn(worker))
+            .collect();
+        for worker in workers {
+            // The panics of `op` are caught, so the workers cannot panic.
+            for (index, result) in worker.join().unwrap() {
+                results[index] = Some(result);
+            }
+        }
+    });
+    results
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "unknown panic".to_owned(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{map, map_until};
+
+    #[test]
+    fn test_map_keeps_order_and_catches_panics() {
+        let items: Vec<u32> = (0..20).collect();
+
+        let results = map(&items, 4, |item| {
+            if *item == 7 {
+                panic!("item {item} failed");
+            }
+            item * 2
+        });
+
+        assert_eq!(results.len(), 20);
+        assert_eq!(results[7], Err("item 7 failed".to_owned()));
+        for (item, result) in items.iter().zip(results) {
+            if *item != 7 {
+                assert_eq!(result, Ok(item * 2));
+            }
+        }
+        assert!(map(&[] as &[u32], 4, |item| *item).is_empty());
+    }
+
+    #[test]
+    fn test_map_until_starts_nothing_after_stopping() {
+        let items: Vec<u32> = (0..20).collect();
+
+        let results = map_until(&items, 1, |item| item * 2, |result| result == &Ok(6));
+
+        assert_eq!(results.len(), 20);
+        assert_eq!(results[3], Some(Ok(6)));
+        assert!(results[..3].iter().all(Option::is_some));
+        assert!(results[4..].iter().all(Option::is_none));
+    }
+}
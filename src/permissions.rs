@@ -0,0 +1,227 @@
+//! Sharing of the environment with the operators of a unix group, set with
+//! [`ObservingEnvironmentBuilder::shared_access`](crate::ObservingEnvironmentBuilder::shared_access).
+//!
+//! The directories and files created by Setup are given to the group, with
+//! the setgid bit set on directories so that what is created in them later
+//! stays in the group, and made writable by it. The clones are configured
+//! with `core.sharedRepository=group`, for git to keep the files it creates
+//! group writable regardless of the umask of who runs it.
+use crate::error::ObsEnvError;
+use std::path::{Path, PathBuf};
+
+/// git configuration making git create files writable by their group.
+pub const SHARED_REPOSITORY: &str = "core.sharedRepository";
+
+/// Unix group the environment is shared with.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Group {
+    pub name: String,
+    pub gid: u32,
+}
+
+impl Group {
+    /// Group named `name`, or with `name` as id, failing if there is none.
+    pub fn lookup(name: &str) -> Result<Group, ObsEnvError> {
+        match name.parse().ok().or_else(|| group_id(name)) {
+            Some(gid) => Ok(Group {
+                name: name.to_owned(),
+                gid,
+            }),
+            None => Err(ObsEnvError::InvalidConfig {
+                message: format!("There is no group {name}"),
+            }),
+        }
+    }
+}
+
+/// How the files of the environment are shared with a group.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SharedAccess {
+    /// Group the files are given to, or that of their creator if none.
+    pub group: Option<Group>,
+    /// Whether the files are made writable by their group.
+    pub group_writable: bool,
+}
+
+impl SharedAccess {
+    /// Whether the files are shared at all.
+    pub fn is_enabled(&self) -> bool {
+        self.group.is_some() || self.group_writable
+    }
+
+    /// Share `path`, and everything under it if `recursive`. Symbolic
+    /// links are given to the group but not followed.
+    pub fn apply(&self, path: &Path, recursive: bool) -> Result<(), ObsEnvError> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        let mut paths = vec![path.to_path_buf()];
+        while let Some(path) = paths.pop() {
+            let metadata = path
+                .symlink_metadata()
+                .map_err(|error| ObsEnvError::io(&path, "read the permissions of", error))?;
+            self.share(&path, &metadata)?;
+            if recursive && metadata.is_dir() {
+                let entries = path
+                    .read_dir()
+                    .map_err(|error| ObsEnvError::io(&path, "read", error))?;
+                paths.extend(entries.flatten().map(|entry| entry.path()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Paths under `path`, included, the group could not write to, or
+    /// where what it creates would not stay in the group.
+    pub fn unshared(&self, path: &Path) -> Vec<PathBuf> {
+        let mut unshared = Vec::new();
+        if !self.is_enabled() {
+            return unshared;
+        }
+        let mut paths = vec![path.to_path_buf()];
+        while let Some(path) = paths.pop() {
+            let Ok(metadata) = path.symlink_metadata() else {
+                continue;
+            };
+            if metadata.is_dir() {
+                if let Ok(entries) = path.read_dir() {
+                    paths.extend(entries.flatten().map(|entry| entry.path()));
+                }
+            }
+            if !self.is_shared(&metadata) {
+                unshared.push(path);
+            }
+        }
+        unshared.sort();
+        unshared
+    }
+
+    #[cfg(unix)]
+    fn share(&self, path: &Path, metadata: &std::fs::Metadata) -> Result<(), ObsEnvError> {
+        use std::os::unix::fs::{lchown, MetadataExt, PermissionsExt};
+
+        if let Some(group) = &self.group {
+            if metadata.gid() != group.gid {
+                lchown(path, None, Some(group.gid))
+                    .map_err(|error| ObsEnvError::io(path, "change the group of", error))?;
+            }
+        }
+        if metadata.is_symlink() {
+            return Ok(());
+        }
+        let mode = metadata.mode() & 0o7777;
+        let shared = shared_mode(mode, metadata.is_dir(), self.group_writable);
+        if shared != mode {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(shared))
+                .map_err(|error| ObsEnvError::io(path, "change the permissions of", error))?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn share(&self, _path: &Path, _metadata: &std::fs::Metadata) -> Result<(), ObsEnvError> {
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    fn is_shared(&self, metadata: &std::fs::Metadata) -> bool {
+        use std::os::unix::fs::MetadataExt;
+
+        let in_group = self
+            .group
+            .as_ref()
+            .is_none_or(|group| metadata.gid() == group.gid);
+        let mode = metadata.mode() & 0o7777;
+        in_group
+            && (metadata.is_symlink()
+                || shared_mode(mode, metadata.is_dir(), self.group_writable) == mode)
+    }
+
+    #[cfg(not(unix))]
+    fn is_shared(&self, _metadata: &std::fs::Metadata) -> bool {
+        true
+    }
+}
+
+/// Permissions `mode` of a file or directory once shared: directories get
+/// the setgid bit, and files are made writable with `group_writable`, and
+/// executable by the group when they are by their owner.
+#[cfg(unix)]
+fn shared_mode(mode: u32, is_dir: bool, group_writable: bool) -> u32 {
+    match (is_dir, group_writable) {
+        (true, true) => mode | 0o2070,
+        (true, false) => mode | 0o2000,
+        (false, true) => mode | 0o060 | (mode & 0o100) >> 3,
+        (false, false) => mode,
+    }
+}
+
+#[cfg(unix)]
+fn group_id(name: &str) -> Option<u32> {
+    use std::{ffi::CString, mem::MaybeUninit, ptr};
+
+    let name = CString::new(name).ok()?;
+    let mut group = MaybeUninit::<libc::group>::uninit();
+    let mut buffer = vec![0 as libc::c_char; 16 * 1024];
+    let mut result = ptr::null_mut();
+    let status = unsafe {
+        libc::getgrnam_r(
+            name.as_ptr(),
+            group.as_mut_ptr(),
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if status != 0 || result.is_null() {
+        return None;
+    }
+    Some(unsafe { group.assume_init() }.gr_gid)
+}
+
+#[cfg(not(unix))]
+fn group_id(_name: &str) -> Option<u32> {
+    None
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::{Group, SharedAccess};
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_shared_access() {
+        let root = TempDir::new().unwrap();
+        let repo = root.path().join("ts_wep");
+        std::fs::create_dir_all(repo.join("python")).unwrap();
+        std::fs::write(repo.join("README.md"), "").unwrap();
+        std::fs::write(repo.join("python/run.sh"), "").unwrap();
+        for (path, mode) in [
+            ("", 0o755),
+            ("python", 0o700),
+            ("README.md", 0o644),
+            ("python/run.sh", 0o744),
+        ] {
+            std::fs::set_permissions(repo.join(path), PermissionsExt::from_mode(mode)).unwrap();
+        }
+        let gid = unsafe { libc::getegid() };
+        let access = SharedAccess {
+            group: Some(Group::lookup(&gid.to_string()).unwrap()),
+            group_writable: true,
+        };
+        assert_eq!(access.unshared(&repo).len(), 4);
+
+        access.apply(&repo, true).unwrap();
+        assert!(access.unshared(&repo).is_empty());
+        let mode = |path: &str| repo.join(path).metadata().unwrap().mode() & 0o7777;
+        assert_eq!(mode(""), 0o2775);
+        assert_eq!(mode("python"), 0o2770);
+        assert_eq!(mode("README.md"), 0o664);
+        assert_eq!(mode("python/run.sh"), 0o774);
+        assert_eq!(repo.metadata().unwrap().gid(), gid);
+
+        assert!(SharedAccess::default().unshared(&repo).is_empty());
+        assert!(Group::lookup("no such group").is_err());
+    }
+}
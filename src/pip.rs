@@ -0,0 +1,94 @@
+use crate::error::ObsEnvError;
+use std::{path::Path, process::Command};
+
+/// Files marking a directory as an installable Python package.
+const PACKAGING_FILES: [&str; 2] = ["pyproject.toml", "setup.py"];
+
+/// Installs repositories of the environment in editable mode into a
+/// Python environment, with `python -m pip install -e <path>`.
+///
+/// ```
+/// use ts_observing_environment::pip::PipInstall;
+///
+/// let pip = PipInstall::new("/opt/conda/bin/python");
+/// assert_eq!(pip.get_python(), "/opt/conda/bin/python");
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct PipInstall {
+    python: String,
+}
+
+impl PipInstall {
+    /// Install with the `python` interpreter.
+    pub fn new(python: &str) -> PipInstall {
+        PipInstall {
+            python: python.to_owned(),
+        }
+    }
+
+    /// Interpreter the packages are installed with.
+    pub fn get_python(&self) -> &str {
+        &self.python
+    }
+
+    /// Whether the directory at `path` has Python packaging metadata.
+    pub fn is_installable(path: &Path) -> bool {
+        PACKAGING_FILES.iter().any(|file| path.join(file).is_file())
+    }
+
+    /// Install the package at `path` in editable mode.
+    ///
+    /// The output of pip is logged at trace level, and carried by the error
+    /// if the installation fails.
+    pub fn install_editable(&self, path: &Path) -> Result<(), ObsEnvError> {
+        let args = ["-m", "pip", "install", "-e", &path.to_string_lossy()];
+        let command = format!("{} {}", self.python, args.join(" "));
+        log::debug!("Running {command}");
+        let output = Command::new(&self.python)
+            .args(args)
+            .output()
+            .map_err(|error| ObsEnvError::CommandFailed {
+                command: command.clone(),
+                message: error.to_string(),
+            })?;
+        let captured = format!(
+            "{}{}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        if output.status.success() {
+            log::trace!("{command}:\n{captured}");
+            Ok(())
+        } else {
+            Err(ObsEnvError::CommandFailed {
+                command,
+                message: captured.trim().to_owned(),
+            })
+        }
+    }
+}
+
+/// Requirements installed for the `python` interpreter, as listed by `pip
+/// freeze`.
+pub fn freeze(python: &str) -> Result<Vec<String>, ObsEnvError> {
+    let command = format!("{python} -m pip freeze");
+    let output = Command::new(python)
+        .args(["-m", "pip", "freeze"])
+        .output()
+        .map_err(|error| ObsEnvError::CommandFailed {
+            command: command.clone(),
+            message: error.to_string(),
+        })?;
+    if !output.status.success() {
+        return Err(ObsEnvError::CommandFailed {
+            command,
+            message: String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        });
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_owned)
+        .collect())
+}
@@ -0,0 +1,154 @@
+use crate::error::ObsEnvError;
+use serde::Serialize;
+use std::{
+    fmt::{self, Display},
+    path::{Path, PathBuf},
+};
+
+/// Bytes in a mebibyte, the unit free space is given in.
+pub(crate) const MIB: u64 = 1024 * 1024;
+
+/// Whether the environment path can be written to, and the space left on
+/// its volume, checked before operations that modify the environment.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PathCheck {
+    /// Directory checked: the environment path, or the closest existing
+    /// parent if it is not created yet.
+    pub path: PathBuf,
+    /// Whether the current user can write to the directory.
+    pub writable: bool,
+    /// Bytes available to the current user on the volume, if they could
+    /// be read.
+    pub available_bytes: Option<u64>,
+    /// Bytes that must be available.
+    pub required_bytes: u64,
+}
+
+impl PathCheck {
+    /// Check the environment at `env_path`, requiring `required_bytes` to
+    /// be available.
+    pub fn new(env_path: &Path, required_bytes: u64) -> PathCheck {
+        let path = env_path
+            .ancestors()
+            .find(|ancestor| ancestor.is_dir())
+            .unwrap_or(env_path)
+            .to_path_buf();
+        PathCheck {
+            writable: is_writable(&path),
+            available_bytes: available_bytes(&path),
+            required_bytes,
+            path,
+        }
+    }
+
+    /// Error for the first check that failed, if any. Space that could not
+    /// be read is not an error.
+    pub fn error(&self) -> Option<ObsEnvError> {
+        if !self.writable {
+            return Some(ObsEnvError::InsufficientPermissions {
+                path: self.path.clone(),
+            });
+        }
+        match self.available_bytes {
+            Some(available) if available < self.required_bytes => {
+                Some(ObsEnvError::InsufficientSpace {
+                    path: self.path.clone(),
+                    available_mib: available / MIB,
+                    required_mib: self.required_bytes / MIB,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Display for PathCheck {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {}, ",
+            self.path.display(),
+            if self.writable {
+                "writable"
+            } else {
+                "not writable"
+            }
+        )?;
+        match self.available_bytes {
+            Some(available) => write!(f, "{} MiB available", available / MIB)?,
+            None => write!(f, "available space unknown")?,
+        }
+        write!(f, " ({} MiB required)", self.required_bytes / MIB)
+    }
+}
+
+#[cfg(unix)]
+fn is_writable(path: &Path) -> bool {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    // Also fails on read-only mounts, with EROFS.
+    unsafe { libc::access(path.as_ptr(), libc::W_OK | libc::X_OK) == 0 }
+}
+
+#[cfg(not(unix))]
+fn is_writable(path: &Path) -> bool {
+    path.metadata()
+        .is_ok_and(|metadata| !metadata.permissions().readonly())
+}
+
+#[cfg(unix)]
+fn available_bytes(path: &Path) -> Option<u64> {
+    use std::{ffi::CString, mem::MaybeUninit, os::unix::ffi::OsStrExt};
+
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+    if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    let stat = unsafe { stat.assume_init() };
+    #[allow(clippy::unnecessary_cast)]
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+fn available_bytes(_path: &Path) -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PathCheck, MIB};
+    use crate::ObsEnvError;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_path_check() {
+        let root = TempDir::new().unwrap();
+        let env_path = root.path().join("obs-env/auto_base_packages");
+
+        let check = PathCheck::new(&env_path, MIB);
+        assert_eq!(check.path, root.path());
+        assert!(check.writable);
+        assert!(check.available_bytes.is_some());
+        assert!(check.error().is_none());
+
+        let check = PathCheck::new(root.path(), u64::MAX);
+        assert!(matches!(
+            check.error(),
+            Some(ObsEnvError::InsufficientSpace { .. })
+        ));
+
+        let check = PathCheck {
+            writable: false,
+            ..check
+        };
+        assert!(matches!(
+            check.error(),
+            Some(ObsEnvError::InsufficientPermissions { .. })
+        ));
+        assert!(check.to_string().contains("not writable"));
+    }
+}
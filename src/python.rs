@@ -0,0 +1,316 @@
+//! Python bindings, built with maturin as the `ts_observing_environment`
+//! extension module.
+//!
+//! ```python
+//! from ts_observing_environment import ObservingEnvironment, ObsEnvException
+//!
+//! obs_env = ObservingEnvironment("/net/obs-env/auto_base_packages")
+//! for repo_name, (current, base) in obs_env.diff().items():
+//!     print(f"{repo_name}: {current} (base: {base})")
+//! ```
+//!
+//! The git operations release the GIL while they run.
+use crate::{error::report, ObsEnvError, ObservingEnvironment};
+use pyo3::{create_exception, exceptions::PyException, prelude::*};
+use std::{collections::BTreeMap, path::PathBuf};
+
+create_exception!(
+    ts_observing_environment,
+    ObsEnvException,
+    PyException,
+    "Base class of the errors raised when managing the observing environment."
+);
+create_exception!(
+    ts_observing_environment,
+    RepoNotFoundError,
+    ObsEnvException,
+    "The repository is not in the list of managed repositories."
+);
+create_exception!(
+    ts_observing_environment,
+    RepoNotClonedError,
+    ObsEnvException,
+    "The repository is not cloned in the environment."
+);
+create_exception!(
+    ts_observing_environment,
+    RevisionNotFoundError,
+    ObsEnvException,
+    "The branch or revision does not exist, or is ambiguous."
+);
+create_exception!(
+    ts_observing_environment,
+    RepoBusyError,
+    ObsEnvException,
+    "The repository has an operation in progress or uncommitted changes."
+);
+create_exception!(
+    ts_observing_environment,
+    NetworkError,
+    ObsEnvException,
+    "A remote could not be reached, or the environment is offline."
+);
+create_exception!(
+    ts_observing_environment,
+    ConfigurationError,
+    ObsEnvException,
+    "The environment is not configured correctly."
+);
+
+impl From<ObsEnvError> for PyErr {
+    fn from(error: ObsEnvError) -> PyErr {
+        let message = report(&error);
+        match error {
+            ObsEnvError::RepoNotFound { .. } => RepoNotFoundError::new_err(message),
+            ObsEnvError::RepoNotCloned { .. } => RepoNotClonedError::new_err(message),
+            ObsEnvError::BranchNotFound { .. }
+            | ObsEnvError::RevisionNotFound { .. }
+            | ObsEnvError::NotOnOrigin { .. }
+            | ObsEnvError::AmbiguousRevision { .. } => RevisionNotFoundError::new_err(message),
+            ObsEnvError::RepoBusy { .. }
+            | ObsEnvError::DirtyWorkingTree { .. }
+            | ObsEnvError::UnsavedWork { .. }
+            | ObsEnvError::EnvLocked { .. } => RepoBusyError::new_err(message),
+            ObsEnvError::CloneFailed { .. }
+            | ObsEnvError::FetchFailed { .. }
+            | ObsEnvError::NetworkTimeout { .. }
+            | ObsEnvError::Offline { .. }
+            | ObsEnvError::BaseEnvUnavailable { .. }
+            | ObsEnvError::ManifestUnavailable { .. } => NetworkError::new_err(message),
+            ObsEnvError::MissingArgument { .. }
+            | ObsEnvError::InvalidConfig { .. }
+            | ObsEnvError::InvalidManifest { .. }
+            | ObsEnvError::InvalidEnvPath { .. }
+            | ObsEnvError::NotAnEnvironment { .. }
+            | ObsEnvError::InsufficientPermissions { .. }
+            | ObsEnvError::InsufficientSpace { .. } => ConfigurationError::new_err(message),
+            _ => ObsEnvException::new_err(message),
+        }
+    }
+}
+
+/// Python view of an [`ObservingEnvironment`].
+#[pyclass(
+    name = "ObservingEnvironment",
+    module = "ts_observing_environment",
+    frozen
+)]
+pub struct PyObservingEnvironment {
+    inner: ObservingEnvironment,
+}
+
+#[pymethods]
+impl PyObservingEnvironment {
+    /// Environment at `env_path`, with its base versions read from
+    /// `base_env_branch` of the base environment repository, or from the
+    /// local `base_env_source`.
+    #[new]
+    #[pyo3(signature = (env_path, base_env_branch = "main", *, base_env_source = None, offline = false, only = None, forks = None))]
+    fn new(
+        env_path: &str,
+        base_env_branch: &str,
+        base_env_source: Option<&str>,
+        offline: bool,
+        only: Option<Vec<String>>,
+        forks: Option<BTreeMap<String, String>>,
+    ) -> PyResult<PyObservingEnvironment> {
+        let mut builder = ObservingEnvironment::builder()
+            .destination(env_path)
+            .base_branch(base_env_branch)
+            .offline(offline);
+        if let Some(base_env_source) = base_env_source {
+            builder = builder.base_env_source(base_env_source);
+        }
+        for repo_name in only.unwrap_or_default() {
+            builder = builder.only(&repo_name);
+        }
+        for (repo_name, owner) in forks.unwrap_or_default() {
+            builder = builder.fork(&repo_name, &owner);
+        }
+        Ok(builder.build()?.into())
+    }
+
+    /// Create the environment path and clone the missing repositories,
+    /// returning the paths of those cloned.
+    fn setup(&self, py: Python<'_>) -> PyResult<Vec<PathBuf>> {
+        py.detach(|| {
+            self.inner.create_path()?;
+            let setup_report = self.inner.clone_repositories();
+            if let Some(error) = setup_report.partial_failure() {
+                return Err(error.into());
+            }
+            Ok(setup_report
+                .cloned()
+                .map(|(_, path)| path.to_owned())
+                .collect())
+        })
+    }
+
+    /// Versions checked out in the environment, by repository name. The
+    /// version is None for the repositories that could not be read.
+    fn current_versions(&self, py: Python<'_>) -> BTreeMap<String, Option<String>> {
+        py.detach(|| current_versions(&self.inner))
+    }
+
+    /// Versions defined by `branch` of the base environment, by repository
+    /// name. `branch` defaults to the one given when creating the
+    /// environment.
+    #[pyo3(signature = (branch = None))]
+    fn base_versions(
+        &self,
+        py: Python<'_>,
+        branch: Option<&str>,
+    ) -> PyResult<BTreeMap<String, String>> {
+        py.detach(|| self.base_versions_of(branch))
+    }
+
+    /// Repositories whose current version differs from the base one, mapped
+    /// to their `(current, base)` versions.
+    #[pyo3(signature = (branch = None))]
+    fn diff(
+        &self,
+        py: Python<'_>,
+        branch: Option<&str>,
+    ) -> PyResult<BTreeMap<String, (Option<String>, String)>> {
+        py.detach(|| {
+            let mut current = current_versions(&self.inner);
+            Ok(self
+                .base_versions_of(branch)?
+                .into_iter()
+                .filter_map(|(repo_name, base)| {
+                    let current = current.remove(&repo_name).flatten();
+                    (current.as_ref() != Some(&base)).then_some((repo_name, (current, base)))
+                })
+                .collect())
+        })
+    }
+
+    /// Check out `branch` in `repo`, returning the name of the branch
+    /// checked out, e.g. "tickets/DM-12345" for "DM-12345".
+    fn checkout_branch(&self, py: Python<'_>, repo: &str, branch: &str) -> PyResult<String> {
+        py.detach(|| Ok(self.inner.checkout_branch(repo, branch)?))
+    }
+
+    /// Reset every repository to its base version. Raises the error of the
+    /// repository that failed, or an ObsEnvException listing all of them
+    /// if several did.
+    #[pyo3(signature = (branch = None))]
+    fn reset(&self, py: Python<'_>, branch: Option<&str>) -> PyResult<()> {
+        py.detach(|| {
+            let branch = branch.unwrap_or(self.inner.get_base_env_branch());
+            match self.inner.reset_base_environment(branch) {
+                Ok(_) => Ok(()),
+                Err(mut errors) if errors.len() == 1 => Err(errors.remove(0).into()),
+                Err(errors) => Err(ObsEnvException::new_err(
+                    errors
+                        .iter()
+                        .map(|error| report(error))
+                        .collect::<Vec<_>>()
+                        .join("\n"),
+                )),
+            }
+        })
+    }
+
+    fn __str__(&self) -> String {
+        self.inner.summarize().to_string()
+    }
+}
+
+impl PyObservingEnvironment {
+    fn base_versions_of(&self, branch: Option<&str>) -> PyResult<BTreeMap<String, String>> {
+        let branch = branch.unwrap_or(self.inner.get_base_env_branch());
+        Ok(self
+            .inner
+            .get_base_env_versions(branch)?
+            .into_iter()
+            .map(|(repo_name, version)| (repo_name, version.describe))
+            .collect())
+    }
+}
+
+impl From<ObservingEnvironment> for PyObservingEnvironment {
+    fn from(obs_env: ObservingEnvironment) -> PyObservingEnvironment {
+        PyObservingEnvironment { inner: obs_env }
+    }
+}
+
+fn current_versions(obs_env: &ObservingEnvironment) -> BTreeMap<String, Option<String>> {
+    obs_env
+        .get_current_env_versions()
+        .into_iter()
+        .map(|(repo_name, version)| (repo_name, version.ok().map(|version| version.describe)))
+        .collect()
+}
+
+#[pymodule]
+fn ts_observing_environment(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    let py = m.py();
+    m.add_class::<PyObservingEnvironment>()?;
+    m.add("ObsEnvException", py.get_type::<ObsEnvException>())?;
+    m.add("RepoNotFoundError", py.get_type::<RepoNotFoundError>())?;
+    m.add("RepoNotClonedError", py.get_type::<RepoNotClonedError>())?;
+    m.add(
+        "RevisionNotFoundError",
+        py.get_type::<RevisionNotFoundError>(),
+    )?;
+    m.add("RepoBusyError", py.get_type::<RepoBusyError>())?;
+    m.add("NetworkError", py.get_type::<NetworkError>())?;
+    m.add("ConfigurationError", py.get_type::<ConfigurationError>())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PyObservingEnvironment, RepoNotFoundError, RevisionNotFoundError};
+    use crate::{testing::FakeBackend, ObservingEnvironment};
+    use pyo3::prelude::*;
+    use std::collections::BTreeMap;
+    use tempfile::TempDir;
+
+    type TestResult<T = (), E = Box<dyn std::error::Error>> = std::result::Result<T, E>;
+
+    const FAKE_ORG: &str = "https://example.com/lsst-ts";
+
+    #[test]
+    fn test_python_environment() -> TestResult {
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        backend.set_branch(&format!("{FAKE_ORG}/ts_wep"), "develop", "1111aaaa");
+        let versions_file = root.path().join("versions.env");
+        std::fs::write(&versions_file, "ts_wep=1.2.0\nts_missing=0.1.0\n")?;
+        let obs_env: PyObservingEnvironment = ObservingEnvironment::builder()
+            .destination(&root.path().to_string_lossy())
+            .repositories(["ts_missing", "ts_wep"].map(|name| (name, FAKE_ORG)))
+            .base_env_source(&versions_file.to_string_lossy())
+            .backend(backend.clone())
+            .build()?
+            .into();
+
+        Python::initialize();
+        Python::attach(|py| {
+            let error = obs_env.setup(py).unwrap_err();
+            assert!(error.to_string().contains("ts_missing"));
+            assert_eq!(
+                obs_env.current_versions(py),
+                BTreeMap::from([
+                    ("ts_missing".to_owned(), None),
+                    ("ts_wep".to_owned(), Some("1111aaaa".to_owned())),
+                ])
+            );
+            assert_eq!(
+                obs_env.diff(py, None).unwrap()["ts_wep"],
+                (Some("1111aaaa".to_owned()), "1.2.0".to_owned())
+            );
+            assert!(obs_env
+                .checkout_branch(py, "ts_unknown", "develop")
+                .unwrap_err()
+                .is_instance_of::<RepoNotFoundError>(py));
+            assert!(obs_env
+                .checkout_branch(py, "ts_wep", "tickets/DM-0")
+                .unwrap_err()
+                .is_instance_of::<RevisionNotFoundError>(py));
+        });
+        Ok(())
+    }
+}
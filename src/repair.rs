@@ -0,0 +1,258 @@
+//! Detection of repositories left broken in the environment, e.g. by a
+//! Setup that was killed while cloning, with
+//! [`ObservingEnvironment::diagnose_repositories`](crate::ObservingEnvironment::diagnose_repositories)
+//! and their repair with
+//! [`ObservingEnvironment::repair_repositories`](crate::ObservingEnvironment::repair_repositories).
+use crate::git_backend::Identity;
+use serde::Serialize;
+use std::{
+    fmt::{self, Display},
+    fs::{read_dir, read_to_string},
+    path::{Path, PathBuf},
+};
+
+/// Damage found in a repository of the environment.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "damage", rename_all = "snake_case")]
+pub enum RepoDamage {
+    /// The repository cannot be opened.
+    Unopenable { reason: String },
+    /// HEAD does not resolve to a commit.
+    MissingHead,
+    /// The object database is empty.
+    NoObjects,
+    /// Lock files were left by an interrupted git operation.
+    StaleLocks { locks: Vec<PathBuf> },
+    /// Objects are corrupt or missing, as found by a deep check.
+    CorruptObjects { objects: Vec<String> },
+}
+
+impl RepoDamage {
+    /// Whether the repository is unusable and has to be cloned again. Lock
+    /// files can just be removed.
+    pub fn is_broken(&self) -> bool {
+        !matches!(self, RepoDamage::StaleLocks { .. })
+    }
+}
+
+impl Display for RepoDamage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RepoDamage::Unopenable { reason } => write!(f, "does not open: {reason}"),
+            RepoDamage::MissingHead => write!(f, "HEAD does not resolve to a commit"),
+            RepoDamage::NoObjects => write!(f, "has no objects"),
+            RepoDamage::CorruptObjects { objects } => write!(
+                f,
+                "has {} corrupt or missing objects, e.g. {}",
+                objects.len(),
+                objects.first().map(String::as_str).unwrap_or_default()
+            ),
+            RepoDamage::StaleLocks { locks } => write!(
+                f,
+                "has lock files left: {}",
+                locks
+                    .iter()
+                    .map(|lock| lock.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+/// Configuration of a repository that keeps git from operating on it,
+/// although the repository itself is fine.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "blocker", rename_all = "snake_case")]
+pub enum RepoBlocker {
+    /// The repository is owned by another user and is not trusted, with
+    /// safe.directory or [`ObservingEnvironmentBuilder::trust_env_path`](crate::ObservingEnvironmentBuilder::trust_env_path).
+    NotOwned,
+    /// No identity is configured to commit with; the manager commits as
+    /// `identity` instead.
+    NoIdentity { identity: Identity },
+    /// The `paths` of the repository are not shared with the group of the
+    /// environment as configured, so its other operators may not be able to
+    /// write to them.
+    NotShared { paths: Vec<PathBuf> },
+    /// The repository is not configured with `core.sharedRepository=group`,
+    /// so what git creates in it may not be writable by the group.
+    NotSharedRepository,
+}
+
+impl RepoBlocker {
+    /// Whether the blocker keeps the group of the environment from
+    /// operating on the repository, rather than the current user.
+    pub fn is_sharing(&self) -> bool {
+        matches!(
+            self,
+            RepoBlocker::NotShared { .. } | RepoBlocker::NotSharedRepository
+        )
+    }
+}
+
+impl Display for RepoBlocker {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RepoBlocker::NotOwned => write!(
+                f,
+                "is owned by another user, add it to safe.directory to operate on it"
+            ),
+            RepoBlocker::NoIdentity { identity } => write!(
+                f,
+                "has no user.name and user.email to commit with, the manager commits as {identity}"
+            ),
+            RepoBlocker::NotShared { paths } => write!(
+                f,
+                "has {} paths not writable by the group, e.g. {}",
+                paths.len(),
+                paths
+                    .first()
+                    .map(|path| path.display().to_string())
+                    .unwrap_or_default()
+            ),
+            RepoBlocker::NotSharedRepository => {
+                write!(f, "is not configured with core.sharedRepository=group")
+            }
+        }
+    }
+}
+
+/// Damage found in one repository of the environment.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RepoDiagnosis {
+    /// Name of the repository.
+    pub name: String,
+    /// Path of the repository in the environment.
+    pub path: PathBuf,
+    pub damage: Vec<RepoDamage>,
+    /// Configuration keeping git from operating on the repository.
+    pub blockers: Vec<RepoBlocker>,
+}
+
+impl RepoDiagnosis {
+    /// Whether no damage was found.
+    pub fn is_healthy(&self) -> bool {
+        self.damage.is_empty()
+    }
+
+    /// Whether the repository has to be cloned again.
+    pub fn is_broken(&self) -> bool {
+        self.damage.iter().any(RepoDamage::is_broken)
+    }
+
+    /// Whether some operations would be refused on the repository.
+    pub fn is_blocked(&self) -> bool {
+        !self.blockers.is_empty()
+    }
+
+    /// Whether the repository is shared with the group of the environment
+    /// as configured.
+    pub fn is_shared(&self) -> bool {
+        !self.blockers.iter().any(RepoBlocker::is_sharing)
+    }
+
+    /// Lock files left in the repository.
+    pub fn locks(&self) -> &[PathBuf] {
+        self.damage
+            .iter()
+            .find_map(|damage| match damage {
+                RepoDamage::StaleLocks { locks } => Some(locks.as_slice()),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+}
+
+impl Display for RepoDiagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_healthy() && !self.is_blocked() {
+            return write!(f, "{}: healthy", self.name);
+        }
+        write!(
+            f,
+            "{}: {}",
+            self.name,
+            self.damage
+                .iter()
+                .map(|damage| damage.to_string())
+                .chain(self.blockers.iter().map(|blocker| blocker.to_string()))
+                .collect::<Vec<_>>()
+                .join("; ")
+        )
+    }
+}
+
+/// What repairing a damaged repository did.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "repair", rename_all = "snake_case")]
+pub enum Repair {
+    /// The lock files left were removed.
+    LocksRemoved { locks: Vec<PathBuf> },
+    /// The repository was removed and cloned again to `path`.
+    Recloned { path: PathBuf },
+}
+
+impl Display for Repair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Repair::LocksRemoved { locks } => write!(f, "removed {} lock files", locks.len()),
+            Repair::Recloned { path } => write!(f, "cloned again into {}", path.display()),
+        }
+    }
+}
+
+/// Git directory of the repository at `path`, if it has its own. That of
+/// worktrees is in the object store and is not inspected.
+pub(crate) fn git_dir(path: &Path) -> Option<PathBuf> {
+    let git_dir = path.join(".git");
+    git_dir.is_dir().then_some(git_dir)
+}
+
+/// Lock files in `git_dir`, outside of the object database.
+pub(crate) fn find_locks(git_dir: &Path) -> Vec<PathBuf> {
+    let mut locks = Vec::new();
+    let mut dirs = vec![git_dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                if path != git_dir.join("objects") {
+                    dirs.push(path);
+                }
+            } else if path
+                .extension()
+                .is_some_and(|extension| extension == "lock")
+            {
+                locks.push(path);
+            }
+        }
+    }
+    locks.sort();
+    locks
+}
+
+/// Whether the object database of `git_dir` has any loose object or pack.
+pub(crate) fn has_objects(git_dir: &Path) -> bool {
+    let Ok(entries) = read_dir(git_dir.join("objects")) else {
+        return false;
+    };
+    entries
+        .flatten()
+        .filter(|entry| entry.file_name() != "info")
+        .any(|entry| read_dir(entry.path()).is_ok_and(|mut objects| objects.next().is_some()))
+}
+
+/// Whether a git process is running, which may be holding the lock files
+/// of a repository. One is assumed to be when /proc cannot tell.
+pub(crate) fn git_process_running() -> bool {
+    let Ok(processes) = read_dir("/proc") else {
+        return true;
+    };
+    processes.flatten().any(|process| {
+        read_to_string(process.path().join("comm")).is_ok_and(|comm| comm.trim() == "git")
+    })
+}
@@ -1,11 +1,24 @@
+use crate::{error::ObsEnvError, git_backend::CloneFilter, hosts::HostType};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeSet,
+    fmt::{self, Display},
+    fs::read_to_string,
+    path::{Component, Path, PathBuf},
+};
+
+/// Repositories built into the tool, making up the default environment.
 #[derive(clap::ValueEnum, Clone, Debug)]
 #[clap(rename_all = "snake_case")]
 pub enum Repos {
     TsObservatoryControl,
     Atmospec,
+    Cwfs,
     Spectractor,
     SummitExtras,
     SummitUtils,
+    TsConfigAttcs,
     TsExternalscripts,
     TsObservingUtilities,
     TsStandardscripts,
@@ -14,13 +27,16 @@ pub enum Repos {
 }
 
 impl Repos {
+    /// Name of the repository, as used in the environment path.
     pub fn get_name(&self) -> &str {
         match self {
             Repos::TsObservatoryControl => "ts_observatory_control",
             Repos::Atmospec => "atmospec",
+            Repos::Cwfs => "cwfs",
             Repos::Spectractor => "Spectractor",
             Repos::SummitExtras => "summit_extras",
             Repos::SummitUtils => "summit_utils",
+            Repos::TsConfigAttcs => "ts_config_attcs",
             Repos::TsExternalscripts => "ts_externalscripts",
             Repos::TsObservingUtilities => "ts_observing_utilities",
             Repos::TsStandardscripts => "ts_standardscripts",
@@ -28,4 +44,419 @@ impl Repos {
             Repos::TsConfigOCS => "ts_config_ocs",
         }
     }
+
+    /// Url the repository is cloned from.
+    pub fn get_url(&self) -> &str {
+        match self {
+            Repos::TsObservatoryControl => "https://github.com/lsst-ts/ts_observatory_control",
+            Repos::Atmospec => "https://github.com/lsst/atmospec",
+            Repos::Cwfs => "https://github.com/lsst-ts/cwfs",
+            Repos::Spectractor => "https://github.com/lsst-dm/Spectractor",
+            Repos::SummitExtras => "https://github.com/lsst-sitcom/summit_extras",
+            Repos::SummitUtils => "https://github.com/lsst-sitcom/summit_utils",
+            Repos::TsConfigAttcs => "https://github.com/lsst-ts/ts_config_attcs",
+            Repos::TsExternalscripts => "https://github.com/lsst-ts/ts_externalscripts",
+            Repos::TsObservingUtilities => "https://github.com/lsst-ts/ts_observing_utilities",
+            Repos::TsStandardscripts => "https://github.com/lsst-ts/ts_standardscripts",
+            Repos::TsWep => "https://github.com/lsst-ts/ts_wep",
+            Repos::TsConfigOCS => "https://github.com/lsst-ts/ts_config_ocs",
+        }
+    }
+
+    /// Branch checked out after cloning the repository.
+    pub fn get_default_branch(&self) -> &str {
+        match self {
+            Repos::Atmospec | Repos::SummitExtras | Repos::SummitUtils => "main",
+            Repos::Cwfs | Repos::Spectractor => "master",
+            Repos::TsObservatoryControl
+            | Repos::TsConfigAttcs
+            | Repos::TsExternalscripts
+            | Repos::TsObservingUtilities
+            | Repos::TsStandardscripts
+            | Repos::TsWep
+            | Repos::TsConfigOCS => "develop",
+        }
+    }
+
+    /// Look up a built-in repository by name.
+    pub fn from_name(name: &str) -> Option<Repos> {
+        <Repos as clap::ValueEnum>::value_variants()
+            .iter()
+            .find(|repo| repo.get_name() == name)
+            .cloned()
+    }
+}
+
+/// Changes to the url, default branch or clone of a repository, e.g. from
+/// a configuration file:
+///
+/// ```toml
+/// [overrides.ts_wep]
+/// url = "git@github.com:lsst-ts/ts_wep.git"
+/// default_branch = "main"
+///
+/// [overrides.ts_config_ocs]
+/// clone_depth = 1
+/// clone_filter = "blobless"
+/// ```
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RepoOverride {
+    /// Url to clone from instead of the configured one.
+    pub url: Option<String>,
+    /// Branch to check out after cloning instead of the configured one.
+    pub default_branch: Option<String>,
+    /// Commits of history to clone instead of the configured number.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clone_depth: Option<u32>,
+    /// Partial clone to make instead of the configured one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clone_filter: Option<CloneFilter>,
+}
+
+/// A repository of the environment: where it is cloned from and how it is
+/// checked out.
+///
+/// The repository set can be given in a toml file, e.g.:
+///
+/// ```toml
+/// [[repositories]]
+/// name = "ts_wep"
+/// url = "https://github.com/lsst-ts/ts_wep"
+/// default_branch = "develop"
+/// groups = ["aos"]
+///
+/// [[repositories]]
+/// name = "ts_aos_utils"
+/// url = "git@git.example.org:ts/ts_aos_utils.git"
+/// host = "gitlab"
+/// path = "aos/ts_aos_utils"
+/// clone_depth = 1
+/// ```
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RepoSpec {
+    /// Name of the repository, as used in the environment path.
+    pub name: String,
+    /// Url the repository is cloned from.
+    pub url: String,
+    /// Branch checked out after cloning, instead of the remote default.
+    #[serde(default)]
+    pub default_branch: Option<String>,
+    /// Groups the repository belongs to.
+    #[serde(default)]
+    pub groups: Vec<String>,
+    /// Kind of server the repository is on, if it cannot be told from the
+    /// url.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<HostType>,
+    /// Subdirectory of the environment the repository is cloned into,
+    /// instead of its name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// Commits of history to clone, instead of the depth of the
+    /// environment; 0 clones all of it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clone_depth: Option<u32>,
+    /// Objects to leave out of the clone, instead of the filter of the
+    /// environment.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clone_filter: Option<CloneFilter>,
+}
+
+impl RepoSpec {
+    /// Repository `name` cloned from `url`.
+    pub fn new(name: &str, url: &str) -> RepoSpec {
+        RepoSpec {
+            name: name.to_owned(),
+            url: url.to_owned(),
+            default_branch: None,
+            groups: Vec::new(),
+            host: None,
+            path: None,
+            clone_depth: None,
+            clone_filter: None,
+        }
+    }
+
+    /// Subdirectory of the environment the repository is cloned into: its
+    /// configured path, or its name.
+    pub fn subdirectory(&self) -> &str {
+        self.path.as_deref().unwrap_or(&self.name)
+    }
+
+    /// Kind of server the repository is on, as configured or told from its
+    /// url.
+    pub fn host_type(&self) -> HostType {
+        self.host.unwrap_or_else(|| HostType::detect(&self.url))
+    }
+
+    /// Apply `repo_override` on top of the repository settings.
+    pub fn with_override(mut self, repo_override: &RepoOverride) -> RepoSpec {
+        if let Some(url) = &repo_override.url {
+            self.url = url.clone();
+        }
+        if let Some(default_branch) = &repo_override.default_branch {
+            self.default_branch = Some(default_branch.clone());
+        }
+        if let Some(clone_depth) = repo_override.clone_depth {
+            self.clone_depth = Some(clone_depth);
+        }
+        if let Some(clone_filter) = repo_override.clone_filter {
+            self.clone_filter = Some(clone_filter);
+        }
+        self
+    }
+
+    /// Load a repository set from a toml file, validating it.
+    pub fn load_file(path: &Path) -> Result<Vec<RepoSpec>, ObsEnvError> {
+        let repo_specs = RepoSpec::read_file(path)?;
+        validate_repo_specs(&repo_specs)?;
+        Ok(repo_specs)
+    }
+
+    /// Load a repository set from a toml file as is, for "ValidateConfig"
+    /// to check it.
+    pub fn read_file(path: &Path) -> Result<Vec<RepoSpec>, ObsEnvError> {
+        let content = read_to_string(path)
+            .map_err(|error| ObsEnvError::io(path, "read repositories file", error))?;
+        let repo_list: RepoList =
+            toml::from_str(&content).map_err(|error| ObsEnvError::InvalidConfig {
+                message: format!("{}: {error}", path.display()),
+            })?;
+        Ok(repo_list.repositories)
+    }
+}
+
+impl From<&Repos> for RepoSpec {
+    fn from(repo: &Repos) -> RepoSpec {
+        RepoSpec {
+            default_branch: Some(repo.get_default_branch().to_owned()),
+            ..RepoSpec::new(repo.get_name(), repo.get_url())
+        }
+    }
+}
+
+/// Content of a repositories file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RepoList {
+    repositories: Vec<RepoSpec>,
+}
+
+/// Where the repository set of an environment comes from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RepoSource {
+    /// The repositories built into the tool.
+    BuiltIn,
+    /// A repositories or configuration file.
+    File(PathBuf),
+    /// A list given through the library API.
+    Custom,
+}
+
+impl Display for RepoSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RepoSource::BuiltIn => write!(f, "built-in list"),
+            RepoSource::File(path) => write!(f, "{}", path.display()),
+            RepoSource::Custom => write!(f, "custom list"),
+        }
+    }
+}
+
+/// Make sure a repository set is usable: it is not empty, names and
+/// subdirectories are unique, subdirectories are inside the environment
+/// and urls are well formed.
+pub fn validate_repo_specs(repo_specs: &[RepoSpec]) -> Result<(), ObsEnvError> {
+    let invalid = |message: String| Err(ObsEnvError::InvalidConfig { message });
+
+    if repo_specs.is_empty() {
+        return invalid("The environment needs at least one repository".to_owned());
+    }
+    let mut names = BTreeSet::new();
+    let mut subdirectories = BTreeSet::new();
+    for repo_spec in repo_specs {
+        if repo_spec.name.is_empty() {
+            return invalid("Repository names cannot be empty".to_owned());
+        }
+        if !names.insert(repo_spec.name.as_str()) {
+            return invalid(format!(
+                "Repository {} given more than once",
+                repo_spec.name
+            ));
+        }
+        if !is_valid_url(&repo_spec.url) {
+            return invalid(format!(
+                "Malformed url {} for repository {}",
+                repo_spec.url, repo_spec.name
+            ));
+        }
+        if !is_valid_subdirectory(repo_spec.subdirectory()) {
+            return invalid(format!(
+                "Path {} of repository {} is not a subdirectory of the environment",
+                repo_spec.subdirectory(),
+                repo_spec.name
+            ));
+        }
+        if !subdirectories.insert(repo_spec.subdirectory()) {
+            return invalid(format!(
+                "Path {} of repository {} is taken by another repository",
+                repo_spec.subdirectory(),
+                repo_spec.name
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Whether `url` can be cloned from: a url with a scheme, an scp-like
+/// `user@host:path` or an absolute local path.
+pub(crate) fn is_valid_url(url: &str) -> bool {
+    // These should never fail because the expressions are valid.
+    let with_scheme = Regex::new(r"^[a-zA-Z][a-zA-Z0-9+.-]*://\S+$").unwrap();
+    let scp_like = Regex::new(r"^[^\s/@:]+@[^\s/:]+:\S+$").unwrap();
+
+    with_scheme.is_match(url) || scp_like.is_match(url) || Path::new(url).is_absolute()
+}
+
+/// Whether `path` is a relative path going down into the environment only.
+pub(crate) fn is_valid_subdirectory(path: &str) -> bool {
+    !path.is_empty()
+        && Path::new(path)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{validate_repo_specs, RepoOverride, RepoSpec, Repos};
+    use crate::{git_backend::CloneFilter, hosts::HostType};
+    use clap::ValueEnum;
+
+    #[test]
+    fn test_repos_accessors() {
+        let expected = [
+            (
+                "ts_observatory_control",
+                "https://github.com/lsst-ts/ts_observatory_control",
+                "develop",
+            ),
+            ("atmospec", "https://github.com/lsst/atmospec", "main"),
+            ("cwfs", "https://github.com/lsst-ts/cwfs", "master"),
+            (
+                "Spectractor",
+                "https://github.com/lsst-dm/Spectractor",
+                "master",
+            ),
+            (
+                "summit_extras",
+                "https://github.com/lsst-sitcom/summit_extras",
+                "main",
+            ),
+            (
+                "summit_utils",
+                "https://github.com/lsst-sitcom/summit_utils",
+                "main",
+            ),
+            (
+                "ts_config_attcs",
+                "https://github.com/lsst-ts/ts_config_attcs",
+                "develop",
+            ),
+            (
+                "ts_externalscripts",
+                "https://github.com/lsst-ts/ts_externalscripts",
+                "develop",
+            ),
+            (
+                "ts_observing_utilities",
+                "https://github.com/lsst-ts/ts_observing_utilities",
+                "develop",
+            ),
+            (
+                "ts_standardscripts",
+                "https://github.com/lsst-ts/ts_standardscripts",
+                "develop",
+            ),
+            ("ts_wep", "https://github.com/lsst-ts/ts_wep", "develop"),
+            (
+                "ts_config_ocs",
+                "https://github.com/lsst-ts/ts_config_ocs",
+                "develop",
+            ),
+        ];
+
+        let repos = Repos::value_variants();
+        assert_eq!(repos.len(), expected.len());
+        for (repo, (name, url, default_branch)) in repos.iter().zip(expected) {
+            assert_eq!(repo.get_name(), name);
+            assert_eq!(repo.get_url(), url);
+            assert_eq!(repo.get_default_branch(), default_branch);
+            assert_eq!(Repos::from_name(name).unwrap().get_name(), name);
+        }
+        assert!(Repos::from_name("ts_unknown").is_none());
+
+        let repo_specs: Vec<RepoSpec> = repos.iter().map(RepoSpec::from).collect();
+        assert!(validate_repo_specs(&repo_specs).is_ok());
+    }
+
+    #[test]
+    fn test_repo_spec_with_override() {
+        let repo_spec = RepoSpec::from(&Repos::TsWep);
+
+        assert_eq!(
+            repo_spec.clone().with_override(&RepoOverride::default()),
+            repo_spec
+        );
+
+        let overridden = repo_spec.with_override(&RepoOverride {
+            url: Some("git@github.com:tribeiro/ts_wep.git".to_owned()),
+            ..RepoOverride::default()
+        });
+        assert_eq!(overridden.url, "git@github.com:tribeiro/ts_wep.git");
+        assert_eq!(overridden.default_branch.as_deref(), Some("develop"));
+
+        let overridden = overridden.with_override(&RepoOverride {
+            default_branch: Some("main".to_owned()),
+            clone_depth: Some(1),
+            clone_filter: Some(CloneFilter::Treeless),
+            ..RepoOverride::default()
+        });
+        assert_eq!(overridden.url, "git@github.com:tribeiro/ts_wep.git");
+        assert_eq!(overridden.default_branch.as_deref(), Some("main"));
+        assert_eq!(overridden.clone_depth, Some(1));
+        assert_eq!(overridden.clone_filter, Some(CloneFilter::Treeless));
+    }
+    #[test]
+    fn test_repo_spec_path() {
+        let repo_spec: RepoSpec = toml::from_str(
+            "name = \"ts_wep\"\nurl = \"https://github.com/lsst-ts/ts_wep\"\npath = \"aos/ts_wep\"\n",
+        )
+        .unwrap();
+        assert_eq!(repo_spec.subdirectory(), "aos/ts_wep");
+        assert_eq!(RepoSpec::from(&Repos::Cwfs).subdirectory(), "cwfs");
+        assert!(validate_repo_specs(&[repo_spec.clone(), RepoSpec::from(&Repos::Cwfs)]).is_ok());
+
+        for path in ["/data/ts_wep", "../ts_wep", "aos/../../ts_wep", "cwfs"] {
+            let repo_spec = RepoSpec {
+                path: Some(path.to_owned()),
+                ..repo_spec.clone()
+            };
+            assert!(
+                validate_repo_specs(&[repo_spec, RepoSpec::from(&Repos::Cwfs)]).is_err(),
+                "{path}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_repo_spec_host_type() {
+        let repo_spec: RepoSpec = toml::from_str(
+            "name = \"ts_aos_utils\"\nurl = \"git@git.example.org:ts/ts_aos_utils.git\"\nhost = \"gitlab\"\n",
+        )
+        .unwrap();
+        assert_eq!(repo_spec.host_type(), HostType::GitLab);
+        assert_eq!(RepoSpec::from(&Repos::TsWep).host_type(), HostType::GitHub);
+    }
 }
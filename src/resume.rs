@@ -0,0 +1,238 @@
+//! Outcome of each repository of the last Setup or Reset that failed, kept
+//! in `.obs_env/resume.json` for `--resume` to only redo the repositories
+//! that failed or were not attempted.
+//!
+//! The state records the action and the arguments it was run with, and a
+//! run resumed with other ones is rejected, since the repositories that
+//! succeeded would not be redone with them.
+use crate::{
+    error::ObsEnvError,
+    observing_environment::{serialized, write_atomically},
+    setup::SetupReport,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::{create_dir_all, read_to_string, remove_file},
+    io::ErrorKind,
+    path::Path,
+};
+
+/// Resume state of the environment, in `.obs_env`.
+pub const RESUME_FILE: &str = "resume.json";
+
+/// Outcome of an action on a repository.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "outcome")]
+pub enum RepoOutcome {
+    Succeeded,
+    Failed {
+        error: String,
+    },
+    /// The action stopped at the failure of another repository first.
+    NotAttempted,
+}
+
+impl From<&ObsEnvError> for RepoOutcome {
+    fn from(error: &ObsEnvError) -> Self {
+        match error {
+            ObsEnvError::NotAttempted { .. } => RepoOutcome::NotAttempted,
+            error => RepoOutcome::Failed {
+                error: crate::error::report(error),
+            },
+        }
+    }
+}
+
+/// Outcomes of an action run with `parameters`, by repository name.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct ResumeState {
+    /// Action, e.g. "reset".
+    pub action: String,
+    /// Arguments changing what the action does, by name.
+    pub parameters: BTreeMap<String, String>,
+    pub repos: BTreeMap<String, RepoOutcome>,
+}
+
+impl ResumeState {
+    /// State of `action` run with `parameters`, with no outcome yet.
+    pub fn new(action: &str, parameters: BTreeMap<String, String>) -> ResumeState {
+        ResumeState {
+            action: action.to_owned(),
+            parameters,
+            repos: BTreeMap::new(),
+        }
+    }
+
+    /// State read from `path`, or none if there is no failed action to
+    /// resume.
+    pub fn load(path: &Path) -> Result<Option<ResumeState>, ObsEnvError> {
+        let content = match read_to_string(path) {
+            Ok(content) => content,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(ObsEnvError::io(path, "read resume state", error)),
+        };
+        serde_json::from_str(&content)
+            .map(Some)
+            .map_err(|error| ObsEnvError::InvalidConfig {
+                message: format!("{}: {error}", path.display()),
+            })
+    }
+
+    /// Write the state to `path`, atomically, or remove it once every
+    /// repository succeeded.
+    pub fn save(&self, path: &Path) -> Result<(), ObsEnvError> {
+        if self.is_complete() {
+            return match remove_file(path) {
+                Err(error) if error.kind() != ErrorKind::NotFound => {
+                    Err(ObsEnvError::io(path, "remove", error))
+                }
+                _ => Ok(()),
+            };
+        }
+        if let Some(dir) = path.parent() {
+            create_dir_all(dir).map_err(|error| ObsEnvError::io(dir, "create", error))?;
+        }
+        write_atomically(path, &serialized(serde_json::to_string_pretty(self)))
+    }
+
+    /// Fail unless the state is that of `action` run with `parameters`,
+    /// naming the arguments that differ.
+    pub fn check(
+        &self,
+        action: &str,
+        parameters: &BTreeMap<String, String>,
+    ) -> Result<(), ObsEnvError> {
+        let rejected = |difference: String| ObsEnvError::InvalidConfig {
+            message: format!("Cannot resume: {difference}. Run without --resume to start over."),
+        };
+        if self.action != action {
+            return Err(rejected(format!(
+                "the failed action is {}, not {action}",
+                self.action
+            )));
+        }
+        let names: BTreeSet<&String> = self.parameters.keys().chain(parameters.keys()).collect();
+        let value = |parameters: &BTreeMap<String, String>, name: &String| {
+            parameters.get(name).cloned().unwrap_or_default()
+        };
+        let differences: Vec<String> = names
+            .into_iter()
+            .filter(|name| self.parameters.get(*name) != parameters.get(*name))
+            .map(|name| {
+                format!(
+                    "{name} was \"{}\", not \"{}\"",
+                    value(&self.parameters, name),
+                    value(parameters, name)
+                )
+            })
+            .collect();
+        if !differences.is_empty() {
+            return Err(rejected(format!(
+                "{} was run with other arguments: {}",
+                self.action,
+                differences.join(", ")
+            )));
+        }
+        Ok(())
+    }
+
+    /// Repositories that failed or were not attempted, sorted.
+    pub fn pending(&self) -> impl Iterator<Item = &str> {
+        self.repos
+            .iter()
+            .filter(|(_, outcome)| **outcome != RepoOutcome::Succeeded)
+            .map(|(repo_name, _)| repo_name.as_str())
+    }
+
+    /// Whether every repository succeeded.
+    pub fn is_complete(&self) -> bool {
+        self.pending().next().is_none()
+    }
+
+    /// Record the outcomes of the repositories of a setup.
+    pub fn record_setup(&mut self, report: &SetupReport) {
+        for repo in report.repos.iter() {
+            let outcome = repo
+                .error()
+                .map_or(RepoOutcome::Succeeded, RepoOutcome::from);
+            self.repos.insert(repo.name.clone(), outcome);
+        }
+    }
+
+    /// Record the outcomes of the repositories `repo_names` an action
+    /// failed on with `errors`, as successes for those with no error.
+    ///
+    /// Nothing is recorded if an error is not about a repository, as it can
+    /// have stopped the action before it attempted any.
+    pub fn record_errors<'a>(
+        &mut self,
+        repo_names: impl IntoIterator<Item = &'a str>,
+        errors: &[ObsEnvError],
+    ) {
+        if errors.iter().any(|error| error.repo().is_none()) {
+            return;
+        }
+        for repo_name in repo_names {
+            self.repos
+                .insert(repo_name.to_owned(), RepoOutcome::Succeeded);
+        }
+        for error in errors {
+            if let Some(repo_name) = error.repo() {
+                self.repos.insert(repo_name.to_owned(), error.into());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RepoOutcome, ResumeState};
+    use crate::error::ObsEnvError;
+    use std::collections::BTreeMap;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_resume_state() {
+        let root = TempDir::new().unwrap();
+        let path = root.path().join("resume.json");
+        assert_eq!(ResumeState::load(&path).unwrap(), None);
+
+        let parameters = BTreeMap::from([("base_branch".to_owned(), "main".to_owned())]);
+        let mut state = ResumeState::new("reset", parameters.clone());
+        state.record_errors(
+            ["cwfs", "ts_wep", "ts_xml"],
+            &[
+                ObsEnvError::DirtyWorkingTree {
+                    repo: "cwfs".to_owned(),
+                },
+                ObsEnvError::NotAttempted {
+                    repo: "ts_xml".to_owned(),
+                    operation: "reset".to_owned(),
+                },
+            ],
+        );
+        assert_eq!(state.pending().collect::<Vec<_>>(), ["cwfs", "ts_xml"]);
+        assert_eq!(state.repos["ts_xml"], RepoOutcome::NotAttempted);
+        state.save(&path).unwrap();
+        assert_eq!(ResumeState::load(&path).unwrap().as_ref(), Some(&state));
+
+        assert!(state.check("reset", &parameters).is_ok());
+        assert!(state.check("setup", &parameters).is_err());
+        let error = state
+            .check(
+                "reset",
+                &BTreeMap::from([("base_branch".to_owned(), "develop".to_owned())]),
+            )
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid configuration: Cannot resume: reset was run with other arguments: base_branch was \"main\", not \"develop\". Run without --resume to start over."
+        );
+
+        state.record_errors(["cwfs", "ts_xml"], &[]);
+        assert!(state.is_complete());
+        state.save(&path).unwrap();
+        assert!(!path.exists());
+    }
+}
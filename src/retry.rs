@@ -0,0 +1,466 @@
+//! Retrying the git operations that reach a remote, which fail now and
+//! then at the summit for reasons that are gone a few seconds later:
+//! [`RetryBackend`] carries them out again, waiting longer each time,
+//! before reporting the failure.
+use crate::git_backend::{
+    CloneOptions, CommitInfo, CommitQuery, CommitSummary, GitBackend, Identity, RepoStatus,
+    TransferObserver,
+};
+use git2::{Error, ErrorClass, ErrorCode};
+use std::{fs::remove_dir_all, path::Path, thread::sleep, time::Duration};
+
+/// Most times the delay between two attempts is doubled.
+const MAX_BACKOFF_DOUBLINGS: u32 = 6;
+
+/// How many times, and how patiently, the network operations are retried.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// Attempts made after the first one fails, 0 not to retry.
+    pub retries: u32,
+    /// Delay before the first retry, doubled for each of the next ones.
+    pub backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Delay before retrying after `failures` failed attempts, the longest
+    /// one a [`Duration`] holds if the backoff is too long to double.
+    pub fn delay(&self, failures: u32) -> Duration {
+        let factor = 2u32.pow(failures.saturating_sub(1).min(MAX_BACKOFF_DOUBLINGS));
+        self.backoff.checked_mul(factor).unwrap_or(Duration::MAX)
+    }
+}
+
+/// Parts of the messages of the network failures that may not happen
+/// again, as reported by libgit2, the system or libssh2, in lower case.
+const TRANSIENT_MESSAGES: &[&str] = &[
+    "timed out",
+    "timeout",
+    "connection reset",
+    "connection refused",
+    "connection aborted",
+    "connection closed",
+    "failed to connect",
+    "could not resolve",
+    "failed to resolve",
+    "temporary failure in name resolution",
+    "network is unreachable",
+    "no route to host",
+    "broken pipe",
+    "early eof",
+    "unexpected eof",
+];
+
+/// Whether `error` is a network failure that may not happen again, e.g. a
+/// connection reset, a timeout or an HTTP 5xx status, as opposed to e.g. a
+/// missing reference, an unsupported url or rejected credentials.
+pub fn is_transient(error: &Error) -> bool {
+    match error.code() {
+        ErrorCode::Auth | ErrorCode::Certificate => return false,
+        ErrorCode::Timeout => return true,
+        _ => {}
+    }
+    if !matches!(
+        error.class(),
+        ErrorClass::Net | ErrorClass::Http | ErrorClass::Ssh | ErrorClass::Os
+    ) {
+        return false;
+    }
+    let message = error.message().to_lowercase();
+    // libgit2 reports the statuses it has no better error for as e.g.
+    // "unexpected http status code: 503".
+    let status = message
+        .split_once("status code: ")
+        .and_then(|(_, rest)| rest.get(..3))
+        .and_then(|code| code.parse::<u16>().ok());
+    if let Some(status) = status {
+        return status >= 500 || status == 429;
+    }
+    TRANSIENT_MESSAGES
+        .iter()
+        .any(|transient| message.contains(transient))
+}
+
+/// [`GitBackend`] retrying the operations of `inner` that reach a remote
+/// when they fail with a [transient](is_transient) error, as set by its
+/// [`RetryPolicy`]. The other operations are passed to `inner` as they are.
+pub struct RetryBackend {
+    inner: Box<dyn GitBackend>,
+    policy: RetryPolicy,
+}
+
+impl RetryBackend {
+    /// Backend retrying the network operations of `inner` with `policy`.
+    pub fn new(inner: Box<dyn GitBackend>, policy: RetryPolicy) -> RetryBackend {
+        RetryBackend { inner, policy }
+    }
+
+    /// Carry out `op`, the `operation` of `target`, again after each
+    /// transient failure until it succeeds or the retries are used up.
+    fn retry<T>(
+        &self,
+        operation: &str,
+        target: &str,
+        mut op: impl FnMut() -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let mut failures = 0;
+        loop {
+            match op() {
+                Err(error) if failures < self.policy.retries && is_transient(&error) => {
+                    failures += 1;
+                    let delay = self.policy.delay(failures);
+                    log::warn!(
+                        "{operation} of {target} failed, retrying in {}s ({failures}/{}): {}",
+                        delay.as_secs_f32(),
+                        self.policy.retries,
+                        error.message()
+                    );
+                    sleep(delay);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Retry `clone`, a clone into `path`, removing what a failed attempt
+    /// left there if `path` did not exist before.
+    fn retry_clone(&self, path: &Path, clone: impl Fn() -> Result<(), Error>) -> Result<(), Error> {
+        let existed = path.exists();
+        self.retry("Clone", &path.display().to_string(), || {
+            let result = clone();
+            if result.is_err() && !existed && path.exists() {
+                if let Err(error) = remove_dir_all(path) {
+                    log::warn!(
+                        "Cannot remove {} after a failed clone: {error}",
+                        path.display()
+                    );
+                }
+            }
+            result
+        })
+    }
+}
+
+impl GitBackend for RetryBackend {
+    fn open(&self, path: &Path) -> Result<(), Error> {
+        self.inner.open(path)
+    }
+
+    fn is_empty(&self, path: &Path) -> Result<bool, Error> {
+        self.inner.is_empty(path)
+    }
+
+    fn clone(
+        &self,
+        url: &str,
+        path: &Path,
+        bare: bool,
+        options: CloneOptions,
+        progress: &dyn TransferObserver,
+    ) -> Result<(), Error> {
+        self.retry_clone(path, || {
+            self.inner.clone(url, path, bare, options, progress)
+        })
+    }
+
+    fn clone_branch(
+        &self,
+        url: &str,
+        path: &Path,
+        branch: &str,
+        options: CloneOptions,
+        progress: &dyn TransferObserver,
+    ) -> Result<(), Error> {
+        self.retry_clone(path, || {
+            self.inner
+                .clone_branch(url, path, branch, options, progress)
+        })
+    }
+
+    fn fetch(
+        &self,
+        path: &Path,
+        refspecs: &[&str],
+        download_tags: bool,
+        progress: &dyn TransferObserver,
+    ) -> Result<(), Error> {
+        self.retry("Fetch", &path.display().to_string(), || {
+            self.inner.fetch(path, refspecs, download_tags, progress)
+        })
+    }
+
+    fn is_shallow(&self, path: &Path) -> Result<bool, Error> {
+        self.inner.is_shallow(path)
+    }
+
+    fn deepen(
+        &self,
+        path: &Path,
+        depth: Option<u32>,
+        progress: &dyn TransferObserver,
+    ) -> Result<(), Error> {
+        self.retry("Fetch", &path.display().to_string(), || {
+            self.inner.deepen(path, depth, progress)
+        })
+    }
+
+    fn checkout_branch(&self, path: &Path, branch: &str) -> Result<(), Error> {
+        self.inner.checkout_branch(path, branch)
+    }
+
+    fn fast_forward(&self, path: &Path, branch: &str) -> Result<(), Error> {
+        self.inner.fast_forward(path, branch)
+    }
+
+    fn reset(&self, path: &Path, revision: &str, branch: Option<&str>) -> Result<(), Error> {
+        self.inner.reset(path, revision, branch)
+    }
+
+    fn rev_parse(&self, path: &Path, spec: &str) -> Result<String, Error> {
+        self.inner.rev_parse(path, spec)
+    }
+
+    fn status(&self, path: &Path) -> Result<RepoStatus, Error> {
+        self.inner.status(path)
+    }
+
+    fn abort_in_progress(&self, path: &Path) -> Result<(), Error> {
+        self.inner.abort_in_progress(path)
+    }
+
+    fn list_refs(&self, path: &Path, glob: &str) -> Result<Vec<String>, Error> {
+        self.inner.list_refs(path, glob)
+    }
+
+    fn describe(&self, path: &Path) -> Result<String, Error> {
+        self.inner.describe(path)
+    }
+
+    fn current_branch(&self, path: &Path) -> Result<Option<String>, Error> {
+        self.inner.current_branch(path)
+    }
+
+    fn remote_head(&self, path: &Path) -> Result<Option<String>, Error> {
+        self.inner.remote_head(path)
+    }
+
+    fn local_commits(&self, path: &Path) -> Result<usize, Error> {
+        self.inner.local_commits(path)
+    }
+
+    fn check_objects(&self, path: &Path) -> Result<Vec<String>, Error> {
+        self.inner.check_objects(path)
+    }
+
+    fn commits_only_in(
+        &self,
+        path: &Path,
+        revision: &str,
+        hidden: &[&str],
+    ) -> Result<Vec<String>, Error> {
+        self.inner.commits_only_in(path, revision, hidden)
+    }
+
+    fn ahead_behind(
+        &self,
+        path: &Path,
+        local: &str,
+        upstream: &str,
+    ) -> Result<(usize, usize), Error> {
+        self.inner.ahead_behind(path, local, upstream)
+    }
+
+    fn commit_log(&self, path: &Path, from: &str, to: &str) -> Result<Vec<CommitSummary>, Error> {
+        self.inner.commit_log(path, from, to)
+    }
+
+    fn search_commits(&self, path: &Path, query: &CommitQuery) -> Result<Vec<CommitInfo>, Error> {
+        self.inner.search_commits(path, query)
+    }
+
+    fn commit_time(&self, path: &Path, revision: &str) -> Result<u64, Error> {
+        self.inner.commit_time(path, revision)
+    }
+
+    fn commit_before(
+        &self,
+        path: &Path,
+        revision: &str,
+        time: u64,
+    ) -> Result<Option<String>, Error> {
+        self.inner.commit_before(path, revision, time)
+    }
+
+    fn create_branch(&self, path: &Path, branch: &str, revision: &str) -> Result<(), Error> {
+        self.inner.create_branch(path, branch, revision)
+    }
+
+    fn delete_branch(&self, path: &Path, branch: &str) -> Result<(), Error> {
+        self.inner.delete_branch(path, branch)
+    }
+
+    fn delete_reference(&self, path: &Path, name: &str) -> Result<(), Error> {
+        self.inner.delete_reference(path, name)
+    }
+
+    fn remote_branches(&self, path: &Path) -> Result<Vec<String>, Error> {
+        self.retry(
+            "Listing the remote branches",
+            &path.display().to_string(),
+            || self.inner.remote_branches(path),
+        )
+    }
+
+    fn stale_remote_refs(&self, path: &Path) -> Result<Vec<String>, Error> {
+        self.retry(
+            "Listing the remote branches",
+            &path.display().to_string(),
+            || self.inner.stale_remote_refs(path),
+        )
+    }
+
+    fn ls_remote(&self, url: &str) -> Result<Vec<String>, Error> {
+        self.retry("Listing the references", url, || self.inner.ls_remote(url))
+    }
+
+    fn read_file(&self, path: &Path, revision: &str, file: &Path) -> Result<String, Error> {
+        self.inner.read_file(path, revision, file)
+    }
+
+    fn remote_url(&self, path: &Path) -> Result<Option<String>, Error> {
+        self.inner.remote_url(path)
+    }
+
+    fn set_remote_url(&self, path: &Path, url: &str) -> Result<(), Error> {
+        self.inner.set_remote_url(path, url)
+    }
+
+    fn identity(&self, path: &Path) -> Result<Option<Identity>, Error> {
+        self.inner.identity(path)
+    }
+
+    fn config(&self, path: &Path, name: &str) -> Result<Option<String>, Error> {
+        self.inner.config(path, name)
+    }
+
+    fn set_config(&self, path: &Path, name: &str, value: &str) -> Result<(), Error> {
+        self.inner.set_config(path, name, value)
+    }
+
+    fn push(&self, path: &Path, url: &str, refspecs: &[String]) -> Result<(), Error> {
+        // Pushing the same refspecs again is harmless if the failed attempt
+        // got some of them through.
+        self.retry("Push", url, || self.inner.push(path, url, refspecs))
+    }
+
+    fn diff_head(&self, path: &Path) -> Result<String, Error> {
+        self.inner.diff_head(path)
+    }
+
+    fn apply_patch(&self, path: &Path, patch: &str) -> Result<(), Error> {
+        self.inner.apply_patch(path, patch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_transient, RetryBackend, RetryPolicy};
+    use crate::{
+        git_backend::{CloneOptions, GitBackend, TransferProgress},
+        testing::FakeBackend,
+    };
+    use git2::{Error, ErrorClass, ErrorCode};
+    use std::{path::Path, time::Duration};
+
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    #[test]
+    fn test_retry_policy() {
+        let policy = RetryPolicy {
+            retries: 10,
+            backoff: Duration::from_secs(2),
+        };
+        let delays: Vec<u64> = (1..=9).map(|n| policy.delay(n).as_secs()).collect();
+        assert_eq!(delays, [2, 4, 8, 16, 32, 64, 128, 128, 128]);
+
+        assert!(is_transient(&Error::new(
+            ErrorCode::GenericError,
+            ErrorClass::Net,
+            "connection reset by peer"
+        )));
+        assert!(!is_transient(&Error::new(
+            ErrorCode::Auth,
+            ErrorClass::Net,
+            "authentication required"
+        )));
+        assert!(!is_transient(&Error::new(
+            ErrorCode::NotFound,
+            ErrorClass::Reference,
+            "reference not found"
+        )));
+        assert!(!is_transient(&Error::new(
+            ErrorCode::GenericError,
+            ErrorClass::Net,
+            "unsupported URL protocol"
+        )));
+        assert!(is_transient(&Error::new(
+            ErrorCode::GenericError,
+            ErrorClass::Http,
+            "unexpected http status code: 503"
+        )));
+        assert!(!is_transient(&Error::new(
+            ErrorCode::GenericError,
+            ErrorClass::Http,
+            "unexpected http status code: 404"
+        )));
+        assert!(is_transient(&Error::new(
+            ErrorCode::GenericError,
+            ErrorClass::Ssh,
+            "Failed to start SSH session: Timed out waiting on socket"
+        )));
+
+        let patient = RetryPolicy {
+            retries: 10,
+            backoff: Duration::from_secs(u64::MAX / 4),
+        };
+        assert_eq!(patient.delay(9), Duration::MAX);
+    }
+
+    #[test]
+    fn test_retry_backend() -> TestResult {
+        let fake = FakeBackend::new();
+        let url = "https://github.com/lsst-ts/ts_wep";
+        fake.set_branch(url, "main", "1111aaaa");
+        let backend = |retries| {
+            let policy = RetryPolicy {
+                retries,
+                backoff: Duration::ZERO,
+            };
+            RetryBackend::new(Box::new(Clone::clone(&fake)), policy)
+        };
+        let path = Path::new("/obs-env/ts_wep");
+        let progress = |_: &TransferProgress| {};
+
+        fake.set_network_failures(2);
+        let error = backend(1)
+            .clone(url, path, false, CloneOptions::default(), &progress)
+            .unwrap_err();
+        assert!(is_transient(&error));
+        backend(1).clone(url, path, false, CloneOptions::default(), &progress)?;
+        assert_eq!(fake.head(path).as_deref(), Some("1111aaaa"));
+
+        fake.set_branch(url, "main", "2222bbbb");
+        fake.set_network_failures(3);
+        backend(3).fetch(
+            path,
+            &["+refs/heads/main:refs/remotes/origin/main"],
+            false,
+            &progress,
+        )?;
+        assert_eq!(backend(0).rev_parse(path, "origin/main")?, "2222bbbb");
+
+        fake.set_network_failures(1);
+        assert!(backend(0).ls_remote(url).is_err());
+        assert!(backend(0).ls_remote(url).is_ok());
+        Ok(())
+    }
+}
@@ -0,0 +1,419 @@
+//! Validation of manifest files against the structure of
+//! [`EnvironmentManifest`](crate::EnvironmentManifest), before any of them
+//! is deserialized, so a mistake in a hand-edited manifest is reported
+//! with its line and what was expected.
+use crate::manifest::MANIFEST_FORMAT_VERSION;
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+    ops::Range,
+};
+use toml::{
+    de::{DeTable, DeValue},
+    Spanned,
+};
+
+/// Type of a field of the manifest.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Integer,
+    String,
+    Boolean,
+    Array,
+    Table,
+}
+
+impl Kind {
+    fn matches(&self, value: &DeValue) -> bool {
+        matches!(
+            (self, value),
+            (Kind::Integer, DeValue::Integer(_))
+                | (Kind::String, DeValue::String(_))
+                | (Kind::Boolean, DeValue::Boolean(_))
+                | (Kind::Array, DeValue::Array(_))
+                | (Kind::Table, DeValue::Table(_))
+        )
+    }
+}
+
+impl Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Kind::Integer => write!(f, "an integer"),
+            Kind::String => write!(f, "a string"),
+            Kind::Boolean => write!(f, "a boolean"),
+            Kind::Array => write!(f, "an array"),
+            Kind::Table => write!(f, "a table"),
+        }
+    }
+}
+
+/// Field of a table of the manifest: its name, type and whether it is
+/// required.
+type Field = (&'static str, Kind, bool);
+
+const MANIFEST_FIELDS: &[Field] = &[
+    ("format_version", Kind::Integer, false),
+    ("created", Kind::Integer, true),
+    ("env_path", Kind::String, true),
+    ("repos", Kind::Array, true),
+    ("overrides", Kind::Table, false),
+    ("python_env", Kind::Table, false),
+];
+
+const REPO_FIELDS: &[Field] = &[
+    ("name", Kind::String, true),
+    ("describe", Kind::String, true),
+    ("url", Kind::String, false),
+    ("branch", Kind::String, false),
+    ("sha", Kind::String, false),
+    ("dirty", Kind::Boolean, false),
+    ("dirty_files", Kind::Array, false),
+    ("untracked", Kind::Integer, false),
+];
+
+const PYTHON_ENV_FIELDS: &[Field] = &[
+    ("pip_freeze", Kind::Array, true),
+    ("conda_env", Kind::String, false),
+];
+
+/// Problem found in a manifest, at `line` of field `field`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ManifestProblem {
+    /// Line of the problem, from 1.
+    pub line: usize,
+    /// Path of the field, e.g. "repos[2].sha", empty for the whole file.
+    pub field: String,
+    pub message: String,
+}
+
+impl Display for ManifestProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.field.is_empty() {
+            write!(f, "line {}: {}", self.line, self.message)
+        } else {
+            write!(f, "line {}: {}: {}", self.line, self.field, self.message)
+        }
+    }
+}
+
+/// Result of [`validate`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ManifestValidation {
+    /// Problems that keep the manifest from being used.
+    pub errors: Vec<ManifestProblem>,
+    /// Problems that do not, like unknown fields, which are ignored.
+    pub warnings: Vec<ManifestProblem>,
+}
+
+impl ManifestValidation {
+    /// Whether the manifest can be used.
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// Check the toml `content` of a manifest.
+///
+/// ```
+/// use ts_observing_environment::schema::validate;
+///
+/// let validation = validate("created = 0\nenv_path = \"/obs-env\"\nrepos = [{ name = \"ts_wep\" }]\n");
+/// assert_eq!(
+///     validation.errors[0].to_string(),
+///     "line 3: repos[0].describe: missing, expected a string"
+/// );
+/// ```
+pub fn validate(content: &str) -> ManifestValidation {
+    let mut checker = Checker {
+        content,
+        validation: ManifestValidation::default(),
+    };
+    match DeTable::parse(content) {
+        Ok(manifest) => checker.check_manifest(&manifest),
+        Err(error) => {
+            let line = error.span().map(|span| checker.line(&span)).unwrap_or(1);
+            checker.error(line, "", error.message().to_owned());
+        }
+    }
+    checker.validation
+}
+
+struct Checker<'c> {
+    content: &'c str,
+    validation: ManifestValidation,
+}
+
+impl Checker<'_> {
+    /// Line of the start of `span`.
+    fn line(&self, span: &Range<usize>) -> usize {
+        self.content[..span.start.min(self.content.len())]
+            .matches('\n')
+            .count()
+            + 1
+    }
+
+    fn error(&mut self, line: usize, field: &str, message: String) {
+        self.validation.errors.push(ManifestProblem {
+            line,
+            field: field.to_owned(),
+            message,
+        });
+    }
+
+    fn warning(&mut self, line: usize, field: &str, message: String) {
+        self.validation.warnings.push(ManifestProblem {
+            line,
+            field: field.to_owned(),
+            message,
+        });
+    }
+
+    /// Check the fields of `table`, whose path in the manifest is `path`,
+    /// returning the known fields that have the expected type.
+    fn check_fields<'t, 'i>(
+        &mut self,
+        table: &'t Spanned<DeTable<'i>>,
+        path: &str,
+        fields: &[Field],
+    ) -> BTreeMap<&'static str, &'t Spanned<DeValue<'i>>> {
+        let field_path = |name: &str| {
+            if path.is_empty() {
+                name.to_owned()
+            } else {
+                format!("{path}.{name}")
+            }
+        };
+        let mut found = BTreeMap::new();
+        for (key, value) in table.get_ref().iter() {
+            let name: &str = key.get_ref();
+            let line = self.line(&key.span());
+            match fields.iter().find(|(field, _, _)| *field == name) {
+                None => self.warning(
+                    line,
+                    &field_path(name),
+                    format!(
+                        "unknown field, ignored (expected one of {})",
+                        fields
+                            .iter()
+                            .map(|(field, _, _)| *field)
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                ),
+                Some((field, kind, _)) if !kind.matches(value.get_ref()) => self.error(
+                    line,
+                    &field_path(name),
+                    format!("expected {kind}, found {}", value.get_ref().type_str()),
+                ),
+                Some((field, _, _)) => {
+                    found.insert(*field, value);
+                }
+            }
+        }
+        let line = self.line(&table.span());
+        for (field, kind, required) in fields {
+            let present = table.get_ref().keys().any(|key| &**key.get_ref() == *field);
+            if *required && !present {
+                self.error(
+                    line,
+                    &field_path(field),
+                    format!("missing, expected {kind}"),
+                );
+            }
+        }
+        found
+    }
+
+    fn check_manifest(&mut self, manifest: &Spanned<DeTable>) {
+        let fields = self.check_fields(manifest, "", MANIFEST_FIELDS);
+        match fields.get("format_version") {
+            Some(value) => {
+                let line = self.line(&value.span());
+                match integer(value.get_ref()) {
+                    Some(version) if (1..=u64::from(MANIFEST_FORMAT_VERSION)).contains(&version) => {}
+                    _ => self.error(
+                        line,
+                        "format_version",
+                        format!(
+                            "expected a version from 1 to {MANIFEST_FORMAT_VERSION}, the newest supported"
+                        ),
+                    ),
+                }
+            }
+            None => self.warning(
+                1,
+                "format_version",
+                format!("missing, assuming {MANIFEST_FORMAT_VERSION}"),
+            ),
+        }
+        if let Some(value) = fields.get("created") {
+            if integer(value.get_ref()).is_none() {
+                let line = self.line(&value.span());
+                self.error(
+                    line,
+                    "created",
+                    "expected seconds since the Unix epoch".to_owned(),
+                );
+            }
+        }
+        if let Some(DeValue::Array(repos)) = fields.get("repos").map(|value| value.get_ref()) {
+            let mut names: BTreeMap<String, usize> = BTreeMap::new();
+            for (index, repo) in repos.iter().enumerate() {
+                let path = format!("repos[{index}]");
+                let line = self.line(&repo.span());
+                let DeValue::Table(table) = repo.get_ref() else {
+                    self.error(
+                        line,
+                        &path,
+                        format!("expected a table, found {}", repo.get_ref().type_str()),
+                    );
+                    continue;
+                };
+                let table = Spanned::new(repo.span(), table.clone());
+                let fields = self.check_fields(&table, &path, REPO_FIELDS);
+                if let Some(value) = fields.get("name") {
+                    let name = value.get_ref().as_str().unwrap_or_default();
+                    let line = self.line(&value.span());
+                    if name.is_empty() {
+                        self.error(line, &format!("{path}.name"), "is empty".to_owned());
+                    } else if let Some(first) = names.get(name) {
+                        self.error(
+                            line,
+                            &format!("{path}.name"),
+                            format!("{name} is already listed on line {first}"),
+                        );
+                    } else {
+                        names.insert(name.to_owned(), line);
+                    }
+                }
+                if let Some(value) = fields.get("sha") {
+                    let sha = value.get_ref().as_str().unwrap_or_default();
+                    if !is_sha(sha) {
+                        let line = self.line(&value.span());
+                        self.error(
+                            line,
+                            &format!("{path}.sha"),
+                            format!("expected 7 to 64 hexadecimal digits, found \"{sha}\""),
+                        );
+                    }
+                }
+            }
+        }
+        if let Some(DeValue::Table(overrides)) =
+            fields.get("overrides").map(|value| value.get_ref())
+        {
+            for (repo, version) in overrides.iter() {
+                if !Kind::String.matches(version.get_ref()) {
+                    let line = self.line(&version.span());
+                    self.error(
+                        line,
+                        &format!("overrides.{}", repo.get_ref()),
+                        format!("expected a string, found {}", version.get_ref().type_str()),
+                    );
+                }
+            }
+        }
+        if let Some(python_env) = fields.get("python_env") {
+            if let DeValue::Table(table) = python_env.get_ref() {
+                let table = Spanned::new(python_env.span(), table.clone());
+                let fields = self.check_fields(&table, "python_env", PYTHON_ENV_FIELDS);
+                if let Some(DeValue::Array(requirements)) =
+                    fields.get("pip_freeze").map(|value| value.get_ref())
+                {
+                    for (index, requirement) in requirements.iter().enumerate() {
+                        if !Kind::String.matches(requirement.get_ref()) {
+                            let line = self.line(&requirement.span());
+                            self.error(
+                                line,
+                                &format!("python_env.pip_freeze[{index}]"),
+                                format!(
+                                    "expected a string, found {}",
+                                    requirement.get_ref().type_str()
+                                ),
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Value of a non-negative integer.
+fn integer(value: &DeValue) -> Option<u64> {
+    let integer = value.as_integer()?;
+    u64::from_str_radix(&integer.as_str().replace('_', ""), integer.radix()).ok()
+}
+
+/// Whether `sha` is a full or abbreviated commit id.
+fn is_sha(sha: &str) -> bool {
+    (7..=64).contains(&sha.len()) && sha.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::validate;
+
+    #[test]
+    fn test_validate() {
+        let validation = validate(concat!(
+            "format_version = 1\n",
+            "created = 1700000000\n",
+            "env_path = \"/obs-env\"\n",
+            "comment = \"nightly\"\n",
+            "\n",
+            "[[repos]]\n",
+            "name = \"ts_wep\"\n",
+            "describe = \"v1.2.0\"\n",
+            "sha = \"1111aaaa\"\n",
+            "\n",
+            "[[repos]]\n",
+            "name = \"ts_wep\"\n",
+            "describe = \"v1.3.0\"\n",
+            "sha = \"not-a-sha\"\n",
+            "dirty = \"no\"\n",
+            "\n",
+            "[[repos]]\n",
+            "name = \"cwfs\"\n",
+        ));
+        assert!(!validation.is_valid());
+        assert_eq!(
+            validation
+                .errors
+                .iter()
+                .map(|problem| problem.to_string())
+                .collect::<Vec<_>>(),
+            [
+                "line 15: repos[1].dirty: expected a boolean, found string",
+                "line 12: repos[1].name: ts_wep is already listed on line 7",
+                "line 14: repos[1].sha: expected 7 to 64 hexadecimal digits, found \"not-a-sha\"",
+                "line 17: repos[2].describe: missing, expected a string",
+            ]
+        );
+        assert_eq!(
+            validation.warnings[0].to_string(),
+            "line 4: comment: unknown field, ignored (expected one of format_version, created, env_path, repos, overrides, python_env)"
+        );
+    }
+
+    #[test]
+    fn test_validate_syntax_and_format_version() {
+        let validation = validate("created = 0\nenv_path = \"/obs-env\"\nrepos = [\n");
+        assert_eq!(validation.errors.len(), 1);
+        assert_eq!(validation.errors[0].line, 3);
+
+        let validation = validate("format_version = 9\ncreated = 0\nenv_path = \"\"\nrepos = []\n");
+        assert_eq!(
+            validation.errors[0].to_string(),
+            "line 1: format_version: expected a version from 1 to 1, the newest supported"
+        );
+
+        let validation = validate("created = 0\nenv_path = \"\"\nrepos = []\n");
+        assert!(validation.is_valid());
+        assert_eq!(
+            validation.warnings[0].to_string(),
+            "line 1: format_version: missing, assuming 1"
+        );
+    }
+}
@@ -0,0 +1,405 @@
+//! Read-only HTTP endpoints reporting the state of an environment, so
+//! dashboards can poll it without a shell on the host, served by
+//! [`StatusServer::serve`].
+//!
+//! | Endpoint         | Content                                              |
+//! |------------------|------------------------------------------------------|
+//! | `/versions`      | Versions checked out, by repository.                 |
+//! | `/base-versions` | Versions of the base environment, by repository.     |
+//! | `/diff`          | Checked out and base versions, and whether they match, which a checkout with local changes never does. |
+//! | `/health`        | Problems found by [`ObservingEnvironment::verify`].  |
+use crate::{
+    error::{report, ObsEnvError},
+    observing_environment::serialized,
+    signal::POLL_INTERVAL,
+    ObservingEnvironment,
+};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Address served by default, reachable from this host only.
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:8642";
+
+/// Time the responses are served from the cache for by default.
+pub const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(5);
+
+/// Longest request accepted, headers included.
+const MAX_REQUEST_BYTES: usize = 8192;
+
+/// Time a client has to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Response to a request, with a json body.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+impl Response {
+    fn json(status: u16, body: &Value) -> Response {
+        Response {
+            status,
+            body: body.to_string(),
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Response {
+        Response::json(status, &json!({ "error": message }))
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            502 => "Bad Gateway",
+            503 => "Service Unavailable",
+            _ => "Internal Server Error",
+        }
+    }
+}
+
+/// Versions of a repository in the base environment and checked out, as
+/// served by `/diff`.
+#[derive(Serialize)]
+struct RepoDiff {
+    current: Option<String>,
+    base: Option<String>,
+    /// Whether the current version has changes to tracked files.
+    dirty: bool,
+    matches: bool,
+}
+
+/// Server of the status of an environment. Nothing it serves modifies the
+/// environment, and the responses are cached for a short time, so polling
+/// it does not walk the repositories on every request.
+pub struct StatusServer<'a> {
+    obs_env: &'a ObservingEnvironment,
+    cache_ttl: Duration,
+    cache: HashMap<String, (Instant, Response)>,
+}
+
+impl<'a> StatusServer<'a> {
+    /// Serve the status of `obs_env`.
+    pub fn new(obs_env: &'a ObservingEnvironment) -> StatusServer<'a> {
+        StatusServer {
+            obs_env,
+            cache_ttl: DEFAULT_CACHE_TTL,
+            cache: HashMap::new(),
+        }
+    }
+
+    /// Serve the responses from the cache for `ttl`, 0 not to cache them.
+    pub fn cache_ttl(mut self, ttl: Duration) -> Self {
+        self.cache_ttl = ttl;
+        self
+    }
+
+    /// Answer a request for `path` with `method`, from the cache if the
+    /// response is recent enough.
+    pub fn respond(&mut self, method: &str, path: &str) -> Response {
+        if method != "GET" && method != "HEAD" {
+            return Response::error(405, "Only GET and HEAD are allowed");
+        }
+        let path = path.split(['?', '#']).next().unwrap_or_default();
+        if let Some((created, response)) = self.cache.get(path) {
+            if created.elapsed() < self.cache_ttl {
+                return response.clone();
+            }
+        }
+        let response = match path {
+            "/versions" => self.versions(),
+            "/base-versions" => self.base_versions(),
+            "/diff" => self.diff(),
+            "/health" => self.health(),
+            _ => return Response::error(404, &format!("No endpoint {path}")),
+        };
+        self.cache
+            .insert(path.to_owned(), (Instant::now(), response.clone()));
+        response
+    }
+
+    /// Answer the clients connecting to `listener` one at a time, until
+    /// `shutdown` is set.
+    pub fn serve(
+        &mut self,
+        listener: TcpListener,
+        shutdown: &AtomicBool,
+    ) -> Result<(), ObsEnvError> {
+        let address = listener
+            .local_addr()
+            .map(|address| address.to_string())
+            .unwrap_or_default();
+        listener
+            .set_nonblocking(true)
+            .map_err(|error| ObsEnvError::io(&address, "listen on", error))?;
+        while !shutdown.load(Ordering::SeqCst) {
+            match listener.accept() {
+                Ok((stream, client)) => {
+                    if let Err(error) = self.answer(stream) {
+                        log::warn!("Could not answer {client}: {error}");
+                    }
+                }
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(POLL_INTERVAL)
+                }
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+                Err(error) => return Err(ObsEnvError::io(&address, "accept clients on", error)),
+            }
+        }
+        log::info!("Stopped serving {address}.");
+        Ok(())
+    }
+
+    /// Read the request sent on `stream` and write the response to it.
+    fn answer(&mut self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+        let mut request = Vec::new();
+        let mut buffer = [0; 1024];
+        while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+            let read = stream.read(&mut buffer)?;
+            if read == 0 || request.len() + read > MAX_REQUEST_BYTES {
+                break;
+            }
+            request.extend_from_slice(&buffer[..read]);
+        }
+        let request = String::from_utf8_lossy(&request);
+        let mut words = request.lines().next().unwrap_or_default().split(' ');
+        let (method, response) = match (words.next(), words.next()) {
+            (Some(method), Some(path)) if !method.is_empty() => {
+                log::debug!("{method} {path}");
+                (method, self.respond(method, path))
+            }
+            _ => ("", Response::error(400, "Malformed request")),
+        };
+        write!(
+            stream,
+            "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n",
+            response.status,
+            response.reason(),
+            response.body.len()
+        )?;
+        if response.status == 405 {
+            write!(stream, "Allow: GET, HEAD\r\n")?;
+        }
+        write!(
+            stream,
+            "Cache-Control: no-store\r\nConnection: close\r\n\r\n"
+        )?;
+        if method != "HEAD" {
+            stream.write_all(response.body.as_bytes())?;
+        }
+        stream.flush()
+    }
+
+    fn versions(&self) -> Response {
+        let versions: BTreeMap<String, Value> = self
+            .obs_env
+            .get_current_env_versions()
+            .into_iter()
+            .map(|(repo_name, version)| {
+                let version = match version {
+                    Ok(version) => serialized(serde_json::to_value(version)),
+                    Err(error) => json!({ "error": report(&error) }),
+                };
+                (repo_name, version)
+            })
+            .collect();
+        Response::json(200, &json!(versions))
+    }
+
+    fn base_versions(&self) -> Response {
+        match self
+            .obs_env
+            .get_base_env_versions_cached(self.obs_env.get_base_env_branch())
+        {
+            Ok(base_versions) => Response::json(
+                200,
+                &json!({
+                    "versions": base_versions.versions,
+                    "cache_age_secs": base_versions.cache_age.map(|age| age.as_secs()),
+                }),
+            ),
+            Err(error) => Response::error(502, &report(&error)),
+        }
+    }
+
+    fn diff(&self) -> Response {
+        let base_versions = match self
+            .obs_env
+            .get_base_env_versions(self.obs_env.get_base_env_branch())
+        {
+            Ok(base_versions) => base_versions,
+            Err(error) => return Response::error(502, &report(&error)),
+        };
+        let current_versions = self.obs_env.get_current_env_versions();
+        let repo_names: BTreeSet<&String> = current_versions
+            .keys()
+            .chain(base_versions.keys())
+            .collect();
+        let diff: BTreeMap<&String, RepoDiff> = repo_names
+            .into_iter()
+            .map(|repo_name| {
+                let current = current_versions
+                    .get(repo_name)
+                    .and_then(|version| version.as_ref().ok());
+                let base = base_versions.get(repo_name);
+                let dirty = current.is_some_and(|version| version.dirty);
+                let matches = match (current, base) {
+                    (Some(current), Some(base)) => current.matches(base) && !dirty,
+                    _ => false,
+                };
+                let diff = RepoDiff {
+                    current: current.map(|version| version.describe.clone()),
+                    base: base.map(|version| version.describe.clone()),
+                    dirty,
+                    matches,
+                };
+                (repo_name, diff)
+            })
+            .collect();
+        Response::json(200, &json!(diff))
+    }
+
+    fn health(&self) -> Response {
+        match self.obs_env.verify() {
+            Ok(()) => Response::json(200, &json!({ "healthy": true, "problems": [] })),
+            Err(ObsEnvError::VerificationFailed { problems, .. }) => {
+                Response::json(503, &json!({ "healthy": false, "problems": problems }))
+            }
+            Err(error) => Response::error(500, &report(&error)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StatusServer;
+    use crate::{testing::FakeBackend, ObservingEnvironment};
+    use serde_json::{json, Value};
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        sync::atomic::{AtomicBool, Ordering},
+        thread,
+        time::Duration,
+    };
+    use tempfile::TempDir;
+
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    fn fixture_environment(root: &TempDir, backend: &FakeBackend) -> ObservingEnvironment {
+        backend.set_branch("https://example.com/lsst-ts/ts_wep", "main", "a1b2c3d4");
+        backend.set_branch("https://example.com/lsst-ts/cwfs", "main", "e5f6a7b8");
+        let versions_file = root.path().join("versions.env");
+        std::fs::write(&versions_file, "ts_wep=main\ncwfs=v1.0.0\n").unwrap();
+        ObservingEnvironment::builder()
+            .destination(&root.path().join("env").to_string_lossy())
+            .repositories([
+                ("ts_wep", "https://example.com/lsst-ts/"),
+                ("cwfs", "https://example.com/lsst-ts/"),
+            ])
+            .base_env_source(&versions_file.to_string_lossy())
+            .backend(backend.clone())
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_respond() -> TestResult {
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        let obs_env = fixture_environment(&root, &backend);
+        obs_env.clone_missing_repository("ts_wep");
+        let mut server = StatusServer::new(&obs_env).cache_ttl(Duration::ZERO);
+
+        let response = server.respond("GET", "/diff?format=json");
+        assert_eq!(response.status, 200);
+        assert_eq!(
+            serde_json::from_str::<Value>(&response.body)?,
+            json!({
+                "cwfs": {"current": null, "base": "v1.0.0", "dirty": false, "matches": false},
+                "ts_wep": {"current": "a1b2c3d4", "base": "main", "dirty": false, "matches": true},
+            })
+        );
+
+        let response = server.respond("GET", "/versions");
+        let versions: Value = serde_json::from_str(&response.body)?;
+        assert_eq!(versions["ts_wep"]["branch"], "main");
+        assert!(versions["cwfs"]["error"].is_string());
+
+        let response = server.respond("GET", "/base-versions");
+        let base_versions: Value = serde_json::from_str(&response.body)?;
+        assert_eq!(base_versions["versions"]["cwfs"]["describe"], "v1.0.0");
+
+        let response = server.respond("GET", "/health");
+        assert_eq!(response.status, 503);
+        assert!(response.body.contains("cwfs is not cloned"));
+
+        assert_eq!(server.respond("POST", "/versions").status, 405);
+        assert_eq!(server.respond("GET", "/reset").status, 404);
+
+        // Local changes make the checkout differ from the base version.
+        backend.set_dirty(root.path().join("env/ts_wep"));
+        let diff: Value = serde_json::from_str(&server.respond("GET", "/diff").body)?;
+        assert_eq!(diff["ts_wep"]["dirty"], true);
+        assert_eq!(diff["ts_wep"]["matches"], false);
+        Ok(())
+    }
+
+    #[test]
+    fn test_respond_from_cache() -> TestResult {
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        let obs_env = fixture_environment(&root, &backend);
+        let mut server = StatusServer::new(&obs_env).cache_ttl(Duration::from_secs(60));
+
+        let before = server.respond("GET", "/versions");
+        obs_env.clone_missing_repository("ts_wep");
+        assert_eq!(server.respond("GET", "/versions"), before);
+        Ok(())
+    }
+
+    #[test]
+    fn test_serve() -> TestResult {
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        let obs_env = fixture_environment(&root, &backend);
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+        let shutdown = AtomicBool::new(false);
+
+        thread::scope(|scope| {
+            let client = scope.spawn(|| {
+                let mut stream = TcpStream::connect(address).unwrap();
+                stream
+                    .write_all(b"GET /base-versions HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                    .unwrap();
+                let mut response = String::new();
+                stream.read_to_string(&mut response).unwrap();
+                shutdown.store(true, Ordering::SeqCst);
+                response
+            });
+            StatusServer::new(&obs_env)
+                .serve(listener, &shutdown)
+                .unwrap();
+            let response = client.join().unwrap();
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+            assert!(response.contains("Content-Type: application/json\r\n"));
+            assert!(response.contains(r#"{"cache_age_secs":null,"versions":{"#));
+        });
+        Ok(())
+    }
+}
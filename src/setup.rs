@@ -0,0 +1,267 @@
+use crate::error::{report, ObsEnvError};
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+use std::{
+    fmt::{self, Display},
+    path::PathBuf,
+    time::Duration,
+};
+
+/// State of a repository in the environment path before setting it up.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RepoPresence {
+    /// The repository is cloned and can be opened.
+    Present,
+    /// Something that is not a git repository is in the way.
+    Broken,
+    /// Nothing is at the path of the repository.
+    Missing,
+}
+
+/// Classification of the repositories of an environment path, made by
+/// [`ObservingEnvironment::survey`](crate::ObservingEnvironment::survey)
+/// in the order of [`ObservingEnvironment::repos`](crate::ObservingEnvironment::repos).
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct EnvSurvey {
+    pub repos: Vec<(String, RepoPresence)>,
+}
+
+impl EnvSurvey {
+    /// Repositories in the `presence` state.
+    pub fn with_presence(&self, presence: RepoPresence) -> impl Iterator<Item = &str> {
+        self.repos
+            .iter()
+            .filter(move |(_, repo_presence)| *repo_presence == presence)
+            .map(|(repo_name, _)| repo_name.as_str())
+    }
+}
+
+impl Display for EnvSurvey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut first = true;
+        for (label, presence) in [
+            ("Present", RepoPresence::Present),
+            ("Broken (not git repositories)", RepoPresence::Broken),
+            ("Missing", RepoPresence::Missing),
+        ] {
+            let repos: Vec<&str> = self.with_presence(presence).collect();
+            if repos.is_empty() {
+                continue;
+            }
+            if !first {
+                writeln!(f)?;
+            }
+            first = false;
+            write!(f, "{label}: {}", repos.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// What happened to a repository when setting up the environment.
+#[derive(Debug, Serialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum RepoSetupOutcome {
+    /// The repository was cloned to `path`, with `head` checked out.
+    Cloned {
+        path: PathBuf,
+        head: Option<String>,
+        #[serde(serialize_with = "serialize_secs")]
+        duration: Duration,
+        received_bytes: usize,
+    },
+    /// The repository was already present at `path`.
+    Skipped { path: PathBuf },
+    /// The repository was already present at `path`, and was fetched and
+    /// fast-forwarded to `head`.
+    Updated {
+        path: PathBuf,
+        head: Option<String>,
+        #[serde(serialize_with = "serialize_secs")]
+        duration: Duration,
+        received_bytes: usize,
+    },
+    /// The repository could not be cloned.
+    Failed {
+        #[serde(serialize_with = "serialize_error")]
+        error: ObsEnvError,
+        #[serde(serialize_with = "serialize_secs")]
+        duration: Duration,
+    },
+}
+
+/// Outcome of setting up one repository.
+#[derive(Debug, Serialize)]
+pub struct RepoSetup {
+    /// Name of the repository.
+    pub name: String,
+    #[serde(flatten)]
+    pub outcome: RepoSetupOutcome,
+}
+
+impl RepoSetup {
+    /// Error the setup of the repository failed with, if it did.
+    pub fn error(&self) -> Option<&ObsEnvError> {
+        match &self.outcome {
+            RepoSetupOutcome::Failed { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Overall result of setting up the environment.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SetupStatus {
+    /// Every repository was cloned, updated or already present.
+    Success,
+    /// At least one repository could not be cloned, serialized as the
+    /// "partial" status of the other actions failing on some repositories.
+    Partial,
+}
+
+/// Report of [`ObservingEnvironment::clone_repositories`](crate::ObservingEnvironment::clone_repositories),
+/// with one entry per repository of the environment, in the order of
+/// [`ObservingEnvironment::repos`](crate::ObservingEnvironment::repos).
+///
+/// Serialized with its [status](SetupReport::status) alongside the
+/// repositories.
+#[derive(Debug)]
+pub struct SetupReport {
+    pub repos: Vec<RepoSetup>,
+}
+
+impl SetupReport {
+    /// Report made of the outcomes of `repos`.
+    pub fn new(repos: Vec<RepoSetup>) -> SetupReport {
+        SetupReport { repos }
+    }
+
+    /// Overall result, from the outcomes of the repositories.
+    pub fn status(&self) -> SetupStatus {
+        match self.failures().next() {
+            Some(_) => SetupStatus::Partial,
+            None => SetupStatus::Success,
+        }
+    }
+
+    /// Whether every repository was cloned, updated or already present.
+    pub fn is_success(&self) -> bool {
+        self.status() == SetupStatus::Success
+    }
+
+    /// Repositories that were cloned, with their path.
+    pub fn cloned(&self) -> impl Iterator<Item = (&str, &PathBuf)> {
+        self.repos.iter().filter_map(|repo| match &repo.outcome {
+            RepoSetupOutcome::Cloned { path, .. } => Some((repo.name.as_str(), path)),
+            _ => None,
+        })
+    }
+
+    /// Repositories that were updated, with their path.
+    pub fn updated(&self) -> impl Iterator<Item = (&str, &PathBuf)> {
+        self.repos.iter().filter_map(|repo| match &repo.outcome {
+            RepoSetupOutcome::Updated { path, .. } => Some((repo.name.as_str(), path)),
+            _ => None,
+        })
+    }
+
+    /// Repositories that failed, with their error.
+    pub fn failures(&self) -> impl Iterator<Item = (&str, &ObsEnvError)> {
+        self.repos
+            .iter()
+            .filter_map(|repo| repo.error().map(|error| (repo.name.as_str(), error)))
+    }
+
+    /// [`ObsEnvError::PartialFailure`] naming the repositories that failed,
+    /// if any did.
+    pub fn partial_failure(&self) -> Option<ObsEnvError> {
+        let failed: Vec<String> = self
+            .failures()
+            .map(|(repo_name, _)| repo_name.to_owned())
+            .collect();
+        (!failed.is_empty()).then(|| ObsEnvError::PartialFailure {
+            operation: "set up".to_owned(),
+            failed,
+        })
+    }
+
+    /// The report if every repository was set up, or the first error.
+    pub fn into_result(self) -> Result<SetupReport, ObsEnvError> {
+        let Some(partial_failure) = self.partial_failure() else {
+            return Ok(self);
+        };
+        let error = self.repos.into_iter().find_map(|repo| match repo.outcome {
+            RepoSetupOutcome::Failed { error, .. } => Some(error),
+            _ => None,
+        });
+        Err(error.unwrap_or(partial_failure))
+    }
+}
+
+impl Serialize for SetupReport {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut report = serializer.serialize_struct("SetupReport", 2)?;
+        report.serialize_field("status", &self.status())?;
+        report.serialize_field("repos", &self.repos)?;
+        report.end()
+    }
+}
+
+fn serialize_error<S: Serializer>(error: &ObsEnvError, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&report(error))
+}
+
+/// `duration` as a number of seconds.
+fn serialize_secs<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RepoSetup, RepoSetupOutcome, SetupReport, SetupStatus};
+    use crate::ObsEnvError;
+    use std::{path::PathBuf, time::Duration};
+
+    #[test]
+    fn test_setup_report_json() {
+        let report = SetupReport::new(vec![
+            RepoSetup {
+                name: "ts_missing".to_owned(),
+                outcome: RepoSetupOutcome::Failed {
+                    error: ObsEnvError::Offline {
+                        operation: "clone ts_missing".to_owned(),
+                    },
+                    duration: Duration::ZERO,
+                },
+            },
+            RepoSetup {
+                name: "ts_wep".to_owned(),
+                outcome: RepoSetupOutcome::Skipped {
+                    path: PathBuf::from("/obs-env/ts_wep"),
+                },
+            },
+        ]);
+
+        assert_eq!(report.status(), SetupStatus::Partial);
+        assert_eq!(report.failures().count(), 1);
+        assert_eq!(report.cloned().count(), 0);
+        assert_eq!(
+            serde_json::to_string(&report).unwrap(),
+            concat!(
+                r#"{"status":"partial","repos":["#,
+                r#"{"name":"ts_missing","outcome":"failed","error":"Cannot clone ts_missing offline","duration":0.0},"#,
+                r#"{"name":"ts_wep","outcome":"skipped","path":"/obs-env/ts_wep"}]}"#
+            )
+        );
+        assert!(matches!(
+            report.into_result(),
+            Err(ObsEnvError::Offline { .. })
+        ));
+
+        let report = SetupReport::new(Vec::new());
+        assert_eq!(report.status(), SetupStatus::Success);
+        assert!(report.partial_failure().is_none());
+        assert!(report.into_result().is_ok());
+    }
+}
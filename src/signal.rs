@@ -0,0 +1,44 @@
+//! Clean stop of the actions running until they are told to, "Serve" and
+//! "Watch", on SIGTERM and SIGINT.
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Interval a shutdown flag is checked at while waiting.
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Flag set when the process receives SIGTERM or SIGINT.
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn request_shutdown(_signal: libc::c_int) {
+    SHUTDOWN.store(true, Ordering::SeqCst);
+}
+
+/// Flag set on SIGTERM and SIGINT, for a long running action to stop
+/// between two steps instead of the process being killed in one.
+pub fn shutdown_on_signal() -> &'static AtomicBool {
+    #[cfg(unix)]
+    unsafe {
+        let handler = request_shutdown as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        libc::signal(libc::SIGTERM, handler);
+        libc::signal(libc::SIGINT, handler);
+    }
+    &SHUTDOWN
+}
+
+/// Wait for `duration`, or until `shutdown` is set, returning whether it
+/// was.
+pub fn wait(duration: Duration, shutdown: &AtomicBool) -> bool {
+    let start = Instant::now();
+    while !shutdown.load(Ordering::SeqCst) {
+        let left = duration.saturating_sub(start.elapsed());
+        if left.is_zero() {
+            return false;
+        }
+        thread::sleep(left.min(POLL_INTERVAL));
+    }
+    true
+}
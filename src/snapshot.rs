@@ -0,0 +1,81 @@
+//! Snapshots of the commits the repositories of an environment are at,
+//! taken with
+//! [`ObservingEnvironment::snapshot`](crate::ObservingEnvironment::snapshot)
+//! and read back with
+//! [`ObservingEnvironment::load_snapshot`](crate::ObservingEnvironment::load_snapshot)
+//! to return to them, e.g. after a night of experiments.
+//!
+//! A snapshot is a manifest kept under the environment path, named after
+//! the time it was taken unless given a name.
+use crate::{audit::format_utc, manifest::EnvironmentManifest};
+use serde::Serialize;
+use std::{
+    fmt::{self, Display},
+    path::PathBuf,
+};
+
+/// Extension of the snapshot files.
+pub(crate) const SNAPSHOT_EXTENSION: &str = "toml";
+
+/// Snapshot of an environment.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Snapshot {
+    pub name: String,
+    /// File the snapshot is kept in.
+    pub path: PathBuf,
+    pub manifest: EnvironmentManifest,
+}
+
+impl Snapshot {
+    /// Repositories with changes to tracked files, which the snapshot only
+    /// lists and cannot restore.
+    pub fn dirty(&self) -> impl Iterator<Item = &str> {
+        self.manifest
+            .repos
+            .iter()
+            .filter(|version| version.dirty)
+            .map(|version| version.name.as_str())
+    }
+}
+
+impl Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} repositories, taken on {}",
+            self.name,
+            self.manifest.repos.len(),
+            format_utc(self.manifest.created)
+        )
+    }
+}
+
+/// Name of a snapshot taken at `timestamp`, e.g. `2024-06-10T22-30-00`,
+/// which sorts in time order.
+pub(crate) fn default_name(timestamp: u64) -> String {
+    format_utc(timestamp).replace(' ', "T").replace(':', "-")
+}
+
+/// Whether `name` can name a snapshot file in the snapshots directory.
+pub(crate) fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{default_name, is_valid_name};
+
+    #[test]
+    fn test_snapshot_names() {
+        assert_eq!(default_name(1_718_058_600), "2024-06-10T22-30-00");
+        assert!(is_valid_name(&default_name(1_718_058_600)));
+        assert!(is_valid_name("good_night.v2"));
+        for name in ["", ".hidden", "../escape", "a/b", "with space"] {
+            assert!(!is_valid_name(name), "{name}");
+        }
+    }
+}
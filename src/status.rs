@@ -0,0 +1,107 @@
+//! State of the working trees of the repositories, found by
+//! [`ObservingEnvironment::working_trees`](crate::ObservingEnvironment::working_trees)
+//! for the "Status" action, to see what a reset would throw away.
+use crate::manifest::AheadBehind;
+use serde::Serialize;
+use std::fmt::{self, Display};
+
+/// Branch, local changes and operation in progress of a cloned repository.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct WorkingTree {
+    pub repo: String,
+    /// Local branch checked out, or none if HEAD is detached.
+    pub branch: Option<String>,
+    /// Commit checked out.
+    pub commit: String,
+    /// Whether tracked files were changed since HEAD.
+    pub dirty: bool,
+    /// Tracked files changed since HEAD, staged or not.
+    pub modified_files: Vec<String>,
+    /// Number of untracked files, or directories, not ignored.
+    pub untracked: usize,
+    /// Position of the branch checked out relative to the same branch on
+    /// origin, as of the last fetch, or none if HEAD is detached or the
+    /// branch is not on origin.
+    pub ahead_behind: Option<AheadBehind>,
+    /// Operation left in progress, e.g. "rebase".
+    pub in_progress: Option<String>,
+}
+
+impl WorkingTree {
+    /// Whether a reset would lose work: tracked files are changed or an
+    /// operation is in progress. Untracked files are left by a reset.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty || self.in_progress.is_some()
+    }
+}
+
+impl Display for WorkingTree {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let commit = &self.commit[..self.commit.len().min(12)];
+        match (&self.branch, &self.ahead_behind) {
+            (Some(branch), Some(ahead_behind)) => {
+                write!(f, "{}: {branch} at {commit} ({ahead_behind})", self.repo)?
+            }
+            (Some(branch), None) => write!(f, "{}: {branch} at {commit} (no upstream)", self.repo)?,
+            (None, _) => write!(f, "{}: detached at {commit}", self.repo)?,
+        }
+        let mut changes = Vec::new();
+        match self.modified_files.len() {
+            0 if self.dirty => changes.push("modified".to_owned()),
+            0 => {}
+            modified => changes.push(format!("{modified} modified")),
+        }
+        if self.untracked > 0 {
+            changes.push(format!("{} untracked", self.untracked));
+        }
+        if let Some(operation) = &self.in_progress {
+            changes.push(format!("{operation} in progress"));
+        }
+        match changes.is_empty() {
+            true => write!(f, ", clean"),
+            false => write!(f, ", {}", changes.join(", ")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WorkingTree;
+    use crate::manifest::AheadBehind;
+
+    #[test]
+    fn test_working_tree() {
+        let clean = WorkingTree {
+            repo: "ts_wep".to_owned(),
+            branch: Some("develop".to_owned()),
+            commit: "0123456789abcdef0123".to_owned(),
+            dirty: false,
+            modified_files: Vec::new(),
+            untracked: 2,
+            ahead_behind: Some(AheadBehind {
+                ahead: 0,
+                behind: 3,
+            }),
+            in_progress: None,
+        };
+        assert!(!clean.is_dirty());
+        assert_eq!(
+            clean.to_string(),
+            "ts_wep: develop at 0123456789ab (+0/-3), 2 untracked"
+        );
+
+        let dirty = WorkingTree {
+            branch: None,
+            dirty: true,
+            modified_files: vec!["python/lsst/ts/wep/task.py".to_owned()],
+            untracked: 0,
+            in_progress: Some("rebase".to_owned()),
+            ..clean
+        };
+        assert!(dirty.is_dirty());
+        assert_eq!(
+            dirty.to_string(),
+            "ts_wep: detached at 0123456789ab, 1 modified, rebase in progress"
+        );
+    }
+}
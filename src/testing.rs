@@ -0,0 +1,894 @@
+use crate::git_backend::{
+    CloneFilter, CloneOptions, CommitInfo, CommitQuery, CommitSummary, GitBackend, Identity,
+    RepoStatus, TransferObserver, TransferProgress,
+};
+use git2::{Error, ErrorClass, ErrorCode};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+};
+
+/// A remote repository known to a [`FakeBackend`].
+#[derive(Clone, Debug, Default)]
+struct FakeRemote {
+    /// Branch names and the commit they point to.
+    branches: BTreeMap<String, String>,
+    /// Tag names and the commit they point to.
+    tags: BTreeMap<String, String>,
+    /// Commits only in the history of the branches, and how many commits
+    /// deep a clone has to be to have them.
+    history: BTreeMap<String, u32>,
+}
+
+/// A repository cloned by a [`FakeBackend`].
+#[derive(Clone, Debug, Default)]
+struct FakeRepository {
+    url: String,
+    bare: bool,
+    /// Full reference names and the commit they point to.
+    refs: BTreeMap<String, String>,
+    /// Commits of history fetched, as a shallow clone only has those of
+    /// [`FakeRemote::history`] within its depth.
+    commits: Vec<String>,
+    /// Number of commits of history of a shallow clone.
+    depth: Option<u32>,
+    /// Objects left out of a partial clone.
+    filter: Option<CloneFilter>,
+    /// Commit checked out, if any.
+    head: Option<String>,
+    /// Local branch checked out, or none if HEAD is detached.
+    branch: Option<String>,
+    in_progress: Option<String>,
+    dirty: bool,
+    /// Refspecs of every fetch, in order.
+    fetches: Vec<Vec<String>>,
+    /// Configuration of the repository itself.
+    config: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default)]
+struct FakeState {
+    remotes: BTreeMap<String, FakeRemote>,
+    repositories: BTreeMap<PathBuf, FakeRepository>,
+    /// Content of the files in each commit.
+    files: BTreeMap<String, BTreeMap<PathBuf, String>>,
+    /// Commit time of the commits that have one, in seconds since the Unix
+    /// epoch.
+    times: BTreeMap<String, u64>,
+    /// Number of the next attempts to reach a remote that fail.
+    network_failures: AtomicUsize,
+}
+
+/// [`GitBackend`] keeping remotes and repositories in memory, so the
+/// logic of an [`ObservingEnvironment`](crate::ObservingEnvironment) can be
+/// tested without network access or real clones.
+///
+/// Commits are plain strings, meant to look like ids. Clones of
+/// the backend share the same state, so a test can keep one to set up
+/// remotes and inspect the repositories after handing another to the
+/// environment. Fetches bring every branch and tag of the remote, whatever
+/// the refspecs. Commits have no parents, so a branch can always be
+/// fast-forwarded. Commits added to the history with
+/// [`set_history`](FakeBackend::set_history) are only fetched by shallow
+/// clones deep enough. A repository of the backend can be cloned and
+/// fetched from by its path, as from a remote with its local branches.
+///
+/// ```
+/// use ts_observing_environment::{testing::FakeBackend, ObservingEnvironment};
+///
+/// let backend = FakeBackend::new();
+/// backend.set_branch("https://example.com/lsst-ts/ts_wep", "main", "a1b2c3d4");
+///
+/// let obs_env = ObservingEnvironment::builder()
+///     .destination("/obs-env")
+///     .repositories([("ts_wep", "https://example.com/lsst-ts/")])
+///     .backend(backend.clone())
+///     .build()?;
+///
+/// obs_env.clone_repositories().into_result()?;
+/// assert_eq!(backend.head("/obs-env/ts_wep").as_deref(), Some("a1b2c3d4"));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct FakeBackend {
+    state: Arc<Mutex<FakeState>>,
+}
+
+impl FakeBackend {
+    /// Backend without any remote or repository.
+    pub fn new() -> FakeBackend {
+        FakeBackend::default()
+    }
+
+    /// Point `branch` of the remote at `url` to `commit`, adding the remote
+    /// if needed.
+    pub fn set_branch(&self, url: &str, branch: &str, commit: &str) {
+        self.lock()
+            .remotes
+            .entry(url.to_owned())
+            .or_default()
+            .branches
+            .insert(branch.to_owned(), commit.to_owned());
+    }
+
+    /// Tag `commit` in the remote at `url`, adding the remote if needed.
+    pub fn set_tag(&self, url: &str, tag: &str, commit: &str) {
+        self.lock()
+            .remotes
+            .entry(url.to_owned())
+            .or_default()
+            .tags
+            .insert(tag.to_owned(), commit.to_owned());
+    }
+
+    /// Add `commit` to the history of the branches of the remote at `url`,
+    /// `depth` commits deep, so that only shallow clones at least that deep
+    /// have it.
+    pub fn set_history(&self, url: &str, commit: &str, depth: u32) {
+        self.lock()
+            .remotes
+            .entry(url.to_owned())
+            .or_default()
+            .history
+            .insert(commit.to_owned(), depth);
+    }
+
+    /// Set the content of `file` in `commit`.
+    pub fn set_file(&self, commit: &str, file: &str, content: &str) {
+        self.lock()
+            .files
+            .entry(commit.to_owned())
+            .or_default()
+            .insert(PathBuf::from(file), content.to_owned());
+    }
+
+    /// Set the commit time of `commit`, in seconds since the Unix epoch.
+    /// Commits have none, i.e. 0, by default.
+    pub fn set_commit_time(&self, commit: &str, time: u64) {
+        self.lock().times.insert(commit.to_owned(), time);
+    }
+
+    /// Leave an operation such as "merge" in progress in the repository at
+    /// `path`.
+    pub fn set_in_progress(&self, path: impl AsRef<Path>, state: &str) {
+        if let Some(repository) = self.lock().repositories.get_mut(path.as_ref()) {
+            repository.in_progress = Some(state.to_owned());
+        }
+    }
+
+    /// Mark the working tree of the repository at `path` as changed.
+    pub fn set_dirty(&self, path: impl AsRef<Path>) {
+        if let Some(repository) = self.lock().repositories.get_mut(path.as_ref()) {
+            repository.dirty = true;
+        }
+    }
+
+    /// Make the next `count` clones, fetches and other operations
+    /// reaching a remote fail with a network error, as if the remote were
+    /// unreachable.
+    pub fn set_network_failures(&self, count: usize) {
+        self.lock().network_failures.store(count, Ordering::SeqCst);
+    }
+
+    /// Commit checked out in the repository at `path`.
+    pub fn head(&self, path: impl AsRef<Path>) -> Option<String> {
+        self.lock()
+            .repositories
+            .get(path.as_ref())
+            .and_then(|repository| repository.head.clone())
+    }
+
+    /// Full names of the branches and tags of the remote at `url`, and the
+    /// commit they point to.
+    pub fn remote_refs(&self, url: &str) -> BTreeMap<String, String> {
+        let state = self.lock();
+        let Some(remote) = state.remotes.get(url) else {
+            return BTreeMap::new();
+        };
+        let branches = remote
+            .branches
+            .iter()
+            .map(|(branch, commit)| (format!("refs/heads/{branch}"), commit.clone()));
+        let tags = remote
+            .tags
+            .iter()
+            .map(|(tag, commit)| (format!("refs/tags/{tag}"), commit.clone()));
+        branches.chain(tags).collect()
+    }
+
+    /// Refspecs of the fetches into the repository at `path`, in order.
+    pub fn fetches(&self, path: impl AsRef<Path>) -> Vec<Vec<String>> {
+        self.lock()
+            .repositories
+            .get(path.as_ref())
+            .map(|repository| repository.fetches.clone())
+            .unwrap_or_default()
+    }
+
+    /// Depth and filter the repository at `path` was cloned with, or none
+    /// if it is not cloned.
+    pub fn clone_options(&self, path: impl AsRef<Path>) -> Option<CloneOptions> {
+        self.lock()
+            .repositories
+            .get(path.as_ref())
+            .map(|repository| CloneOptions {
+                depth: repository.depth,
+                filter: repository.filter,
+            })
+    }
+
+    /// Local branch checked out in the repository at `path`, or none if
+    /// HEAD is detached.
+    pub fn branch(&self, path: impl AsRef<Path>) -> Option<String> {
+        self.lock()
+            .repositories
+            .get(path.as_ref())
+            .and_then(|repository| repository.branch.clone())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, FakeState> {
+        // A test panicking while holding the lock leaves no invariant
+        // broken, so the state is still good to use.
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Run `op` on the repository at `path`.
+    fn with_repository<T>(
+        &self,
+        path: &Path,
+        op: impl FnOnce(&mut FakeRepository, &FakeState) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let mut state = self.lock();
+        let mut repository = state
+            .repositories
+            .get(path)
+            .cloned()
+            .ok_or_else(|| not_found(&format!("no repository at {}", path.display())))?;
+        let result = op(&mut repository, &state)?;
+        state.repositories.insert(path.to_path_buf(), repository);
+        Ok(result)
+    }
+}
+
+/// Remote at `url`, or the repository at that path seen as a remote, with
+/// its local branches and tags, and the commits of its remote-tracking
+/// branches and HEAD in its history. A connection failure while failures
+/// set by [`FakeBackend::set_network_failures`] are left, and an error
+/// that is not worth retrying for an unknown remote.
+fn remote_of(state: &FakeState, url: &str) -> Result<FakeRemote, Error> {
+    let failures = &state.network_failures;
+    if failures
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+            count.checked_sub(1)
+        })
+        .is_ok()
+    {
+        return Err(Error::new(
+            ErrorCode::GenericError,
+            ErrorClass::Net,
+            format!("failed to connect to {url}: Connection timed out"),
+        ));
+    }
+    if let Some(remote) = state.remotes.get(url) {
+        return Ok(remote.clone());
+    }
+    let repository = state.repositories.get(Path::new(url)).ok_or_else(|| {
+        Error::new(
+            ErrorCode::GenericError,
+            ErrorClass::Net,
+            format!("remote {url} not found"),
+        )
+    })?;
+    let mut remote = FakeRemote::default();
+    for (name, commit) in repository.refs.iter() {
+        if let Some(branch) = name.strip_prefix("refs/heads/") {
+            remote.branches.insert(branch.to_owned(), commit.clone());
+        } else if let Some(tag) = name.strip_prefix("refs/tags/") {
+            remote.tags.insert(tag.to_owned(), commit.clone());
+        } else {
+            remote.history.insert(commit.clone(), 0);
+        }
+    }
+    for commit in repository.head.iter().chain(repository.commits.iter()) {
+        remote.history.insert(commit.clone(), 0);
+    }
+    Ok(remote)
+}
+
+/// Update the references of `repository` from the remote.
+fn update_refs(repository: &mut FakeRepository, remote: &FakeRemote) {
+    for (branch, commit) in remote.branches.iter() {
+        repository
+            .refs
+            .insert(format!("refs/remotes/origin/{branch}"), commit.clone());
+        if repository.bare {
+            repository
+                .refs
+                .insert(format!("refs/heads/{branch}"), commit.clone());
+        }
+    }
+    for (tag, commit) in remote.tags.iter() {
+        repository
+            .refs
+            .insert(format!("refs/tags/{tag}"), commit.clone());
+    }
+    repository.commits = remote
+        .history
+        .iter()
+        .filter(|(_, depth)| repository.depth.is_none_or(|shallow| **depth <= shallow))
+        .map(|(commit, _)| commit.clone())
+        .collect();
+}
+
+/// Commit `spec` resolves to, trying it as a reference name and then as a
+/// full or abbreviated commit id.
+fn resolve(repository: &FakeRepository, spec: &str) -> Result<String, Error> {
+    for name in [
+        spec.to_owned(),
+        format!("refs/tags/{spec}"),
+        format!("refs/heads/{spec}"),
+        format!("refs/remotes/{spec}"),
+    ] {
+        if let Some(commit) = repository.refs.get(&name) {
+            return Ok(commit.clone());
+        }
+    }
+    if spec == "HEAD" {
+        if let Some(head) = &repository.head {
+            return Ok(head.clone());
+        }
+    }
+
+    let mut commits: Vec<&String> = repository
+        .refs
+        .values()
+        .chain(repository.head.iter())
+        .chain(repository.commits.iter())
+        .filter(|commit| spec.len() >= 4 && commit.starts_with(spec))
+        .collect();
+    commits.sort();
+    commits.dedup();
+    match commits.as_slice() {
+        [commit] => Ok((*commit).clone()),
+        [] => Err(not_found(&format!("revspec '{spec}' not found"))),
+        _ => Err(Error::new(
+            ErrorCode::Ambiguous,
+            ErrorClass::Object,
+            format!("ambiguous short id '{spec}'"),
+        )),
+    }
+}
+
+/// Whether `name` matches `glob`, where `*` matches any sequence of
+/// characters.
+fn glob_matches(glob: &str, name: &str) -> bool {
+    match glob.split_once('*') {
+        None => glob == name,
+        Some((prefix, rest)) => {
+            name.starts_with(prefix)
+                && (0..=name.len() - prefix.len())
+                    .filter(|start| name.is_char_boundary(prefix.len() + start))
+                    .any(|start| glob_matches(rest, &name[prefix.len() + start..]))
+        }
+    }
+}
+
+/// Progress of a transfer that brought one object per reference.
+fn transferred(repository: &FakeRepository) -> TransferProgress {
+    TransferProgress {
+        received_objects: repository.refs.len(),
+        total_objects: repository.refs.len(),
+        received_bytes: 0,
+    }
+}
+
+fn not_found(message: &str) -> Error {
+    Error::new(ErrorCode::NotFound, ErrorClass::Reference, message)
+}
+
+impl GitBackend for FakeBackend {
+    fn open(&self, path: &Path) -> Result<(), Error> {
+        self.with_repository(path, |_, _| Ok(()))
+    }
+
+    fn is_empty(&self, path: &Path) -> Result<bool, Error> {
+        self.with_repository(path, |repository, _| {
+            Ok(repository.head.is_none() && repository.refs.is_empty())
+        })
+    }
+
+    fn clone(
+        &self,
+        url: &str,
+        path: &Path,
+        bare: bool,
+        options: CloneOptions,
+        progress: &dyn TransferObserver,
+    ) -> Result<(), Error> {
+        let mut state = self.lock();
+        if state.repositories.contains_key(path) {
+            return Err(Error::new(
+                ErrorCode::Exists,
+                ErrorClass::Repository,
+                format!("'{}' exists and is not an empty directory", path.display()),
+            ));
+        }
+        let remote = remote_of(&state, url)?;
+
+        let mut repository = FakeRepository {
+            url: url.to_owned(),
+            bare,
+            depth: options.depth,
+            filter: options.filter,
+            ..Default::default()
+        };
+        update_refs(&mut repository, &remote);
+        if !bare {
+            if let Some((branch, commit)) = remote
+                .branches
+                .get_key_value("main")
+                .or_else(|| remote.branches.iter().next())
+            {
+                repository
+                    .refs
+                    .insert(format!("refs/heads/{branch}"), commit.clone());
+                repository.head = Some(commit.clone());
+                repository.branch = Some(branch.clone());
+            }
+        }
+        progress.on_progress(&transferred(&repository));
+        state.repositories.insert(path.to_path_buf(), repository);
+        Ok(())
+    }
+
+    fn clone_branch(
+        &self,
+        url: &str,
+        path: &Path,
+        branch: &str,
+        options: CloneOptions,
+        progress: &dyn TransferObserver,
+    ) -> Result<(), Error> {
+        let has_branch = self
+            .lock()
+            .remotes
+            .get(url)
+            .is_some_and(|remote| remote.branches.contains_key(branch));
+        if !has_branch {
+            return Err(not_found(&format!(
+                "remote branch '{branch}' not found in upstream origin"
+            )));
+        }
+        GitBackend::clone(self, url, path, false, options, progress)?;
+        self.with_repository(path, |repository, _| {
+            let tracking = format!("refs/remotes/origin/{branch}");
+            repository
+                .refs
+                .retain(|name, _| name.starts_with("refs/tags/") || *name == tracking);
+            let commit = repository.refs[&tracking].clone();
+            repository
+                .refs
+                .insert(format!("refs/heads/{branch}"), commit.clone());
+            repository.head = Some(commit);
+            repository.branch = Some(branch.to_owned());
+            Ok(())
+        })
+    }
+
+    fn fetch(
+        &self,
+        path: &Path,
+        refspecs: &[&str],
+        _download_tags: bool,
+        progress: &dyn TransferObserver,
+    ) -> Result<(), Error> {
+        self.with_repository(path, |repository, state| {
+            repository
+                .fetches
+                .push(refspecs.iter().map(|refspec| refspec.to_string()).collect());
+            let remote = remote_of(state, &repository.url)?;
+            update_refs(repository, &remote);
+            progress.on_progress(&transferred(repository));
+            Ok(())
+        })
+    }
+
+    fn is_shallow(&self, path: &Path) -> Result<bool, Error> {
+        self.with_repository(path, |repository, _| Ok(repository.depth.is_some()))
+    }
+
+    fn deepen(
+        &self,
+        path: &Path,
+        depth: Option<u32>,
+        progress: &dyn TransferObserver,
+    ) -> Result<(), Error> {
+        self.with_repository(path, |repository, _| {
+            repository.depth = depth;
+            Ok(())
+        })?;
+        GitBackend::fetch(self, path, &[], true, progress)
+    }
+
+    fn checkout_branch(&self, path: &Path, branch: &str) -> Result<(), Error> {
+        self.with_repository(path, |repository, _| {
+            let commit = repository
+                .refs
+                .get(&format!("refs/remotes/origin/{branch}"))
+                .cloned()
+                .ok_or_else(|| {
+                    not_found(&format!(
+                        "cannot locate remote-tracking branch 'origin/{branch}'"
+                    ))
+                })?;
+            repository
+                .refs
+                .insert(format!("refs/heads/{branch}"), commit.clone());
+            repository.head = Some(commit);
+            repository.branch = Some(branch.to_owned());
+            repository.dirty = false;
+            Ok(())
+        })
+    }
+
+    fn fast_forward(&self, path: &Path, branch: &str) -> Result<(), Error> {
+        self.with_repository(path, |repository, _| {
+            let commit = repository
+                .refs
+                .get(&format!("refs/remotes/origin/{branch}"))
+                .cloned()
+                .ok_or_else(|| {
+                    not_found(&format!(
+                        "cannot locate remote-tracking branch 'origin/{branch}'"
+                    ))
+                })?;
+            repository
+                .refs
+                .insert(format!("refs/heads/{branch}"), commit.clone());
+            repository.head = Some(commit);
+            Ok(())
+        })
+    }
+
+    fn reset(&self, path: &Path, revision: &str, branch: Option<&str>) -> Result<(), Error> {
+        self.with_repository(path, |repository, _| {
+            let commit = resolve(repository, revision)?;
+            if let Some(branch) = branch {
+                repository
+                    .refs
+                    .insert(format!("refs/heads/{branch}"), commit.clone());
+            }
+            repository.head = Some(commit);
+            repository.branch = None;
+            repository.dirty = false;
+            Ok(())
+        })
+    }
+
+    fn rev_parse(&self, path: &Path, spec: &str) -> Result<String, Error> {
+        self.with_repository(path, |repository, _| resolve(repository, spec))
+    }
+
+    fn status(&self, path: &Path) -> Result<RepoStatus, Error> {
+        self.with_repository(path, |repository, _| {
+            Ok(RepoStatus {
+                in_progress: repository.in_progress.clone(),
+                dirty: repository.dirty,
+                ..RepoStatus::default()
+            })
+        })
+    }
+
+    fn abort_in_progress(&self, path: &Path) -> Result<(), Error> {
+        self.with_repository(path, |repository, _| {
+            repository.in_progress = None;
+            repository.dirty = false;
+            Ok(())
+        })
+    }
+
+    fn list_refs(&self, path: &Path, glob: &str) -> Result<Vec<String>, Error> {
+        self.with_repository(path, |repository, _| {
+            Ok(repository
+                .refs
+                .keys()
+                .filter(|name| glob_matches(glob, name))
+                .cloned()
+                .collect())
+        })
+    }
+
+    fn describe(&self, path: &Path) -> Result<String, Error> {
+        self.with_repository(path, |repository, _| {
+            let head = repository
+                .head
+                .clone()
+                .ok_or_else(|| not_found("reference 'HEAD' not found"))?;
+            Ok(repository
+                .refs
+                .iter()
+                .find_map(|(name, commit)| {
+                    name.strip_prefix("refs/tags/")
+                        .filter(|_| *commit == head)
+                        .map(|tag| tag.to_owned())
+                })
+                .unwrap_or(head))
+        })
+    }
+
+    fn current_branch(&self, path: &Path) -> Result<Option<String>, Error> {
+        self.with_repository(path, |repository, _| Ok(repository.branch.clone()))
+    }
+
+    fn remote_head(&self, path: &Path) -> Result<Option<String>, Error> {
+        // Clones check out main, or the first branch if there is none.
+        self.with_repository(path, |repository, state| {
+            Ok(state.remotes.get(&repository.url).and_then(|remote| {
+                remote
+                    .branches
+                    .keys()
+                    .find(|branch| *branch == "main")
+                    .or_else(|| remote.branches.keys().next())
+                    .cloned()
+            }))
+        })
+    }
+
+    fn local_commits(&self, path: &Path) -> Result<usize, Error> {
+        // Nothing commits in the fake repositories.
+        self.with_repository(path, |_, _| Ok(0))
+    }
+
+    fn check_objects(&self, path: &Path) -> Result<Vec<String>, Error> {
+        // The fake repositories have no objects to corrupt.
+        self.with_repository(path, |_, _| Ok(Vec::new()))
+    }
+
+    fn commits_only_in(
+        &self,
+        path: &Path,
+        revision: &str,
+        hidden: &[&str],
+    ) -> Result<Vec<String>, Error> {
+        // Commits have no history, so only the commit itself can be hidden.
+        self.with_repository(path, |repository, _| {
+            let commit = resolve(repository, revision)?;
+            let is_hidden = repository.refs.iter().any(|(name, target)| {
+                *target == commit && hidden.iter().any(|glob| glob_matches(glob, name))
+            });
+            Ok(if is_hidden { Vec::new() } else { vec![commit] })
+        })
+    }
+
+    fn ahead_behind(
+        &self,
+        path: &Path,
+        local: &str,
+        upstream: &str,
+    ) -> Result<(usize, usize), Error> {
+        // Commits have no history, so different commits have diverged.
+        self.with_repository(path, |repository, _| {
+            if resolve(repository, local)? == resolve(repository, upstream)? {
+                Ok((0, 0))
+            } else {
+                Ok((1, 1))
+            }
+        })
+    }
+
+    fn search_commits(&self, path: &Path, _query: &CommitQuery) -> Result<Vec<CommitInfo>, Error> {
+        // Commits have no message, so none matches.
+        self.with_repository(path, |_, _| Ok(Vec::new()))
+    }
+
+    fn commit_log(&self, path: &Path, from: &str, to: &str) -> Result<Vec<CommitSummary>, Error> {
+        // Commits have no history nor message, so only `to` is listed.
+        self.with_repository(path, |repository, _| {
+            let to = resolve(repository, to)?;
+            if resolve(repository, from)? == to {
+                Ok(Vec::new())
+            } else {
+                Ok(vec![CommitSummary {
+                    id: to,
+                    subject: String::new(),
+                }])
+            }
+        })
+    }
+
+    fn commit_time(&self, path: &Path, revision: &str) -> Result<u64, Error> {
+        self.with_repository(path, |repository, state| {
+            let commit = resolve(repository, revision)?;
+            Ok(state.times.get(&commit).copied().unwrap_or_default())
+        })
+    }
+
+    fn commit_before(
+        &self,
+        path: &Path,
+        revision: &str,
+        _time: u64,
+    ) -> Result<Option<String>, Error> {
+        // Commits have no date, so the commit itself is always before.
+        self.with_repository(path, |repository, _| {
+            resolve(repository, revision).map(Some)
+        })
+    }
+
+    fn create_branch(&self, path: &Path, branch: &str, revision: &str) -> Result<(), Error> {
+        self.with_repository(path, |repository, _| {
+            let commit = resolve(repository, revision)?;
+            let name = format!("refs/heads/{branch}");
+            if repository.refs.contains_key(&name) {
+                return Err(Error::new(
+                    ErrorCode::Exists,
+                    ErrorClass::Reference,
+                    format!("a reference with name '{name}' already exists"),
+                ));
+            }
+            repository.refs.insert(name, commit);
+            Ok(())
+        })
+    }
+
+    fn delete_branch(&self, path: &Path, branch: &str) -> Result<(), Error> {
+        self.with_repository(path, |repository, _| {
+            repository
+                .refs
+                .remove(&format!("refs/heads/{branch}"))
+                .map(|_| ())
+                .ok_or_else(|| not_found(&format!("cannot locate local branch '{branch}'")))
+        })
+    }
+
+    fn delete_reference(&self, path: &Path, name: &str) -> Result<(), Error> {
+        self.with_repository(path, |repository, _| {
+            repository
+                .refs
+                .remove(name)
+                .map(|_| ())
+                .ok_or_else(|| not_found(&format!("reference '{name}' not found")))
+        })
+    }
+
+    fn remote_branches(&self, path: &Path) -> Result<Vec<String>, Error> {
+        self.with_repository(path, |repository, state| {
+            let remote = state.remotes.get(&repository.url).ok_or_else(|| {
+                Error::new(
+                    ErrorCode::GenericError,
+                    ErrorClass::Net,
+                    format!("remote {} not found", repository.url),
+                )
+            })?;
+            Ok(remote.branches.keys().cloned().collect())
+        })
+    }
+
+    fn stale_remote_refs(&self, path: &Path) -> Result<Vec<String>, Error> {
+        self.with_repository(path, |repository, state| {
+            let remote = remote_of(state, &repository.url)?;
+            Ok(repository
+                .refs
+                .keys()
+                .filter(|name| {
+                    name.strip_prefix("refs/remotes/origin/")
+                        .is_some_and(|branch| !remote.branches.contains_key(branch))
+                })
+                .cloned()
+                .collect())
+        })
+    }
+
+    fn ls_remote(&self, url: &str) -> Result<Vec<String>, Error> {
+        let state = self.lock();
+        let remote = remote_of(&state, url)?;
+        // Fake remotes have no default branch, only their HEAD.
+        let head = (!remote.branches.is_empty()).then(|| "HEAD".to_owned());
+        Ok(head
+            .into_iter()
+            .chain(
+                remote
+                    .branches
+                    .keys()
+                    .map(|branch| format!("refs/heads/{branch}")),
+            )
+            .chain(remote.tags.keys().map(|tag| format!("refs/tags/{tag}")))
+            .collect())
+    }
+
+    fn read_file(&self, path: &Path, revision: &str, file: &Path) -> Result<String, Error> {
+        self.with_repository(path, |repository, state| {
+            let commit = resolve(repository, revision)?;
+            state
+                .files
+                .get(&commit)
+                .and_then(|files| files.get(file))
+                .cloned()
+                .ok_or_else(|| {
+                    not_found(&format!(
+                        "the path '{}' does not exist in the given tree",
+                        file.display()
+                    ))
+                })
+        })
+    }
+
+    fn remote_url(&self, path: &Path) -> Result<Option<String>, Error> {
+        self.with_repository(path, |repository, _| Ok(Some(repository.url.clone())))
+    }
+
+    fn set_remote_url(&self, path: &Path, url: &str) -> Result<(), Error> {
+        self.with_repository(path, |repository, _| {
+            repository.url = url.to_owned();
+            Ok(())
+        })
+    }
+
+    fn identity(&self, path: &Path) -> Result<Option<Identity>, Error> {
+        self.with_repository(path, |_, _| Ok(None))
+    }
+
+    fn config(&self, path: &Path, name: &str) -> Result<Option<String>, Error> {
+        self.with_repository(path, |repository, _| {
+            Ok(repository.config.get(name).cloned())
+        })
+    }
+
+    fn set_config(&self, path: &Path, name: &str, value: &str) -> Result<(), Error> {
+        self.with_repository(path, |repository, _| {
+            repository.config.insert(name.to_owned(), value.to_owned());
+            Ok(())
+        })
+    }
+
+    fn push(&self, path: &Path, url: &str, refspecs: &[String]) -> Result<(), Error> {
+        let updates = self.with_repository(path, |repository, _| {
+            refspecs
+                .iter()
+                .map(|refspec| {
+                    let refspec = refspec.trim_start_matches('+');
+                    let (source, destination) =
+                        refspec.split_once(':').unwrap_or((refspec, refspec));
+                    Ok((destination.to_owned(), resolve(repository, source)?))
+                })
+                .collect::<Result<Vec<_>, Error>>()
+        })?;
+        let mut state = self.lock();
+        let remote = state.remotes.get_mut(url).ok_or_else(|| {
+            Error::new(
+                ErrorCode::GenericError,
+                ErrorClass::Net,
+                format!("remote {url} not found"),
+            )
+        })?;
+        for (destination, commit) in updates {
+            if let Some(branch) = destination.strip_prefix("refs/heads/") {
+                remote.branches.insert(branch.to_owned(), commit);
+            } else if let Some(tag) = destination.strip_prefix("refs/tags/") {
+                remote.tags.insert(tag.to_owned(), commit);
+            }
+        }
+        Ok(())
+    }
+
+    fn diff_head(&self, path: &Path) -> Result<String, Error> {
+        // Changes have no content, the patch only tells there are some.
+        self.with_repository(path, |repository, _| match repository.dirty {
+            true => Ok(format!("changes of {}\n", path.display())),
+            false => Ok(String::new()),
+        })
+    }
+
+    fn apply_patch(&self, path: &Path, patch: &str) -> Result<(), Error> {
+        self.with_repository(path, |repository, _| {
+            repository.dirty |= !patch.is_empty();
+            Ok(())
+        })
+    }
+}
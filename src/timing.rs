@@ -0,0 +1,157 @@
+//! Time spent in the phases of the operations on an environment.
+//!
+//! Every [`ObservingEnvironment`](crate::ObservingEnvironment) records how
+//! long its phases take, e.g. each clone, fetch or checkout, into
+//! [`Timings`]. Recording is cheap, so it is always on; whether the
+//! breakdown is shown is up to the caller.
+//!
+//! ```
+//! use std::time::Duration;
+//! use ts_observing_environment::timing::Timings;
+//!
+//! let timings = Timings::new();
+//! timings.record("fetch", Some("ts_wep"), Duration::from_millis(300));
+//! timings.record("fetch", Some("ts_xml"), Duration::from_millis(500));
+//!
+//! let report = timings.report();
+//! assert_eq!(report.phases[0].count, 2);
+//! assert_eq!(report.phases[0].slowest_repo.as_deref(), Some("ts_xml"));
+//! ```
+use serde::Serialize;
+use std::{
+    fmt::{self, Display},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// One timed run of a phase.
+#[derive(Debug)]
+struct Span {
+    phase: &'static str,
+    repo: Option<String>,
+    duration: Duration,
+}
+
+/// Collects the time spent in each phase, from any thread.
+#[derive(Debug, Default)]
+pub struct Timings {
+    spans: Mutex<Vec<Span>>,
+}
+
+impl Timings {
+    /// Collector without any span.
+    pub fn new() -> Timings {
+        Timings::default()
+    }
+
+    /// Record that `phase` took `duration`, on `repo` if it is about one
+    /// repository.
+    pub fn record(&self, phase: &'static str, repo: Option<&str>, duration: Duration) {
+        let mut spans = self.spans.lock().unwrap_or_else(|error| error.into_inner());
+        spans.push(Span {
+            phase,
+            repo: repo.map(str::to_owned),
+            duration,
+        });
+    }
+
+    /// Run `op`, recording how long it took as `phase`.
+    pub(crate) fn time<T>(
+        &self,
+        phase: &'static str,
+        repo: Option<&str>,
+        op: impl FnOnce() -> T,
+    ) -> T {
+        let start = Instant::now();
+        let result = op();
+        self.record(phase, repo, start.elapsed());
+        result
+    }
+
+    /// Breakdown of the time spent so far, by phase in the order they
+    /// first ran.
+    pub fn report(&self) -> TimingReport {
+        let spans = self.spans.lock().unwrap_or_else(|error| error.into_inner());
+        let mut phases: Vec<PhaseTiming> = Vec::new();
+        for span in spans.iter() {
+            let index = match phases.iter().position(|phase| phase.phase == span.phase) {
+                Some(index) => index,
+                None => {
+                    phases.push(PhaseTiming {
+                        phase: span.phase.to_owned(),
+                        ..PhaseTiming::default()
+                    });
+                    phases.len() - 1
+                }
+            };
+            let phase = &mut phases[index];
+            phase.count += 1;
+            phase.total += span.duration;
+            if span.duration >= phase.max {
+                phase.max = span.duration;
+                phase.slowest_repo = span.repo.clone();
+            }
+        }
+        TimingReport { phases }
+    }
+}
+
+/// Time spent in one phase.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct PhaseTiming {
+    pub phase: String,
+    /// Number of times the phase ran.
+    pub count: usize,
+    /// Time spent in the phase, summed over the runs, even if they ran at
+    /// the same time.
+    pub total: Duration,
+    /// Longest run.
+    pub max: Duration,
+    /// Repository of the longest run, if it was about one.
+    pub slowest_repo: Option<String>,
+}
+
+/// Breakdown of the time spent by phase, from [`Timings::report`].
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct TimingReport {
+    pub phases: Vec<PhaseTiming>,
+}
+
+impl Display for TimingReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Time spent by phase:")?;
+        for phase in self.phases.iter() {
+            write!(
+                f,
+                "\n{:<16} {:>4} x, {:>10.2?} total, {:>10.2?} max",
+                phase.phase, phase.count, phase.total, phase.max
+            )?;
+            if let Some(repo) = &phase.slowest_repo {
+                write!(f, " ({repo})")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Timings;
+    use std::time::Duration;
+
+    #[test]
+    fn test_timing_report() {
+        let timings = Timings::new();
+        timings.record("base env fetch", None, Duration::from_millis(1500));
+        timings.record("checkout", Some("ts_wep"), Duration::from_millis(20));
+        timings.record("checkout", Some("ts_xml"), Duration::from_millis(30));
+        assert_eq!(timings.time("version", Some("ts_wep"), || 42), 42);
+
+        let report = timings.report();
+        assert_eq!(report.phases.len(), 3);
+        assert_eq!(report.phases[1].total, Duration::from_millis(50));
+        assert_eq!(report.phases[1].max, Duration::from_millis(30));
+        let text = report.to_string();
+        assert!(text.starts_with("Time spent by phase:\nbase env fetch      1 x,      1.50s total,      1.50s max\ncheckout            2 x,    50.00ms total,    30.00ms max (ts_xml)\nversion"));
+    }
+}
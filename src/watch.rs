@@ -0,0 +1,392 @@
+//! Convergence of an environment to a manifest kept in a file or a git
+//! repository, checked periodically by [`Watcher::run`] so changing the
+//! manifest is enough to change the environment.
+use crate::{
+    audit::AuditOutcome,
+    error::{report, ObsEnvError},
+    manifest::{EnvironmentManifest, RepoVersion},
+    signal, ObservingEnvironment,
+};
+use std::{
+    fmt::{self, Display},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+/// Name of the operation recorded in the audit log and metadata for each
+/// reconciliation.
+pub const WATCH_ACTION: &str = "watch";
+
+/// Doublings of the interval between cycles after repeated failures.
+const MAX_BACKOFF_DOUBLINGS: u32 = 4;
+
+/// Where the manifest to converge to is read from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ManifestSource {
+    /// A manifest file.
+    File(PathBuf),
+    /// The manifest `file` on `branch` of the git repository at `url`.
+    Repository {
+        url: String,
+        branch: String,
+        file: PathBuf,
+    },
+}
+
+impl ManifestSource {
+    /// `source` as a manifest file if it is one, or else as the url of a
+    /// git repository with the manifest `file` on `branch`.
+    pub fn new(source: &str, branch: &str, file: &str) -> ManifestSource {
+        if Path::new(source).is_file() {
+            ManifestSource::File(PathBuf::from(source))
+        } else {
+            ManifestSource::Repository {
+                url: source.to_owned(),
+                branch: branch.to_owned(),
+                file: PathBuf::from(file),
+            }
+        }
+    }
+}
+
+impl Display for ManifestSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ManifestSource::File(path) => write!(f, "{}", path.display()),
+            ManifestSource::Repository { url, branch, file } => {
+                write!(f, "{} on branch {branch} of {url}", file.display())
+            }
+        }
+    }
+}
+
+/// What one cycle of [`Watcher::run`] did.
+#[derive(Debug, Default)]
+pub struct Reconciliation {
+    /// Versions of the manifest the environment was not at, and that were
+    /// applied.
+    pub applied: Vec<RepoVersion>,
+    /// Errors of the repositories the manifest could not be applied to.
+    pub errors: Vec<ObsEnvError>,
+}
+
+/// State of the environment when [`Watcher::run`] stopped.
+#[derive(Debug, Default)]
+pub struct WatchReport {
+    /// Manifest source watched.
+    pub source: String,
+    /// Number of cycles run.
+    pub cycles: usize,
+    /// Number of cycles that applied versions of the manifest.
+    pub reconciliations: usize,
+    /// Number of cycles that failed.
+    pub failures: usize,
+    /// Versions of the last manifest read the environment is not at, or
+    /// none if no manifest could be read.
+    pub out_of_sync: Option<Vec<RepoVersion>>,
+}
+
+impl Display for WatchReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Watched {} for {} cycles: {} reconciliations, {} failed.",
+            self.source, self.cycles, self.reconciliations, self.failures
+        )?;
+        match &self.out_of_sync {
+            None => write!(f, "\nThe manifest could not be read."),
+            Some(versions) if versions.is_empty() => {
+                write!(f, "\nThe environment is in sync with the manifest.")
+            }
+            Some(versions) => {
+                write!(f, "\nThe environment is out of sync with the manifest:")?;
+                for version in versions {
+                    write!(f, "\n  {}: {}", version.name, version.describe)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Periodic convergence of an environment to the manifest of a source.
+pub struct Watcher<'a> {
+    obs_env: &'a ObservingEnvironment,
+    source: ManifestSource,
+    interval: Duration,
+    manifest: Option<EnvironmentManifest>,
+}
+
+impl<'a> Watcher<'a> {
+    /// Converge `obs_env` to the manifest of `source`, checked every
+    /// `interval`.
+    pub fn new(
+        obs_env: &'a ObservingEnvironment,
+        source: ManifestSource,
+        interval: Duration,
+    ) -> Watcher<'a> {
+        Watcher {
+            obs_env,
+            source,
+            interval,
+            manifest: None,
+        }
+    }
+
+    /// Read the manifest and apply the versions the environment is not at,
+    /// holding the lock of the environment. Each reconciliation that
+    /// applies versions is recorded in the audit log.
+    pub fn reconcile(&mut self) -> Result<Reconciliation, ObsEnvError> {
+        let _lock = self.obs_env.lock()?;
+        let manifest = self.obs_env.fetch_manifest(&self.source)?;
+        let differences = self.obs_env.manifest_differences(&manifest);
+        self.manifest = Some(manifest.clone());
+        if differences.is_empty() {
+            return Ok(Reconciliation::default());
+        }
+        let before = self.obs_env.head_commits();
+        let errors = self
+            .obs_env
+            .apply_manifest(&EnvironmentManifest {
+                repos: differences.clone(),
+                ..manifest
+            })
+            .err()
+            .unwrap_or_default();
+        let outcome = if errors.is_empty() {
+            AuditOutcome::Success
+        } else if errors.len() < differences.len() {
+            AuditOutcome::PartialFailure
+        } else {
+            AuditOutcome::Failed
+        };
+        let error = (!errors.is_empty()).then(|| {
+            errors
+                .iter()
+                .map(|error| error.to_string())
+                .collect::<Vec<_>>()
+                .join("; ")
+        });
+        if let Err(error) = self
+            .obs_env
+            .record_audit(WATCH_ACTION, &before, outcome, error)
+        {
+            log::warn!("Failed to record the reconciliation: {}", report(&error));
+        }
+        let applied: Vec<&str> = differences
+            .iter()
+            .map(|version| version.name.as_str())
+            .filter(|repo_name| !errors.iter().any(|error| error.repo() == Some(*repo_name)))
+            .collect();
+        for (repo_name, result) in self.obs_env.declare_eups(applied.iter().copied()) {
+            if let Err(error) = result {
+                log::error!("Failed to declare {repo_name} in EUPS: {}", report(&error));
+            }
+        }
+        for (repo_name, result) in self.obs_env.develop_install(applied.iter().copied()) {
+            if let Err(error) = result {
+                log::error!("Failed to install {repo_name}: {}", report(&error));
+            }
+        }
+        if errors.is_empty() {
+            if let Err(error) = self.obs_env.write_metadata(WATCH_ACTION) {
+                log::warn!("Failed to write the metadata: {}", report(&error));
+            }
+        }
+        Ok(Reconciliation {
+            applied: differences,
+            errors,
+        })
+    }
+
+    /// Reconcile the environment every interval until `shutdown` is set,
+    /// logging each reconciliation. After failed cycles, the interval is
+    /// doubled for each consecutive failure, up to 16 times.
+    pub fn run(&mut self, shutdown: &AtomicBool) -> WatchReport {
+        let mut watch_report = WatchReport {
+            source: self.source.to_string(),
+            ..WatchReport::default()
+        };
+        let mut consecutive_failures = 0;
+        while !shutdown.load(Ordering::SeqCst) {
+            watch_report.cycles += 1;
+            let failed = match self.reconcile() {
+                Ok(reconciliation) if reconciliation.applied.is_empty() => {
+                    log::debug!("The environment is in sync with {}.", self.source);
+                    false
+                }
+                Ok(reconciliation) => {
+                    watch_report.reconciliations += 1;
+                    for version in reconciliation.applied.iter() {
+                        log::info!("{}: {}", version.name, version.describe);
+                    }
+                    for error in reconciliation.errors.iter() {
+                        log::error!("{}", report(error));
+                    }
+                    !reconciliation.errors.is_empty()
+                }
+                Err(error) => {
+                    log::error!(
+                        "Failed to reconcile with {}: {}",
+                        self.source,
+                        report(&error)
+                    );
+                    true
+                }
+            };
+            if failed {
+                watch_report.failures += 1;
+                consecutive_failures += 1;
+            } else {
+                consecutive_failures = 0;
+            }
+            let delay = self.interval * 2u32.pow(consecutive_failures.min(MAX_BACKOFF_DOUBLINGS));
+            if consecutive_failures > 0 {
+                log::warn!(
+                    "{consecutive_failures} failed cycles in a row, next one in {}s.",
+                    delay.as_secs()
+                );
+            }
+            if signal::wait(delay, shutdown) {
+                break;
+            }
+        }
+        watch_report.out_of_sync = self
+            .manifest
+            .as_ref()
+            .map(|manifest| self.obs_env.manifest_differences(manifest));
+        watch_report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ManifestSource, Watcher};
+    use crate::{
+        manifest::{EnvironmentManifest, RepoVersion},
+        testing::FakeBackend,
+        ObsEnvError, ObservingEnvironment,
+    };
+    use std::{
+        path::PathBuf,
+        sync::atomic::{AtomicBool, Ordering},
+        thread,
+        time::Duration,
+    };
+    use tempfile::TempDir;
+
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    const WEP_URL: &str = "https://example.com/lsst-ts/ts_wep";
+
+    fn fixture_environment(root: &TempDir, backend: &FakeBackend) -> ObservingEnvironment {
+        backend.set_branch(WEP_URL, "main", "1111aaaa");
+        backend.set_branch(WEP_URL, "develop", "2222bbbb");
+        let obs_env = ObservingEnvironment::builder()
+            .destination(&root.path().join("env").to_string_lossy())
+            .repositories([("ts_wep", "https://example.com/lsst-ts/")])
+            .backend(backend.clone())
+            .build()
+            .unwrap();
+        obs_env.clone_repositories().into_result().unwrap();
+        obs_env
+    }
+
+    fn fixture_manifest(commit: &str) -> EnvironmentManifest {
+        let mut version = RepoVersion::new("ts_wep", "develop");
+        version.sha = Some(commit.to_owned());
+        EnvironmentManifest::new("/obs-env", vec![version])
+    }
+
+    #[test]
+    fn test_manifest_source() {
+        let root = TempDir::new().unwrap();
+        let path = root.path().join("manifest.toml");
+        std::fs::write(&path, "").unwrap();
+        assert_eq!(
+            ManifestSource::new(&path.to_string_lossy(), "main", "manifest.toml"),
+            ManifestSource::File(path)
+        );
+        let source = ManifestSource::new(
+            "https://example.com/lsst-ts/test_stand",
+            "main",
+            "obs_env.toml",
+        );
+        assert_eq!(
+            source.to_string(),
+            "obs_env.toml on branch main of https://example.com/lsst-ts/test_stand"
+        );
+    }
+
+    #[test]
+    fn test_reconcile() -> TestResult {
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        let obs_env = fixture_environment(&root, &backend);
+        let manifest_url = "https://example.com/lsst-ts/test_stand";
+        backend.set_branch(manifest_url, "main", "3333cccc");
+        backend.set_file(
+            "3333cccc",
+            "manifest.toml",
+            &fixture_manifest("2222bbbb").to_toml(),
+        );
+        let source = ManifestSource::Repository {
+            url: manifest_url.to_owned(),
+            branch: "main".to_owned(),
+            file: PathBuf::from("manifest.toml"),
+        };
+        let mut watcher = Watcher::new(&obs_env, source, Duration::ZERO);
+
+        let reconciliation = watcher.reconcile()?;
+        assert_eq!(reconciliation.applied.len(), 1);
+        assert!(reconciliation.errors.is_empty());
+        let wep_path = root.path().join("env/ts_wep");
+        assert_eq!(backend.head(&wep_path).as_deref(), Some("2222bbbb"));
+        assert_eq!(obs_env.history(&Default::default())?[0].action, "watch");
+
+        assert!(watcher.reconcile()?.applied.is_empty());
+
+        backend.set_branch(manifest_url, "main", "4444dddd");
+        backend.set_file("4444dddd", "manifest.toml", "repos = 1");
+        assert!(matches!(
+            watcher.reconcile(),
+            Err(ObsEnvError::InvalidManifest { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_until_shutdown() -> TestResult {
+        let root = TempDir::new()?;
+        let backend = FakeBackend::new();
+        let obs_env = fixture_environment(&root, &backend);
+        let path = root.path().join("manifest.toml");
+        let mut watcher =
+            Watcher::new(&obs_env, ManifestSource::File(path.clone()), Duration::ZERO);
+
+        let shutdown = AtomicBool::new(false);
+        let report = thread::scope(|scope| {
+            scope.spawn(|| {
+                thread::sleep(Duration::from_millis(200));
+                shutdown.store(true, Ordering::SeqCst);
+            });
+            watcher.run(&shutdown)
+        });
+        assert!(report.cycles > 0);
+        assert_eq!(report.failures, report.cycles);
+        assert!(report
+            .to_string()
+            .ends_with("The manifest could not be read."));
+
+        fixture_manifest("2222bbbb").save(&path)?;
+        watcher.reconcile()?;
+        let report = watcher.run(&shutdown);
+        assert_eq!(report.cycles, 0);
+        assert_eq!(report.out_of_sync.as_deref(), Some(&[][..]));
+        assert!(report
+            .to_string()
+            .ends_with("The environment is in sync with the manifest."));
+        Ok(())
+    }
+}
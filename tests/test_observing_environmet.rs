@@ -1,6 +1,6 @@
-use ts_observing_environment::observing_environment::ObservingEnvironment;
+use ts_observing_environment::ObservingEnvironment;
 
 #[test]
 fn test_observing_environment() {
-    let obs_env = ObservingEnvironment::with_destination("/tmp");
+    let _obs_env = ObservingEnvironment::with_destination("/tmp");
 }